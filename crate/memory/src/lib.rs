@@ -17,6 +17,12 @@ pub use crate::addr::*;
 
 pub enum VMError {
     InvalidPtr,
+    /// Part of the range is not mapped
+    Unmapped,
+    /// The area does not allow the new attributes
+    AccessDenied,
+    /// A page table to split a huge page can't be allocated
+    NoMem,
 }
 
 pub type VMResult<T> = Result<T, VMError>;
@@ -22,6 +22,16 @@ impl<T: FrameAllocator> MemoryHandler for ByFrame<T> {
         pt.unmap(addr);
     }
 
+    fn unmap_keep(&self, pt: &mut dyn PageTable, addr: VirtAddr) -> Option<PhysAddr> {
+        let target = pt.get_entry(addr).expect("fail to get entry").target();
+        pt.unmap(addr);
+        Some(target)
+    }
+
+    fn release(&self, _addr: VirtAddr, frame: PhysAddr) {
+        self.allocator.dealloc(frame);
+    }
+
     fn clone_map(
         &self,
         pt: &mut dyn PageTable,
@@ -27,6 +27,21 @@ impl<T: FrameAllocator> MemoryHandler for Delay<T> {
         pt.unmap(addr);
     }
 
+    fn unmap_keep(&self, pt: &mut dyn PageTable, addr: VirtAddr) -> Option<PhysAddr> {
+        let entry = pt.get_entry(addr).expect("failed to get entry");
+        let frame = match entry.present() {
+            true => Some(entry.target()),
+            false => None,
+        };
+        entry.set_present(true);
+        pt.unmap(addr);
+        frame
+    }
+
+    fn release(&self, _addr: VirtAddr, frame: PhysAddr) {
+        self.allocator.dealloc(frame);
+    }
+
     fn clone_map(
         &self,
         pt: &mut dyn PageTable,
@@ -34,6 +49,7 @@ impl<T: FrameAllocator> MemoryHandler for Delay<T> {
         addr: VirtAddr,
         attr: &MemoryAttr,
     ) {
+        // huge pages of the parent are split by `MemorySet::clone`
         let entry = src_pt.get_entry(addr).expect("failed to get entry");
         if entry.present() {
             // eager map and copy data
@@ -68,6 +84,38 @@ impl<T: FrameAllocator> MemoryHandler for Delay<T> {
         pt.flush_cache_copy_user(addr, addr + len, false);
         true
     }
+
+    fn map_huge(&self, pt: &mut dyn PageTable, addr: VirtAddr, attr: &MemoryAttr) -> bool {
+        let frames = HUGE_PAGE_SIZE / PAGE_SIZE;
+        let target = match self
+            .allocator
+            .alloc_contiguous(frames, frames.trailing_zeros() as usize)
+        {
+            Some(target) => target,
+            // fragmented
+            None => return false,
+        };
+        match pt.map_huge(addr, target) {
+            Some(entry) => attr.apply(entry),
+            None => {
+                for i in 0..frames {
+                    self.allocator.dealloc(target + i * PAGE_SIZE);
+                }
+                return false;
+            }
+        }
+        for i in 0..frames {
+            for x in pt.get_page_slice_mut(addr + i * PAGE_SIZE) {
+                *x = 0;
+            }
+        }
+        pt.flush_cache_copy_user(addr, addr + HUGE_PAGE_SIZE, attr.execute);
+        true
+    }
+
+    fn zero_fill(&self) -> bool {
+        true
+    }
 }
 
 impl<T: FrameAllocator> Delay<T> {
@@ -36,6 +36,21 @@ impl<F: Read, T: FrameAllocator> MemoryHandler for File<F, T> {
         pt.unmap(addr);
     }
 
+    fn unmap_keep(&self, pt: &mut dyn PageTable, addr: usize) -> Option<PhysAddr> {
+        let entry = pt.get_entry(addr).expect("failed to get entry");
+        let frame = match entry.present() {
+            true => Some(entry.target()),
+            false => None,
+        };
+        entry.set_present(true);
+        pt.unmap(addr);
+        frame
+    }
+
+    fn release(&self, _addr: usize, frame: PhysAddr) {
+        self.allocator.dealloc(frame);
+    }
+
     fn clone_map(
         &self,
         pt: &mut dyn PageTable,
@@ -100,3 +115,74 @@ impl<F, T> Debug for File<F, T> {
             .finish()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::paging::MockPageTable;
+    use alloc::sync::Arc;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    /// A file of `.0` bytes of 0xaa
+    #[derive(Clone)]
+    struct Pattern(usize);
+
+    impl Read for Pattern {
+        fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
+            let len = buf.len().min(self.0.saturating_sub(offset));
+            buf[..len].iter_mut().for_each(|x| *x = 0xaa);
+            len
+        }
+    }
+
+    /// Give out frames 1, 2, 3...
+    #[derive(Debug, Clone)]
+    struct FrameAlloc(Arc<AtomicUsize>);
+
+    impl FrameAllocator for FrameAlloc {
+        fn alloc(&self) -> Option<PhysAddr> {
+            Some(self.0.fetch_add(1, Ordering::Relaxed) * PAGE_SIZE)
+        }
+        fn alloc_contiguous(&self, _size: usize, _align_log2: usize) -> Option<PhysAddr> {
+            unimplemented!()
+        }
+        fn dealloc(&self, _target: PhysAddr) {}
+    }
+
+    /// The bss of an ELF segment, after its file data, reads as zero,
+    /// including the tail of the page shared with the file data
+    #[test]
+    fn bss() {
+        // file data in [0x1000, 0x2800), bss in [0x2800, 0x5000)
+        let (start, file_size, mem_size) = (0x1000, 0x1800, 0x4000);
+        let mut pt = MockPageTable::new();
+
+        // leave garbage in the frames to be allocated
+        for addr in (PAGE_SIZE..5 * PAGE_SIZE).step_by(PAGE_SIZE) {
+            pt.map(addr, addr);
+            for offset in 0..PAGE_SIZE {
+                pt.write(addr + offset, 0x55);
+            }
+            pt.unmap(addr);
+        }
+
+        let file = File {
+            file: Pattern(file_size),
+            mem_start: start,
+            file_start: 0,
+            file_end: file_size,
+            allocator: FrameAlloc(Arc::new(AtomicUsize::new(1))),
+        };
+        for addr in (start..start + mem_size).step_by(PAGE_SIZE) {
+            file.map(&mut pt, addr, &MemoryAttr::default().user());
+        }
+        pt.set_handler(Box::new(move |pt: &mut MockPageTable, addr: VirtAddr| {
+            assert!(file.handle_page_fault(pt, addr));
+        }));
+
+        for addr in start..start + mem_size {
+            let expected = if addr < start + file_size { 0xaa } else { 0 };
+            assert_eq!(pt.read(addr), expected, "at {:#x}", addr);
+        }
+    }
+}
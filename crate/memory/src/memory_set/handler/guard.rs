@@ -0,0 +1,33 @@
+use super::*;
+
+/// An area which is never mapped, e.g. the gap below a stack.
+/// Any access to it is an error.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Guard;
+
+impl MemoryHandler for Guard {
+    fn box_clone(&self) -> Box<dyn MemoryHandler> {
+        Box::new(self.clone())
+    }
+
+    fn map(&self, _pt: &mut dyn PageTable, _addr: VirtAddr, _attr: &MemoryAttr) {}
+
+    fn unmap(&self, _pt: &mut dyn PageTable, _addr: VirtAddr) {}
+
+    fn clone_map(
+        &self,
+        _pt: &mut dyn PageTable,
+        _src_pt: &mut dyn PageTable,
+        _addr: VirtAddr,
+        _attr: &MemoryAttr,
+    ) {
+    }
+
+    fn handle_page_fault(&self, _pt: &mut dyn PageTable, _addr: VirtAddr) -> bool {
+        false
+    }
+
+    fn is_guard(&self) -> bool {
+        true
+    }
+}
@@ -1,5 +1,8 @@
 use super::*;
 
+/// Write-back of part of an area, see `MemoryHandler::sync`
+pub type SyncJob = Box<dyn FnOnce() -> bool + Send>;
+
 // here may be a interesting part for lab
 pub trait MemoryHandler: Debug + Send + Sync + 'static {
     fn box_clone(&self) -> Box<dyn MemoryHandler>;
@@ -11,6 +14,17 @@ pub trait MemoryHandler: Debug + Send + Sync + 'static {
     /// Unmap `addr` in the page table
     fn unmap(&self, pt: &mut dyn PageTable, addr: VirtAddr);
 
+    /// Unmap `addr` like `unmap`, but keep the frame it mapped and return it,
+    /// to be freed by `release` once no TLB can hold the old entry any more.
+    /// None if there is no frame to free.
+    fn unmap_keep(&self, pt: &mut dyn PageTable, addr: VirtAddr) -> Option<PhysAddr> {
+        self.unmap(pt, addr);
+        None
+    }
+
+    /// Free `frame` returned by `unmap_keep` for `addr`
+    fn release(&self, _addr: VirtAddr, _frame: PhysAddr) {}
+
     /// Clone map `addr` from page table `src_pt` to `pt`.
     fn clone_map(
         &self,
@@ -20,9 +34,40 @@ pub trait MemoryHandler: Debug + Send + Sync + 'static {
         attr: &MemoryAttr,
     );
 
-    /// Handle page fault on `addr`
+    /// Handle page fault on `addr`, which is not present
     /// Return true if success, false if error
     fn handle_page_fault(&self, pt: &mut dyn PageTable, addr: VirtAddr) -> bool;
+
+    /// Map the huge page at `addr` on its first access,
+    /// which is aligned to `HUGE_PAGE_SIZE` and lies within the area.
+    /// Return false if it has to be faulted in page by page instead.
+    fn map_huge(&self, _pt: &mut dyn PageTable, _addr: VirtAddr, _attr: &MemoryAttr) -> bool {
+        false
+    }
+
+    /// Write back `[start, end)` of the area to the file backing it, for `msync`.
+    /// Return a job to run after the memory set is unlocked, as it may wait for I/O,
+    /// which returns false if it fails. None if there is no file.
+    fn sync(&self, _start: VirtAddr, _end: VirtAddr) -> Option<SyncJob> {
+        None
+    }
+
+    /// Whether the area is an inaccessible guard
+    fn is_guard(&self) -> bool {
+        false
+    }
+
+    /// Whether the area may be made writable after mapping,
+    /// false for pages the kernel shares with all processes
+    fn may_write(&self) -> bool {
+        true
+    }
+
+    /// Whether a page unmapped and mapped again reads as zero when faulted in,
+    /// so its frame can be dropped whenever the contents are no longer needed
+    fn zero_fill(&self) -> bool {
+        false
+    }
 }
 
 impl Clone for Box<dyn MemoryHandler> {
@@ -40,6 +85,7 @@ pub trait FrameAllocator: Debug + Clone + Send + Sync + 'static {
 mod byframe;
 mod delay;
 mod file;
+mod guard;
 mod linear;
 mod shared;
 //mod swap;
@@ -47,5 +93,6 @@ mod shared;
 pub use self::byframe::ByFrame;
 pub use self::delay::Delay;
 pub use self::file::{File, Read};
+pub use self::guard::Guard;
 pub use self::linear::Linear;
 pub use self::shared::{Shared, SharedGuard};
@@ -35,13 +35,18 @@ impl<T: FrameAllocator> SharedGuard<T> {
         self.target.insert(virt_addr, phys_addr);
         Some(phys_addr)
     }
-    
+
     pub fn dealloc(&mut self, virt_addr: usize) {
         let phys_addr = self.target.get(&virt_addr).unwrap().clone();
         self.allocator.dealloc(phys_addr);
         self.target.remove(&virt_addr);
     }
 
+    /// Number of frames allocated so far
+    pub fn frames(&self) -> usize {
+        self.target.len()
+    }
+
     pub fn get(&self, addr: usize) -> Option<usize> {
         match self.target.get(&addr) {
             Some(phys_addr) => Some(phys_addr.clone()),
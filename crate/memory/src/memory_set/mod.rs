@@ -1,6 +1,6 @@
 //! Memory management structures
 
-use alloc::{boxed::Box, vec::Vec};
+use alloc::{boxed::Box, collections::BTreeSet, vec::Vec};
 use core::fmt::{Debug, Error, Formatter};
 use core::mem::size_of;
 
@@ -30,6 +30,9 @@ impl MemoryArea {
     /// Check the array is within the readable memory.
     /// Return the size of space covered in the area.
     fn check_read_array<S>(&self, ptr: *const S, count: usize) -> usize {
+        if self.handler.is_guard() {
+            return 0;
+        }
         // page align
         let min_bound = (ptr as usize).max(Page::of_addr(self.start_addr).start_address());
         let max_bound = unsafe { ptr.add(count) as usize }
@@ -63,10 +66,75 @@ impl MemoryArea {
             self.handler.map(pt, page.start_address(), &self.attr);
         }
     }
-    /// Unmap all pages in the area from page table `pt`
-    fn unmap(&self, pt: &mut dyn PageTable) {
-        for page in Page::range_of(self.start_addr, self.end_addr) {
-            self.handler.unmap(pt, page.start_address());
+    /// Unmap all pages in the area from page table `pt`,
+    /// keeping their frames until the returned `Unmapped` is dropped.
+    /// Huge pages must lie in the area as a whole.
+    fn unmap_keep(self, pt: &mut dyn PageTable) -> Unmapped {
+        let mut frames = Vec::new();
+        let mut addr = self.start_addr;
+        while addr < self.end_addr {
+            if let Some(target) = pt.huge_page(addr) {
+                debug_assert!(
+                    addr & (HUGE_PAGE_SIZE - 1) == 0 && addr + HUGE_PAGE_SIZE <= self.end_addr
+                );
+                // its frames are given back one by one
+                pt.unmap_huge(addr);
+                for offset in (0..HUGE_PAGE_SIZE).step_by(PAGE_SIZE) {
+                    frames.push((addr + offset, target + offset));
+                }
+                addr += HUGE_PAGE_SIZE;
+                continue;
+            }
+            frames.extend(self.handler.unmap_keep(pt, addr).map(|frame| (addr, frame)));
+            addr += PAGE_SIZE;
+        }
+        Unmapped {
+            handler: self.handler,
+            frames,
+        }
+    }
+    /// Drop the page at `addr` and map it again, so it is faulted in afresh.
+    /// Return the frame it mapped, to be freed by the handler later.
+    fn remap_page(&self, pt: &mut dyn PageTable, addr: VirtAddr) -> Option<PhysAddr> {
+        let frame = self.handler.unmap_keep(pt, addr);
+        self.handler.map(pt, addr, &self.attr);
+        frame
+    }
+}
+
+/// Frames of pages unmapped from an area, which may still be cached by TLBs of other CPUs.
+/// They are given back to the handler when this is dropped.
+struct Unmapped {
+    handler: Box<dyn MemoryHandler>,
+    frames: Vec<(VirtAddr, PhysAddr)>,
+}
+
+impl Drop for Unmapped {
+    fn drop(&mut self) {
+        for &(addr, frame) in self.frames.iter() {
+            self.handler.release(addr, frame);
+        }
+    }
+}
+
+/// Mappings changed in a `MemorySet`, taken by `take_flush`.
+/// TLB entries of `[start, end)` may be stale on other CPUs, so the frames
+/// of the pages and page tables unmapped are only freed when this is dropped,
+/// which must be after the TLB shootdown.
+pub struct TlbFlush {
+    pub start: VirtAddr,
+    pub end: VirtAddr,
+    unmapped: Vec<Unmapped>,
+    tables: Vec<PhysAddr>,
+    free_table: fn(PhysAddr),
+}
+
+impl Drop for TlbFlush {
+    fn drop(&mut self) {
+        // give the frames back to their handlers
+        self.unmapped.clear();
+        for &table in self.tables.iter() {
+            (self.free_table)(table);
         }
     }
 }
@@ -78,6 +146,8 @@ pub struct MemoryAttr {
     readonly: bool,
     execute: bool,
     mmio: u8,
+    /// Back aligned ranges with huge pages where the handler can
+    huge: bool,
 }
 
 impl MemoryAttr {
@@ -101,6 +171,10 @@ impl MemoryAttr {
         self.mmio = value;
         self
     }
+    pub fn huge(mut self) -> Self {
+        self.huge = true;
+        self
+    }
     /// Apply the attributes to page table entry, then update it.
     /// NOTE: You may need to set present manually.
     pub fn apply(&self, entry: &mut dyn Entry) {
@@ -112,6 +186,18 @@ impl MemoryAttr {
     }
 }
 
+/// What a faulting access tried to do, decoded from the trap by each architecture
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct PageFaultCause {
+    /// It was a write
+    pub write: bool,
+    /// It was an instruction fetch
+    pub exec: bool,
+    /// The page was present, i.e. a protection fault.
+    /// Architectures which can not tell leave it false.
+    pub present: bool,
+}
+
 /// A set of memory space with multiple memory areas with associated page table
 /// NOTE: Don't remove align(64), or you will fail to run MIPS.
 /// Temporary solution for rv64
@@ -119,6 +205,15 @@ impl MemoryAttr {
 pub struct MemorySet<T: PageTableExt> {
     areas: Vec<MemoryArea>,
     page_table: T,
+    /// Range of mappings changed since last `take_flush`,
+    /// whose TLB entries may be stale on other CPUs
+    flush_range: Option<(VirtAddr, VirtAddr)>,
+    /// Frames of pages unmapped in `flush_range`, freed after the shootdown
+    unmapped: Vec<Unmapped>,
+    /// Frames of page tables dropped in `flush_range`, freed after the shootdown
+    dropped_tables: Vec<PhysAddr>,
+    /// Pages marked by `lazy_free`, read-only until written again
+    freeable: BTreeSet<VirtAddr>,
 }
 
 impl<T: PageTableExt> MemorySet<T> {
@@ -127,6 +222,10 @@ impl<T: PageTableExt> MemorySet<T> {
         MemorySet {
             areas: Vec::new(),
             page_table: T::new(),
+            flush_range: None,
+            unmapped: Vec::new(),
+            dropped_tables: Vec::new(),
+            freeable: BTreeSet::new(),
         }
     }
     /// Create a new `MemorySet` for kernel remap
@@ -134,6 +233,10 @@ impl<T: PageTableExt> MemorySet<T> {
         MemorySet {
             areas: Vec::new(),
             page_table: T::new_bare(),
+            flush_range: None,
+            unmapped: Vec::new(),
+            dropped_tables: Vec::new(),
+            freeable: BTreeSet::new(),
         }
     }
     /// Check the pointer is within the readable memory
@@ -234,7 +337,10 @@ impl<T: PageTableExt> MemorySet<T> {
         for i in 0..self.areas.len() {
             if self.areas[i].start_addr == start_addr && self.areas[i].end_addr == end_addr {
                 let area = self.areas.remove(i);
-                area.unmap(&mut self.page_table);
+                let unmapped = area.unmap_keep(&mut self.page_table);
+                self.unmapped.push(unmapped);
+                self.mark_flush(start_addr, end_addr);
+                self.untag(start_addr, end_addr);
                 return;
             }
         }
@@ -243,15 +349,27 @@ impl<T: PageTableExt> MemorySet<T> {
 
     /// Remove the area `[start_addr, end_addr)` from `MemorySet`
     /// and split existed ones when necessary.
-    pub fn pop_with_split(&mut self, start_addr: VirtAddr, end_addr: VirtAddr) {
+    /// Fail without changing anything if a huge page across either end can't be split.
+    pub fn pop_with_split(&mut self, start_addr: VirtAddr, end_addr: VirtAddr) -> VMResult<()> {
         assert!(start_addr <= end_addr, "invalid memory area");
+        self.split_huge_at(start_addr)?;
+        self.split_huge_at(end_addr)?;
+        if self
+            .areas
+            .iter()
+            .any(|area| area.is_overlap_with(start_addr, end_addr))
+        {
+            self.mark_flush(start_addr, end_addr);
+            self.untag(start_addr, end_addr);
+        }
         let mut i = 0;
         while i < self.areas.len() {
             if self.areas[i].is_overlap_with(start_addr, end_addr) {
                 if self.areas[i].start_addr >= start_addr && self.areas[i].end_addr <= end_addr {
                     // subset
                     let area = self.areas.remove(i);
-                    area.unmap(&mut self.page_table);
+                    let unmapped = area.unmap_keep(&mut self.page_table);
+                    self.unmapped.push(unmapped);
                     i = i.wrapping_sub(1);
                 } else if self.areas[i].start_addr >= start_addr
                     && self.areas[i].start_addr < end_addr
@@ -265,7 +383,8 @@ impl<T: PageTableExt> MemorySet<T> {
                         handler: area.handler.box_clone(),
                         name: area.name,
                     };
-                    dead_area.unmap(&mut self.page_table);
+                    let unmapped = dead_area.unmap_keep(&mut self.page_table);
+                    self.unmapped.push(unmapped);
                     let new_area = MemoryArea {
                         start_addr: end_addr,
                         end_addr: area.end_addr,
@@ -285,7 +404,8 @@ impl<T: PageTableExt> MemorySet<T> {
                         handler: area.handler.box_clone(),
                         name: area.name,
                     };
-                    dead_area.unmap(&mut self.page_table);
+                    let unmapped = dead_area.unmap_keep(&mut self.page_table);
+                    self.unmapped.push(unmapped);
                     let new_area = MemoryArea {
                         start_addr: area.start_addr,
                         end_addr: start_addr,
@@ -304,7 +424,8 @@ impl<T: PageTableExt> MemorySet<T> {
                         handler: area.handler.box_clone(),
                         name: area.name,
                     };
-                    dead_area.unmap(&mut self.page_table);
+                    let unmapped = dead_area.unmap_keep(&mut self.page_table);
+                    self.unmapped.push(unmapped);
                     let new_area_left = MemoryArea {
                         start_addr: area.start_addr,
                         end_addr: start_addr,
@@ -326,6 +447,320 @@ impl<T: PageTableExt> MemorySet<T> {
             }
             i = i.wrapping_add(1);
         }
+        Ok(())
+    }
+
+    /// Change the attributes of `[start_addr, end_addr)` to `attr`,
+    /// splitting existed areas at both ends when necessary.
+    /// Fail without changing anything if part of the range is not mapped,
+    /// or may not be made writable, or if huge pages in it can't be split.
+    pub fn protect(
+        &mut self,
+        start_addr: VirtAddr,
+        end_addr: VirtAddr,
+        attr: MemoryAttr,
+    ) -> VMResult<()> {
+        let start_addr = start_addr & !(PAGE_SIZE - 1);
+        let end_addr = (end_addr + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+        if start_addr >= end_addr {
+            return Ok(());
+        }
+        self.check_mapped(start_addr, end_addr)?;
+        if !attr.readonly
+            && self
+                .areas
+                .iter()
+                .any(|area| area.is_overlap_with(start_addr, end_addr) && !area.handler.may_write())
+        {
+            return Err(VMError::AccessDenied);
+        }
+        self.split_huge(start_addr, end_addr)?;
+
+        // freeable pages made writable would lose writes on reclaim, so they are kept instead
+        self.untag(start_addr, end_addr);
+        self.split_at(start_addr);
+        self.split_at(end_addr);
+        let Self {
+            ref mut page_table,
+            ref mut areas,
+            ..
+        } = self;
+        for area in areas
+            .iter_mut()
+            .filter(|area| area.start_addr >= start_addr && area.end_addr <= end_addr)
+        {
+            area.attr = MemoryAttr {
+                mmio: area.attr.mmio,
+                huge: area.attr.huge,
+                ..attr
+            };
+            // entries not present yet keep them once faulted in
+            for page in Page::range_of(area.start_addr, area.end_addr) {
+                if let Some(entry) = page_table.get_entry(page.start_address()) {
+                    area.attr.apply(entry);
+                }
+            }
+        }
+        self.mark_flush(start_addr, end_addr);
+        Ok(())
+    }
+
+    /// Set whether `[start_addr, end_addr)` is backed by huge pages on later faults,
+    /// splitting existed areas at both ends when necessary.
+    /// Pages mapped already are left as they are.
+    pub fn set_huge(
+        &mut self,
+        start_addr: VirtAddr,
+        end_addr: VirtAddr,
+        huge: bool,
+    ) -> VMResult<()> {
+        let start_addr = start_addr & !(PAGE_SIZE - 1);
+        let end_addr = (end_addr + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+        if start_addr >= end_addr {
+            return Ok(());
+        }
+        self.check_mapped(start_addr, end_addr)?;
+        // a huge page must not cross the areas split here
+        self.split_huge_at(start_addr)?;
+        self.split_huge_at(end_addr)?;
+        self.split_at(start_addr);
+        self.split_at(end_addr);
+        for area in self
+            .areas
+            .iter_mut()
+            .filter(|area| area.start_addr >= start_addr && area.end_addr <= end_addr)
+        {
+            area.attr.huge = huge;
+        }
+        Ok(())
+    }
+
+    /// Fail if part of the page aligned range `[start_addr, end_addr)` is not mapped
+    fn check_mapped(&self, start_addr: VirtAddr, end_addr: VirtAddr) -> VMResult<()> {
+        // areas are ordered by start address, so they must cover the range one after another
+        let mut covered = start_addr;
+        for area in self.areas.iter().filter(|area| !area.handler.is_guard()) {
+            if area.end_addr <= covered {
+                continue;
+            }
+            if area.start_addr > covered || covered >= end_addr {
+                break;
+            }
+            covered = area.end_addr;
+        }
+        if covered < end_addr {
+            return Err(VMError::Unmapped);
+        }
+        Ok(())
+    }
+
+    /// Drop the pages of zero-fill areas in `[start_addr, end_addr)`,
+    /// so they read as zero on the next access, as `MADV_DONTNEED`.
+    /// Pages of other areas are left alone.
+    pub fn discard(&mut self, start_addr: VirtAddr, end_addr: VirtAddr) -> VMResult<()> {
+        let start_addr = start_addr & !(PAGE_SIZE - 1);
+        let end_addr = (end_addr + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+        if start_addr >= end_addr {
+            return Ok(());
+        }
+        self.check_mapped(start_addr, end_addr)?;
+        self.split_huge(start_addr, end_addr)?;
+        self.untag(start_addr, end_addr);
+        let Self {
+            ref mut page_table,
+            ref areas,
+            ref mut unmapped,
+            ..
+        } = self;
+        for area in areas.iter().filter(|area| area.handler.zero_fill()) {
+            let start = area.start_addr.max(start_addr);
+            let end = area.end_addr.min(end_addr);
+            if start >= end {
+                continue;
+            }
+            let mut frames = Vec::new();
+            for page in Page::range_of(start, end) {
+                let addr = page.start_address();
+                if page_table
+                    .get_entry(addr)
+                    .map_or(false, |entry| entry.present())
+                {
+                    frames.extend(area.remap_page(page_table, addr).map(|frame| (addr, frame)));
+                }
+            }
+            unmapped.push(Unmapped {
+                handler: area.handler.box_clone(),
+                frames,
+            });
+        }
+        self.mark_flush(start_addr, end_addr);
+        Ok(())
+    }
+
+    /// Mark the present pages of zero-fill areas in `[start_addr, end_addr)` as freeable,
+    /// as `MADV_FREE`. They keep their contents until dropped by `reclaim_freeable`,
+    /// unless written before, so they are made read-only to catch the write.
+    /// Pages of other areas are left alone.
+    /// Return whether any page was marked.
+    pub fn lazy_free(&mut self, start_addr: VirtAddr, end_addr: VirtAddr) -> VMResult<bool> {
+        let start_addr = start_addr & !(PAGE_SIZE - 1);
+        let end_addr = (end_addr + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+        if start_addr >= end_addr {
+            return Ok(false);
+        }
+        self.check_mapped(start_addr, end_addr)?;
+        self.split_huge(start_addr, end_addr)?;
+        let Self {
+            ref mut page_table,
+            ref areas,
+            ref mut freeable,
+            ..
+        } = self;
+        let mut marked = false;
+        for area in areas.iter().filter(|area| area.handler.zero_fill()) {
+            let start = area.start_addr.max(start_addr);
+            let end = area.end_addr.min(end_addr);
+            if start >= end {
+                continue;
+            }
+            for page in Page::range_of(start, end) {
+                let addr = page.start_address();
+                if let Some(entry) = page_table.get_entry(addr) {
+                    if entry.present() {
+                        entry.set_writable(false);
+                        entry.update();
+                        freeable.insert(addr);
+                        marked = true;
+                    }
+                }
+            }
+        }
+        self.mark_flush(start_addr, end_addr);
+        Ok(marked)
+    }
+
+    /// Drop up to `count` freeable pages, which read as zero afterwards.
+    /// Return the number of pages dropped.
+    pub fn reclaim_freeable(&mut self, count: usize) -> usize {
+        let mut dropped = 0;
+        while dropped < count {
+            let addr = match self.freeable.iter().next() {
+                Some(&addr) => addr,
+                None => break,
+            };
+            self.freeable.remove(&addr);
+            // marks are removed along with the areas, so one must contain it
+            let area = self
+                .areas
+                .iter()
+                .find(|area| area.contains(addr))
+                .expect("no memory area of freeable page");
+            let frames = area
+                .remap_page(&mut self.page_table, addr)
+                .map(|frame| (addr, frame));
+            self.unmapped.push(Unmapped {
+                handler: area.handler.box_clone(),
+                frames: frames.into_iter().collect(),
+            });
+            self.mark_flush(addr, addr + PAGE_SIZE);
+            dropped += 1;
+        }
+        dropped
+    }
+
+    /// Test whether any page is marked by `lazy_free` and not yet dropped or written
+    pub fn has_freeable(&self) -> bool {
+        !self.freeable.is_empty()
+    }
+
+    /// Remove the freeable marks of pages in `[start_addr, end_addr)`
+    fn untag(&mut self, start_addr: VirtAddr, end_addr: VirtAddr) {
+        let start_addr = start_addr & !(PAGE_SIZE - 1);
+        let marked: Vec<VirtAddr> = self.freeable.range(start_addr..end_addr).cloned().collect();
+        for addr in marked {
+            self.freeable.remove(&addr);
+        }
+    }
+
+    /// Split the huge pages in `[start_addr, end_addr)` into small pages,
+    /// so each of them can be changed on its own
+    fn split_huge(&mut self, start_addr: VirtAddr, end_addr: VirtAddr) -> VMResult<()> {
+        let mut block = start_addr & !(HUGE_PAGE_SIZE - 1);
+        while block < end_addr {
+            if !self.page_table.split_huge(block) {
+                return Err(VMError::NoMem);
+            }
+            block = match block.checked_add(HUGE_PAGE_SIZE) {
+                Some(next) => next,
+                None => break,
+            };
+        }
+        Ok(())
+    }
+
+    /// Split the huge page across `addr`, if any, so that areas can be split there
+    fn split_huge_at(&mut self, addr: VirtAddr) -> VMResult<()> {
+        if addr & (HUGE_PAGE_SIZE - 1) == 0 || self.page_table.split_huge(addr) {
+            Ok(())
+        } else {
+            Err(VMError::NoMem)
+        }
+    }
+
+    /// Split the area containing `addr` into two at `addr`
+    fn split_at(&mut self, addr: VirtAddr) {
+        let i = match self
+            .areas
+            .iter()
+            .position(|area| area.start_addr < addr && addr < area.end_addr)
+        {
+            Some(i) => i,
+            None => return,
+        };
+        let area = &mut self.areas[i];
+        let right = MemoryArea {
+            start_addr: addr,
+            end_addr: area.end_addr,
+            attr: area.attr,
+            handler: area.handler.box_clone(),
+            name: area.name,
+        };
+        area.end_addr = addr;
+        self.areas.insert(i + 1, right);
+    }
+
+    /// Record that mappings in `[start_addr, end_addr)` have changed
+    pub fn mark_flush(&mut self, start_addr: VirtAddr, end_addr: VirtAddr) {
+        self.flush_range = Some(match self.flush_range {
+            Some((start, end)) => (start.min(start_addr), end.max(end_addr)),
+            None => (start_addr, end_addr),
+        });
+    }
+
+    /// Take the changed mappings, which need a TLB shootdown
+    pub fn take_flush(&mut self) -> Option<TlbFlush> {
+        let (start, end) = self.flush_range.take()?;
+        Some(TlbFlush {
+            start,
+            end,
+            unmapped: core::mem::take(&mut self.unmapped),
+            tables: core::mem::take(&mut self.dropped_tables),
+            free_table: T::free_table,
+        })
+    }
+
+    /// Jobs writing back `[start_addr, end_addr)` to the files backing it, for `msync`.
+    /// See `MemoryHandler::sync`.
+    pub fn sync(&self, start_addr: VirtAddr, end_addr: VirtAddr) -> Vec<handler::SyncJob> {
+        self.areas
+            .iter()
+            .filter(|area| area.is_overlap_with(start_addr, end_addr))
+            .filter_map(|area| {
+                let start = start_addr.max(area.start_addr);
+                let end = end_addr.min(area.end_addr);
+                area.handler.sync(start, end)
+            })
+            .collect()
     }
 
     /// Get iterator of areas
@@ -354,14 +789,19 @@ impl<T: PageTableExt> MemorySet<T> {
             ref mut areas,
             ..
         } = self;
-        for area in areas.iter() {
-            area.unmap(page_table);
+        for area in areas.drain(..) {
+            // the frames are freed right away
+            area.unmap_keep(page_table);
         }
-        areas.clear();
+        self.freeable.clear();
     }
 
     /// Get physical address of the page of given virtual `addr`
     pub fn translate(&mut self, addr: VirtAddr) -> Option<PhysAddr> {
+        if let Some(target) = self.page_table.huge_page(addr) {
+            // only user pages are mapped huge
+            return Some(target);
+        }
         self.page_table.get_entry(addr).and_then(|entry| {
             if entry.user() {
                 Some(entry.target())
@@ -376,15 +816,82 @@ impl<T: PageTableExt> MemorySet<T> {
         &mut self.page_table
     }
 
-    pub fn handle_page_fault(&mut self, addr: VirtAddr) -> bool {
-        let area = self.areas.iter().find(|area| area.contains(addr));
-        match area {
-            Some(area) => area.handler.handle_page_fault(&mut self.page_table, addr),
-            None => false,
+    /// Test whether `addr` is in a guard area
+    pub fn is_guard(&self, addr: VirtAddr) -> bool {
+        self.areas
+            .iter()
+            .any(|area| area.contains(addr) && area.handler.is_guard())
+    }
+
+    /// Handle page fault on `addr` caused by `cause`.
+    /// Return false if the access is not allowed, e.g. to raise SIGSEGV.
+    pub fn handle_page_fault(&mut self, addr: VirtAddr, cause: PageFaultCause) -> bool {
+        let area = match self.areas.iter().find(|area| area.contains(addr)) {
+            Some(area) => area,
+            None => return false,
+        };
+        if (cause.write && area.attr.readonly) || (cause.exec && !area.attr.execute) {
+            return false;
+        }
+        if self.page_table.huge_page(addr).is_some() {
+            // huge pages are split before their pages are changed,
+            // so the fault came from a stale TLB entry
+            return true;
+        }
+        let (present, writable) = match self.page_table.get_entry(addr) {
+            Some(entry) => (entry.present(), entry.writable()),
+            None => (false, false),
+        };
+        if !present {
+            // not mapped yet
+            let block = addr & !(HUGE_PAGE_SIZE - 1);
+            if area.attr.huge
+                && block >= area.start_addr
+                && block + HUGE_PAGE_SIZE <= area.end_addr
+                && area
+                    .handler
+                    .map_huge(&mut self.page_table, block, &area.attr)
+            {
+                // the table of small pages it replaced may be cached on other CPUs
+                let tables = self.page_table.take_dropped_tables();
+                self.dropped_tables.extend(tables);
+                self.mark_flush(block, block + HUGE_PAGE_SIZE);
+                return true;
+            }
+            area.handler.handle_page_fault(&mut self.page_table, addr)
+        } else if !cause.present && (!cause.write || writable) {
+            // mapped after the fault, e.g. by another thread
+            true
+        } else if cause.write && writable {
+            // made writable by another thread, the fault came from a stale TLB entry
+            true
+        } else if cause.write {
+            // present but read-only
+            let page = addr & !(PAGE_SIZE - 1);
+            if self.freeable.remove(&page) {
+                // written after `lazy_free`, so the contents are wanted again
+                let entry = self.page_table.get_entry(addr).unwrap();
+                area.attr.apply(entry);
+                return true;
+            }
+            // no page is shared copy-on-write, fork copies them
+            false
+        } else {
+            false
         }
     }
 
-    pub fn clone(&mut self) -> Self {
+    /// Copy the memory set, as `fork`.
+    /// Huge pages are split to be copied page by page, which fails if that can't be done.
+    pub fn clone(&mut self) -> VMResult<Self> {
+        let ranges: Vec<(VirtAddr, VirtAddr)> = self
+            .areas
+            .iter()
+            .map(|area| (area.start_addr, area.end_addr))
+            .collect();
+        for (start_addr, end_addr) in ranges {
+            self.split_huge(start_addr, end_addr)?;
+        }
         let mut new_page_table = T::new();
         let Self {
             ref mut page_table,
@@ -401,16 +908,23 @@ impl<T: PageTableExt> MemorySet<T> {
                 );
             }
         }
-        MemorySet {
+        Ok(MemorySet {
             areas: areas.clone(),
             page_table: new_page_table,
-        }
+            flush_range: None,
+            unmapped: Vec::new(),
+            dropped_tables: Vec::new(),
+            freeable: BTreeSet::new(),
+        })
     }
 }
 
 impl<T: PageTableExt> Drop for MemorySet<T> {
     fn drop(&mut self) {
         self.clear();
+        for &table in self.dropped_tables.iter() {
+            T::free_table(table);
+        }
     }
 }
 
@@ -27,6 +27,9 @@ pub struct MockEntry {
     writable_shared: bool,
     readonly_shared: bool,
     swapped: bool,
+    user: bool,
+    execute: bool,
+    mmio: u8,
 }
 
 impl Entry for MockEntry {
@@ -82,22 +85,22 @@ impl Entry for MockEntry {
         self.swapped = value;
     }
     fn user(&self) -> bool {
-        unimplemented!()
+        self.user
     }
-    fn set_user(&mut self, _value: bool) {
-        unimplemented!()
+    fn set_user(&mut self, value: bool) {
+        self.user = value;
     }
     fn execute(&self) -> bool {
-        unimplemented!()
+        self.execute
     }
-    fn set_execute(&mut self, _value: bool) {
-        unimplemented!()
+    fn set_execute(&mut self, value: bool) {
+        self.execute = value;
     }
     fn mmio(&self) -> u8 {
-        unimplemented!()
+        self.mmio
     }
-    fn set_mmio(&mut self, _value: u8) {
-        unimplemented!()
+    fn set_mmio(&mut self, value: u8) {
+        self.mmio = value;
     }
 }
 
@@ -136,6 +139,7 @@ impl PageTable for MockPageTable {
         self._write(addr);
         self.data[self.translate(addr)] = data;
     }
+    fn flush_cache_copy_user(&mut self, _start: VirtAddr, _end: VirtAddr, _execute: bool) {}
 }
 
 impl MockPageTable {
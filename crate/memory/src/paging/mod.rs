@@ -5,6 +5,7 @@
 #[cfg(test)]
 pub use self::mock_page_table::MockPageTable;
 use super::*;
+use alloc::vec::Vec;
 
 #[cfg(test)]
 mod mock_page_table;
@@ -19,8 +20,40 @@ pub trait PageTable {
     /// Unmap a page of virual address `addr`
     fn unmap(&mut self, addr: VirtAddr);
 
+    /// Map the huge page of virtual address `addr`, aligned to `HUGE_PAGE_SIZE`,
+    /// to the contiguous frames from physics address `target`.
+    /// Small pages in the range may have been mapped, but none of them may be present.
+    /// Return the entry of the huge page, or `None` if it can't be mapped,
+    /// e.g. as huge pages are not supported.
+    fn map_huge(&mut self, _addr: VirtAddr, _target: PhysAddr) -> Option<&mut dyn Entry> {
+        None
+    }
+
+    /// Get the frame of the page of virtual address `addr`
+    /// if it is part of a huge page mapped by `map_huge`
+    fn huge_page(&mut self, _addr: VirtAddr) -> Option<PhysAddr> {
+        None
+    }
+
+    /// Split the huge page containing virtual address `addr`, if any,
+    /// into small pages of the same frames, so `get_entry` can reach them.
+    /// Return false if a page table can't be allocated.
+    fn split_huge(&mut self, _addr: VirtAddr) -> bool {
+        true
+    }
+
+    /// Unmap the huge page containing virtual address `addr`
+    fn unmap_huge(&mut self, _addr: VirtAddr) {}
+
+    /// Take the frames of page tables dropped since the last call,
+    /// e.g. those replaced by huge pages, which other CPUs may still walk.
+    /// They are freed by `PageTableExt::free_table` after a TLB shootdown.
+    fn take_dropped_tables(&mut self) -> Vec<PhysAddr> {
+        Vec::new()
+    }
+
     /// Get the page table entry of a page of virual address `addr`
-    /// If its page do not exist, return `None`
+    /// If its page do not exist, or is part of a huge page, return `None`
     fn get_entry(&mut self, addr: VirtAddr) -> Option<&mut dyn Entry>;
 
     /// Get a mutable reference of the content of a page of virtual address `addr`
@@ -108,6 +141,9 @@ pub trait PageTableExt: PageTable + Sized {
     fn active_token() -> usize;
     fn flush_tlb();
 
+    /// Free a page table taken by `PageTable::take_dropped_tables`
+    fn free_table(_frame: PhysAddr) {}
+
     /// Activate this page table
     unsafe fn activate(&self) {
         let old_token = Self::active_token();
@@ -0,0 +1,61 @@
+//! Inter-processor interrupts through the mailboxes of the BCM2836 local peripherals.
+//!
+//! Each core has four mailboxes which raise its IRQ when any bit is set.
+//! Only mailbox 0 is used, and it carries no message: the receiver checks what
+//! it has been asked for, see `memory::fence_on`.
+
+use crate::arch::memory::ioremap;
+use core::ptr::{read_volatile, write_volatile};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+const LOCAL_PERIPHERALS: usize = 0x4000_0000;
+const LOCAL_PERIPHERALS_SIZE: usize = 0x1000;
+/// Mailbox interrupt control of core 0, followed by those of other cores
+const MAILBOX_INT_CONTROL: usize = 0x50;
+/// IRQ source of core 0, followed by those of other cores
+const IRQ_SOURCE: usize = 0x60;
+/// Write-set register of mailbox 0 of core 0, 0x10 bytes apart for each core
+const MAILBOX0_SET: usize = 0x80;
+/// Write-clear register of mailbox 0 of core 0, 0x10 bytes apart for each core
+const MAILBOX0_CLEAR: usize = 0xc0;
+const IRQ_SOURCE_MAILBOX0: u32 = 1 << 4;
+
+static BASE: AtomicUsize = AtomicUsize::new(0);
+
+fn reg(offset: usize) -> *mut u32 {
+    (BASE.load(Ordering::Relaxed) + offset) as *mut u32
+}
+
+/// Map the local peripherals, on the boot core after `memory::init`
+pub fn init() {
+    let base = ioremap(
+        LOCAL_PERIPHERALS,
+        LOCAL_PERIPHERALS_SIZE,
+        "local_peripherals",
+    );
+    BASE.store(base, Ordering::Relaxed);
+    init_other();
+}
+
+/// Enable the IRQ of mailbox 0 on this core
+pub fn init_other() {
+    let cpu = super::super::cpu::id();
+    unsafe { write_volatile(reg(MAILBOX_INT_CONTROL + 4 * cpu), 1) };
+}
+
+/// Interrupt core `cpu`
+pub fn send(cpu: usize) {
+    unsafe { write_volatile(reg(MAILBOX0_SET + 0x10 * cpu), 1) };
+}
+
+/// Acknowledge an IPI to this core, return whether there was one
+pub fn take() -> bool {
+    let cpu = super::super::cpu::id();
+    unsafe {
+        if read_volatile(reg(IRQ_SOURCE + 4 * cpu)) & IRQ_SOURCE_MAILBOX0 == 0 {
+            return false;
+        }
+        write_volatile(reg(MAILBOX0_CLEAR + 0x10 * cpu), !0);
+    }
+    true
+}
@@ -2,6 +2,7 @@
 
 use bcm2837::{addr::bus_to_phys, atags::Atags};
 
+pub mod ipi;
 pub mod mailbox;
 pub mod timer;
 
@@ -1,6 +1,7 @@
 use crate::consts::SMP_CORES;
 use crate::memory::{kernel_offset, phys_to_virt};
 use aarch64::{asm, cache::*};
+use alloc::string::String;
 use core::{cmp, mem};
 
 pub use super::board::{CPU_NUM, CPU_SPIN_TABLE};
@@ -13,6 +14,18 @@ pub fn id() -> usize {
     asm::cpuid()
 }
 
+/// Model of this CPU for `/proc/cpuinfo`
+pub fn model_name() -> String {
+    String::from("ARMv8-A")
+}
+
+/// Features for `AT_HWCAP`: FP and ASIMD, which every ARMv8-A we run on has
+pub fn hwcap() -> usize {
+    const HWCAP_FP: usize = 1 << 0;
+    const HWCAP_ASIMD: usize = 1 << 1;
+    HWCAP_FP | HWCAP_ASIMD
+}
+
 /// Write `slave_startup` address to the spin table to start other CPUs.
 pub unsafe fn start_others() {
     extern "C" {
@@ -1,4 +1,5 @@
 use super::syndrome::{Fault, Syndrome};
+use crate::signal::{Signal, BUS_ADRALN, FPE_FLTINV, ILL_ILLOPC, TRAP_BRKPT, TRAP_TRACE};
 use aarch64::regs::*;
 
 pub fn is_page_fault(trap: usize) -> bool {
@@ -30,7 +31,12 @@ pub const Timer: usize = 0x10002;
 pub const Syscall: usize = 0x00002;
 
 pub fn is_syscall(trap: usize) -> bool {
+    // other synchronous exceptions share the trap number
     trap == Syscall
+        && match Syndrome::from(ESR_EL1.get() as u32) {
+            Syndrome::Svc(_) => true,
+            _ => false,
+        }
 }
 
 pub fn is_intr(trap: usize) -> bool {
@@ -44,3 +50,24 @@ pub fn is_timer_intr(trap: usize) -> bool {
 pub fn is_reserved_inst(trap: usize) -> bool {
     false
 }
+
+/// Signal and `si_code` for a CPU exception from user mode
+pub fn exception_signal(trap: usize) -> Option<(Signal, i32)> {
+    // 2: from lower el, sync error
+    if trap != 0x2 {
+        return None;
+    }
+    let signal = match Syndrome::from(ESR_EL1.get() as u32) {
+        Syndrome::Unknown | Syndrome::IllegalExecutionState => (Signal::SIGILL, ILL_ILLOPC),
+        Syndrome::Brk(_) | Syndrome::Breakpoint => (Signal::SIGTRAP, TRAP_BRKPT),
+        Syndrome::Step => (Signal::SIGTRAP, TRAP_TRACE),
+        Syndrome::PCAlignmentFault | Syndrome::SpAlignmentFault => (Signal::SIGBUS, BUS_ADRALN),
+        Syndrome::DataAbort {
+            kind: Fault::Alignment,
+            level: _,
+        } => (Signal::SIGBUS, BUS_ADRALN),
+        Syndrome::TrappedFpu => (Signal::SIGFPE, FPE_FLTINV),
+        _ => return None,
+    };
+    Some(signal)
+}
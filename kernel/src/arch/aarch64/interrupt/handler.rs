@@ -1,8 +1,6 @@
 //! Trap handler
 
 use super::syndrome::{Fault, Syndrome};
-use crate::arch::board::timer;
-use crate::drivers::IRQ_MANAGER;
 use aarch64::regs::*;
 use log::*;
 use trapframe::TrapFrame;
@@ -81,7 +79,8 @@ pub extern "C" fn trap_handler(tf: &mut TrapFrame) {
                 | Syndrome::InstructionAbort { kind, level: _ } => match kind {
                     Fault::Translation | Fault::AccessFlag | Fault::Permission => {
                         let addr = FAR_EL1.get() as usize;
-                        if !crate::memory::handle_page_fault(addr) {
+                        let cause = super::page_fault_cause(esr);
+                        if !crate::memory::handle_page_fault(addr, cause) {
                             panic!("\nEXCEPTION: Page Fault @ {:#x}", addr);
                         }
                     }
@@ -91,12 +90,7 @@ pub extern "C" fn trap_handler(tf: &mut TrapFrame) {
             }
         }
         Kind::Irq => {
-            if timer::is_pending() {
-                crate::arch::board::timer::set_next();
-                crate::trap::timer();
-            } else {
-                IRQ_MANAGER.read().try_handle_interrupt(Some(tf.trap_num));
-            }
+            super::ack_and_dispatch(tf.trap_num);
         }
         _ => panic!(),
     }
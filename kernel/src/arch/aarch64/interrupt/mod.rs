@@ -2,6 +2,8 @@
 
 pub use self::handler::*;
 use crate::arch::board::timer::is_pending;
+use crate::drivers::IRQ_MANAGER;
+use crate::memory::PageFaultCause;
 use crate::process::thread::Thread;
 use aarch64::regs::*;
 use alloc::sync::Arc;
@@ -11,6 +13,8 @@ pub mod consts;
 pub mod handler;
 mod syndrome;
 
+use self::syndrome::{Fault, Syndrome};
+
 /// Enable the interrupt (only IRQ).
 #[inline(always)]
 pub unsafe fn enable() {
@@ -49,6 +53,23 @@ pub fn timer() {
     }
 }
 
+/// Acknowledge the interrupt `trap_num` and dispatch it to the timer or drivers.
+/// Return true if it is the timer, so that the current thread should yield.
+pub fn ack_and_dispatch(trap_num: usize) -> bool {
+    ack(trap_num);
+    if crate::arch::board::ipi::take() {
+        crate::arch::memory::handle_fence();
+    }
+    if is_pending() {
+        crate::arch::board::timer::set_next();
+        crate::trap::timer();
+        true
+    } else {
+        IRQ_MANAGER.read().try_handle_interrupt(Some(trap_num));
+        false
+    }
+}
+
 pub fn ack(_irq: usize) {
     // TODO
 }
@@ -71,8 +92,30 @@ pub fn wait_for_interrupt() {
     DAIF.set(daif);
 }
 
-pub fn handle_user_page_fault(thread: &Arc<Thread>, addr: usize) -> bool {
-    thread.vm.lock().handle_page_fault(addr)
+pub fn handle_user_page_fault(thread: &Arc<Thread>, addr: usize, cause: PageFaultCause) -> bool {
+    thread.lock_vm().handle_page_fault(addr, cause)
+}
+
+pub fn get_page_fault_cause(_cx: &UserContext) -> PageFaultCause {
+    page_fault_cause(ESR_EL1.get() as u32)
+}
+
+/// Decode the cause of a page fault from the syndrome
+pub(super) fn page_fault_cause(esr: u32) -> PageFaultCause {
+    match Syndrome::from(esr) {
+        Syndrome::DataAbort { kind, level: _ } => PageFaultCause {
+            // WnR bit
+            write: esr & (1 << 6) != 0,
+            exec: false,
+            present: kind == Fault::Permission,
+        },
+        Syndrome::InstructionAbort { kind, level: _ } => PageFaultCause {
+            write: false,
+            exec: true,
+            present: kind == Fault::Permission,
+        },
+        _ => PageFaultCause::default(),
+    }
 }
 
 pub fn handle_reserved_inst(tf: &mut UserContext) -> bool {
@@ -2,11 +2,14 @@
 
 use super::paging::MMIOType;
 use crate::consts::{KERNEL_OFFSET, MEMORY_OFFSET};
-use crate::memory::{init_heap, kernel_offset, Linear, MemoryAttr, MemorySet, FRAME_ALLOCATOR};
+use crate::memory::{
+    init_heap, insert_frames, kernel_offset, Linear, MemoryAttr, MemorySet, FRAME_ALLOCATOR,
+};
 use crate::sync::SpinNoIrqLock as Mutex;
 use aarch64::paging::frame::PhysFrame as Frame;
 use aarch64::regs::*;
 use aarch64::translation::{local_invalidate_tlb_all, ttbr_el1_write};
+use core::sync::atomic::{spin_loop_hint, AtomicUsize, Ordering};
 use log::*;
 use rcore_memory::PAGE_SIZE;
 
@@ -35,7 +38,7 @@ fn init_frame_allocator() {
         .1;
     let start = kernel_offset(_end as usize) + MEMORY_OFFSET + PAGE_SIZE;
     let mut ba = FRAME_ALLOCATOR.lock();
-    ba.insert(to_range(start, end));
+    insert_frames(&mut ba, to_range(start, end));
     info!("FrameAllocator init end");
 
     /// Transform memory area `[start, end)` to integer range for `FrameAllocator`
@@ -141,3 +144,37 @@ pub fn set_page_table(vmtoken: usize) {
 pub fn get_page_fault_addr() -> usize {
     FAR_EL1.get() as usize
 }
+
+/// Invalidate TLB entries of `[start, end)` on `cpus`
+pub fn flush_tlb_on(_cpus: &[usize], _start: usize, _end: usize) {
+    // broadcast to all cores in the inner shareable domain
+    unsafe {
+        llvm_asm!("dsb ishst; tlbi vmalle1is; dsb ish; isb" :::: "volatile");
+    }
+}
+
+/// Cores asked by `fence_on` to issue a memory barrier, one bit each
+static FENCE_PENDING: AtomicUsize = AtomicUsize::new(0);
+
+/// Issue a full memory barrier on `cpus` by IPI, and wait for them
+pub fn fence_on(cpus: &[usize]) {
+    let mask = cpus.iter().fold(0, |mask, cpu| mask | (1 << cpu));
+    FENCE_PENDING.fetch_or(mask, Ordering::SeqCst);
+    for &cpu in cpus {
+        super::board::ipi::send(cpu);
+    }
+    while FENCE_PENDING.load(Ordering::SeqCst) & mask != 0 {
+        // one of them may be waiting for this core meanwhile
+        handle_fence();
+        spin_loop_hint();
+    }
+}
+
+/// Issue the memory barrier asked by `fence_on` if any, on IPI
+pub fn handle_fence() {
+    let bit = 1 << super::cpu::id();
+    if FENCE_PENDING.load(Ordering::SeqCst) & bit != 0 {
+        unsafe { llvm_asm!("dmb ish" ::: "memory" : "volatile") };
+        FENCE_PENDING.fetch_and(!bit, Ordering::SeqCst);
+    }
+}
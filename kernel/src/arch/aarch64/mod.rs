@@ -32,6 +32,7 @@ pub extern "C" fn main_start() -> ! {
         trapframe::init();
     }
     memory::init();
+    board::ipi::init();
 
     board::early_init();
     println!("Hello {}! from CPU {}", board::BOARD_NAME, cpu::id());
@@ -40,6 +41,7 @@ pub extern "C" fn main_start() -> ! {
     crate::lkm::manager::ModuleManager::init();
     board::init();
 
+    crate::net::init();
     crate::process::init();
 
     // wake up other CPUs
@@ -60,6 +62,7 @@ pub extern "C" fn others_start() -> ! {
         trapframe::init();
     }
     memory::init_other();
+    board::ipi::init_other();
     //timer::init();
     crate::kmain();
 }
@@ -8,6 +8,9 @@ pub const KERNEL_OFFSET: usize = 0x8010_0000;
 
 pub const PHYSICAL_MEMORY_OFFSET: usize = 0x8000_0000;
 
+/// End of user address space, kseg0 and above are kernel only
+pub const USER_SPACE_END: usize = 0x8000_0000;
+
 pub const USER_STACK_OFFSET: usize = 0x7000_0000 - USER_STACK_SIZE;
 pub const USER_STACK_SIZE: usize = 0x10000;
 
@@ -1,4 +1,5 @@
 use crate::consts::MAX_CPU_NUM;
+use alloc::string::String;
 use core::ptr::{read_volatile, write_volatile};
 use mips::instructions;
 use mips::registers::cp0;
@@ -9,6 +10,16 @@ pub fn id() -> usize {
     (cp0::ebase::read_u32() as usize) & 0x3ff
 }
 
+/// Model of this CPU for `/proc/cpuinfo`
+pub fn model_name() -> String {
+    String::from("MIPS32")
+}
+
+/// Features for `AT_HWCAP`, none beyond the base ISA
+pub fn hwcap() -> usize {
+    0
+}
+
 pub unsafe fn has_started(cpu_id: usize) -> bool {
     read_volatile(&STARTED[cpu_id])
 }
@@ -1,3 +1,6 @@
+use crate::signal::{
+    Signal, BUS_ADRALN, BUS_ADRERR, FPE_FLTINV, FPE_INTOVF, ILL_ILLOPC, SI_KERNEL, TRAP_BRKPT,
+};
 use mips::registers::cp0;
 
 pub fn is_page_fault(trap: usize) -> bool {
@@ -44,3 +47,26 @@ pub fn is_reserved_inst(trap: usize) -> bool {
         _ => false,
     }
 }
+
+/// Signal and `si_code` for a CPU exception from user mode
+pub fn exception_signal(trap: usize) -> Option<(Signal, i32)> {
+    // ExcCode field of the cause register
+    let signal = match (trap >> 2) & 0x1f {
+        // AdEL, AdES
+        4 | 5 => (Signal::SIGBUS, BUS_ADRALN),
+        // IBE, DBE
+        6 | 7 => (Signal::SIGBUS, BUS_ADRERR),
+        // Bp
+        9 => (Signal::SIGTRAP, TRAP_BRKPT),
+        // RI, not emulated
+        10 => (Signal::SIGILL, ILL_ILLOPC),
+        // Ov
+        12 => (Signal::SIGFPE, FPE_INTOVF),
+        // Tr
+        13 => (Signal::SIGTRAP, SI_KERNEL),
+        // FPE
+        15 => (Signal::SIGFPE, FPE_FLTINV),
+        _ => return None,
+    };
+    Some(signal)
+}
@@ -1,5 +1,6 @@
 use crate::arch::paging::get_root_page_table_ptr;
 use crate::drivers::IRQ_MANAGER;
+use crate::memory::PageFaultCause;
 use crate::process::thread::Thread;
 use alloc::sync::Arc;
 use log::*;
@@ -66,7 +67,9 @@ pub extern "C" fn trap_handler(tf: &mut TrapFrame) {
     };
     trace!("Exception @ CPU{}: {:?} ", 0, cause.cause());
     match cause.cause() {
-        E::Interrupt => interrupt_dispatcher(tf),
+        E::Interrupt => {
+            ack_and_dispatch(tf.cause);
+        }
         E::Syscall => syscall(tf),
         E::TLBModification => page_fault(tf),
         E::TLBLoadMiss => page_fault(tf),
@@ -78,14 +81,19 @@ pub extern "C" fn trap_handler(tf: &mut TrapFrame) {
     trace!("Interrupt end");
 }
 
-fn interrupt_dispatcher(tf: &mut TrapFrame) {
+/// Acknowledge the interrupt `trap_num`, the cause register, and dispatch it
+/// to the timer or drivers.
+/// Return true if it is the timer, so that the current thread should yield.
+pub fn ack_and_dispatch(trap_num: usize) -> bool {
+    ack(trap_num);
     let cause = cp0::cause::Cause {
-        bits: tf.cause as u32,
+        bits: trap_num as u32,
     };
     let pint = cause.pending_interrupt();
     trace!("  Interrupt {:08b} ", pint);
     if (pint & 0b100_000_00) != 0 {
         timer();
+        return true;
     } else if (pint & 0b011_111_00) != 0 {
         for i in 0..6 {
             if (pint & (1 << i)) != 0 {
@@ -95,6 +103,7 @@ fn interrupt_dispatcher(tf: &mut TrapFrame) {
     } else {
         ipi();
     }
+    false
 }
 
 fn ipi() {
@@ -205,7 +214,7 @@ pub fn handle_reserved_inst(tf: &mut UserContext) -> bool {
     false
 }
 
-pub fn handle_user_page_fault(thread: &Arc<Thread>, addr: usize) -> bool {
+pub fn handle_user_page_fault(thread: &Arc<Thread>, addr: usize, cause: PageFaultCause) -> bool {
     let virt_addr = VirtAddr::new(addr);
     let root_table = unsafe { &mut *(get_root_page_table_ptr() as *mut MIPSPageTable) };
     let tlb_result = root_table.lookup(addr);
@@ -223,8 +232,8 @@ pub fn handle_user_page_fault(thread: &Arc<Thread>, addr: usize) -> bool {
                 tlb_entry.entry_lo1.valid()
             };
 
-            if !tlb_valid {
-                if !thread.vm.lock().handle_page_fault(addr) {
+            if !tlb_valid || cause.present {
+                if !thread.lock_vm().handle_page_fault(addr, cause) {
                     return false;
                 }
             }
@@ -233,14 +242,40 @@ pub fn handle_user_page_fault(thread: &Arc<Thread>, addr: usize) -> bool {
             true
         }
         Err(()) => {
-            return thread.vm.lock().handle_page_fault(addr);
+            return thread.lock_vm().handle_page_fault(addr, cause);
         }
     }
 }
 
+pub fn get_page_fault_cause(cx: &UserContext) -> PageFaultCause {
+    page_fault_cause(cx.cause)
+}
+
+/// Decode the cause of a page fault from the cause register.
+/// It does not tell instruction fetches from loads.
+fn page_fault_cause(cause: usize) -> PageFaultCause {
+    use cp0::cause::Exception as E;
+    let cause = cp0::cause::Cause { bits: cause as u32 };
+    match cause.cause() {
+        // write to a valid TLB entry without the dirty bit, i.e. read-only
+        E::TLBModification => PageFaultCause {
+            write: true,
+            exec: false,
+            present: true,
+        },
+        E::TLBStoreMiss => PageFaultCause {
+            write: true,
+            exec: false,
+            present: false,
+        },
+        _ => PageFaultCause::default(),
+    }
+}
+
 fn page_fault(tf: &mut TrapFrame) {
     // TODO: set access/dirty bit
     let addr = tf.vaddr;
+    let cause = page_fault_cause(tf.cause);
     // info!("\nEXCEPTION: Page Fault @ {:#x}", addr);
 
     let virt_addr = VirtAddr::new(addr);
@@ -260,8 +295,8 @@ fn page_fault(tf: &mut TrapFrame) {
                 tlb_entry.entry_lo1.valid()
             };
 
-            if !tlb_valid {
-                if !crate::memory::handle_page_fault(addr) {
+            if !tlb_valid || cause.present {
+                if !crate::memory::handle_page_fault(addr, cause) {
                     extern "C" {
                         fn _copy_user_start();
                         fn _copy_user_end();
@@ -278,7 +313,7 @@ fn page_fault(tf: &mut TrapFrame) {
             tlb_entry.write_random()
         }
         Err(()) => {
-            if !crate::memory::handle_page_fault(addr) {
+            if !crate::memory::handle_page_fault(addr, cause) {
                 extern "C" {
                     fn _copy_user_start();
                     fn _copy_user_end();
@@ -1,6 +1,6 @@
 use crate::arch::paging::*;
 use crate::consts::{KERNEL_OFFSET, MEMORY_END, MEMORY_OFFSET};
-use crate::memory::{init_heap, FRAME_ALLOCATOR};
+use crate::memory::{init_heap, insert_frames, FRAME_ALLOCATOR};
 use mips::registers::cp0;
 use rcore_memory::PAGE_SIZE;
 
@@ -31,7 +31,7 @@ fn init_frame_allocator() {
         (end as usize) - KERNEL_OFFSET + MEMORY_OFFSET + PAGE_SIZE,
         MEMORY_END,
     );
-    ba.insert(range);
+    insert_frames(&mut ba, range);
 
     info!("frame allocator: init end");
 
@@ -80,3 +80,13 @@ pub fn set_page_table(vmtoken: usize) {
 pub fn get_page_fault_addr() -> usize {
     cp0::bad_vaddr::read_u32() as usize
 }
+
+/// Invalidate TLB entries of `[start, end)` on `cpus`
+pub fn flush_tlb_on(_cpus: &[usize], _start: usize, _end: usize) {
+    // only one core runs user threads on mipsel
+}
+
+/// Issue a full memory barrier on `cpus`, and wait for them
+pub fn fence_on(_cpus: &[usize]) {
+    // only one core runs user threads on mipsel
+}
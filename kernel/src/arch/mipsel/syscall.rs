@@ -382,6 +382,9 @@ define_syscall!(PKEY_FREE, 365);
 define_syscall!(STATX, 366);
 define_syscall!(RSEQ, 367);
 define_syscall!(IO_PGETEVENTS, 368);
+define_syscall!(IO_URING_SETUP, 425);
+define_syscall!(IO_URING_ENTER, 426);
+define_syscall!(CLOSE_RANGE, 436);
 
 // non-existent syscalls, will not be called or matched
 pub const SYS_NEWFSTATAT: usize = 0;
@@ -389,3 +392,4 @@ pub const SYS_NEWFSTATAT: usize = 0;
 // custom temporary syscall
 pub const SYS_MAP_PCI_DEVICE: usize = 999;
 pub const SYS_GET_PADDR: usize = 998;
+pub const SYS_SYSCALL_TRACE: usize = 997;
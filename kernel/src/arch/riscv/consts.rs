@@ -15,6 +15,12 @@ pub const MEMORY_OFFSET: usize = 0x8000_0000;
 // TODO: get memory end from device tree
 pub const MEMORY_END: usize = 0x8800_0000;
 
+/// End of user address space
+#[cfg(target_arch = "riscv32")]
+pub const USER_SPACE_END: usize = 0x8000_0000;
+#[cfg(target_arch = "riscv64")]
+pub const USER_SPACE_END: usize = 0x0000_0040_0000_0000;
+
 // TODO: rv64 `sh` and `ls` will crash if stack top > 0x80000000 ???
 pub const USER_STACK_OFFSET: usize = 0x40000000 - USER_STACK_SIZE;
 pub const USER_STACK_SIZE: usize = 0x10000;
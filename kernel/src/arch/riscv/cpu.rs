@@ -1,3 +1,5 @@
+use alloc::string::String;
+
 // tp is unused in kernel space
 pub unsafe fn set_cpu_id(cpu_id: usize) {
     llvm_asm!("mv tp, $0" : : "r"(cpu_id));
@@ -11,6 +13,16 @@ pub fn id() -> usize {
     cpu_id
 }
 
+/// Model of this CPU for `/proc/cpuinfo`
+pub fn model_name() -> String {
+    String::from("RISC-V")
+}
+
+/// Features for `AT_HWCAP`, none beyond the base ISA
+pub fn hwcap() -> usize {
+    0
+}
+
 pub fn send_ipi(cpu_id: usize) {
     super::sbi::send_ipi(1 << cpu_id);
 }
@@ -1,3 +1,13 @@
+use crate::signal::{Signal, BUS_ADRALN, ILL_ILLOPC, SEGV_ACCERR, TRAP_BRKPT};
+
+pub const InstructionMisaligned: usize = 0;
+pub const InstructionFault: usize = 1;
+pub const IllegalInstruction: usize = 2;
+pub const Breakpoint: usize = 3;
+pub const LoadMisaligned: usize = 4;
+pub const LoadFault: usize = 5;
+pub const StoreMisaligned: usize = 6;
+pub const StoreFault: usize = 7;
 pub const Syscall: usize = 8;
 pub const InstructionPageFault: usize = 12;
 pub const LoadPageFault: usize = 13;
@@ -7,6 +17,7 @@ pub const StorePageFault: usize = 15;
 pub const IrqMin: usize = usize::MAX / 2;
 pub const IrqMax: usize = usize::MAX;
 
+pub const SupervisorSoft: usize = usize::MAX / 2 + 1 + 1;
 pub const Timer: usize = usize::MAX / 2 + 1 + 5;
 pub const SupervisorExternal: usize = usize::MAX / 2 + 1 + 8;
 
@@ -29,3 +40,15 @@ pub fn is_timer_intr(trap: usize) -> bool {
 pub fn is_reserved_inst(trap: usize) -> bool {
     false
 }
+
+/// Signal and `si_code` for a CPU exception from user mode
+pub fn exception_signal(trap: usize) -> Option<(Signal, i32)> {
+    let signal = match trap {
+        IllegalInstruction => (Signal::SIGILL, ILL_ILLOPC),
+        Breakpoint => (Signal::SIGTRAP, TRAP_BRKPT),
+        InstructionMisaligned | LoadMisaligned | StoreMisaligned => (Signal::SIGBUS, BUS_ADRALN),
+        InstructionFault | LoadFault | StoreFault => (Signal::SIGSEGV, SEGV_ACCERR),
+        _ => return None,
+    };
+    Some(signal)
+}
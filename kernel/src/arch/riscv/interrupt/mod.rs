@@ -1,5 +1,6 @@
 use crate::arch::interrupt::consts::SupervisorExternal;
 use crate::drivers::IRQ_MANAGER;
+use crate::memory::PageFaultCause;
 use crate::process::thread::Thread;
 use alloc::sync::Arc;
 use log::*;
@@ -9,6 +10,11 @@ use trapframe::{TrapFrame, UserContext};
 
 pub mod consts;
 
+/// Enable inter-processor interrupts on this hart, see `memory::fence_on`
+pub unsafe fn init_ipi() {
+    sie::set_ssoft();
+}
+
 /// Enable interrupt
 #[inline]
 pub unsafe fn enable() {
@@ -36,17 +42,19 @@ pub unsafe fn restore(flags: usize) {
 /// This function is called from `trap.asm`.
 #[no_mangle]
 pub extern "C" fn trap_handler(tf: &mut TrapFrame) {
-    use self::scause::{Exception as E, Interrupt as I, Trap};
+    use self::scause::{Exception as E, Trap};
     let scause = scause::read();
     let stval = stval::read();
     trace!("Interrupt @ CPU{}: {:?} ", super::cpu::id(), scause.cause());
     match scause.cause() {
-        Trap::Interrupt(I::SupervisorExternal) => external(),
-        Trap::Interrupt(I::SupervisorSoft) => ipi(),
-        Trap::Interrupt(I::SupervisorTimer) => timer(),
-        Trap::Exception(E::LoadPageFault) => page_fault(stval, tf),
-        Trap::Exception(E::StorePageFault) => page_fault(stval, tf),
-        Trap::Exception(E::InstructionPageFault) => page_fault(stval, tf),
+        Trap::Interrupt(_) => {
+            ack_and_dispatch(scause.bits());
+        }
+        Trap::Exception(E::LoadPageFault)
+        | Trap::Exception(E::StorePageFault)
+        | Trap::Exception(E::InstructionPageFault) => {
+            page_fault(stval, page_fault_cause(scause.bits()), tf)
+        }
         _ => panic!("unhandled trap {:?}", scause.cause()),
     }
     trace!("Interrupt end");
@@ -66,6 +74,7 @@ fn external() {
 fn ipi() {
     debug!("IPI");
     super::sbi::clear_ipi();
+    super::memory::handle_fence();
 }
 
 pub fn timer() {
@@ -73,11 +82,11 @@ pub fn timer() {
     crate::trap::timer();
 }
 
-fn page_fault(stval: usize, tf: &mut TrapFrame) {
+fn page_fault(stval: usize, cause: PageFaultCause, tf: &mut TrapFrame) {
     let addr = stval;
     trace!("\nEXCEPTION: Page Fault @ {:#x}", addr);
 
-    if crate::memory::handle_page_fault(addr) {
+    if crate::memory::handle_page_fault(addr, cause) {
         return;
     }
     extern "C" {
@@ -92,6 +101,22 @@ fn page_fault(stval: usize, tf: &mut TrapFrame) {
     panic!("unhandled page fault");
 }
 
+/// Acknowledge the interrupt `trap_num` and dispatch it to the timer or drivers.
+/// Return true if it is the timer, so that the current thread should yield.
+pub fn ack_and_dispatch(trap_num: usize) -> bool {
+    ack(trap_num);
+    match trap_num {
+        consts::Timer => {
+            timer();
+            return true;
+        }
+        consts::SupervisorExternal => external(),
+        consts::SupervisorSoft => ipi(),
+        _ => warn!("unhandled interrupt {:#x}", trap_num),
+    }
+    false
+}
+
 pub fn ack(irq: usize) {
     // Handled in PLIC driver
 }
@@ -116,8 +141,23 @@ pub fn wait_for_interrupt() {
     }
 }
 
-pub fn handle_user_page_fault(thread: &Arc<Thread>, addr: usize) -> bool {
-    thread.vm.lock().handle_page_fault(addr)
+pub fn handle_user_page_fault(thread: &Arc<Thread>, addr: usize, cause: PageFaultCause) -> bool {
+    thread.lock_vm().handle_page_fault(addr, cause)
+}
+
+pub fn get_page_fault_cause(_context: &UserContext) -> PageFaultCause {
+    page_fault_cause(scause::read().bits())
+}
+
+/// Decode the cause of a page fault from scause.
+/// It does not tell whether the page is present.
+fn page_fault_cause(scause: usize) -> PageFaultCause {
+    use self::consts::{InstructionPageFault, StorePageFault};
+    PageFaultCause {
+        write: scause == StorePageFault,
+        exec: scause == InstructionPageFault,
+        present: false,
+    }
 }
 
 pub fn handle_reserved_inst(tf: &mut UserContext) -> bool {
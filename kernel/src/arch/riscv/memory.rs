@@ -1,6 +1,7 @@
 use crate::consts::{KERNEL_OFFSET, MEMORY_END, MEMORY_OFFSET};
-use crate::memory::{init_heap, MemorySet, FRAME_ALLOCATOR};
+use crate::memory::{init_heap, insert_frames, MemorySet, FRAME_ALLOCATOR};
 use core::mem;
+use core::sync::atomic::{spin_loop_hint, AtomicUsize, Ordering};
 use log::*;
 use rcore_memory::PAGE_SIZE;
 use riscv::asm::sfence_vma_all;
@@ -35,7 +36,7 @@ fn init_frame_allocator() {
         (end as usize) - KERNEL_OFFSET + MEMORY_OFFSET + PAGE_SIZE,
         MEMORY_END,
     );
-    ba.insert(range);
+    insert_frames(&mut ba, range);
 
     info!("frame allocator: init end");
 
@@ -99,3 +100,33 @@ pub fn set_page_table(vmtoken: usize) {
     satp::write(vmtoken);
     unsafe { sfence_vma_all() }
 }
+
+/// Invalidate TLB entries of `[start, end)` on `cpus`
+pub fn flush_tlb_on(cpus: &[usize], start: usize, end: usize) {
+    let hart_mask = cpus.iter().fold(0, |mask, cpu| mask | (1 << cpu));
+    super::sbi::remote_sfence_vma(hart_mask, start, end - start);
+}
+
+/// Harts asked by `fence_on` to issue a memory barrier, one bit each
+static FENCE_PENDING: AtomicUsize = AtomicUsize::new(0);
+
+/// Issue a full memory barrier on `cpus` by IPI, and wait for them
+pub fn fence_on(cpus: &[usize]) {
+    let hart_mask = cpus.iter().fold(0, |mask, cpu| mask | (1 << cpu));
+    FENCE_PENDING.fetch_or(hart_mask, Ordering::SeqCst);
+    super::sbi::send_ipi(hart_mask);
+    while FENCE_PENDING.load(Ordering::SeqCst) & hart_mask != 0 {
+        // one of them may be waiting for this hart meanwhile
+        handle_fence();
+        spin_loop_hint();
+    }
+}
+
+/// Issue the memory barrier asked by `fence_on` if any, on IPI
+pub fn handle_fence() {
+    let bit = 1 << super::cpu::id();
+    if FENCE_PENDING.load(Ordering::SeqCst) & bit != 0 {
+        unsafe { llvm_asm!("fence rw, rw" ::: "memory" : "volatile") };
+        FENCE_PENDING.fetch_and(!bit, Ordering::SeqCst);
+    }
+}
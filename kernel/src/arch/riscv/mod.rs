@@ -53,12 +53,16 @@ pub extern "C" fn rust_main(hartid: usize, device_tree_paddr: usize) -> ! {
     }
     memory::init(device_tree_vaddr);
     timer::init();
+    unsafe {
+        interrupt::init_ipi();
+    }
     // TODO: init driver on u540
     #[cfg(not(any(feature = "board_u540")))]
     board::init(device_tree_vaddr);
     unsafe {
         board::init_external_interrupt();
     }
+    crate::net::init();
     crate::process::init();
 
     AP_CAN_INIT.store(true, Ordering::Relaxed);
@@ -71,6 +75,9 @@ fn others_main(hartid: usize) -> ! {
     }
     memory::init_other();
     timer::init();
+    unsafe {
+        interrupt::init_ipi();
+    }
     info!("Hello RISCV! in hart {}", hartid);
     crate::kmain();
 }
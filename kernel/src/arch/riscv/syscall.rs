@@ -277,9 +277,13 @@ pub const SYS_PWRITEV2: usize = 287;
 pub const SYS_PKEY_MPROTECT: usize = 288;
 pub const SYS_PKEY_ALLOC: usize = 289;
 pub const SYS_PKEY_FREE: usize = 290;
+pub const SYS_IO_URING_SETUP: usize = 425;
+pub const SYS_IO_URING_ENTER: usize = 426;
+pub const SYS_CLOSE_RANGE: usize = 436;
 pub const SYS_SYSRISCV: usize = SYS_ARCH_SPECIFIC_SYSCALL;
 pub const SYS_RISCV_FLUSH_ICACHE: usize = SYS_SYSRISCV + 15;
 
 // custom temporary syscall
 pub const SYS_MAP_PCI_DEVICE: usize = 999;
 pub const SYS_GET_PADDR: usize = 998;
+pub const SYS_SYSCALL_TRACE: usize = 997;
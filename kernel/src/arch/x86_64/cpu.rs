@@ -1,4 +1,6 @@
+use super::interrupt::consts::IPIWakeup;
 use crate::memory::phys_to_virt;
+use alloc::string::String;
 use apic::{LocalApic, XApic};
 use raw_cpuid::CpuId;
 use x86_64::registers::control::{Cr0, Cr0Flags, Cr4, Cr4Flags};
@@ -31,14 +33,32 @@ pub fn id() -> usize {
         .initial_local_apic_id() as usize
 }
 
+/// The brand string of this CPU from CPUID, for `/proc/cpuinfo`
+pub fn model_name() -> String {
+    CpuId::new()
+        .get_extended_function_info()
+        .and_then(|info| {
+            info.processor_brand_string()
+                .map(|s| String::from(s.trim()))
+        })
+        .unwrap_or_else(|| String::from("x86_64"))
+}
+
+/// Features for `AT_HWCAP`, the EDX of CPUID leaf 1 as on Linux
+pub fn hwcap() -> usize {
+    unsafe { core::arch::x86_64::__cpuid(1) }.edx as usize
+}
+
+/// Wake up `cpu_id` halted in `wait_for_interrupt`
 pub fn send_ipi(cpu_id: usize) {
     let mut lapic = unsafe { XApic::new(phys_to_virt(0xfee00000)) };
-    lapic.send_ipi(cpu_id as u8, 0x30); // TODO: Find a IPI trap num
+    lapic.send_ipi(cpu_id as u8, IPIWakeup as u8);
 }
 
 pub fn init() {
     let mut lapic = unsafe { XApic::new(phys_to_virt(0xfee00000)) };
     lapic.cpu_init();
+    super::timer::init_cpu();
 
     // enable FPU, the manual Volume 3 Chapter 13
     unsafe {
@@ -1,5 +1,7 @@
+use super::interrupt::consts::DoubleFault;
 use super::ipi::IPIEventItem;
 use crate::consts::MAX_CPU_NUM;
+use crate::memory::KernelStack;
 use crate::sync::SpinLock as Mutex;
 use alloc::vec::*;
 use core::sync::atomic::{AtomicBool, Ordering};
@@ -114,6 +116,43 @@ impl Cpu {
 
 pub const DOUBLE_FAULT_IST_INDEX: usize = 0;
 
+/// Pointer loaded by `lgdt` and `lidt`
+#[repr(C, packed)]
+#[derive(Default)]
+struct TablePointer {
+    limit: u16,
+    base: u64,
+}
+
+/// Give the double fault handler of this CPU a stack of its own,
+/// in the TSS and IDT set up by `trapframe::init`.
+///
+/// A kernel stack overflow faults again when the page fault frame is pushed onto the guard page,
+/// which turns into a double fault. Without a known good stack that would reset the machine.
+pub unsafe fn init_double_fault_stack() {
+    let stack = KernelStack::new();
+    let top = stack.top();
+    // used by this CPU forever
+    core::mem::forget(stack);
+
+    // find the TSS from its descriptor in the GDT
+    let mut gdt = TablePointer::default();
+    let selector: u16;
+    llvm_asm!("sgdt ($0)" :: "r"(&mut gdt) : "memory");
+    llvm_asm!("str $0" : "=r"(selector));
+    let descriptor = (gdt.base as *const u64).add(selector as usize >> 3);
+    let (low, high) = (*descriptor, *descriptor.add(1));
+    let base = ((low >> 16) & 0xff_ffff) | ((low >> 56) << 24) | ((high & 0xffff_ffff) << 32);
+    let tss = &mut *(base as *mut TaskStateSegment);
+    tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX] = VirtAddr::new(top as u64);
+
+    // the low 3 bits of the options of a gate select the IST entry, counting from 1
+    let mut idt = TablePointer::default();
+    llvm_asm!("sidt ($0)" :: "r"(&mut idt) : "memory");
+    let options = (idt.base as *mut u16).add(DoubleFault * 8 + 2);
+    *options = (*options & !0b111) | (DOUBLE_FAULT_IST_INDEX as u16 + 1);
+}
+
 // Copied from xv6 x86_64
 const KCODE: Descriptor = Descriptor::UserSegment(0x0020980000000000); // EXECUTABLE | USER_SEGMENT | PRESENT | LONG_MODE
 const UCODE: Descriptor = Descriptor::UserSegment(0x0020F80000000000); // EXECUTABLE | USER_SEGMENT | USER_MODE | PRESENT | LONG_MODE
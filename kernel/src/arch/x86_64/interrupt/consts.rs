@@ -1,6 +1,10 @@
 #![allow(non_upper_case_globals)]
 // Reference: https://wiki.osdev.org/Exceptions
 
+use crate::signal::{
+    Signal, BUS_ADRALN, FPE_FLTINV, FPE_INTDIV, ILL_ILLOPN, SI_KERNEL, TRAP_TRACE,
+};
+
 pub const DivideError: usize = 0;
 pub const Debug: usize = 1;
 pub const NonMaskableInterrupt: usize = 2;
@@ -24,7 +28,8 @@ pub const VirtualizationException: usize = 20;
 pub const SecurityException: usize = 30;
 
 pub const IrqMin: usize = 0x20;
-pub const IrqMax: usize = 0x3f;
+/// Vectors above 0x3f are for MSI and MSI-X
+pub const IrqMax: usize = 0xdf;
 pub const Syscall: usize = 0x100;
 
 // IRQ
@@ -43,8 +48,13 @@ pub const PIRQF: usize = 21;
 pub const PIRQG: usize = 22;
 pub const PIRQH: usize = 23;
 
+/// Irqs routed by the IOAPIC, those above are message signalled
+pub const IoApicPins: usize = 24;
+
 // IPI constants
 pub const IPIFuncCall: usize = 0xfc;
+/// Wakes up a halted CPU to run tasks, doing nothing else
+pub const IPIWakeup: usize = 0xfd;
 
 pub fn is_page_fault(trap: usize) -> bool {
     trap == PageFault
@@ -55,7 +65,7 @@ pub fn is_syscall(trap: usize) -> bool {
 }
 
 pub fn is_intr(trap: usize) -> bool {
-    IrqMin <= trap && trap <= IrqMax
+    IrqMin <= trap && trap <= IrqMax || trap == IPIFuncCall || trap == IPIWakeup
 }
 
 pub fn is_timer_intr(trap: usize) -> bool {
@@ -65,3 +75,20 @@ pub fn is_timer_intr(trap: usize) -> bool {
 pub fn is_reserved_inst(trap: usize) -> bool {
     false
 }
+
+/// Signal and `si_code` for a CPU exception from user mode
+pub fn exception_signal(trap: usize) -> Option<(Signal, i32)> {
+    let signal = match trap {
+        DivideError => (Signal::SIGFPE, FPE_INTDIV),
+        Debug => (Signal::SIGTRAP, TRAP_TRACE),
+        Breakpoint => (Signal::SIGTRAP, SI_KERNEL),
+        Overflow | BoundRangeExceeded => (Signal::SIGSEGV, SI_KERNEL),
+        InvalidOpcode => (Signal::SIGILL, ILL_ILLOPN),
+        GeneralProtectionFault => (Signal::SIGSEGV, SI_KERNEL),
+        SegmentNotPresent | StackSegmentFault => (Signal::SIGBUS, SI_KERNEL),
+        AlignmentCheck => (Signal::SIGBUS, BUS_ADRALN),
+        FloatingPointException | SIMDFloatingPointException => (Signal::SIGFPE, FPE_FLTINV),
+        _ => return None,
+    };
+    Some(signal)
+}
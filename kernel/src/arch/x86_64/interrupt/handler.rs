@@ -66,7 +66,7 @@
 
 use super::consts::*;
 use super::TrapFrame;
-use crate::drivers::IRQ_MANAGER;
+use crate::memory::PageFaultCause;
 use bitflags::*;
 use log::*;
 use x86_64::registers::control::Cr2;
@@ -84,51 +84,46 @@ pub extern "C" fn trap_handler(tf: &mut TrapFrame) {
     match tf.trap_num {
         DoubleFault => double_fault(tf),
         PageFault => page_fault(tf),
-        IrqMin..=IrqMax => {
-            let irq = tf.trap_num - IrqMin;
-            super::ack(irq); // must ack before switching
-            match tf.trap_num {
-                Timer => {
-                    crate::trap::timer();
-                }
-                _ => {
-                    if IRQ_MANAGER.read().try_handle_interrupt(Some(irq)) {
-                        trace!("driver processed interrupt");
-                        return;
-                    }
-                    warn!("unhandled external IRQ number: {}", irq);
-                }
-            }
-        }
-        IPIFuncCall => {
-            let irq = tf.trap_num - IrqMin;
-            super::ack(irq); // must ack before switching
-            super::super::gdt::Cpu::current().handle_ipi();
+        IrqMin..=IrqMax | IPIFuncCall | IPIWakeup => {
+            super::ack_and_dispatch(tf.trap_num);
         }
         _ => panic!("Unhandled interrupt {:x}", tf.trap_num),
     }
 }
 
+/// Running on its own stack, see `gdt::init_double_fault_stack`
 fn double_fault(tf: &TrapFrame) {
+    // most likely the page fault of an overflow could not be delivered
+    crate::memory::check_kstack_overflow(Cr2::read().as_u64() as usize);
     error!("\nEXCEPTION: Double Fault\n{:#x?}", tf);
     loop {}
 }
 
-fn page_fault(tf: &mut TrapFrame) {
-    let addr = Cr2::read().as_u64() as usize;
+bitflags! {
+    struct PageError: u8 {
+        const PRESENT = 1 << 0;
+        const WRITE = 1 << 1;
+        const USER = 1 << 2;
+        const RESERVED_WRITE = 1 << 3;
+        const INST = 1 << 4;
+    }
+}
 
-    bitflags! {
-        struct PageError: u8 {
-            const PRESENT = 1 << 0;
-            const WRITE = 1 << 1;
-            const USER = 1 << 2;
-            const RESERVED_WRITE = 1 << 3;
-            const INST = 1 << 4;
-        }
+/// Decode the cause of a page fault from its error code
+pub fn page_fault_cause(error_code: usize) -> PageFaultCause {
+    let code = PageError::from_bits_truncate(error_code as u8);
+    PageFaultCause {
+        write: code.contains(PageError::WRITE),
+        exec: code.contains(PageError::INST),
+        present: code.contains(PageError::PRESENT),
     }
-    let code = PageError::from_bits(tf.error_code as u8).unwrap();
+}
+
+fn page_fault(tf: &mut TrapFrame) {
+    let addr = Cr2::read().as_u64() as usize;
+    let code = PageError::from_bits_truncate(tf.error_code as u8);
 
-    if crate::memory::handle_page_fault(addr) {
+    if crate::memory::handle_page_fault(addr, page_fault_cause(tf.error_code)) {
         return;
     }
 
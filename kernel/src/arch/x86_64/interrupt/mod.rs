@@ -2,10 +2,13 @@ pub mod consts;
 mod handler;
 
 pub use self::handler::*;
-use crate::memory::phys_to_virt;
+use crate::drivers::IRQ_MANAGER;
+use crate::memory::{phys_to_virt, PageFaultCause};
 use crate::process::thread::Thread;
+use crate::process::tlb_shootdown;
 use alloc::sync::Arc;
 use apic::*;
+use log::*;
 use trapframe::{TrapFrame, UserContext};
 
 #[inline(always)]
@@ -39,6 +42,10 @@ pub fn no_interrupt(f: impl FnOnce()) {
 
 #[inline(always)]
 pub fn enable_irq(irq: usize) {
+    if irq >= consts::IoApicPins {
+        // message signalled, enabled on the device
+        return;
+    }
     let mut ioapic = unsafe { IoApic::new(phys_to_virt(IOAPIC_ADDR as usize)) };
     ioapic.set_irq_vector(irq as u8, (consts::IrqMin + irq) as u8);
     ioapic.enable(irq as u8, 0);
@@ -54,17 +61,55 @@ pub fn ack(_irq: usize) {
     lapic.eoi();
 }
 
+/// Acknowledge the interrupt `trap_num` and dispatch it to the timer or drivers.
+/// Return true if it is the timer, so that the current thread should yield.
+pub fn ack_and_dispatch(trap_num: usize) -> bool {
+    let irq = trap_num - consts::IrqMin;
+    ack(irq); // must ack before switching
+    match trap_num {
+        consts::Timer => {
+            crate::trap::timer();
+            // other interrupts of a one-shot timer are for kernel timers only
+            return super::timer::set_next();
+        }
+        consts::IPIFuncCall => super::gdt::Cpu::current().handle_ipi(),
+        consts::IPIWakeup => {}
+        _ => {
+            if IRQ_MANAGER.read().try_handle_interrupt(Some(irq)) {
+                trace!("driver processed interrupt");
+            } else {
+                warn!("unhandled external IRQ number: {}", irq);
+            }
+        }
+    }
+    false
+}
+
 pub fn get_trap_num(context: &UserContext) -> usize {
     context.trap_num
 }
 
 pub fn wait_for_interrupt() {
+    super::timer::set_idle();
     x86_64::instructions::interrupts::enable_interrupts_and_hlt();
     x86_64::instructions::interrupts::disable();
+    super::timer::set_next();
+}
+
+pub fn handle_user_page_fault(thread: &Arc<Thread>, addr: usize, cause: PageFaultCause) -> bool {
+    let mut vm = thread.lock_vm();
+    let handled = vm.handle_page_fault(addr, cause);
+    // mapping a huge page frees a page table, which must not be walked any more
+    let flush = vm.take_flush();
+    drop(vm);
+    if let Some(flush) = flush {
+        tlb_shootdown(&thread.vm(), flush.start, flush.end);
+    }
+    handled
 }
 
-pub fn handle_user_page_fault(thread: &Arc<Thread>, addr: usize) -> bool {
-    thread.vm.lock().handle_page_fault(addr)
+pub fn get_page_fault_cause(context: &UserContext) -> PageFaultCause {
+    handler::page_fault_cause(context.error_code)
 }
 
 pub fn handle_reserved_inst(tf: &mut UserContext) -> bool {
@@ -4,6 +4,7 @@
 use crate::memory::phys_to_virt;
 use alloc::boxed::Box;
 use alloc::sync::Arc;
+use alloc::vec::Vec;
 use apic::{LocalApic, XApic, LAPIC_ADDR};
 use core::sync::atomic::{spin_loop_hint, AtomicU8, Ordering};
 
@@ -15,14 +16,20 @@ unsafe fn get_apic() -> XApic {
 }
 
 pub fn invoke_on_allcpu(f: impl Fn() + 'static, wait: bool) {
+    let cpus: Vec<usize> = super::gdt::Cpu::iter().map(|cpu| cpu.id()).collect();
+    invoke_on_cpus(&cpus, f, wait);
+}
+
+/// Call `f` on each CPU in `cpus`
+pub fn invoke_on_cpus(cpus: &[usize], f: impl Fn() + 'static, wait: bool) {
     // Step 1: initialize
     use super::interrupt::consts::IPIFuncCall;
     let mut apic = unsafe { get_apic() };
     let func = Arc::new(f);
-    let cpu_count = super::gdt::Cpu::iter().count();
-    let rest_count = Arc::new(AtomicU8::new(cpu_count as u8));
+    let targets = || super::gdt::Cpu::iter().filter(|cpu| cpus.contains(&cpu.id()));
+    let rest_count = Arc::new(AtomicU8::new(targets().count() as u8));
     // Step 2: invoke
-    for cpu in super::gdt::Cpu::iter() {
+    for cpu in targets() {
         let func_clone = func.clone();
         let rest_clone = rest_count.clone();
         cpu.notify_event(Box::new(move || {
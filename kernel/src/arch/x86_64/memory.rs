@@ -1,5 +1,5 @@
 use super::paging::PageTableImpl;
-use crate::memory::FRAME_ALLOCATOR;
+use crate::memory::{insert_frames, FRAME_ALLOCATOR};
 use bitmap_allocator::BitAlloc;
 use rboot::{BootInfo, MemoryType};
 use rcore_memory::paging::*;
@@ -22,7 +22,7 @@ fn init_frame_allocator(boot_info: &BootInfo) {
         if region.ty == MemoryType::CONVENTIONAL {
             let start_frame = region.phys_start as usize / PAGE_SIZE;
             let end_frame = start_frame + region.page_count as usize;
-            ba.insert(start_frame..end_frame);
+            insert_frames(&mut ba, start_frame..end_frame);
         }
     }
 }
@@ -53,3 +53,30 @@ pub fn set_page_table(vmtoken: usize) {
 pub fn get_page_fault_addr() -> usize {
     Cr2::read().as_u64() as usize
 }
+
+/// Invalidate TLB entries of `[start, end)` on `cpus` by IPI
+pub fn flush_tlb_on(cpus: &[usize], start: usize, end: usize) {
+    use x86_64::instructions::tlb;
+    super::ipi::invoke_on_cpus(
+        cpus,
+        move || {
+            if end - start > 32 * PAGE_SIZE {
+                tlb::flush_all();
+            } else {
+                for addr in (start..end).step_by(PAGE_SIZE) {
+                    tlb::flush(VirtAddr::new(addr as u64));
+                }
+            }
+        },
+        true,
+    );
+}
+
+/// Issue a full memory barrier on `cpus` by IPI, and wait for them
+pub fn fence_on(cpus: &[usize]) {
+    super::ipi::invoke_on_cpus(
+        cpus,
+        || unsafe { llvm_asm!("mfence" ::: "memory" : "volatile") },
+        true,
+    );
+}
@@ -17,6 +17,7 @@ pub mod rand;
 pub mod signal;
 pub mod syscall;
 pub mod timer;
+pub mod vdso;
 
 static AP_CAN_INIT: AtomicBool = AtomicBool::new(false);
 
@@ -51,6 +52,9 @@ pub extern "C" fn _start(boot_info: &'static BootInfo) -> ! {
     // Init physical memory management
     memory::init(boot_info);
 
+    // calibrate the TSC for the local apic timers
+    timer::init();
+
     // Init trap handler
     unsafe {
         trapframe::init();
@@ -58,12 +62,16 @@ pub extern "C" fn _start(boot_info: &'static BootInfo) -> ! {
 
     // init virtual space
     memory::init_kernel_kseg2_map();
+    unsafe {
+        gdt::init_double_fault_stack();
+    }
     // init local apic
     cpu::init();
     // now we can start LKM.
     crate::lkm::manager::ModuleManager::init();
     // init board
     board::init(boot_info);
+    crate::net::init();
     // init cpu scheduler and process manager, and add user shell app in process manager
     crate::process::init();
     // load acpi
@@ -81,6 +89,7 @@ fn other_start() -> ! {
     // init trap handling
     unsafe {
         trapframe::init();
+        gdt::init_double_fault_stack();
     }
     // init local apic
     cpu::init();
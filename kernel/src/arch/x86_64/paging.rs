@@ -1,8 +1,10 @@
 use super::consts::*;
 use crate::memory::{alloc_frame, dealloc_frame, phys_to_virt};
+use alloc::vec::Vec;
 use core::mem::ManuallyDrop;
 use log::*;
 use rcore_memory::paging::*;
+use rcore_memory::{HUGE_PAGE_SIZE, PAGE_SIZE};
 use x86_64::instructions::tlb;
 use x86_64::registers::control::{Cr3, Cr3Flags};
 use x86_64::structures::paging::{
@@ -42,6 +44,8 @@ pub struct PageTableImpl(
     MappedPageTable<'static, fn(Frame) -> *mut x86PageTable>,
     Option<PageEntry>,
     Frame,
+    /// Tables replaced by huge pages, see `take_dropped_tables`
+    Vec<usize>,
 );
 
 pub struct PageEntry(&'static mut PageTableEntry, Page, Frame);
@@ -69,6 +73,65 @@ impl PageTable for PageTableImpl {
         flush_tlb_all(addr);
     }
 
+    fn map_huge(&mut self, addr: usize, target: usize) -> Option<&mut dyn Entry> {
+        let mut page_table = frame_to_page_table(self.2);
+        for level in 0..3 {
+            let index = (addr >> (12 + (3 - level) * 9)) & 0o777;
+            let entry = unsafe { &mut (&mut *page_table)[index] };
+            if level == 2 {
+                if entry.flags().contains(EF::PRESENT) {
+                    if entry.flags().contains(EF::HUGE_PAGE) {
+                        return None;
+                    }
+                    // the table of small pages can go if none of them is present,
+                    // once other CPUs stop walking it
+                    let table = unsafe { &*frame_to_page_table(entry.frame().unwrap()) };
+                    if table.iter().any(|e| e.flags().contains(EF::PRESENT)) {
+                        return None;
+                    }
+                    self.3.push(entry.addr().as_u64() as usize);
+                }
+                let flags = EF::PRESENT | EF::WRITABLE | EF::HUGE_PAGE | EF::NO_EXECUTE;
+                entry.set_addr(PhysAddr::new(target as u64), flags);
+                tlb::flush(VirtAddr::new(addr as u64));
+                let page = Page::of_addr(addr);
+                self.1 = Some(PageEntry(entry, page, self.2));
+                return Some(self.1.as_mut().unwrap());
+            }
+            if !entry.flags().contains(EF::PRESENT) {
+                let frame = Frame::of_addr(alloc_frame()?);
+                unsafe { (&mut *frame_to_page_table(frame)).zero() };
+                entry.set_frame(frame, EF::PRESENT | EF::WRITABLE);
+            }
+            page_table = frame_to_page_table(entry.frame().unwrap());
+        }
+        unreachable!();
+    }
+
+    fn huge_page(&mut self, addr: usize) -> Option<usize> {
+        let entry = self.huge_entry(addr)?;
+        let offset = addr & (HUGE_PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+        Some(entry.addr().as_u64() as usize + offset)
+    }
+
+    fn split_huge(&mut self, addr: usize) -> bool {
+        match self.huge_entry(addr) {
+            Some(entry) => split_huge_page(entry, addr).is_some(),
+            None => true,
+        }
+    }
+
+    fn unmap_huge(&mut self, addr: usize) {
+        if let Some(entry) = self.huge_entry(addr) {
+            entry.set_unused();
+            tlb::flush(VirtAddr::new((addr & !(HUGE_PAGE_SIZE - 1)) as u64));
+        }
+    }
+
+    fn take_dropped_tables(&mut self) -> Vec<usize> {
+        core::mem::take(&mut self.3)
+    }
+
     fn get_entry(&mut self, addr: usize) -> Option<&mut dyn Entry> {
         let mut page_table = frame_to_page_table(self.2);
         for level in 0..4 {
@@ -79,7 +142,7 @@ impl PageTable for PageTableImpl {
                 self.1 = Some(PageEntry(entry, page, self.2));
                 return Some(self.1.as_mut().unwrap());
             }
-            if !entry.flags().contains(EF::PRESENT) {
+            if !entry.flags().contains(EF::PRESENT) || entry.flags().contains(EF::HUGE_PAGE) {
                 return None;
             }
             page_table = frame_to_page_table(entry.frame().unwrap());
@@ -88,14 +151,48 @@ impl PageTable for PageTableImpl {
     }
 
     fn get_page_slice_mut<'a>(&mut self, addr: usize) -> &'a mut [u8] {
-        let frame = self.0.translate_page(Page::of_addr(addr)).unwrap();
-        let vaddr = phys_to_virt(frame.start_address().as_u64() as usize);
-        unsafe { core::slice::from_raw_parts_mut(vaddr as *mut u8, 0x1000) }
+        // walk by hand, as huge pages are left whole
+        let mut page_table = frame_to_page_table(self.2);
+        for level in 0..4 {
+            let index = (addr >> (12 + (3 - level) * 9)) & 0o777;
+            let entry = unsafe { &(&*page_table)[index] };
+            assert!(entry.flags().contains(EF::PRESENT), "page not present");
+            let target = if level == 3 {
+                entry.addr().as_u64() as usize
+            } else if level == 2 && entry.flags().contains(EF::HUGE_PAGE) {
+                entry.addr().as_u64() as usize + (addr & (HUGE_PAGE_SIZE - 1))
+            } else {
+                page_table = frame_to_page_table(entry.frame().unwrap());
+                continue;
+            };
+            let vaddr = phys_to_virt(target & !(PAGE_SIZE - 1));
+            return unsafe { core::slice::from_raw_parts_mut(vaddr as *mut u8, PAGE_SIZE) };
+        }
+        unreachable!();
     }
 
     fn flush_cache_copy_user(&mut self, _start: usize, _end: usize, _execute: bool) {}
 }
 
+/// Replace the huge page of PDE `entry`, which contains `addr`, with a table of the same small pages.
+/// Return `None` if the table can't be allocated.
+fn split_huge_page(entry: &mut PageTableEntry, addr: usize) -> Option<()> {
+    let frame = Frame::of_addr(alloc_frame()?);
+    let table = unsafe { &mut *frame_to_page_table(frame) };
+    let target = entry.addr();
+    let flags = entry.flags() - EF::HUGE_PAGE;
+    for (i, small) in table.iter_mut().enumerate() {
+        small.set_addr(target + (i * PAGE_SIZE) as u64, flags);
+    }
+    // protection is up to the small pages now, as for tables made by `map`
+    entry.set_frame(
+        frame,
+        EF::PRESENT | EF::WRITABLE | (flags & EF::USER_ACCESSIBLE),
+    );
+    tlb::flush(VirtAddr::new((addr & !(HUGE_PAGE_SIZE - 1)) as u64));
+    Some(())
+}
+
 fn frame_to_page_table(frame: Frame) -> *mut x86PageTable {
     let vaddr = phys_to_virt(frame.start_address().as_u64() as usize);
     vaddr as *mut x86PageTable
@@ -171,7 +268,7 @@ impl Entry for PageEntry {
                     (self.1.start_address().as_u64() as usize >> (12 + (3 - level) * 9)) & 0o777;
                 let entry = unsafe { &mut (&mut *page_table)[index] };
                 entry.set_flags(entry.flags() | EF::USER_ACCESSIBLE);
-                if level == 3 {
+                if level == 3 || entry.flags().contains(EF::HUGE_PAGE) {
                     return;
                 }
                 page_table = frame_to_page_table(entry.frame().unwrap());
@@ -206,6 +303,7 @@ impl PageTableImpl {
             MappedPageTable::new(table, frame_to_page_table),
             None,
             frame,
+            Vec::new(),
         ))
     }
     /// The method for getting the kernel page table.
@@ -213,6 +311,22 @@ impl PageTableImpl {
     pub unsafe fn kernel_table() -> ManuallyDrop<Self> {
         Self::active()
     }
+    /// The PDE of the huge page containing `addr`, if it is in one
+    fn huge_entry(&mut self, addr: usize) -> Option<&'static mut PageTableEntry> {
+        let mut page_table = frame_to_page_table(self.2);
+        for level in 0..3 {
+            let index = (addr >> (12 + (3 - level) * 9)) & 0o777;
+            let entry = unsafe { &mut (&mut *page_table)[index] };
+            if !entry.flags().contains(EF::PRESENT) {
+                return None;
+            }
+            if level == 2 {
+                return Some(entry).filter(|entry| entry.flags().contains(EF::HUGE_PAGE));
+            }
+            page_table = frame_to_page_table(entry.frame().unwrap());
+        }
+        unreachable!();
+    }
 }
 
 impl PageTableExt for PageTableImpl {
@@ -226,6 +340,7 @@ impl PageTableExt for PageTableImpl {
                 MappedPageTable::new(table, frame_to_page_table),
                 None,
                 frame,
+                Vec::new(),
             )
         }
     }
@@ -259,6 +374,10 @@ impl PageTableExt for PageTableImpl {
     fn flush_tlb() {
         tlb::flush_all();
     }
+
+    fn free_table(frame: usize) {
+        dealloc_frame(frame);
+    }
 }
 
 impl Drop for PageTableImpl {
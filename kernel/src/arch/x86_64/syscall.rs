@@ -336,7 +336,11 @@ pub const SYS_PKEY_FREE: usize = 331;
 pub const SYS_STATX: usize = 332;
 pub const SYS_IO_PGETEVENTS: usize = 333;
 pub const SYS_RSEQ: usize = 334;
+pub const SYS_IO_URING_SETUP: usize = 425;
+pub const SYS_IO_URING_ENTER: usize = 426;
+pub const SYS_CLOSE_RANGE: usize = 436;
 
 // custom temporary syscall
 pub const SYS_MAP_PCI_DEVICE: usize = 999;
 pub const SYS_GET_PADDR: usize = 998;
+pub const SYS_SYSCALL_TRACE: usize = 997;
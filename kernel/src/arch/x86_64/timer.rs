@@ -1,8 +1,209 @@
+//! Time keeping by the TSC, and the timer interrupt of the local APIC
+//!
+//! The TSC frequency is calibrated against the PIT at boot.
+//! If the CPU has the TSC-deadline mode, the timer is one-shot, programmed at the end of
+//! the time slice or at the earliest kernel timer if sooner, so timeouts fire on time
+//! rather than on the next tick. Idle CPUs then sleep without ticks, except CPU 0,
+//! which keeps the vDSO clock and load averages.
+//! Otherwise the timer stays periodic as set by `cpu_init` of the LAPIC.
+
+use super::cpu;
+use super::interrupt::consts::Timer as TIMER_VECTOR;
+use crate::consts::{MAX_CPU_NUM, USEC_PER_TICK};
+use crate::memory::phys_to_virt;
+use crate::trap::NAIVE_TIMER;
+use apic::LAPIC_ADDR;
+use core::arch::x86_64::_rdtsc;
+use core::sync::atomic::{spin_loop_hint, AtomicBool, AtomicU64, Ordering};
 use core::time::Duration;
+use log::*;
+use raw_cpuid::CpuId;
+use x86_64::instructions::port::Port;
+use x86_64::registers::model_specific::Msr;
+
+/// TSC cycles per millisecond, assumed until calibrated
+static TSC_KHZ: AtomicU64 = AtomicU64::new(DEFAULT_TSC_KHZ);
+
+/// Time and TSC when the frequency was calibrated,
+/// so that the time goes on from there instead of jumping
+static BASE_NSEC: AtomicU64 = AtomicU64::new(0);
+static BASE_TSC: AtomicU64 = AtomicU64::new(0);
+
+/// Whether the LAPIC timers are in the TSC-deadline mode
+static DEADLINE_MODE: AtomicBool = AtomicBool::new(false);
+
+/// End of the time slice of each CPU in TSC cycles, 0 while it is idle
+static mut SLICE_END: [u64; MAX_CPU_NUM] = [0; MAX_CPU_NUM];
+
+/// Deadline programmed on each CPU in TSC cycles
+static mut PROGRAMMED: [u64; MAX_CPU_NUM] = [0; MAX_CPU_NUM];
+
+/// Longest sleep of an idle CPU, in case a timer is added by a CPU that goes idle too
+const IDLE_MAX: Duration = Duration::from_secs(1);
+
+const IA32_TSC_DEADLINE: u32 = 0x6e0;
+const LAPIC_LVT_TIMER: usize = 0x320;
+const LVT_TIMER_TSC_DEADLINE: u32 = 0b10 << 17;
+
+const DEFAULT_TSC_KHZ: u64 = 2_600_000;
+const PIT_FREQUENCY: u64 = 1_193_182;
+const CALIBRATE_MSEC: u64 = 10;
+/// Give up calibrating after this many TSC cycles, 10 times the calibration at the default frequency
+const CALIBRATE_TIMEOUT: u64 = DEFAULT_TSC_KHZ * CALIBRATE_MSEC * 10;
 
 pub fn timer_now() -> Duration {
-    // TODO: get actual tsc
-    const TSC_FREQUENCY: u16 = 2600;
-    let tsc = unsafe { core::arch::x86_64::_rdtsc() };
-    Duration::from_nanos(tsc * 1000 / TSC_FREQUENCY as u64)
+    let tsc = unsafe { _rdtsc() };
+    Duration::from_nanos(tsc_to_nsec(tsc))
+}
+
+fn tsc_to_nsec(tsc: u64) -> u64 {
+    let khz = TSC_KHZ.load(Ordering::Relaxed) as u128;
+    let cycles = tsc.saturating_sub(BASE_TSC.load(Ordering::Relaxed)) as u128;
+    BASE_NSEC.load(Ordering::Relaxed) + (cycles * 1_000_000 / khz) as u64
+}
+
+/// TSC cycles in the span `duration`
+fn duration_to_cycles(duration: Duration) -> u64 {
+    let khz = TSC_KHZ.load(Ordering::Relaxed) as u128;
+    (duration.as_nanos() * khz / 1_000_000) as u64
+}
+
+/// TSC value at the time `time` since boot
+fn to_tsc(time: Duration) -> u64 {
+    let khz = TSC_KHZ.load(Ordering::Relaxed) as u128;
+    let nsec = time
+        .as_nanos()
+        .saturating_sub(BASE_NSEC.load(Ordering::Relaxed) as u128);
+    BASE_TSC.load(Ordering::Relaxed) + (nsec * khz / 1_000_000) as u64
+}
+
+/// Calibrate the TSC, on the bootstrap CPU before others start.
+/// The default frequency is kept if the PIT does not count.
+pub fn init() {
+    let khz = match unsafe { calibrate_tsc() } {
+        Some(khz) => khz,
+        None => {
+            warn!(
+                "timer: failed to calibrate the TSC, assuming {} kHz",
+                DEFAULT_TSC_KHZ
+            );
+            DEFAULT_TSC_KHZ
+        }
+    };
+    let tsc = unsafe { _rdtsc() };
+    BASE_NSEC.store(tsc_to_nsec(tsc), Ordering::Relaxed);
+    BASE_TSC.store(tsc, Ordering::Relaxed);
+    TSC_KHZ.store(khz, Ordering::Relaxed);
+    let deadline_mode = CpuId::new()
+        .get_feature_info()
+        .map_or(false, |info| info.has_tsc_deadline());
+    DEADLINE_MODE.store(deadline_mode, Ordering::Relaxed);
+    info!("timer: TSC {} kHz, TSC-deadline: {}", khz, deadline_mode);
+}
+
+/// Count TSC cycles per millisecond while channel 2 of the PIT counts down,
+/// None if it does not reach the terminal count in time
+unsafe fn calibrate_tsc() -> Option<u64> {
+    let mut gate = Port::<u8>::new(0x61);
+    let mut command = Port::<u8>::new(0x43);
+    let mut channel2 = Port::<u8>::new(0x42);
+    let latch = PIT_FREQUENCY * CALIBRATE_MSEC / 1000;
+
+    // gate on, speaker off
+    let old = gate.read();
+    gate.write((old & !0x02) | 0x01);
+    // channel 2, low then high byte, interrupt on terminal count
+    command.write(0xb0);
+    channel2.write(latch as u8);
+    channel2.write((latch >> 8) as u8);
+    let start = _rdtsc();
+    // the output goes high at terminal count
+    let end = loop {
+        let now = _rdtsc();
+        if gate.read() & 0x20 != 0 {
+            break Some(now);
+        }
+        if now - start > CALIBRATE_TIMEOUT {
+            break None;
+        }
+        spin_loop_hint();
+    };
+    gate.write(old);
+    end.map(|end| (end - start) / CALIBRATE_MSEC)
+        .filter(|&khz| khz != 0)
+}
+
+/// Switch the LAPIC timer of this CPU to the TSC-deadline mode if there is,
+/// and start the first time slice
+pub fn init_cpu() {
+    if !DEADLINE_MODE.load(Ordering::Relaxed) {
+        return;
+    }
+    unsafe {
+        let lvt = phys_to_virt(LAPIC_ADDR + LAPIC_LVT_TIMER) as *mut u32;
+        lvt.write_volatile(LVT_TIMER_TSC_DEADLINE | TIMER_VECTOR as u32);
+        // the mode must be set before the deadline, see Intel SDM 10.5.4.1
+        llvm_asm!("mfence" ::: "memory");
+        SLICE_END[cpu::id()] = 0;
+    }
+    set_next();
+}
+
+/// Program the timer of this CPU at `deadline` in TSC cycles
+unsafe fn program(deadline: u64) {
+    PROGRAMMED[cpu::id()] = deadline;
+    // 0 would disarm it
+    Msr::new(IA32_TSC_DEADLINE).write(deadline.max(1));
+}
+
+fn next_timer() -> u64 {
+    NAIVE_TIMER.lock().next().map_or(u64::MAX, to_tsc)
+}
+
+/// Program the timer at the end of the time slice, or at the next kernel timer if sooner.
+/// A new slice starts if the last one has ended, which is returned.
+///
+/// Called on each timer interrupt. A periodic tick always ends the slice.
+pub fn set_next() -> bool {
+    if !DEADLINE_MODE.load(Ordering::Relaxed) {
+        return true;
+    }
+    let id = cpu::id();
+    let now = unsafe { _rdtsc() };
+    unsafe {
+        let ended = SLICE_END[id] <= now;
+        if ended {
+            SLICE_END[id] = now + duration_to_cycles(Duration::from_micros(USEC_PER_TICK as u64));
+        }
+        program(SLICE_END[id].min(next_timer()));
+        ended
+    }
+}
+
+/// Let this CPU sleep until the next kernel timer without time slices,
+/// until `set_next` on waking up
+pub fn set_idle() {
+    let id = cpu::id();
+    if !DEADLINE_MODE.load(Ordering::Relaxed) || id == 0 {
+        return;
+    }
+    let now = unsafe { _rdtsc() };
+    unsafe {
+        SLICE_END[id] = 0;
+        program(next_timer().min(now + duration_to_cycles(IDLE_MAX)));
+    }
+}
+
+/// Fire the timer of this CPU at `deadline` of a new kernel timer, if sooner than programmed
+pub fn set_deadline(deadline: Duration) {
+    if !DEADLINE_MODE.load(Ordering::Relaxed) {
+        return;
+    }
+    let deadline = to_tsc(deadline);
+    unsafe {
+        // nothing is programmed before `init_cpu`
+        if deadline < PROGRAMMED[cpu::id()] {
+            program(deadline);
+        }
+    }
 }
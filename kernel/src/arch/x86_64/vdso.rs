@@ -0,0 +1,14 @@
+//! The vDSO image, see `vdso.S`
+
+global_asm!(include_str!("vdso.S"));
+
+/// The ELF image of the vDSO
+pub fn image() -> &'static [u8] {
+    extern "C" {
+        fn vdso_image_start();
+        fn vdso_image_end();
+    }
+    let start = vdso_image_start as usize;
+    let end = vdso_image_end as usize;
+    unsafe { core::slice::from_raw_parts(start as *const u8, end - start) }
+}
@@ -4,6 +4,9 @@ pub use crate::arch::consts::*;
 pub const MAX_CPU_NUM: usize = 64;
 pub const MAX_PROCESS_NUM: usize = 512;
 
+/// Size of the unmapped gap on each side of the user stack
+pub const USER_STACK_GUARD_SIZE: usize = 0x10000;
+
 pub const USEC_PER_TICK: usize = 10000;
 
 pub const INFORM_PER_MSEC: usize = 50;
@@ -2,7 +2,10 @@ use super::Driver;
 
 pub mod ahci;
 pub mod ide;
+pub mod partition;
 pub mod virtio_blk;
+#[cfg(target_arch = "x86_64")]
+pub mod virtio_blk_pci;
 
 pub trait BlockDriver: Driver {
     fn read_block(&self, _block_id: usize, _buf: &mut [u8]) -> bool {
@@ -12,4 +15,9 @@ pub trait BlockDriver: Driver {
     fn write_block(&self, _block_id: usize, _buf: &[u8]) -> bool {
         unimplemented!("not a block driver")
     }
+
+    /// Number of blocks, `None` if unknown
+    fn num_blocks(&self) -> Option<usize> {
+        None
+    }
 }
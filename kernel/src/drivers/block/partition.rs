@@ -0,0 +1,400 @@
+//! Partition tables of block devices
+//!
+//! Both MBR (with logical partitions in an extended partition) and GPT are
+//! supported. A GPT disk is recognized by its protective MBR; both the primary
+//! and the backup GPT header are checked, falling back to the backup one if the
+//! primary is corrupted.
+//!
+//! Every partition is exposed as its own block device, named after the disk
+//! like Linux does: `vda`, `vda1`, `vda2`, ..., `sda`, `sda1`, ...
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::convert::TryInto;
+
+use super::super::{DeviceType, Driver, BLK_DRIVERS};
+use super::BlockDriver;
+
+pub const BLOCK_SIZE: usize = 512;
+
+/// Max number of logical partitions followed in an extended partition
+const MAX_LOGICAL: usize = 64;
+/// Max number of GPT partition entries
+const MAX_GPT_ENTRIES: usize = 256;
+
+const MBR_SIGNATURE: u16 = 0xaa55;
+const MBR_TYPE_EMPTY: u8 = 0x00;
+const MBR_TYPE_PROTECTIVE: u8 = 0xee;
+const MBR_TYPES_EXTENDED: [u8; 3] = [0x05, 0x0f, 0x85];
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+
+/// A range of blocks on a disk
+pub struct Partition {
+    disk: Arc<dyn BlockDriver>,
+    name: String,
+    /// Partition number, starting from 1
+    number: usize,
+    /// First block on the disk
+    start: usize,
+    /// Number of blocks
+    blocks: usize,
+}
+
+impl Partition {
+    pub fn number(&self) -> usize {
+        self.number
+    }
+}
+
+impl Driver for Partition {
+    fn try_handle_interrupt(&self, _irq: Option<usize>) -> bool {
+        false
+    }
+
+    fn device_type(&self) -> DeviceType {
+        DeviceType::Block
+    }
+
+    fn get_id(&self) -> String {
+        self.name.clone()
+    }
+
+    fn as_block(&self) -> Option<&dyn BlockDriver> {
+        Some(self)
+    }
+}
+
+impl BlockDriver for Partition {
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) -> bool {
+        block_id < self.blocks && self.disk.read_block(self.start + block_id, buf)
+    }
+
+    fn write_block(&self, block_id: usize, buf: &[u8]) -> bool {
+        block_id < self.blocks && self.disk.write_block(self.start + block_id, buf)
+    }
+
+    fn num_blocks(&self) -> Option<usize> {
+        Some(self.blocks)
+    }
+}
+
+/// A block device to show in /dev
+pub struct BlockDevice {
+    pub name: String,
+    pub driver: Arc<dyn BlockDriver>,
+    /// Index of the disk it is on
+    pub disk: usize,
+    /// Partition number, 0 for the whole disk
+    pub partition: usize,
+}
+
+/// All disks and their partitions, in the order of `BLK_DRIVERS`
+pub fn block_devices() -> Vec<BlockDevice> {
+    let disks = BLK_DRIVERS.read().clone();
+    let mut prefixes = Vec::new();
+    let mut devices = Vec::new();
+    for (i, disk) in disks.into_iter().enumerate() {
+        let prefix = disk_prefix(&*disk);
+        let letter = prefixes.iter().filter(|&&p| p == prefix).count();
+        prefixes.push(prefix);
+        let name = format!("{}{}", prefix, (b'a' + letter as u8) as char);
+        let partitions = scan(&disk, &name);
+        devices.push(BlockDevice {
+            name,
+            driver: disk,
+            disk: i,
+            partition: 0,
+        });
+        for part in partitions {
+            devices.push(BlockDevice {
+                name: part.name.clone(),
+                partition: part.number,
+                driver: part,
+                disk: i,
+            });
+        }
+    }
+    devices
+}
+
+fn disk_prefix(disk: &dyn BlockDriver) -> &'static str {
+    let id = disk.get_id();
+    if id.starts_with("virtio") {
+        "vd"
+    } else if id.starts_with("ide") {
+        "hd"
+    } else {
+        "sd"
+    }
+}
+
+/// Find the partitions of `disk`
+pub fn scan(disk: &Arc<dyn BlockDriver>, disk_name: &str) -> Vec<Arc<Partition>> {
+    let ranges = match read_table(&**disk) {
+        Some(ranges) => ranges,
+        None => return Vec::new(),
+    };
+    let mut parts = Vec::new();
+    for (number, start, blocks) in ranges {
+        // out of the disk
+        if let Some(total) = disk.num_blocks() {
+            if start >= total || blocks > total - start {
+                warn!("partition {} of {} exceeds the disk", number, disk_name);
+                continue;
+            }
+        }
+        info!(
+            "partition {}{}: start {:#x}, {} blocks",
+            disk_name, number, start, blocks
+        );
+        parts.push(Arc::new(Partition {
+            disk: disk.clone(),
+            name: format!("{}{}", disk_name, number),
+            number,
+            start,
+            blocks,
+        }));
+    }
+    parts
+}
+
+/// (number, start, blocks) of each partition
+fn read_table(disk: &dyn BlockDriver) -> Option<Vec<(usize, usize, usize)>> {
+    let mbr = read_lba(disk, 0)?;
+    if u16_at(&mbr, 510) != MBR_SIGNATURE {
+        return None;
+    }
+    let entries = mbr_entries(&mbr);
+    if entries.iter().any(|e| e.kind == MBR_TYPE_PROTECTIVE) {
+        return read_gpt(disk);
+    }
+
+    let mut res = Vec::new();
+    for (i, entry) in entries.iter().enumerate() {
+        if entry.kind == MBR_TYPE_EMPTY || entry.blocks == 0 {
+            continue;
+        }
+        res.push((i + 1, entry.start, entry.blocks));
+        if MBR_TYPES_EXTENDED.contains(&entry.kind) {
+            read_logical(disk, entry.start, &mut res);
+        }
+    }
+    Some(res)
+}
+
+struct MbrEntry {
+    kind: u8,
+    start: usize,
+    blocks: usize,
+}
+
+fn mbr_entries(sector: &[u8]) -> Vec<MbrEntry> {
+    (0..4)
+        .map(|i| {
+            let entry = &sector[446 + i * 16..446 + (i + 1) * 16];
+            MbrEntry {
+                kind: entry[4],
+                start: u32_at(entry, 8) as usize,
+                blocks: u32_at(entry, 12) as usize,
+            }
+        })
+        .collect()
+}
+
+/// Follow the chain of EBRs in the extended partition at `base`.
+/// Logical partitions are numbered from 5.
+fn read_logical(disk: &dyn BlockDriver, base: usize, res: &mut Vec<(usize, usize, usize)>) {
+    let mut ebr_lba = base;
+    for number in 5..5 + MAX_LOGICAL {
+        let ebr = match read_lba(disk, ebr_lba) {
+            Some(ebr) if u16_at(&ebr, 510) == MBR_SIGNATURE => ebr,
+            _ => return,
+        };
+        let entries = mbr_entries(&ebr);
+        // the first entry is relative to this EBR
+        if entries[0].kind != MBR_TYPE_EMPTY && entries[0].blocks != 0 {
+            match ebr_lba.checked_add(entries[0].start) {
+                Some(start) => res.push((number, start, entries[0].blocks)),
+                None => warn!("logical partition {} exceeds the disk", number),
+            }
+        }
+        // the second one links to the next EBR, relative to the extended partition
+        if entries[1].kind == MBR_TYPE_EMPTY || entries[1].start == 0 {
+            return;
+        }
+        ebr_lba = match base.checked_add(entries[1].start) {
+            Some(lba) => lba,
+            None => return,
+        };
+    }
+    warn!("too many logical partitions");
+}
+
+/// Fields of a GPT header
+#[derive(Debug, PartialEq, Eq)]
+struct GptHeader {
+    my_lba: u64,
+    alternate_lba: u64,
+    first_usable: u64,
+    last_usable: u64,
+    disk_guid: [u8; 16],
+    entries_lba: u64,
+    num_entries: u32,
+    entry_size: u32,
+    entries_crc: u32,
+}
+
+fn read_gpt(disk: &dyn BlockDriver) -> Option<Vec<(usize, usize, usize)>> {
+    let primary = read_gpt_header(disk, 1);
+    let backup_lba = match &primary {
+        Some((header, _)) => Some(header.alternate_lba),
+        // the last block, if the disk tells its size
+        None => disk
+            .num_blocks()
+            .and_then(|n| n.checked_sub(1))
+            .map(|n| n as u64),
+    };
+    let backup = backup_lba.and_then(|lba| read_gpt_header(disk, lba));
+
+    let (header, entries) = match (primary, backup) {
+        (Some(primary), Some(backup)) => {
+            if !consistent(&primary.0, &backup.0) {
+                warn!("GPT: primary and backup headers differ, using primary");
+            }
+            primary
+        }
+        (Some(primary), None) => {
+            warn!("GPT: backup header is corrupted");
+            primary
+        }
+        (None, Some(backup)) => {
+            warn!("GPT: primary header is corrupted, using backup");
+            backup
+        }
+        (None, None) => {
+            warn!("GPT: no valid header");
+            return None;
+        }
+    };
+
+    let mut res = Vec::new();
+    for i in 0..header.num_entries as usize {
+        let entry = &entries[i * header.entry_size as usize..];
+        // unused entry
+        if entry[..16].iter().all(|&b| b == 0) {
+            continue;
+        }
+        let first = u64_at(entry, 32);
+        let last = u64_at(entry, 40);
+        if first < header.first_usable
+            || last > header.last_usable
+            || first > last
+            || last >= usize::max_value() as u64
+        {
+            warn!("GPT: invalid range of partition {}", i + 1);
+            continue;
+        }
+        res.push((i + 1, first as usize, (last - first + 1) as usize));
+    }
+    Some(res)
+}
+
+/// The two headers describe the same disk, pointing to each other
+fn consistent(primary: &GptHeader, backup: &GptHeader) -> bool {
+    primary.my_lba == backup.alternate_lba
+        && primary.alternate_lba == backup.my_lba
+        && primary.first_usable == backup.first_usable
+        && primary.last_usable == backup.last_usable
+        && primary.disk_guid == backup.disk_guid
+        && primary.num_entries == backup.num_entries
+        && primary.entry_size == backup.entry_size
+        && primary.entries_crc == backup.entries_crc
+}
+
+/// Read and validate the GPT header at `lba` and its partition entries
+fn read_gpt_header(disk: &dyn BlockDriver, lba: u64) -> Option<(GptHeader, Vec<u8>)> {
+    let sector = read_lba(disk, lba as usize)?;
+    if &sector[0..8] != GPT_SIGNATURE {
+        return None;
+    }
+    let header_size = u32_at(&sector, 12) as usize;
+    if header_size < 92 || header_size > BLOCK_SIZE {
+        return None;
+    }
+    let mut copy = sector[..header_size].to_vec();
+    copy[16..20].copy_from_slice(&[0; 4]);
+    if crc32(&copy) != u32_at(&sector, 16) {
+        warn!("GPT: bad header CRC at LBA {}", lba);
+        return None;
+    }
+    let mut disk_guid = [0u8; 16];
+    disk_guid.copy_from_slice(&sector[56..72]);
+    let header = GptHeader {
+        my_lba: u64_at(&sector, 24),
+        alternate_lba: u64_at(&sector, 32),
+        first_usable: u64_at(&sector, 40),
+        last_usable: u64_at(&sector, 48),
+        disk_guid,
+        entries_lba: u64_at(&sector, 72),
+        num_entries: u32_at(&sector, 80),
+        entry_size: u32_at(&sector, 84),
+        entries_crc: u32_at(&sector, 88),
+    };
+    if header.my_lba != lba
+        || header.entry_size < 128
+        || header.entry_size as usize % 8 != 0
+        || header.num_entries as usize > MAX_GPT_ENTRIES
+    {
+        return None;
+    }
+
+    let size = header.num_entries as usize * header.entry_size as usize;
+    let mut entries = Vec::with_capacity(size);
+    let mut block = header.entries_lba as usize;
+    while entries.len() < size {
+        let sector = read_lba(disk, block)?;
+        let len = (size - entries.len()).min(BLOCK_SIZE);
+        entries.extend_from_slice(&sector[..len]);
+        block += 1;
+    }
+    if crc32(&entries) != header.entries_crc {
+        warn!("GPT: bad partition entries CRC at LBA {}", lba);
+        return None;
+    }
+    Some((header, entries))
+}
+
+fn read_lba(disk: &dyn BlockDriver, lba: usize) -> Option<[u8; BLOCK_SIZE]> {
+    let mut buf = [0u8; BLOCK_SIZE];
+    if disk.read_block(lba, &mut buf) {
+        Some(buf)
+    } else {
+        None
+    }
+}
+
+fn u16_at(buf: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(buf[offset..offset + 2].try_into().unwrap())
+}
+
+fn u32_at(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_at(buf: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(buf[offset..offset + 8].try_into().unwrap())
+}
+
+/// CRC-32 used by GPT (IEEE 802.3, reflected)
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    !crc
+}
@@ -25,7 +25,7 @@ impl Driver for VirtIOBlkDriver {
     }
 
     fn as_block(&self) -> Option<&dyn BlockDriver> {
-        None
+        Some(self)
     }
 
     fn as_net(&self) -> Option<&dyn NetDriver> {
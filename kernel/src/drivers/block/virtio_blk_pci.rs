@@ -0,0 +1,526 @@
+//! Driver for legacy virtio-blk over PCI (vendor 0x1af4, device 0x1001)
+//!
+//! The MMIO transport is handled by `virtio_blk` through the virtio-drivers crate,
+//! while this one drives the legacy I/O port interface directly.
+//!
+//! Spec: https://docs.oasis-open.org/virtio/virtio/v1.1/virtio-v1.1.html (4.1.4.8 Legacy Interfaces)
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::future::Future;
+use core::pin::Pin;
+use core::ptr::{read_volatile, write_volatile};
+use core::sync::atomic::{fence, Ordering};
+use core::task::{Context, Poll, Waker};
+use x86_64::instructions::interrupts;
+use x86_64::instructions::port::Port;
+
+use super::{
+    super::{DeviceType, Driver, BLK_DRIVERS, DRIVERS, IRQ_MANAGER},
+    BlockDriver,
+};
+use crate::arch::cpu;
+use crate::drivers::NetDriver;
+use crate::memory::{alloc_frame_contiguous, phys_to_virt};
+use crate::sync::SpinNoIrqLock as Mutex;
+use rcore_memory::PAGE_SIZE;
+
+const VIRTIO_PCI_HOST_FEATURES: u16 = 0x00;
+const VIRTIO_PCI_GUEST_FEATURES: u16 = 0x04;
+const VIRTIO_PCI_QUEUE_PFN: u16 = 0x08;
+const VIRTIO_PCI_QUEUE_NUM: u16 = 0x0c;
+const VIRTIO_PCI_QUEUE_SEL: u16 = 0x0e;
+const VIRTIO_PCI_QUEUE_NOTIFY: u16 = 0x10;
+const VIRTIO_PCI_STATUS: u16 = 0x12;
+const VIRTIO_PCI_ISR: u16 = 0x13;
+/// Device specific config, when MSI-X is disabled
+const VIRTIO_PCI_CONFIG: u16 = 0x14;
+
+const STATUS_ACKNOWLEDGE: u8 = 1;
+const STATUS_DRIVER: u8 = 2;
+const STATUS_DRIVER_OK: u8 = 4;
+const STATUS_FAILED: u8 = 0x80;
+
+const VIRTIO_BLK_F_RO: u32 = 1 << 5;
+
+const VIRTIO_BLK_T_IN: u32 = 0;
+const VIRTIO_BLK_T_OUT: u32 = 1;
+const VIRTIO_BLK_S_OK: u8 = 0;
+
+const VIRTQ_DESC_F_NEXT: u16 = 1;
+const VIRTQ_DESC_F_WRITE: u16 = 2;
+
+pub const BLOCK_SIZE: usize = 512;
+
+/// Each request takes 3 descriptors: header, data and status
+const DESC_PER_REQ: usize = 3;
+/// Max number of requests in flight
+const MAX_SLOTS: usize = 16;
+/// DMA buffer of a request: header at 0, status at 16, data at 512
+const SLOT_SIZE: usize = 1024;
+const SLOT_STATUS: usize = 16;
+const SLOT_DATA: usize = 512;
+
+#[repr(C)]
+struct VirtqDesc {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+#[repr(C)]
+struct BlkReqHeader {
+    type_: u32,
+    reserved: u32,
+    sector: u64,
+}
+
+/// A physically contiguous region for DMA
+struct Dma {
+    paddr: usize,
+    vaddr: usize,
+}
+
+impl Dma {
+    fn new(pages: usize) -> Option<Self> {
+        let paddr = alloc_frame_contiguous(pages, 0)?;
+        let vaddr = phys_to_virt(paddr);
+        unsafe {
+            core::ptr::write_bytes(vaddr as *mut u8, 0, pages * PAGE_SIZE);
+        }
+        Some(Dma { paddr, vaddr })
+    }
+}
+
+struct Inner {
+    /// the virtqueue in legacy layout
+    queue: Dma,
+    queue_size: usize,
+    used_offset: usize,
+    avail_idx: u16,
+    last_used_idx: u16,
+    /// request buffers
+    buffers: Dma,
+    /// slots not in flight
+    free_slots: Vec<usize>,
+    /// whether the request in each slot is completed
+    done: [bool; MAX_SLOTS],
+    /// tasks waiting for each slot
+    wakers: [Option<Waker>; MAX_SLOTS],
+    /// tasks waiting for a free slot
+    slot_wakers: Vec<Waker>,
+}
+
+pub struct VirtIOBlkPciDriver {
+    io_base: u16,
+    irq: Option<usize>,
+    read_only: bool,
+    capacity: u64,
+    inner: Mutex<Inner>,
+}
+
+impl Inner {
+    fn desc(&mut self, i: usize) -> &mut VirtqDesc {
+        unsafe { &mut *((self.queue.vaddr + i * 16) as *mut VirtqDesc) }
+    }
+
+    fn slot_vaddr(&self, slot: usize) -> usize {
+        self.buffers.vaddr + slot * SLOT_SIZE
+    }
+
+    fn slot_paddr(&self, slot: usize) -> usize {
+        self.buffers.paddr + slot * SLOT_SIZE
+    }
+
+    /// Fill descriptors of `slot` and put it to the available ring
+    fn submit(&mut self, slot: usize, write: bool, block_id: usize) {
+        let header = BlkReqHeader {
+            type_: if write {
+                VIRTIO_BLK_T_OUT
+            } else {
+                VIRTIO_BLK_T_IN
+            },
+            reserved: 0,
+            sector: block_id as u64,
+        };
+        let vaddr = self.slot_vaddr(slot);
+        let paddr = self.slot_paddr(slot);
+        unsafe {
+            write_volatile(vaddr as *mut BlkReqHeader, header);
+            write_volatile((vaddr + SLOT_STATUS) as *mut u8, 0xff);
+        }
+        let head = slot * DESC_PER_REQ;
+        *self.desc(head) = VirtqDesc {
+            addr: paddr as u64,
+            len: 16,
+            flags: VIRTQ_DESC_F_NEXT,
+            next: (head + 1) as u16,
+        };
+        *self.desc(head + 1) = VirtqDesc {
+            addr: (paddr + SLOT_DATA) as u64,
+            len: BLOCK_SIZE as u32,
+            flags: VIRTQ_DESC_F_NEXT | if write { 0 } else { VIRTQ_DESC_F_WRITE },
+            next: (head + 2) as u16,
+        };
+        *self.desc(head + 2) = VirtqDesc {
+            addr: (paddr + SLOT_STATUS) as u64,
+            len: 1,
+            flags: VIRTQ_DESC_F_WRITE,
+            next: 0,
+        };
+
+        // avail ring: flags, idx, ring[queue_size]
+        let avail = self.queue.vaddr + self.queue_size * 16;
+        let pos = self.avail_idx as usize % self.queue_size;
+        unsafe {
+            write_volatile((avail + 4 + pos * 2) as *mut u16, head as u16);
+            fence(Ordering::SeqCst);
+            self.avail_idx = self.avail_idx.wrapping_add(1);
+            write_volatile((avail + 2) as *mut u16, self.avail_idx);
+        }
+        fence(Ordering::SeqCst);
+        self.done[slot] = false;
+    }
+
+    /// Collect completed requests from the used ring and wake their waiters
+    fn process_used(&mut self) {
+        // used ring: flags, idx, ring[queue_size] of (id: u32, len: u32)
+        let used = self.queue.vaddr + self.used_offset;
+        loop {
+            let used_idx = unsafe { read_volatile((used + 2) as *const u16) };
+            if used_idx == self.last_used_idx {
+                break;
+            }
+            fence(Ordering::SeqCst);
+            let pos = self.last_used_idx as usize % self.queue_size;
+            let id = unsafe { read_volatile((used + 4 + pos * 8) as *const u32) } as usize;
+            let slot = id / DESC_PER_REQ;
+            self.done[slot] = true;
+            if let Some(waker) = self.wakers[slot].take() {
+                waker.wake();
+            }
+            self.last_used_idx = self.last_used_idx.wrapping_add(1);
+        }
+    }
+
+    /// Finish the completed request in `slot`, return whether it succeeded
+    fn finish(&mut self, slot: usize) -> bool {
+        let status = unsafe { read_volatile((self.slot_vaddr(slot) + SLOT_STATUS) as *const u8) };
+        self.free_slots.push(slot);
+        for waker in self.slot_wakers.drain(..) {
+            waker.wake();
+        }
+        status == VIRTIO_BLK_S_OK
+    }
+}
+
+impl VirtIOBlkPciDriver {
+    unsafe fn read8(&self, reg: u16) -> u8 {
+        Port::new(self.io_base + reg).read()
+    }
+    unsafe fn write8(&self, reg: u16, val: u8) {
+        Port::new(self.io_base + reg).write(val)
+    }
+    unsafe fn read16(&self, reg: u16) -> u16 {
+        Port::new(self.io_base + reg).read()
+    }
+    unsafe fn write16(&self, reg: u16, val: u16) {
+        Port::new(self.io_base + reg).write(val)
+    }
+    unsafe fn read32(&self, reg: u16) -> u32 {
+        Port::new(self.io_base + reg).read()
+    }
+    unsafe fn write32(&self, reg: u16, val: u32) {
+        Port::new(self.io_base + reg).write(val)
+    }
+
+    fn notify(&self) {
+        unsafe { self.write16(VIRTIO_PCI_QUEUE_NOTIFY, 0) };
+    }
+
+    /// Number of 512-byte sectors
+    pub fn capacity(&self) -> u64 {
+        self.capacity
+    }
+
+    /// Take a free slot, copy in the data to write, and submit the request
+    fn try_submit(&self, write: bool, block_id: usize, data: Option<&[u8]>) -> Option<usize> {
+        let mut inner = self.inner.lock();
+        let slot = inner.free_slots.pop()?;
+        if let Some(data) = data {
+            let dst = inner.slot_vaddr(slot) + SLOT_DATA;
+            unsafe {
+                core::ptr::copy_nonoverlapping(data.as_ptr(), dst as *mut u8, BLOCK_SIZE);
+            }
+        }
+        inner.submit(slot, write, block_id);
+        drop(inner);
+        self.notify();
+        Some(slot)
+    }
+
+    /// Finish the request in `slot`, copy out the data read
+    fn complete(&self, slot: usize, buf: Option<&mut [u8]>) -> bool {
+        let mut inner = self.inner.lock();
+        if let Some(buf) = buf {
+            let src = inner.slot_vaddr(slot) + SLOT_DATA;
+            unsafe {
+                core::ptr::copy_nonoverlapping(src as *const u8, buf.as_mut_ptr(), BLOCK_SIZE);
+            }
+        }
+        inner.finish(slot)
+    }
+
+    /// Submit a request and wait for it, used by the synchronous interface
+    fn request_sync(&self, write: bool, block_id: usize, data: Option<&[u8]>) -> usize {
+        let slot = self.wait_sync(|| self.try_submit(write, block_id, data));
+        self.wait_sync(|| match self.inner.lock().done[slot] {
+            true => Some(slot),
+            false => None,
+        })
+    }
+
+    /// Wait until `condition` holds after collecting completed requests.
+    ///
+    /// The CPU halts until the next interrupt in between, which is the completion
+    /// of a request if nothing else comes first. Interrupts are kept off from the check
+    /// to the halt, so a completion can not slip in between and be missed.
+    /// If the caller runs with interrupts off, or the device has no interrupt line,
+    /// the used ring is polled instead. So it is on CPUs other than 0, which is where
+    /// MSI and the IOAPIC send the interrupt: they would sleep until their next timer tick.
+    fn wait_sync<T>(&self, mut condition: impl FnMut() -> Option<T>) -> T {
+        let enabled = interrupts::are_enabled();
+        let sleep = enabled && self.irq.is_some() && cpu::id() == 0;
+        loop {
+            interrupts::disable();
+            self.inner.lock().process_used();
+            if let Some(result) = condition() {
+                if enabled {
+                    interrupts::enable();
+                }
+                return result;
+            }
+            if sleep {
+                interrupts::enable_interrupts_and_hlt();
+            } else {
+                core::sync::atomic::spin_loop_hint();
+            }
+        }
+    }
+
+    /// Read the sector `block_id` to `buf`, sleeping until the device interrupts
+    pub async fn read_block_async(&self, block_id: usize, buf: &mut [u8]) -> bool {
+        if buf.len() < BLOCK_SIZE || block_id as u64 >= self.capacity {
+            return false;
+        }
+        let slot = SlotFuture {
+            driver: self,
+            write: false,
+            block_id,
+            data: None,
+        }
+        .await;
+        CompletionFuture { driver: self, slot }.await;
+        self.complete(slot, Some(buf))
+    }
+
+    /// Write `buf` to the sector `block_id`, sleeping until the device interrupts
+    pub async fn write_block_async(&self, block_id: usize, buf: &[u8]) -> bool {
+        if self.read_only || buf.len() < BLOCK_SIZE || block_id as u64 >= self.capacity {
+            return false;
+        }
+        let slot = SlotFuture {
+            driver: self,
+            write: true,
+            block_id,
+            data: Some(buf),
+        }
+        .await;
+        CompletionFuture { driver: self, slot }.await;
+        self.complete(slot, None)
+    }
+}
+
+/// Wait for a free slot and submit the request in it
+struct SlotFuture<'a> {
+    driver: &'a VirtIOBlkPciDriver,
+    write: bool,
+    block_id: usize,
+    data: Option<&'a [u8]>,
+}
+
+impl Future for SlotFuture<'_> {
+    type Output = usize;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        if let Some(slot) = self.driver.try_submit(self.write, self.block_id, self.data) {
+            return Poll::Ready(slot);
+        }
+        let mut inner = self.driver.inner.lock();
+        if !inner.free_slots.is_empty() {
+            // a slot was freed just now
+            cx.waker().wake_by_ref();
+        } else {
+            inner.slot_wakers.push(cx.waker().clone());
+        }
+        Poll::Pending
+    }
+}
+
+/// Wait for the completion of the request in `slot`
+struct CompletionFuture<'a> {
+    driver: &'a VirtIOBlkPciDriver,
+    slot: usize,
+}
+
+impl Future for CompletionFuture<'_> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let mut inner = self.driver.inner.lock();
+        inner.process_used();
+        if inner.done[self.slot] {
+            return Poll::Ready(());
+        }
+        inner.wakers[self.slot] = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl Driver for VirtIOBlkPciDriver {
+    fn try_handle_interrupt(&self, irq: Option<usize>) -> bool {
+        if irq.is_some() && self.irq.is_some() && irq != self.irq {
+            return false;
+        }
+        // reading ISR acknowledges the interrupt
+        let isr = unsafe { self.read8(VIRTIO_PCI_ISR) };
+        if isr & 1 == 0 {
+            return false;
+        }
+        self.inner.lock().process_used();
+        true
+    }
+
+    fn device_type(&self) -> DeviceType {
+        DeviceType::Block
+    }
+
+    fn get_id(&self) -> String {
+        format!("virtio_blk_pci_{:#x}", self.io_base)
+    }
+
+    fn as_block(&self) -> Option<&dyn BlockDriver> {
+        Some(self)
+    }
+
+    fn as_net(&self) -> Option<&dyn NetDriver> {
+        None
+    }
+}
+
+impl BlockDriver for VirtIOBlkPciDriver {
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) -> bool {
+        if buf.len() < BLOCK_SIZE || block_id as u64 >= self.capacity {
+            return false;
+        }
+        let slot = self.request_sync(false, block_id, None);
+        self.complete(slot, Some(buf))
+    }
+
+    fn write_block(&self, block_id: usize, buf: &[u8]) -> bool {
+        if self.read_only || buf.len() < BLOCK_SIZE || block_id as u64 >= self.capacity {
+            return false;
+        }
+        let slot = self.request_sync(true, block_id, Some(buf));
+        self.complete(slot, None)
+    }
+
+    fn num_blocks(&self) -> Option<usize> {
+        Some(self.capacity as usize)
+    }
+}
+
+/// Legacy virtqueue size in bytes: descriptors and available ring,
+/// then the used ring starting at the next page
+fn queue_layout(queue_size: usize) -> (usize, usize) {
+    let align = |x: usize| (x + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+    let used_offset = align(queue_size * 16 + 6 + queue_size * 2);
+    let total = used_offset + align(6 + queue_size * 8);
+    (used_offset, total)
+}
+
+pub fn init(io_base: u16, irq: Option<usize>) -> Option<Arc<VirtIOBlkPciDriver>> {
+    let mut driver = VirtIOBlkPciDriver {
+        io_base,
+        irq,
+        read_only: false,
+        capacity: 0,
+        inner: Mutex::new(Inner {
+            queue: Dma { paddr: 0, vaddr: 0 },
+            queue_size: 0,
+            used_offset: 0,
+            avail_idx: 0,
+            last_used_idx: 0,
+            buffers: Dma { paddr: 0, vaddr: 0 },
+            free_slots: Vec::new(),
+            done: [false; MAX_SLOTS],
+            wakers: Default::default(),
+            slot_wakers: Vec::new(),
+        }),
+    };
+    unsafe {
+        // reset, then tell the device we know how to drive it
+        driver.write8(VIRTIO_PCI_STATUS, 0);
+        driver.write8(VIRTIO_PCI_STATUS, STATUS_ACKNOWLEDGE);
+        driver.write8(VIRTIO_PCI_STATUS, STATUS_ACKNOWLEDGE | STATUS_DRIVER);
+
+        // negotiate features: we only care whether it is read only
+        let host_features = driver.read32(VIRTIO_PCI_HOST_FEATURES);
+        driver.write32(VIRTIO_PCI_GUEST_FEATURES, host_features & VIRTIO_BLK_F_RO);
+        driver.read_only = host_features & VIRTIO_BLK_F_RO != 0;
+        driver.capacity = driver.read32(VIRTIO_PCI_CONFIG) as u64
+            | (driver.read32(VIRTIO_PCI_CONFIG + 4) as u64) << 32;
+
+        // set up queue 0
+        driver.write16(VIRTIO_PCI_QUEUE_SEL, 0);
+        let queue_size = driver.read16(VIRTIO_PCI_QUEUE_NUM) as usize;
+        if queue_size < DESC_PER_REQ {
+            warn!("virtio-blk: bad queue size {}", queue_size);
+            driver.write8(VIRTIO_PCI_STATUS, STATUS_FAILED);
+            return None;
+        }
+        let (used_offset, total) = queue_layout(queue_size);
+        let queue = Dma::new(total / PAGE_SIZE)?;
+        let slots = (queue_size / DESC_PER_REQ).min(MAX_SLOTS);
+        let buffers = Dma::new((slots * SLOT_SIZE + PAGE_SIZE - 1) / PAGE_SIZE)?;
+        driver.write32(VIRTIO_PCI_QUEUE_PFN, (queue.paddr / PAGE_SIZE) as u32);
+        {
+            let mut inner = driver.inner.lock();
+            inner.queue = queue;
+            inner.queue_size = queue_size;
+            inner.used_offset = used_offset;
+            inner.buffers = buffers;
+            inner.free_slots = (0..slots).rev().collect();
+        }
+
+        driver.write8(
+            VIRTIO_PCI_STATUS,
+            STATUS_ACKNOWLEDGE | STATUS_DRIVER | STATUS_DRIVER_OK,
+        );
+    }
+    info!(
+        "virtio-blk: io {:#x} irq {:?}, {} sectors{}",
+        io_base,
+        irq,
+        driver.capacity,
+        if driver.read_only { ", read only" } else { "" }
+    );
+
+    let driver = Arc::new(driver);
+    DRIVERS.write().push(driver.clone());
+    IRQ_MANAGER.write().register_opt(irq, driver.clone());
+    BLK_DRIVERS.write().push(driver.clone());
+    Some(driver)
+}
@@ -4,11 +4,17 @@ use crate::drivers::{Driver, DRIVERS, NET_DRIVERS};
 use crate::memory::phys_to_virt;
 use crate::sync::SpinLock as Mutex;
 use alloc::collections::BTreeMap;
+use alloc::string::String;
 use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicUsize, Ordering};
 use pci::*;
 use rcore_memory::PAGE_SIZE;
+use spin::RwLock;
 
 const PCI_COMMAND: u16 = 0x04;
+const PCI_STATUS: u16 = 0x06;
 const PCI_CAP_PTR: u16 = 0x34;
 const PCI_INTERRUPT_LINE: u16 = 0x3c;
 const PCI_INTERRUPT_PIN: u16 = 0x3d;
@@ -19,7 +25,29 @@ const PCI_MSI_UPPER_ADDR: u16 = 0x08;
 const PCI_MSI_DATA_32: u16 = 0x08;
 const PCI_MSI_DATA_64: u16 = 0x0C;
 
+const PCI_MSIX_CTRL: u16 = 0x02;
+const PCI_MSIX_TABLE: u16 = 0x04;
+
 const PCI_CAP_ID_MSI: u8 = 0x05;
+const PCI_CAP_ID_MSIX: u8 = 0x11;
+
+/// The function has a capability list
+const PCI_STATUS_CAP_LIST: u16 = 1 << 4;
+
+const MSIX_ENABLE: u16 = 1 << 15;
+const MSIX_FUNCTION_MASK: u16 = 1 << 14;
+/// Bytes of an entry of the MSI-X table: address, upper address, data and vector control
+const MSIX_ENTRY_SIZE: usize = 16;
+
+/// Address of messages to the LAPIC, whose id is put at bit 12.
+/// See the manual Volume 3 Chapter 10.11 Message Signalled Interrupts.
+const MSI_ADDR: u32 = 0xfee00000;
+/// 0 is (usually) the apic id of the bsp.
+const MSI_DEST_APIC_ID: u32 = 0;
+
+/// Irqs for MSI and MSI-X, after those of the IOAPIC
+const MSI_IRQ_MIN: usize = 32;
+const MSI_IRQ_MAX: usize = 0xbf;
 
 struct PortOpsImpl;
 
@@ -75,56 +103,60 @@ impl PortOps for PortOpsImpl {
     }
 }
 
+/// Allocate an irq for MSI or MSI-X, or `None` if all are taken
+fn alloc_msi_irq() -> Option<usize> {
+    static NEXT_MSI_IRQ: AtomicUsize = AtomicUsize::new(MSI_IRQ_MIN);
+    let irq = NEXT_MSI_IRQ.fetch_add(1, Ordering::Relaxed);
+    if irq > MSI_IRQ_MAX {
+        warn!("out of MSI irqs");
+        return None;
+    }
+    Some(irq)
+}
+
+/// Walk the capability list of the function at `loc`.
+/// Return the id and config space offset of each capability.
+unsafe fn capabilities(loc: Location) -> Vec<(u8, u16)> {
+    let ops = &PortOpsImpl;
+    let am = CSpaceAccessMethod::IO;
+    let mut caps = Vec::new();
+    if am.read16(ops, loc, PCI_STATUS) & PCI_STATUS_CAP_LIST == 0 {
+        return caps;
+    }
+    let mut cap_ptr = am.read8(ops, loc, PCI_CAP_PTR) as u16 & !0x3;
+    // there are at most 48 capabilities in the 192 bytes after the header,
+    // so stop there in case the list is broken into a loop
+    while cap_ptr >= 0x40 && caps.len() < 48 {
+        let cap_id = am.read8(ops, loc, cap_ptr);
+        caps.push((cap_id, cap_ptr));
+        cap_ptr = am.read8(ops, loc, cap_ptr + 1) as u16 & !0x3;
+    }
+    caps
+}
+
+/// Offset of the capability `id` of the function at `loc`
+unsafe fn find_capability(loc: Location, id: u8) -> Option<u16> {
+    capabilities(loc)
+        .into_iter()
+        .find(|&(cap_id, _)| cap_id == id)
+        .map(|(_, cap_ptr)| cap_ptr)
+}
+
 /// Enable the pci device and its interrupt
 /// Return assigned MSI interrupt number when applicable
 unsafe fn enable(loc: Location) -> Option<usize> {
     let ops = &PortOpsImpl;
     let am = CSpaceAccessMethod::IO;
 
-    // 23 and lower are used
-    static mut MSI_IRQ: u32 = 23;
-
     let orig = am.read16(ops, loc, PCI_COMMAND);
     // IO Space | MEM Space | Bus Mastering | Special Cycles | PCI Interrupt Disable
     am.write32(ops, loc, PCI_COMMAND, (orig | 0x40f) as u32);
 
-    // find MSI cap
-    let mut msi_found = false;
-    let mut cap_ptr = am.read8(ops, loc, PCI_CAP_PTR) as u16;
-    let mut assigned_irq = None;
-    while cap_ptr > 0 {
-        let cap_id = am.read8(ops, loc, cap_ptr);
-        if cap_id == PCI_CAP_ID_MSI {
-            let orig_ctrl = am.read32(ops, loc, cap_ptr + PCI_MSI_CTRL_CAP);
-            // The manual Volume 3 Chapter 10.11 Message Signalled Interrupts
-            // 0 is (usually) the apic id of the bsp.
-            am.write32(ops, loc, cap_ptr + PCI_MSI_ADDR, 0xfee00000 | (0 << 12));
-            MSI_IRQ += 1;
-            let irq = MSI_IRQ;
-            assigned_irq = Some(irq as usize);
-            // we offset all our irq numbers by 32
-            if (orig_ctrl >> 16) & (1 << 7) != 0 {
-                // 64bit
-                am.write32(ops, loc, cap_ptr + PCI_MSI_DATA_64, irq + 32);
-            } else {
-                // 32bit
-                am.write32(ops, loc, cap_ptr + PCI_MSI_DATA_32, irq + 32);
-            }
-
-            // enable MSI interrupt, assuming 64bit for now
-            am.write32(ops, loc, cap_ptr + PCI_MSI_CTRL_CAP, orig_ctrl | 0x10000);
-            debug!(
-                "MSI control {:#b}, enabling MSI interrupt {}",
-                orig_ctrl >> 16,
-                irq
-            );
-            msi_found = true;
-        }
-        debug!("PCI device has cap id {} at {:#X}", cap_id, cap_ptr);
-        cap_ptr = am.read8(ops, loc, cap_ptr + 1) as u16;
-    }
-
-    if !msi_found {
+    let assigned_irq = match find_capability(loc, PCI_CAP_ID_MSI) {
+        Some(cap_ptr) => enable_msi(loc, cap_ptr),
+        None => None,
+    };
+    if assigned_irq.is_none() {
         // Use PCI legacy interrupt instead
         // IO Space | MEM Space | Bus Mastering | Special Cycles
         am.write32(ops, loc, PCI_COMMAND, (orig | 0xf) as u32);
@@ -136,6 +168,87 @@ unsafe fn enable(loc: Location) -> Option<usize> {
     assigned_irq
 }
 
+/// Route MSI of the function at `loc`, whose capability is at `cap_ptr`, to a new irq
+unsafe fn enable_msi(loc: Location, cap_ptr: u16) -> Option<usize> {
+    let ops = &PortOpsImpl;
+    let am = CSpaceAccessMethod::IO;
+    let irq = alloc_msi_irq()?;
+    let orig_ctrl = am.read32(ops, loc, cap_ptr + PCI_MSI_CTRL_CAP);
+    am.write32(
+        ops,
+        loc,
+        cap_ptr + PCI_MSI_ADDR,
+        MSI_ADDR | (MSI_DEST_APIC_ID << 12),
+    );
+    // we offset all our irq numbers by 32
+    if (orig_ctrl >> 16) & (1 << 7) != 0 {
+        // 64bit
+        am.write32(ops, loc, cap_ptr + PCI_MSI_UPPER_ADDR, 0);
+        am.write32(ops, loc, cap_ptr + PCI_MSI_DATA_64, irq as u32 + 32);
+    } else {
+        // 32bit
+        am.write32(ops, loc, cap_ptr + PCI_MSI_DATA_32, irq as u32 + 32);
+    }
+
+    // enable MSI interrupt
+    am.write32(ops, loc, cap_ptr + PCI_MSI_CTRL_CAP, orig_ctrl | 0x10000);
+    debug!(
+        "MSI control {:#b}, enabling MSI interrupt {}",
+        orig_ctrl >> 16,
+        irq
+    );
+    Some(irq)
+}
+
+/// Enable the pci device `dev`, with all vectors of its MSI-X table routed to one new irq.
+/// Return the irq, or `None` if the device has no MSI-X, leaving it disabled.
+///
+/// Unlike `enable`, this is left to drivers, since MSI-X moves registers of some devices,
+/// e.g. the device specific config of legacy virtio.
+pub unsafe fn enable_msix(dev: &PCIFunction) -> Option<usize> {
+    let ops = &PortOpsImpl;
+    let am = CSpaceAccessMethod::IO;
+    let loc = dev.loc;
+
+    let cap_ptr = find_capability(loc, PCI_CAP_ID_MSIX)?;
+    let ctrl = am.read16(ops, loc, cap_ptr + PCI_MSIX_CTRL);
+    let table_size = (ctrl & 0x7ff) as usize + 1;
+    let table = am.read32(ops, loc, cap_ptr + PCI_MSIX_TABLE);
+    // the table is in memory, at an offset in one of the BARs
+    let table_addr = match dev.bars[(table & 0x7) as usize] {
+        Some(BAR::Memory(addr, _, _, _)) => addr as usize + (table & !0x7) as usize,
+        _ => return None,
+    };
+    let irq = alloc_msi_irq()?;
+
+    let orig = am.read16(ops, loc, PCI_COMMAND);
+    // MEM Space | Bus Mastering | PCI Interrupt Disable
+    am.write32(ops, loc, PCI_COMMAND, (orig | 0x406) as u32);
+    // mask all while the table is written
+    am.write16(ops, loc, cap_ptr + PCI_MSIX_CTRL, ctrl | MSIX_FUNCTION_MASK);
+    let base = phys_to_virt(table_addr);
+    for i in 0..table_size {
+        let entry = (base + i * MSIX_ENTRY_SIZE) as *mut u32;
+        entry.write_volatile(MSI_ADDR | (MSI_DEST_APIC_ID << 12));
+        entry.add(1).write_volatile(0);
+        // we offset all our irq numbers by 32
+        entry.add(2).write_volatile(irq as u32 + 32);
+        // unmasked
+        entry.add(3).write_volatile(0);
+    }
+    am.write16(
+        ops,
+        loc,
+        cap_ptr + PCI_MSIX_CTRL,
+        (ctrl | MSIX_ENABLE) & !MSIX_FUNCTION_MASK,
+    );
+    debug!(
+        "MSI-X table of {} entries at {:#x}, enabling MSI-X interrupt {}",
+        table_size, table_addr, irq
+    );
+    Some(irq)
+}
+
 pub fn init_driver(dev: &PCIDevice) {
     let name = format!("enp{}s{}f{}", dev.loc.bus, dev.loc.device, dev.loc.function);
     match (dev.id.vendor_id, dev.id.device_id) {
@@ -167,6 +280,17 @@ pub fn init_driver(dev: &PCIDevice) {
                 return;
             }
         }
+        #[cfg(target_arch = "x86_64")]
+        (0x1af4, 0x1001) => {
+            // legacy virtio block device
+            if let Some(BAR::IO(port, _)) = dev.bars[0] {
+                let irq = unsafe { enable(dev.loc) };
+                if let Some(driver) = virtio_blk_pci::init(port as u16, irq) {
+                    PCI_DRIVERS.lock().insert(dev.loc, driver);
+                }
+                return;
+            }
+        }
         _ => {}
     }
     if dev.id.class == 0x01 && dev.id.subclass == 0x06 {
@@ -199,6 +323,19 @@ pub fn detach_driver(loc: &Location) -> bool {
     }
 }
 
+/// A function found by the bus scan, for drivers to bind to by id
+#[derive(Debug, Clone)]
+pub struct PCIFunction {
+    pub loc: Location,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub class: u8,
+    pub subclass: u8,
+    pub bars: [Option<BAR>; 6],
+    /// Id and config space offset of each capability
+    pub capabilities: Vec<(u8, u16)>,
+}
+
 pub fn init() {
     let pci_iter = unsafe { scan_bus(&PortOpsImpl, CSpaceAccessMethod::IO) };
     for dev in pci_iter {
@@ -214,18 +351,74 @@ pub fn init() {
             dev.pic_interrupt_line,
             dev.interrupt_pin,
         );
+        let function = PCIFunction {
+            loc: dev.loc,
+            vendor_id: dev.id.vendor_id,
+            device_id: dev.id.device_id,
+            class: dev.id.class,
+            subclass: dev.id.subclass,
+            bars: dev.bars,
+            capabilities: unsafe { capabilities(dev.loc) },
+        };
+        for (i, bar) in function.bars.iter().enumerate() {
+            if let Some(bar) = bar {
+                info!("pci:   BAR{}: {:x?}", i, bar);
+            }
+        }
+        debug!("pci:   capabilities: {:x?}", function.capabilities);
+        PCI_DEVICES.write().push(function);
         init_driver(&dev);
     }
 }
 
+/// Functions found by the bus scan
+pub fn devices() -> Vec<PCIFunction> {
+    PCI_DEVICES.read().clone()
+}
+
+/// Functions found by the bus scan with ids `vendor` and `device`
+pub fn find_devices(vendor: u16, device: u16) -> Vec<PCIFunction> {
+    PCI_DEVICES
+        .read()
+        .iter()
+        .filter(|dev| dev.vendor_id == vendor && dev.device_id == device)
+        .cloned()
+        .collect()
+}
+
 pub fn find_device(vendor: u16, product: u16) -> Option<Location> {
-    let pci_iter = unsafe { scan_bus(&PortOpsImpl, CSpaceAccessMethod::IO) };
-    for dev in pci_iter {
-        if dev.id.vendor_id == vendor && dev.id.device_id == product {
-            return Some(dev.loc);
+    find_devices(vendor, product).first().map(|dev| dev.loc)
+}
+
+/// Content of `/proc/pci`: each function found by the scan and its BARs
+pub fn pciinfo() -> String {
+    let mut res = String::new();
+    for dev in PCI_DEVICES.read().iter() {
+        writeln!(
+            res,
+            "{:02x}:{:02x}.{} {:04x}:{:04x} class {:02x}{:02x}",
+            dev.loc.bus,
+            dev.loc.device,
+            dev.loc.function,
+            dev.vendor_id,
+            dev.device_id,
+            dev.class,
+            dev.subclass
+        )
+        .unwrap();
+        for (i, bar) in dev.bars.iter().enumerate() {
+            match bar {
+                Some(BAR::Memory(addr, len, _, _)) => {
+                    writeln!(res, "  BAR{}: mem {:#x} size {:#x}", i, addr, len).unwrap()
+                }
+                Some(BAR::IO(port, len)) => {
+                    writeln!(res, "  BAR{}: io {:#x} size {:#x}", i, port, len).unwrap()
+                }
+                None => {}
+            }
         }
     }
-    None
+    res
 }
 
 pub fn get_bar0_mem(loc: Location) -> Option<(usize, usize)> {
@@ -238,6 +431,7 @@ pub fn get_bar0_mem(loc: Location) -> Option<(usize, usize)> {
 }
 
 lazy_static! {
+    static ref PCI_DEVICES: RwLock<Vec<PCIFunction>> = RwLock::new(Vec::new());
     pub static ref PCI_DRIVERS: Mutex<BTreeMap<Location, Arc<dyn Driver>>> =
         Mutex::new(BTreeMap::new());
 }
@@ -22,7 +22,7 @@ use crate::sync::SpinNoIrqLock as Mutex;
 
 use super::{
     super::{DeviceType, Driver, DRIVERS, IRQ_MANAGER, NET_DRIVERS, SOCKET_ACTIVITY},
-    NetDriver,
+    ip_config, routes, NetDriver,
 };
 
 #[derive(Clone)]
@@ -197,15 +197,17 @@ pub fn init(name: String, irq: Option<usize>, header: usize, size: usize, index:
     let net_driver = E1000Driver(Arc::new(Mutex::new(e1000)));
 
     let ethernet_addr = EthernetAddress::from_bytes(&mac);
-    let ip_addrs = [IpCidr::new(IpAddress::v4(10, 0, index as u8, 2), 24)];
+    let (ip_addr, gateway) = ip_config(&name, index);
+    let ip_addrs = [ip_addr];
     let neighbor_cache = NeighborCache::new(BTreeMap::new());
     let iface = EthernetInterfaceBuilder::new(net_driver.clone())
         .ethernet_addr(ethernet_addr)
         .ip_addrs(ip_addrs)
         .neighbor_cache(neighbor_cache)
+        .routes(routes(gateway))
         .finalize();
 
-    info!("e1000 interface {} up with addr 10.0.{}.2/24", name, index);
+    info!("e1000 interface {} up with addr {}", name, ip_addr);
     let e1000_iface = E1000Interface {
         iface: Mutex::new(iface),
         driver: net_driver.clone(),
@@ -23,7 +23,7 @@ use super::{
     super::{
         provider::Provider, DeviceType, Driver, DRIVERS, IRQ_MANAGER, NET_DRIVERS, SOCKET_ACTIVITY,
     },
-    NetDriver,
+    ip_config, routes, NetDriver,
 };
 
 #[derive(Clone)]
@@ -214,17 +214,17 @@ pub fn ixgbe_init(
         mtu: 1500,
     };
 
-    let ip_addrs = [IpCidr::new(IpAddress::v4(10, 0, index as u8, 2), 24)];
+    let (ip_addr, gateway) = ip_config(&name, index);
+    let ip_addrs = [ip_addr];
     let neighbor_cache = NeighborCache::new(BTreeMap::new());
-    let routes = Routes::new(BTreeMap::new());
     let iface = EthernetInterfaceBuilder::new(net_driver.clone())
         .ethernet_addr(ethernet_addr)
         .ip_addrs(ip_addrs)
         .neighbor_cache(neighbor_cache)
-        .routes(routes)
+        .routes(routes(gateway))
         .finalize();
 
-    info!("ixgbe interface {} up with addr 10.0.{}.2/24", name, index);
+    info!("ixgbe interface {} up with addr {}", name, ip_addr);
 
     let ixgbe_iface = IXGBEInterface {
         iface: Mutex::new(iface),
@@ -0,0 +1,121 @@
+//! Loopback network interface
+//!
+//! Frames sent to `127.0.0.0/8` are queued by `smoltcp::phy::Loopback`
+//! and received by the next poll, so local sockets work without any hardware.
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use smoltcp::iface::*;
+use smoltcp::phy::Loopback;
+use smoltcp::time::Instant;
+use smoltcp::wire::*;
+
+use super::{
+    super::{DeviceType, Driver, DRIVERS, NET_DRIVERS, SOCKET_ACTIVITY},
+    NetDriver,
+};
+use crate::net::SOCKETS;
+use crate::{drivers::BlockDriver, sync::SpinNoIrqLock as Mutex};
+
+/// Max number of polls to drain the queue, in case sockets keep talking
+const MAX_POLLS: usize = 64;
+
+pub struct LoopbackInterface {
+    iface: Mutex<EthernetInterface<'static, 'static, 'static, Loopback>>,
+}
+
+impl Driver for LoopbackInterface {
+    fn try_handle_interrupt(&self, _irq: Option<usize>) -> bool {
+        false
+    }
+
+    fn device_type(&self) -> DeviceType {
+        DeviceType::Net
+    }
+
+    fn get_id(&self) -> String {
+        String::from("loopback")
+    }
+
+    fn as_net(&self) -> Option<&dyn NetDriver> {
+        Some(self)
+    }
+
+    fn as_block(&self) -> Option<&dyn BlockDriver> {
+        None
+    }
+}
+
+impl NetDriver for LoopbackInterface {
+    fn get_mac(&self) -> EthernetAddress {
+        self.iface.lock().ethernet_addr()
+    }
+
+    fn get_ifname(&self) -> String {
+        String::from("lo")
+    }
+
+    fn get_ip_addresses(&self) -> Vec<IpCidr> {
+        Vec::from(self.iface.lock().ip_addrs())
+    }
+
+    fn ipv4_address(&self) -> Option<Ipv4Address> {
+        self.iface.lock().ipv4_address()
+    }
+
+    fn poll(&self) {
+        // a frame sent in one poll is only received in the next one,
+        // so keep polling until nothing changes, e.g. a whole TCP handshake.
+        // The socket set is unlocked between polls to let other CPUs use their sockets.
+        let mut changed = false;
+        for _ in 0..MAX_POLLS {
+            let timestamp = Instant::from_millis(crate::trap::uptime_msec() as i64);
+            let result = {
+                let mut sockets = SOCKETS.lock();
+                self.iface.lock().poll(&mut sockets, timestamp)
+            };
+            match result {
+                Ok(true) => changed = true,
+                Ok(false) => break,
+                Err(err) => {
+                    debug!("poll got err {}", err);
+                }
+            }
+        }
+        if changed {
+            SOCKET_ACTIVITY.notify_all();
+        }
+    }
+
+    fn send(&self, _data: &[u8]) -> Option<usize> {
+        None
+    }
+
+    fn get_arp(&self, ip: IpAddress) -> Option<EthernetAddress> {
+        let iface = self.iface.lock();
+        let cache = iface.neighbor_cache();
+        cache.lookup_pure(&ip, Instant::from_millis(0))
+    }
+}
+
+/// Bring up `lo` with addr 127.0.0.1/8
+pub fn init() {
+    let ethernet_addr = EthernetAddress([0x02, 0, 0, 0, 0, 1]);
+    let ip_addrs = [IpCidr::new(IpAddress::v4(127, 0, 0, 1), 8)];
+    let neighbor_cache = NeighborCache::new(BTreeMap::new());
+    let iface = EthernetInterfaceBuilder::new(Loopback::new())
+        .ethernet_addr(ethernet_addr)
+        .ip_addrs(ip_addrs)
+        .neighbor_cache(neighbor_cache)
+        .finalize();
+
+    info!("loopback interface lo up with addr 127.0.0.1/8");
+    let lo = Arc::new(LoopbackInterface {
+        iface: Mutex::new(iface),
+    });
+    DRIVERS.write().push(lo.clone());
+    NET_DRIVERS.write().push(lo);
+}
@@ -1,10 +1,18 @@
-use super::Driver;
+//! Network interfaces, each an smoltcp `EthernetInterface` polled on interrupts
+//!
+//! ICMP echo requests to the addresses of an interface are answered by smoltcp
+//! while it is polled, so `ping` works with no responder here.
+
+use super::{Driver, CMDLINE};
+use alloc::collections::BTreeMap;
 use alloc::string::String;
 use alloc::vec::Vec;
-use smoltcp::wire::{EthernetAddress, IpAddress, IpCidr, Ipv4Address};
+use smoltcp::iface::Routes;
+use smoltcp::wire::{EthernetAddress, IpAddress, IpCidr, Ipv4Address, Ipv4Cidr};
 
 pub mod e1000;
 pub mod ixgbe;
+pub mod loopback;
 pub mod virtio_net;
 
 pub trait NetDriver: Driver {
@@ -43,3 +51,58 @@ pub trait NetDriver: Driver {
         unimplemented!("not a net driver")
     }
 }
+
+/// Address and default gateway of interface `ifname`, the `index`th one probed.
+///
+/// They are set in the kernel command line as on Linux,
+/// by `ip=<client-ip>::<gw-ip>:<netmask>::<device>`, where no device means `eth0`.
+/// Otherwise the interface gets `10.0.<index>.2/24` and no gateway.
+pub fn ip_config(ifname: &str, index: usize) -> (IpCidr, Option<Ipv4Address>) {
+    let cmdline = CMDLINE.read();
+    let config = cmdline
+        .split_whitespace()
+        .filter(|arg| arg.starts_with("ip="))
+        .map(|arg| arg["ip=".len()..].split(':').collect::<Vec<_>>())
+        .find(|fields| match fields.get(5) {
+            Some(&device) if !device.is_empty() => device == ifname,
+            _ => ifname == "eth0",
+        });
+    let default = IpCidr::new(IpAddress::v4(10, 0, index as u8, 2), 24);
+    let fields = match config {
+        Some(fields) => fields,
+        None => return (default, None),
+    };
+    let field = |i: usize| fields.get(i).and_then(|field| parse_ipv4(field));
+    let addr = match field(0) {
+        Some(addr) => addr,
+        None => {
+            warn!("ip: bad address for {}, using {}", ifname, default);
+            return (default, None);
+        }
+    };
+    // the class C netmask by default, as most setups use
+    let prefix_len = field(3).map_or(24, |mask| (!u32::from_be_bytes(mask.0)).leading_zeros());
+    let cidr = IpCidr::Ipv4(Ipv4Cidr::new(addr, prefix_len as u8));
+    (cidr, field(2))
+}
+
+/// Routing table with only a default route through `gateway` if any
+pub fn routes(gateway: Option<Ipv4Address>) -> Routes<'static> {
+    let mut routes = Routes::new(BTreeMap::new());
+    if let Some(gateway) = gateway {
+        routes.add_default_ipv4_route(gateway).unwrap();
+    }
+    routes
+}
+
+fn parse_ipv4(s: &str) -> Option<Ipv4Address> {
+    let mut bytes = [0u8; 4];
+    let mut parts = s.split('.');
+    for byte in bytes.iter_mut() {
+        *byte = parts.next()?.parse().ok()?;
+    }
+    match parts.next() {
+        Some(_) => None,
+        None => Some(Ipv4Address(bytes)),
+    }
+}
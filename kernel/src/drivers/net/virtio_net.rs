@@ -1,43 +1,63 @@
+//! VirtIO network device driver
+//!
+//! Feature negotiation and the RX/TX virtqueues are handled by `virtio_drivers`.
+//! Received frames are processed when the device interrupts.
+
+use alloc::collections::BTreeMap;
 use alloc::format;
 use alloc::string::String;
 use alloc::sync::Arc;
+use alloc::vec::Vec;
 
+use smoltcp::iface::*;
 use smoltcp::phy::{self, DeviceCapabilities};
 use smoltcp::time::Instant;
-use smoltcp::wire::{EthernetAddress, Ipv4Address};
+use smoltcp::wire::*;
 use smoltcp::Result;
 use virtio_drivers::{VirtIOHeader, VirtIONet};
 
 use super::{
-    super::{DeviceType, Driver, DRIVERS, IRQ_MANAGER, NET_DRIVERS},
-    NetDriver,
+    super::{DeviceType, Driver, DRIVERS, IRQ_MANAGER, NET_DRIVERS, SOCKET_ACTIVITY},
+    ip_config, routes, NetDriver,
 };
+use crate::net::SOCKETS;
 use crate::{drivers::BlockDriver, sync::SpinNoIrqLock as Mutex};
 
+/// Max size of an ethernet frame, without the virtio header
+const FRAME_SIZE: usize = 1536;
+
 #[derive(Clone)]
 pub struct VirtIONetDriver(Arc<Mutex<VirtIONet<'static>>>);
 
-impl NetDriver for VirtIONetDriver {
-    fn get_mac(&self) -> EthernetAddress {
-        EthernetAddress(self.0.lock().mac())
-    }
-
-    fn get_ifname(&self) -> String {
-        format!("virtio{:?}", self.0.lock().mac())
-    }
-
-    fn ipv4_address(&self) -> Option<Ipv4Address> {
-        unimplemented!()
-    }
+pub struct VirtIONetInterface {
+    iface: Mutex<EthernetInterface<'static, 'static, 'static, VirtIONetDriver>>,
+    driver: VirtIONetDriver,
+    name: String,
+}
 
-    fn poll(&self) {
-        unimplemented!()
+impl VirtIONetInterface {
+    fn poll_iface(&self) {
+        let timestamp = Instant::from_millis(crate::trap::uptime_msec() as i64);
+        let mut sockets = SOCKETS.lock();
+        match self.iface.lock().poll(&mut sockets, timestamp) {
+            Ok(true) => {
+                SOCKET_ACTIVITY.notify_all();
+            }
+            Ok(false) => {}
+            Err(err) => {
+                debug!("poll got err {}", err);
+            }
+        }
     }
 }
 
-impl Driver for VirtIONetDriver {
+impl Driver for VirtIONetInterface {
     fn try_handle_interrupt(&self, _irq: Option<usize>) -> bool {
-        self.0.lock().ack_interrupt()
+        let handled = self.driver.0.lock().ack_interrupt();
+        if handled {
+            self.poll_iface();
+        }
+        handled
     }
 
     fn device_type(&self) -> DeviceType {
@@ -45,7 +65,7 @@ impl Driver for VirtIONetDriver {
     }
 
     fn get_id(&self) -> String {
-        format!("virtio_net")
+        format!("virtio_net_{}", self.name)
     }
 
     fn as_net(&self) -> Option<&dyn NetDriver> {
@@ -57,23 +77,77 @@ impl Driver for VirtIONetDriver {
     }
 }
 
+impl NetDriver for VirtIONetInterface {
+    fn get_mac(&self) -> EthernetAddress {
+        self.iface.lock().ethernet_addr()
+    }
+
+    fn get_ifname(&self) -> String {
+        self.name.clone()
+    }
+
+    fn get_ip_addresses(&self) -> Vec<IpCidr> {
+        Vec::from(self.iface.lock().ip_addrs())
+    }
+
+    fn ipv4_address(&self) -> Option<Ipv4Address> {
+        self.iface.lock().ipv4_address()
+    }
+
+    fn poll(&self) {
+        self.poll_iface();
+    }
+
+    fn send(&self, data: &[u8]) -> Option<usize> {
+        use smoltcp::phy::TxToken;
+        if data.len() > FRAME_SIZE || !self.driver.0.lock().can_send() {
+            return None;
+        }
+        let token = VirtIONetTxToken(self.driver.clone());
+        token
+            .consume(Instant::from_millis(0), data.len(), |buffer| {
+                buffer.copy_from_slice(&data);
+                Ok(())
+            })
+            .ok()
+            .map(|_| data.len())
+    }
+
+    fn get_arp(&self, ip: IpAddress) -> Option<EthernetAddress> {
+        let iface = self.iface.lock();
+        let cache = iface.neighbor_cache();
+        cache.lookup_pure(&ip, Instant::from_millis(0))
+    }
+}
+
+pub struct VirtIONetRxToken(Vec<u8>);
+pub struct VirtIONetTxToken(VirtIONetDriver);
+
 impl phy::Device<'_> for VirtIONetDriver {
-    type RxToken = VirtIONetDriver;
-    type TxToken = VirtIONetDriver;
+    type RxToken = VirtIONetRxToken;
+    type TxToken = VirtIONetTxToken;
 
     fn receive(&mut self) -> Option<(Self::RxToken, Self::TxToken)> {
-        let net = self.0.lock();
-        if net.can_recv() {
-            Some((self.clone(), self.clone()))
-        } else {
-            None
+        let mut net = self.0.lock();
+        if !net.can_recv() {
+            return None;
+        }
+        let mut buffer = vec![0u8; FRAME_SIZE];
+        match net.recv(&mut buffer) {
+            Ok(len) => {
+                buffer.truncate(len);
+                Some((VirtIONetRxToken(buffer), VirtIONetTxToken(self.clone())))
+            }
+            Err(err) => {
+                warn!("virtio_net: failed to recv packet: {:?}", err);
+                None
+            }
         }
     }
 
     fn transmit(&mut self) -> Option<Self::TxToken> {
-        let net = self.0.lock();
-        if net.can_send() {
-            Some(self.clone())
+        if self.0.lock().can_send() {
+            Some(VirtIONetTxToken(self.clone()))
         } else {
             None
         }
@@ -81,42 +155,66 @@ impl phy::Device<'_> for VirtIONetDriver {
 
     fn capabilities(&self) -> DeviceCapabilities {
         let mut caps = DeviceCapabilities::default();
-        caps.max_transmission_unit = 1536;
+        caps.max_transmission_unit = FRAME_SIZE;
         caps.max_burst_size = Some(1);
         caps
     }
 }
 
-impl phy::RxToken for VirtIONetDriver {
-    fn consume<R, F>(self, _timestamp: Instant, f: F) -> Result<R>
+impl phy::RxToken for VirtIONetRxToken {
+    fn consume<R, F>(mut self, _timestamp: Instant, f: F) -> Result<R>
     where
         F: FnOnce(&mut [u8]) -> Result<R>,
     {
-        let mut buffer = [0u8; 2000];
-        let mut driver = self.0.lock();
-        let len = driver.recv(&mut buffer).expect("failed to recv packet");
-        f(&mut buffer[..len])
+        f(&mut self.0)
     }
 }
 
-impl phy::TxToken for VirtIONetDriver {
+impl phy::TxToken for VirtIONetTxToken {
     fn consume<R, F>(self, _timestamp: Instant, len: usize, f: F) -> Result<R>
     where
         F: FnOnce(&mut [u8]) -> Result<R>,
     {
-        let mut buffer = [0u8; 2000];
+        let mut buffer = [0u8; FRAME_SIZE];
         let result = f(&mut buffer[..len]);
-        let mut driver = self.0.lock();
-        driver.send(&buffer).expect("failed to send packet");
+        let mut driver = (self.0).0.lock();
+        if driver.send(&buffer[..len]).is_err() {
+            warn!("virtio_net: failed to send packet");
+            return Err(smoltcp::Error::Exhausted);
+        }
         result
     }
 }
 
 pub fn init(header: &'static mut VirtIOHeader) {
     let net = VirtIONet::new(header).expect("failed to create net driver");
-    let driver = Arc::new(VirtIONetDriver(Arc::new(Mutex::new(net))));
-
-    DRIVERS.write().push(driver.clone());
-    IRQ_MANAGER.write().register_all(driver.clone());
-    NET_DRIVERS.write().push(driver);
+    let mac = net.mac();
+    let driver = VirtIONetDriver(Arc::new(Mutex::new(net)));
+
+    let index = NET_DRIVERS.read().len();
+    let name = format!("eth{}", index);
+    let ethernet_addr = EthernetAddress::from_bytes(&mac);
+    let (ip_addr, gateway) = ip_config(&name, index);
+    let ip_addrs = [ip_addr];
+    let neighbor_cache = NeighborCache::new(BTreeMap::new());
+    let iface = EthernetInterfaceBuilder::new(driver.clone())
+        .ethernet_addr(ethernet_addr)
+        .ip_addrs(ip_addrs)
+        .neighbor_cache(neighbor_cache)
+        .routes(routes(gateway))
+        .finalize();
+
+    info!(
+        "virtio_net interface {} ({}) up with addr {}",
+        name, ethernet_addr, ip_addr
+    );
+    let virtio_iface = Arc::new(VirtIONetInterface {
+        iface: Mutex::new(iface),
+        driver,
+        name,
+    });
+
+    DRIVERS.write().push(virtio_iface.clone());
+    IRQ_MANAGER.write().register_all(virtio_iface.clone());
+    NET_DRIVERS.write().push(virtio_iface);
 }
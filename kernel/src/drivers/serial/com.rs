@@ -9,6 +9,7 @@ use crate::{
 use alloc::string::String;
 use alloc::sync::Arc;
 use uart_16550::SerialPort;
+use x86_64::instructions::port::Port;
 
 pub const COM2: usize = 3;
 pub const COM1: usize = 4;
@@ -24,12 +25,21 @@ impl COM {
         port.lock().init();
         COM { port, base }
     }
+
+    /// Data ready bit of the line status register
+    fn can_read(&self) -> bool {
+        let mut line_status = Port::<u8>::new(self.base + 5);
+        unsafe { line_status.read() & 1 != 0 }
+    }
 }
 
 impl Driver for COM {
     fn try_handle_interrupt(&self, irq: Option<usize>) -> bool {
-        let c = self.read();
-        crate::trap::serial(c);
+        // the FIFO may hold several bytes for one interrupt
+        while self.can_read() {
+            let c = self.read();
+            crate::trap::serial(c);
+        }
         true
     }
 
@@ -8,7 +8,8 @@ use crate::{
 };
 use alloc::string::String;
 use alloc::sync::Arc;
-use pc_keyboard::{layouts, DecodedKey, HandleControl, KeyCode, ScancodeSet1};
+use core::sync::atomic::{AtomicBool, Ordering};
+use pc_keyboard::{layouts, DecodedKey, HandleControl, KeyCode, KeyState, ScancodeSet1};
 use uart_16550::SerialPort;
 use x86_64::instructions::port::Port;
 
@@ -16,6 +17,8 @@ pub const Keyboard: usize = 1;
 
 struct Keyboard {
     keyboard: Mutex<pc_keyboard::Keyboard<layouts::Us104Key, ScancodeSet1>>,
+    /// Ctrl is held, only letters are mapped to control chars by `pc_keyboard`
+    ctrl: AtomicBool,
 }
 
 impl Keyboard {
@@ -24,8 +27,9 @@ impl Keyboard {
             keyboard: Mutex::new(pc_keyboard::Keyboard::new(
                 layouts::Us104Key,
                 ScancodeSet1,
-                HandleControl::Ignore,
+                HandleControl::MapLettersToUnicode,
             )),
+            ctrl: AtomicBool::new(false),
         }
     }
 }
@@ -39,9 +43,21 @@ impl Driver for Keyboard {
         if unsafe { status_port.read() } & (1 << 0) != 0 {
             let scancode = unsafe { data_port.read() };
             if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
+                match key_event.code {
+                    KeyCode::ControlLeft | KeyCode::ControlRight => {
+                        let down = key_event.state == KeyState::Down;
+                        self.ctrl.store(down, Ordering::Relaxed);
+                    }
+                    _ => {}
+                }
                 if let Some(key) = keyboard.process_keyevent(key_event) {
                     match key {
                         DecodedKey::Unicode(c) => {
+                            // Ctrl-\ is QUIT
+                            let c = match c {
+                                '\\' if self.ctrl.load(Ordering::Relaxed) => '\u{1c}',
+                                c => c,
+                            };
                             // at most 4 is needed
                             let mut buffer = [0u8; 4];
                             let res = c.encode_utf8(&mut buffer);
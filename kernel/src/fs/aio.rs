@@ -0,0 +1,390 @@
+//! Contexts of Linux AIO, created by `io_setup`
+//!
+//! Each operation queued by `io_submit` runs as a task on the executor,
+//! so a thread may have many of them in flight without blocking.
+//! Completions are queued on the context, and `io_getevents` waits for them on its event bus.
+//! Only files are supported yet.
+//!
+//! The contexts of a process are cancelled when it execs or exits, see `AioContext::cancel`.
+
+use super::FileHandle;
+use crate::arch::timer::timer_now;
+use crate::memory::{MemorySet, PageFaultCause};
+use crate::process::{lock_memory_set, spawn_task, Thread};
+use crate::sync::{Event, EventBus, SpinNoIrqLock as Mutex};
+use crate::syscall::{SysError, SysResult};
+use crate::trap::NAIVE_TIMER;
+use alloc::{
+    boxed::Box,
+    collections::{BTreeSet, VecDeque},
+    sync::Arc,
+    vec,
+    vec::Vec,
+};
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::{Context, Poll};
+use core::time::Duration;
+use rcore_memory::paging::PageTable;
+use rcore_memory::PAGE_SIZE;
+
+/// `struct iocb` of Linux
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct IoCb {
+    /// Returned in the event as it is
+    pub data: u64,
+    pub key: u32,
+    /// `RWF_*`
+    pub rw_flags: i32,
+    /// `IOCB_CMD_*`
+    pub lio_opcode: u16,
+    pub reqprio: i16,
+    pub fildes: u32,
+    pub buf: u64,
+    pub nbytes: u64,
+    pub offset: i64,
+    pub reserved2: u64,
+    /// `IOCB_FLAG_*`
+    pub flags: u32,
+    /// Eventfd to signal with `IOCB_FLAG_RESFD`
+    pub resfd: u32,
+}
+
+/// `struct io_event` of Linux
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct IoEvent {
+    /// `data` of the iocb
+    pub data: u64,
+    /// Address of the iocb
+    pub obj: u64,
+    /// Bytes transferred, or a negated errno
+    pub res: i64,
+    pub res2: i64,
+}
+
+pub const IOCB_CMD_PREAD: u16 = 0;
+pub const IOCB_CMD_PWRITE: u16 = 1;
+pub const IOCB_CMD_FSYNC: u16 = 2;
+pub const IOCB_CMD_FDSYNC: u16 = 3;
+
+/// Bytes copied between the file and user memory at a time
+const CHUNK_SIZE: usize = 16 * PAGE_SIZE;
+
+/// What an iocb asks for, with user buffers in the address space of the submitter.
+/// Without an offset, the file position is used and advanced, which io_uring allows.
+pub enum AioOp {
+    Read {
+        buf: usize,
+        len: usize,
+        offset: Option<usize>,
+    },
+    Write {
+        buf: usize,
+        len: usize,
+        offset: Option<usize>,
+    },
+    Sync {
+        data_only: bool,
+    },
+}
+
+pub struct AioContext {
+    /// Events which may be pending at once, given to `io_setup`
+    capacity: usize,
+    queue: Mutex<AioQueue>,
+    /// `READABLE` is notified on each completion, and `CLOSED` on cancellation
+    eventbus: Arc<Mutex<EventBus>>,
+    /// Set when the process execs or exits
+    cancelled: AtomicBool,
+}
+
+#[derive(Default)]
+struct AioQueue {
+    /// Addresses of the iocbs of operations still running
+    in_flight: BTreeSet<usize>,
+    completed: VecDeque<IoEvent>,
+}
+
+impl AioContext {
+    pub fn new(capacity: usize) -> Arc<Self> {
+        Arc::new(AioContext {
+            capacity,
+            queue: Mutex::new(AioQueue::default()),
+            eventbus: EventBus::new(),
+            cancelled: AtomicBool::new(false),
+        })
+    }
+
+    /// Start `op` on `file` for the iocb at `obj`, whose user data is `data`.
+    /// Fail with EAGAIN if as many events as the capacity are pending.
+    pub fn submit(
+        self: &Arc<Self>,
+        obj: usize,
+        data: u64,
+        file: FileHandle,
+        vm: Arc<Mutex<MemorySet>>,
+        op: AioOp,
+    ) -> Result<(), SysError> {
+        let mut queue = self.queue.lock();
+        if queue.in_flight.len() + queue.completed.len() >= self.capacity {
+            return Err(SysError::EAGAIN);
+        }
+        queue.in_flight.insert(obj);
+        drop(queue);
+        let context = self.clone();
+        spawn_task(async move {
+            let res = Cancellable {
+                context: context.clone(),
+                inner: Box::pin(run(file, vm, op)),
+            }
+            .await;
+            context.complete(obj, data, res);
+        });
+        Ok(())
+    }
+
+    /// Stop the operations in flight, as the process execs or exits (`exit_aio` of Linux).
+    /// Each completes with ECANCELED instead of running further,
+    /// so that the memory set of the process is not written to or kept alive by them.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+        self.eventbus.lock().notify_all(Event::CLOSED);
+    }
+
+    /// Whether the operation of the iocb at `obj` is still running
+    pub fn is_in_flight(&self, obj: usize) -> bool {
+        self.queue.lock().in_flight.contains(&obj)
+    }
+
+    fn complete(&self, obj: usize, data: u64, res: SysResult) {
+        let mut queue = self.queue.lock();
+        queue.in_flight.remove(&obj);
+        queue.completed.push_back(IoEvent {
+            data,
+            obj: obj as u64,
+            res: match res {
+                Ok(len) => len as i64,
+                Err(err) => -(err as i64),
+            },
+            res2: 0,
+        });
+        drop(queue);
+        self.eventbus.lock().notify_all(Event::READABLE);
+    }
+
+    /// Wait until `min_nr` events have completed, then take up to `nr` of them.
+    /// At `deadline` take what there is, maybe nothing.
+    /// Fail with EINTR if `thread` gets a signal before.
+    pub fn get_events(
+        self: &Arc<Self>,
+        thread: Arc<Thread>,
+        min_nr: usize,
+        nr: usize,
+        deadline: Option<Duration>,
+    ) -> impl Future<Output = Result<Vec<IoEvent>, SysError>> {
+        GetEventsFuture {
+            context: self.clone(),
+            thread,
+            min_nr,
+            nr,
+            deadline,
+        }
+    }
+}
+
+#[must_use = "future does nothing unless polled/`await`-ed"]
+struct GetEventsFuture {
+    context: Arc<AioContext>,
+    thread: Arc<Thread>,
+    min_nr: usize,
+    nr: usize,
+    deadline: Option<Duration>,
+}
+
+impl Future for GetEventsFuture {
+    type Output = Result<Vec<IoEvent>, SysError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let mut queue = self.context.queue.lock();
+        let expired = self
+            .deadline
+            .map_or(false, |deadline| timer_now() >= deadline);
+        if queue.completed.len() >= self.min_nr || expired {
+            let count = queue.completed.len().min(self.nr);
+            return Poll::Ready(Ok(queue.completed.drain(..count).collect()));
+        }
+        if self.thread.has_signal_to_handle() {
+            return Poll::Ready(Err(SysError::EINTR));
+        }
+
+        // subscribe while the queue is locked, so no completion is missed
+        let waker = cx.waker().clone();
+        self.context.eventbus.lock().subscribe(Box::new(move |_| {
+            waker.wake_by_ref();
+            true
+        }));
+        drop(queue);
+        if let Some(deadline) = self.deadline {
+            let waker = cx.waker().clone();
+            NAIVE_TIMER
+                .lock()
+                .add(deadline, Box::new(move |_| waker.wake()));
+        }
+        // signals
+        let waker = cx.waker().clone();
+        let eventbus = self.thread.lock_proc().eventbus.clone();
+        eventbus.lock().subscribe(Box::new(move |_| {
+            waker.wake_by_ref();
+            true
+        }));
+        Poll::Pending
+    }
+}
+
+/// An operation of `context`, which ends with ECANCELED once the context is cancelled
+#[must_use = "future does nothing unless polled/`await`-ed"]
+struct Cancellable<F> {
+    context: Arc<AioContext>,
+    inner: Pin<Box<F>>,
+}
+
+impl<F: Future<Output = SysResult>> Future for Cancellable<F> {
+    type Output = SysResult;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        if self.context.cancelled.load(Ordering::SeqCst) {
+            return Poll::Ready(Err(SysError::ECANCELED));
+        }
+        let res = self.inner.as_mut().poll(cx);
+        if res.is_pending() {
+            let waker = cx.waker().clone();
+            // woken by completions too, which is harmless
+            self.context.eventbus.lock().subscribe(Box::new(move |_| {
+                waker.wake_by_ref();
+                true
+            }));
+            // it may have been cancelled before subscribing
+            if self.context.cancelled.load(Ordering::SeqCst) {
+                return Poll::Ready(Err(SysError::ECANCELED));
+            }
+        }
+        res
+    }
+}
+
+/// Run `op` on `file`, returning the bytes transferred.
+/// An error after part of them is dropped, as by read and write.
+pub async fn run(mut file: FileHandle, vm: Arc<Mutex<MemorySet>>, op: AioOp) -> SysResult {
+    match op {
+        AioOp::Read { buf, len, offset } => {
+            let mut chunk = vec![0u8; len.min(CHUNK_SIZE)];
+            let mut done = 0;
+            while done < len {
+                let want = (len - done).min(CHUNK_SIZE);
+                let read = match offset {
+                    Some(offset) => file.read_at(offset + done, &mut chunk[..want]).await,
+                    None => file.read(&mut chunk[..want]).await,
+                };
+                let res = match read {
+                    Ok(read) => {
+                        let mut vm = lock_memory_set(&vm);
+                        access_vm(&mut vm, buf + done, &mut chunk[..read], true).map(|_| read)
+                    }
+                    Err(err) => Err(err.into()),
+                };
+                match res {
+                    Ok(read) => {
+                        done += read;
+                        if read < want {
+                            break;
+                        }
+                    }
+                    Err(err) if done == 0 => return Err(err),
+                    Err(_) => break,
+                }
+            }
+            Ok(done)
+        }
+        AioOp::Write { buf, len, offset } => {
+            let mut chunk = vec![0u8; len.min(CHUNK_SIZE)];
+            let mut done = 0;
+            while done < len {
+                let want = (len - done).min(CHUNK_SIZE);
+                let mut locked = lock_memory_set(&vm);
+                let copied = access_vm(&mut locked, buf + done, &mut chunk[..want], false);
+                drop(locked);
+                let res = match copied {
+                    Ok(_) => match offset {
+                        Some(offset) => file.write_at(offset + done, &chunk[..want]).await,
+                        None => file.write(&chunk[..want]).await,
+                    }
+                    .map_err(SysError::from),
+                    Err(err) => Err(err),
+                };
+                match res {
+                    Ok(written) => {
+                        done += written;
+                        if written < want {
+                            break;
+                        }
+                    }
+                    Err(err) if done == 0 => return Err(err),
+                    Err(_) => break,
+                }
+            }
+            Ok(done)
+        }
+        AioOp::Sync { data_only: true } => {
+            file.sync_data()?;
+            Ok(0)
+        }
+        AioOp::Sync { data_only: false } => {
+            file.sync_all()?;
+            Ok(0)
+        }
+    }
+}
+
+/// Copy between `buf` and `[addr, addr + buf.len())` of `vm`, which may not be active here,
+/// writing to it if `write`. Pages are faulted in as the user access would.
+fn access_vm(vm: &mut MemorySet, addr: usize, buf: &mut [u8], write: bool) -> Result<(), SysError> {
+    unsafe {
+        if write {
+            vm.check_write_array(addr as *mut u8, buf.len())?;
+        } else {
+            vm.check_read_array(addr as *const u8, buf.len())?;
+        }
+    }
+    let mut pos = 0;
+    while pos < buf.len() {
+        let vaddr = addr + pos;
+        let (present, writable) = match vm.get_page_table_mut().get_entry(vaddr) {
+            Some(entry) => (entry.present(), entry.writable()),
+            None => (false, false),
+        };
+        let cause = PageFaultCause {
+            write,
+            exec: false,
+            present,
+        };
+        // read-only pages may be copied on write, or marked by MADV_FREE
+        if (!present || (write && !writable)) && !vm.handle_page_fault(vaddr, cause) {
+            return Err(SysError::EFAULT);
+        }
+        let page_offset = vaddr % PAGE_SIZE;
+        let len = (PAGE_SIZE - page_offset).min(buf.len() - pos);
+        let pt = vm.get_page_table_mut();
+        let page = pt.get_page_slice_mut(vaddr);
+        if write {
+            page[page_offset..page_offset + len].copy_from_slice(&buf[pos..pos + len]);
+            pt.flush_cache_copy_user(vaddr, vaddr + len, false);
+        } else {
+            buf[pos..pos + len].copy_from_slice(&page[page_offset..page_offset + len]);
+        }
+        pos += len;
+    }
+    Ok(())
+}
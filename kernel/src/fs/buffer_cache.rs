@@ -0,0 +1,554 @@
+//! Cache of disk blocks between file systems and block drivers
+//!
+//! Blocks are cached in buffers of one frame each, covering `BLOCKS_PER_BUFFER`
+//! consecutive blocks which are loaded on demand. Buffers are indexed by their
+//! first block and evicted in LRU order when the cache is full, or when the frame
+//! allocator runs out of memory.
+//!
+//! In write-back mode, written blocks stay dirty in the cache until `sync`
+//! or eviction. In write-through mode, they reach the device immediately.
+//!
+//! File data is cached by the page cache, which reads and writes it `uncached`:
+//! those blocks pass through without staying here, so this cache holds
+//! the metadata of file systems, and whatever is accessed as a raw device.
+//!
+//! The cache lock is never held across disk I/O. Dirty blocks are copied out
+//! to be written back, and their buffer is not evicted until that is done.
+//! The frame allocator only takes clean buffers, see `reclaim`.
+
+use super::fs_id;
+use crate::consts::MAX_CPU_NUM;
+use crate::drivers::BlockDriver;
+use crate::memory::{phys_to_virt, GlobalFrameAlloc};
+use crate::sync::SpinNoIrqLock as Mutex;
+use alloc::{collections::BTreeMap, string::String, sync::Arc, vec::Vec};
+use core::fmt::Write;
+use core::slice;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use rcore_fs::dev::{self, BlockDevice, DevError};
+use rcore_fs::vfs::FileSystem;
+use rcore_memory::memory_set::handler::FrameAllocator;
+use rcore_memory::PAGE_SIZE;
+use spin::RwLock;
+
+const BLOCK_SIZE: usize = 512;
+const BLOCKS_PER_BUFFER: usize = PAGE_SIZE / BLOCK_SIZE;
+/// Default max number of buffers of a cache
+pub const BUFFER_CACHE_MAX_BUFFERS: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteMode {
+    WriteBack,
+    WriteThrough,
+}
+
+struct Buffer {
+    frame: usize,
+    /// Bit i is set if block i of this buffer is loaded
+    valid: u32,
+    /// Bit i is set if block i of this buffer is newer than the device
+    dirty: u32,
+    /// Bit i is set if block i of this buffer is being written back
+    writing: u32,
+    /// LRU timestamp, key in `Inner::lru`
+    last_access: usize,
+}
+
+impl Buffer {
+    fn block(&self, i: usize) -> &'static mut [u8] {
+        unsafe {
+            slice::from_raw_parts_mut(
+                (phys_to_virt(self.frame) + i * BLOCK_SIZE) as *mut u8,
+                BLOCK_SIZE,
+            )
+        }
+    }
+
+    fn evictable(&self) -> bool {
+        self.dirty == 0 && self.writing == 0
+    }
+}
+
+struct Inner {
+    /// First block id -> buffer
+    buffers: BTreeMap<usize, Buffer>,
+    /// LRU timestamp -> first block id
+    lru: BTreeMap<usize, usize>,
+    tick: usize,
+    /// Incremented around device writes no valid buffer covers throughout,
+    /// so that a read of the device overlapping one does not cache what it got
+    device_writes: usize,
+}
+
+/// Dirty blocks copied out of a buffer to be written back
+struct WriteBack {
+    start: usize,
+    /// Bits of the blocks, as in `Buffer::dirty`
+    blocks: u32,
+    data: Vec<u8>,
+}
+
+impl Inner {
+    /// Get the buffer starting at block `start` if present, as most recently used
+    fn touch(&mut self, start: usize) -> Option<&mut Buffer> {
+        self.tick += 1;
+        let tick = self.tick;
+        let buf = self.buffers.get_mut(&start)?;
+        self.lru.remove(&buf.last_access);
+        self.lru.insert(tick, start);
+        buf.last_access = tick;
+        Some(buf)
+    }
+
+    fn insert(&mut self, start: usize, frame: usize) -> &mut Buffer {
+        self.tick += 1;
+        let tick = self.tick;
+        self.lru.insert(tick, start);
+        self.buffers.insert(
+            start,
+            Buffer {
+                frame,
+                valid: 0,
+                dirty: 0,
+                writing: 0,
+                last_access: tick,
+            },
+        );
+        self.buffers.get_mut(&start).unwrap()
+    }
+
+    /// Remove the least recently used clean buffer, and return its frame
+    fn evict_clean(&mut self) -> Option<usize> {
+        let buffers = &self.buffers;
+        let (tick, start) = self
+            .lru
+            .iter()
+            .map(|(&tick, &start)| (tick, start))
+            .find(|(_, start)| buffers[start].evictable())?;
+        self.lru.remove(&tick);
+        Some(self.buffers.remove(&start).unwrap().frame)
+    }
+
+    /// The first buffer with dirty blocks, the least recently used first if `lru`,
+    /// or from block `from` on otherwise
+    fn find_dirty(&self, lru: bool, from: usize) -> Option<usize> {
+        if lru {
+            return self
+                .lru
+                .values()
+                .cloned()
+                .find(|start| self.buffers[start].dirty != 0);
+        }
+        self.buffers
+            .range(from..)
+            .find(|(_, buf)| buf.dirty != 0)
+            .map(|(&start, _)| start)
+    }
+
+    /// Copy out the dirty blocks of the buffer at `start`, which are then being written
+    fn take_dirty(&mut self, start: usize) -> WriteBack {
+        let buf = self.buffers.get_mut(&start).unwrap();
+        let mut data = Vec::with_capacity(buf.dirty.count_ones() as usize * BLOCK_SIZE);
+        for i in (0..BLOCKS_PER_BUFFER).filter(|i| buf.dirty & (1 << i) != 0) {
+            data.extend_from_slice(buf.block(i));
+        }
+        let blocks = buf.dirty;
+        buf.writing |= blocks;
+        buf.dirty = 0;
+        WriteBack {
+            start,
+            blocks,
+            data,
+        }
+    }
+}
+
+pub struct BufferCache {
+    driver: Arc<dyn BlockDriver>,
+    mode: WriteMode,
+    max_buffers: usize,
+    /// The file system on the device, see `set_fs`
+    fs: AtomicUsize,
+    inner: Mutex<Inner>,
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+    writebacks: AtomicUsize,
+}
+
+/// Usage of a buffer cache
+#[derive(Debug, Clone)]
+pub struct BufferCacheStats {
+    pub device: String,
+    pub buffers: usize,
+    pub dirty_blocks: usize,
+    pub hits: usize,
+    pub misses: usize,
+    pub writebacks: usize,
+}
+
+lazy_static! {
+    /// All buffer caches, for statistics, `sync` and memory reclaiming
+    static ref BUFFER_CACHES: RwLock<Vec<Arc<BufferCache>>> = RwLock::new(Vec::new());
+}
+
+/// Depth of `uncached` on each CPU.
+/// Disk I/O is synchronous, so the running task stays on the CPU meanwhile.
+static mut UNCACHED: [usize; MAX_CPU_NUM] = [0; MAX_CPU_NUM];
+
+/// Run `f` with blocks it reads and writes not cached unless they already are,
+/// for file data which the page cache holds
+pub fn uncached<T>(f: impl FnOnce() -> T) -> T {
+    let cpu = crate::arch::cpu::id();
+    unsafe { UNCACHED[cpu] += 1 };
+    let res = f();
+    unsafe { UNCACHED[cpu] -= 1 };
+    res
+}
+
+fn caching() -> bool {
+    unsafe { UNCACHED[crate::arch::cpu::id()] == 0 }
+}
+
+impl BufferCache {
+    /// Create a cache of at most `max_buffers` buffers in front of `driver`
+    pub fn new(driver: Arc<dyn BlockDriver>, mode: WriteMode, max_buffers: usize) -> Arc<Self> {
+        let cache = Arc::new(BufferCache {
+            driver,
+            mode,
+            max_buffers,
+            fs: AtomicUsize::new(0),
+            inner: Mutex::new(Inner {
+                buffers: BTreeMap::new(),
+                lru: BTreeMap::new(),
+                tick: 0,
+                device_writes: 0,
+            }),
+            hits: AtomicUsize::new(0),
+            misses: AtomicUsize::new(0),
+            writebacks: AtomicUsize::new(0),
+        });
+        BUFFER_CACHES.write().push(cache.clone());
+        cache
+    }
+
+    /// Record that `fs` is on the device, for `sync_fs`
+    pub fn set_fs(&self, fs: Arc<dyn FileSystem>) {
+        self.fs.store(fs_id(&fs), Ordering::Relaxed);
+    }
+
+    /// Write back all dirty blocks.
+    /// All are tried, and the first error is returned.
+    pub fn flush(&self) -> dev::Result<()> {
+        let mut res = Ok(());
+        let mut from = 0;
+        loop {
+            let taken = {
+                let mut inner = self.inner.lock();
+                match inner.find_dirty(false, from) {
+                    Some(start) => inner.take_dirty(start),
+                    None => return res,
+                }
+            };
+            from = taken.start + BLOCKS_PER_BUFFER;
+            if self.write_back(taken).is_err() && res.is_ok() {
+                res = Err(DevError);
+            }
+        }
+    }
+
+    /// Evict up to `count` least recently used buffers, return the number evicted.
+    /// Dirty ones are written back first.
+    pub fn evict(&self, count: usize) -> usize {
+        let mut freed = 0;
+        while freed < count {
+            let frame = self.inner.lock().evict_clean();
+            match frame {
+                Some(frame) => GlobalFrameAlloc.dealloc(frame),
+                None if self.clean_one() => continue,
+                None => break,
+            }
+            freed += 1;
+        }
+        freed
+    }
+
+    pub fn stats(&self) -> BufferCacheStats {
+        let inner = self.inner.lock();
+        BufferCacheStats {
+            device: self.driver.get_id(),
+            buffers: inner.buffers.len(),
+            dirty_blocks: inner
+                .buffers
+                .values()
+                .map(|buf| (buf.dirty | buf.writing).count_ones() as usize)
+                .sum(),
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            writebacks: self.writebacks.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Write the blocks taken by `Inner::take_dirty` without the lock,
+    /// then mark those failing dirty again
+    fn write_back(&self, taken: WriteBack) -> dev::Result<()> {
+        let mut failed = 0;
+        let blocks = (0..BLOCKS_PER_BUFFER).filter(|i| taken.blocks & (1 << i) != 0);
+        for (i, data) in blocks.zip(taken.data.chunks(BLOCK_SIZE)) {
+            if self.driver.write_block(taken.start + i, data) {
+                self.writebacks.fetch_add(1, Ordering::Relaxed);
+            } else {
+                error!(
+                    "buffer cache: failed to write back block {:#x}",
+                    taken.start + i
+                );
+                failed |= 1 << i;
+            }
+        }
+        // the buffer is not evicted while being written
+        let mut inner = self.inner.lock();
+        inner.device_writes += 1;
+        let buf = inner.buffers.get_mut(&taken.start).unwrap();
+        buf.writing &= !taken.blocks;
+        // keep them rather than losing data
+        buf.dirty |= failed;
+        match failed {
+            0 => Ok(()),
+            _ => Err(DevError),
+        }
+    }
+
+    /// Write back the least recently used dirty buffer, so that it can be evicted.
+    /// Return false if there is none, or it fails.
+    fn clean_one(&self) -> bool {
+        let taken = {
+            let mut inner = self.inner.lock();
+            match inner.find_dirty(true, 0) {
+                Some(start) => inner.take_dirty(start),
+                None => return false,
+            }
+        };
+        self.write_back(taken).is_ok()
+    }
+
+    /// Run `f` on the buffer starting at block `start` with the lock held,
+    /// creating it if absent. Return `None` if no frame is available.
+    fn with_buffer<T>(&self, start: usize, f: impl FnOnce(&mut Buffer) -> T) -> Option<T> {
+        // allocate without the lock, as the allocator may reclaim buffers
+        let mut frame = None;
+        loop {
+            let mut inner = self.inner.lock();
+            if let Some(buf) = inner.touch(start) {
+                let res = f(buf);
+                drop(inner);
+                if let Some(frame) = frame {
+                    GlobalFrameAlloc.dealloc(frame);
+                }
+                return Some(res);
+            }
+            if let Some(frame) = frame {
+                let evicted = match inner.buffers.len() >= self.max_buffers {
+                    true => inner.evict_clean(),
+                    false => None,
+                };
+                let res = f(inner.insert(start, frame));
+                drop(inner);
+                if let Some(evicted) = evicted {
+                    GlobalFrameAlloc.dealloc(evicted);
+                }
+                return Some(res);
+            }
+            drop(inner);
+            frame = Some(GlobalFrameAlloc.alloc()?);
+        }
+    }
+
+    /// Write dirty buffers back and evict them while there are too many,
+    /// which `with_buffer` can not do without blocking
+    fn shrink(&self) {
+        while self.inner.lock().buffers.len() > self.max_buffers {
+            if self.evict(1) == 0 {
+                break;
+            }
+        }
+    }
+
+    /// Write a block to the device without a buffer
+    fn write_bypass(&self, block_id: usize, buf: &[u8]) -> dev::Result<()> {
+        self.inner.lock().device_writes += 1;
+        let written = self.driver.write_block(block_id, &buf[..BLOCK_SIZE]);
+        self.inner.lock().device_writes += 1;
+        match written {
+            true => Ok(()),
+            false => Err(DevError),
+        }
+    }
+}
+
+impl BlockDevice for BufferCache {
+    const BLOCK_SIZE_LOG2: u8 = 9; // 512
+
+    fn read_at(&self, block_id: usize, buf: &mut [u8]) -> dev::Result<()> {
+        let i = block_id % BLOCKS_PER_BUFFER;
+        let start = block_id - i;
+        let len = buf.len().min(BLOCK_SIZE);
+        let device_writes = {
+            let mut inner = self.inner.lock();
+            if let Some(buffer) = inner.touch(start) {
+                if buffer.valid & (1 << i) != 0 {
+                    buf[..len].copy_from_slice(&buffer.block(i)[..len]);
+                    self.hits.fetch_add(1, Ordering::Relaxed);
+                    return Ok(());
+                }
+            }
+            inner.device_writes
+        };
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let mut block = [0u8; BLOCK_SIZE];
+        if !self.driver.read_block(block_id, &mut block) {
+            return Err(DevError);
+        }
+        buf[..len].copy_from_slice(&block[..len]);
+        if !caching() {
+            return Ok(());
+        }
+        let cache = |buffer: &mut Buffer| {
+            // a write meanwhile is newer
+            if buffer.valid & (1 << i) == 0 {
+                buffer.block(i).copy_from_slice(&block);
+                buffer.valid |= 1 << i;
+            }
+        };
+        // a write to the device meanwhile may have been missed by the read
+        if self.inner.lock().device_writes == device_writes {
+            self.with_buffer(start, cache);
+            self.shrink();
+        }
+        Ok(())
+    }
+
+    fn write_at(&self, block_id: usize, buf: &[u8]) -> dev::Result<()> {
+        if buf.len() < BLOCK_SIZE {
+            return Err(DevError);
+        }
+        let i = block_id % BLOCKS_PER_BUFFER;
+        let start = block_id - i;
+        let mode = self.mode;
+        let update = |buffer: &mut Buffer| {
+            buffer.block(i).copy_from_slice(&buf[..BLOCK_SIZE]);
+            buffer.valid |= 1 << i;
+            match mode {
+                WriteMode::WriteBack => buffer.dirty |= 1 << i,
+                // not evicted until it reaches the device
+                WriteMode::WriteThrough => buffer.writing |= 1 << i,
+            }
+        };
+        let cached = match caching() {
+            true => self.with_buffer(start, update).is_some(),
+            // update a cached copy, but do not make one
+            false => self.inner.lock().touch(start).map(update).is_some(),
+        };
+        if !cached {
+            return self.write_bypass(block_id, buf);
+        }
+        if mode == WriteMode::WriteThrough {
+            let written = self.driver.write_block(block_id, &buf[..BLOCK_SIZE]);
+            let mut inner = self.inner.lock();
+            // a concurrent write of the block may be done first and let it be evicted
+            inner.device_writes += 1;
+            let buffer = inner.buffers.get_mut(&start).unwrap();
+            buffer.writing &= !(1 << i);
+            if !written {
+                // the cached copy may never reach the device, drop it
+                buffer.valid &= !(1 << i);
+                return Err(DevError);
+            }
+            self.writebacks.fetch_add(1, Ordering::Relaxed);
+        }
+        self.shrink();
+        Ok(())
+    }
+
+    fn sync(&self) -> dev::Result<()> {
+        self.flush()
+    }
+}
+
+/// Write back dirty blocks of all buffer caches.
+/// All are tried, and the first error is returned.
+pub fn sync_all() -> dev::Result<()> {
+    let caches = BUFFER_CACHES.read().clone();
+    let mut res = Ok(());
+    for cache in caches.iter() {
+        if cache.flush().is_err() && res.is_ok() {
+            res = Err(DevError);
+        }
+    }
+    res
+}
+
+/// Write back dirty blocks of the buffer caches under `fs`.
+/// All are tried, and the first error is returned.
+pub fn sync_fs(fs: &Arc<dyn FileSystem>) -> dev::Result<()> {
+    let id = fs_id(fs);
+    let caches = BUFFER_CACHES.read().clone();
+    let mut res = Ok(());
+    for cache in caches.iter() {
+        if cache.fs.load(Ordering::Relaxed) == id && cache.flush().is_err() && res.is_ok() {
+            res = Err(DevError);
+        }
+    }
+    res
+}
+
+/// Number of frames used by all buffer caches
+pub fn cached_buffers() -> usize {
+    BUFFER_CACHES
+        .read()
+        .iter()
+        .map(|cache| cache.inner.lock().buffers.len())
+        .sum()
+}
+
+/// Reclaim clean buffers when physical memory is short.
+/// Called from the frame allocator, so it must neither block on any cache lock
+/// nor write back dirty blocks.
+pub fn reclaim(count: usize) -> usize {
+    let caches = match BUFFER_CACHES.try_read() {
+        Some(caches) => caches,
+        None => return 0,
+    };
+    let mut freed = 0;
+    for cache in caches.iter() {
+        if let Some(mut inner) = cache.inner.try_lock() {
+            while freed < count {
+                match inner.evict_clean() {
+                    Some(frame) => GlobalFrameAlloc.dealloc(frame),
+                    None => break,
+                }
+                freed += 1;
+            }
+        }
+    }
+    freed
+}
+
+/// Content of `/proc/buffercache`
+pub fn buffercache_info() -> String {
+    let mut res =
+        String::from("# device            <buffers> <dirty> <hits> <misses> <writebacks>\n");
+    for cache in BUFFER_CACHES.read().iter() {
+        let stats = cache.stats();
+        writeln!(
+            res,
+            "{:<20} {:>9} {:>7} {:>6} {:>8} {:>12}",
+            stats.device,
+            stats.buffers,
+            stats.dirty_blocks,
+            stats.hits,
+            stats.misses,
+            stats.writebacks
+        )
+        .unwrap();
+    }
+    res
+}
@@ -0,0 +1,120 @@
+//! Implement INode for block devices and their partitions
+
+use alloc::sync::Arc;
+use core::any::Any;
+
+use rcore_fs::vfs::*;
+
+use crate::drivers::block::partition::{BlockDevice, BLOCK_SIZE};
+use crate::drivers::BlockDriver;
+
+pub struct BlockINode {
+    driver: Arc<dyn BlockDriver>,
+    rdev: usize,
+}
+
+impl BlockINode {
+    pub fn new(dev: &BlockDevice) -> Self {
+        // like Linux, each disk has 16 minor numbers
+        let major = match &dev.name[..2] {
+            "vd" => 254,
+            "hd" => 3,
+            _ => 8,
+        };
+        BlockINode {
+            driver: dev.driver.clone(),
+            rdev: make_rdev(major, dev.disk * 16 + dev.partition),
+        }
+    }
+
+    fn size(&self) -> usize {
+        self.driver.num_blocks().unwrap_or(0) * BLOCK_SIZE
+    }
+}
+
+impl INode for BlockINode {
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        let mut block = [0u8; BLOCK_SIZE];
+        let mut pos = 0;
+        while pos < buf.len() {
+            let off = offset + pos;
+            if self
+                .driver
+                .num_blocks()
+                .map_or(false, |n| off / BLOCK_SIZE >= n)
+            {
+                break;
+            }
+            if !self.driver.read_block(off / BLOCK_SIZE, &mut block) {
+                if pos == 0 {
+                    return Err(FsError::DeviceError);
+                }
+                break;
+            }
+            let begin = off % BLOCK_SIZE;
+            let len = (BLOCK_SIZE - begin).min(buf.len() - pos);
+            buf[pos..pos + len].copy_from_slice(&block[begin..begin + len]);
+            pos += len;
+        }
+        Ok(pos)
+    }
+
+    fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize> {
+        let mut block = [0u8; BLOCK_SIZE];
+        let mut pos = 0;
+        while pos < buf.len() {
+            let off = offset + pos;
+            let id = off / BLOCK_SIZE;
+            if self.driver.num_blocks().map_or(false, |n| id >= n) {
+                if pos == 0 {
+                    return Err(FsError::NoDeviceSpace);
+                }
+                break;
+            }
+            let begin = off % BLOCK_SIZE;
+            let len = (BLOCK_SIZE - begin).min(buf.len() - pos);
+            // read-modify-write a partial block
+            if len < BLOCK_SIZE && !self.driver.read_block(id, &mut block) {
+                return Err(FsError::DeviceError);
+            }
+            block[begin..begin + len].copy_from_slice(&buf[pos..pos + len]);
+            if !self.driver.write_block(id, &block) {
+                return Err(FsError::DeviceError);
+            }
+            pos += len;
+        }
+        Ok(pos)
+    }
+
+    fn poll(&self) -> Result<PollStatus> {
+        Ok(PollStatus {
+            read: true,
+            write: true,
+            error: false,
+        })
+    }
+
+    fn metadata(&self) -> Result<Metadata> {
+        let size = self.size();
+        Ok(Metadata {
+            dev: 1,
+            inode: 1,
+            size,
+            blk_size: BLOCK_SIZE,
+            blocks: size / BLOCK_SIZE,
+            atime: Timespec { sec: 0, nsec: 0 },
+            mtime: Timespec { sec: 0, nsec: 0 },
+            ctime: Timespec { sec: 0, nsec: 0 },
+            type_: FileType::BlockDevice,
+            mode: 0o660,
+            nlinks: 1,
+            uid: 0,
+            gid: 0,
+            rdev: self.rdev,
+        })
+    }
+
+    fn as_any_ref(&self) -> &dyn Any {
+        self
+    }
+}
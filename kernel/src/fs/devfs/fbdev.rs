@@ -101,8 +101,8 @@ impl INode for Fbdev {
                 return Err(FsError::NoDeviceSpace);
             }
             /*
-            let thread = unsafe { crate::process::current_thread() };
-            thread.vm.lock().push(
+            let thread = crate::process::Thread::current();
+            thread.lock_vm().push(
                 area.start_vaddr,
                 area.end_vaddr,
                 attr,
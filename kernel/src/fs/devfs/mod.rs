@@ -1,10 +1,12 @@
 //! Device file system mounted at /dev
 
+mod block;
 mod fbdev;
 mod random;
 mod shm;
 mod tty;
 
+pub use block::*;
 pub use fbdev::*;
 pub use random::*;
 pub use shm::*;
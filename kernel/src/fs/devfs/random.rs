@@ -1,19 +1,12 @@
 //! Implement INode for RandomINode
 
-use alloc::sync::Arc;
 use core::any::Any;
 
 use rcore_fs::vfs::*;
 
-use crate::sync::SpinNoIrqLock as Mutex;
-
-struct RandomINodeData {
-    seed: u32,
-}
-
+/// Both devices read from the kernel random generator, see `crate::rand`
 #[derive(Clone)]
 pub struct RandomINode {
-    data: Arc<Mutex<RandomINodeData>>,
     secure: bool,
 }
 
@@ -21,26 +14,14 @@ impl RandomINode {
     // urandom -> secure=true
     // random -> secure=false
     pub fn new(secure: bool) -> RandomINode {
-        RandomINode {
-            secure,
-            data: Arc::new(Mutex::new(RandomINodeData { seed: 1 })),
-        }
+        RandomINode { secure }
     }
 }
 
 impl INode for RandomINode {
     fn read_at(&self, _offset: usize, buf: &mut [u8]) -> Result<usize> {
-        if buf.len() > 0 {
-            let mut data = self.data.lock();
-            // from K&R
-            for i in 0..buf.len() {
-                data.seed = data.seed.wrapping_mul(1103515245).wrapping_add(12345);
-                buf[i] = (data.seed / 65536) as u8;
-            }
-            Ok(buf.len())
-        } else {
-            Ok(0)
-        }
+        crate::rand::fill(buf);
+        Ok(buf.len())
     }
 
     fn write_at(&self, _offset: usize, _buf: &[u8]) -> Result<usize> {
@@ -2,6 +2,7 @@ use crate::fs::ioctl::*;
 use crate::process::{process_group, Pgid};
 use crate::signal::{send_signal, Signal};
 use crate::signal::{Siginfo, SI_KERNEL};
+use crate::sync::SpinNoIrqLock;
 use crate::{sync::Event, sync::EventBus, syscall::SysError};
 use alloc::boxed::Box;
 use alloc::collections::VecDeque;
@@ -13,7 +14,10 @@ use core::task::Context;
 use core::task::Poll;
 use rcore_fs::vfs::FsError::NotSupported;
 use rcore_fs::vfs::*;
-use spin::{Mutex, RwLock};
+use spin::RwLock;
+
+/// Size of the input buffer, bytes received when it is full are dropped
+const TTY_BUF_SIZE: usize = 4096;
 
 /// console tty
 // Ref: [https://linux.die.net/man/4/tty]
@@ -21,8 +25,10 @@ use spin::{Mutex, RwLock};
 pub struct TtyINode {
     /// foreground process group
     foreground_pgid: RwLock<Pgid>,
-    buf: Mutex<VecDeque<u8>>,
-    eventbus: Mutex<EventBus>,
+    /// input from serial and keyboard interrupts, so IRQ is disabled while locked.
+    /// Lock `eventbus` first if both are needed.
+    buf: SpinNoIrqLock<VecDeque<u8>>,
+    eventbus: SpinNoIrqLock<EventBus>,
     winsize: RwLock<Winsize>,
     termios: RwLock<Termios>,
 }
@@ -37,41 +43,62 @@ pub fn foreground_pgid() -> Pgid {
 
 impl TtyINode {
     pub fn push(&self, c: u8) {
-        let lflag = LocalModes::from_bits_truncate(self.termios.read().lflag);
-        if lflag.contains(LocalModes::ISIG) && [0o3, 0o34, 0o32, 0o31].contains(&(c as i32)) {
-            use Signal::*;
-            let foregroud_processes = process_group(foreground_pgid());
-            match c as i32 {
-                // INTR
-                0o3 => {
-                    for proc in foregroud_processes {
-                        send_signal(
-                            proc,
-                            -1,
-                            Siginfo {
-                                signo: SIGINT as i32,
-                                errno: 0,
-                                code: SI_KERNEL,
-                                field: Default::default(),
-                            },
-                        );
-                    }
-                }
-                _ => warn!("special char {} is unimplented", c),
+        let termios = *self.termios.read();
+        let lflag = LocalModes::from_bits_truncate(termios.lflag);
+        let signal = match c {
+            _ if !lflag.contains(LocalModes::ISIG) => None,
+            // _POSIX_VDISABLE
+            0 => None,
+            c if c == termios.cc[VINTR] => Some(Signal::SIGINT),
+            c if c == termios.cc[VQUIT] => Some(Signal::SIGQUIT),
+            c if c == termios.cc[VSUSP] => Some(Signal::SIGTSTP),
+            _ => None,
+        };
+        if let Some(signal) = signal {
+            if !lflag.contains(LocalModes::NOFLSH) {
+                let mut eventbus = self.eventbus.lock();
+                self.buf.lock().clear();
+                eventbus.clear(Event::READABLE);
+            }
+            for proc in process_group(foreground_pgid()) {
+                send_signal(
+                    proc,
+                    -1,
+                    Siginfo {
+                        signo: signal as i32,
+                        errno: 0,
+                        code: SI_KERNEL,
+                        field: Default::default(),
+                    },
+                );
             }
         } else {
-            self.buf.lock().push_back(c);
-            self.eventbus.lock().set(Event::READABLE);
+            // both are locked, so a reader can't take the byte and clear READABLE
+            // between the push and the set, leaving it set with nothing to read
+            let mut eventbus = self.eventbus.lock();
+            let mut buf = self.buf.lock();
+            if buf.len() >= TTY_BUF_SIZE {
+                warn!("tty: input buffer full, drop {:#x}", c);
+                return;
+            }
+            buf.push_back(c);
+            drop(buf);
+            eventbus.set(Event::READABLE);
         }
     }
 
-    pub fn pop(&self) -> u8 {
-        let mut buf_lock = self.buf.lock();
-        let c = buf_lock.pop_front().unwrap();
-        if buf_lock.len() == 0 {
-            self.eventbus.lock().clear(Event::READABLE);
+    /// Move buffered input into `data`, return the number of bytes moved
+    pub fn pop(&self, data: &mut [u8]) -> usize {
+        let mut eventbus = self.eventbus.lock();
+        let mut buf = self.buf.lock();
+        let len = data.len().min(buf.len());
+        for (dst, src) in data.iter_mut().zip(buf.drain(..len)) {
+            *dst = src;
+        }
+        if buf.is_empty() {
+            eventbus.clear(Event::READABLE);
         }
-        return c;
+        len
     }
 
     pub fn can_read(&self) -> bool {
@@ -82,11 +109,9 @@ impl TtyINode {
 impl INode for TtyINode {
     /// Read bytes at `offset` into `buf`, return the number of bytes read.
     fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
-        if self.can_read() {
-            buf[0] = self.pop() as u8;
-            Ok(1)
-        } else {
-            Err(FsError::Again)
+        match self.pop(buf) {
+            0 if !buf.is_empty() => Err(FsError::Again),
+            len => Ok(len),
         }
     }
 
@@ -120,11 +145,14 @@ impl INode for TtyINode {
             type Output = Result<PollStatus>;
 
             fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+                // check with eventbus locked, so that input is not missed
+                // between the check and the subscription
+                let mut eventbus = self.tty.eventbus.lock();
                 if self.tty.can_read() {
                     return Poll::Ready(self.tty.poll());
                 }
                 let waker = cx.waker().clone();
-                self.tty.eventbus.lock().subscribe(Box::new({
+                eventbus.subscribe(Box::new({
                     move |_| {
                         waker.wake_by_ref();
                         true
@@ -152,6 +180,12 @@ impl INode for TtyINode {
                 info!("tty: set foreground process group to {}", fpgid);
                 Ok(0)
             }
+            TIOCSTI => {
+                // input as if it was received, `sys_ioctl` has copied it from the user
+                let c = unsafe { *(data as *const u8) };
+                self.push(c);
+                Ok(0)
+            }
             TIOCGWINSZ => {
                 let winsize = data as *mut Winsize;
                 unsafe {
@@ -14,6 +14,10 @@ const F_LINUX_SPECIFIC_BASE: usize = 1024;
 
 pub const FD_CLOEXEC: usize = 1;
 pub const F_DUPFD_CLOEXEC: usize = F_LINUX_SPECIFIC_BASE + 6;
+pub const F_SETPIPE_SZ: usize = F_LINUX_SPECIFIC_BASE + 7;
+pub const F_GETPIPE_SZ: usize = F_LINUX_SPECIFIC_BASE + 8;
+pub const F_ADD_SEALS: usize = F_LINUX_SPECIFIC_BASE + 9;
+pub const F_GET_SEALS: usize = F_LINUX_SPECIFIC_BASE + 10;
 
 pub const O_NONBLOCK: usize = 0o4000;
 pub const O_APPEND: usize = 0o2000;
@@ -1,17 +1,27 @@
 //! File handle for process
 
 use crate::memory::GlobalFrameAlloc;
-use crate::process::{current_thread, INodeForMap};
-use crate::syscall::{MmapProt, SysResult, TimeSpec};
-use alloc::{string::String, sync::Arc};
+use crate::process::{INodeForMap, Thread};
+use crate::syscall::{MmapFlags, MmapProt, SysError, SysResult, TimeSpec};
+use alloc::{
+    collections::BTreeMap,
+    string::String,
+    sync::{Arc, Weak},
+};
 use core::fmt;
 
-use rcore_fs::vfs::FsError::{Interrupted, NotSupported};
+use rcore_fs::vfs::FsError::Interrupted;
 use rcore_fs::vfs::{FileType, FsError, INode, MMapArea, Metadata, PollStatus, Result};
 use rcore_memory::memory_set::handler::File;
 
+use crate::fs::buffer_cache;
+use crate::fs::devfs::TtyINode;
 use crate::fs::fcntl::{O_APPEND, O_NONBLOCK};
-use crate::sync::SpinLock as Mutex;
+use crate::fs::inotify::{self, Entry, Inotify, IN_CLOSE_WRITE, IN_MODIFY};
+use crate::fs::memfd::MemFd;
+use crate::fs::page_cache::{self, cacheable, Advice, CachedFile, InodeKey, Readahead};
+use crate::fs::pipe::Pipe;
+use crate::sync::{Semaphore, SpinLock as Mutex};
 use crate::syscall::SysError::{EAGAIN, ESPIPE};
 use bitflags::_core::cell::Cell;
 use spin::RwLock;
@@ -22,28 +32,86 @@ enum Flock {
     Exclusive = 2,
 }
 
+/// An open file description, shared by the fds `dup`-ed from it
+/// and inherited across `fork`
 struct OpenFileDescription {
+    inode: Arc<dyn INode>,
+    path: String,
+    pipe: bool, // specify if this is pipe, socket, or FIFO
+    status: RwLock<FileStatus>,
+}
+
+impl Drop for OpenFileDescription {
+    fn drop(&mut self) {
+        // the last fd of it is closed
+        let status = self.status.read();
+        if status.options.write {
+            inotify::notify_file(&self.inode, IN_CLOSE_WRITE, status.entry.as_ref());
+        }
+    }
+}
+
+/// Mutable part of an open file description
+struct FileStatus {
     offset: u64,
     options: OpenOptions,
     flock: Flock,
+    readahead: Readahead,
+    /// The directory entry it was opened by, for inotify
+    entry: Option<Entry>,
+}
+
+lazy_static! {
+    /// Locks serializing writes to each regular file, so that an append never
+    /// interleaves with another write to it. Writers sleep on them, as a write
+    /// does disk I/O and may fault on the user buffer. Only files being written
+    /// have one.
+    static ref WRITE_LOCKS: Mutex<BTreeMap<InodeKey, Weak<Semaphore>>> =
+        Mutex::new(BTreeMap::new());
 }
 
-impl OpenFileDescription {
-    fn create(options: OpenOptions) -> Arc<RwLock<Self>> {
-        Arc::new(RwLock::new(OpenFileDescription {
-            offset: 0,
-            options,
-            flock: Flock::None,
-        }))
+/// The write lock of an inode held, see `lock_write`
+struct WriteGuard {
+    key: InodeKey,
+    lock: Arc<Semaphore>,
+}
+
+impl Drop for WriteGuard {
+    fn drop(&mut self) {
+        self.lock.release();
+        let mut locks = WRITE_LOCKS.lock();
+        // no other writer has it, and none can find it meanwhile
+        if Arc::strong_count(&self.lock) == 1 {
+            locks.remove(&self.key);
+        }
     }
 }
 
+/// Take the write lock of `inode` if it is a regular file, unlike pipes and devices
+async fn lock_write(inode: &Arc<dyn INode>) -> Option<WriteGuard> {
+    if !cacheable(inode) {
+        return None;
+    }
+    let key = page_cache::key_of(inode, &inode.metadata().ok()?);
+    let lock = {
+        let mut locks = WRITE_LOCKS.lock();
+        match locks.get(&key).and_then(Weak::upgrade) {
+            Some(lock) => lock,
+            None => {
+                let lock = Arc::new(Semaphore::new(1));
+                locks.insert(key, Arc::downgrade(&lock));
+                lock
+            }
+        }
+    };
+    // it is never removed
+    lock.acquire().await.ok()?;
+    Some(WriteGuard { key, lock })
+}
+
 #[derive(Clone)]
 pub struct FileHandle {
-    inode: Arc<dyn INode>,
-    description: Arc<RwLock<OpenFileDescription>>,
-    pub path: String,
-    pub pipe: bool, // specify if this is pipe, socket, or FIFO
+    description: Arc<OpenFileDescription>,
     pub fd_cloexec: bool,
 }
 
@@ -72,10 +140,18 @@ impl FileHandle {
         fd_cloexec: bool,
     ) -> Self {
         return FileHandle {
-            inode,
-            description: OpenFileDescription::create(options),
-            path,
-            pipe,
+            description: Arc::new(OpenFileDescription {
+                inode,
+                path,
+                pipe,
+                status: RwLock::new(FileStatus {
+                    offset: 0,
+                    options,
+                    flock: Flock::None,
+                    readahead: Readahead::new(),
+                    entry: None,
+                }),
+            }),
             fd_cloexec,
         };
     }
@@ -83,42 +159,81 @@ impl FileHandle {
     // do almost as default clone does, but with fd_cloexec specified
     pub fn dup(&self, fd_cloexec: bool) -> Self {
         FileHandle {
-            inode: self.inode.clone(),
             description: self.description.clone(),
-            path: self.path.clone(),
-            pipe: self.pipe,
             fd_cloexec, // this field do not share
         }
     }
 
+    pub fn path(&self) -> &str {
+        &self.description.path
+    }
+
+    /// Record the directory entry the file is opened by,
+    /// so that its watches also see the events on the file
+    pub fn set_entry(&self, entry: Entry) {
+        self.description.status.write().entry = Some(entry);
+    }
+
+    /// Report event `mask` on the file to inotify
+    fn notify(&self, mask: u32) {
+        let status = self.description.status.read();
+        inotify::notify_file(&self.description.inode, mask, status.entry.as_ref());
+    }
+
+    /// Whether this is pipe, socket, or FIFO
+    pub fn is_pipe(&self) -> bool {
+        self.description.pipe
+    }
+
+    pub fn options(&self) -> OpenOptions {
+        self.description.status.read().options
+    }
+
     pub fn set_options(&self, arg: usize) {
-        let options = &mut self.description.write().options;
+        let options = &mut self.description.status.write().options;
         options.nonblock = (arg & O_NONBLOCK) != 0;
-        // TODO: handle append
-        // options.append = (arg & O_APPEND) != 0;
+        options.append = (arg & O_APPEND) != 0;
+    }
+
+    /// Set or clear O_NONBLOCK, keeping the other status flags
+    pub fn set_nonblocking(&self, nonblock: bool) {
+        self.description.status.write().options.nonblock = nonblock;
     }
 
     // pub fn get_options(&self) -> usize {
-    // let options = self.description.read().options;
+    // let options = self.description.status.read().options;
     // let mut ret = 0 as usize;
     // }
 
     pub async fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
-        let offset = self.description.read().offset as usize;
+        let offset = self.description.status.read().offset as usize;
         let len = self.read_at(offset, buf).await?;
-        self.description.write().offset += len as u64;
+        self.description.status.write().offset += len as u64;
         Ok(len)
     }
 
     pub async fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
-        // let options = &self.description.read().options;
-        if !self.description.read().options.read {
+        // let options = &self.description.status.read().options;
+        if !self.description.status.read().options.read {
             return Err(FsError::InvalidParam); // TODO: => EBADF
         }
-        if !self.description.read().options.nonblock {
+        if cacheable(&self.description.inode) {
+            let len = page_cache::read_at(&self.description.inode, offset, buf)?;
+            let readahead = self
+                .description
+                .status
+                .write()
+                .readahead
+                .on_read(offset, len);
+            if let Some((start, count)) = readahead {
+                page_cache::readahead_async(self.description.inode.clone(), start, count);
+            }
+            return Ok(len);
+        }
+        if !self.description.status.read().options.nonblock {
             // block
             loop {
-                match self.inode.read_at(offset, buf) {
+                match self.description.inode.read_at(offset, buf) {
                     Ok(read_len) => {
                         return Ok(read_len);
                     }
@@ -131,112 +246,230 @@ impl FileHandle {
                 }
             }
         } else {
-            let len = self.inode.read_at(offset, buf)?;
+            let len = self.description.inode.read_at(offset, buf)?;
             Ok(len)
         }
     }
 
-    pub fn write(&mut self, buf: &[u8]) -> Result<usize> {
-        let description = self.description.read();
-        let offset = match description.options.append {
-            true => self.inode.metadata()?.size as u64,
-            false => description.offset,
-        } as usize;
-        drop(description);
-        let len = self.write_at(offset, buf)?;
-        self.description.write().offset += len as u64;
+    pub async fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let _guard = lock_write(&self.description.inode).await;
+        if self.description.status.read().options.append {
+            // the end of file can not move until the write is done
+            let offset = self.description.inode.metadata()?.size;
+            let len = self.write_unlocked(offset, buf)?;
+            self.description.status.write().offset = (offset + len) as u64;
+            return Ok(len);
+        }
+        let offset = self.description.status.read().offset as usize;
+        let len = self.write_unlocked(offset, buf)?;
+        self.description.status.write().offset += len as u64;
         Ok(len)
     }
 
-    pub fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize> {
-        if !self.description.read().options.write {
+    /// Write as `write`, but wait for room in a full pipe unless nonblocking.
+    /// A blocking write to a pipe returns once all of `buf` is written.
+    pub async fn async_write(&mut self, buf: &[u8]) -> Result<usize> {
+        let mut done = 0;
+        loop {
+            match self.write(&buf[done..]).await {
+                Ok(len) => done += len,
+                Err(FsError::Again) if done > 0 => {}
+                Err(err) => return Err(err),
+            }
+            if done == buf.len() || !self.is_pipe() || self.options().nonblock {
+                return Ok(done);
+            }
+            self.async_poll().await?;
+        }
+    }
+
+    pub async fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize> {
+        let _guard = lock_write(&self.description.inode).await;
+        self.write_unlocked(offset, buf)
+    }
+
+    /// Write at `offset`, with the write lock of the inode held by the caller
+    fn write_unlocked(&self, offset: usize, buf: &[u8]) -> Result<usize> {
+        if !self.description.status.read().options.write {
             return Err(FsError::InvalidParam); // TODO: => EBADF
         }
-        let len = self.inode.write_at(offset, buf)?;
-        TimeSpec::update(&self.inode);
+        if let Some(memfd) = self.memfd() {
+            memfd.check_write(offset, buf.len())?;
+        }
+        let len = if cacheable(&self.description.inode) {
+            page_cache::write_at(&self.description.inode, offset, buf)?
+        } else {
+            self.description.inode.write_at(offset, buf)?
+        };
+        TimeSpec::update(&self.description.inode);
+        self.notify(IN_MODIFY);
         Ok(len)
     }
 
     pub fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
-        let mut description = self.description.write();
+        let mut description = self.description.status.write();
         description.offset = match pos {
             SeekFrom::Start(offset) => offset,
-            SeekFrom::End(offset) => (self.inode.metadata()?.size as i64 + offset) as u64,
+            SeekFrom::End(offset) => {
+                (self.description.inode.metadata()?.size as i64 + offset) as u64
+            }
             SeekFrom::Current(offset) => (description.offset as i64 + offset) as u64,
         };
         Ok(description.offset)
     }
 
     pub fn set_len(&mut self, len: u64) -> Result<()> {
-        if !self.description.read().options.write {
+        if !self.description.status.read().options.write {
             return Err(FsError::InvalidParam); // TODO: => EBADF
         }
-        self.inode.resize(len as usize)?;
+        if let Some(memfd) = self.memfd() {
+            memfd.check_resize(len as usize)?;
+        }
+        self.description.inode.resize(len as usize)?;
+        page_cache::truncate(&self.description.inode, len as usize);
+        self.notify(IN_MODIFY);
+        Ok(())
+    }
+
+    /// Write zeros to `[offset, offset + len)`, growing the file if needed
+    pub async fn zero_range(&self, offset: usize, len: usize) -> Result<()> {
+        let zeros = [0u8; 4096];
+        let end = offset + len;
+        let mut pos = offset;
+        while pos < end {
+            let chunk = (end - pos).min(zeros.len());
+            match self.write_at(pos, &zeros[..chunk]).await? {
+                0 => return Err(FsError::NoDeviceSpace),
+                n => pos += n,
+            }
+        }
         Ok(())
     }
 
     pub fn sync_all(&mut self) -> Result<()> {
-        self.inode.sync_all()
+        self.sync_cache()?;
+        self.description.inode.sync_all()?;
+        buffer_cache::sync_all()?;
+        Ok(())
     }
 
     pub fn sync_data(&mut self) -> Result<()> {
-        self.inode.sync_data()
+        self.sync_cache()?;
+        self.description.inode.sync_data()?;
+        buffer_cache::sync_all()?;
+        Ok(())
+    }
+
+    /// Write back dirty pages of this file in the page cache
+    pub fn sync_cache(&self) -> Result<()> {
+        if cacheable(&self.description.inode) {
+            page_cache::sync(&self.description.inode)?;
+        }
+        Ok(())
+    }
+
+    /// Set the access pattern advised by `posix_fadvise`
+    pub fn set_advice(&self, advice: Advice) {
+        self.description.status.write().readahead.advice = advice;
     }
 
     pub fn metadata(&self) -> Result<Metadata> {
-        self.inode.metadata()
+        self.description.inode.metadata()
     }
 
     pub fn lookup_follow(&self, path: &str, max_follow: usize) -> Result<Arc<dyn INode>> {
-        self.inode.lookup_follow(path, max_follow)
+        self.description.inode.lookup_follow(path, max_follow)
     }
 
     pub fn read_entry(&mut self) -> Result<String> {
-        let mut description = self.description.write();
+        let mut description = self.description.status.write();
         if !description.options.read {
             return Err(FsError::InvalidParam); // TODO: => EBADF
         }
         let mut offset = &mut description.offset;
-        let name = self.inode.get_entry(*offset as usize)?;
+        let name = self.description.inode.get_entry(*offset as usize)?;
         *offset += 1;
         Ok(name)
     }
 
     pub fn read_entry_with_metadata(&mut self) -> Result<(Metadata, String)> {
-        let mut description = self.description.write();
+        let mut description = self.description.status.write();
         if !description.options.read {
             return Err(FsError::InvalidParam); // TODO: => EBADF
         }
         let mut offset = &mut description.offset;
-        let ret = self.inode.get_entry_with_metadata(*offset as usize)?;
+        let ret = self
+            .description
+            .inode
+            .get_entry_with_metadata(*offset as usize)?;
         *offset += 1;
         Ok(ret)
     }
 
     pub fn poll(&self) -> Result<PollStatus> {
-        self.inode.poll()
+        self.description.inode.poll()
     }
 
     pub async fn async_poll(&self) -> Result<PollStatus> {
-        self.inode.async_poll().await
+        self.description.inode.async_poll().await
     }
 
     pub fn io_control(&self, cmd: u32, arg: usize) -> Result<usize> {
-        self.inode.io_control(cmd, arg)
+        self.description.inode.io_control(cmd, arg)
     }
 
-    pub fn mmap(&mut self, area: MMapArea) -> Result<()> {
-        info!("mmap file path is {}", self.path);
-        match self.inode.metadata()?.type_ {
+    /// Map the file as `area` asks, in the memory set of the current thread.
+    /// The file must be open for reading, and for writing too if a shared mapping is writable.
+    pub fn mmap(&mut self, area: MMapArea) -> core::result::Result<(), SysError> {
+        info!("mmap file path is {}", self.description.path);
+        match self.description.inode.metadata()?.type_ {
             FileType::File => {
                 let prot = MmapProt::from_bits_truncate(area.prot);
-                let thread = current_thread().unwrap();
-                thread.vm.lock().push(
+                let flags = MmapFlags::from_bits_truncate(area.flags);
+                let options = self.options();
+                if !options.read {
+                    return Err(SysError::EACCES);
+                }
+                let thread = Thread::current();
+                if flags.contains(MmapFlags::SHARED) {
+                    if prot.contains(MmapProt::WRITE) && !options.write {
+                        return Err(SysError::EACCES);
+                    }
+                    let mut may_write = options.write;
+                    let mut writable_map = None;
+                    if let (Some(memfd), true) = (self.memfd(), may_write) {
+                        writable_map = memfd.map_writable();
+                        // a memfd sealed against writes can only be mapped read-only
+                        if writable_map.is_none() {
+                            if prot.contains(MmapProt::WRITE) {
+                                return Err(SysError::EBUSY);
+                            }
+                            may_write = false;
+                        }
+                    }
+                    // map frames of the page cache directly, so that
+                    // writes are visible to read/write and other mappings
+                    thread.lock_vm().push(
+                        area.start_vaddr,
+                        area.end_vaddr,
+                        prot.to_attr(),
+                        CachedFile {
+                            inode: self.description.inode.clone(),
+                            mem_start: area.start_vaddr,
+                            file_start: area.offset,
+                            may_write,
+                            writable_map,
+                        },
+                        "mmap_file_shared",
+                    );
+                    return Ok(());
+                }
+                thread.lock_vm().push(
                     area.start_vaddr,
                     area.end_vaddr,
                     prot.to_attr(),
                     File {
-                        file: INodeForMap(self.inode.clone()),
+                        file: INodeForMap(self.description.inode.clone()),
                         mem_start: area.start_vaddr,
                         file_start: area.offset,
                         file_end: area.offset + area.end_vaddr - area.start_vaddr,
@@ -246,24 +479,47 @@ impl FileHandle {
                 );
                 Ok(())
             }
-            FileType::CharDevice => self.inode.mmap(area),
-            _ => Err(FsError::NotSupported),
+            FileType::CharDevice => Ok(self.description.inode.mmap(area)?),
+            _ => Err(SysError::ENOSYS),
         }
     }
 
     pub fn inode(&self) -> Arc<dyn INode> {
-        self.inode.clone()
+        self.description.inode.clone()
+    }
+
+    /// The memfd of this file, if it is one
+    pub fn memfd(&self) -> Option<&MemFd> {
+        self.description.inode.as_any_ref().downcast_ref::<MemFd>()
+    }
+
+    /// The pipe end of this file, if it is one
+    pub fn pipe(&self) -> Option<&Pipe> {
+        self.description.inode.as_any_ref().downcast_ref::<Pipe>()
+    }
+
+    /// Whether this is a terminal, which has no position unlike other character devices
+    pub fn is_terminal(&self) -> bool {
+        self.description.inode.as_any_ref().is::<TtyINode>()
+    }
+
+    /// The inotify instance of this file, if it is one
+    pub fn inotify(&self) -> Option<&Inotify> {
+        self.description
+            .inode
+            .as_any_ref()
+            .downcast_ref::<Inotify>()
     }
 }
 
 impl fmt::Debug for FileHandle {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let description = self.description.read();
+        let description = self.description.status.read();
         return f
             .debug_struct("FileHandle")
             .field("offset", &description.offset)
             .field("options", &description.options)
-            .field("path", &self.path)
+            .field("path", &self.description.path)
             .finish();
     }
 }
@@ -3,9 +3,10 @@ use core::fmt;
 use super::ioctl::*;
 use super::FileHandle;
 use crate::fs::epoll::EpollInstance;
+use crate::fs::io_uring::IoUring;
 use crate::net::Socket;
 use crate::syscall::{SysError, SysResult};
-use alloc::boxed::Box;
+use alloc::{boxed::Box, sync::Arc};
 use rcore_fs::vfs::{MMapArea, PollStatus};
 
 // TODO: merge FileLike to FileHandle ?
@@ -14,6 +15,7 @@ pub enum FileLike {
     File(FileHandle),
     Socket(Box<dyn Socket>),
     EpollInstance(EpollInstance),
+    IoUring(Arc<IoUring>),
 }
 
 impl FileLike {
@@ -23,6 +25,7 @@ impl FileLike {
             File(file) => File(file.dup(fd_cloexec)),
             Socket(s) => Socket(s.clone()),
             EpollInstance(e) => EpollInstance(e.clone()),
+            IoUring(ring) => IoUring(ring.clone()),
         }
     }
 
@@ -30,17 +33,23 @@ impl FileLike {
         let len = match self {
             FileLike::File(file) => file.read(buf).await?,
             FileLike::Socket(socket) => socket.read(buf).0?,
-            FileLike::EpollInstance(_) => {
+            FileLike::EpollInstance(_) | FileLike::IoUring(_) => {
                 return Err(SysError::ENOSYS);
             }
         };
         Ok(len)
     }
-    pub fn write(&mut self, buf: &[u8]) -> SysResult {
+    /// Write `buf`, and a blocking write to a full pipe waits for room
+    pub async fn async_write(&mut self, buf: &[u8]) -> SysResult {
         let len = match self {
-            FileLike::File(file) => file.write(buf)?,
+            FileLike::File(file) => match file.async_write(buf).await {
+                Err(_) if file.pipe().map_or(false, |pipe| pipe.room().is_none()) => {
+                    return Err(SysError::EPIPE);
+                }
+                result => result?,
+            },
             FileLike::Socket(socket) => socket.write(buf, None)?,
-            FileLike::EpollInstance(_) => {
+            FileLike::EpollInstance(_) | FileLike::IoUring(_) => {
                 return Err(SysError::ENOSYS);
             }
         };
@@ -50,7 +59,7 @@ impl FileLike {
         match self {
             FileLike::File(file) => file.io_control(request as u32, arg1).map_err(Into::into),
             FileLike::Socket(socket) => socket.ioctl(request, arg1, arg2, arg3),
-            FileLike::EpollInstance(_) => {
+            FileLike::EpollInstance(_) | FileLike::IoUring(_) => {
                 return Err(SysError::ENOSYS);
             }
         }
@@ -58,6 +67,7 @@ impl FileLike {
     pub fn mmap(&mut self, area: MMapArea) -> SysResult {
         match self {
             FileLike::File(file) => file.mmap(area)?,
+            FileLike::IoUring(ring) => ring.mmap(area)?,
             _ => return Err(SysError::ENOSYS),
         };
         Ok(0)
@@ -69,6 +79,7 @@ impl FileLike {
                 let (read, write, error) = socket.poll();
                 PollStatus { read, write, error }
             }
+            FileLike::IoUring(ring) => ring.poll(),
             FileLike::EpollInstance(_) => {
                 return Err(SysError::ENOSYS);
             }
@@ -82,6 +93,7 @@ impl FileLike {
                 let (read, write, error) = socket.poll();
                 PollStatus { read, write, error }
             }
+            FileLike::IoUring(ring) => ring.async_poll().await,
             FileLike::EpollInstance(_) => {
                 return Err(SysError::ENOSYS);
             }
@@ -96,6 +108,7 @@ impl fmt::Debug for FileLike {
             FileLike::File(file) => write!(f, "File({:?})", file),
             FileLike::Socket(socket) => write!(f, "Socket({:?})", socket),
             FileLike::EpollInstance(_) => write!(f, "EpollInstance()"),
+            FileLike::IoUring(_) => write!(f, "IoUring()"),
         }
     }
 }
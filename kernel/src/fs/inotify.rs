@@ -0,0 +1,397 @@
+//! File system events of `inotify_init1`
+//!
+//! An inotify instance is a file whose reads return `struct inotify_event` records.
+//! Watches are kept here by the device and inode number of the watched file,
+//! and the syscalls changing a directory or a file report events to them.
+//! As an inode does not know its parent, an open file remembers the directory
+//! entry it was opened by, see `Entry`, and events on it are also reported
+//! to the watches on that directory, with the name.
+//!
+//! The watches on a file are removed when its last link is, as its inode number
+//! may then be given to another file.
+
+use crate::sync::{Event, EventBus, SpinNoIrqLock as Mutex};
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::any::Any;
+use core::future::Future;
+use core::mem::size_of;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicU32, Ordering};
+use core::task::{Context, Poll};
+use rcore_fs::vfs::*;
+
+pub const IN_MODIFY: u32 = 0x2;
+pub const IN_CLOSE_WRITE: u32 = 0x8;
+pub const IN_MOVED_FROM: u32 = 0x40;
+pub const IN_MOVED_TO: u32 = 0x80;
+pub const IN_CREATE: u32 = 0x100;
+pub const IN_DELETE: u32 = 0x200;
+pub const IN_DELETE_SELF: u32 = 0x400;
+pub const IN_Q_OVERFLOW: u32 = 0x4000;
+pub const IN_IGNORED: u32 = 0x8000;
+pub const IN_ONLYDIR: u32 = 0x0100_0000;
+pub const IN_DONT_FOLLOW: u32 = 0x0200_0000;
+pub const IN_MASK_ADD: u32 = 0x2000_0000;
+pub const IN_ISDIR: u32 = 0x4000_0000;
+
+/// Events a watch can ask for
+pub const IN_ALL_EVENTS: u32 = IN_MODIFY
+    | IN_CLOSE_WRITE
+    | IN_MOVED_FROM
+    | IN_MOVED_TO
+    | IN_CREATE
+    | IN_DELETE
+    | IN_DELETE_SELF;
+
+/// Most events queued on an instance, `max_queued_events` of Linux
+const MAX_QUEUED_EVENTS: usize = 16384;
+
+/// Last cookie relating the two events of a rename
+static COOKIE: AtomicU32 = AtomicU32::new(0);
+
+/// Device and inode number of a watched file
+type WatchKey = (usize, usize);
+
+/// `struct inotify_event` of Linux, followed by `len` bytes of the name
+#[repr(C)]
+struct InotifyEvent {
+    wd: i32,
+    mask: u32,
+    cookie: u32,
+    len: u32,
+}
+
+struct Watch {
+    queue: Weak<Mutex<EventQueue>>,
+    wd: i32,
+    mask: u32,
+}
+
+lazy_static! {
+    /// Watches of all inotify instances
+    static ref WATCHES: Mutex<BTreeMap<WatchKey, Vec<Watch>>> = Mutex::new(BTreeMap::new());
+}
+
+struct EventQueue {
+    /// Encoded events, each with its name
+    events: VecDeque<Vec<u8>>,
+    eventbus: EventBus,
+    /// Watched files by watch descriptor
+    watches: BTreeMap<i32, WatchKey>,
+    next_wd: i32,
+}
+
+impl EventQueue {
+    fn push(&mut self, wd: i32, mask: u32, cookie: u32, name: Option<&str>) {
+        if self.events.len() >= MAX_QUEUED_EVENTS {
+            let overflowed = self
+                .events
+                .back()
+                .map_or(false, |event| event_mask(event) == IN_Q_OVERFLOW);
+            if !overflowed {
+                self.events.push_back(encode(-1, IN_Q_OVERFLOW, 0, None));
+            }
+            return;
+        }
+        self.events.push_back(encode(wd, mask, cookie, name));
+        self.eventbus.set(Event::READABLE);
+    }
+}
+
+/// Encode an event, with the name padded by NULs as Linux does
+fn encode(wd: i32, mask: u32, cookie: u32, name: Option<&str>) -> Vec<u8> {
+    let header = size_of::<InotifyEvent>();
+    let len = match name {
+        // at least one NUL
+        Some(name) => (name.len() + header) / header * header,
+        None => 0,
+    };
+    let event = InotifyEvent {
+        wd,
+        mask,
+        cookie,
+        len: len as u32,
+    };
+    let mut buf = vec![0u8; header + len];
+    let bytes = unsafe { core::slice::from_raw_parts(&event as *const _ as *const u8, header) };
+    buf[..header].copy_from_slice(bytes);
+    if let Some(name) = name {
+        buf[header..header + name.len()].copy_from_slice(name.as_bytes());
+    }
+    buf
+}
+
+fn event_mask(event: &[u8]) -> u32 {
+    let event = unsafe { &*(event.as_ptr() as *const InotifyEvent) };
+    event.mask
+}
+
+pub struct Inotify {
+    queue: Arc<Mutex<EventQueue>>,
+}
+
+impl Inotify {
+    pub fn new() -> Self {
+        Inotify {
+            queue: Arc::new(Mutex::new(EventQueue {
+                events: VecDeque::new(),
+                eventbus: EventBus::default(),
+                watches: BTreeMap::new(),
+                next_wd: 1,
+            })),
+        }
+    }
+
+    /// Watch `inode` for events in `mask`, returning the watch descriptor.
+    /// A file is watched once by an instance, so watching it again changes the mask.
+    pub fn add_watch(&self, inode: &Arc<dyn INode>, mask: u32) -> Result<i32> {
+        let metadata = inode.metadata()?;
+        if mask & IN_ONLYDIR != 0 && metadata.type_ != FileType::Dir {
+            return Err(FsError::NotDir);
+        }
+        let key = (metadata.dev, metadata.inode);
+        let events = mask & IN_ALL_EVENTS;
+        let mut watches = WATCHES.lock();
+        let list = watches.entry(key).or_insert_with(Vec::new);
+        let queue = Arc::downgrade(&self.queue);
+        if let Some(watch) = list.iter_mut().find(|watch| watch.queue.ptr_eq(&queue)) {
+            watch.mask = match mask & IN_MASK_ADD {
+                0 => events,
+                _ => watch.mask | events,
+            };
+            return Ok(watch.wd);
+        }
+        let mut inner = self.queue.lock();
+        let wd = inner.next_wd;
+        inner.next_wd += 1;
+        inner.watches.insert(wd, key);
+        list.push(Watch {
+            queue,
+            wd,
+            mask: events,
+        });
+        Ok(wd)
+    }
+
+    /// Remove the watch `wd`, queueing `IN_IGNORED` for it
+    pub fn rm_watch(&self, wd: i32) -> Result<()> {
+        let key = self
+            .queue
+            .lock()
+            .watches
+            .remove(&wd)
+            .ok_or(FsError::InvalidParam)?;
+        remove_watches(key, &Arc::downgrade(&self.queue));
+        self.queue.lock().push(wd, IN_IGNORED, 0, None);
+        Ok(())
+    }
+}
+
+/// Remove the watch of `queue` on `key`
+fn remove_watches(key: WatchKey, queue: &Weak<Mutex<EventQueue>>) {
+    let mut watches = WATCHES.lock();
+    if let Some(list) = watches.get_mut(&key) {
+        list.retain(|watch| !watch.queue.ptr_eq(queue));
+        if list.is_empty() {
+            watches.remove(&key);
+        }
+    }
+}
+
+impl Drop for Inotify {
+    fn drop(&mut self) {
+        let keys: Vec<WatchKey> = self.queue.lock().watches.values().cloned().collect();
+        let queue = Arc::downgrade(&self.queue);
+        for key in keys {
+            remove_watches(key, &queue);
+        }
+    }
+}
+
+/// The entry of a file in its directory, by which it was opened
+pub struct Entry {
+    dir: WatchKey,
+    name: String,
+}
+
+impl Entry {
+    pub fn new(dir: &Arc<dyn INode>, name: &str) -> Option<Self> {
+        let metadata = dir.metadata().ok()?;
+        Some(Entry {
+            dir: (metadata.dev, metadata.inode),
+            name: String::from(name),
+        })
+    }
+}
+
+/// Report event `mask` on `inode` to its watches,
+/// with the `name` of the entry if `inode` is the directory containing it
+pub fn notify(inode: &Arc<dyn INode>, mask: u32, name: Option<&str>) {
+    notify_cookie(inode, mask, 0, name);
+}
+
+/// Report event `mask` on a file to its watches,
+/// and to those on the directory of `entry` with its name
+pub fn notify_file(inode: &Arc<dyn INode>, mask: u32, entry: Option<&Entry>) {
+    notify(inode, mask, None);
+    if let Some(entry) = entry {
+        notify_key(entry.dir, mask, 0, Some(&entry.name));
+    }
+}
+
+fn notify_cookie(inode: &Arc<dyn INode>, mask: u32, cookie: u32, name: Option<&str>) {
+    if WATCHES.lock().is_empty() {
+        return;
+    }
+    if let Ok(metadata) = inode.metadata() {
+        notify_key((metadata.dev, metadata.inode), mask, cookie, name);
+    }
+}
+
+fn notify_key(key: WatchKey, mask: u32, cookie: u32, name: Option<&str>) {
+    let queues: Vec<(Arc<Mutex<EventQueue>>, i32)> = match WATCHES.lock().get(&key) {
+        Some(list) => list
+            .iter()
+            .filter(|watch| watch.mask & mask != 0)
+            .filter_map(|watch| watch.queue.upgrade().map(|queue| (queue, watch.wd)))
+            .collect(),
+        None => return,
+    };
+    for (queue, wd) in queues {
+        queue.lock().push(wd, mask, cookie, name);
+    }
+}
+
+/// Report that `inode` has lost its last link, and remove the watches on it,
+/// each with `IN_IGNORED`.
+/// Call it with `inode` still held, so that its number is not given to another file yet.
+pub fn notify_delete_self(inode: &Arc<dyn INode>) {
+    if WATCHES.lock().is_empty() {
+        return;
+    }
+    let metadata = match inode.metadata() {
+        Ok(metadata) => metadata,
+        Err(_) => return,
+    };
+    let key = (metadata.dev, metadata.inode);
+    let watches = match WATCHES.lock().remove(&key) {
+        Some(watches) => watches,
+        None => return,
+    };
+    for watch in watches {
+        if let Some(queue) = watch.queue.upgrade() {
+            let mut queue = queue.lock();
+            if watch.mask & IN_DELETE_SELF != 0 {
+                queue.push(watch.wd, IN_DELETE_SELF, 0, None);
+            }
+            queue.watches.remove(&watch.wd);
+            queue.push(watch.wd, IN_IGNORED, 0, None);
+        }
+    }
+}
+
+/// Report the creation or removal of `name` in `dir`, of type `type_`
+pub fn notify_entry(dir: &Arc<dyn INode>, mask: u32, name: &str, type_: FileType) {
+    notify_cookie(dir, mask | isdir(type_), 0, Some(name));
+}
+
+/// Report a rename of an entry of type `type_`, as two events with the same cookie
+pub fn notify_move(
+    old_dir: &Arc<dyn INode>,
+    old_name: &str,
+    new_dir: &Arc<dyn INode>,
+    new_name: &str,
+    type_: FileType,
+) {
+    let cookie = COOKIE.fetch_add(1, Ordering::Relaxed) + 1;
+    notify_cookie(
+        old_dir,
+        IN_MOVED_FROM | isdir(type_),
+        cookie,
+        Some(old_name),
+    );
+    notify_cookie(new_dir, IN_MOVED_TO | isdir(type_), cookie, Some(new_name));
+}
+
+fn isdir(type_: FileType) -> u32 {
+    match type_ {
+        FileType::Dir => IN_ISDIR,
+        _ => 0,
+    }
+}
+
+impl INode for Inotify {
+    /// Read as many whole events as fit in `buf`
+    fn read_at(&self, _offset: usize, buf: &mut [u8]) -> Result<usize> {
+        let mut queue = self.queue.lock();
+        let first = match queue.events.front() {
+            Some(event) => event.len(),
+            None => return Err(FsError::Again),
+        };
+        if first > buf.len() {
+            return Err(FsError::InvalidParam);
+        }
+        let mut len = 0;
+        while let Some(event) = queue.events.front() {
+            if len + event.len() > buf.len() {
+                break;
+            }
+            buf[len..len + event.len()].copy_from_slice(event);
+            len += event.len();
+            queue.events.pop_front();
+        }
+        if queue.events.is_empty() {
+            queue.eventbus.clear(Event::READABLE);
+        }
+        Ok(len)
+    }
+
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> Result<usize> {
+        Err(FsError::InvalidParam)
+    }
+
+    fn poll(&self) -> Result<PollStatus> {
+        Ok(PollStatus {
+            read: !self.queue.lock().events.is_empty(),
+            write: false,
+            error: false,
+        })
+    }
+
+    fn async_poll<'a>(
+        &'a self,
+    ) -> Pin<Box<dyn Future<Output = Result<PollStatus>> + Send + Sync + 'a>> {
+        #[must_use = "future does nothing unless polled/`await`-ed"]
+        struct InotifyFuture<'a> {
+            inotify: &'a Inotify,
+        };
+
+        impl<'a> Future for InotifyFuture<'a> {
+            type Output = Result<PollStatus>;
+
+            fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+                let mut queue = self.inotify.queue.lock();
+                if !queue.events.is_empty() {
+                    drop(queue);
+                    return Poll::Ready(self.inotify.poll());
+                }
+                let waker = cx.waker().clone();
+                queue.eventbus.subscribe(Box::new(move |_| {
+                    waker.wake_by_ref();
+                    true
+                }));
+                Poll::Pending
+            }
+        }
+
+        Box::pin(InotifyFuture { inotify: self })
+    }
+
+    fn as_any_ref(&self) -> &dyn Any {
+        self
+    }
+}
@@ -0,0 +1,503 @@
+//! Rings of `io_uring_setup`, shared with user space
+//!
+//! Both rings and the array of submission entries are kernel frames, mapped into the process
+//! by `mmap` on the fd, so completions can be posted while it is not running.
+//! Entries are taken from the submission ring only in `io_uring_enter`,
+//! each starting a task on the executor, which posts the completion once done.
+
+use super::FileLike;
+use crate::arch::timer::timer_now;
+use crate::memory::{alloc_frame_contiguous, dealloc_frame, phys_to_virt};
+use crate::process::{spawn_task, Thread};
+use crate::sync::{Event, EventBus, SpinNoIrqLock as Mutex};
+use crate::syscall::{MmapProt, SysError, SysResult};
+use crate::trap::NAIVE_TIMER;
+use alloc::{boxed::Box, sync::Arc};
+use core::future::Future;
+use core::mem::size_of;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicU32, Ordering};
+use core::task::{Context, Poll};
+use core::time::Duration;
+use rcore_fs::vfs::{MMapArea, PollStatus};
+use rcore_memory::memory_set::handler::MemoryHandler;
+use rcore_memory::memory_set::MemoryAttr;
+use rcore_memory::paging::PageTable;
+use rcore_memory::{VirtAddr, PAGE_SIZE};
+
+/// `struct io_uring_params` of Linux
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct IoUringParams {
+    pub sq_entries: u32,
+    pub cq_entries: u32,
+    /// `IORING_SETUP_*`
+    pub flags: u32,
+    pub sq_thread_cpu: u32,
+    pub sq_thread_idle: u32,
+    /// `IORING_FEAT_*`, filled by the kernel
+    pub features: u32,
+    pub wq_fd: u32,
+    pub resv: [u32; 3],
+    pub sq_off: SqRingOffsets,
+    pub cq_off: CqRingOffsets,
+}
+
+/// `struct io_sqring_offsets` of Linux, offsets in the mapping at `IORING_OFF_SQ_RING`
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SqRingOffsets {
+    pub head: u32,
+    pub tail: u32,
+    pub ring_mask: u32,
+    pub ring_entries: u32,
+    pub flags: u32,
+    pub dropped: u32,
+    pub array: u32,
+    pub resv1: u32,
+    pub resv2: u64,
+}
+
+/// `struct io_cqring_offsets` of Linux, offsets in the mapping at `IORING_OFF_CQ_RING`
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct CqRingOffsets {
+    pub head: u32,
+    pub tail: u32,
+    pub ring_mask: u32,
+    pub ring_entries: u32,
+    pub overflow: u32,
+    pub cqes: u32,
+    pub flags: u32,
+    pub resv1: u32,
+    pub resv2: u64,
+}
+
+/// `struct io_uring_sqe` of Linux
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct IoUringSqe {
+    /// `IORING_OP_*`
+    pub opcode: u8,
+    /// `IOSQE_*`
+    pub flags: u8,
+    pub ioprio: u16,
+    pub fd: i32,
+    pub off: u64,
+    pub addr: u64,
+    pub len: u32,
+    /// `rw_flags`, `fsync_flags` or `poll_events`, by the opcode
+    pub op_flags: u32,
+    /// Returned in the completion as it is
+    pub user_data: u64,
+    pub buf_index: u16,
+    pub personality: u16,
+    pub splice_fd_in: i32,
+    pub pad: [u64; 2],
+}
+
+/// `struct io_uring_cqe` of Linux
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct IoUringCqe {
+    pub user_data: u64,
+    /// Result of the operation, or a negated errno
+    pub res: i32,
+    pub flags: u32,
+}
+
+/// Offsets to `mmap` the fd at
+pub const IORING_OFF_SQ_RING: usize = 0;
+pub const IORING_OFF_CQ_RING: usize = 0x800_0000;
+pub const IORING_OFF_SQES: usize = 0x1000_0000;
+
+// Layout of the frames of both rings, like `struct io_rings` of Linux.
+// The submission ring is followed by the completion ring on another cache line,
+// then by the completion entries and the array of indexes of submission entries.
+const SQ_HEAD: usize = 0;
+const SQ_TAIL: usize = 4;
+const SQ_RING_MASK: usize = 8;
+const SQ_RING_ENTRIES: usize = 12;
+const SQ_FLAGS: usize = 16;
+const SQ_DROPPED: usize = 20;
+const CQ_HEAD: usize = 64;
+const CQ_TAIL: usize = 68;
+const CQ_RING_MASK: usize = 72;
+const CQ_RING_ENTRIES: usize = 76;
+const CQ_OVERFLOW: usize = 80;
+const CQ_FLAGS: usize = 84;
+const CQES: usize = 128;
+
+/// Physically contiguous frames, zeroed when allocated
+#[derive(Debug)]
+struct Frames {
+    paddr: usize,
+    pages: usize,
+}
+
+impl Frames {
+    fn new(size: usize) -> Option<Self> {
+        let pages = (size + PAGE_SIZE - 1) / PAGE_SIZE;
+        let paddr = alloc_frame_contiguous(pages, 0)?;
+        unsafe {
+            core::ptr::write_bytes(phys_to_virt(paddr) as *mut u8, 0, pages * PAGE_SIZE);
+        }
+        Some(Frames { paddr, pages })
+    }
+
+    fn vaddr(&self) -> usize {
+        phys_to_virt(self.paddr)
+    }
+
+    fn len(&self) -> usize {
+        self.pages * PAGE_SIZE
+    }
+}
+
+impl Drop for Frames {
+    fn drop(&mut self) {
+        for i in 0..self.pages {
+            dealloc_frame(self.paddr + i * PAGE_SIZE);
+        }
+    }
+}
+
+pub struct IoUring {
+    sq_entries: usize,
+    cq_entries: usize,
+    /// Both rings, as one mapping serves for both
+    rings: Arc<Frames>,
+    sqes: Arc<Frames>,
+    /// Held to post to the completion ring
+    cq_lock: Mutex<()>,
+    /// `READABLE` is notified on each completion
+    eventbus: Arc<Mutex<EventBus>>,
+}
+
+impl IoUring {
+    /// Create rings of `sq_entries` and `cq_entries`, both powers of two,
+    /// filling the offsets in `params`.
+    /// Return `None` if the frames can't be allocated.
+    pub fn new(sq_entries: usize, cq_entries: usize, params: &mut IoUringParams) -> Option<Self> {
+        let array = CQES + cq_entries * size_of::<IoUringCqe>();
+        let rings = Frames::new(array + sq_entries * size_of::<u32>())?;
+        let sqes = Frames::new(sq_entries * size_of::<IoUringSqe>())?;
+        let ring = IoUring {
+            sq_entries,
+            cq_entries,
+            rings: Arc::new(rings),
+            sqes: Arc::new(sqes),
+            cq_lock: Mutex::new(()),
+            eventbus: EventBus::new(),
+        };
+        ring.field(SQ_RING_MASK)
+            .store(sq_entries as u32 - 1, Ordering::Relaxed);
+        ring.field(SQ_RING_ENTRIES)
+            .store(sq_entries as u32, Ordering::Relaxed);
+        ring.field(CQ_RING_MASK)
+            .store(cq_entries as u32 - 1, Ordering::Relaxed);
+        ring.field(CQ_RING_ENTRIES)
+            .store(cq_entries as u32, Ordering::Relaxed);
+
+        params.sq_entries = sq_entries as u32;
+        params.cq_entries = cq_entries as u32;
+        params.sq_off = SqRingOffsets {
+            head: SQ_HEAD as u32,
+            tail: SQ_TAIL as u32,
+            ring_mask: SQ_RING_MASK as u32,
+            ring_entries: SQ_RING_ENTRIES as u32,
+            flags: SQ_FLAGS as u32,
+            dropped: SQ_DROPPED as u32,
+            array: array as u32,
+            ..SqRingOffsets::default()
+        };
+        params.cq_off = CqRingOffsets {
+            head: CQ_HEAD as u32,
+            tail: CQ_TAIL as u32,
+            ring_mask: CQ_RING_MASK as u32,
+            ring_entries: CQ_RING_ENTRIES as u32,
+            overflow: CQ_OVERFLOW as u32,
+            cqes: CQES as u32,
+            flags: CQ_FLAGS as u32,
+            ..CqRingOffsets::default()
+        };
+        Some(ring)
+    }
+
+    /// The word at `offset` of the rings, shared with user space
+    fn field(&self, offset: usize) -> &AtomicU32 {
+        unsafe { &*((self.rings.vaddr() + offset) as *const AtomicU32) }
+    }
+
+    /// Take the next submission entry, if user space has queued one.
+    /// Entries with an index out of range are counted as dropped and skipped.
+    pub fn next_sqe(&self) -> Option<IoUringSqe> {
+        loop {
+            // only the kernel moves the head
+            let head = self.field(SQ_HEAD).load(Ordering::Relaxed);
+            if head == self.field(SQ_TAIL).load(Ordering::Acquire) {
+                return None;
+            }
+            let slot = head as usize & (self.sq_entries - 1);
+            let array = self.rings.vaddr() + self.array_offset();
+            let index = unsafe { (array as *const u32).add(slot).read_volatile() } as usize;
+            let sqe = if index < self.sq_entries {
+                let sqes = self.sqes.vaddr() as *const IoUringSqe;
+                Some(unsafe { sqes.add(index).read_volatile() })
+            } else {
+                self.field(SQ_DROPPED).fetch_add(1, Ordering::Relaxed);
+                None
+            };
+            // the entry is copied, so user space may reuse it
+            self.field(SQ_HEAD)
+                .store(head.wrapping_add(1), Ordering::Release);
+            if sqe.is_some() {
+                return sqe;
+            }
+        }
+    }
+
+    fn array_offset(&self) -> usize {
+        CQES + self.cq_entries * size_of::<IoUringCqe>()
+    }
+
+    /// Completions posted and not yet consumed by user space
+    fn cq_ready(&self) -> usize {
+        let tail = self.field(CQ_TAIL).load(Ordering::Relaxed);
+        let head = self.field(CQ_HEAD).load(Ordering::Acquire);
+        tail.wrapping_sub(head) as usize
+    }
+
+    /// Post the completion of the entry with `user_data`.
+    /// If the ring is full, it is dropped and counted as overflow.
+    pub fn complete(&self, user_data: u64, res: SysResult) {
+        let lock = self.cq_lock.lock();
+        if self.cq_ready() >= self.cq_entries {
+            self.field(CQ_OVERFLOW).fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        let tail = self.field(CQ_TAIL).load(Ordering::Relaxed);
+        let cqe = IoUringCqe {
+            user_data,
+            res: match res {
+                Ok(len) => len as i32,
+                Err(err) => -(err as i32),
+            },
+            flags: 0,
+        };
+        let slot = tail as usize & (self.cq_entries - 1);
+        let cqes = (self.rings.vaddr() + CQES) as *mut IoUringCqe;
+        unsafe { cqes.add(slot).write_volatile(cqe) };
+        self.field(CQ_TAIL)
+            .store(tail.wrapping_add(1), Ordering::Release);
+        drop(lock);
+        self.eventbus.lock().notify_all(Event::READABLE);
+    }
+
+    /// Run `op` on the executor, then post its result for `user_data`
+    pub fn spawn(
+        self: &Arc<Self>,
+        user_data: u64,
+        op: impl Future<Output = SysResult> + Send + 'static,
+    ) {
+        let ring = self.clone();
+        spawn_task(async move {
+            let res = op.await;
+            ring.complete(user_data, res);
+        });
+    }
+
+    /// Wait until `min_complete` completions are in the ring.
+    /// Fail with EINTR if `thread` gets a signal before.
+    pub fn wait_cq(
+        self: &Arc<Self>,
+        thread: Arc<Thread>,
+        min_complete: usize,
+    ) -> impl Future<Output = Result<(), SysError>> {
+        CqWaitFuture {
+            ring: self.clone(),
+            thread: Some(thread),
+            min_complete,
+        }
+    }
+
+    /// Map the part of the rings at `area.offset` into the current process
+    pub fn mmap(&self, area: MMapArea) -> SysResult {
+        let frames = match area.offset {
+            IORING_OFF_SQ_RING | IORING_OFF_CQ_RING => &self.rings,
+            IORING_OFF_SQES => &self.sqes,
+            _ => return Err(SysError::EINVAL),
+        };
+        if area.end_vaddr - area.start_vaddr > frames.len() {
+            return Err(SysError::EINVAL);
+        }
+        let prot = MmapProt::from_bits_truncate(area.prot);
+        Thread::current().lock_vm().push(
+            area.start_vaddr,
+            area.end_vaddr,
+            prot.to_attr(),
+            RingMapping {
+                frames: frames.clone(),
+                mem_start: area.start_vaddr,
+            },
+            "io_uring",
+        );
+        Ok(0)
+    }
+
+    /// Readable if there are completions to reap
+    pub fn poll(&self) -> PollStatus {
+        PollStatus {
+            read: self.cq_ready() > 0,
+            write: false,
+            error: false,
+        }
+    }
+
+    /// Wait until readable
+    pub async fn async_poll(self: &Arc<Self>) -> PollStatus {
+        let wait = CqWaitFuture {
+            ring: self.clone(),
+            thread: None,
+            min_complete: 1,
+        };
+        // only fails on signals, which are not waited for
+        let _ = wait.await;
+        self.poll()
+    }
+}
+
+#[must_use = "future does nothing unless polled/`await`-ed"]
+struct CqWaitFuture {
+    ring: Arc<IoUring>,
+    /// Interrupted by signals to it, if any
+    thread: Option<Arc<Thread>>,
+    min_complete: usize,
+}
+
+impl Future for CqWaitFuture {
+    type Output = Result<(), SysError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        // completions are posted under the lock, so none is missed after the check
+        let lock = self.ring.cq_lock.lock();
+        if self.ring.cq_ready() >= self.min_complete {
+            return Poll::Ready(Ok(()));
+        }
+        if let Some(thread) = &self.thread {
+            if thread.has_signal_to_handle() {
+                return Poll::Ready(Err(SysError::EINTR));
+            }
+        }
+        let waker = cx.waker().clone();
+        self.ring.eventbus.lock().subscribe(Box::new(move |_| {
+            waker.wake_by_ref();
+            true
+        }));
+        drop(lock);
+        if let Some(thread) = &self.thread {
+            // signals
+            let waker = cx.waker().clone();
+            let eventbus = thread.lock_proc().eventbus.clone();
+            eventbus.lock().subscribe(Box::new(move |_| {
+                waker.wake_by_ref();
+                true
+            }));
+        }
+        Poll::Pending
+    }
+}
+
+/// Wait until `file` has any of the `poll(2)` `events`, and return those it has,
+/// as `IORING_OP_POLL_ADD`.
+pub async fn poll_add(file: FileLike, events: u16) -> SysResult {
+    PollAddFuture { file, events }.await
+}
+
+const POLLIN: u16 = 0x1;
+const POLLOUT: u16 = 0x4;
+const POLLERR: u16 = 0x8;
+const POLLHUP: u16 = 0x10;
+
+/// How long to wait before polling again a file woken for other events
+const POLL_RETRY: Duration = Duration::from_millis(10);
+
+#[must_use = "future does nothing unless polled/`await`-ed"]
+struct PollAddFuture {
+    file: FileLike,
+    events: u16,
+}
+
+impl Future for PollAddFuture {
+    type Output = SysResult;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        // errors and hangups are reported even if not asked for
+        let wanted = self.events | POLLERR | POLLHUP;
+        let mut fut = Box::pin(self.file.async_poll());
+        match fut.as_mut().poll(cx) {
+            Poll::Ready(Ok(status)) => {
+                let mut ready = 0;
+                if status.read {
+                    ready |= POLLIN;
+                }
+                if status.write {
+                    ready |= POLLOUT;
+                }
+                if status.error {
+                    ready |= POLLERR;
+                }
+                if ready & wanted != 0 {
+                    return Poll::Ready(Ok((ready & wanted) as usize));
+                }
+                // ready for something else, which wakes nobody up when it changes
+                let waker = cx.waker().clone();
+                let deadline = timer_now() + POLL_RETRY;
+                NAIVE_TIMER
+                    .lock()
+                    .add(deadline, Box::new(move |_| waker.wake()));
+                Poll::Pending
+            }
+            Poll::Ready(Err(err)) => Poll::Ready(Err(err)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// Mapping of the frames of rings, shared by the parent and child after `fork`
+#[derive(Debug, Clone)]
+struct RingMapping {
+    frames: Arc<Frames>,
+    mem_start: usize,
+}
+
+impl MemoryHandler for RingMapping {
+    fn box_clone(&self) -> Box<dyn MemoryHandler> {
+        Box::new(self.clone())
+    }
+
+    fn map(&self, pt: &mut dyn PageTable, addr: VirtAddr, attr: &MemoryAttr) {
+        let target = self.frames.paddr + (addr - self.mem_start);
+        let entry = pt.map(addr, target);
+        attr.apply(entry);
+    }
+
+    fn unmap(&self, pt: &mut dyn PageTable, addr: VirtAddr) {
+        // the frames are freed with the rings
+        pt.unmap(addr);
+    }
+
+    fn clone_map(
+        &self,
+        pt: &mut dyn PageTable,
+        _src_pt: &mut dyn PageTable,
+        addr: VirtAddr,
+        attr: &MemoryAttr,
+    ) {
+        self.map(pt, addr, attr);
+    }
+
+    fn handle_page_fault(&self, _pt: &mut dyn PageTable, _addr: VirtAddr) -> bool {
+        false
+    }
+}
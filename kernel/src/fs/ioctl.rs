@@ -27,6 +27,12 @@ pub const TIOCSPGRP: usize = 0x5410;
 #[cfg(target_arch = "mips")]
 pub const TIOCSPGRP: usize = 0x8_004_74_76;
 
+#[cfg(not(target_arch = "mips"))]
+pub const TIOCSTI: usize = 0x5412;
+// _IOW('t', 114, char)
+#[cfg(target_arch = "mips")]
+pub const TIOCSTI: usize = 0x8_001_74_72;
+
 #[cfg(not(target_arch = "mips"))]
 pub const TIOCGWINSZ: usize = 0x5413;
 // _IOR('t', 104, struct winsize)
@@ -87,6 +93,11 @@ pub struct Termios {
     pub ospeed: u32,
 }
 
+// indices of special characters in `Termios::cc`
+pub const VINTR: usize = 0;
+pub const VQUIT: usize = 1;
+pub const VSUSP: usize = 10;
+
 impl Default for Termios {
     fn default() -> Self {
         Termios {
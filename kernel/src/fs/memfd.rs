@@ -0,0 +1,179 @@
+//! Anonymous in-memory files of `memfd_create`
+//!
+//! A memfd is an unlinked file of a private RamFS, so it lives as long as it is open,
+//! and goes through the page cache like any regular file, which makes `MAP_SHARED` work.
+//!
+//! Seals are checked by `FileHandle` before writing, resizing or mapping.
+//! As `FsError` has no permission error, violations are reported as `EBUSY`
+//! instead of `EPERM`.
+//!
+//! `F_SEAL_WRITE` can not be added while a shared mapping may be made writable,
+//! so such mappings are counted, like `i_mmap_writable` of Linux.
+
+use alloc::{format, sync::Arc};
+use core::any::Any;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use bitflags::*;
+use rcore_fs::vfs::*;
+use rcore_fs_ramfs::RamFS;
+
+use crate::sync::SpinNoIrqLock as Mutex;
+use crate::syscall::SysError;
+
+lazy_static! {
+    static ref MEMFD_FS: Arc<RamFS> = RamFS::new();
+}
+
+/// Used to name files in `MEMFD_FS`
+static MEMFD_ID: AtomicUsize = AtomicUsize::new(0);
+
+bitflags! {
+    /// Seals of `fcntl(F_ADD_SEALS)`
+    pub struct Seals: u32 {
+        /// No more seals can be added
+        const SEAL = 1;
+        /// The file can not shrink
+        const SHRINK = 2;
+        /// The file can not grow
+        const GROW = 4;
+        /// The content can not be modified
+        const WRITE = 8;
+    }
+}
+
+pub struct MemFd {
+    inode: Arc<dyn INode>,
+    seals: Mutex<Seals>,
+    /// Number of `WritableMap`s alive
+    writable_maps: Arc<AtomicUsize>,
+}
+
+/// Held by a shared mapping of a memfd which may be made writable,
+/// and by each copy of it made by splitting the area or by fork
+pub struct WritableMap(Arc<AtomicUsize>);
+
+impl Clone for WritableMap {
+    fn clone(&self) -> Self {
+        self.0.fetch_add(1, Ordering::SeqCst);
+        WritableMap(self.0.clone())
+    }
+}
+
+impl Drop for WritableMap {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl MemFd {
+    /// Create an empty memfd. Without `allow_sealing`, it is sealed with `SEAL`.
+    pub fn new(allow_sealing: bool) -> Result<Self> {
+        let root = MEMFD_FS.root_inode();
+        let name = format!("memfd{}", MEMFD_ID.fetch_add(1, Ordering::Relaxed));
+        let inode = root.create(&name, FileType::File, 0o600)?;
+        root.unlink(&name)?;
+        let seals = match allow_sealing {
+            true => Seals::empty(),
+            false => Seals::SEAL,
+        };
+        Ok(MemFd {
+            inode,
+            seals: Mutex::new(seals),
+            writable_maps: Arc::new(AtomicUsize::new(0)),
+        })
+    }
+
+    pub fn seals(&self) -> Seals {
+        *self.seals.lock()
+    }
+
+    pub fn add_seals(&self, seals: Seals) -> core::result::Result<(), SysError> {
+        let mut current = self.seals.lock();
+        if current.contains(Seals::SEAL) {
+            return Err(SysError::EPERM);
+        }
+        if seals.contains(Seals::WRITE) && self.writable_maps.load(Ordering::SeqCst) != 0 {
+            return Err(SysError::EBUSY);
+        }
+        current.insert(seals);
+        Ok(())
+    }
+
+    /// Count a shared mapping which may be made writable, unless sealed against writes
+    pub fn map_writable(&self) -> Option<WritableMap> {
+        // counted first, so that `add_seals` sees it or it sees the seal
+        self.writable_maps.fetch_add(1, Ordering::SeqCst);
+        let map = WritableMap(self.writable_maps.clone());
+        match self.seals().contains(Seals::WRITE) {
+            true => None,
+            false => Some(map),
+        }
+    }
+
+    /// Check that writing `len` bytes at `offset` is allowed by the seals
+    pub fn check_write(&self, offset: usize, len: usize) -> Result<()> {
+        let seals = self.seals();
+        if seals.contains(Seals::WRITE) {
+            return Err(FsError::Busy);
+        }
+        if seals.contains(Seals::GROW) && offset + len > self.inode.metadata()?.size {
+            return Err(FsError::Busy);
+        }
+        Ok(())
+    }
+
+    /// Check that resizing to `len` is allowed by the seals
+    pub fn check_resize(&self, len: usize) -> Result<()> {
+        let seals = self.seals();
+        let size = self.inode.metadata()?.size;
+        if (seals.contains(Seals::SHRINK) && len < size)
+            || (seals.contains(Seals::GROW) && len > size)
+        {
+            return Err(FsError::Busy);
+        }
+        Ok(())
+    }
+}
+
+impl INode for MemFd {
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize> {
+        self.inode.read_at(offset, buf)
+    }
+
+    fn write_at(&self, offset: usize, buf: &[u8]) -> Result<usize> {
+        self.inode.write_at(offset, buf)
+    }
+
+    fn poll(&self) -> Result<PollStatus> {
+        self.inode.poll()
+    }
+
+    fn metadata(&self) -> Result<Metadata> {
+        self.inode.metadata()
+    }
+
+    fn set_metadata(&self, metadata: &Metadata) -> Result<()> {
+        self.inode.set_metadata(metadata)
+    }
+
+    fn sync_all(&self) -> Result<()> {
+        self.inode.sync_all()
+    }
+
+    fn sync_data(&self) -> Result<()> {
+        self.inode.sync_data()
+    }
+
+    fn resize(&self, len: usize) -> Result<()> {
+        self.inode.resize(len)
+    }
+
+    fn fs(&self) -> Arc<dyn FileSystem> {
+        self.inode.fs()
+    }
+
+    fn as_any_ref(&self) -> &dyn Any {
+        self
+    }
+}
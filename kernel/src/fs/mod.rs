@@ -1,6 +1,6 @@
-use alloc::{sync::Arc, vec::Vec};
+use alloc::{string::String, sync::Arc, vec::Vec};
 
-use rcore_fs::{dev::block_cache::BlockCache, vfs::*};
+use rcore_fs::vfs::*;
 use rcore_fs_devfs::{
     special::{NullINode, ZeroINode},
     DevFS,
@@ -9,24 +9,34 @@ use rcore_fs_mountfs::MountFS;
 use rcore_fs_ramfs::RamFS;
 use rcore_fs_sfs::{INodeImpl, SimpleFileSystem};
 
-use self::devfs::{Fbdev, RandomINode};
+use self::buffer_cache::{BufferCache, WriteMode, BUFFER_CACHE_MAX_BUFFERS};
+use self::devfs::{BlockINode, Fbdev, RandomINode};
+use self::mount::MountNamespace;
 
 pub use self::devfs::{ShmINode, TTY};
 pub use self::file::*;
 pub use self::file_like::*;
-pub use self::pipe::Pipe;
+pub use self::pipe::{Pipe, PIPE_MAX_SIZE};
 pub use self::pseudo::*;
-use crate::drivers::{BlockDriver, BlockDriverWrapper};
+use crate::drivers::{block::partition::block_devices, BlockDriver, CMDLINE};
 
+pub mod aio;
+pub mod buffer_cache;
 mod devfs;
 mod device;
 pub mod epoll;
 pub mod fcntl;
 mod file;
 mod file_like;
+pub mod inotify;
+pub mod io_uring;
 pub mod ioctl;
+pub mod memfd;
+pub mod mount;
+pub mod page_cache;
 mod pipe;
 mod pseudo;
+pub mod special;
 
 // Hard link user programs
 #[cfg(feature = "link_user")]
@@ -46,17 +56,25 @@ _user_img_end:
 lazy_static! {
     /// The root of file system
     pub static ref ROOT_INODE: Arc<dyn INode> = {
+        let block_devices = block_devices();
+
         #[cfg(not(feature = "link_user"))]
         let device = {
-            let driver = BlockDriverWrapper(
-                crate::drivers::BLK_DRIVERS
-                    .read().iter()
-                    .next().expect("Block device not found")
-                    .clone()
-            );
+            // use the device given by `root=` in the command line, or the first disk
+            let root_dev = match root_device_name() {
+                Some(name) => block_devices
+                    .iter()
+                    .find(|dev| dev.name == name)
+                    .unwrap_or_else(|| panic!("root device {} not found", name)),
+                None => block_devices.first().expect("Block device not found"),
+            };
+            info!("rootfs on /dev/{}", root_dev.name);
             // enable block cache
-            Arc::new(BlockCache::new(driver, 0x100))
-            // Arc::new(driver)
+            BufferCache::new(
+                root_dev.driver.clone(),
+                WriteMode::WriteBack,
+                BUFFER_CACHE_MAX_BUFFERS,
+            )
         };
         #[cfg(feature = "link_user")]
         let device = {
@@ -69,8 +87,10 @@ lazy_static! {
         };
 
         // use SFS as rootfs
-        let sfs = SimpleFileSystem::open(device).expect("failed to open SFS");
+        let sfs = SimpleFileSystem::open(device.clone()).expect("failed to open SFS");
         let rootfs = MountFS::new(sfs);
+        #[cfg(not(feature = "link_user"))]
+        device.set_fs(rootfs.clone());
         let root = rootfs.root_inode();
 
         // create DevFS
@@ -82,10 +102,16 @@ lazy_static! {
         devfs.add("tty", TTY.clone()).expect("failed to mknod /dev/tty");
         devfs.add("fb0", Arc::new(Fbdev::default())).expect("failed to mknod /dev/fb0");
         devfs.add("shm", Arc::new(ShmINode::default())).expect("failed to mkdir shm");
+        for dev in block_devices.iter() {
+            devfs.add(&dev.name, Arc::new(BlockINode::new(dev))).expect("failed to mknod block device");
+        }
 
         #[cfg(feature = "hypervisor")]
         devfs.add("rvm", Arc::new(crate::rvm::RvmINode::new())).expect("failed to mknod /dev/rvm");
 
+        // let nodes made by mknod elsewhere find the drivers
+        special::register_devices(&devfs.root_inode());
+
         // mount DevFS at /dev
         let dev = root.find(true, "dev").unwrap_or_else(|_| {
             root.create("dev", FileType::Dir, 0o666).expect("failed to mkdir /dev")
@@ -108,10 +134,133 @@ lazy_static! {
     };
 }
 
+/// Name of the root device given by `root=/dev/<name>` in the kernel command line
+#[cfg(not(feature = "link_user"))]
+fn root_device_name() -> Option<String> {
+    let cmdline = CMDLINE.read();
+    cmdline
+        .split_whitespace()
+        .find(|arg| arg.starts_with("root="))
+        .map(|arg| String::from(arg["root=".len()..].trim_start_matches("/dev/")))
+}
+
 pub const FOLLOW_MAX_DEPTH: usize = 3;
 
+/// Look up `path` from `dir` without leaving `root`, the root directory set by chroot:
+/// absolute paths and symbolic links start at `root`, and `..` of it is itself.
+/// File systems in `mounts` are seen on the directories they cover.
+/// The last component of `path` is dereferenced only if `follow`.
+pub fn lookup_beneath(
+    root: &Arc<dyn INode>,
+    mounts: &MountNamespace,
+    dir: Arc<dyn INode>,
+    path: &str,
+    follow: bool,
+    follow_max_depth: usize,
+) -> Result<Arc<dyn INode>> {
+    let mut inode = if path.starts_with('/') {
+        mounts.cover(root.clone())?
+    } else {
+        dir
+    };
+    let names: Vec<&str> = path
+        .split('/')
+        .filter(|&name| name != "" && name != ".")
+        .collect();
+    for (i, &name) in names.iter().enumerate() {
+        if name == ".." {
+            if let Some(parent) = parent_beneath(&inode, root, mounts)? {
+                inode = parent;
+            }
+            continue;
+        }
+        let parent = inode;
+        inode = mounts.cover(parent.find(name)?)?;
+        let last = i + 1 == names.len();
+        if (follow || !last) && inode.metadata()?.type_ == FileType::SymLink {
+            if follow_max_depth == 0 {
+                return Err(FsError::SymLoop);
+            }
+            let target =
+                String::from_utf8(inode.read_as_vec()?).map_err(|_| FsError::InvalidParam)?;
+            inode = lookup_beneath(root, mounts, parent, &target, true, follow_max_depth - 1)?;
+        }
+    }
+    Ok(inode)
+}
+
+/// `..` of directory `dir` seen through `mounts`, leaving the root of a mounted
+/// file system for the directory it covers. None if `dir` is `root` or the real root.
+fn parent_beneath(
+    dir: &Arc<dyn INode>,
+    root: &Arc<dyn INode>,
+    mounts: &MountNamespace,
+) -> Result<Option<Arc<dyn INode>>> {
+    let mut dir = dir.clone();
+    loop {
+        if same_inode(&dir, root)? {
+            return Ok(None);
+        }
+        match mounts.covered_by(&dir)? {
+            Some(covered) => dir = covered,
+            None => break,
+        }
+    }
+    let parent = dir.find("..")?;
+    // `..` of the real root is itself
+    if same_inode(&parent, &dir)? {
+        return Ok(None);
+    }
+    Ok(Some(mounts.cover(parent)?))
+}
+
+/// Whether directory `dir` is `root` or below it, seen through `mounts`
+pub fn is_beneath(
+    dir: &Arc<dyn INode>,
+    root: &Arc<dyn INode>,
+    mounts: &MountNamespace,
+) -> Result<bool> {
+    let mut dir = dir.clone();
+    loop {
+        if same_inode(&dir, root)? {
+            return Ok(true);
+        }
+        dir = match mounts.covered_by(&dir)? {
+            Some(covered) => covered,
+            None => {
+                let parent = dir.find("..")?;
+                // `..` of the real root is itself
+                if same_inode(&parent, &dir)? {
+                    return Ok(false);
+                }
+                parent
+            }
+        };
+    }
+}
+
+/// Whether directories `a` and `b` are the same.
+/// Directories always belong to a file system, unlike some device files.
+fn same_inode(a: &Arc<dyn INode>, b: &Arc<dyn INode>) -> Result<bool> {
+    let (ma, mb) = (a.metadata()?, b.metadata()?);
+    if ma.dev != mb.dev || ma.inode != mb.inode {
+        return Ok(false);
+    }
+    // inode numbers are only unique in a file system
+    Ok(fs_id(&a.fs()) == fs_id(&b.fs()))
+}
+
+/// Identity of a file system, which its inodes all share
+pub fn fs_id(fs: &Arc<dyn FileSystem>) -> usize {
+    &**fs as *const dyn FileSystem as *const () as usize
+}
+
 pub trait INodeExt {
     fn read_as_vec(&self) -> Result<Vec<u8>>;
+
+    /// The first offset at or after `offset` in data, or in a hole if `hole`,
+    /// for `SEEK_DATA` and `SEEK_HOLE`. None if `offset` is at or past the end of file.
+    fn seek_data_hole(&self, offset: usize, hole: bool) -> Result<Option<usize>>;
 }
 
 impl INodeExt for dyn INode {
@@ -124,4 +273,14 @@ impl INodeExt for dyn INode {
         self.read_at(0, buf.as_mut_slice())?;
         Ok(buf)
     }
+
+    fn seek_data_hole(&self, offset: usize, hole: bool) -> Result<Option<usize>> {
+        // none of our file systems, RamFS included, keeps sparse files,
+        // so a file is all data, followed by the implicit hole at its end
+        let size = self.metadata()?.size;
+        if offset >= size {
+            return Ok(None);
+        }
+        Ok(Some(if hole { size } else { offset }))
+    }
 }
@@ -0,0 +1,102 @@
+//! Mount table of a mount namespace
+//!
+//! The file systems mounted at boot (`/dev`, `/tmp`, ...) are part of
+//! `ROOT_INODE` and seen by everyone. File systems mounted by mount(2)
+//! only live in the mount table of the caller's namespace. They are found
+//! on the directories they cover as a path is walked, see `cover`, so
+//! lookups from a directory fd see them too and `..` is resolved on the
+//! directories actually reached rather than on the path.
+
+use super::same_inode;
+use alloc::{string::String, sync::Arc, vec::Vec};
+use rcore_fs::vfs::{FileSystem, INode, Result};
+use spin::RwLock;
+
+#[derive(Clone)]
+struct Mount {
+    /// The directory it covers
+    covered: Arc<dyn INode>,
+    fs: Arc<dyn FileSystem>,
+}
+
+#[derive(Clone, Default)]
+pub struct MountNamespace {
+    /// In mount order
+    mounts: Vec<Mount>,
+}
+
+lazy_static! {
+    /// The mount namespace of the init process
+    pub static ref ROOT_MOUNT_NS: Arc<RwLock<MountNamespace>> =
+        Arc::new(RwLock::new(MountNamespace::default()));
+}
+
+impl MountNamespace {
+    /// Mount `fs` on directory `covered`, hiding what was seen there.
+    /// `covered` may itself be the root of a mounted file system.
+    pub fn mount(&mut self, covered: Arc<dyn INode>, fs: Arc<dyn FileSystem>) {
+        self.mounts.push(Mount { covered, fs });
+    }
+
+    /// Unmount the file system whose root is `root`, as seen at its mount point.
+    /// Return false if `root` is not the root of a mounted file system.
+    pub fn umount(&mut self, root: &Arc<dyn INode>) -> Result<bool> {
+        for i in (0..self.mounts.len()).rev() {
+            if same_inode(&self.mounts[i].fs.root_inode(), root)? {
+                self.mounts.remove(i);
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// All mounted file systems, in mount order
+    pub fn filesystems(&self) -> Vec<Arc<dyn FileSystem>> {
+        self.mounts.iter().map(|mount| mount.fs.clone()).collect()
+    }
+
+    /// What is seen at directory `dir`: the root of the file system mounted on it if any
+    pub fn cover(&self, dir: Arc<dyn INode>) -> Result<Arc<dyn INode>> {
+        let mut dir = dir;
+        // a later mount at the same point covers the root of the earlier one
+        for mount in self.mounts.iter() {
+            if same_inode(&mount.covered, &dir)? {
+                dir = mount.fs.root_inode();
+            }
+        }
+        Ok(dir)
+    }
+
+    /// The directory covered by `root` if it is the root of a mounted file system
+    pub fn covered_by(&self, root: &Arc<dyn INode>) -> Result<Option<Arc<dyn INode>>> {
+        for mount in self.mounts.iter().rev() {
+            if same_inode(&mount.fs.root_inode(), root)? {
+                return Ok(Some(mount.covered.clone()));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Normalize absolute `path`, resolving `.` and `..` lexically
+pub fn normalize(path: &str) -> String {
+    let mut segs: Vec<&str> = Vec::new();
+    for seg in path.split('/') {
+        match seg {
+            "" | "." => {}
+            ".." => {
+                segs.pop();
+            }
+            _ => segs.push(seg),
+        }
+    }
+    let mut res = String::new();
+    for seg in segs {
+        res.push('/');
+        res.push_str(seg);
+    }
+    if res.is_empty() {
+        res.push('/');
+    }
+    res
+}
@@ -0,0 +1,828 @@
+//! Per-inode page cache shared by file I/O and file-backed mmap
+//!
+//! Every page of a regular file lives in at most one physical frame, which is
+//! used both by `FileHandle::read_at/write_at` and by `MAP_SHARED` mappings.
+//! Dirty pages are written back on `fsync`/`msync`/close, or when evicted.
+//!
+//! The cache lock is never held across disk I/O: a page is pinned while it is
+//! loaded, copied or written back, and pinned pages are not evicted.
+//!
+//! Pages are loaded and written back `uncached` by the buffer cache,
+//! so that file data is not kept in memory twice.
+
+use super::buffer_cache::uncached;
+use super::fs_id;
+use super::memfd::WritableMap;
+use super::pseudo::Pseudo;
+use crate::memory::{phys_to_virt, GlobalFrameAlloc};
+use crate::sync::SpinNoIrqLock as Mutex;
+use alloc::{boxed::Box, collections::BTreeMap, sync::Arc, vec::Vec};
+use core::slice;
+use rcore_fs::vfs::{FileSystem, FileType, FsError, INode, Metadata, Result};
+use rcore_memory::memory_set::handler::{FrameAllocator, MemoryHandler, SyncJob};
+use rcore_memory::memory_set::MemoryAttr;
+use rcore_memory::paging::PageTable;
+use rcore_memory::{PhysAddr, VirtAddr, PAGE_SIZE};
+
+/// Max number of cached pages before LRU eviction kicks in
+const PAGE_CACHE_MAX_PAGES: usize = 4096;
+/// Readahead window in pages when sequential reads are detected,
+/// doubled on each following sequential read up to the max
+const READAHEAD_MIN_PAGES: usize = 4;
+const READAHEAD_MAX_PAGES: usize = 32;
+
+/// Identify an inode by (file system, inode number).
+/// The same inode may be wrapped by different `Arc`s (e.g. MountFS),
+/// and the device number in the metadata is 0 for most file systems.
+pub type InodeKey = (usize, usize);
+
+struct CachedPage {
+    frame: usize,
+    dirty: bool,
+    /// Number of in-flight loads, copies and write-backs using this frame
+    pins: usize,
+    /// Number of page table entries mapping this frame
+    maps: usize,
+    /// LRU timestamp, the key in `PageCache::lru` while not in use
+    last_access: usize,
+}
+
+impl CachedPage {
+    fn in_use(&self) -> bool {
+        self.pins + self.maps > 0
+    }
+}
+
+unsafe fn frame_data(frame: usize) -> &'static mut [u8] {
+    slice::from_raw_parts_mut(phys_to_virt(frame) as *mut u8, PAGE_SIZE)
+}
+
+struct FileCache {
+    inode: Arc<dyn INode>,
+    pages: BTreeMap<usize, CachedPage>,
+}
+
+struct PageCache {
+    files: BTreeMap<InodeKey, FileCache>,
+    /// Pages not in use by `last_access`, the least recently used first
+    lru: BTreeMap<usize, (InodeKey, usize)>,
+    total: usize,
+    tick: usize,
+}
+
+lazy_static! {
+    static ref PAGE_CACHE: Mutex<PageCache> = Mutex::new(PageCache::new());
+}
+
+pub fn key_of(inode: &Arc<dyn INode>, metadata: &Metadata) -> InodeKey {
+    (fs_id(&inode.fs()), metadata.inode)
+}
+
+/// Whether I/O on this inode should go through the page cache.
+/// Pseudo files are generated on each open, and belong to no file system.
+pub fn cacheable(inode: &Arc<dyn INode>) -> bool {
+    if inode.as_any_ref().is::<Pseudo>() {
+        return false;
+    }
+    match inode.metadata() {
+        Ok(metadata) => metadata.type_ == FileType::File,
+        Err(_) => false,
+    }
+}
+
+/// A dirty page taken for write-back, pinned meanwhile
+struct WriteBack {
+    inode: Arc<dyn INode>,
+    key: InodeKey,
+    index: usize,
+    frame: usize,
+}
+
+impl PageCache {
+    fn new() -> Self {
+        PageCache {
+            files: BTreeMap::new(),
+            lru: BTreeMap::new(),
+            total: 0,
+            tick: 0,
+        }
+    }
+
+    /// Pin page `index` of the file if it is cached, and return its frame.
+    /// With `map`, count a page table entry rather than a pin.
+    fn get(&mut self, key: InodeKey, index: usize, write: bool, map: bool) -> Option<usize> {
+        self.tick += 1;
+        let tick = self.tick;
+        let page = self.files.get_mut(&key)?.pages.get_mut(&index)?;
+        if !page.in_use() {
+            self.lru.remove(&page.last_access);
+        }
+        page.last_access = tick;
+        page.dirty |= write;
+        match map {
+            true => page.maps += 1,
+            false => page.pins += 1,
+        }
+        Some(page.frame)
+    }
+
+    /// Insert the page loaded into `frame` and pin it as `get` does.
+    /// If another one got in meanwhile, that is used instead,
+    /// and `frame` should be freed by the caller.
+    fn insert(
+        &mut self,
+        inode: &Arc<dyn INode>,
+        key: InodeKey,
+        index: usize,
+        frame: usize,
+        write: bool,
+        map: bool,
+    ) -> usize {
+        if let Some(frame) = self.get(key, index, write, map) {
+            return frame;
+        }
+        let file = self.files.entry(key).or_insert_with(|| FileCache {
+            inode: inode.clone(),
+            pages: BTreeMap::new(),
+        });
+        file.pages.insert(
+            index,
+            CachedPage {
+                frame,
+                dirty: write,
+                pins: !map as usize,
+                maps: map as usize,
+                last_access: self.tick,
+            },
+        );
+        self.total += 1;
+        frame
+    }
+
+    /// Undo `get` or `insert`, marking the page dirty if it was written to
+    fn put(&mut self, key: InodeKey, index: usize, write: bool, map: bool) {
+        let page = match self.files.get_mut(&key) {
+            Some(file) => match file.pages.get_mut(&index) {
+                Some(page) => page,
+                None => return,
+            },
+            None => return,
+        };
+        page.dirty |= write;
+        match map {
+            true => page.maps -= 1,
+            false => page.pins -= 1,
+        }
+        if !page.in_use() {
+            self.lru.insert(page.last_access, (key, index));
+        }
+    }
+
+    /// Take dirty pages of the file at `key` in `range` for write-back, see `write_back`
+    fn take_dirty(&mut self, key: InodeKey, range: (usize, usize), taken: &mut Vec<WriteBack>) {
+        let file = match self.files.get_mut(&key) {
+            Some(file) if range.0 < range.1 => file,
+            _ => return,
+        };
+        for (&index, page) in file.pages.range_mut(range.0..range.1) {
+            if !page.dirty {
+                continue;
+            }
+            if !page.in_use() {
+                self.lru.remove(&page.last_access);
+            }
+            page.pins += 1;
+            // pages mapped shared may be written through the page table at any time
+            page.dirty = page.maps > 0;
+            taken.push(WriteBack {
+                inode: file.inode.clone(),
+                key,
+                index,
+                frame: page.frame,
+            });
+        }
+    }
+
+    /// Remove the least recently used page which is not in use.
+    /// A clean one is returned to be freed, a dirty one is taken for write-back.
+    fn take_lru(&mut self) -> Option<core::result::Result<usize, WriteBack>> {
+        let (&tick, &(key, index)) = self.lru.iter().next()?;
+        self.lru.remove(&tick);
+        let file = self.files.get_mut(&key).unwrap();
+        let page = file.pages.get_mut(&index).unwrap();
+        if page.dirty {
+            page.pins += 1;
+            page.dirty = false;
+            return Some(Err(WriteBack {
+                inode: file.inode.clone(),
+                key,
+                index,
+                frame: page.frame,
+            }));
+        }
+        let page = file.pages.remove(&index).unwrap();
+        if file.pages.is_empty() {
+            self.files.remove(&key);
+        }
+        self.total -= 1;
+        Some(Ok(page.frame))
+    }
+
+    /// Remove up to `count` least recently used clean pages which are not in use,
+    /// and return their frames
+    fn take_clean(&mut self, count: usize) -> Vec<usize> {
+        let victims: Vec<_> = self
+            .lru
+            .iter()
+            .filter(|(_, (key, index))| !self.files[key].pages[index].dirty)
+            .map(|(&tick, &victim)| (tick, victim))
+            .take(count)
+            .collect();
+        let mut frames = Vec::new();
+        for (tick, (key, index)) in victims {
+            self.lru.remove(&tick);
+            let file = self.files.get_mut(&key).unwrap();
+            frames.push(file.pages.remove(&index).unwrap().frame);
+            if file.pages.is_empty() {
+                self.files.remove(&key);
+            }
+            self.total -= 1;
+        }
+        frames
+    }
+
+    /// Remove the clean pages of the file at `key` in `range` which are not in use,
+    /// and return their frames
+    fn take_range(&mut self, key: InodeKey, range: (usize, usize)) -> Vec<usize> {
+        let mut frames = Vec::new();
+        let file = match self.files.get_mut(&key) {
+            Some(file) if range.0 < range.1 => file,
+            _ => return frames,
+        };
+        let removed: Vec<usize> = file
+            .pages
+            .range(range.0..range.1)
+            .filter(|(_, page)| !page.in_use() && !page.dirty)
+            .map(|(&index, _)| index)
+            .collect();
+        for index in removed {
+            let page = file.pages.remove(&index).unwrap();
+            self.lru.remove(&page.last_access);
+            frames.push(page.frame);
+            self.total -= 1;
+        }
+        if file.pages.is_empty() {
+            self.files.remove(&key);
+        }
+        frames
+    }
+
+    /// Drop cached pages beyond `len` after the file is truncated,
+    /// and zero the tail of the last page.
+    fn truncate(&mut self, key: InodeKey, len: usize) -> Vec<usize> {
+        let mut frames = Vec::new();
+        let file = match self.files.get_mut(&key) {
+            Some(file) => file,
+            None => return frames,
+        };
+        let first = (len + PAGE_SIZE - 1) / PAGE_SIZE;
+        let removed: Vec<usize> = file
+            .pages
+            .range(first..)
+            .filter(|(_, page)| !page.in_use())
+            .map(|(&index, _)| index)
+            .collect();
+        for index in removed {
+            let page = file.pages.remove(&index).unwrap();
+            self.lru.remove(&page.last_access);
+            frames.push(page.frame);
+            self.total -= 1;
+        }
+        if len % PAGE_SIZE != 0 {
+            if let Some(page) = file.pages.get_mut(&(len / PAGE_SIZE)) {
+                let data = unsafe { frame_data(page.frame) };
+                data[len % PAGE_SIZE..].iter_mut().for_each(|x| *x = 0);
+            }
+        }
+        if file.pages.is_empty() {
+            self.files.remove(&key);
+        }
+        frames
+    }
+}
+
+/// Pin page `index` of `inode`, loading it from disk if absent, and return its frame.
+/// The caller must `unpin` it, or `unmap_frame` it if `map`.
+fn pin(
+    inode: &Arc<dyn INode>,
+    key: InodeKey,
+    index: usize,
+    write: bool,
+    map: bool,
+) -> Result<usize> {
+    if let Some(frame) = PAGE_CACHE.lock().get(key, index, write, map) {
+        return Ok(frame);
+    }
+    if cached_pages() >= PAGE_CACHE_MAX_PAGES {
+        evict(1);
+    }
+    let frame = GlobalFrameAlloc.alloc().ok_or(FsError::NoDeviceSpace)?;
+    let data = unsafe { frame_data(frame) };
+    let len = match uncached(|| inode.read_at(index * PAGE_SIZE, data)) {
+        Ok(len) => len,
+        Err(err) => {
+            GlobalFrameAlloc.dealloc(frame);
+            return Err(err);
+        }
+    };
+    data[len..].iter_mut().for_each(|x| *x = 0);
+    let cached = PAGE_CACHE
+        .lock()
+        .insert(inode, key, index, frame, write, map);
+    if cached != frame {
+        GlobalFrameAlloc.dealloc(frame);
+    }
+    Ok(cached)
+}
+
+fn unpin(key: InodeKey, index: usize, write: bool) {
+    PAGE_CACHE.lock().put(key, index, write, false);
+}
+
+/// Get the frame backing page `index` of `inode` for a shared mapping.
+/// The caller must call `unmap_frame` when the mapping goes away.
+pub fn map_frame(inode: &Arc<dyn INode>, index: usize, write: bool) -> Result<usize> {
+    let key = key_of(inode, &inode.metadata()?);
+    // we can not track writes through the page table, so assume the worst
+    pin(inode, key, index, write, true)
+}
+
+pub fn unmap_frame(inode: &Arc<dyn INode>, index: usize) {
+    if let Ok(metadata) = inode.metadata() {
+        PAGE_CACHE
+            .lock()
+            .put(key_of(inode, &metadata), index, false, true);
+    }
+}
+
+/// Write back pages taken by `PageCache::take_dirty` or `take_lru`, then unpin them.
+/// All are tried, and the first error is returned.
+fn write_back(pages: Vec<WriteBack>) -> Result<()> {
+    let mut res = Ok(());
+    for page in pages {
+        let offset = page.index * PAGE_SIZE;
+        let written = page.inode.metadata().and_then(|metadata| {
+            if offset >= metadata.size {
+                return Ok(0);
+            }
+            let len = (metadata.size - offset).min(PAGE_SIZE);
+            let data = unsafe { &frame_data(page.frame)[..len] };
+            uncached(|| page.inode.write_at(offset, data))
+        });
+        PAGE_CACHE
+            .lock()
+            .put(page.key, page.index, written.is_err(), false);
+        if let Err(err) = written {
+            warn!(
+                "page cache: failed to write back page {}: {:?}",
+                page.index, err
+            );
+            if res.is_ok() {
+                res = Err(err);
+            }
+        }
+    }
+    res
+}
+
+/// Write back all dirty pages of `inode`
+pub fn sync(inode: &Arc<dyn INode>) -> Result<()> {
+    sync_range(inode, 0, usize::MAX)
+}
+
+/// Write back dirty pages of `inode` covering bytes `[start, end)`
+pub fn sync_range(inode: &Arc<dyn INode>, start: usize, end: usize) -> Result<()> {
+    let key = key_of(inode, &inode.metadata()?);
+    let range = (
+        start / PAGE_SIZE,
+        end.saturating_add(PAGE_SIZE - 1) / PAGE_SIZE,
+    );
+    let mut taken = Vec::new();
+    PAGE_CACHE.lock().take_dirty(key, range, &mut taken);
+    write_back(taken)
+}
+
+/// Write back all dirty pages in the cache
+pub fn sync_all() -> Result<()> {
+    sync_files(|_| true)
+}
+
+/// Write back dirty pages of files in `fs`
+pub fn sync_fs(fs: &Arc<dyn FileSystem>) -> Result<()> {
+    let id = fs_id(fs);
+    sync_files(|key| key.0 == id)
+}
+
+fn sync_files(filter: impl Fn(&InodeKey) -> bool) -> Result<()> {
+    let mut taken = Vec::new();
+    let mut cache = PAGE_CACHE.lock();
+    let keys: Vec<InodeKey> = cache.files.keys().cloned().filter(filter).collect();
+    for key in keys {
+        cache.take_dirty(key, (0, usize::MAX), &mut taken);
+    }
+    drop(cache);
+    write_back(taken)
+}
+
+/// Write back and drop the pages of `inode` covering bytes `[start, end)`,
+/// as POSIX_FADV_DONTNEED. Pages in use stay.
+pub fn invalidate(inode: &Arc<dyn INode>, start: usize, end: usize) -> Result<()> {
+    sync_range(inode, start, end)?;
+    let key = key_of(inode, &inode.metadata()?);
+    let range = (
+        start / PAGE_SIZE,
+        end.saturating_add(PAGE_SIZE - 1) / PAGE_SIZE,
+    );
+    let frames = PAGE_CACHE.lock().take_range(key, range);
+    for frame in frames {
+        GlobalFrameAlloc.dealloc(frame);
+    }
+    Ok(())
+}
+
+/// Drop cached pages beyond `len` after `inode` is truncated,
+/// and zero the tail of the last page.
+pub fn truncate(inode: &Arc<dyn INode>, len: usize) {
+    let key = match inode.metadata() {
+        Ok(metadata) => key_of(inode, &metadata),
+        Err(_) => return,
+    };
+    let frames = PAGE_CACHE.lock().truncate(key, len);
+    for frame in frames {
+        GlobalFrameAlloc.dealloc(frame);
+    }
+}
+
+/// Evict up to `count` least recently used pages which are not in use,
+/// writing back dirty ones. Return the number of pages actually freed.
+pub fn evict(count: usize) -> usize {
+    let mut freed = 0;
+    while freed < count {
+        let victim = PAGE_CACHE.lock().take_lru();
+        match victim {
+            Some(Ok(frame)) => {
+                GlobalFrameAlloc.dealloc(frame);
+                freed += 1;
+            }
+            // it goes back to its place in the LRU list, clean unless the write failed
+            Some(Err(page)) => {
+                if write_back(alloc::vec![page]).is_err() {
+                    break;
+                }
+            }
+            None => break,
+        }
+    }
+    freed
+}
+
+/// Read from `inode` at `offset` through the page cache.
+///
+/// The cache lock is released while copying to `buf`, since `buf` may be
+/// user memory whose page fault handler needs the cache as well.
+pub fn read_at(inode: &Arc<dyn INode>, offset: usize, buf: &mut [u8]) -> Result<usize> {
+    let metadata = inode.metadata()?;
+    let key = key_of(inode, &metadata);
+    if offset >= metadata.size {
+        return Ok(0);
+    }
+    let end = (offset + buf.len()).min(metadata.size);
+    let mut pos = offset;
+    while pos < end {
+        let index = pos / PAGE_SIZE;
+        let page_offset = pos % PAGE_SIZE;
+        let len = (PAGE_SIZE - page_offset).min(end - pos);
+        let frame = pin(inode, key, index, false, false)?;
+        let data = unsafe { frame_data(frame) };
+        buf[pos - offset..pos - offset + len]
+            .copy_from_slice(&data[page_offset..page_offset + len]);
+        unpin(key, index, false);
+        pos += len;
+    }
+    Ok(end - offset)
+}
+
+/// Read from `inode` at `offset`, through the page cache if it is `cacheable`
+pub fn read_inode_at(inode: &Arc<dyn INode>, offset: usize, buf: &mut [u8]) -> Result<usize> {
+    match cacheable(inode) {
+        true => read_at(inode, offset, buf),
+        false => inode.read_at(offset, buf),
+    }
+}
+
+/// Write to `inode` at `offset` through the page cache.
+/// The data reaches the disk on `sync`, or when the page is evicted.
+pub fn write_at(inode: &Arc<dyn INode>, offset: usize, buf: &[u8]) -> Result<usize> {
+    let metadata = inode.metadata()?;
+    let key = key_of(inode, &metadata);
+    let end = offset + buf.len();
+    if end > metadata.size {
+        // extend the file first, so that later reads see the right size
+        inode.resize(end)?;
+    }
+    let mut pos = offset;
+    while pos < end {
+        let index = pos / PAGE_SIZE;
+        let page_offset = pos % PAGE_SIZE;
+        let len = (PAGE_SIZE - page_offset).min(end - pos);
+        let frame = pin(inode, key, index, true, false)?;
+        let data = unsafe { frame_data(frame) };
+        data[page_offset..page_offset + len]
+            .copy_from_slice(&buf[pos - offset..pos - offset + len]);
+        // dirty again, in case it was written back during the copy
+        unpin(key, index, true);
+        pos += len;
+    }
+    Ok(buf.len())
+}
+
+/// Access pattern advised by `posix_fadvise`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Advice {
+    Normal,
+    Sequential,
+    Random,
+}
+
+/// Readahead state of an open file description
+#[derive(Debug)]
+pub struct Readahead {
+    pub advice: Advice,
+    /// Offset where the next read is expected if reading sequentially
+    next: usize,
+    /// Current window in pages, 0 if not reading sequentially
+    window: usize,
+    /// First page not prefetched yet
+    ahead: usize,
+}
+
+impl Readahead {
+    pub fn new() -> Self {
+        Readahead {
+            advice: Advice::Normal,
+            next: 0,
+            window: 0,
+            ahead: 0,
+        }
+    }
+
+    /// Record a read of `len` bytes at `offset`,
+    /// return the range of pages to prefetch as (first page, count)
+    pub fn on_read(&mut self, offset: usize, len: usize) -> Option<(usize, usize)> {
+        let sequential = offset == self.next;
+        self.next = offset + len;
+        let max = match self.advice {
+            Advice::Random => return None,
+            Advice::Normal => READAHEAD_MAX_PAGES,
+            Advice::Sequential => READAHEAD_MAX_PAGES * 2,
+        };
+        if !sequential {
+            // random access, shrink the window
+            self.window /= 2;
+            self.ahead = 0;
+            if self.advice != Advice::Sequential {
+                return None;
+            }
+        }
+        self.window = (self.window * 2).max(READAHEAD_MIN_PAGES).min(max);
+        let first = (self.next + PAGE_SIZE - 1) / PAGE_SIZE;
+        let end = first + self.window;
+        let start = first.max(self.ahead);
+        if start >= end {
+            return None;
+        }
+        self.ahead = end;
+        Some((start, end - start))
+    }
+}
+
+/// Load page `index` of `inode` into the cache.
+/// Return false past the end of file, or if the cache is full.
+fn readahead_page(inode: &Arc<dyn INode>, index: usize) -> Result<bool> {
+    let metadata = inode.metadata()?;
+    let key = key_of(inode, &metadata);
+    // do not evict pages to make room for speculative ones
+    if index >= (metadata.size + PAGE_SIZE - 1) / PAGE_SIZE
+        || cached_pages() >= PAGE_CACHE_MAX_PAGES
+    {
+        return Ok(false);
+    }
+    pin(inode, key, index, false, false)?;
+    unpin(key, index, false);
+    Ok(true)
+}
+
+/// Prefetch `count` pages of `inode` from page `start` in the background,
+/// stopping at the end of file.
+///
+/// Disk I/O is synchronous, so the task yields after each page
+/// to let the reader and other tasks run in between.
+pub fn readahead_async(inode: Arc<dyn INode>, start: usize, count: usize) {
+    crate::process::spawn_task(async move {
+        for index in start..start.saturating_add(count) {
+            match readahead_page(&inode, index) {
+                Ok(true) => crate::process::yield_now().await,
+                Ok(false) => break,
+                Err(err) => {
+                    debug!("readahead failed: {:?}", err);
+                    break;
+                }
+            }
+        }
+    });
+}
+
+/// Prefetch the pages covering `len` bytes of `inode` from `offset` in the background
+pub fn prefetch(inode: Arc<dyn INode>, offset: usize, len: usize) {
+    let start = offset / PAGE_SIZE;
+    let end = offset.saturating_add(len).saturating_add(PAGE_SIZE - 1) / PAGE_SIZE;
+    readahead_async(inode, start, end - start);
+}
+
+/// Compare reading a file in 4 KB chunks from the start of it, as `cat` does,
+/// with and without readahead. The largest file in the root directory is read,
+/// with its pages evicted first.
+///
+/// The reader yields after each chunk, as a process returning to user mode would.
+#[cfg(feature = "profile")]
+pub fn benchmark() {
+    use super::{FileHandle, OpenOptions, ROOT_INODE};
+    use crate::arch::timer::timer_now;
+    use alloc::string::String;
+    let inode = (0..)
+        .map(|id| ROOT_INODE.get_entry(id))
+        .take_while(|name| name.is_ok())
+        .filter_map(|name| ROOT_INODE.lookup(&name.unwrap()).ok())
+        .filter(|inode| cacheable(inode))
+        .max_by_key(|inode| inode.metadata().map(|metadata| metadata.size).unwrap_or(0));
+    let inode = match inode {
+        Some(inode) => inode,
+        None => return,
+    };
+    crate::process::spawn_task(async move {
+        let size = inode.metadata().unwrap().size;
+        let mut buf = [0u8; PAGE_SIZE];
+        for &advice in [Advice::Random, Advice::Normal, Advice::Sequential].iter() {
+            evict(PAGE_CACHE_MAX_PAGES);
+            let options = OpenOptions {
+                read: true,
+                write: false,
+                append: false,
+                nonblock: false,
+            };
+            let file = FileHandle::new(inode.clone(), options, String::new(), false, false);
+            file.set_advice(advice);
+            let begin = timer_now();
+            let mut offset = 0;
+            loop {
+                match file.read_at(offset, &mut buf).await {
+                    Ok(0) | Err(_) => break,
+                    Ok(len) => offset += len,
+                }
+                crate::process::yield_now().await;
+            }
+            warn!(
+                "readahead benchmark: {} KB with {:?} advice in {} us",
+                size / 1024,
+                advice,
+                (timer_now() - begin).as_micros()
+            );
+        }
+    });
+}
+
+/// Number of pages in the page cache
+pub fn cached_pages() -> usize {
+    PAGE_CACHE.lock().total
+}
+
+/// Reclaim clean cached pages when physical memory is short.
+/// Called from the frame allocator, so it must neither block on the cache lock
+/// nor write back dirty pages.
+pub fn reclaim(count: usize) -> usize {
+    let frames = match PAGE_CACHE.try_lock() {
+        Some(mut cache) => cache.take_clean(count),
+        None => return 0,
+    };
+    for &frame in frames.iter() {
+        GlobalFrameAlloc.dealloc(frame);
+    }
+    frames.len()
+}
+
+/// Shared mapping of a file, backed directly by frames in the page cache.
+#[derive(Clone)]
+pub struct CachedFile {
+    pub inode: Arc<dyn INode>,
+    pub mem_start: usize,
+    pub file_start: usize,
+    /// Whether the file was opened for writing, like `VM_MAYWRITE` of Linux
+    pub may_write: bool,
+    /// Held if the file is a memfd and `may_write`, which keeps it from being sealed
+    /// against writes, so `may_write` holds for as long as the mapping
+    pub writable_map: Option<WritableMap>,
+}
+
+impl CachedFile {
+    fn page_index(&self, addr: VirtAddr) -> usize {
+        (addr - self.mem_start + self.file_start) / PAGE_SIZE
+    }
+}
+
+impl MemoryHandler for CachedFile {
+    fn box_clone(&self) -> Box<dyn MemoryHandler> {
+        Box::new(self.clone())
+    }
+
+    fn map(&self, pt: &mut dyn PageTable, addr: VirtAddr, attr: &MemoryAttr) {
+        let entry = pt.map(addr, 0);
+        entry.set_present(false);
+        attr.apply(entry);
+    }
+
+    fn unmap(&self, pt: &mut dyn PageTable, addr: VirtAddr) {
+        let entry = pt.get_entry(addr).expect("failed to get entry");
+        if entry.present() {
+            // the frame belongs to the page cache
+            unmap_frame(&self.inode, self.page_index(addr));
+        }
+        // PageTable::unmap requires page to be present
+        entry.set_present(true);
+        pt.unmap(addr);
+    }
+
+    fn unmap_keep(&self, pt: &mut dyn PageTable, addr: VirtAddr) -> Option<PhysAddr> {
+        let entry = pt.get_entry(addr).expect("failed to get entry");
+        let frame = match entry.present() {
+            true => Some(entry.target()),
+            false => None,
+        };
+        entry.set_present(true);
+        pt.unmap(addr);
+        frame
+    }
+
+    fn release(&self, addr: VirtAddr, _frame: PhysAddr) {
+        // the page may be evicted from now on
+        unmap_frame(&self.inode, self.page_index(addr));
+    }
+
+    fn clone_map(
+        &self,
+        pt: &mut dyn PageTable,
+        _src_pt: &mut dyn PageTable,
+        addr: VirtAddr,
+        attr: &MemoryAttr,
+    ) {
+        // the child shares the same cached frames, map them on demand
+        self.map(pt, addr, attr);
+    }
+
+    fn handle_page_fault(&self, pt: &mut dyn PageTable, addr: VirtAddr) -> bool {
+        let addr = addr & !(PAGE_SIZE - 1);
+        let entry = pt.get_entry(addr).expect("failed to get entry");
+        if entry.present() {
+            return false;
+        }
+        let execute = entry.execute();
+        let frame = match map_frame(&self.inode, self.page_index(addr), entry.writable()) {
+            Ok(frame) => frame,
+            Err(_) => return false,
+        };
+        entry.set_target(frame);
+        entry.set_present(true);
+        entry.update();
+        pt.flush_cache_copy_user(addr, addr + PAGE_SIZE, execute);
+        true
+    }
+
+    fn sync(&self, start: VirtAddr, end: VirtAddr) -> Option<SyncJob> {
+        let inode = self.inode.clone();
+        let start = start - self.mem_start + self.file_start;
+        let end = end - self.mem_start + self.file_start;
+        Some(Box::new(move || sync_range(&inode, start, end).is_ok()))
+    }
+
+    fn may_write(&self) -> bool {
+        self.may_write
+    }
+}
+
+impl core::fmt::Debug for CachedFile {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("CachedFile")
+            .field("mem_start", &self.mem_start)
+            .field("file_start", &self.file_start)
+            .finish()
+    }
+}
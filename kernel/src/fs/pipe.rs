@@ -1,10 +1,14 @@
 //! Implement INode for Pipe
+//!
+//! A FIFO has no data of its own on disk. All ends open on it share a pipe,
+//! which is discarded once they are all closed.
 
 use crate::sync::{Event, EventBus, SpinNoIrqLock as Mutex};
 use crate::syscall::SysError::EAGAIN;
 use alloc::boxed::Box;
-use alloc::collections::BTreeSet;
-use alloc::{collections::vec_deque::VecDeque, sync::Arc};
+use alloc::collections::vec_deque::VecDeque;
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::sync::{Arc, Weak};
 use core::any::Any;
 use core::cmp::min;
 use core::{
@@ -15,18 +19,50 @@ use core::{
 };
 use rcore_fs::vfs::FsError::Again;
 use rcore_fs::vfs::*;
+use rcore_memory::PAGE_SIZE;
+
+/// Capacity of a new pipe, as on Linux
+pub const PIPE_DEFAULT_SIZE: usize = 16 * PAGE_SIZE;
+/// Most capacity an unprivileged process may set, `pipe-max-size` of Linux
+pub const PIPE_MAX_SIZE: usize = 0x100000;
 
 #[derive(Clone, PartialEq)]
 pub enum PipeEnd {
     Read,
     Write,
+    /// Both ends, of a FIFO opened for reading and writing
+    Both,
+}
+
+impl PipeEnd {
+    fn readable(&self) -> bool {
+        *self != PipeEnd::Write
+    }
+
+    fn writable(&self) -> bool {
+        *self != PipeEnd::Read
+    }
+}
+
+/// Device and inode number of a FIFO
+type FifoKey = (usize, usize);
+
+lazy_static! {
+    /// Pipes of the FIFOs open now
+    static ref FIFOS: Mutex<BTreeMap<FifoKey, Weak<Mutex<PipeData>>>> =
+        Mutex::new(BTreeMap::new());
 }
 
 pub struct PipeData {
     buf: VecDeque<u8>,
+    /// Most bytes `buf` may hold, set by `F_SETPIPE_SZ`
+    capacity: usize,
     eventbus: EventBus,
-    /// number of pipe ends
-    end_cnt: i32,
+    /// number of open ends for reading and for writing
+    readers: usize,
+    writers: usize,
+    /// The FIFO of the pipe, if it is not anonymous
+    fifo: Option<FifoKey>,
 }
 
 #[derive(Clone)]
@@ -39,18 +75,41 @@ impl Drop for Pipe {
     fn drop(&mut self) {
         // pipe end closed
         let mut data = self.data.lock();
-        data.end_cnt -= 1;
-        data.eventbus.set(Event::CLOSED);
+        if self.direction.readable() {
+            data.readers -= 1;
+        }
+        if self.direction.writable() {
+            data.writers -= 1;
+        }
+        // wake the other ends even if closed before, as a FIFO can be opened again
+        data.eventbus.notify_all(Event::CLOSED);
+        let fifo = data.fifo;
+        drop(data);
+        if let Some(key) = fifo {
+            let mut fifos = FIFOS.lock();
+            let data = self.data.lock();
+            let current = fifos
+                .get(&key)
+                .map_or(false, |pipe| pipe.ptr_eq(&Arc::downgrade(&self.data)));
+            if current && data.readers == 0 && data.writers == 0 {
+                fifos.remove(&key);
+            }
+        }
     }
 }
 
 impl Pipe {
     /// Create a pair of INode: (read, write)
     pub fn create_pair() -> (Pipe, Pipe) {
+        let mut eventbus = EventBus::default();
+        eventbus.set(Event::WRITABLE);
         let inner = PipeData {
             buf: VecDeque::new(),
-            eventbus: EventBus::default(),
-            end_cnt: 2, // one read, one write
+            capacity: PIPE_DEFAULT_SIZE,
+            eventbus,
+            readers: 1,
+            writers: 1,
+            fifo: None,
         };
         let data = Arc::new(Mutex::new(inner));
         (
@@ -65,22 +124,162 @@ impl Pipe {
         )
     }
 
-    fn can_read(&self) -> bool {
-        if let PipeEnd::Read = self.direction {
-            // true
-            let data = self.data.lock();
-            data.buf.len() > 0 || data.end_cnt < 2 // other end closed
-        } else {
-            false
+    /// Open an end of the FIFO `key` for reading, writing or both,
+    /// sharing the pipe of the ends open on it already
+    pub fn open_fifo(key: FifoKey, read: bool, write: bool) -> Pipe {
+        let direction = match (read, write) {
+            (true, true) => PipeEnd::Both,
+            (false, true) => PipeEnd::Write,
+            _ => PipeEnd::Read,
+        };
+        let mut fifos = FIFOS.lock();
+        let data = match fifos.get(&key).and_then(Weak::upgrade) {
+            Some(data) => data,
+            None => {
+                let mut eventbus = EventBus::default();
+                eventbus.set(Event::WRITABLE);
+                let data = Arc::new(Mutex::new(PipeData {
+                    buf: VecDeque::new(),
+                    capacity: PIPE_DEFAULT_SIZE,
+                    eventbus,
+                    readers: 0,
+                    writers: 0,
+                    fifo: Some(key),
+                }));
+                fifos.insert(key, Arc::downgrade(&data));
+                data
+            }
+        };
+        let mut inner = data.lock();
+        if direction.readable() {
+            inner.readers += 1;
+        }
+        if direction.writable() {
+            inner.writers += 1;
         }
+        // wake the ends waiting in `wait_peer`
+        inner.eventbus.notify_all(Event::OPENED);
+        drop(inner);
+        Pipe { data, direction }
     }
 
-    fn can_write(&self) -> bool {
-        if let PipeEnd::Write = self.direction {
-            self.data.lock().end_cnt == 2
-        } else {
-            false
+    /// Whether an end of the other direction is open
+    pub fn has_peer(&self) -> bool {
+        self.peer_open(&self.data.lock())
+    }
+
+    fn peer_open(&self, data: &PipeData) -> bool {
+        match self.direction {
+            PipeEnd::Read => data.writers > 0,
+            PipeEnd::Write => data.readers > 0,
+            PipeEnd::Both => true,
+        }
+    }
+
+    /// Wait until an end of the other direction is opened, as `open` of a FIFO does
+    pub fn wait_peer(&self) -> impl Future<Output = ()> + '_ {
+        #[must_use = "future does nothing unless polled/`await`-ed"]
+        struct PeerFuture<'a> {
+            pipe: &'a Pipe,
+        };
+
+        impl<'a> Future for PeerFuture<'a> {
+            type Output = ();
+
+            fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+                let mut data = self.pipe.data.lock();
+                if self.pipe.peer_open(&data) {
+                    return Poll::Ready(());
+                }
+                let waker = cx.waker().clone();
+                data.eventbus.subscribe(Box::new(move |_| {
+                    waker.wake_by_ref();
+                    true
+                }));
+                Poll::Pending
+            }
+        }
+
+        PeerFuture { pipe: self }
+    }
+
+    /// Copy data at the front of the pipe to `buf` without consuming it
+    pub fn peek(&self, buf: &mut [u8]) -> Result<usize> {
+        if buf.len() == 0 || !self.direction.readable() {
+            return Ok(0);
+        }
+        let data = self.data.lock();
+        if data.buf.len() == 0 && data.writers > 0 {
+            return Err(Again);
         }
+        let len = min(buf.len(), data.buf.len());
+        for (dst, src) in buf.iter_mut().zip(data.buf.iter()) {
+            *dst = *src;
+        }
+        Ok(len)
+    }
+
+    /// Discard `len` bytes at the front of the pipe, as if they were read
+    pub fn consume(&self, len: usize) {
+        if self.direction.readable() {
+            Self::consume_locked(&mut self.data.lock(), len);
+        }
+    }
+
+    fn consume_locked(data: &mut PipeData, len: usize) {
+        let len = min(len, data.buf.len());
+        data.buf.drain(..len);
+        let empty = match data.buf.len() {
+            0 => Event::READABLE,
+            _ => Event::empty(),
+        };
+        data.eventbus.change(empty, Event::WRITABLE);
+    }
+
+    /// Bytes that can be written without blocking, or None if nobody can read them
+    pub fn room(&self) -> Option<usize> {
+        let data = self.data.lock();
+        match data.readers {
+            0 => None,
+            _ => Some(data.capacity - data.buf.len()),
+        }
+    }
+
+    /// Whether both are ends of the same pipe
+    pub fn same_pipe(&self, other: &Pipe) -> bool {
+        Arc::ptr_eq(&self.data, &other.data)
+    }
+
+    /// Bytes the pipe may hold
+    pub fn capacity(&self) -> usize {
+        self.data.lock().capacity
+    }
+
+    /// Let the pipe hold `size` bytes, failing if more are unread
+    pub fn set_capacity(&self, size: usize) -> Result<()> {
+        let mut data = self.data.lock();
+        if data.buf.len() > size {
+            return Err(FsError::Busy);
+        }
+        data.capacity = size;
+        if data.buf.len() < size {
+            data.eventbus.set(Event::WRITABLE);
+        }
+        Ok(())
+    }
+
+    fn can_read(&self, data: &PipeData) -> bool {
+        // at the end of file once all writers are closed
+        self.direction.readable() && (data.buf.len() > 0 || data.writers == 0)
+    }
+
+    fn can_write(&self, data: &PipeData) -> bool {
+        self.direction.writable() && data.buf.len() < data.capacity
+    }
+
+    /// Whether this is a write end whose readers are all closed, so a write fails at once
+    fn broken(&self, data: &PipeData) -> bool {
+        self.direction.writable() && data.readers == 0
     }
 }
 
@@ -89,18 +288,16 @@ impl INode for Pipe {
         if buf.len() == 0 {
             return Ok(0);
         }
-        if let PipeEnd::Read = self.direction {
+        if self.direction.readable() {
             let mut data = self.data.lock();
-            if data.buf.len() == 0 && data.end_cnt == 2 {
+            if data.buf.len() == 0 && data.writers > 0 {
                 Err(Again)
             } else {
                 let len = min(buf.len(), data.buf.len());
-                for i in 0..len {
-                    buf[i] = data.buf.pop_front().unwrap();
-                }
-                if data.buf.len() == 0 {
-                    data.eventbus.clear(Event::READABLE);
+                for (dst, src) in buf.iter_mut().zip(data.buf.iter()) {
+                    *dst = *src;
                 }
+                Self::consume_locked(&mut data, len);
                 Ok(len)
             }
         } else {
@@ -109,23 +306,34 @@ impl INode for Pipe {
     }
 
     fn write_at(&self, _offset: usize, buf: &[u8]) -> Result<usize> {
-        if let PipeEnd::Write = self.direction {
+        if self.direction.writable() {
             let mut data = self.data.lock();
-            for c in buf {
-                data.buf.push_back(*c);
+            if data.readers == 0 {
+                // there is no error for a broken pipe, `FileLike::async_write` makes it EPIPE
+                return Err(FsError::DeviceError);
+            }
+            let len = min(buf.len(), data.capacity - data.buf.len());
+            if len == 0 && buf.len() > 0 {
+                return Err(Again);
             }
-            data.eventbus.set(Event::READABLE);
-            Ok(buf.len())
+            data.buf.extend(&buf[..len]);
+            let full = match data.buf.len() {
+                n if n == data.capacity => Event::WRITABLE,
+                _ => Event::empty(),
+            };
+            data.eventbus.change(full, Event::READABLE);
+            Ok(len)
         } else {
             Ok(0)
         }
     }
 
     fn poll(&self) -> Result<PollStatus> {
+        let data = self.data.lock();
         Ok(PollStatus {
-            read: self.can_read(),
-            write: self.can_write(),
-            error: false,
+            read: self.can_read(&data),
+            write: self.can_write(&data),
+            error: self.broken(&data),
         })
     }
 
@@ -141,11 +349,16 @@ impl INode for Pipe {
             type Output = Result<PollStatus>;
 
             fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
-                if self.pipe.can_read() || self.pipe.can_write() {
+                // subscribe while locked, so no change is missed
+                let mut data = self.pipe.data.lock();
+                if self.pipe.can_read(&data)
+                    || self.pipe.can_write(&data)
+                    || self.pipe.broken(&data)
+                {
+                    drop(data);
                     return Poll::Ready(self.pipe.poll());
                 }
                 let waker = cx.waker().clone();
-                let mut data = self.pipe.data.lock();
                 data.eventbus.subscribe(Box::new({
                     move |_| {
                         waker.wake_by_ref();
@@ -0,0 +1,75 @@
+//! Device nodes made by `mknod`
+//!
+//! Our file systems keep no device number for a node, so the numbers of nodes
+//! made by `mknod` are kept in memory, by the device and inode number of the node.
+//! Opening such a node opens the device file of the driver with that number instead,
+//! i.e. the one in /dev.
+
+use alloc::{collections::BTreeMap, sync::Arc};
+use rcore_fs::vfs::*;
+use spin::RwLock;
+
+/// Device and inode number of a node
+type NodeKey = (usize, usize);
+
+lazy_static! {
+    /// Device files of drivers, by device number
+    static ref DEVICES: RwLock<BTreeMap<usize, Arc<dyn INode>>> = RwLock::new(BTreeMap::new());
+    /// Device numbers of nodes made by `mknod`
+    static ref DEVICE_NODES: RwLock<BTreeMap<NodeKey, usize>> = RwLock::new(BTreeMap::new());
+}
+
+/// Register the device files in `dir` by their device numbers
+pub fn register_devices(dir: &Arc<dyn INode>) {
+    let mut devices = DEVICES.write();
+    let mut i = 0;
+    while let Ok(name) = dir.get_entry(i) {
+        i += 1;
+        let inode = match dir.find(&name) {
+            Ok(inode) => inode,
+            Err(_) => continue,
+        };
+        match inode.metadata() {
+            Ok(metadata) if is_device(metadata.type_) => {
+                devices.insert(metadata.rdev, inode);
+            }
+            _ => {}
+        }
+    }
+}
+
+pub fn is_device(type_: FileType) -> bool {
+    type_ == FileType::CharDevice || type_ == FileType::BlockDevice
+}
+
+/// Make a device node `name` in `dir` for device number `rdev`
+pub fn make_device_node(
+    dir: &Arc<dyn INode>,
+    name: &str,
+    type_: FileType,
+    mode: u32,
+    rdev: usize,
+) -> Result<Arc<dyn INode>> {
+    let inode = dir.create(name, type_, mode)?;
+    let metadata = inode.metadata()?;
+    DEVICE_NODES
+        .write()
+        .insert((metadata.dev, metadata.inode), rdev);
+    Ok(inode)
+}
+
+/// The device number of a node made by `mknod`, none for other files
+pub fn node_rdev(metadata: &Metadata) -> Option<usize> {
+    if !is_device(metadata.type_) {
+        return None;
+    }
+    DEVICE_NODES
+        .read()
+        .get(&(metadata.dev, metadata.inode))
+        .cloned()
+}
+
+/// The device file of the driver with device number `rdev`
+pub fn find_device(rdev: usize) -> Option<Arc<dyn INode>> {
+    DEVICES.read().get(&rdev).cloned()
+}
@@ -13,6 +13,16 @@ lazy_static! {
         RwLock::new(BTreeMap::new());
 }
 
+/// Frames held by System V shared memory segments
+pub fn shm_frames() -> usize {
+    KEY2SHM
+        .read()
+        .values()
+        .filter_map(|guard| guard.upgrade())
+        .map(|guard| guard.lock().frames())
+        .sum()
+}
+
 #[derive(Clone)]
 pub struct ShmIdentifier {
     pub addr: VirtAddr,
@@ -52,10 +52,12 @@ pub mod lkm;
 pub mod memory;
 pub mod net;
 pub mod process;
+pub mod rand;
 #[cfg(feature = "hypervisor")]
 pub mod rvm;
 pub mod shell;
 pub mod signal;
+pub mod slab;
 pub mod sync;
 pub mod syscall;
 pub mod trap;
@@ -78,17 +80,22 @@ pub mod arch;
 pub mod arch;
 
 pub fn kmain() -> ! {
+    process::set_cpu_online();
     loop {
-        executor::run_until_idle();
-        arch::interrupt::wait_for_interrupt();
+        let wakeups = process::wakeups();
+        process::run_until_idle();
+        process::idle(wakeups);
     }
 }
 
-/// Global heap allocator
+/// Global allocator, serving small objects from slab caches
 ///
-/// Available after `memory::init()`.
+/// Slabs are available after `memory::init()`, and the heap before.
 ///
 /// It should be defined in memory mod, but in Rust `global_allocator` must be in root mod.
 #[global_allocator]
+static GLOBAL_ALLOCATOR: slab::SlabAllocator = slab::SlabAllocator;
+
+/// Heap allocator for sizes not served by slab caches
 static HEAP_ALLOCATOR: LockedHeapWithRescue =
     LockedHeapWithRescue::new(crate::memory::enlarge_heap);
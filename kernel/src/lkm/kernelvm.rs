@@ -6,8 +6,9 @@ use alloc::vec::*;
 use core::mem::ManuallyDrop;
 use core::ops::DerefMut;
 use lazy_static::lazy_static;
-use rcore_memory::memory_set::handler::{ByFrame, MemoryHandler};
+use rcore_memory::memory_set::handler::{ByFrame, FrameAllocator, MemoryHandler};
 use rcore_memory::memory_set::MemoryAttr;
+use rcore_memory::paging::{Entry, PageTable};
 use rcore_memory::{Page, PAGE_SIZE};
 
 ///Allocated virtual memory space by pages. returns some vaddr.
@@ -22,27 +23,37 @@ pub trait MemorySpaceManager {
     }
 }
 
-/// The most simple strategy: no free and allocate ahead.
+/// The most simple strategy: allocate ahead, and reuse freed ranges of the same size,
+/// which is enough for kernel stacks.
 /// TODO: A better allocation strategy required.
 pub struct LinearManager {
     last_page: usize,
+    /// Freed ranges as start and size
+    freed: Vec<(usize, usize)>,
 }
 use crate::arch::consts::KSEG2_START;
 
 impl MemorySpaceManager for LinearManager {
     fn new() -> LinearManager {
-        LinearManager { last_page: 0 }
+        LinearManager {
+            last_page: 0,
+            freed: Vec::new(),
+        }
     }
     fn alloc(&mut self, size: usize) -> Option<(usize, usize)> {
         let required_pages = (size + PAGE_SIZE - 1) / PAGE_SIZE;
+        let size = required_pages * PAGE_SIZE;
+        if let Some(i) = self.freed.iter().position(|&(_, freed)| freed == size) {
+            return Some(self.freed.swap_remove(i));
+        }
 
         let current = self.last_page * PAGE_SIZE + KSEG2_START;
         self.last_page += required_pages;
         Some((current, required_pages * PAGE_SIZE))
     }
 
-    fn free(&mut self, (_addr, _size): (usize, usize)) {
-        //Do nothing.
+    fn free(&mut self, target: (usize, usize)) {
+        self.freed.push(target);
     }
 }
 
@@ -95,9 +106,24 @@ impl VirtualSpace {
 
 impl Drop for VirtualSpace {
     fn drop(&mut self) {
+        let mut frames = Vec::new();
         for v in self.areas.iter_mut() {
-            v.unmap(self.allocator, &mut self.page_allocator);
+            v.unmap(self.allocator, &mut frames);
+        }
+        // other CPUs may have cached the mappings, which are global,
+        // so neither the frames nor the range may be reused before they forget them
+        let cpu_id = crate::arch::cpu::id();
+        let cpus: Vec<usize> = crate::process::online_cpus()
+            .into_iter()
+            .filter(|&i| i != cpu_id)
+            .collect();
+        if !cpus.is_empty() {
+            crate::arch::memory::flush_tlb_on(&cpus, self.start, self.start + self.size);
+        }
+        for frame in frames {
+            GlobalFrameAlloc.dealloc(frame);
         }
+        self.allocator.lock().free((self.start, self.size));
     }
 }
 
@@ -131,11 +157,16 @@ impl VirtualArea {
             _attr: attr.clone(),
         }
     }
-    pub fn unmap(&mut self, allocator: &LockedVMM, parent: &mut ByFrame<GlobalFrameAlloc>) {
+    /// Unmap the area, collecting its frames to `frames` to be freed by the caller
+    pub fn unmap(&mut self, allocator: &LockedVMM, frames: &mut Vec<usize>) {
         let lock = allocator.lock();
         let mut active_pt = lock.kernel_table();
         for p in Page::range_of(self.start, self.end) {
-            parent.unmap(active_pt.deref_mut(), p.start_address());
+            let addr = p.start_address();
+            if let Some(entry) = active_pt.get_entry(addr) {
+                frames.push(entry.target());
+            }
+            active_pt.unmap(addr);
         }
     }
 }
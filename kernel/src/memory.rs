@@ -1,18 +1,25 @@
 //! Define the FrameAllocator for physical memory
 
 use super::HEAP_ALLOCATOR;
-use crate::consts::{KERNEL_OFFSET, MEMORY_OFFSET, PHYSICAL_MEMORY_OFFSET};
-use crate::process::current_thread;
+use crate::consts::{
+    KERNEL_OFFSET, MAX_CPU_NUM, MEMORY_OFFSET, PHYSICAL_MEMORY_OFFSET, USER_SPACE_END,
+};
+#[cfg(target_arch = "x86_64")]
+use crate::lkm::kernelvm::{VirtualSpace, KERNELVM_MANAGER};
+use crate::process::Thread;
 use crate::sync::SpinNoIrqLock;
+use alloc::{collections::BTreeSet, format, string::String};
 use bitmap_allocator::BitAlloc;
 use buddy_system_allocator::Heap;
 use core::mem;
 use core::mem::size_of;
+use core::ops::Range;
+use core::sync::atomic::{AtomicUsize, Ordering};
 use log::*;
 use rcore_memory::*;
 
 pub use crate::arch::paging::*;
-pub use rcore_memory::memory_set::{handler::*, MemoryArea, MemoryAttr};
+pub use rcore_memory::memory_set::{handler::*, MemoryArea, MemoryAttr, PageFaultCause};
 pub type MemorySet = rcore_memory::memory_set::MemorySet<PageTableImpl>;
 
 // x86_64 support up to 1T memory
@@ -30,6 +37,58 @@ pub type FrameAlloc = bitmap_allocator::BitAlloc1M;
 
 pub static FRAME_ALLOCATOR: SpinNoIrqLock<FrameAlloc> = SpinNoIrqLock::new(FrameAlloc::DEFAULT);
 
+/// Number of frames given to `FRAME_ALLOCATOR`
+static FRAMES_TOTAL: AtomicUsize = AtomicUsize::new(0);
+/// Number of frames allocated from `FRAME_ALLOCATOR`
+static FRAMES_ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+/// Snapshot of physical frame usage
+#[derive(Debug, Clone, Copy)]
+pub struct FrameStats {
+    pub total: usize,
+    pub allocated: usize,
+    pub free: usize,
+}
+
+/// Get physical frame usage without locking the allocator
+pub fn frame_stats() -> FrameStats {
+    let total = FRAMES_TOTAL.load(Ordering::Relaxed);
+    let allocated = FRAMES_ALLOCATED.load(Ordering::Relaxed);
+    FrameStats {
+        total,
+        allocated,
+        free: total.saturating_sub(allocated),
+    }
+}
+
+/// Content of `/proc/meminfo`
+pub fn meminfo() -> String {
+    let stats = frame_stats();
+    let cached = crate::fs::page_cache::cached_pages();
+    let buffers = crate::fs::buffer_cache::cached_buffers();
+    let kb = |frames: usize| frames * PAGE_SIZE / 1024;
+    format!(
+        "MemTotal:       {:8} kB\n\
+         MemFree:        {:8} kB\n\
+         MemAvailable:   {:8} kB\n\
+         Buffers:        {:8} kB\n\
+         Cached:         {:8} kB\n\
+         Shmem:          {:8} kB\n",
+        kb(stats.total),
+        kb(stats.free),
+        kb(stats.free + cached + buffers),
+        kb(buffers),
+        kb(cached),
+        kb(crate::ipc::shm_frames()),
+    )
+}
+
+/// Give frames with numbers in `range` to the allocator, used at boot
+pub fn insert_frames(ba: &mut FrameAlloc, range: Range<usize>) {
+    FRAMES_TOTAL.fetch_add(range.len(), Ordering::Relaxed);
+    ba.insert(range);
+}
+
 /// Convert physical address to virtual address
 #[inline]
 #[cfg(not(mipsel))]
@@ -66,10 +125,24 @@ pub struct GlobalFrameAlloc;
 impl FrameAllocator for GlobalFrameAlloc {
     fn alloc(&self) -> Option<usize> {
         // get the real address of the alloc frame
-        let ret = FRAME_ALLOCATOR
+        let mut ret = FRAME_ALLOCATOR
             .lock()
             .alloc()
             .map(|id| id * PAGE_SIZE + MEMORY_OFFSET);
+        if ret.is_none()
+            && (crate::process::reclaim_freeable(1) > 0
+                || crate::fs::page_cache::reclaim(1) > 0
+                || crate::fs::buffer_cache::reclaim(1) > 0)
+        {
+            // retry after dropping freeable user pages, or evicting from the page cache or buffer cache
+            ret = FRAME_ALLOCATOR
+                .lock()
+                .alloc()
+                .map(|id| id * PAGE_SIZE + MEMORY_OFFSET);
+        }
+        if ret.is_some() {
+            FRAMES_ALLOCATED.fetch_add(1, Ordering::Relaxed);
+        }
         trace!("Allocate frame: {:x?}", ret);
         ret
         // TODO: try to swap out when alloc failed
@@ -80,6 +153,9 @@ impl FrameAllocator for GlobalFrameAlloc {
             .lock()
             .alloc_contiguous(size, align_log2)
             .map(|id| id * PAGE_SIZE + MEMORY_OFFSET);
+        if ret.is_some() {
+            FRAMES_ALLOCATED.fetch_add(size, Ordering::Relaxed);
+        }
         trace!("Allocate frame: {:x?}", ret);
         ret
         // TODO: try to swap out when alloc failed
@@ -89,6 +165,7 @@ impl FrameAllocator for GlobalFrameAlloc {
         FRAME_ALLOCATOR
             .lock()
             .dealloc((target - MEMORY_OFFSET) / PAGE_SIZE);
+        FRAMES_ALLOCATED.fetch_sub(1, Ordering::Relaxed);
     }
 }
 
@@ -102,9 +179,49 @@ pub fn alloc_frame_contiguous(size: usize, align_log2: usize) -> Option<usize> {
     GlobalFrameAlloc.alloc_contiguous(size, align_log2)
 }
 
+/// Kernel stack of a thread.
+///
+/// On x86_64 it is mapped in kernel virtual space (kseg2), which every page table shares,
+/// with an unmapped guard page below it to catch overflow. The fault is reported
+/// by the double fault handler, which has a stack of its own.
+/// Its pages are mapped eagerly, since a fault on the stack in use can't be handled on it.
+/// Other architectures do not share kseg2 with user page tables, and would handle
+/// the fault on the overflowed stack anyway, so their stacks are on the heap.
+#[cfg(target_arch = "x86_64")]
+pub struct KernelStack(VirtualSpace);
+#[cfg(not(target_arch = "x86_64"))]
 pub struct KernelStack(usize);
 const KSTACK_SIZE: usize = 0x4000; //16KB
 
+lazy_static! {
+    /// Guard pages of all kernel stacks
+    static ref KSTACK_GUARDS: SpinNoIrqLock<BTreeSet<usize>> = SpinNoIrqLock::new(BTreeSet::new());
+}
+
+#[cfg(target_arch = "x86_64")]
+impl KernelStack {
+    pub fn new() -> Self {
+        let mut space = VirtualSpace::new(&KERNELVM_MANAGER, PAGE_SIZE + KSTACK_SIZE)
+            .expect("failed to allocate kernel stack");
+        let bottom = space.start() + PAGE_SIZE;
+        space.add_area(bottom, bottom + KSTACK_SIZE, &MemoryAttr::default());
+        KSTACK_GUARDS.lock().insert(space.start());
+        KernelStack(space)
+    }
+    pub fn top(&self) -> usize {
+        self.0.start() + PAGE_SIZE + KSTACK_SIZE
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+impl Drop for KernelStack {
+    fn drop(&mut self) {
+        // the stack is unmapped, and its range recycled, when the space is dropped
+        KSTACK_GUARDS.lock().remove(&self.0.start());
+    }
+}
+
+#[cfg(not(target_arch = "x86_64"))]
 impl KernelStack {
     pub fn new() -> Self {
         use alloc::alloc::{alloc, Layout};
@@ -117,6 +234,7 @@ impl KernelStack {
     }
 }
 
+#[cfg(not(target_arch = "x86_64"))]
 impl Drop for KernelStack {
     fn drop(&mut self) {
         use alloc::alloc::{dealloc, Layout};
@@ -129,14 +247,86 @@ impl Drop for KernelStack {
     }
 }
 
-/// Handle page fault at `addr`.
+/// Panic with the current thread if `addr` is in the guard page of a kernel stack
+pub fn check_kstack_overflow(addr: usize) {
+    if KSTACK_GUARDS.lock().contains(&(addr & !(PAGE_SIZE - 1))) {
+        let tid = Thread::try_current().map_or(0, |thread| thread.tid);
+        panic!("kernel stack overflow in tid {} @ {:#x}", tid, addr);
+    }
+}
+
+/// Memory set activated by `with_vm` on each CPU, 0 if none
+static mut FOREIGN_VM: [usize; MAX_CPU_NUM] = [0; MAX_CPU_NUM];
+
+/// Run `f` with `vm` activated, which is not the memory set of the current thread,
+/// e.g. to fill the stack of a new process.
+/// Page faults on user addresses are handled in `vm` meanwhile.
+pub fn with_vm(vm: &mut MemorySet, f: impl FnOnce()) {
+    let cpu_id = crate::arch::cpu::id();
+    let vm = vm as *mut MemorySet;
+    unsafe {
+        let old = FOREIGN_VM[cpu_id];
+        FOREIGN_VM[cpu_id] = vm as usize;
+        (*vm).with(f);
+        FOREIGN_VM[cpu_id] = old;
+    }
+}
+
+/// Handle page fault from kernel mode at `addr`.
+/// Faults on user addresses, e.g. when a syscall touches a lazily mapped page,
+/// are handled in the active memory set.
 /// Return true to continue, false to halt.
-pub fn handle_page_fault(addr: usize) -> bool {
-    debug!("page fault from kernel @ {:#x}", addr);
+pub fn handle_page_fault(addr: usize, cause: PageFaultCause) -> bool {
+    debug!("page fault from kernel @ {:#x}, {:?}", addr, cause);
+
+    check_kstack_overflow(addr);
+
+    if addr >= USER_SPACE_END {
+        // a genuine fault of the kernel
+        return false;
+    }
 
-    let thread = current_thread().unwrap();
-    let mut lock = thread.vm.lock();
-    lock.handle_page_fault(addr)
+    let foreign = unsafe { FOREIGN_VM[crate::arch::cpu::id()] };
+    if foreign != 0 {
+        // it is borrowed by `with_vm` on this CPU
+        let vm = unsafe { &mut *(foreign as *mut MemorySet) };
+        return vm.handle_page_fault(addr, cause);
+    }
+
+    match Thread::try_current() {
+        Some(thread) => thread.lock_vm().handle_page_fault(addr, cause),
+        None => false,
+    }
+}
+
+/// Most memory ranges given to `HEAP_ALLOCATOR`
+const MAX_HEAP_REGIONS: usize = 256;
+
+/// Memory ranges given to `HEAP_ALLOCATOR`, as start and end.
+/// Only `init_heap` and `enlarge_heap` add to it, with the heap locked.
+static HEAP_REGIONS: [(AtomicUsize, AtomicUsize); MAX_HEAP_REGIONS] =
+    [NO_HEAP_REGION; MAX_HEAP_REGIONS];
+const NO_HEAP_REGION: (AtomicUsize, AtomicUsize) = (AtomicUsize::new(0), AtomicUsize::new(0));
+static HEAP_REGION_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Record `[start, start + len)` as heap memory, or return false if too many are recorded
+fn add_heap_region(start: usize, len: usize) -> bool {
+    let i = HEAP_REGION_COUNT.load(Ordering::Acquire);
+    if i == MAX_HEAP_REGIONS {
+        return false;
+    }
+    HEAP_REGIONS[i].0.store(start, Ordering::Relaxed);
+    HEAP_REGIONS[i].1.store(start + len, Ordering::Relaxed);
+    HEAP_REGION_COUNT.store(i + 1, Ordering::Release);
+    true
+}
+
+/// Whether `addr` is in memory of `HEAP_ALLOCATOR`, rather than in a slab
+pub fn in_heap(addr: usize) -> bool {
+    let count = HEAP_REGION_COUNT.load(Ordering::Acquire);
+    HEAP_REGIONS[..count].iter().any(|(start, end)| {
+        (start.load(Ordering::Relaxed)..end.load(Ordering::Relaxed)).contains(&addr)
+    })
 }
 
 pub fn init_heap() {
@@ -145,9 +335,9 @@ pub fn init_heap() {
     const HEAP_BLOCK: usize = KERNEL_HEAP_SIZE / MACHINE_ALIGN;
     static mut HEAP: [usize; HEAP_BLOCK] = [0; HEAP_BLOCK];
     unsafe {
-        HEAP_ALLOCATOR
-            .lock()
-            .init(HEAP.as_ptr() as usize, HEAP_BLOCK * MACHINE_ALIGN);
+        let mut heap = HEAP_ALLOCATOR.lock();
+        add_heap_region(HEAP.as_ptr() as usize, HEAP_BLOCK * MACHINE_ALIGN);
+        heap.init(HEAP.as_ptr() as usize, HEAP_BLOCK * MACHINE_ALIGN);
     }
 }
 
@@ -172,6 +362,14 @@ pub fn enlarge_heap(heap: &mut Heap) {
         addr_len += 1;
     }
     for (addr, len) in addrs[..addr_len].into_iter() {
+        // the slab allocator must be able to tell heap objects from its own
+        if !add_heap_region(*addr, *len) {
+            warn!("too many heap regions, giving {:#X} {:#X} back", addr, len);
+            for va in (*addr..*addr + *len).step_by(PAGE_SIZE) {
+                dealloc_frame(va - va_offset);
+            }
+            continue;
+        }
         info!("Adding {:#X} {:#X} to heap", addr, len);
         unsafe {
             heap.init(*addr, *len);
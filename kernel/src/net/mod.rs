@@ -1,5 +1,13 @@
 mod structs;
 mod test;
+mod unix;
 
 pub use self::structs::*;
 pub use self::test::server;
+pub use self::unix::*;
+
+/// Bring up the loopback interface.
+/// Called after hardware interfaces are probed, so they keep their indexes.
+pub fn init() {
+    crate::drivers::net::loopback::init();
+}
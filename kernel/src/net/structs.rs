@@ -1,5 +1,6 @@
 use crate::arch::rand;
 use crate::drivers::{NET_DRIVERS, SOCKET_ACTIVITY};
+use crate::fs::FileLike;
 use crate::sync::SpinNoIrqLock as Mutex;
 use crate::syscall::*;
 use crate::util;
@@ -11,8 +12,10 @@ use bitflags::*;
 use core::cmp::min;
 use core::mem::size_of;
 use core::slice;
+use core::sync::atomic::{AtomicBool, Ordering};
 
 use smoltcp::socket::*;
+use smoltcp::time::Duration;
 use smoltcp::wire::*;
 
 #[derive(Clone, Debug)]
@@ -48,12 +51,23 @@ pub enum Endpoint {
     Ip(IpEndpoint),
     LinkLevel(LinkLevelEndpoint),
     Netlink(NetlinkEndpoint),
+    /// The unnamed peer of a socket pair
+    Unix,
 }
 
 /// Common methods that a socket must have
 pub trait Socket: Send + Sync + Debug {
     fn read(&self, data: &mut [u8]) -> (SysResult, Endpoint);
     fn write(&self, data: &[u8], sendto_endpoint: Option<Endpoint>) -> SysResult;
+    /// Read like `read`, also taking the files passed with the data (SCM_RIGHTS)
+    fn read_rights(&self, data: &mut [u8]) -> (SysResult, Endpoint, Vec<FileLike>) {
+        let (result, endpoint) = self.read(data);
+        (result, endpoint, Vec::new())
+    }
+    /// Write `data` to the peer, passing `files` along (SCM_RIGHTS)
+    fn write_rights(&self, _data: &[u8], _files: Vec<FileLike>) -> SysResult {
+        Err(SysError::EINVAL)
+    }
     fn poll(&self) -> (bool, bool, bool); // (in, out, err)
     fn connect(&mut self, endpoint: Endpoint) -> SysResult;
     fn bind(&mut self, _endpoint: Endpoint) -> SysResult {
@@ -62,7 +76,8 @@ pub trait Socket: Send + Sync + Debug {
     fn listen(&mut self) -> SysResult {
         Err(SysError::EINVAL)
     }
-    fn shutdown(&self) -> SysResult {
+    /// Shut down part of a full-duplex connection, `how` is one of `SHUT_*`
+    fn shutdown(&mut self, _how: usize) -> SysResult {
         Err(SysError::EINVAL)
     }
     fn accept(&mut self) -> Result<(Box<dyn Socket>, Endpoint), SysError> {
@@ -78,10 +93,21 @@ pub trait Socket: Send + Sync + Debug {
         warn!("setsockopt is unimplemented");
         Ok(0)
     }
+    /// Store the option into `data`, return its length
+    fn getsockopt(&mut self, _level: usize, _opt: usize, _data: &mut [u8]) -> SysResult {
+        Err(SysError::ENOPROTOOPT)
+    }
     fn ioctl(&mut self, _request: usize, _arg1: usize, _arg2: usize, _arg3: usize) -> SysResult {
         warn!("ioctl is unimplemented for this socket");
         Ok(0)
     }
+    /// Whether operations fail with EAGAIN instead of blocking (O_NONBLOCK)
+    fn nonblocking(&self) -> bool {
+        false
+    }
+    fn set_nonblocking(&mut self, _nonblock: bool) {
+        warn!("nonblocking mode is unimplemented for this socket");
+    }
     fn box_clone(&self) -> Box<dyn Socket>;
 }
 
@@ -100,23 +126,51 @@ lazy_static! {
         Mutex::new(SocketSet::new(vec![]));
 }
 
+/// Options set by `setsockopt`
+#[derive(Debug, Clone)]
+pub struct SocketOptions {
+    reuse_addr: bool,
+    keep_alive: bool,
+    /// Only recorded, smoltcp never delays small segments
+    no_delay: bool,
+    send_buf: usize,
+    recv_buf: usize,
+    /// Errno of a pending error, reported and cleared by SO_ERROR
+    error: usize,
+    /// Set by O_NONBLOCK
+    nonblock: bool,
+}
+
 #[derive(Debug, Clone)]
 pub struct TcpSocketState {
-    handle: GlobalSocketHandle,
+    /// Replaced by accept and by resizing the buffers, for all fds of the socket
+    handle: Arc<Mutex<GlobalSocketHandle>>,
     local_endpoint: Option<IpEndpoint>, // save local endpoint for bind()
     is_listening: bool,
+    /// Shared by all fds of the socket, like `handle`
+    options: Arc<Mutex<SocketOptions>>,
+    /// Shut down for reading, further data is discarded
+    shut_rd: bool,
+    /// Shut down for writing, a FIN has been sent
+    shut_wr: bool,
+    /// A non-blocking connect is in progress or its result is not reported yet,
+    /// to whichever fd of the socket asks first
+    connecting: Arc<AtomicBool>,
 }
 
 #[derive(Debug, Clone)]
 pub struct UdpSocketState {
     handle: GlobalSocketHandle,
     remote_endpoint: Option<IpEndpoint>, // remember remote endpoint for connect()
+    /// Shared by all fds of the socket
+    options: Arc<Mutex<SocketOptions>>,
 }
 
 #[derive(Debug, Clone)]
 pub struct RawSocketState {
     handle: GlobalSocketHandle,
-    header_included: bool,
+    /// Set by IP_HDRINCL for all fds of the socket
+    header_included: Arc<AtomicBool>,
 }
 
 #[derive(Debug, Clone)]
@@ -153,28 +207,126 @@ impl Drop for GlobalSocketHandle {
     }
 }
 
+impl SocketOptions {
+    fn new(send_buf: usize, recv_buf: usize) -> Self {
+        SocketOptions {
+            reuse_addr: false,
+            keep_alive: false,
+            no_delay: false,
+            send_buf,
+            recv_buf,
+            error: 0,
+            nonblock: false,
+        }
+    }
+
+    /// Set an option common to all kinds of sockets
+    fn set(&mut self, level: usize, opt: usize, data: &[u8]) -> SysResult {
+        let value = read_int_opt(data)?;
+        match (level, opt) {
+            (SOL_SOCKET, SO_REUSEADDR) => self.reuse_addr = value != 0,
+            (SOL_SOCKET, SO_KEEPALIVE) => self.keep_alive = value != 0,
+            (SOL_SOCKET, SO_SNDBUF) => {
+                self.send_buf = value.max(SOCKET_MIN_BUF).min(SOCKET_MAX_BUF)
+            }
+            (SOL_SOCKET, SO_RCVBUF) => {
+                self.recv_buf = value.max(SOCKET_MIN_BUF).min(SOCKET_MAX_BUF)
+            }
+            _ => return Err(SysError::ENOPROTOOPT),
+        }
+        Ok(0)
+    }
+
+    /// Get an option common to all kinds of sockets
+    fn get(&mut self, level: usize, opt: usize, data: &mut [u8]) -> SysResult {
+        let value = match (level, opt) {
+            (SOL_SOCKET, SO_REUSEADDR) => self.reuse_addr as usize,
+            (SOL_SOCKET, SO_KEEPALIVE) => self.keep_alive as usize,
+            (SOL_SOCKET, SO_SNDBUF) => self.send_buf,
+            (SOL_SOCKET, SO_RCVBUF) => self.recv_buf,
+            (SOL_SOCKET, SO_ERROR) => core::mem::replace(&mut self.error, 0),
+            _ => return Err(SysError::ENOPROTOOPT),
+        };
+        write_int_opt(value, data)
+    }
+}
+
+/// Read an `int` option value
+fn read_int_opt(data: &[u8]) -> Result<usize, SysError> {
+    if data.len() < size_of::<i32>() {
+        return Err(SysError::EINVAL);
+    }
+    Ok(i32::from_ne_bytes([data[0], data[1], data[2], data[3]]) as usize)
+}
+
+/// Write an `int` option value, return its length
+fn write_int_opt(value: usize, data: &mut [u8]) -> SysResult {
+    if data.len() < size_of::<i32>() {
+        return Err(SysError::EINVAL);
+    }
+    data[..4].copy_from_slice(&(value as i32).to_ne_bytes());
+    Ok(size_of::<i32>())
+}
+
+fn new_tcp_socket(options: &SocketOptions) -> TcpSocket<'static> {
+    let rx_buffer = TcpSocketBuffer::new(vec![0; options.recv_buf]);
+    let tx_buffer = TcpSocketBuffer::new(vec![0; options.send_buf]);
+    let mut socket = TcpSocket::new(rx_buffer, tx_buffer);
+    if options.keep_alive {
+        socket.set_keep_alive(Some(Duration::from_secs(TCP_KEEPALIVE_SECS)));
+    }
+    socket
+}
+
 impl TcpSocketState {
     pub fn new() -> Self {
-        let rx_buffer = TcpSocketBuffer::new(vec![0; TCP_RECVBUF]);
-        let tx_buffer = TcpSocketBuffer::new(vec![0; TCP_SENDBUF]);
-        let socket = TcpSocket::new(rx_buffer, tx_buffer);
+        let options = SocketOptions::new(TCP_SENDBUF, TCP_RECVBUF);
+        let socket = new_tcp_socket(&options);
         let handle = GlobalSocketHandle(SOCKETS.lock().add(socket));
 
         TcpSocketState {
-            handle,
+            handle: Arc::new(Mutex::new(handle)),
             local_endpoint: None,
             is_listening: false,
+            options: Arc::new(Mutex::new(options)),
+            shut_rd: false,
+            shut_wr: false,
+            connecting: Arc::new(AtomicBool::new(false)),
         }
     }
+
+    /// The smoltcp socket, only valid while `SOCKETS` is locked,
+    /// as another fd of the socket may replace it
+    fn handle(&self) -> SocketHandle {
+        self.handle.lock().0
+    }
+
+    /// Result of a non-blocking connect, `None` if still in progress.
+    /// Once known, it is no longer pending.
+    fn connect_result(&mut self) -> Option<SysResult> {
+        let mut sockets = SOCKETS.lock();
+        let socket = sockets.get::<TcpSocket>(self.handle());
+        let result = match socket.state() {
+            TcpState::SynSent => return None,
+            TcpState::Closed => Err(SysError::ECONNREFUSED),
+            _ => Ok(0),
+        };
+        self.connecting.store(false, Ordering::SeqCst);
+        Some(result)
+    }
 }
 
 impl Socket for TcpSocketState {
     fn read(&self, data: &mut [u8]) -> (SysResult, Endpoint) {
-        spin_and_wait(&[&SOCKET_ACTIVITY], move || {
+        let mut try_read = move || {
             poll_ifaces();
             let mut sockets = SOCKETS.lock();
-            let mut socket = sockets.get::<TcpSocket>(self.handle.0);
+            let mut socket = sockets.get::<TcpSocket>(self.handle());
 
+            if self.shut_rd {
+                discard_recv(&mut socket);
+                return Some((Ok(0), Endpoint::Ip(socket.remote_endpoint())));
+            }
             if socket.may_recv() {
                 if let Ok(size) = socket.recv_slice(data) {
                     if size > 0 {
@@ -188,18 +340,32 @@ impl Socket for TcpSocketState {
                     }
                 }
             } else {
-                return Some((
-                    Err(SysError::ENOTCONN),
-                    Endpoint::Ip(IpEndpoint::UNSPECIFIED),
-                ));
+                match socket.state() {
+                    TcpState::Closed | TcpState::Listen | TcpState::SynSent => {
+                        return Some((
+                            Err(SysError::ENOTCONN),
+                            Endpoint::Ip(IpEndpoint::UNSPECIFIED),
+                        ));
+                    }
+                    // the peer has shut down writing, end of file
+                    _ => return Some((Ok(0), Endpoint::Ip(socket.remote_endpoint()))),
+                }
             }
             None
-        })
+        };
+        if self.options.lock().nonblock {
+            return try_read()
+                .unwrap_or((Err(SysError::EAGAIN), Endpoint::Ip(IpEndpoint::UNSPECIFIED)));
+        }
+        spin_and_wait(&[&SOCKET_ACTIVITY], try_read)
     }
 
     fn write(&self, data: &[u8], _sendto_endpoint: Option<Endpoint>) -> SysResult {
+        if self.shut_wr {
+            return Err(SysError::EPIPE);
+        }
         let mut sockets = SOCKETS.lock();
-        let mut socket = sockets.get::<TcpSocket>(self.handle.0);
+        let mut socket = sockets.get::<TcpSocket>(self.handle());
 
         if socket.is_open() {
             if socket.can_send() {
@@ -224,12 +390,21 @@ impl Socket for TcpSocketState {
 
     fn poll(&self) -> (bool, bool, bool) {
         let mut sockets = SOCKETS.lock();
-        let socket = sockets.get::<TcpSocket>(self.handle.0);
+        let socket = sockets.get::<TcpSocket>(self.handle());
 
         let (mut input, mut output, mut err) = (false, false, false);
         if self.is_listening && socket.is_active() {
             // a new connection
             input = true;
+        } else if self.shut_rd {
+            // reading returns end of file at once
+            input = true;
+        } else if self.connecting.load(Ordering::SeqCst) && socket.state() == TcpState::SynSent {
+            // handshake in progress
+        } else if self.connecting.load(Ordering::SeqCst) && socket.state() == TcpState::Closed {
+            // connection failed, see SO_ERROR
+            output = true;
+            err = true;
         } else if !socket.is_open() {
             err = true;
         } else {
@@ -244,13 +419,33 @@ impl Socket for TcpSocketState {
     }
 
     fn connect(&mut self, endpoint: Endpoint) -> SysResult {
+        if self.connecting.load(Ordering::SeqCst) {
+            // connect again to get the result of a non-blocking connect
+            return match self.connect_result() {
+                None => Err(SysError::EALREADY),
+                Some(Ok(_)) => Err(SysError::EISCONN),
+                Some(Err(err)) => Err(err),
+            };
+        }
         let mut sockets = SOCKETS.lock();
-        let mut socket = sockets.get::<TcpSocket>(self.handle.0);
+        let mut socket = sockets.get::<TcpSocket>(self.handle());
 
         if let Endpoint::Ip(ip) = endpoint {
             let temp_port = get_ephemeral_port();
 
             match socket.connect(ip, temp_port) {
+                Ok(()) if self.options.lock().nonblock => {
+                    drop(socket);
+                    drop(sockets);
+
+                    // send SYN, it may even be answered at once on loopback
+                    poll_ifaces();
+                    self.connecting.store(true, Ordering::SeqCst);
+                    match self.connect_result() {
+                        None => Err(SysError::EINPROGRESS),
+                        Some(result) => result,
+                    }
+                }
                 Ok(()) => {
                     // avoid deadlock
                     drop(socket);
@@ -261,7 +456,7 @@ impl Socket for TcpSocketState {
                         poll_ifaces();
 
                         let mut sockets = SOCKETS.lock();
-                        let socket = sockets.get::<TcpSocket>(self.handle.0);
+                        let socket = sockets.get::<TcpSocket>(self.handle());
                         match socket.state() {
                             TcpState::SynSent => {
                                 // still connecting
@@ -289,6 +484,8 @@ impl Socket for TcpSocketState {
         if let Endpoint::Ip(mut ip) = endpoint {
             if ip.port == 0 {
                 ip.port = get_ephemeral_port();
+            } else if tcp_port_in_use(ip, self.options.lock().reuse_addr) {
+                return Err(SysError::EADDRINUSE);
             }
             self.local_endpoint = Some(ip);
             self.is_listening = false;
@@ -305,7 +502,7 @@ impl Socket for TcpSocketState {
         }
         let local_endpoint = self.local_endpoint.ok_or(SysError::EINVAL)?;
         let mut sockets = SOCKETS.lock();
-        let mut socket = sockets.get::<TcpSocket>(self.handle.0);
+        let mut socket = sockets.get::<TcpSocket>(self.handle());
 
         info!("socket listening on {:?}", local_endpoint);
         if socket.is_listening() {
@@ -320,10 +517,33 @@ impl Socket for TcpSocketState {
         }
     }
 
-    fn shutdown(&self) -> SysResult {
+    fn shutdown(&mut self, how: usize) -> SysResult {
+        let (rd, wr) = match how {
+            SHUT_RD => (true, false),
+            SHUT_WR => (false, true),
+            SHUT_RDWR => (true, true),
+            _ => return Err(SysError::EINVAL),
+        };
         let mut sockets = SOCKETS.lock();
-        let mut socket = sockets.get::<TcpSocket>(self.handle.0);
-        socket.close();
+        let mut socket = sockets.get::<TcpSocket>(self.handle());
+        if !socket.is_active() {
+            return Err(SysError::ENOTCONN);
+        }
+        if rd && !self.shut_rd {
+            self.shut_rd = true;
+            discard_recv(&mut socket);
+        }
+        if wr && !self.shut_wr {
+            self.shut_wr = true;
+            // smoltcp only closes the transmit half, we can still receive
+            socket.close();
+        }
+        drop(socket);
+        drop(sockets);
+
+        // send FIN, and wake up local readers
+        poll_ifaces();
+        SOCKET_ACTIVITY.notify_all();
         Ok(0)
     }
 
@@ -331,24 +551,28 @@ impl Socket for TcpSocketState {
         let endpoint = self.local_endpoint.ok_or(SysError::EINVAL)?;
         loop {
             let mut sockets = SOCKETS.lock();
-            let socket = sockets.get::<TcpSocket>(self.handle.0);
+            let socket = sockets.get::<TcpSocket>(self.handle());
 
             if socket.is_active() {
                 let remote_endpoint = socket.remote_endpoint();
                 drop(socket);
 
                 let new_socket = {
-                    let rx_buffer = TcpSocketBuffer::new(vec![0; TCP_RECVBUF]);
-                    let tx_buffer = TcpSocketBuffer::new(vec![0; TCP_SENDBUF]);
-                    let mut socket = TcpSocket::new(rx_buffer, tx_buffer);
+                    let mut socket = new_tcp_socket(&self.options.lock());
                     socket.listen(endpoint).unwrap();
                     let new_handle = GlobalSocketHandle(sockets.add(socket));
-                    let old_handle = ::core::mem::replace(&mut self.handle, new_handle);
+                    let old_handle = ::core::mem::replace(&mut *self.handle.lock(), new_handle);
 
+                    // accepted sockets inherit options of the listener
+                    let options = self.options.lock().clone();
                     Box::new(TcpSocketState {
-                        handle: old_handle,
+                        handle: Arc::new(Mutex::new(old_handle)),
                         local_endpoint: self.local_endpoint,
                         is_listening: false,
+                        options: Arc::new(Mutex::new(options)),
+                        shut_rd: false,
+                        shut_wr: false,
+                        connecting: Arc::new(AtomicBool::new(false)),
                     })
                 };
 
@@ -358,6 +582,9 @@ impl Socket for TcpSocketState {
             }
 
             drop(socket);
+            if self.options.lock().nonblock {
+                return Err(SysError::EAGAIN);
+            }
             SOCKET_ACTIVITY.wait(sockets);
         }
     }
@@ -368,7 +595,7 @@ impl Socket for TcpSocketState {
             .map(|e| Endpoint::Ip(e))
             .or_else(|| {
                 let mut sockets = SOCKETS.lock();
-                let socket = sockets.get::<TcpSocket>(self.handle.0);
+                let socket = sockets.get::<TcpSocket>(self.handle());
                 let endpoint = socket.local_endpoint();
                 if endpoint.port != 0 {
                     Some(Endpoint::Ip(endpoint))
@@ -380,7 +607,7 @@ impl Socket for TcpSocketState {
 
     fn remote_endpoint(&self) -> Option<Endpoint> {
         let mut sockets = SOCKETS.lock();
-        let socket = sockets.get::<TcpSocket>(self.handle.0);
+        let socket = sockets.get::<TcpSocket>(self.handle());
         if socket.is_open() {
             Some(Endpoint::Ip(socket.remote_endpoint()))
         } else {
@@ -388,6 +615,64 @@ impl Socket for TcpSocketState {
         }
     }
 
+    fn setsockopt(&mut self, level: usize, opt: usize, data: &[u8]) -> SysResult {
+        match (level, opt) {
+            (IPPROTO_TCP, TCP_NODELAY) => {
+                self.options.lock().no_delay = read_int_opt(data)? != 0;
+                return Ok(0);
+            }
+            (IPPROTO_TCP, TCP_CONGESTION) => return Ok(0),
+            _ => self.options.lock().set(level, opt, data)?,
+        };
+        let mut sockets = SOCKETS.lock();
+        let mut socket = sockets.get::<TcpSocket>(self.handle());
+        match opt {
+            SO_KEEPALIVE => {
+                let interval = match self.options.lock().keep_alive {
+                    true => Some(Duration::from_secs(TCP_KEEPALIVE_SECS)),
+                    false => None,
+                };
+                socket.set_keep_alive(interval);
+            }
+            SO_SNDBUF | SO_RCVBUF => {
+                // buffers can only be resized before use
+                if socket.state() == TcpState::Closed && !self.is_listening {
+                    drop(socket);
+                    let socket = new_tcp_socket(&self.options.lock());
+                    let new_handle = GlobalSocketHandle(sockets.add(socket));
+                    let old_handle = core::mem::replace(&mut *self.handle.lock(), new_handle);
+                    drop(sockets);
+                    drop(old_handle);
+                }
+            }
+            _ => {}
+        }
+        Ok(0)
+    }
+
+    fn getsockopt(&mut self, level: usize, opt: usize, data: &mut [u8]) -> SysResult {
+        if (level, opt) == (SOL_SOCKET, SO_ERROR) && self.connecting.load(Ordering::SeqCst) {
+            if let Some(Err(err)) = self.connect_result() {
+                self.options.lock().error = err as usize;
+            }
+        }
+        match (level, opt) {
+            (IPPROTO_TCP, TCP_NODELAY) => {
+                write_int_opt(self.options.lock().no_delay as usize, data)
+            }
+            (IPPROTO_TCP, TCP_CONGESTION) => Ok(0),
+            _ => self.options.lock().get(level, opt, data),
+        }
+    }
+
+    fn nonblocking(&self) -> bool {
+        self.options.lock().nonblock
+    }
+
+    fn set_nonblocking(&mut self, nonblock: bool) {
+        self.options.lock().nonblock = nonblock;
+    }
+
     fn box_clone(&self) -> Box<dyn Socket> {
         Box::new(self.clone())
     }
@@ -409,6 +694,7 @@ impl UdpSocketState {
         UdpSocketState {
             handle,
             remote_endpoint: None,
+            options: Arc::new(Mutex::new(SocketOptions::new(UDP_SENDBUF, UDP_RECVBUF))),
         }
     }
 }
@@ -425,27 +711,27 @@ struct ArpReq {
 impl Socket for UdpSocketState {
     fn read(&self, data: &mut [u8]) -> (SysResult, Endpoint) {
         loop {
+            // deliver datagrams queued on the loopback interface
+            poll_ifaces();
             let mut sockets = SOCKETS.lock();
             let mut socket = sockets.get::<UdpSocket>(self.handle.0);
 
             if socket.can_recv() {
                 if let Ok((size, remote_endpoint)) = socket.recv_slice(data) {
-                    let endpoint = remote_endpoint;
-                    // avoid deadlock
-                    drop(socket);
-                    drop(sockets);
-
-                    poll_ifaces();
-                    return (Ok(size), Endpoint::Ip(endpoint));
+                    if let Some(connected) = self.remote_endpoint {
+                        if remote_endpoint != connected {
+                            // a connected socket only receives from its peer
+                            continue;
+                        }
+                    }
+                    return (Ok(size), Endpoint::Ip(remote_endpoint));
                 }
-            } else {
-                return (
-                    Err(SysError::ENOTCONN),
-                    Endpoint::Ip(IpEndpoint::UNSPECIFIED),
-                );
             }
 
             drop(socket);
+            if self.options.lock().nonblock {
+                return (Err(SysError::EAGAIN), Endpoint::Ip(IpEndpoint::UNSPECIFIED));
+            }
             SOCKET_ACTIVITY.wait(sockets);
         }
     }
@@ -503,6 +789,15 @@ impl Socket for UdpSocketState {
 
     fn connect(&mut self, endpoint: Endpoint) -> SysResult {
         if let Endpoint::Ip(ip) = endpoint {
+            let mut sockets = SOCKETS.lock();
+            let mut socket = sockets.get::<UdpSocket>(self.handle.0);
+            // bind now, so that the peer can reply before the first send
+            if socket.endpoint().port == 0 {
+                let temp_port = get_ephemeral_port();
+                socket
+                    .bind(IpEndpoint::new(IpAddress::Unspecified, temp_port))
+                    .unwrap();
+            }
             self.remote_endpoint = Some(ip);
             Ok(0)
         } else {
@@ -575,6 +870,23 @@ impl Socket for UdpSocketState {
         self.remote_endpoint.clone().map(|e| Endpoint::Ip(e))
     }
 
+    fn setsockopt(&mut self, level: usize, opt: usize, data: &[u8]) -> SysResult {
+        // buffers are fixed, SO_SNDBUF and SO_RCVBUF are only recorded
+        self.options.lock().set(level, opt, data)
+    }
+
+    fn getsockopt(&mut self, level: usize, opt: usize, data: &mut [u8]) -> SysResult {
+        self.options.lock().get(level, opt, data)
+    }
+
+    fn nonblocking(&self) -> bool {
+        self.options.lock().nonblock
+    }
+
+    fn set_nonblocking(&mut self, nonblock: bool) {
+        self.options.lock().nonblock = nonblock;
+    }
+
     fn box_clone(&self) -> Box<dyn Socket> {
         Box::new(self.clone())
     }
@@ -600,7 +912,7 @@ impl RawSocketState {
 
         RawSocketState {
             handle,
-            header_included: false,
+            header_included: Arc::new(AtomicBool::new(false)),
         }
     }
 }
@@ -629,7 +941,7 @@ impl Socket for RawSocketState {
     }
 
     fn write(&self, data: &[u8], sendto_endpoint: Option<Endpoint>) -> SysResult {
-        if self.header_included {
+        if self.header_included.load(Ordering::Relaxed) {
             let mut sockets = SOCKETS.lock();
             let mut socket = sockets.get::<RawSocket>(self.handle.0);
 
@@ -693,8 +1005,8 @@ impl Socket for RawSocketState {
         match (level, opt) {
             (IPPROTO_IP, IP_HDRINCL) => {
                 if let Some(arg) = data.first() {
-                    self.header_included = *arg > 0;
-                    debug!("hdrincl set to {}", self.header_included);
+                    self.header_included.store(*arg > 0, Ordering::Relaxed);
+                    debug!("hdrincl set to {}", *arg > 0);
                 }
             }
             _ => {}
@@ -1087,6 +1399,33 @@ fn get_ephemeral_port() -> u16 {
     }
 }
 
+/// Whether `endpoint` conflicts with the local endpoint of an existing TCP socket.
+/// With SO_REUSEADDR, only listening sockets conflict,
+/// so that a server can be restarted while old connections are in TIME-WAIT.
+fn tcp_port_in_use(endpoint: IpEndpoint, reuse_addr: bool) -> bool {
+    let sockets = SOCKETS.lock();
+    sockets.iter().any(|socket| match socket {
+        smoltcp::socket::Socket::Tcp(socket) => {
+            let local = socket.local_endpoint();
+            local.port == endpoint.port
+                && (local.addr == endpoint.addr
+                    || local.addr.is_unspecified()
+                    || endpoint.addr.is_unspecified())
+                && (!reuse_addr || socket.state() == TcpState::Listen)
+        }
+        _ => false,
+    })
+}
+
+/// Drop all data received by `socket`
+fn discard_recv(socket: &mut TcpSocket) {
+    while socket.can_recv() {
+        if socket.recv(|buf| (buf.len(), ())).is_err() {
+            break;
+        }
+    }
+}
+
 /// Safety: call this without SOCKETS locked
 fn poll_ifaces() {
     for iface in NET_DRIVERS.read().iter() {
@@ -1096,6 +1435,12 @@ fn poll_ifaces() {
 
 pub const TCP_SENDBUF: usize = 512 * 1024; // 512K
 pub const TCP_RECVBUF: usize = 512 * 1024; // 512K
+/// Idle time before keep-alive probes, the default of Linux
+const TCP_KEEPALIVE_SECS: u64 = 7200;
+
+/// Bounds of SO_SNDBUF and SO_RCVBUF
+const SOCKET_MIN_BUF: usize = 4 * 1024; // 4K
+const SOCKET_MAX_BUF: usize = 4 * 1024 * 1024; // 4M
 
 const UDP_METADATA_BUF: usize = 1024;
 const UDP_SENDBUF: usize = 64 * 1024; // 64K
@@ -0,0 +1,185 @@
+//! Unix domain stream sockets, connected in pairs by `socketpair`
+//!
+//! Each direction is a byte stream in a `Channel`. Files passed with
+//! SCM_RIGHTS ride along with the first byte of the write carrying them,
+//! and a read stops short of the next such byte, so they are received
+//! with the data they were sent with.
+
+use super::{Endpoint, Socket};
+use crate::drivers::SOCKET_ACTIVITY;
+use crate::fs::FileLike;
+use crate::sync::SpinNoIrqLock as Mutex;
+use crate::syscall::*;
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::cmp::min;
+use core::mem::replace;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// Bytes a channel holds before writers block
+const UNIX_BUF_SIZE: usize = 64 * 1024;
+
+/// One direction of a connected pair
+#[derive(Debug, Default)]
+struct Channel {
+    data: VecDeque<u8>,
+    /// Bytes read so far, the offset of the first byte in `data`
+    read: usize,
+    /// Files sent, by the offset of the byte they came with
+    rights: VecDeque<(usize, Vec<FileLike>)>,
+    reader_closed: bool,
+    writer_closed: bool,
+}
+
+/// The state of one socket of a pair, shared by all its fds
+#[derive(Debug)]
+struct UnixEnd {
+    rx: Arc<Mutex<Channel>>,
+    tx: Arc<Mutex<Channel>>,
+    /// Set by O_NONBLOCK
+    nonblock: AtomicBool,
+}
+
+impl Drop for UnixEnd {
+    fn drop(&mut self) {
+        // the files may hold the other end, which locks these channels on drop
+        let rights = {
+            let mut rx = self.rx.lock();
+            rx.reader_closed = true;
+            rx.data.clear();
+            replace(&mut rx.rights, VecDeque::new())
+        };
+        drop(rights);
+        self.tx.lock().writer_closed = true;
+        SOCKET_ACTIVITY.notify_all();
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct UnixSocketState {
+    end: Arc<UnixEnd>,
+}
+
+impl UnixSocketState {
+    /// A pair of sockets connected to each other
+    pub fn pair() -> (Self, Self) {
+        let a = Arc::new(Mutex::new(Channel::default()));
+        let b = Arc::new(Mutex::new(Channel::default()));
+        let end = |rx, tx| UnixSocketState {
+            end: Arc::new(UnixEnd {
+                rx,
+                tx,
+                nonblock: AtomicBool::new(false),
+            }),
+        };
+        (end(a.clone(), b.clone()), end(b, a))
+    }
+}
+
+impl Socket for UnixSocketState {
+    fn read(&self, data: &mut [u8]) -> (SysResult, Endpoint) {
+        // the files are closed if the caller has no room for them
+        let (result, endpoint, _) = self.read_rights(data);
+        (result, endpoint)
+    }
+
+    fn write(&self, data: &[u8], _sendto_endpoint: Option<Endpoint>) -> SysResult {
+        self.write_rights(data, Vec::new())
+    }
+
+    fn read_rights(&self, data: &mut [u8]) -> (SysResult, Endpoint, Vec<FileLike>) {
+        if data.is_empty() {
+            return (Ok(0), Endpoint::Unix, Vec::new());
+        }
+        let mut try_read = || {
+            let mut rx = self.end.rx.lock();
+            if rx.data.is_empty() {
+                if rx.writer_closed {
+                    return Some((Ok(0), Endpoint::Unix, Vec::new()));
+                }
+                return None;
+            }
+            let mut files = Vec::new();
+            if rx.rights.front().map(|(at, _)| *at) == Some(rx.read) {
+                files = rx.rights.pop_front().unwrap().1;
+            }
+            let mut len = min(data.len(), rx.data.len());
+            if let Some((at, _)) = rx.rights.front() {
+                len = min(len, at - rx.read);
+            }
+            for (dst, src) in data.iter_mut().zip(rx.data.drain(..len)) {
+                *dst = src;
+            }
+            rx.read += len;
+            drop(rx);
+            SOCKET_ACTIVITY.notify_all();
+            Some((Ok(len), Endpoint::Unix, files))
+        };
+        if self.nonblocking() {
+            return try_read().unwrap_or((Err(SysError::EAGAIN), Endpoint::Unix, Vec::new()));
+        }
+        spin_and_wait(&[&SOCKET_ACTIVITY], try_read)
+    }
+
+    fn write_rights(&self, data: &[u8], files: Vec<FileLike>) -> SysResult {
+        // files need a byte to come with
+        if data.is_empty() {
+            return Ok(0);
+        }
+        let mut files = Some(files);
+        let mut try_write = || {
+            let mut tx = self.end.tx.lock();
+            if tx.reader_closed {
+                return Some(Err(SysError::EPIPE));
+            }
+            let len = min(data.len(), UNIX_BUF_SIZE - tx.data.len());
+            if len == 0 {
+                return None;
+            }
+            let files = files.take().unwrap();
+            if !files.is_empty() {
+                let at = tx.read + tx.data.len();
+                tx.rights.push_back((at, files));
+            }
+            tx.data.extend(&data[..len]);
+            drop(tx);
+            SOCKET_ACTIVITY.notify_all();
+            Some(Ok(len))
+        };
+        if self.nonblocking() {
+            return try_write().unwrap_or(Err(SysError::EAGAIN));
+        }
+        spin_and_wait(&[&SOCKET_ACTIVITY], try_write)
+    }
+
+    fn poll(&self) -> (bool, bool, bool) {
+        let rx = self.end.rx.lock();
+        let input = !rx.data.is_empty() || rx.writer_closed;
+        drop(rx);
+        let tx = self.end.tx.lock();
+        let output = tx.reader_closed || tx.data.len() < UNIX_BUF_SIZE;
+        (input, output, false)
+    }
+
+    fn connect(&mut self, _endpoint: Endpoint) -> SysResult {
+        Err(SysError::EISCONN)
+    }
+
+    fn remote_endpoint(&self) -> Option<Endpoint> {
+        Some(Endpoint::Unix)
+    }
+
+    fn nonblocking(&self) -> bool {
+        self.end.nonblock.load(Ordering::Relaxed)
+    }
+
+    fn set_nonblocking(&mut self, nonblock: bool) {
+        self.end.nonblock.store(nonblock, Ordering::Relaxed);
+    }
+
+    fn box_clone(&self) -> Box<dyn Socket> {
+        Box::new(self.clone())
+    }
+}
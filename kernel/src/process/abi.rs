@@ -7,14 +7,19 @@ pub struct ProcInitInfo {
     pub args: Vec<String>,
     pub envs: Vec<String>,
     pub auxv: BTreeMap<u8, usize>,
+    /// Bytes pointed by `AT_RANDOM`
+    pub random: [u8; 16],
 }
 
 impl ProcInitInfo {
-    pub unsafe fn push_at(&self, stack_top: usize) -> usize {
+    pub unsafe fn push_at(&mut self, stack_top: usize) -> usize {
         let mut writer = StackWriter { sp: stack_top };
         // from stack_top:
         // program name
         writer.push_str(&self.args[0]);
+        // random bytes
+        writer.push_slice(&self.random);
+        self.auxv.insert(AT_RANDOM, writer.sp);
         // environment strings
         let envs: Vec<_> = self
             .envs
@@ -76,3 +81,12 @@ pub const AT_PHNUM: u8 = 5;
 pub const AT_PAGESZ: u8 = 6;
 pub const AT_BASE: u8 = 7;
 pub const AT_ENTRY: u8 = 9;
+pub const AT_UID: u8 = 11;
+pub const AT_EUID: u8 = 12;
+pub const AT_GID: u8 = 13;
+pub const AT_EGID: u8 = 14;
+pub const AT_HWCAP: u8 = 16;
+pub const AT_CLKTCK: u8 = 17;
+pub const AT_SECURE: u8 = 23;
+pub const AT_RANDOM: u8 = 25;
+pub const AT_SYSINFO_EHDR: u8 = 33;
@@ -0,0 +1,183 @@
+//! Builder of new user processes, e.g. init
+
+use super::Thread;
+use crate::fs::mount::{normalize, MountNamespace, ROOT_MOUNT_NS};
+use crate::fs::{
+    lookup_beneath, FileHandle, FileLike, OpenOptions, FOLLOW_MAX_DEPTH, ROOT_INODE, TTY,
+};
+use crate::memory::MemorySet;
+use alloc::{collections::BTreeMap, string::String, sync::Arc, vec::Vec};
+use rcore_fs::vfs::{FileType, INode};
+use spin::RwLock;
+
+/// Options to spawn a user process from an ELF file.
+///
+/// Fds 0, 1 and 2 not given by `file` are opened on `/dev/tty`, unless `no_stdio`.
+/// The process joins the mount namespace of init, unless given another by `mount_ns`.
+///
+/// ```ignore
+/// let thread = ProcessBuilder::new(inode, "/busybox")
+///     .args(vec!["busybox".into(), "ash".into()])
+///     .root("/rootfs")
+///     .file(1, log_file)
+///     .build()?;
+/// ```
+pub struct ProcessBuilder {
+    inode: Arc<dyn INode>,
+    exec_path: String,
+    args: Vec<String>,
+    envs: Vec<String>,
+    cwd: String,
+    root: Option<String>,
+    mount_ns: Arc<RwLock<MountNamespace>>,
+    files: BTreeMap<usize, FileLike>,
+    stdio: bool,
+}
+
+impl ProcessBuilder {
+    /// Run the ELF at `inode`, which is at `exec_path`
+    pub fn new(inode: Arc<dyn INode>, exec_path: &str) -> Self {
+        ProcessBuilder {
+            inode,
+            exec_path: String::from(exec_path),
+            args: Vec::new(),
+            envs: Vec::new(),
+            cwd: String::from("/"),
+            root: None,
+            mount_ns: ROOT_MOUNT_NS.clone(),
+            files: BTreeMap::new(),
+            stdio: true,
+        }
+    }
+
+    pub fn args(mut self, args: Vec<String>) -> Self {
+        self.args = args;
+        self
+    }
+
+    pub fn envs(mut self, envs: Vec<String>) -> Self {
+        self.envs = envs;
+        self
+    }
+
+    /// Set the working directory, an absolute path inside the root directory
+    pub fn cwd(mut self, cwd: &str) -> Self {
+        self.cwd = String::from(cwd);
+        self
+    }
+
+    /// Confine the process to the directory at absolute path `root`, as chroot does
+    pub fn root(mut self, root: &str) -> Self {
+        self.root = Some(normalize(root));
+        self
+    }
+
+    /// Join mount namespace `mount_ns`, in which the paths are looked up
+    pub fn mount_ns(mut self, mount_ns: Arc<RwLock<MountNamespace>>) -> Self {
+        self.mount_ns = mount_ns;
+        self
+    }
+
+    /// Open `file` as `fd`
+    pub fn file(mut self, fd: usize, file: FileLike) -> Self {
+        self.files.insert(fd, file);
+        self
+    }
+
+    /// Do not open `/dev/tty` as stdio
+    pub fn no_stdio(mut self) -> Self {
+        self.stdio = false;
+        self
+    }
+
+    pub fn build(mut self) -> Result<Arc<Thread>, &'static str> {
+        let mount_ns = self.mount_ns.clone();
+        let mounts = mount_ns.read();
+        let root = match self.root.take() {
+            Some(path) => {
+                let inode = lookup_beneath(
+                    &ROOT_INODE,
+                    &mounts,
+                    ROOT_INODE.clone(),
+                    &path,
+                    true,
+                    FOLLOW_MAX_DEPTH,
+                )
+                .map_err(|_| "root directory not found")?;
+                if !is_dir(&inode)? {
+                    return Err("root directory is not a directory");
+                }
+                Some((path, inode))
+            }
+            None => None,
+        };
+        let root_inode = match &root {
+            Some((_, root)) => root.clone(),
+            None => ROOT_INODE.clone(),
+        };
+        let cwd = lookup_beneath(
+            &root_inode,
+            &mounts,
+            root_inode.clone(),
+            &self.cwd,
+            true,
+            FOLLOW_MAX_DEPTH,
+        )
+        .map_err(|_| "working directory not found")?;
+        if !is_dir(&cwd)? {
+            return Err("working directory is not a directory");
+        }
+
+        let mut vm = MemorySet::new();
+        let (entry_addr, ustack_top) = Thread::new_user_vm(
+            &self.inode,
+            self.args,
+            self.envs,
+            &mut vm,
+            0,
+            &root_inode,
+            &mounts,
+        )?;
+        drop(mounts);
+
+        if self.stdio {
+            for fd in 0..3 {
+                if !self.files.contains_key(&fd) {
+                    self.files.insert(fd, tty(fd == 0));
+                }
+            }
+        }
+
+        Ok(Thread::new_process(
+            vm,
+            entry_addr,
+            ustack_top,
+            self.files,
+            self.cwd,
+            root,
+            mount_ns,
+            &self.exec_path,
+        ))
+    }
+}
+
+fn is_dir(inode: &Arc<dyn INode>) -> Result<bool, &'static str> {
+    let metadata = inode.metadata().map_err(|_| "failed to stat directory")?;
+    Ok(metadata.type_ == FileType::Dir)
+}
+
+/// `/dev/tty` opened for reading or writing
+fn tty(read: bool) -> FileLike {
+    FileLike::File(FileHandle::new(
+        TTY.clone(),
+        OpenOptions {
+            read,
+            write: !read,
+            append: false,
+            nonblock: false,
+        },
+        String::from("/dev/tty"),
+        false,
+        false,
+    ))
+}
@@ -0,0 +1,113 @@
+//! Executor for reproducible runs, with the `deterministic` feature
+//!
+//! Every task runs on CPU 0. The next one is picked among the woken tasks by a generator
+//! seeded from `SCHED_SEED` at build time, and user threads yield every `QUANTUM` syscalls
+//! instead of on timer ticks. So a program runs the same way every time,
+//! as long as it depends neither on time nor on devices:
+//! wakeups by interrupts, e.g. of `nanosleep`, still come whenever the hardware says.
+//! A thread spinning without syscalls keeps CPU 0 to itself.
+
+use crate::arch::cpu;
+use crate::sync::SpinNoIrqLock as Mutex;
+use alloc::{boxed::Box, collections::BTreeMap, vec::Vec};
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, RawWaker, RawWakerVTable, Waker};
+
+/// Syscalls between two yields of a user thread
+pub const QUANTUM: usize = 64;
+
+type Task = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+struct Executor {
+    /// Tasks not being polled, by id
+    tasks: BTreeMap<usize, Task>,
+    /// Woken tasks, in order of wakeup
+    ready: Vec<usize>,
+    next_id: usize,
+    /// State of xorshift64
+    rng: u64,
+}
+
+lazy_static! {
+    static ref EXECUTOR: Mutex<Executor> = Mutex::new(Executor {
+        tasks: BTreeMap::new(),
+        ready: Vec::new(),
+        next_id: 0,
+        rng: option_env!("SCHED_SEED")
+            .and_then(|seed| seed.parse().ok())
+            .filter(|&seed| seed != 0)
+            .unwrap_or(0x2545_f491_4f6c_dd1d),
+    });
+}
+
+impl Executor {
+    fn wake(&mut self, id: usize) {
+        if !self.ready.contains(&id) {
+            self.ready.push(id);
+        }
+    }
+
+    fn next_random(&mut self) -> u64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        self.rng
+    }
+}
+
+pub fn spawn(future: impl Future<Output = ()> + Send + 'static) {
+    let mut executor = EXECUTOR.lock();
+    let id = executor.next_id;
+    executor.next_id += 1;
+    executor.tasks.insert(id, Box::pin(future));
+    executor.wake(id);
+}
+
+/// Poll the woken tasks on CPU 0 until none is left
+pub fn run_until_idle() {
+    if cpu::id() != 0 {
+        return;
+    }
+    loop {
+        let (id, mut task) = {
+            let mut executor = EXECUTOR.lock();
+            if executor.ready.is_empty() {
+                return;
+            }
+            let index = executor.next_random() as usize % executor.ready.len();
+            let id = executor.ready.remove(index);
+            match executor.tasks.remove(&id) {
+                Some(task) => (id, task),
+                // finished
+                None => continue,
+            }
+        };
+        // the task is put back before it can be picked again, as only this CPU picks
+        let waker = waker(id);
+        if task
+            .as_mut()
+            .poll(&mut Context::from_waker(&waker))
+            .is_pending()
+        {
+            EXECUTOR.lock().tasks.insert(id, task);
+        }
+    }
+}
+
+/// A waker holding nothing but the task id
+fn waker(id: usize) -> Waker {
+    unsafe { Waker::from_raw(raw_waker(id as *const ())) }
+}
+
+unsafe fn raw_waker(data: *const ()) -> RawWaker {
+    RawWaker::new(data, &VTABLE)
+}
+
+static VTABLE: RawWakerVTable = RawWakerVTable::new(raw_waker, wake_task, wake_task, drop_waker);
+
+unsafe fn wake_task(data: *const ()) {
+    EXECUTOR.lock().wake(data as usize);
+}
+
+unsafe fn drop_waker(_data: *const ()) {}
@@ -7,17 +7,48 @@ use crate::{
 use alloc::boxed::Box;
 use alloc::{collections::VecDeque, sync::Arc};
 use core::pin::Pin;
+use core::sync::atomic::{AtomicI32, Ordering};
 use core::task::{Context, Poll};
 use core::{future::Future, task::Waker, time::Duration};
 
+/// Waiters with any bit in common with the bitset of a wake are woken
+pub const FUTEX_BITSET_MATCH_ANY: u32 = u32::MAX;
+
+/// Bits of the word of a PI futex besides the tid of its owner
+pub const FUTEX_WAITERS: u32 = 0x8000_0000;
+pub const FUTEX_OWNER_DIED: u32 = 0x4000_0000;
+pub const FUTEX_TID_MASK: u32 = 0x3fff_ffff;
+
+/// Size of `struct robust_list_head` given to `set_robust_list`:
+/// the next entry, the offset of the futex word in each entry, and the entry being changed
+pub const ROBUST_LIST_HEAD_LEN: usize = 3 * core::mem::size_of::<usize>();
+/// Most entries walked in a robust list, in case it loops, as in Linux
+pub const ROBUST_LIST_LIMIT: usize = 2048;
+
 pub struct Waiter {
     waker: Option<Waker>,
     woken: bool,
-    futex: Arc<Futex>,
+    /// Whether it is in the queue of the futex
+    queued: bool,
+    /// Bitset given to `FUTEX_WAIT_BITSET`, 0 for waiters of PI futexes
+    bitset: u32,
+    pi: Option<PiWaiter>,
+}
+
+/// A thread waiting to be given a PI futex
+#[derive(Debug, Clone, Copy)]
+pub struct PiWaiter {
+    pub tid: usize,
+    /// Tid stored to the word, which may be namespace-local
+    pub word_tid: u32,
+    /// Nice value which the owner inherits
+    pub nice: i8,
 }
 
 pub struct FutexInner {
     waiters: VecDeque<Arc<Mutex<Waiter>>>,
+    /// Thread owning the PI futex while it has waiters, 0 if none
+    pub pi_owner: usize,
 }
 
 pub struct Futex {
@@ -29,76 +60,190 @@ impl Futex {
         Futex {
             inner: Mutex::new(FutexInner {
                 waiters: VecDeque::new(),
+                pi_owner: 0,
             }),
         }
     }
 
     pub fn wake(&self, wake_count: usize) -> usize {
+        self.wake_bitset(wake_count, FUTEX_BITSET_MATCH_ANY)
+    }
+
+    /// Wake the first `wake_count` waiters whose bitsets intersect `bitset`
+    pub fn wake_bitset(&self, wake_count: usize, bitset: u32) -> usize {
         let mut inner = self.inner.lock();
-        for i in 0..wake_count {
-            if let Some(waiter) = inner.waiters.pop_front() {
-                let mut waiter = waiter.lock();
-                waiter.woken = true;
-                if let Some(waker) = waiter.waker.take() {
-                    waker.wake();
-                }
-            } else {
-                return i;
+        let mut woken = 0;
+        inner.waiters.retain(|waiter| {
+            if woken == wake_count {
+                return true;
             }
-        }
-        wake_count
+            let mut waiter = waiter.lock();
+            if waiter.bitset & bitset == 0 {
+                return true;
+            }
+            waiter.woken = true;
+            if let Some(waker) = waiter.waker.take() {
+                waker.wake();
+            }
+            woken += 1;
+            false
+        });
+        woken
     }
 
     pub fn wait(self: &Arc<Self>, timeout: Option<Duration>) -> impl Future<Output = SysResult> {
-        #[must_use = "future does nothing unless polled/`await`-ed"]
-        struct FutexFuture {
-            waiter: Arc<Mutex<Waiter>>,
-            deadline: Option<Duration>,
+        self.wait_bitset(timeout.map(|t| timer_now() + t), FUTEX_BITSET_MATCH_ANY)
+    }
+
+    /// Wait until woken by a wake whose bitset intersects `bitset`, or until `deadline`
+    pub fn wait_bitset(
+        self: &Arc<Self>,
+        deadline: Option<Duration>,
+        bitset: u32,
+    ) -> impl Future<Output = SysResult> {
+        self.wait_as(bitset, None, deadline)
+    }
+
+    /// Queue `waiter` to be given the PI futex owned by `owner` by `unlock_pi`,
+    /// and wait until then or until `deadline`.
+    /// It is queued at once, so an unlock after the futex word is updated can't miss it.
+    pub fn lock_pi(
+        self: &Arc<Self>,
+        waiter: PiWaiter,
+        owner: usize,
+        deadline: Option<Duration>,
+    ) -> impl Future<Output = SysResult> {
+        let future = self.wait_as(0, Some(waiter), deadline);
+        let mut inner = self.inner.lock();
+        inner.pi_owner = owner;
+        inner.waiters.push_back(future.waiter.clone());
+        future.waiter.lock().queued = true;
+        future
+    }
+
+    /// Give the PI futex to the waiter with the lowest nice value, the first of them if several.
+    /// Its tid is stored to `word` with `flags` before it is woken, or just `flags` if there is
+    /// no waiter. Return the tid of the new owner.
+    pub fn unlock_pi(&self, word: &AtomicI32, flags: u32) -> Option<usize> {
+        let mut inner = self.inner.lock();
+        let next = inner
+            .waiters
+            .iter()
+            .enumerate()
+            .filter_map(|(i, waiter)| waiter.lock().pi.map(|pi| (i, pi)))
+            .min_by_key(|&(_, pi)| pi.nice);
+        let (i, pi) = match next {
+            Some(next) => next,
+            None => {
+                inner.pi_owner = 0;
+                word.store(flags as i32, Ordering::Release);
+                return None;
+            }
+        };
+        let waiter = inner.waiters.remove(i).unwrap();
+        let more = inner
+            .waiters
+            .iter()
+            .any(|waiter| waiter.lock().pi.is_some());
+        let waiters = if more { FUTEX_WAITERS } else { 0 };
+        word.store((pi.word_tid | flags | waiters) as i32, Ordering::Release);
+        inner.pi_owner = pi.tid;
+
+        let mut waiter = waiter.lock();
+        waiter.woken = true;
+        if let Some(waker) = waiter.waker.take() {
+            waker.wake();
         }
+        Some(pi.tid)
+    }
 
-        impl Future for FutexFuture {
-            type Output = SysResult;
-
-            fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-                let mut inner = self.waiter.lock();
-                // check wakeup
-                if inner.woken {
-                    return Poll::Ready(Ok(0));
-                }
-                if let Some(deadline) = self.deadline {
-                    if timer_now() >= deadline {
-                        inner.woken = true;
-                        return Poll::Ready(Err(SysError::ETIMEDOUT));
-                    }
-                }
-
-                // first time?
-                if inner.waker.is_none() {
-                    // futex
-                    let mut futex = inner.futex.inner.lock();
-                    futex.waiters.push_back(self.waiter.clone());
-                    drop(futex);
-                    inner.waker.replace(cx.waker().clone());
-
-                    // timer
-                    if let Some(deadline) = self.deadline {
-                        let waker = cx.waker().clone();
-                        NAIVE_TIMER
-                            .lock()
-                            .add(deadline, Box::new(move |_| waker.wake()));
-                    }
-                }
-                Poll::Pending
+    /// Change the nice value of PI waiter `tid`, as its own boost changed
+    pub fn set_pi_nice(&self, tid: usize, nice: i8) {
+        let inner = self.inner.lock();
+        for waiter in inner.waiters.iter() {
+            if let Some(pi) = waiter.lock().pi.as_mut().filter(|pi| pi.tid == tid) {
+                pi.nice = nice;
             }
         }
+    }
+
+    /// The lowest nice value of the waiters of the PI futex, inherited by its owner
+    pub fn pi_boost(&self) -> Option<i8> {
+        let inner = self.inner.lock();
+        inner
+            .waiters
+            .iter()
+            .filter_map(|waiter| waiter.lock().pi.map(|pi| pi.nice))
+            .min()
+    }
 
+    fn wait_as(
+        self: &Arc<Self>,
+        bitset: u32,
+        pi: Option<PiWaiter>,
+        deadline: Option<Duration>,
+    ) -> FutexFuture {
         FutexFuture {
             waiter: Arc::new(Mutex::new(Waiter {
                 waker: None,
                 woken: false,
-                futex: self.clone(),
+                queued: false,
+                bitset,
+                pi,
             })),
-            deadline: timeout.map(|t| timer_now() + t),
+            futex: self.clone(),
+            deadline,
+        }
+    }
+}
+
+#[must_use = "future does nothing unless polled/`await`-ed"]
+struct FutexFuture {
+    waiter: Arc<Mutex<Waiter>>,
+    futex: Arc<Futex>,
+    deadline: Option<Duration>,
+}
+
+impl Future for FutexFuture {
+    type Output = SysResult;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // the futex is locked before the waiter, as by wake
+        let mut futex = self.futex.inner.lock();
+        let mut inner = self.waiter.lock();
+        // check wakeup
+        if inner.woken {
+            return Poll::Ready(Ok(0));
+        }
+        if let Some(deadline) = self.deadline {
+            if timer_now() >= deadline {
+                inner.woken = true;
+                futex
+                    .waiters
+                    .retain(|waiter| !Arc::ptr_eq(waiter, &self.waiter));
+                return Poll::Ready(Err(SysError::ETIMEDOUT));
+            }
+        }
+
+        // first time?
+        if inner.waker.is_none() {
+            // futex
+            if !inner.queued {
+                futex.waiters.push_back(self.waiter.clone());
+                inner.queued = true;
+            }
+            inner.waker.replace(cx.waker().clone());
+            drop(inner);
+            drop(futex);
+
+            // timer
+            if let Some(deadline) = self.deadline {
+                let waker = cx.waker().clone();
+                NAIVE_TIMER
+                    .lock()
+                    .add(deadline, Box::new(move |_| waker.wake()));
+            }
         }
+        Poll::Pending
     }
 }
@@ -0,0 +1,129 @@
+//! Halting CPUs which have no task to run
+//!
+//! Any CPU polls tasks from the global queue of the executor, and halts when it is empty.
+//! So the waker of each task also sends an IPI to one halted CPU,
+//! which would otherwise sleep until its next timer interrupt.
+//!
+//! The time spent halted is the idle time of `/proc/uptime`,
+//! the part of the uptime in which the CPUs left the host alone.
+
+use crate::arch::cpu;
+use crate::arch::timer::timer_now;
+use crate::consts::MAX_CPU_NUM;
+use alloc::{boxed::Box, sync::Arc, vec::Vec};
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+use core::time::Duration;
+
+lazy_static! {
+    /// Whether each CPU is halted. Not a bit mask, which would not fit
+    /// `MAX_CPU_NUM` CPUs in the atomics of 32-bit targets.
+    static ref IDLE_CPUS: Vec<AtomicBool> =
+        (0..MAX_CPU_NUM).map(|_| AtomicBool::new(false)).collect();
+}
+
+/// Time each CPU has spent halted, updated by itself
+static mut IDLE_TIME: [Duration; MAX_CPU_NUM] = [Duration::from_secs(0); MAX_CPU_NUM];
+
+/// Tasks woken so far
+static WAKEUPS: AtomicUsize = AtomicUsize::new(0);
+
+/// Tasks woken so far, to be given to `idle` after running them
+pub fn wakeups() -> usize {
+    WAKEUPS.load(Ordering::SeqCst)
+}
+
+/// Halt this CPU until an interrupt, unless tasks have been woken since `wakeups`
+pub fn idle(wakeups: usize) {
+    let idle = &IDLE_CPUS[cpu::id()];
+    idle.store(true, Ordering::SeqCst);
+    // a task woken after the mark sends an IPI, which interrupts the halt
+    if WAKEUPS.load(Ordering::SeqCst) == wakeups {
+        let begin = timer_now();
+        crate::arch::interrupt::wait_for_interrupt();
+        unsafe {
+            IDLE_TIME[cpu::id()] += timer_now() - begin;
+        }
+    }
+    idle.store(false, Ordering::SeqCst);
+}
+
+/// Time all CPUs have spent halted
+pub fn idle_time() -> Duration {
+    unsafe { IDLE_TIME.iter().sum() }
+}
+
+/// Send an IPI to a halted CPU other than this one, if there is
+fn kick_idle_cpu() {
+    WAKEUPS.fetch_add(1, Ordering::SeqCst);
+    let this = cpu::id();
+    for (id, idle) in IDLE_CPUS.iter().enumerate() {
+        // each halted CPU is kicked once
+        if id != this && idle.swap(false, Ordering::SeqCst) {
+            #[cfg(any(target_arch = "x86_64", riscv))]
+            cpu::send_ipi(id);
+            return;
+        }
+    }
+}
+
+/// A task whose waker kicks a halted CPU to run it
+pub struct WakeIdle {
+    future: Pin<Box<dyn Future<Output = ()> + Send>>,
+    /// The waker given by the executor, and the one wrapping it
+    waker: Option<(Waker, Waker)>,
+}
+
+impl WakeIdle {
+    pub fn new(future: impl Future<Output = ()> + Send + 'static) -> Self {
+        WakeIdle {
+            future: Box::pin(future),
+            waker: None,
+        }
+    }
+}
+
+impl Future for WakeIdle {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let this = self.get_mut();
+        let same = match &this.waker {
+            Some((waker, _)) => waker.will_wake(cx.waker()),
+            None => false,
+        };
+        if !same {
+            let waker = cx.waker().clone();
+            let data = Arc::into_raw(Arc::new(waker.clone())) as *const ();
+            let wrapped = unsafe { Waker::from_raw(RawWaker::new(data, &VTABLE)) };
+            this.waker = Some((waker, wrapped));
+        }
+        let waker = &this.waker.as_ref().unwrap().1;
+        this.future.as_mut().poll(&mut Context::from_waker(waker))
+    }
+}
+
+static VTABLE: RawWakerVTable = RawWakerVTable::new(clone_raw, wake_raw, wake_by_ref_raw, drop_raw);
+
+unsafe fn clone_raw(data: *const ()) -> RawWaker {
+    let waker = Arc::from_raw(data as *const Waker);
+    let cloned = Arc::into_raw(waker.clone()) as *const ();
+    core::mem::forget(waker);
+    RawWaker::new(cloned, &VTABLE)
+}
+
+unsafe fn wake_raw(data: *const ()) {
+    wake_by_ref_raw(data);
+    drop_raw(data);
+}
+
+unsafe fn wake_by_ref_raw(data: *const ()) {
+    (*(data as *const Waker)).wake_by_ref();
+    kick_idle_cpu();
+}
+
+unsafe fn drop_raw(data: *const ()) {
+    drop(Arc::from_raw(data as *const Waker));
+}
@@ -0,0 +1,64 @@
+//! Load averages, as reported by `sysinfo`
+//!
+//! Every `LOAD_FREQ`, the number of runnable threads is sampled from the timer on CPU 0
+//! and folded into exponentially-weighted averages over 1, 5 and 15 minutes,
+//! in the fixed point of Linux `kernel/sched/loadavg.c`.
+//! A thread is runnable if it is running on a CPU or has yielded its time slice.
+//! Threads woken but not yet polled by the executor are missed.
+
+use super::PROCESSORS;
+use crate::arch::{cpu, timer::timer_now};
+use crate::consts::MAX_CPU_NUM;
+use crate::sync::SpinNoIrqLock as Mutex;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::time::Duration;
+
+/// Bits of fraction in the load averages
+pub const FSHIFT: usize = 11;
+/// 1.0 in fixed point
+const FIXED_1: usize = 1 << FSHIFT;
+/// 1 / exp(5s / 1min), 1 / exp(5s / 5min) and 1 / exp(5s / 15min) in fixed point
+const EXP: [usize; 3] = [1884, 2014, 2037];
+/// Interval between two samples
+const LOAD_FREQ: Duration = Duration::from_secs(5);
+
+/// Threads parked in `yield_now`, which the executor will poll again soon
+pub(super) static YIELDING: AtomicUsize = AtomicUsize::new(0);
+
+struct LoadAvg {
+    /// Time of the next sample
+    next: Duration,
+    /// 1, 5 and 15 minutes averages
+    avenrun: [usize; 3],
+}
+
+static LOAD: Mutex<LoadAvg> = Mutex::new(LoadAvg {
+    next: Duration::from_secs(0),
+    avenrun: [0; 3],
+});
+
+/// Called by the timer, update the averages if it is time to sample
+pub fn sample_load() {
+    if cpu::id() != 0 {
+        return;
+    }
+    let now = timer_now();
+    let mut load = LOAD.lock();
+    if now < load.next {
+        return;
+    }
+    load.next = now + LOAD_FREQ;
+
+    let running = (0..MAX_CPU_NUM)
+        .filter(|&i| unsafe { PROCESSORS[i].is_some() })
+        .count();
+    let active = (running + YIELDING.load(Ordering::Relaxed)) * FIXED_1;
+    for (avg, &exp) in load.avenrun.iter_mut().zip(EXP.iter()) {
+        *avg = (*avg * exp + active * (FIXED_1 - exp)) >> FSHIFT;
+    }
+}
+
+/// 1, 5 and 15 minutes load averages, with `FSHIFT` bits of fraction
+pub fn load_averages() -> [usize; 3] {
+    LOAD.lock().avenrun
+}
@@ -2,46 +2,210 @@ pub use self::structs::*;
 use crate::arch::cpu;
 use crate::{
     consts::{MAX_CPU_NUM, MAX_PROCESS_NUM},
-    memory::phys_to_virt,
+    memory::{phys_to_virt, MemorySet},
     syscall::handle_syscall,
 };
-use alloc::{boxed::Box, sync::Arc};
+use alloc::{
+    boxed::Box,
+    format,
+    string::String,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
 use log::*;
 use trapframe::UserContext;
 
 mod abi;
+mod builder;
+#[cfg(feature = "deterministic")]
+mod deterministic;
 pub mod futex;
+mod idle;
+mod load;
+pub mod pidns;
 pub mod proc;
+pub mod ptrace;
 pub mod structs;
 pub mod thread;
+mod vdso;
 
 use crate::sync::SpinNoIrqLock as Mutex;
+pub use builder::ProcessBuilder;
 use core::{
     future::Future,
     pin::Pin,
+    ptr::{read_volatile, write_volatile},
+    sync::atomic::{fence, Ordering},
     task::{Context, Poll},
 };
 pub use futex::*;
+pub use idle::{idle, idle_time, wakeups};
+pub use load::{load_averages, sample_load, FSHIFT};
+pub use pidns::*;
 pub use proc::*;
+pub use ptrace::*;
 pub use structs::*;
 pub use thread::*;
+pub use vdso::update_vdso;
 
 pub fn init() {
+    crate::syscall::init_trace();
     // create init process
     crate::shell::add_user_shell();
+    #[cfg(feature = "profile")]
+    crate::slab::benchmark();
+    #[cfg(feature = "profile")]
+    crate::fs::page_cache::benchmark();
 
     info!("process: init end");
 }
 
 static mut PROCESSORS: [Option<Arc<Thread>>; MAX_CPU_NUM] = [None; MAX_CPU_NUM];
 
-/// Get current thread
+/// CPUs running the executor, each marking itself in `kmain`
+static mut ONLINE_CPUS: [bool; MAX_CPU_NUM] = [false; MAX_CPU_NUM];
+
+/// Mark this CPU as running threads
+pub fn set_cpu_online() {
+    unsafe { write_volatile(&mut ONLINE_CPUS[cpu::id()], true) }
+}
+
+/// Ids of the CPUs running threads
+pub fn online_cpus() -> Vec<usize> {
+    (0..MAX_CPU_NUM)
+        .filter(|&i| unsafe { read_volatile(&ONLINE_CPUS[i]) })
+        .collect()
+}
+
+/// Spawn a task on the executor of the kernel
+pub fn spawn_task(future: impl Future<Output = ()> + Send + 'static) {
+    #[cfg(not(feature = "deterministic"))]
+    executor::spawn(idle::WakeIdle::new(future));
+    #[cfg(feature = "deterministic")]
+    deterministic::spawn(future);
+}
+
+/// Poll the tasks ready on this CPU until there is none, called by `kmain`
+pub fn run_until_idle() {
+    #[cfg(not(feature = "deterministic"))]
+    executor::run_until_idle();
+    #[cfg(feature = "deterministic")]
+    deterministic::run_until_idle();
+}
+
+/// Content of `/proc/cpuinfo`
+pub fn cpuinfo() -> String {
+    let model = cpu::model_name();
+    let mut info = String::new();
+    for id in online_cpus() {
+        info += &format!("processor\t: {}\nmodel name\t: {}\n\n", id, model);
+    }
+    info
+}
+
+/// Content of `/proc/uptime`: seconds since boot, and seconds all CPUs have been idle
+pub fn uptime() -> String {
+    let (uptime, idle) = (crate::arch::timer::timer_now(), idle_time());
+    format!(
+        "{}.{:02} {}.{:02}\n",
+        uptime.as_secs(),
+        uptime.subsec_millis() / 10,
+        idle.as_secs(),
+        idle.subsec_millis() / 10
+    )
+}
+
+/// Invalidate TLB entries of `[start, end)` on other CPUs
+/// which are running threads sharing the address space `vm`.
 ///
-/// `Thread` is a thread-local object.
-/// It is safe to call this once, and pass `&mut Thread` as a function argument.
+/// CPUs running other address spaces will flush on the next `set_page_table`.
+/// Must be called without holding the lock of `vm`.
+pub fn tlb_shootdown(vm: &Arc<Mutex<MemorySet>>, start: usize, end: usize) {
+    let cpus = other_cpus_running(vm);
+    if !cpus.is_empty() {
+        crate::arch::memory::flush_tlb_on(&cpus, start, end);
+    }
+}
+
+/// Ids of the CPUs other than this one running threads of the address space `vm`
+fn other_cpus_running(vm: &Arc<Mutex<MemorySet>>) -> Vec<usize> {
+    let cpu_id = cpu::id();
+    (0..MAX_CPU_NUM)
+        .filter(|&i| i != cpu_id)
+        .filter(|&i| match unsafe { PROCESSORS[i].as_ref() } {
+            Some(thread) => thread.uses_vm(vm),
+            None => false,
+        })
+        .collect()
+}
+
+lazy_static! {
+    /// Address spaces which may have pages marked by `madvise(MADV_FREE)`
+    static ref FREEABLE_VMS: Mutex<Vec<Weak<Mutex<MemorySet>>>> = Mutex::new(Vec::new());
+}
+
+/// Remember that `vm` has freeable pages, to be dropped by `reclaim_freeable`
+pub fn add_freeable_vm(vm: &Arc<Mutex<MemorySet>>) {
+    let mut vms = FREEABLE_VMS.lock();
+    let vm = Arc::downgrade(vm);
+    if !vms.iter().any(|other| other.ptr_eq(&vm)) {
+        vms.push(vm);
+    }
+}
+
+/// Issue a full memory barrier on this CPU and on other CPUs running threads,
+/// only those sharing the address space `vm` if given, for `membarrier`.
 ///
-/// Don't use it unless necessary.
-pub fn current_thread() -> Option<Arc<Thread>> {
+/// A CPU switching to another thread later orders its accesses by the switch itself.
+pub fn membarrier(vm: Option<&Arc<Mutex<MemorySet>>>) {
     let cpu_id = cpu::id();
-    unsafe { PROCESSORS[cpu_id].clone() }
+    let cpus: Vec<usize> = (0..MAX_CPU_NUM)
+        .filter(|&i| i != cpu_id)
+        .filter(|&i| match unsafe { PROCESSORS[i].as_ref() } {
+            Some(thread) => vm.map_or(true, |vm| thread.uses_vm(vm)),
+            None => false,
+        })
+        .collect();
+    fence(Ordering::SeqCst);
+    if !cpus.is_empty() {
+        crate::arch::memory::fence_on(&cpus);
+    }
+    fence(Ordering::SeqCst);
+}
+
+/// Drop up to `count` pages marked by `madvise(MADV_FREE)` when physical memory is short.
+/// Called from the frame allocator, so address spaces locked by others are skipped.
+/// The TLBs of other CPUs can not be flushed from here, so the frames of an address space
+/// running on them are left to the next shootdown by its threads.
+/// Return the number of pages freed.
+pub fn reclaim_freeable(count: usize) -> usize {
+    let mut vms = match FREEABLE_VMS.try_lock() {
+        Some(vms) => vms,
+        None => return 0,
+    };
+    let mut freed = 0;
+    vms.retain(|vm| {
+        let vm = match vm.upgrade() {
+            Some(vm) => vm,
+            None => return false,
+        };
+        if freed == count || !other_cpus_running(&vm).is_empty() {
+            return true;
+        }
+        let mut inner = match vm.try_lock() {
+            Some(inner) => inner,
+            None => return true,
+        };
+        let dropped = inner.reclaim_freeable(count - freed);
+        // a CPU may have switched to `vm` since the check above, and cached the old
+        // mappings. One switching to it from now on walks the new page table.
+        fence(Ordering::SeqCst);
+        if dropped > 0 && other_cpus_running(&vm).is_empty() {
+            // this CPU flushed its own entries while unmapping
+            inner.take_flush();
+            freed += dropped;
+        }
+        inner.has_freeable()
+    });
+    freed
 }
@@ -0,0 +1,159 @@
+//! PID namespaces
+//!
+//! Every process and thread has a global id, which is its key in `PROCESSES` and `THREADS`.
+//! It also has an id local to its namespace and to each of the ancestors of it,
+//! the first process in a new namespace being local pid 1.
+//! Ids of the root namespace are the global ones.
+
+use super::{lock_process, process, Pid, Process};
+use crate::signal::{send_signal, Siginfo, Signal, SI_KERNEL};
+use crate::sync::SpinNoIrqLock as Mutex;
+use crate::syscall::SysError;
+use alloc::{collections::BTreeMap, sync::Arc, vec::Vec};
+use spin::RwLock;
+
+/// Maximum depth of nested namespaces, as Linux
+const MAX_LEVEL: usize = 32;
+
+pub struct PidNamespace {
+    /// Parent namespace, `None` for the root namespace
+    parent: Option<Arc<PidNamespace>>,
+    /// Depth below the root namespace
+    level: usize,
+    ids: RwLock<Ids>,
+}
+
+#[derive(Default)]
+struct Ids {
+    /// Namespace-local id -> global id
+    globals: BTreeMap<usize, usize>,
+    /// Global id -> namespace-local id
+    locals: BTreeMap<usize, usize>,
+}
+
+lazy_static! {
+    /// The namespace of the init process
+    pub static ref ROOT_PID_NS: Arc<PidNamespace> = Arc::new(PidNamespace {
+        parent: None,
+        level: 0,
+        ids: RwLock::new(Ids::default()),
+    });
+}
+
+impl PidNamespace {
+    /// Create a namespace below `parent`
+    pub fn new_child(parent: &Arc<PidNamespace>) -> Result<Arc<PidNamespace>, SysError> {
+        if parent.level == MAX_LEVEL {
+            return Err(SysError::ENOSPC);
+        }
+        Ok(Arc::new(PidNamespace {
+            parent: Some(parent.clone()),
+            level: parent.level + 1,
+            ids: RwLock::new(Ids::default()),
+        }))
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.parent.is_none()
+    }
+
+    /// This namespace and its ancestors below the root namespace
+    fn ancestors(&self) -> impl Iterator<Item = &PidNamespace> {
+        let mut ns = Some(self);
+        core::iter::from_fn(move || {
+            let cur = ns.filter(|ns| !ns.is_root())?;
+            ns = cur.parent.as_deref();
+            Some(cur)
+        })
+    }
+
+    /// Allocate the lowest free local id for the process or thread with global id `global`
+    /// here and in every ancestor, returning the one of this namespace
+    pub fn alloc(&self, global: Pid) -> usize {
+        for ns in self.ancestors() {
+            let mut ids = ns.ids.write();
+            let local = (Pid::INIT..)
+                .find(|id| !ids.globals.contains_key(id))
+                .unwrap();
+            ids.globals.insert(local, global.get());
+            ids.locals.insert(global.get(), local);
+        }
+        self.local(global).unwrap()
+    }
+
+    /// Release the local ids of the process or thread with global id `global`
+    pub fn free(&self, global: Pid) {
+        for ns in self.ancestors() {
+            let mut ids = ns.ids.write();
+            if let Some(local) = ids.locals.remove(&global.get()) {
+                ids.globals.remove(&local);
+            }
+        }
+    }
+
+    /// Translate an id seen from this namespace to a global id
+    pub fn global(&self, local: usize) -> Option<usize> {
+        if self.is_root() {
+            return Some(local);
+        }
+        self.ids.read().globals.get(&local).cloned()
+    }
+
+    /// Translate a global id to the one seen from this namespace,
+    /// `None` if the process or thread is not visible here
+    pub fn local(&self, global: Pid) -> Option<usize> {
+        if self.is_root() {
+            return Some(global.get());
+        }
+        self.ids.read().locals.get(&global.get()).cloned()
+    }
+
+    /// Whether the process with global pid `global` is the init of this namespace
+    pub fn is_init(&self, global: Pid) -> bool {
+        self.local(global) == Some(Pid::INIT)
+    }
+
+    /// Global ids of all processes and threads visible in this namespace,
+    /// including those in namespaces below it
+    pub fn members(&self) -> Vec<usize> {
+        self.ids.read().locals.keys().cloned().collect()
+    }
+}
+
+impl Process {
+    /// The pid of this process seen from namespace `ns`
+    pub fn pid_in(&self, ns: &PidNamespace) -> Option<usize> {
+        ns.local(self.pid)
+    }
+
+    /// Whether this process can be seen from namespace `ns`
+    pub fn visible_in(&self, ns: &PidNamespace) -> bool {
+        self.pid_in(ns).is_some()
+    }
+}
+
+/// When the init of a namespace exits, kill all the other processes in it.
+/// Must be called without holding the lock of any process.
+pub fn pid_ns_exit(proc: &Arc<Mutex<Process>>) {
+    let (ns, pid) = {
+        let proc = lock_process(proc);
+        (proc.pid_ns.clone(), proc.pid)
+    };
+    if ns.is_root() || !ns.is_init(pid) {
+        return;
+    }
+    let info = Siginfo {
+        signo: Signal::SIGKILL as i32,
+        errno: 0,
+        code: SI_KERNEL,
+        field: Default::default(),
+    };
+    for global in ns.members() {
+        if global == pid.get() {
+            continue;
+        }
+        if let Some(member) = process(global) {
+            send_signal(member, -1, info);
+        }
+    }
+}
@@ -1,18 +1,28 @@
 use super::{
     abi::{self, ProcInitInfo},
-    Futex, Tid,
+    lock_memory_set, pid_ns_exit, Futex, PidNamespace, Ptrace, Thread, Tid, FUTEX_OWNER_DIED,
+    FUTEX_TID_MASK, FUTEX_WAITERS, ROBUST_LIST_LIMIT,
 };
 use crate::arch::paging::*;
-use crate::fs::{FileHandle, FileLike, OpenOptions, FOLLOW_MAX_DEPTH};
+use crate::fs::{
+    aio::AioContext, mount::MountNamespace, FileHandle, FileLike, OpenOptions, FOLLOW_MAX_DEPTH,
+};
 use crate::ipc::{SemProc, ShmProc};
 use crate::memory::{
     phys_to_virt, ByFrame, Delay, File, GlobalFrameAlloc, KernelStack, MemoryAttr, MemorySet, Read,
 };
 use crate::process::thread::THREADS;
-use crate::sync::{Event, EventBus, SpinLock, SpinNoIrqLock as Mutex};
+use crate::sync::{
+    lock_ordered, Event, EventBus, LockClass, MutexGuard, OrderedGuard, SpinLock, SpinNoIrq,
+    SpinNoIrqLock as Mutex,
+};
 use crate::{
-    signal::{Siginfo, Signal, SignalAction, SignalStack, Sigset},
-    syscall::handle_syscall,
+    signal::{
+        notify_parent, send_signal, Siginfo, Signal, SignalAction, SignalActionFlags, SignalStack,
+        Sigset, CLD_CONTINUED, CLD_DUMPED, CLD_EXITED, CLD_KILLED, CLD_STOPPED, CLD_TRAPPED,
+        CORE_DUMP_FLAG, SIG_IGN, SI_USER,
+    },
+    syscall::{handle_syscall, WaitOptions},
 };
 use alloc::{
     boxed::Box, collections::BTreeMap, collections::VecDeque, string::String, sync::Arc,
@@ -23,8 +33,9 @@ use core::fmt;
 use core::str;
 use core::{
     future::Future,
-    mem::MaybeUninit,
+    mem::{replace, MaybeUninit},
     pin::Pin,
+    sync::atomic::{AtomicI32, Ordering},
     task::{Context, Poll},
 };
 use log::*;
@@ -70,6 +81,68 @@ impl fmt::Display for Pid {
 /// process group id type
 pub type Pgid = i32;
 
+/// Children selected by wait4 and waitid
+#[derive(Debug, Clone, Copy)]
+pub enum WaitFor {
+    AnyChild,
+    /// Any child in this process group
+    Group(Pgid),
+    /// The child with this global pid
+    Pid(usize),
+}
+
+impl WaitFor {
+    /// Whether the child with global `pid` in group `pgid` is selected
+    pub fn selects(self, pid: usize, pgid: Pgid) -> bool {
+        match self {
+            WaitFor::AnyChild => true,
+            WaitFor::Group(group) => group == pgid,
+            WaitFor::Pid(target) => target == pid,
+        }
+    }
+
+    /// Key on the event bus of a waiter for `self` with `options`,
+    /// see `Process::wake_child_waiter`
+    pub fn key(self, options: WaitOptions) -> usize {
+        let target = match self {
+            WaitFor::AnyChild => 0,
+            WaitFor::Pid(pid) => pid << 1,
+            WaitFor::Group(pgid) => (pgid as usize) << 1 | 1,
+        };
+        // the changes to report fit in the low bits, and WNOWAIT takes the bit of WNOHANG,
+        // which never waits
+        let reports = options & WAIT_KEY_REPORTS;
+        let nowait = options.contains(WaitOptions::NOWAIT) as usize;
+        target << WAIT_KEY_SHIFT | reports.bits() | nowait
+    }
+
+    fn from_key(key: usize) -> (Self, WaitOptions) {
+        let mut options = WaitOptions::from_bits_truncate(key) & WAIT_KEY_REPORTS;
+        options.set(WaitOptions::NOWAIT, key & 1 != 0);
+        let target = match key >> WAIT_KEY_SHIFT {
+            0 => WaitFor::AnyChild,
+            target if target & 1 == 0 => WaitFor::Pid(target >> 1),
+            target => WaitFor::Group((target >> 1) as Pgid),
+        };
+        (target, options)
+    }
+}
+
+/// Bits of `WaitFor::key` taken by the options
+const WAIT_KEY_SHIFT: usize = 4;
+/// Options in `WaitFor::key` as they are
+const WAIT_KEY_REPORTS: WaitOptions = WaitOptions::from_bits_truncate(
+    WaitOptions::UNTRACED.bits() | WaitOptions::EXITED.bits() | WaitOptions::CONTINUED.bits(),
+);
+
+/// Longest chain of PI futex owners a boost is passed along, in case it loops
+const PI_CHAIN_MAX: usize = 1024;
+
+/// personality(2): disable address space randomization
+pub const ADDR_NO_RANDOMIZE: usize = 0x0040000;
+/// personality(2): readable memory is also executable
+pub const READ_IMPLIES_EXEC: usize = 0x0400000;
+
 pub struct Process {
     /// Virtual memory
     pub vm: Arc<Mutex<MemorySet>>,
@@ -80,6 +153,9 @@ pub struct Process {
     /// Current working dirctory
     pub cwd: String,
 
+    /// Root directory set by chroot, and its path outside of it. None for the real root
+    pub root: Option<(String, Arc<dyn INode>)>,
+
     /// Executable path
     pub exec_path: String,
 
@@ -112,6 +188,15 @@ pub struct Process {
     /// Exit code
     pub exit_code: usize,
 
+    /// `CLD_*` code and status of the exit, for SIGCHLD and waitid
+    pub exit_reason: (i32, i32),
+
+    /// Signal which stopped the process, `None` if it is running
+    pub stopped: Option<Signal>,
+
+    /// `CLD_STOPPED` or `CLD_CONTINUED` if the last stop or continue is not reported by wait
+    pub job_report: Option<i32>,
+
     // delivered signals, tid specified thread, -1 stands for any thread
     // TODO: implement with doubly linked list, but how to do it in rust safely? [doggy]
     pub sig_queue: VecDeque<(Siginfo, isize)>,
@@ -122,6 +207,40 @@ pub struct Process {
 
     /// shared memory
     pub shm_identifiers: ShmProc,
+
+    /// AIO contexts created by io_setup, by the address of their ring page
+    pub aio_contexts: BTreeMap<usize, Arc<AioContext>>,
+
+    /// ptrace state if this process is traced
+    pub ptrace: Option<Ptrace>,
+
+    /// Execution domain set by personality(2)
+    pub personality: usize,
+
+    /// Set by PR_SET_NO_NEW_PRIVS, then inherited and never cleared
+    pub no_new_privs: bool,
+
+    /// Whether a core dump is reported when killed, set by PR_SET_DUMPABLE and reset by execve
+    pub dumpable: bool,
+
+    /// Signal sent to this process when its parent thread exits, set by PR_SET_PDEATHSIG
+    pub pdeathsig: Option<Signal>,
+
+    /// Thread of the parent which created this process, or took it over from the creator
+    pub parent_thread: usize,
+
+    /// Nice value from -20 to 19 set by setpriority, weighing the time slices of the threads
+    pub nice: i8,
+
+    /// PID namespace this process is in
+    pub pid_ns: Arc<PidNamespace>,
+
+    /// PID namespace of children created afterwards,
+    /// changed by unshare(CLONE_NEWPID)
+    pub pid_ns_for_children: Arc<PidNamespace>,
+
+    /// Mount table, shared with the parent unless CLONE_NEWNS
+    pub mount_ns: Arc<RwLock<MountNamespace>>,
 }
 
 lazy_static! {
@@ -130,27 +249,185 @@ lazy_static! {
         RwLock::new(BTreeMap::new());
 }
 
+/// Lock `proc`, checking the order as `Thread::lock_proc` does.
+/// Of two processes, a parent is always locked before its child.
+pub fn lock_process(proc: &Mutex<Process>) -> OrderedGuard<MutexGuard<'_, Process, SpinNoIrq>> {
+    lock_ordered(LockClass::Process, || proc.lock())
+}
+
+/// Capabilities letting a process bypass permission checks, by their numbers on Linux
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capability {
+    Fowner = 3,
+    SysChroot = 18,
+    SysPtrace = 19,
+    SysAdmin = 21,
+    SysNice = 23,
+    SysResource = 24,
+    Mknod = 27,
+}
+
+/// Number of the last capability of Linux
+pub const CAP_LAST_CAP: usize = 40;
+
+/// Effective uid of every process.
+/// There are no credentials, so all processes run as root.
+pub const EUID: u32 = 0;
+
+/// Capability sets of a thread, a bit for each capability, see capabilities(7)
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CapSets {
+    pub effective: u64,
+    pub permitted: u64,
+    pub inheritable: u64,
+}
+
+impl CapSets {
+    /// The sets given by exec to a thread of effective uid `euid`:
+    /// all capabilities for root, and none for others
+    pub fn for_euid(euid: u32) -> Self {
+        let all = match euid {
+            0 => (1 << (CAP_LAST_CAP + 1)) - 1,
+            _ => 0,
+        };
+        CapSets {
+            effective: all,
+            permitted: all,
+            inheritable: 0,
+        }
+    }
+}
+
+/// Whether the current thread has capability `cap`.
+/// As on Linux, a process of euid 0 has all of them, unless it drops some with `capset`.
+pub fn capable(cap: Capability) -> bool {
+    match Thread::try_current() {
+        Some(thread) => thread.inner.lock().caps.effective & (1 << cap as u64) != 0,
+        // the kernel itself
+        None => true,
+    }
+}
+
+/// Clean up after process `proc` has exited, which must not be locked:
+/// tell its parent, end its pid namespace if it is the init,
+/// and send children their parent-death signals.
+/// Orphans are not reparented, so their `getppid` returns 0.
+pub fn process_exited(proc: &Arc<Mutex<Process>>) {
+    notify_exit(proc);
+    pid_ns_exit(proc);
+    let children = lock_process(proc).children.clone();
+    for child in children.iter().filter_map(|(_, child)| child.upgrade()) {
+        let signal = lock_process(&child).pdeathsig;
+        send_pdeathsig(child, signal);
+    }
+}
+
+/// Clean up after thread `tid` of `proc` has exited leaving others, which must not be locked.
+/// As in Linux, the children it created get their parent-death signals,
+/// then another thread takes them over.
+pub fn thread_exited(proc: &Arc<Mutex<Process>>, tid: usize) {
+    let (children, heir) = {
+        let proc = lock_process(proc);
+        (proc.children.clone(), proc.threads.first().cloned())
+    };
+    let heir = match heir {
+        Some(heir) => heir,
+        // the process exits, see `process_exited`
+        None => return,
+    };
+    for child in children.iter().filter_map(|(_, child)| child.upgrade()) {
+        let signal = {
+            let mut child = lock_process(&child);
+            if child.parent_thread != tid {
+                continue;
+            }
+            child.parent_thread = heir;
+            child.pdeathsig
+        };
+        send_pdeathsig(child, signal);
+    }
+}
+
+/// Send SIGCHLD to the parent of the exited process `proc` and wake its waiters.
+/// The parent is locked after `proc`, which would deadlock with wait4
+/// locking its children, so it is done here instead of in `Process::exit`.
+fn notify_exit(proc: &Arc<Mutex<Process>>) {
+    let (parent, pid, pgid, pid_ns, (code, status)) = {
+        let proc = lock_process(proc);
+        let parent = proc.parent.1.upgrade();
+        (
+            parent,
+            proc.pid,
+            proc.pgid,
+            proc.pid_ns.clone(),
+            proc.exit_reason,
+        )
+    };
+    let parent = match parent {
+        Some(parent) => parent,
+        None => return,
+    };
+    notify_parent(&parent, pid, code, status);
+    let mut parent = lock_process(&parent);
+    // a parent ignoring SIGCHLD or with SA_NOCLDWAIT leaves no zombie to wait for,
+    // but the init of a pid namespace is kept for pid_ns_exit.
+    // A waiter may have reaped it already.
+    if parent.ignores_children()
+        && !pid_ns.is_init(pid)
+        && parent.children.iter().any(|(p, _)| *p == pid)
+    {
+        parent.children.retain(|(p, _)| *p != pid);
+        PROCESSES.write().remove(&pid.get());
+        pid_ns.free(pid);
+    }
+    parent.wake_child_waiter(pid, pgid, code);
+}
+
+/// Report a stop or continue of `proc`, which must not be locked, to its parent,
+/// with `code` and `status` returned by `Process::stop` or `Process::cont`.
+/// Like `notify_exit`, the parent can not be locked while holding the child.
+pub fn notify_job(proc: &Arc<Mutex<Process>>, code: i32, status: i32) {
+    let (parent, pid, pgid) = {
+        let proc = lock_process(proc);
+        (proc.parent.1.upgrade(), proc.pid, proc.pgid)
+    };
+    if let Some(parent) = parent {
+        notify_parent(&parent, pid, code, status);
+        lock_process(&parent).wake_child_waiter(pid, pgid, code);
+    }
+}
+
+fn send_pdeathsig(child: Arc<Mutex<Process>>, signal: Option<Signal>) {
+    if let Some(signal) = signal {
+        let info = Siginfo {
+            signo: signal as i32,
+            errno: 0,
+            code: SI_USER,
+            field: Default::default(),
+        };
+        send_signal(child, -1, info);
+    }
+}
+
 /// Return the process which thread tid is in
 pub fn process_of(tid: usize) -> Option<Arc<Mutex<Process>>> {
     PROCESSES
         .read()
         .iter()
         .map(|(_, proc)| proc.clone())
-        .find(|proc| proc.lock().threads.contains(&tid))
+        .find(|proc| lock_process(proc).threads.contains(&tid))
 }
 
-/// Get process by pid
+/// Get process by pid, which may have exited but not been reaped.
+/// See `Process::find` for live ones.
 pub fn process(pid: usize) -> Option<Arc<Mutex<Process>>> {
     PROCESSES.read().get(&pid).cloned()
 }
 
-/// Get process group by pgid
+/// Get live processes of a process group by pgid
 pub fn process_group(pgid: Pgid) -> Vec<Arc<Mutex<Process>>> {
-    PROCESSES
-        .read()
-        .iter()
-        .map(|(_, proc)| proc.clone())
-        .filter(|proc| proc.lock().pgid == pgid)
+    Process::iter()
+        .filter(|proc| lock_process(proc).pgid == pgid)
         .collect::<Vec<_>>()
 }
 
@@ -158,14 +435,40 @@ pub fn process_group(pgid: Pgid) -> Vec<Arc<Mutex<Process>>> {
 pub fn add_to_process_table(proc: Arc<Mutex<Process>>, pid: Pid) {
     let mut process_table = PROCESSES.write();
 
-    // set pid
-    proc.lock().pid = pid;
+    // set pid and allocate the namespace-local one
+    {
+        let mut proc = lock_process(&proc);
+        proc.pid = pid;
+        proc.pid_ns.alloc(pid);
+    }
 
     // put to process table
     process_table.insert(pid.get(), proc.clone());
 }
 
+/// Remove a reaped process from global process table and its namespace.
+pub fn remove_from_process_table(pid: Pid) {
+    let proc = PROCESSES.write().remove(&pid.get());
+    if let Some(proc) = proc {
+        lock_process(&proc).pid_ns.free(pid);
+    }
+}
+
 impl Process {
+    /// Get a live process by global pid, `None` if it has exited or does not exist
+    pub fn find(pid: usize) -> Option<Arc<Mutex<Process>>> {
+        process(pid).filter(|proc| !lock_process(proc).exited())
+    }
+
+    /// Iterate over live processes in the order of global pid.
+    /// The table is copied first, so processes may be locked and created meanwhile.
+    pub fn iter() -> impl Iterator<Item = Arc<Mutex<Process>>> {
+        let procs: Vec<_> = PROCESSES.read().values().cloned().collect();
+        procs
+            .into_iter()
+            .filter(|proc| !lock_process(proc).exited())
+    }
+
     /// Get lowest free fd
     fn get_free_fd(&self) -> usize {
         (0..).find(|i| !self.files.contains_key(i)).unwrap()
@@ -191,37 +494,268 @@ impl Process {
         self.futexes.get(&uaddr).unwrap().clone()
     }
 
+    /// Let thread `tid` inherit the lowest nice value of the waiters of the PI futexes it owns.
+    /// If it waits for a PI futex itself, the change goes on to the owner of that one,
+    /// and so on along the chain of owners.
+    pub fn update_pi_boost(&self, mut tid: usize) {
+        for _ in 0..PI_CHAIN_MAX {
+            let boost = self
+                .futexes
+                .values()
+                .filter(|futex| futex.inner.lock().pi_owner == tid)
+                .filter_map(|futex| futex.pi_boost())
+                .min();
+            let thread = match THREADS.read().get(&tid) {
+                Some(thread) => thread.clone(),
+                None => return,
+            };
+            let blocked_on = {
+                let mut inner = thread.inner.lock();
+                if inner.pi_boost == boost {
+                    return;
+                }
+                inner.pi_boost = boost;
+                inner.pi_blocked_on
+            };
+            let futex = match blocked_on.and_then(|uaddr| self.futexes.get(&uaddr)) {
+                Some(futex) => futex,
+                None => return,
+            };
+            // as `effective_nice`
+            futex.set_pi_nice(tid, self.nice.min(boost.unwrap_or(i8::MAX)));
+            let owner = futex.inner.lock().pi_owner;
+            if owner == 0 || owner == tid {
+                return;
+            }
+            tid = owner;
+        }
+    }
+
+    /// Release the futexes `thread` holds as it exits, or as exec drops the memory set.
+    ///
+    /// PI futexes with waiters go to the next owner. Then each futex in the robust list
+    /// of the thread still owned by it is marked `FUTEX_OWNER_DIED`, and a waiter of
+    /// a non-PI one is woken to take it over, see `set_robust_list(2)`.
+    /// User memory is accessed, so it must be called in a thread of the process.
+    pub fn release_futexes(&self, thread: &Thread) {
+        let tid = thread.tid;
+        let word_tid = self.pid_ns.local(Pid(tid)).unwrap_or(tid) as u32;
+        let vm = lock_memory_set(&self.vm);
+        let owned: Vec<_> = self
+            .futexes
+            .iter()
+            .filter(|(_, futex)| futex.inner.lock().pi_owner == tid)
+            .map(|(&uaddr, futex)| (uaddr, futex.clone()))
+            .collect();
+        for (uaddr, futex) in owned {
+            if let Ok(word) = unsafe { vm.check_write_ptr(uaddr as *mut AtomicI32) } {
+                if let Some(next) = futex.unlock_pi(word, FUTEX_OWNER_DIED) {
+                    self.update_pi_boost(next);
+                }
+            }
+        }
+
+        let head = replace(&mut thread.inner.lock().robust_list, 0);
+        let (mut entry, offset, pending) =
+            match unsafe { vm.check_read_array(head as *const usize, 3) } {
+                Ok(head) => (head[0], head[1], head[2]),
+                Err(_) => return,
+            };
+        // the low bit of a pointer to an entry tells if its futex is PI
+        for _ in 0..ROBUST_LIST_LIMIT {
+            if entry & !1 == head {
+                break;
+            }
+            let next = match unsafe { vm.check_read_ptr((entry & !1) as *const usize) } {
+                Ok(next) => *next,
+                Err(_) => break,
+            };
+            // the one being locked or unlocked is done last
+            if entry & !1 != pending & !1 {
+                self.robust_owner_died(&vm, entry, offset, word_tid);
+            }
+            entry = next;
+        }
+        if pending != 0 {
+            self.robust_owner_died(&vm, pending, offset, word_tid);
+        }
+    }
+
+    /// Mark the futex of robust list `entry` with `FUTEX_OWNER_DIED` if its owner is `word_tid`
+    fn robust_owner_died(&self, vm: &MemorySet, entry: usize, offset: usize, word_tid: u32) {
+        let uaddr = (entry & !1).wrapping_add(offset);
+        if uaddr % 4 != 0 {
+            return;
+        }
+        let word = match unsafe { vm.check_write_ptr(uaddr as *mut AtomicI32) } {
+            Ok(word) => word,
+            Err(_) => return,
+        };
+        loop {
+            let value = word.load(Ordering::Acquire) as u32;
+            if value & FUTEX_TID_MASK != word_tid {
+                return;
+            }
+            let new = (value & FUTEX_WAITERS) | FUTEX_OWNER_DIED;
+            if word
+                .compare_exchange(
+                    value as i32,
+                    new as i32,
+                    Ordering::AcqRel,
+                    Ordering::Acquire,
+                )
+                .is_ok()
+            {
+                // a PI futex with waiters was handed over already
+                if entry & 1 == 0 && value & FUTEX_WAITERS != 0 {
+                    if let Some(futex) = self.futexes.get(&uaddr) {
+                        futex.wake(1);
+                    }
+                }
+                return;
+            }
+        }
+    }
+
     /// Exit the process.
     /// Kill all threads and notify parent with the exit code.
     pub fn exit(&mut self, exit_code: usize) {
+        self.exit_with(exit_code, CLD_EXITED, exit_code as i32);
+    }
+
+    /// Exit the process as the default action of `signal`, dumping core if `core` and dumpable
+    pub fn exit_by_signal(&mut self, signal: Signal, core: bool) {
+        let signo = signal as usize;
+        if core && self.dumpable {
+            self.exit_with(signo | CORE_DUMP_FLAG, CLD_DUMPED, signo as i32);
+        } else {
+            self.exit_with(signo + 128, CLD_KILLED, signo as i32);
+        }
+    }
+
+    /// Exit with `exit_code` for wait4, and `code` and `status` for SIGCHLD,
+    /// which is sent to the parent by `process_exited`
+    fn exit_with(&mut self, exit_code: usize, code: i32, status: i32) {
         // avoid some strange dead lock
         // self.files.clear(); this does not work sometime, for unknown reason
         // manually drop
         let fds = self.files.iter().map(|(fd, _)| *fd).collect::<Vec<_>>();
         for fd in fds.iter() {
             let file = self.files.remove(fd).unwrap();
+            if let FileLike::File(file) = &file {
+                file.sync_cache().ok();
+            }
             drop(file);
         }
-
-        // notify parent and fill exit code
-        self.eventbus.lock().set(Event::PROCESS_QUIT);
-        if let Some(parent) = self.parent.1.upgrade() {
-            parent.lock().eventbus.lock().set(Event::CHILD_PROCESS_QUIT);
+        // operations in flight would keep the memory set alive
+        for context in self.aio_contexts.values() {
+            context.cancel();
         }
+        self.aio_contexts.clear();
+
+        // fill exit code
         self.exit_code = exit_code;
+        self.exit_reason = (code, status);
+        self.eventbus.lock().set(Event::PROCESS_QUIT);
 
         // quit all threads
         // this must be after setting the value of subprocess, or the threads will be treated exit before actually exits
+        let threads: Vec<_> = {
+            let thread_table = THREADS.read();
+            self.threads
+                .iter()
+                .filter_map(|tid| thread_table.get(tid).cloned())
+                .collect()
+        };
+        for thread in threads.iter() {
+            self.release_futexes(thread);
+        }
         // remove from thread table
         let mut thread_table = THREADS.write();
-        for tid in self.threads.iter() {
-            thread_table.remove(tid);
+        for &tid in self.threads.iter() {
+            thread_table.remove(&tid);
+            if tid != self.pid.get() {
+                self.pid_ns.free(Pid(tid));
+            }
         }
         self.threads.clear();
 
         info!("process {} exit with {}", self.pid.get(), exit_code);
     }
 
+    /// Stop the process by `signal`, its threads park when they return to user.
+    /// Return the change to report by `notify_job` once the process is unlocked,
+    /// as `CLD_*` code and signal, `None` if it was stopped already.
+    #[must_use]
+    pub fn stop(&mut self, signal: Signal) -> Option<(i32, i32)> {
+        if self.stopped.is_some() {
+            return None;
+        }
+        info!("process {} stopped by {:?}", self.pid, signal);
+        self.stopped = Some(signal);
+        self.job_report = Some(CLD_STOPPED);
+        self.eventbus.lock().clear(Event::PROCESS_CONTINUE);
+        Some((CLD_STOPPED, signal as i32))
+    }
+
+    /// Resume a stopped process. If `report`, return the change to report
+    /// by `notify_job` once the process is unlocked, as for `stop`.
+    #[must_use]
+    pub fn cont(&mut self, report: bool) -> Option<(i32, i32)> {
+        if self.stopped.take().is_none() {
+            return None;
+        }
+        info!("process {} continued", self.pid);
+        self.eventbus.lock().set(Event::PROCESS_CONTINUE);
+        if report {
+            self.job_report = Some(CLD_CONTINUED);
+            Some((CLD_CONTINUED, Signal::SIGCONT as i32))
+        } else {
+            self.job_report = None;
+            None
+        }
+    }
+
+    /// Wake waiters of wait4 or waitid which select the child `pid` in group `pgid`
+    /// and report its change `code`, one of `CLD_*`, up to the first one reaping it.
+    /// Those with `WNOWAIT` leave it to the next ones.
+    pub fn wake_child_waiter(&self, pid: Pid, pgid: Pgid, code: i32) {
+        self.eventbus
+            .lock()
+            .notify_one(Event::CHILD_PROCESS_QUIT, move |key| {
+                let (target, options) = WaitFor::from_key(key);
+                let reported = match code {
+                    CLD_STOPPED => options.contains(WaitOptions::UNTRACED),
+                    CLD_CONTINUED => options.contains(WaitOptions::CONTINUED),
+                    // the tracer gets ptrace stops even without WUNTRACED
+                    CLD_TRAPPED => true,
+                    _ => options.contains(WaitOptions::EXITED),
+                };
+                match reported && target.selects(pid.get(), pgid) {
+                    true => Some(!options.contains(WaitOptions::NOWAIT)),
+                    false => None,
+                }
+            });
+    }
+
+    /// An unreported stop if `stopped`, or an unreported continue if `continued`,
+    /// as `CLD_*` code and signal
+    pub fn pending_job(&self, stopped: bool, continued: bool) -> Option<(i32, i32)> {
+        match self.job_report {
+            Some(CLD_STOPPED) if stopped => Some((CLD_STOPPED, self.stopped? as i32)),
+            Some(CLD_CONTINUED) if continued => Some((CLD_CONTINUED, Signal::SIGCONT as i32)),
+            _ => None,
+        }
+    }
+
+    /// Whether exited children are reaped without wait4,
+    /// as SIGCHLD is ignored or has SA_NOCLDWAIT
+    pub fn ignores_children(&self) -> bool {
+        let action = &self.dispositions[Signal::SIGCHLD as usize];
+        let flags = SignalActionFlags::from_bits_truncate(action.flags);
+        action.handler == SIG_IGN || flags.contains(SignalActionFlags::NOCLDWAIT)
+    }
+
     pub fn exited(&self) -> bool {
         self.threads.is_empty()
     }
@@ -0,0 +1,87 @@
+//! Tracee side of ptrace: stop states and syscall stops
+
+use super::{notify_job, Thread};
+use crate::signal::{Signal, CLD_TRAPPED};
+use crate::sync::{wait_for_event, Event};
+use alloc::sync::Arc;
+
+/// The reason a tracee is stopped
+#[derive(Debug, Clone, Copy)]
+pub enum PtraceStop {
+    /// Stopped before executing a syscall
+    SyscallEntry {
+        nr: usize,
+        args: [usize; 6],
+        pc: usize,
+        sp: usize,
+    },
+    /// Stopped after executing a syscall
+    SyscallExit { ret: isize, pc: usize, sp: usize },
+    /// Stopped after a successful execve
+    Exec,
+}
+
+/// Per-process ptrace state of a tracee.
+/// The tracer is always the parent process (`PTRACE_TRACEME` or `PTRACE_ATTACH`).
+#[derive(Debug, Default)]
+pub struct Ptrace {
+    /// Stop on next syscall entry and exit (`PTRACE_SYSCALL`)
+    pub syscall_trace: bool,
+    /// Current stop, if any
+    pub stop: Option<PtraceStop>,
+    /// Whether the current stop has been reported by wait4 or waitid
+    pub reported: bool,
+    /// Set bit 7 of the signal number in syscall stops (`PTRACE_O_TRACESYSGOOD`)
+    pub sysgood: bool,
+}
+
+impl Ptrace {
+    /// The stop signal of the current stop if it has not been reported,
+    /// with bit 7 set for syscall stops if `sysgood`
+    pub fn pending(&self) -> Option<i32> {
+        let stop = match self.stop {
+            Some(stop) if !self.reported => stop,
+            _ => return None,
+        };
+        let mut signo = Signal::SIGTRAP as i32;
+        match stop {
+            PtraceStop::SyscallEntry { .. } | PtraceStop::SyscallExit { .. } if self.sysgood => {
+                signo |= 0x80;
+            }
+            _ => {}
+        }
+        Some(signo)
+    }
+
+    /// Whether the process of `thread` is traced
+    pub fn traced(thread: &Arc<Thread>) -> bool {
+        thread.lock_proc().ptrace.is_some()
+    }
+
+    /// Whether the tracee should stop around syscalls
+    pub fn tracing_syscall(thread: &Arc<Thread>) -> bool {
+        let proc = thread.lock_proc();
+        proc.ptrace.as_ref().map_or(false, |p| p.syscall_trace)
+    }
+}
+
+/// Stop the tracee with `stop`, notify the tracer,
+/// and sleep until the tracer resumes it.
+pub async fn ptrace_stop(thread: &Arc<Thread>, stop: PtraceStop) {
+    let eventbus = {
+        let mut proc = thread.lock_proc();
+        match proc.ptrace.as_mut() {
+            Some(ptrace) => {
+                ptrace.stop = Some(stop);
+                ptrace.reported = false;
+            }
+            None => return,
+        }
+        proc.eventbus.lock().clear(Event::PTRACE_RESUME);
+        proc.eventbus.clone()
+    };
+    // the parent is locked after the tracee is unlocked, see `notify_job`
+    notify_job(&thread.proc, CLD_TRAPPED, Signal::SIGTRAP as i32);
+    wait_for_event(eventbus.clone(), Event::PTRACE_RESUME).await;
+    eventbus.lock().clear(Event::PTRACE_RESUME);
+}
@@ -1,5 +1,8 @@
 use super::abi::{self, ProcInitInfo};
+use super::READ_IMPLIES_EXEC;
 use crate::arch::paging::*;
+use crate::consts::USER_SPACE_END;
+use crate::fs::page_cache;
 use crate::fs::{FileHandle, FileLike, OpenOptions, FOLLOW_MAX_DEPTH};
 use crate::ipc::SemProc;
 use crate::memory::{
@@ -53,10 +56,33 @@ impl ToMemoryAttr for Flags {
     }
 }
 
+/// Memory attribute of a loadable segment under `personality`
+fn segment_attr(flags: Flags, personality: usize) -> MemoryAttr {
+    let mut attr = flags.to_attr();
+    if personality & READ_IMPLIES_EXEC != 0 && flags.is_read() {
+        attr = attr.execute();
+    }
+    attr
+}
+
 /// Helper functions to process ELF file
 pub trait ElfExt {
     /// Setup MemorySet according to the ELF file.
-    fn make_memory_set(&self, ms: &mut MemorySet, inode: &Arc<dyn INode>) -> usize;
+    fn make_memory_set(
+        &self,
+        ms: &mut MemorySet,
+        inode: &Arc<dyn INode>,
+        personality: usize,
+    ) -> usize;
+
+    /// Check that PT_LOAD segments loaded at `bias` fit in user space,
+    /// away from the ranges in `taken` and from each other, which are then added to `taken`.
+    /// Return the end of the highest segment.
+    fn check_segments(
+        &self,
+        bias: usize,
+        taken: &mut Vec<(usize, usize)>,
+    ) -> Result<usize, &'static str>;
 
     /// Get interpreter string if it has.
     fn get_interpreter(&self) -> Result<&str, &str>;
@@ -68,24 +94,37 @@ pub trait ElfExt {
         inode: &Arc<dyn INode>,
         memory_set: &mut MemorySet,
         bias: usize,
+        personality: usize,
     );
 
     /// Get virtual address of PHDR section if it has.
     fn get_phdr_vaddr(&self) -> Option<u64>;
+
+    /// Memory attribute of the user stack under `personality`,
+    /// executable only if asked by PT_GNU_STACK
+    fn stack_attr(&self, personality: usize) -> MemoryAttr;
+
+    /// Make the PT_GNU_RELRO range read-only, as ld.so does after relocation
+    fn protect_relro(&self, ms: &mut MemorySet, personality: usize);
 }
 
 impl ElfExt for ElfFile<'_> {
-    fn make_memory_set(&self, ms: &mut MemorySet, inode: &Arc<dyn INode>) -> usize {
+    fn make_memory_set(
+        &self,
+        ms: &mut MemorySet,
+        inode: &Arc<dyn INode>,
+        personality: usize,
+    ) -> usize {
         debug!("creating MemorySet from ELF");
         let mut farthest_memory: usize = 0;
         for ph in self.program_iter() {
-            if ph.get_type() != Ok(Type::Load) {
+            if ph.get_type() != Ok(Type::Load) || ph.mem_size() == 0 {
                 continue;
             }
             ms.push(
                 ph.virtual_addr() as usize,
                 ph.virtual_addr() as usize + ph.mem_size() as usize,
-                ph.flags().to_attr(),
+                segment_attr(ph.flags(), personality),
                 File {
                     file: INodeForMap(inode.clone()),
                     mem_start: ph.virtual_addr() as usize,
@@ -102,17 +141,64 @@ impl ElfExt for ElfFile<'_> {
 
         Page::of_addr(farthest_memory + PAGE_SIZE).start_address()
     }
-    fn append_as_interpreter(&self, inode: &Arc<dyn INode>, ms: &mut MemorySet, bias: usize) {
+    fn check_segments(
+        &self,
+        bias: usize,
+        taken: &mut Vec<(usize, usize)>,
+    ) -> Result<usize, &'static str> {
+        let mut farthest = 0;
+        for ph in self.program_iter() {
+            if ph.get_type() != Ok(Type::Load) || ph.mem_size() == 0 {
+                continue;
+            }
+            if ph.file_size() > ph.mem_size() {
+                return Err("segment is larger in file than in memory");
+            }
+            match ph.offset().checked_add(ph.file_size()) {
+                Some(end) if end <= usize::MAX as u64 => {}
+                _ => return Err("segment is out of file"),
+            }
+            let align = ph.align();
+            if align > 1
+                && (!align.is_power_of_two() || ph.virtual_addr() % align != ph.offset() % align)
+            {
+                return Err("segment is misaligned");
+            }
+            // in u64, as addresses in ELF64 may not fit in a 32-bit usize
+            let end = ph
+                .virtual_addr()
+                .checked_add(bias as u64)
+                .and_then(|start| start.checked_add(ph.mem_size()))
+                .filter(|&end| end <= USER_SPACE_END as u64)
+                .ok_or("segment is out of user space")? as usize;
+            farthest = farthest.max(end);
+            let start = Page::of_addr(ph.virtual_addr() as usize + bias).start_address();
+            let end = Page::of_addr(end - 1).start_address() + PAGE_SIZE;
+            if taken.iter().any(|&(s, e)| start < e && s < end) {
+                return Err("segment overlaps with others");
+            }
+            taken.push((start, end));
+        }
+        Ok(farthest)
+    }
+
+    fn append_as_interpreter(
+        &self,
+        inode: &Arc<dyn INode>,
+        ms: &mut MemorySet,
+        bias: usize,
+        personality: usize,
+    ) {
         debug!("inserting interpreter from ELF");
 
         for ph in self.program_iter() {
-            if ph.get_type() != Ok(Type::Load) {
+            if ph.get_type() != Ok(Type::Load) || ph.mem_size() == 0 {
                 continue;
             }
             ms.push(
                 ph.virtual_addr() as usize + bias,
                 ph.virtual_addr() as usize + ph.mem_size() as usize + bias,
-                ph.flags().to_attr(),
+                segment_attr(ph.flags(), personality),
                 File {
                     file: INodeForMap(inode.clone()),
                     mem_start: ph.virtual_addr() as usize + bias,
@@ -160,13 +246,62 @@ impl ElfExt for ElfFile<'_> {
             None
         }
     }
+
+    fn stack_attr(&self, personality: usize) -> MemoryAttr {
+        // a non-executable stack leaves signal frames without SA_RESTORER unable to return,
+        // as their `RET_CODE` is on the stack, but libc always sets it
+        let attr = MemoryAttr::default().user();
+        match self
+            .program_iter()
+            .find(|ph| ph.get_type() == Ok(Type::OsSpecific(PT_GNU_STACK)))
+        {
+            Some(ph) if !ph.flags().is_execute() && personality & READ_IMPLIES_EXEC == 0 => attr,
+            // old binaries without the header expect an executable stack, as Linux does
+            _ => attr.execute(),
+        }
+    }
+
+    fn protect_relro(&self, ms: &mut MemorySet, personality: usize) {
+        let relro = match self
+            .program_iter()
+            .find(|ph| ph.get_type() == Ok(Type::OsSpecific(PT_GNU_RELRO)))
+        {
+            Some(ph) => ph,
+            None => return,
+        };
+        let start = relro.virtual_addr() as usize;
+        let end = start + relro.mem_size() as usize;
+        // the partial page at the end shares data with the writable rest, so ld.so rounds down
+        let (start, end) = (
+            Page::of_addr(start).start_address(),
+            Page::of_addr(end).start_address(),
+        );
+        if start >= end {
+            return;
+        }
+        let segment = self.program_iter().find(|ph| {
+            ph.get_type() == Ok(Type::Load)
+                && ph.virtual_addr() as usize <= start
+                && end <= (ph.virtual_addr() + ph.mem_size()) as usize
+        });
+        if let Some(segment) = segment {
+            let attr = segment_attr(segment.flags(), personality).readonly();
+            ms.protect(start, end, attr).ok();
+        }
+    }
 }
 
+/// Program header telling whether the stack is executable
+const PT_GNU_STACK: u32 = 0x6474_e551;
+/// Program header of the range to be read-only after relocation
+const PT_GNU_RELRO: u32 = 0x6474_e552;
+
 #[derive(Clone)]
 pub struct INodeForMap(pub Arc<dyn INode>);
 
 impl Read for INodeForMap {
     fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
-        self.0.read_at(offset, buf).unwrap()
+        // go through the page cache to see the latest written data
+        page_cache::read_inode_at(&self.0, offset, buf).unwrap()
     }
 }
@@ -1,28 +1,41 @@
 use super::{
     abi::{self, ProcInitInfo},
-    add_to_process_table, Pid, Process, PROCESSORS,
+    add_to_process_table,
+    load::YIELDING,
+    CapSets, Pid, PidNamespace, Process, ProcessBuilder, ADDR_NO_RANDOMIZE, EUID, PROCESSORS,
+    ROOT_PID_NS,
 };
 use crate::arch::interrupt::consts::{
-    is_intr, is_page_fault, is_reserved_inst, is_syscall, is_timer_intr,
+    exception_signal, is_intr, is_page_fault, is_reserved_inst, is_syscall,
+};
+use crate::arch::interrupt::{
+    ack_and_dispatch, get_page_fault_cause, get_trap_num, handle_reserved_inst,
+    handle_user_page_fault,
 };
-use crate::arch::interrupt::{get_trap_num, handle_reserved_inst, handle_user_page_fault};
 use crate::arch::{
     cpu,
     fp::FpState,
     memory::{get_page_fault_addr, set_page_table},
     paging::*,
 };
-use crate::drivers::IRQ_MANAGER;
-use crate::fs::{FileHandle, FileLike, OpenOptions, FOLLOW_MAX_DEPTH};
+use crate::consts::MAX_CPU_NUM;
+use crate::fs::{lookup_beneath, mount::MountNamespace, page_cache, FileLike, FOLLOW_MAX_DEPTH};
 use crate::ipc::{SemProc, ShmProc};
 use crate::memory::{
-    phys_to_virt, ByFrame, Delay, File, GlobalFrameAlloc, KernelStack, MemoryAttr, MemorySet, Read,
+    phys_to_virt, with_vm, ByFrame, Delay, File, GlobalFrameAlloc, Guard, KernelStack, MemoryAttr,
+    MemorySet, Read,
 };
 use crate::process::structs::ElfExt;
-use crate::sync::{EventBus, SpinLock, SpinNoIrqLock as Mutex};
+use crate::sync::{
+    assert_unlocked, lock_ordered, EventBus, LockClass, MutexGuard, OrderedGuard, SpinLock,
+    SpinNoIrq, SpinNoIrqLock as Mutex,
+};
 use crate::{
-    signal::{handle_signal, Siginfo, Signal, SignalAction, SignalStack, Sigset},
-    syscall::handle_syscall,
+    signal::{
+        force_signal, handle_signal, wait_while_stopped, Siginfo, SiginfoFields, Signal,
+        SignalAction, SignalStack, Sigset, SEGV_ACCERR, SEGV_MAPERR, SI_KERNEL,
+    },
+    syscall::{handle_syscall, user_pc, Seccomp},
 };
 use alloc::{
     boxed::Box, collections::BTreeMap, collections::VecDeque, string::String, sync::Arc,
@@ -33,15 +46,16 @@ use core::fmt;
 use core::str;
 use core::{
     future::Future,
-    mem::MaybeUninit,
+    ops::{Deref, DerefMut},
     pin::Pin,
+    sync::atomic::{fence, AtomicUsize, Ordering},
     task::{Context, Poll},
 };
 use log::*;
 use num::FromPrimitive;
 use pc_keyboard::KeyCode::BackTick;
 use rcore_fs::vfs::INode;
-use rcore_memory::{Page, PAGE_SIZE};
+use rcore_memory::{Page, VMResult, PAGE_SIZE};
 use spin::RwLock;
 use trapframe::TrapFrame;
 use trapframe::UserContext;
@@ -71,16 +85,32 @@ pub struct ThreadInner {
     pub clear_child_tid: usize,
     /// Signal mask
     pub sig_mask: Sigset,
+    /// Mask to restore once a signal interrupts `sigsuspend`
+    pub saved_sig_mask: Option<Sigset>,
     /// signal alternate stack
     pub signal_alternate_stack: SignalStack,
+    /// Syscall filters, inherited by new threads and processes
+    pub seccomp: Seccomp,
+    /// Nice value inherited from waiters of PI futexes owned by the thread
+    pub pi_boost: Option<i8>,
+    /// Address of the PI futex the thread waits for, which passes its boost on to the owner
+    pub pi_blocked_on: Option<usize>,
+    /// Head of the list of robust futexes given to `set_robust_list`, 0 if none
+    pub robust_list: usize,
+    /// Capabilities, inherited by new threads and processes
+    pub caps: CapSets,
 }
 
 #[allow(dead_code)]
 pub struct Thread {
     /// Mutable part
     pub inner: Mutex<ThreadInner>,
-    /// This is same as `proc.vm`, avoid extra locking
-    pub vm: Arc<Mutex<MemorySet>>,
+    /// This is same as `proc.vm`, avoid extra locking.
+    /// Exec gives the thread a new one, see `set_vm`
+    /// Lock `proc` before `vm`, see `sync::lock_order`
+    vm: Mutex<Arc<Mutex<MemorySet>>>,
+    /// Page table token of `vm`, switched to whenever the thread runs
+    vm_token: AtomicUsize,
     /// The process that this thread belongs to
     pub proc: Arc<Mutex<Process>>,
     /// Thread id
@@ -93,7 +123,92 @@ lazy_static! {
         RwLock::new(BTreeMap::new());
 }
 
+/// Range of the random distance between the executable and ld.so, in pages
+const INTERP_SHIFT_PAGES: usize = 0x4000;
+/// Range of the random distance between the user stack and the vDSO, in pages
+const VDSO_SHIFT_PAGES: usize = 0x1000;
+
+/// A random number below `n` to randomize the address space,
+/// or 0 if `personality` asks for ADDR_NO_RANDOMIZE
+fn random_below(personality: usize, n: usize) -> usize {
+    if personality & ADDR_NO_RANDOMIZE != 0 {
+        return 0;
+    }
+    let mut bytes = [0; 8];
+    crate::rand::fill(&mut bytes);
+    u64::from_le_bytes(bytes) as usize % n
+}
+
 impl Thread {
+    /// The thread running on this CPU.
+    ///
+    /// Panic if there is none, e.g. in an interrupt on an idle CPU. Use `try_current` there.
+    /// Syscalls should use the thread they are given instead.
+    pub fn current() -> Arc<Thread> {
+        Self::try_current().expect("no thread running on this CPU")
+    }
+
+    /// The thread running on this CPU, if any
+    pub fn try_current() -> Option<Arc<Thread>> {
+        unsafe { PROCESSORS[cpu::id()].clone() }
+    }
+
+    /// The process of the thread running on this CPU, see `current`
+    pub fn current_proc() -> Arc<Mutex<Process>> {
+        Self::current().proc.clone()
+    }
+
+    /// The CPU running this thread, `None` if it is parked
+    pub fn cpu(&self) -> Option<usize> {
+        (0..MAX_CPU_NUM).find(|&i| match unsafe { PROCESSORS[i].as_ref() } {
+            Some(thread) => thread.tid == self.tid,
+            None => false,
+        })
+    }
+
+    /// Nice value of the process, or that inherited through PI futexes if lower
+    pub fn effective_nice(&self) -> i8 {
+        let nice = self.lock_proc().nice;
+        match self.inner.lock().pi_boost {
+            Some(boost) => nice.min(boost),
+            None => nice,
+        }
+    }
+
+    /// Lock the process of this thread
+    pub fn lock_proc(&self) -> OrderedGuard<MutexGuard<'_, Process, SpinNoIrq>> {
+        lock_process(&self.proc)
+    }
+
+    /// The memory set of this thread
+    pub fn vm(&self) -> Arc<Mutex<MemorySet>> {
+        self.vm.lock().clone()
+    }
+
+    /// Whether this thread runs in the memory set `vm`
+    pub fn uses_vm(&self, vm: &Arc<Mutex<MemorySet>>) -> bool {
+        Arc::ptr_eq(&self.vm.lock(), vm)
+    }
+
+    /// Lock the memory set of this thread.
+    /// Its process must not be locked after this.
+    pub fn lock_vm(&self) -> VmGuard<'_> {
+        let vm = self.vm();
+        // the memory set is kept alive by `VmGuard::_vm` as long as it is locked
+        let mutex = unsafe { &*(&*vm as *const Mutex<MemorySet>) };
+        VmGuard {
+            guard: lock_memory_set(mutex),
+            _vm: vm,
+        }
+    }
+
+    /// Switch to the new memory set `vm` of the process, as exec does.
+    /// The caller must update `proc.vm` and activate `vm` as well.
+    pub fn set_vm(&self, vm: Arc<Mutex<MemorySet>>, token: usize) {
+        *self.vm.lock() = vm;
+        self.vm_token.store(token, Ordering::SeqCst);
+    }
+
     /// Assign a tid and put itself to global thread table.
     pub fn add_to_table(mut self) -> Arc<Self> {
         let mut thread_table = THREADS.write();
@@ -112,21 +227,23 @@ impl Thread {
     }
 
     /// Construct virtual memory of a new user process from ELF at `inode`.
+    /// Its interpreter is looked up in `root`, the root directory of the process, through `mounts`.
     /// Return `(MemorySet, entry_point, ustack_top)`
     pub fn new_user_vm(
         inode: &Arc<dyn INode>,
         args: Vec<String>,
         envs: Vec<String>,
         vm: &mut MemorySet,
+        personality: usize,
+        root: &Arc<dyn INode>,
+        mounts: &MountNamespace,
     ) -> Result<(usize, usize), &'static str> {
-        // Read ELF header
-        // 0x3c0: magic number from ld-musl.so
-        let mut data = [0u8; 0x3c0];
-        inode
-            .read_at(0, &mut data)
-            .map_err(|_| "failed to read from INode")?;
-
-        // Parse ELF
+        use crate::consts::{
+            USEC_PER_TICK, USER_STACK_GUARD_SIZE, USER_STACK_OFFSET, USER_STACK_SIZE,
+        };
+
+        // Read and parse ELF
+        let data = read_elf_headers(inode)?;
         let elf = ElfFile::new(&data)?;
 
         // Check ELF type
@@ -158,30 +275,74 @@ impl Thread {
             map.insert(abi::AT_PHENT, elf.header.pt2.ph_entry_size() as usize);
             map.insert(abi::AT_PHNUM, elf.header.pt2.ph_count() as usize);
             map.insert(abi::AT_PAGESZ, PAGE_SIZE);
+            map.insert(abi::AT_HWCAP, cpu::hwcap());
+            map.insert(abi::AT_CLKTCK, 1_000_000 / USEC_PER_TICK);
+            // everyone is root, and nothing is setuid
+            map.insert(abi::AT_UID, 0);
+            map.insert(abi::AT_EUID, 0);
+            map.insert(abi::AT_GID, 0);
+            map.insert(abi::AT_EGID, 0);
+            map.insert(abi::AT_SECURE, 0);
             map
         };
 
+        // Check interpreter (for dynamic link)
+        // Look it up before clearing `vm`
+        let interp_data;
+        let interp = match elf.get_interpreter() {
+            Ok(loader_path) => {
+                // assuming absolute path, in the root directory of the process
+                let interp_inode = lookup_beneath(
+                    root,
+                    mounts,
+                    root.clone(),
+                    loader_path,
+                    true,
+                    FOLLOW_MAX_DEPTH,
+                )
+                .map_err(|_| "interpreter not found")?;
+                interp_data = read_elf_headers(&interp_inode)?;
+                Some((interp_inode, ElfFile::new(&interp_data)?))
+            }
+            Err(_) => None,
+        };
+
+        // Check the segments before clearing `vm`, so that a bad ELF fails exec harmlessly.
+        // The user stack and its guards are mapped after them.
+        let mut taken = alloc::vec![(
+            USER_STACK_OFFSET - USER_STACK_GUARD_SIZE,
+            USER_STACK_OFFSET + USER_STACK_SIZE + USER_STACK_GUARD_SIZE,
+        )];
+        // ld.so is loaded at a random distance after the executable
+        let interp_shift = random_below(personality, INTERP_SHIFT_PAGES) * PAGE_SIZE;
+        let end = elf.check_segments(0, &mut taken)?;
+        if let Some((_, elf_interp)) = &interp {
+            // at the bias returned by `make_memory_set`
+            let bias = Page::of_addr(end + PAGE_SIZE).start_address() + interp_shift;
+            elf_interp.check_segments(bias, &mut taken)?;
+        }
+
+        // Static executables have nothing to relocate, so RELRO is final right away.
+        // Dynamically-linked ones and static PIE relocate themselves, then mprotect it.
+        let is_static = interp.is_none()
+            && !elf
+                .program_iter()
+                .any(|ph| ph.get_type() == Ok(Type::Dynamic));
+
         // entry point
         let mut entry_addr = elf.header.pt2.entry_point() as usize;
         // Make page table
         vm.clear();
-        let bias = elf.make_memory_set(vm, inode);
+        let bias = elf.make_memory_set(vm, inode, personality) + interp_shift;
+        if is_static {
+            elf.protect_relro(vm, personality);
+        }
 
-        // Check interpreter (for dynamic link)
         // When interpreter is used, map both dynamic linker and executable
-        if let Ok(loader_path) = elf.get_interpreter() {
+        if let Some((interp_inode, elf_interp)) = interp {
             info!("Handling interpreter... offset={:x}", bias);
-            // assuming absolute path
-            let interp_inode = crate::fs::ROOT_INODE
-                .lookup_follow(loader_path, FOLLOW_MAX_DEPTH)
-                .map_err(|_| "interpreter not found")?;
             // load loader by bias and set aux vector.
-            let mut interp_data: [u8; 0x3c0] = unsafe { MaybeUninit::zeroed().assume_init() };
-            interp_inode
-                .read_at(0, &mut interp_data)
-                .map_err(|_| "failed to read from INode")?;
-            let elf_interp = ElfFile::new(&interp_data)?;
-            elf_interp.append_as_interpreter(&interp_inode, vm, bias);
+            elf_interp.append_as_interpreter(&interp_inode, vm, bias, personality);
 
             // update auxiliary vector
             auxv.insert(abi::AT_ENTRY, elf.header.pt2.entry_point() as usize);
@@ -193,102 +354,97 @@ impl Thread {
         }
 
         // User stack
-        use crate::consts::{USER_STACK_OFFSET, USER_STACK_SIZE};
+        let stack_attr = elf.stack_attr(personality);
         let mut ustack_top = {
             let ustack_buttom = USER_STACK_OFFSET;
             let ustack_top = USER_STACK_OFFSET + USER_STACK_SIZE;
 
+            // keep other mappings away from both ends of the stack
+            vm.push(
+                ustack_buttom - USER_STACK_GUARD_SIZE,
+                ustack_buttom,
+                MemoryAttr::default(),
+                Guard,
+                "user_stack_guard",
+            );
+            vm.push(
+                ustack_top,
+                ustack_top + USER_STACK_GUARD_SIZE,
+                MemoryAttr::default(),
+                Guard,
+                "user_stack_guard",
+            );
+
             // user stack except top 4 pages
             vm.push(
                 ustack_buttom,
                 ustack_top - PAGE_SIZE * 4,
-                MemoryAttr::default().user().execute(),
+                stack_attr,
                 Delay::new(GlobalFrameAlloc),
                 "user_stack_delay",
             );
 
-            // We are going to write init info now. So map the last 4 pages eagerly.
+            // We are going to write init info now. So map the last 4 pages eagerly,
+            // faults beyond them are handled in this memory set by `with_vm`.
             vm.push(
                 ustack_top - PAGE_SIZE * 4,
                 ustack_top,
-                MemoryAttr::default().user().execute(), // feature
+                stack_attr,
                 ByFrame::new(GlobalFrameAlloc),
                 "user_stack",
             );
             ustack_top
         };
 
-        // Make init info
-        let init_info = ProcInitInfo { args, envs, auxv };
-        unsafe {
-            vm.with(|| ustack_top = init_info.push_at(ustack_top));
+        let vdso_shift = random_below(personality, VDSO_SHIFT_PAGES) * PAGE_SIZE;
+        if let Some(vdso) = super::vdso::map(vm, vdso_shift) {
+            auxv.insert(abi::AT_SYSINFO_EHDR, vdso);
         }
 
+        // the initial stack pointer moves within a page, 16 bytes aligned
+        ustack_top -= random_below(personality, PAGE_SIZE / 16) * 16;
+
+        // Make init info
+        let mut random = [0; 16];
+        crate::rand::fill(&mut random);
+        let mut init_info = ProcInitInfo {
+            args,
+            envs,
+            auxv,
+            random,
+        };
+        with_vm(vm, || ustack_top = init_info.push_at(ustack_top));
+
         Ok((entry_addr, ustack_top))
     }
 
-    /// Make a new user process from ELF `data`
+    /// Make a new user process from ELF at `inode`, with stdio on `/dev/tty`.
+    /// See `ProcessBuilder` for more options.
     pub fn new_user(
         inode: &Arc<dyn INode>,
         exec_path: &str,
         args: Vec<String>,
         envs: Vec<String>,
-    ) -> Arc<Thread> {
-        // get virtual memory info
-        let mut vm = MemorySet::new();
-        let (entry_addr, ustack_top) = Self::new_user_vm(inode, args, envs, &mut vm).unwrap();
+    ) -> Result<Arc<Thread>, &'static str> {
+        ProcessBuilder::new(inode.clone(), exec_path)
+            .args(args)
+            .envs(envs)
+            .build()
+    }
 
-        let vm_token = vm.token();
+    /// Make the first thread of a new process running from `entry_addr` in `vm`
+    pub(super) fn new_process(
+        vm: MemorySet,
+        entry_addr: usize,
+        ustack_top: usize,
+        files: BTreeMap<usize, FileLike>,
+        cwd: String,
+        root: Option<(String, Arc<dyn INode>)>,
+        mount_ns: Arc<RwLock<MountNamespace>>,
+        exec_path: &str,
+    ) -> Arc<Thread> {
         let vm = Arc::new(Mutex::new(vm));
 
-        // initial fds
-        let mut files = BTreeMap::new();
-        files.insert(
-            0,
-            FileLike::File(FileHandle::new(
-                crate::fs::TTY.clone(),
-                OpenOptions {
-                    read: true,
-                    write: false,
-                    append: false,
-                    nonblock: false,
-                },
-                String::from("/dev/tty"),
-                false,
-                false,
-            )),
-        );
-        files.insert(
-            1,
-            FileLike::File(FileHandle::new(
-                crate::fs::TTY.clone(),
-                OpenOptions {
-                    read: false,
-                    write: true,
-                    append: false,
-                    nonblock: false,
-                },
-                String::from("/dev/tty"),
-                false,
-                false,
-            )),
-        );
-        files.insert(
-            2,
-            FileLike::File(FileHandle::new(
-                crate::fs::TTY.clone(),
-                OpenOptions {
-                    read: false,
-                    write: true,
-                    append: false,
-                    nonblock: false,
-                },
-                String::from("/dev/tty"),
-                false,
-                false,
-            )),
-        );
-
         // user context
         let mut context = UserContext::default();
         context.set_ip(entry_addr);
@@ -328,13 +484,21 @@ impl Thread {
                 }),
                 clear_child_tid: 0,
                 sig_mask: Sigset::default(),
+                saved_sig_mask: None,
                 signal_alternate_stack: SignalStack::default(),
+                seccomp: Seccomp::default(),
+                pi_boost: None,
+                pi_blocked_on: None,
+                robust_list: 0,
+                caps: CapSets::for_euid(EUID),
             }),
-            vm: vm.clone(),
+            vm_token: AtomicUsize::new(lock_memory_set(&vm).token()),
+            vm: Mutex::new(vm.clone()),
             proc: Arc::new(Mutex::new(Process {
                 vm,
                 files,
-                cwd: String::from("/"),
+                cwd,
+                root,
                 exec_path: String::from(exec_path),
                 futexes: BTreeMap::default(),
                 semaphores: SemProc::default(),
@@ -344,11 +508,25 @@ impl Thread {
                 children: Vec::new(),
                 threads: Vec::new(),
                 exit_code: 0,
+                exit_reason: (0, 0),
+                stopped: None,
+                job_report: None,
                 pending_sigset: Sigset::empty(),
                 sig_queue: VecDeque::new(),
                 dispositions: [SignalAction::default(); Signal::RTMAX + 1],
                 eventbus: EventBus::new(),
                 shm_identifiers: ShmProc::default(),
+                aio_contexts: BTreeMap::new(),
+                ptrace: None,
+                personality: 0,
+                no_new_privs: false,
+                dumpable: true,
+                pdeathsig: None,
+                parent_thread: 0,
+                nice: 0,
+                pid_ns: ROOT_PID_NS.clone(),
+                pid_ns_for_children: ROOT_PID_NS.clone(),
+                mount_ns,
             })),
         };
 
@@ -362,45 +540,99 @@ impl Thread {
 
     /// Fork a new process from current one
     /// Only current process is persisted
-    pub fn fork(&self, tf: &UserContext) -> Arc<Thread> {
-        // clone virtual memory
-        let vm = self.vm.lock().clone();
-        let vm_token = vm.token();
-        let vm = Arc::new(Mutex::new(vm));
+    /// The child is put into `pid_ns` if given (CLONE_NEWPID),
+    /// otherwise into the namespace for children of current process.
+    pub fn fork(
+        &self,
+        tf: &UserContext,
+        pid_ns: Option<Arc<PidNamespace>>,
+    ) -> VMResult<Arc<Thread>> {
+        self.clone_process(tf, false, false, pid_ns)
+    }
+
+    /// Create a new process from current one, as `fork` does.
+    /// The new process shares the address space if `share_vm` (CLONE_VM),
+    /// and becomes a sibling rather than a child if `sibling` (CLONE_PARENT).
+    pub fn clone_process(
+        &self,
+        tf: &UserContext,
+        share_vm: bool,
+        sibling: bool,
+        pid_ns: Option<Arc<PidNamespace>>,
+    ) -> VMResult<Arc<Thread>> {
+        // lock process before vm
+        let mut proc = self.lock_proc();
+
+        // clone virtual memory, or share it
+        let (vm, vm_token) = if share_vm {
+            (self.vm(), self.lock_vm().token())
+        } else {
+            let vm = self.lock_vm().clone()?;
+            let vm_token = vm.token();
+            (Arc::new(Mutex::new(vm)), vm_token)
+        };
 
         // context of new thread
         let mut context = tf.clone();
         context.set_syscall_ret(0);
 
-        let mut proc = self.proc.lock();
+        let pid_ns = pid_ns.unwrap_or_else(|| proc.pid_ns_for_children.clone());
 
         let new_proc = Arc::new(Mutex::new(Process {
             vm: vm.clone(),
             files: proc.files.clone(), // share open file descriptions
             cwd: proc.cwd.clone(),
+            root: proc.root.clone(),
             exec_path: proc.exec_path.clone(),
             futexes: BTreeMap::default(),
             semaphores: proc.semaphores.clone(),
             pid: Pid::new(), // assigned later
             pgid: proc.pgid,
-            parent: (proc.pid.clone(), Arc::downgrade(&self.proc)),
+            parent: match sibling {
+                true => proc.parent.clone(),
+                false => (proc.pid.clone(), Arc::downgrade(&self.proc)),
+            },
             children: Vec::new(),
             threads: Vec::new(),
             exit_code: 0,
+            exit_reason: (0, 0),
+            stopped: None,
+            job_report: None,
             pending_sigset: Sigset::empty(),
             sig_queue: VecDeque::new(),
             dispositions: proc.dispositions.clone(),
             eventbus: EventBus::new(),
             shm_identifiers: proc.shm_identifiers.clone(),
+            // not inherited by the child, as in Linux
+            aio_contexts: BTreeMap::new(),
+            ptrace: None,
+            personality: proc.personality,
+            no_new_privs: proc.no_new_privs,
+            dumpable: proc.dumpable,
+            // not inherited by the child of fork
+            pdeathsig: None,
+            parent_thread: match sibling {
+                true => proc.parent_thread,
+                false => self.tid,
+            },
+            nice: proc.nice,
+            pid_ns: pid_ns.clone(),
+            pid_ns_for_children: pid_ns,
+            mount_ns: proc.mount_ns.clone(),
         }));
 
         // new thread
-        // this part in linux manpage seems ambiguous:
-        // Each of the threads in a process has its own signal mask.
-        // A child created via fork(2) inherits a copy of its parent's signal
-        // mask; the signal mask is preserved across execve(2).
+        // it inherits the signal mask and the alternate signal stack of this thread,
+        // while pending signals are not inherited, see fork(2).
+        // An alternate stack in a shared address space would be used by both, so it is
+        // cleared with CLONE_VM as Linux does. CLONE_VFORK never gets here with CLONE_VM.
         let sig_mask = self.inner.lock().sig_mask;
-        let sigaltstack = self.inner.lock().signal_alternate_stack;
+        let sigaltstack = match share_vm {
+            true => SignalStack::default(),
+            false => self.inner.lock().signal_alternate_stack,
+        };
+        let seccomp = self.inner.lock().seccomp.clone();
+        let caps = self.inner.lock().caps;
         let new_thread = Thread {
             tid: 0, // allocated below
             inner: Mutex::new(ThreadInner {
@@ -410,9 +642,16 @@ impl Thread {
                 }),
                 clear_child_tid: 0,
                 sig_mask,
+                saved_sig_mask: None,
                 signal_alternate_stack: sigaltstack,
+                seccomp,
+                pi_boost: None,
+                pi_blocked_on: None,
+                robust_list: 0,
+                caps,
             }),
-            vm,
+            vm: Mutex::new(vm),
+            vm_token: AtomicUsize::new(vm_token),
             proc: new_proc,
         }
         .add_to_table();
@@ -420,13 +659,22 @@ impl Thread {
         // link thread and process
         let child_pid = Pid(new_thread.tid);
         add_to_process_table(new_thread.proc.clone(), Pid(new_thread.tid));
-        new_thread.proc.lock().threads.push(new_thread.tid);
+        new_thread.lock_proc().threads.push(new_thread.tid);
 
         // link to parent
-        proc.children
-            .push((child_pid, Arc::downgrade(&new_thread.proc)));
+        let child = (child_pid, Arc::downgrade(&new_thread.proc));
+        if sibling {
+            let parent = proc.parent.1.upgrade();
+            // do not hold two processes at once
+            drop(proc);
+            if let Some(parent) = parent {
+                lock_process(&parent).children.push(child);
+            }
+        } else {
+            proc.children.push(child);
+        }
 
-        new_thread
+        Ok(new_thread)
     }
 
     /// Create a new thread in the same process.
@@ -434,34 +682,48 @@ impl Thread {
         &self,
         context: &UserContext,
         stack_top: usize,
-        tls: usize,
+        tls: Option<usize>,
         clear_child_tid: usize,
     ) -> Arc<Thread> {
-        let vm_token = self.vm.lock().token();
+        let vm_token = self.lock_vm().token();
         let mut new_context = context.clone();
         new_context.set_syscall_ret(0);
         new_context.set_sp(stack_top);
-        new_context.set_tls(tls);
+        if let Some(tls) = tls {
+            new_context.set_tls(tls);
+        }
         let thread_context = ThreadContext {
             user: Box::new(new_context),
             fp: Box::new(FpState::new()),
         };
 
+        // the alternate signal stack of this thread is not for the new one
         let sig_mask = self.inner.lock().sig_mask;
-        let sigaltstack = self.inner.lock().signal_alternate_stack;
+        let seccomp = self.inner.lock().seccomp.clone();
+        let caps = self.inner.lock().caps;
         let thread = Thread {
             tid: 0,
             inner: Mutex::new(ThreadInner {
                 clear_child_tid,
                 context: Some(thread_context),
                 sig_mask,
-                signal_alternate_stack: sigaltstack,
+                saved_sig_mask: None,
+                signal_alternate_stack: SignalStack::default(),
+                seccomp,
+                pi_boost: None,
+                pi_blocked_on: None,
+                robust_list: 0,
+                caps,
             }),
-            vm: self.vm.clone(),
+            vm: Mutex::new(self.vm()),
+            vm_token: AtomicUsize::new(vm_token),
             proc: self.proc.clone(),
         };
         let res = thread.add_to_table();
-        res.proc.lock().threads.push(res.tid);
+        let mut proc = res.lock_proc();
+        proc.pid_ns.alloc(Pid(res.tid));
+        proc.threads.push(res.tid);
+        drop(proc);
         res
     }
 
@@ -475,8 +737,7 @@ impl Thread {
 
     /// this thread has signal to handle
     pub fn has_signal_to_handle(&self) -> bool {
-        self.proc
-            .lock()
+        self.lock_proc()
             .sig_queue
             .iter()
             .find(|(info, tid)| {
@@ -493,10 +754,104 @@ impl Thread {
     }
 }
 
+/// Max size of the program header table, as Linux does
+const ELF_PHDRS_MAX: usize = 0x10000;
+/// Max length of the interpreter path, `PATH_MAX`
+const ELF_INTERP_MAX: usize = 4096;
+
+/// Read the parts of the ELF at `inode` that `ElfFile` parses:
+/// the ELF header, the program header table and the interpreter path.
+/// Segments are mapped from the file later, so they are not read.
+fn read_elf_headers(inode: &Arc<dyn INode>) -> Result<Vec<u8>, &'static str> {
+    let size = inode
+        .metadata()
+        .map_err(|_| "failed to read from INode")?
+        .size;
+
+    // large enough for both ELF32 and ELF64 headers
+    let mut data = Vec::new();
+    read_to(inode, &mut data, size.min(64))?;
+    let header = header::parse_header(&data)?;
+
+    let ph_size = header.pt2.ph_entry_size() as usize * header.pt2.ph_count() as usize;
+    if ph_size > ELF_PHDRS_MAX {
+        return Err("too many program headers");
+    }
+    let ph_end = (header.pt2.ph_offset() as usize).saturating_add(ph_size);
+    if ph_end > size {
+        return Err("ELF file is truncated");
+    }
+    read_to(inode, &mut data, ph_end)?;
+
+    // the interpreter path usually follows the program headers, but may be anywhere
+    let interp = ElfFile::new(&data)?
+        .program_iter()
+        .find(|ph| ph.get_type() == Ok(Type::Interp))
+        .map(|ph| (ph.offset() as usize, ph.file_size() as usize));
+    if let Some((offset, len)) = interp {
+        if len > ELF_INTERP_MAX {
+            return Err("interpreter path is too long");
+        }
+        let end = offset.saturating_add(len);
+        if end > size {
+            return Err("ELF file is truncated");
+        }
+        read_to(inode, &mut data, end)?;
+    }
+    Ok(data)
+}
+
+/// Extend `data`, which holds `inode` from offset 0, to `len` bytes
+fn read_to(inode: &Arc<dyn INode>, data: &mut Vec<u8>, len: usize) -> Result<(), &'static str> {
+    let start = data.len();
+    if len <= start {
+        return Ok(());
+    }
+    data.resize(len, 0);
+    // through the page cache, which may have data not written back yet
+    match page_cache::read_inode_at(inode, start, &mut data[start..]) {
+        Ok(read) if read == len - start => Ok(()),
+        Ok(_) => Err("ELF file is truncated"),
+        Err(_) => Err("failed to read from INode"),
+    }
+}
+
+/// Lock `vm`, checking the order as `Thread::lock_vm` does
+pub fn lock_memory_set(
+    vm: &Mutex<MemorySet>,
+) -> OrderedGuard<MutexGuard<'_, MemorySet, SpinNoIrq>> {
+    lock_ordered(LockClass::MemorySet, || vm.lock())
+}
+
+/// The locked memory set of a thread, see `Thread::lock_vm`.
+///
+/// It holds a reference to the memory set, as exec may give the thread another one meanwhile.
+pub struct VmGuard<'a> {
+    // dropped before `_vm`
+    guard: OrderedGuard<MutexGuard<'a, MemorySet, SpinNoIrq>>,
+    _vm: Arc<Mutex<MemorySet>>,
+}
+
+impl Deref for VmGuard<'_> {
+    type Target = MemorySet;
+    fn deref(&self) -> &MemorySet {
+        &self.guard
+    }
+}
+
+impl DerefMut for VmGuard<'_> {
+    fn deref_mut(&mut self) -> &mut MemorySet {
+        &mut self.guard
+    }
+}
+
 pub fn spawn(thread: Arc<Thread>) {
-    let vmtoken = thread.vm.lock().token();
-    let temp = thread.clone();
+    let wrapper_thread = thread.clone();
     let future = async move {
+        #[cfg(feature = "deterministic")]
+        let mut syscalls = 0;
+        // timer ticks in the current time slice
+        let mut ticks = 0;
         loop {
             let mut thread_context = thread.begin_running();
             let cx = &mut thread_context.user;
@@ -510,97 +865,158 @@ pub fn spawn(thread: Arc<Thread>) {
             trace!("back from user: {:#x?} trap_num {:#x}", cx, trap_num);
 
             let mut exit = false;
-            let mut do_yield = false;
+            // turns to give up to other threads
+            let mut yields = 0;
             match trap_num {
                 // must be first
                 _ if is_page_fault(trap_num) => {
                     // page fault
                     let addr = get_page_fault_addr();
-                    info!("page fault from user @ {:#x}", addr);
+                    let cause = get_page_fault_cause(&cx);
+                    info!("page fault from user @ {:#x}, {:?}", addr, cause);
 
-                    if !handle_user_page_fault(&thread, addr) {
-                        // TODO: SIGSEGV
-                        panic!("page fault handle failed");
+                    if !handle_user_page_fault(&thread, addr, cause) {
+                        // the stack guard is mapped too, so stack overflow is SEGV_ACCERR
+                        let mapped = thread.lock_vm().iter().any(|area| area.contains(addr));
+                        let code = if mapped { SEGV_ACCERR } else { SEGV_MAPERR };
+                        info!("unhandled page fault @ {:#x}, code {}", addr, code);
+                        let info = Siginfo {
+                            signo: Signal::SIGSEGV as i32,
+                            errno: 0,
+                            code,
+                            field: SiginfoFields { addr },
+                        };
+                        force_signal(&thread, info);
                     }
                 }
-                _ if is_syscall(trap_num) => exit = handle_syscall(&thread, cx).await,
-                _ if is_intr(trap_num) => {
-                    crate::arch::interrupt::ack(trap_num);
-                    trace!("handle irq {:#x}", trap_num);
-                    if is_timer_intr(trap_num) {
-                        do_yield = true;
-                        crate::arch::interrupt::timer();
+                _ if is_syscall(trap_num) => {
+                    exit = handle_syscall(&thread, cx).await;
+                    #[cfg(feature = "deterministic")]
+                    {
+                        syscalls += 1;
+                        if syscalls % super::deterministic::QUANTUM == 0 {
+                            yields = 1;
+                        }
                     }
-                    IRQ_MANAGER.read().try_handle_interrupt(Some(trap_num));
                 }
-                _ if is_reserved_inst(trap_num) => {
-                    if !handle_reserved_inst(cx) {
-                        panic!(
-                            "unhandled reserved intr in thread {} trap {:#x} {:x?}",
-                            thread.tid, trap_num, cx
-                        );
+                _ if is_intr(trap_num) => {
+                    trace!("handle irq {:#x}", trap_num);
+                    // the timer preempts, unless runs must be reproducible
+                    if ack_and_dispatch(trap_num) && !cfg!(feature = "deterministic") {
+                        let (slice, turns) = timeslice(thread.effective_nice());
+                        ticks += 1;
+                        if ticks >= slice {
+                            ticks = 0;
+                            yields = turns;
+                        }
                     }
                 }
+                _ if is_reserved_inst(trap_num) && handle_reserved_inst(cx) => {}
                 _ => {
-                    panic!(
-                        "unhandled trap in thread {} trap {:#x} {:x?}",
-                        thread.tid, trap_num, cx
+                    // a CPU exception caused by the user program
+                    let pc = user_pc(cx);
+                    let (signal, code) = exception_signal(trap_num).unwrap_or_else(|| {
+                        error!(
+                            "unhandled trap {:#x} in thread {} @ {:#x}: {:x?}",
+                            trap_num, thread.tid, pc, cx
+                        );
+                        (Signal::SIGSEGV, SI_KERNEL)
+                    });
+                    info!(
+                        "trap {:#x} in thread {} @ {:#x}, send {:?}",
+                        trap_num, thread.tid, pc, signal
                     );
+                    let info = Siginfo {
+                        signo: signal as i32,
+                        errno: 0,
+                        code,
+                        field: SiginfoFields { addr: pc },
+                    };
+                    force_signal(&thread, info);
                 }
             }
 
-            // check signals
+            // check signals, and park while stopped by one
             if !exit {
                 exit = handle_signal(&thread, cx);
+                while !exit && wait_while_stopped(&thread).await {
+                    exit = handle_signal(&thread, cx);
+                }
             }
 
             thread.end_running(thread_context);
             if exit {
                 info!("thread {} stopped", thread.tid);
                 break;
-            } else if do_yield {
+            }
+            for _ in 0..yields {
                 yield_now().await;
             }
         }
     };
 
-    spawn_thread(Box::pin(future), vmtoken, temp);
+    spawn_thread(Box::pin(future), wrapper_thread);
+}
+
+/// Timer ticks in a time slice, and turns given up to other threads after it,
+/// for a thread of a process with `nice`.
+/// Every 5 nice values double or halve the share of CPU time.
+fn timeslice(nice: i8) -> (usize, usize) {
+    let shift = (nice.abs() as usize + 4) / 5;
+    match nice {
+        _ if nice < 0 => (1 << shift, 1),
+        _ => (1, 1 << shift),
+    }
 }
 
-fn spawn_thread(
-    future: Pin<Box<dyn Future<Output = ()> + Send + 'static>>,
-    vmtoken: usize,
-    thread: Arc<Thread>,
-) {
-    executor::spawn(PageTableSwitchWrapper {
-        inner: Mutex::new(future),
-        vmtoken,
-        thread,
+fn spawn_thread(future: Pin<Box<dyn Future<Output = ()> + Send + 'static>>, thread: Arc<Thread>) {
+    super::spawn_task(PageTableSwitchWrapper {
+        inner: Mutex::new(Some(future)),
+        thread: Some(thread),
     });
 }
 
+/// Run the future of a thread in its address space.
+///
+/// Both the future and the thread are released as soon as the thread exits,
+/// as the executor may keep the task itself alive for a while.
 #[must_use = "future does nothing unless polled/`await`-ed"]
 struct PageTableSwitchWrapper {
-    inner: Mutex<Pin<Box<dyn Future<Output = ()> + Send>>>,
-    vmtoken: usize,
-    thread: Arc<Thread>,
+    inner: Mutex<Option<Pin<Box<dyn Future<Output = ()> + Send>>>>,
+    thread: Option<Arc<Thread>>,
 }
 
 impl Future for PageTableSwitchWrapper {
     type Output = ();
-    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let thread = match &self.thread {
+            Some(thread) => thread.clone(),
+            // exited
+            None => return Poll::Ready(()),
+        };
         // set cpu local thread
         // TODO: task local?
         let cpu_id = cpu::id();
+        // changed by exec only, which switches to the new one itself
+        let vm_token = thread.vm_token.load(Ordering::SeqCst);
         unsafe {
-            PROCESSORS[cpu_id] = Some(self.thread.clone());
+            PROCESSORS[cpu_id] = Some(thread);
+        }
+        // seen by `reclaim_freeable` before the page table is walked
+        fence(Ordering::SeqCst);
+        set_page_table(vm_token);
+        let res = self.inner.lock().as_mut().unwrap().as_mut().poll(cx);
+        if res.is_pending() {
+            // the thread is parked, e.g. in a blocking syscall
+            assert_unlocked("thread");
         }
-        // vmtoken won't change
-        set_page_table(self.vmtoken);
-        let res = self.inner.lock().as_mut().poll(cx);
         unsafe {
             PROCESSORS[cpu_id] = None;
         }
+        if res.is_ready() {
+            self.inner.lock().take();
+            self.thread.take();
+        }
         res
     }
 }
@@ -620,10 +1036,13 @@ impl Future for YieldFuture {
     type Output = ();
 
     fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        // counted as runnable for the load averages while parked
         if self.flag {
+            YIELDING.fetch_sub(1, Ordering::Relaxed);
             Poll::Ready(())
         } else {
             self.flag = true;
+            YIELDING.fetch_add(1, Ordering::Relaxed);
             cx.waker().clone().wake();
             Poll::Pending
         }
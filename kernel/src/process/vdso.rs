@@ -0,0 +1,146 @@
+//! The vDSO and vvar pages, shared by all user processes
+//!
+//! The vvar page holds the time, updated by the timer on CPU 0 under a sequence lock.
+//! The vDSO right after it is an ELF shared object whose functions read the time from vvar,
+//! so `clock_gettime(CLOCK_REALTIME)`, `gettimeofday` and `time` need no syscall.
+//! Its address is passed in `AT_SYSINFO_EHDR`. Only x86_64 has an image yet.
+
+use crate::arch::cpu;
+use crate::consts::{USER_STACK_GUARD_SIZE, USER_STACK_OFFSET};
+use crate::memory::{phys_to_virt, GlobalFrameAlloc, MemorySet};
+use crate::syscall::TimeSpec;
+use alloc::boxed::Box;
+use core::ptr::write_volatile;
+use core::sync::atomic::{fence, Ordering};
+use rcore_memory::memory_set::handler::{FrameAllocator, MemoryHandler};
+use rcore_memory::memory_set::MemoryAttr;
+use rcore_memory::paging::PageTable;
+use rcore_memory::{PhysAddr, VirtAddr, PAGE_SIZE};
+
+/// Layout of the vvar page, known to the vDSO code
+#[repr(C)]
+struct VVar {
+    /// Odd while the time below is being written
+    seq: u64,
+    sec: u64,
+    nsec: u64,
+    usec: u64,
+}
+
+struct Pages {
+    vvar: PhysAddr,
+    vdso: PhysAddr,
+}
+
+lazy_static! {
+    static ref PAGES: Pages = {
+        let image = image();
+        assert!(image.len() <= PAGE_SIZE, "vDSO image larger than a page");
+        let vvar = GlobalFrameAlloc
+            .alloc()
+            .expect("failed to alloc vvar frame");
+        let vdso = GlobalFrameAlloc
+            .alloc()
+            .expect("failed to alloc vDSO frame");
+        unsafe {
+            let vvar = phys_to_virt(vvar) as *mut u8;
+            core::ptr::write_bytes(vvar, 0, PAGE_SIZE);
+            let vdso = phys_to_virt(vdso) as *mut u8;
+            core::ptr::write_bytes(vdso, 0, PAGE_SIZE);
+            core::ptr::copy_nonoverlapping(image.as_ptr(), vdso, image.len());
+        }
+        Pages { vvar, vdso }
+    };
+}
+
+#[cfg(target_arch = "x86_64")]
+use crate::arch::vdso::image;
+
+#[cfg(not(target_arch = "x86_64"))]
+fn image() -> &'static [u8] {
+    &[]
+}
+
+/// Called by the timer, publish the current time in vvar
+pub fn update_vdso() {
+    if cpu::id() != 0 || image().is_empty() {
+        return;
+    }
+    let now = TimeSpec::get_epoch();
+    let vvar = phys_to_virt(PAGES.vvar) as *mut VVar;
+    unsafe {
+        let seq = (*vvar).seq;
+        write_volatile(&mut (*vvar).seq, seq + 1);
+        fence(Ordering::Release);
+        write_volatile(&mut (*vvar).sec, now.sec as u64);
+        write_volatile(&mut (*vvar).nsec, now.nsec as u64);
+        write_volatile(&mut (*vvar).usec, now.nsec as u64 / 1000);
+        fence(Ordering::Release);
+        write_volatile(&mut (*vvar).seq, seq + 2);
+    }
+}
+
+/// Map vvar and the vDSO below the user stack, `shift` bytes further down if possible.
+/// Return the address of the vDSO.
+pub fn map(vm: &mut MemorySet, shift: usize) -> Option<VirtAddr> {
+    if image().is_empty() {
+        return None;
+    }
+    let hint = USER_STACK_OFFSET - USER_STACK_GUARD_SIZE - PAGE_SIZE * 2 - shift;
+    let start = vm.find_free_area(hint, PAGE_SIZE * 2);
+    vm.push(
+        start,
+        start + PAGE_SIZE,
+        MemoryAttr::default().user().readonly(),
+        SharedPage { frame: PAGES.vvar },
+        "vvar",
+    );
+    vm.push(
+        start + PAGE_SIZE,
+        start + PAGE_SIZE * 2,
+        MemoryAttr::default().user().readonly().execute(),
+        SharedPage { frame: PAGES.vdso },
+        "vdso",
+    );
+    Some(start + PAGE_SIZE)
+}
+
+/// A page of the kernel mapped into every process, which must stay read-only
+#[derive(Debug, Clone)]
+struct SharedPage {
+    frame: PhysAddr,
+}
+
+impl MemoryHandler for SharedPage {
+    fn box_clone(&self) -> Box<dyn MemoryHandler> {
+        Box::new(self.clone())
+    }
+
+    fn map(&self, pt: &mut dyn PageTable, addr: VirtAddr, attr: &MemoryAttr) {
+        let entry = pt.map(addr, self.frame);
+        attr.apply(entry);
+    }
+
+    fn unmap(&self, pt: &mut dyn PageTable, addr: VirtAddr) {
+        // the frame lives forever
+        pt.unmap(addr);
+    }
+
+    fn clone_map(
+        &self,
+        pt: &mut dyn PageTable,
+        _src_pt: &mut dyn PageTable,
+        addr: VirtAddr,
+        attr: &MemoryAttr,
+    ) {
+        self.map(pt, addr, attr);
+    }
+
+    fn handle_page_fault(&self, _pt: &mut dyn PageTable, _addr: VirtAddr) -> bool {
+        false
+    }
+
+    fn may_write(&self) -> bool {
+        false
+    }
+}
@@ -0,0 +1,101 @@
+//! Random numbers for the kernel and user programs
+//!
+//! A ChaCha20 keystream, whose key is stirred with the cycle counter and the time on every use
+//! and replaced after each request, so that earlier output can't be recovered from the state.
+//! There is no hardware RNG driver, so it is only as unpredictable as those timings.
+
+use crate::arch::{rand::rand, timer::timer_now};
+use crate::sync::SpinNoIrqLock as Mutex;
+use rcore_memory::PAGE_SIZE;
+
+/// "expand 32-byte k"
+const SIGMA: [u32; 4] = [0x6170_7865, 0x3320_646e, 0x7962_2d32, 0x6b20_6574];
+
+struct ChaCha {
+    key: [u32; 8],
+    counter: u64,
+}
+
+static RNG: Mutex<ChaCha> = Mutex::new(ChaCha {
+    key: [0; 8],
+    counter: 0,
+});
+
+/// Fill `buf` with random bytes
+pub fn fill(buf: &mut [u8]) {
+    // the lock disables interrupts, so serve long requests a page at a time
+    for part in buf.chunks_mut(PAGE_SIZE) {
+        let mut rng = RNG.lock();
+        rng.stir();
+        for chunk in part.chunks_mut(64) {
+            let block = rng.block();
+            chunk.copy_from_slice(&block[..chunk.len()]);
+        }
+        rng.rekey();
+    }
+}
+
+impl ChaCha {
+    /// Mix the current timings into the key
+    fn stir(&mut self) {
+        // the same numbers on every run, so is the address space layout
+        if cfg!(feature = "deterministic") {
+            return;
+        }
+        let cycles = rand();
+        let nanos = timer_now().as_nanos() as u64;
+        self.key[0] ^= cycles as u32;
+        self.key[1] ^= (cycles >> 32) as u32;
+        self.key[2] ^= nanos as u32;
+        self.key[3] ^= (nanos >> 32) as u32;
+    }
+
+    /// Replace the key with the next block of keystream
+    fn rekey(&mut self) {
+        let block = self.block();
+        for (word, bytes) in self.key.iter_mut().zip(block.chunks(4)) {
+            *word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+    }
+
+    /// Next 64 bytes of keystream
+    fn block(&mut self) -> [u8; 64] {
+        let mut input = [0u32; 16];
+        input[..4].copy_from_slice(&SIGMA);
+        input[4..12].copy_from_slice(&self.key);
+        input[12] = self.counter as u32;
+        input[13] = (self.counter >> 32) as u32;
+        self.counter = self.counter.wrapping_add(1);
+
+        let mut state = input;
+        for _ in 0..10 {
+            // columns
+            quarter_round(&mut state, 0, 4, 8, 12);
+            quarter_round(&mut state, 1, 5, 9, 13);
+            quarter_round(&mut state, 2, 6, 10, 14);
+            quarter_round(&mut state, 3, 7, 11, 15);
+            // diagonals
+            quarter_round(&mut state, 0, 5, 10, 15);
+            quarter_round(&mut state, 1, 6, 11, 12);
+            quarter_round(&mut state, 2, 7, 8, 13);
+            quarter_round(&mut state, 3, 4, 9, 14);
+        }
+
+        let mut out = [0u8; 64];
+        for (i, bytes) in out.chunks_mut(4).enumerate() {
+            bytes.copy_from_slice(&state[i].wrapping_add(input[i]).to_le_bytes());
+        }
+        out
+    }
+}
+
+fn quarter_round(s: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    s[a] = s[a].wrapping_add(s[b]);
+    s[d] = (s[d] ^ s[a]).rotate_left(16);
+    s[c] = s[c].wrapping_add(s[d]);
+    s[b] = (s[b] ^ s[c]).rotate_left(12);
+    s[a] = s[a].wrapping_add(s[b]);
+    s[d] = (s[d] ^ s[a]).rotate_left(8);
+    s[c] = s[c].wrapping_add(s[d]);
+    s[b] = (s[b] ^ s[c]).rotate_left(7);
+}
@@ -30,11 +30,11 @@ impl Guest {
 
     pub fn add_memory_region(&self, gpaddr: GuestPhysAddr, size: usize) -> RvmResult<HostVirtAddr> {
         self.inner.add_memory_region(gpaddr, size, None)?;
-        let thread = crate::process::current_thread().unwrap();
-        let hvaddr = thread.vm.lock().find_free_area(PAGE_SIZE, size);
+        let thread = crate::process::Thread::current();
+        let hvaddr = thread.lock_vm().find_free_area(PAGE_SIZE, size);
         let handler =
             RvmPageTableHandlerDelay::new(gpaddr, hvaddr, self.gpm.clone(), GlobalFrameAlloc);
-        thread.vm.lock().push(
+        thread.lock_vm().push(
             hvaddr,
             hvaddr + size,
             MemoryAttr::default().user().writable(),
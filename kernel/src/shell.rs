@@ -25,8 +25,10 @@ pub fn add_user_shell() {
     let init_args: Vec<String> = vec!["busybox".into(), "ash".into()];
 
     if let Ok(inode) = ROOT_INODE.lookup(init_shell) {
-        let thread = Thread::new_user(&inode, init_shell, init_args, init_envs);
-        spawn(thread);
+        match Thread::new_user(&inode, init_shell, init_args, init_envs) {
+            Ok(thread) => spawn(thread),
+            Err(err) => error!("failed to run {}: {}", init_shell, err),
+        }
     } else {
         todo!()
     }
@@ -19,6 +19,41 @@ pub const SI_USER: i32 = 0;
 pub const SI_KERNEL: i32 = 128;
 /// from kernel
 
+/// SIGSEGV: address not mapped to object
+pub const SEGV_MAPERR: i32 = 1;
+/// SIGSEGV: invalid permissions for mapped object
+pub const SEGV_ACCERR: i32 = 2;
+/// SIGILL: illegal opcode
+pub const ILL_ILLOPC: i32 = 1;
+/// SIGILL: illegal operand
+pub const ILL_ILLOPN: i32 = 2;
+/// SIGFPE: integer divide by zero
+pub const FPE_INTDIV: i32 = 1;
+/// SIGFPE: integer overflow
+pub const FPE_INTOVF: i32 = 2;
+/// SIGFPE: invalid floating-point operation
+pub const FPE_FLTINV: i32 = 7;
+/// SIGBUS: invalid address alignment
+pub const BUS_ADRALN: i32 = 1;
+/// SIGBUS: nonexistent physical address
+pub const BUS_ADRERR: i32 = 2;
+/// SIGTRAP: process breakpoint
+pub const TRAP_BRKPT: i32 = 1;
+/// SIGTRAP: process trace trap
+pub const TRAP_TRACE: i32 = 2;
+/// SIGCHLD: child has exited
+pub const CLD_EXITED: i32 = 1;
+/// SIGCHLD: child was killed
+pub const CLD_KILLED: i32 = 2;
+/// SIGCHLD: child was killed and dumped core
+pub const CLD_DUMPED: i32 = 3;
+/// SIGCHLD: traced child has stopped
+pub const CLD_TRAPPED: i32 = 4;
+/// SIGCHLD: child has stopped
+pub const CLD_STOPPED: i32 = 5;
+/// SIGCHLD: stopped child has continued
+pub const CLD_CONTINUED: i32 = 6;
+
 // yet there's a bug because of mismatching bits: https://sourceware.org/bugzilla/show_bug.cgi?id=25657
 // just support 64bits size sigset
 /// Linux struct sigset_t
@@ -47,6 +82,10 @@ impl Sigset {
     pub fn remove_set(&mut self, sigset: &Sigset) {
         self.0 ^= self.0 & sigset.0;
     }
+    /// Signals in both sets
+    pub fn intersect(&self, sigset: &Sigset) -> Sigset {
+        Sigset(self.0 & sigset.0)
+    }
 }
 
 /// Linux struct sigaction
@@ -74,9 +113,48 @@ impl Debug for SignalAction {
 #[derive(Copy, Clone)]
 pub union SiginfoFields {
     pad: [u8; Self::PAD_SIZE],
+    /// faulting address of SIGSEGV, SIGBUS, SIGILL and SIGFPE
+    pub addr: usize,
+    /// sender and value of `sigqueue`
+    pub rt: SiginfoRt,
+    /// child state of SIGCHLD
+    pub chld: SiginfoChild,
+    /// syscall trapped by seccomp, of SIGSYS
+    pub sys: SiginfoSys,
     // TODO: fill this union
 }
 
+/// `_rt` in Linux siginfo_t
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct SiginfoRt {
+    pub pid: i32,
+    pub uid: u32,
+    /// `sigval`, an int or a pointer
+    pub value: usize,
+}
+
+/// `_sigchld` in Linux siginfo_t
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct SiginfoChild {
+    pub pid: i32,
+    pub uid: u32,
+    /// exit status, or the signal which killed, stopped or continued the child
+    pub status: i32,
+    pub utime: isize,
+    pub stime: isize,
+}
+
+/// `_sigsys` in Linux siginfo_t
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct SiginfoSys {
+    pub call_addr: usize,
+    pub syscall: i32,
+    pub arch: u32,
+}
+
 impl SiginfoFields {
     const PAD_SIZE: usize = 128 - 2 * core::mem::size_of::<i32>() - core::mem::size_of::<usize>();
 }
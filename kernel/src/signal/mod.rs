@@ -2,8 +2,11 @@ use crate::arch::{
     signal::{set_signal_handler, MachineContext, RET_CODE},
     syscall::SYS_RT_SIGRETURN,
 };
-use crate::process::{process, process_of, Process, Thread};
-use crate::sync::{Event, MutexGuard, SpinNoIrq, SpinNoIrqLock as Mutex};
+use crate::process::{
+    lock_memory_set, lock_process, notify_job, process, process_exited, process_of, Pid, Process,
+    Thread,
+};
+use crate::sync::{wait_for_event, Event, MutexGuard, SpinNoIrq, SpinNoIrqLock as Mutex};
 use alloc::sync::Arc;
 use bitflags::*;
 use num::FromPrimitive;
@@ -82,6 +85,9 @@ pub enum Signal {
     SIGRT64 = 64,
 }
 
+/// Set in the wait status of a process whose default action dumped core
+pub const CORE_DUMP_FLAG: usize = 0x80;
+
 impl Signal {
     pub const RTMIN: usize = 32;
     pub const RTMAX: usize = 64;
@@ -89,22 +95,231 @@ impl Signal {
     pub fn is_standard(self) -> bool {
         (self as usize) < Self::RTMIN
     }
+
+    /// Action taken when the disposition is `SIG_DFL`, which every process starts with
+    pub fn default_action(self) -> DefaultAction {
+        use Signal::*;
+        match self {
+            SIGQUIT | SIGILL | SIGTRAP | SIGABRT | SIGBUS | SIGFPE | SIGSEGV | SIGXCPU
+            | SIGXFSZ | SIGSYS => DefaultAction::Core,
+            SIGCHLD | SIGURG | SIGWINCH => DefaultAction::Ign,
+            SIGSTOP | SIGTSTP | SIGTTIN | SIGTTOU => DefaultAction::Stop,
+            SIGCONT => DefaultAction::Cont,
+            // the rest, including all real-time signals
+            _ => DefaultAction::Term,
+        }
+    }
+
+    /// Whether the default action is to stop the process
+    pub fn is_stop(self) -> bool {
+        self.default_action() == DefaultAction::Stop
+    }
+
+    /// Whether the default action is to ignore the signal.
+    /// SIGCONT is counted in, as it has already continued the process when sent.
+    pub fn is_ignored_by_default(self) -> bool {
+        match self.default_action() {
+            DefaultAction::Ign | DefaultAction::Cont => true,
+            _ => false,
+        }
+    }
+}
+
+/// Default actions of signals, see signal(7)
+#[derive(Eq, PartialEq, Debug, Copy, Clone)]
+pub enum DefaultAction {
+    /// Terminate the process
+    Term,
+    /// Terminate the process and dump core
+    Core,
+    /// Ignore the signal
+    Ign,
+    /// Stop the process
+    Stop,
+    /// Continue the process if it is stopped
+    Cont,
 }
 
 // process and tid must be checked
-pub fn send_signal(process: Arc<Mutex<Process>>, tid: isize, info: Siginfo) {
+pub fn send_signal(proc: Arc<Mutex<Process>>, tid: isize, info: Siginfo) {
     let signal: Signal = <Signal as FromPrimitive>::from_i32(info.signo).unwrap();
-    let mut process = process.lock();
-    if signal.is_standard() && process.pending_sigset.contains(signal) {
-        return;
+    let mut process = lock_process(&proc);
+    // stop signals and SIGCONT cancel each other as they are sent, see signal(7).
+    // SIGKILL also wakes a stopped process, but without reporting it.
+    let mut job = None;
+    match signal {
+        Signal::SIGCONT | Signal::SIGKILL => {
+            discard_pending(&mut process, Signal::is_stop);
+            job = process.cont(signal == Signal::SIGCONT);
+        }
+        _ if signal.is_stop() => discard_pending(&mut process, |s| s == Signal::SIGCONT),
+        _ => {}
     }
-    process.sig_queue.push_back((info, tid));
-    process.pending_sigset.add(signal);
-    process.eventbus.lock().set(Event::RECEIVE_SIGNAL);
-    info!(
-        "send signal {} to pid {} tid {}",
-        info.signo, process.pid, tid
-    )
+    if !signal.is_standard() || !process.pending_sigset.contains(signal) {
+        process.sig_queue.push_back((info, tid));
+        process.pending_sigset.add(signal);
+        // wake sleepers even if an earlier signal has set the event
+        process.eventbus.lock().notify_all(Event::RECEIVE_SIGNAL);
+        info!(
+            "send signal {} to pid {} tid {}",
+            info.signo, process.pid, tid
+        );
+    }
+    drop(process);
+    if let Some((code, status)) = job {
+        notify_job(&proc, code, status);
+    }
+}
+
+/// Remove pending signals for which `discard` is true
+fn discard_pending(process: &mut Process, discard: impl Fn(Signal) -> bool) {
+    let is_discarded = |info: &Siginfo| discard(Signal::from_i32(info.signo).unwrap());
+    let mut removed = Sigset::empty();
+    for (info, _) in process
+        .sig_queue
+        .iter()
+        .filter(|(info, _)| is_discarded(info))
+    {
+        removed.add(Signal::from_i32(info.signo).unwrap());
+    }
+    process.sig_queue.retain(|(info, _)| !is_discarded(info));
+    process.pending_sigset.remove_set(&removed);
+}
+
+/// Whether a queued signal can be delivered to `thread` and will run an action.
+/// Deliverable signals which would only be ignored are discarded on the way.
+pub fn has_action_to_run(process: &mut Process, thread: &Thread) -> bool {
+    let mask = thread.inner.lock().sig_mask;
+    loop {
+        let idx = match next_queued(process, thread.tid, |signal| !mask.contains(signal)) {
+            Some(idx) => idx,
+            None => return false,
+        };
+        let signal = Signal::from_i32(process.sig_queue[idx].0.signo).unwrap();
+        let handler = process.dispositions[signal as usize].handler;
+        if handler != SIG_IGN && !(handler == SIG_DFL && signal.is_ignored_by_default()) {
+            return true;
+        }
+        remove_queued(process, idx);
+    }
+}
+
+/// Remove the next signal in `set` queued for `thread`
+pub fn dequeue_signal(process: &mut Process, thread: &Thread, set: &Sigset) -> Option<Siginfo> {
+    let idx = next_queued(process, thread.tid, |signal| set.contains(signal))?;
+    Some(remove_queued(process, idx))
+}
+
+/// Index in the queue of the next signal for thread `tid` among those `select`ed.
+/// Lower numbers go first, so standard signals before real-time ones,
+/// and instances of the same real-time signal in the order they are sent.
+fn next_queued(process: &Process, tid: usize, select: impl Fn(Signal) -> bool) -> Option<usize> {
+    process
+        .sig_queue
+        .iter()
+        .enumerate()
+        .filter(|&(_, &(info, target))| {
+            (target == -1 || target as usize == tid)
+                && select(Signal::from_i32(info.signo).unwrap())
+        })
+        // the first of equal ones
+        .min_by_key(|&(_, &(info, _))| info.signo)
+        .map(|(idx, _)| idx)
+}
+
+/// Remove the signal at `idx` of the queue.
+/// It stays pending if another instance of it, a real-time signal, is still queued.
+fn remove_queued(process: &mut Process, idx: usize) -> Siginfo {
+    let (info, _) = process.sig_queue.remove(idx).unwrap();
+    if process
+        .sig_queue
+        .iter()
+        .all(|(other, _)| other.signo != info.signo)
+    {
+        process
+            .pending_sigset
+            .remove(Signal::from_i32(info.signo).unwrap());
+    }
+    info
+}
+
+/// Whether a queued signal can be delivered to `thread`, like `Thread::has_signal_to_handle`
+/// but with the process already locked
+pub fn has_signal_to_deliver(process: &Process, thread: &Thread) -> bool {
+    let mask = thread.inner.lock().sig_mask;
+    process.sig_queue.iter().any(|&(info, tid)| {
+        (tid == -1 || tid as usize == thread.tid)
+            && !mask.contains(Signal::from_i32(info.signo).unwrap())
+    })
+}
+
+/// Park `thread` while its process is stopped, until SIGCONT or SIGKILL.
+/// Return whether it was parked.
+pub async fn wait_while_stopped(thread: &Arc<Thread>) -> bool {
+    let mut parked = false;
+    loop {
+        let eventbus = {
+            let proc = thread.lock_proc();
+            if proc.stopped.is_none() {
+                return parked;
+            }
+            proc.eventbus.clone()
+        };
+        parked = true;
+        wait_for_event(eventbus, Event::PROCESS_CONTINUE).await;
+    }
+}
+
+/// Send a signal raised by a fault of `thread` itself, e.g. SIGSEGV.
+/// The faulting instruction would only run again if the signal were blocked or ignored,
+/// so in that case the default action is restored and the signal unblocked, as Linux does.
+pub fn force_signal(thread: &Arc<Thread>, info: Siginfo) {
+    let signal: Signal = <Signal as FromPrimitive>::from_i32(info.signo).unwrap();
+    {
+        let mut process = thread.lock_proc();
+        let mut inner = thread.inner.lock();
+        let action = &mut process.dispositions[info.signo as usize];
+        if inner.sig_mask.contains(signal) || action.handler == SIG_IGN {
+            action.handler = SIG_DFL;
+            inner.sig_mask.remove(signal);
+        }
+    }
+    send_signal(thread.proc.clone(), thread.tid as isize, info);
+}
+
+/// Send SIGCHLD to `parent` as its child `pid` changed state for `code`, one of `CLD_*`.
+/// `status` is the exit status, or the signal which killed, stopped or continued the child.
+/// Nothing is sent if the parent ignores SIGCHLD, or for stops and continues with SA_NOCLDSTOP.
+pub fn notify_parent(parent: &Arc<Mutex<Process>>, pid: Pid, code: i32, status: i32) {
+    let local_pid = {
+        let parent = lock_process(parent);
+        let action = &parent.dispositions[Signal::SIGCHLD as usize];
+        let flags = SignalActionFlags::from_bits_truncate(action.flags);
+        if action.handler == SIG_IGN {
+            return;
+        }
+        if (code == CLD_STOPPED || code == CLD_CONTINUED)
+            && flags.contains(SignalActionFlags::NOCLDSTOP)
+        {
+            return;
+        }
+        parent.pid_ns.local(pid).unwrap_or(0)
+    };
+    let mut field = SiginfoFields::default();
+    field.chld = SiginfoChild {
+        pid: local_pid as i32,
+        uid: 0,
+        status,
+        utime: 0,
+        stime: 0,
+    };
+    let info = Siginfo {
+        signo: Signal::SIGCHLD as i32,
+        errno: 0,
+        code,
+        field,
+    };
+    send_signal(parent.clone(), -1, info);
 }
 
 /// See musl struct __ucontext
@@ -130,57 +345,62 @@ pub struct SignalFrame {
 
 /// return whether this thread exits
 pub fn handle_signal(thread: &Arc<Thread>, tf: &mut UserContext) -> bool {
-    let mut process = thread.proc.lock();
-    while let Some((idx, info)) =
-        process
-            .sig_queue
-            .iter()
-            .enumerate()
-            .find_map(|(idx, &(info, tid))| {
-                if (tid == -1 || tid as usize == thread.tid)
-                    && !thread
-                        .inner
-                        .lock()
-                        .sig_mask
-                        .contains(FromPrimitive::from_i32(info.signo).unwrap())
-                {
-                    Some((idx, info))
-                } else {
-                    None
-                }
-            })
-    {
+    let mut process = thread.lock_proc();
+    // a stop to report to the parent after unlocking the process
+    let mut job = None;
+    while let Some(idx) = next_queued(&process, thread.tid, |signal| {
+        !thread.inner.lock().sig_mask.contains(signal)
+    }) {
         use crate::signal::SignalActionFlags;
         use Signal::*;
 
+        let info = process.sig_queue[idx].0;
         let signal: Signal = <Signal as FromPrimitive>::from_i32(info.signo).unwrap();
         info!(
             "process {} thread {} received signal: {:?}",
             process.pid, thread.tid, signal
         );
 
-        process.sig_queue.remove(idx);
-        process.pending_sigset.remove(signal);
+        remove_queued(&mut process, idx);
 
         let action = process.dispositions[info.signo as usize];
         let action_flags = SignalActionFlags::from_bits_truncate(action.flags);
 
         // enter signal handler
         match action.handler {
-            // TODO: complete default actions
-            x if x == SIG_DFL => {
-                match signal {
-                    SIGALRM | SIGHUP | SIGINT => {
-                        info!("default action: Term");
-                        // TODO: exit code ref please?
-                        process.exit(info.signo as usize + 128);
-                        return true;
+            x if x == SIG_DFL => match signal.default_action() {
+                default @ DefaultAction::Core | default @ DefaultAction::Term => {
+                    let core = default == DefaultAction::Core;
+                    if core {
+                        // no core file is written, only the status tells a core dump
+                        error!(
+                            "process {} thread {} killed by {:?} @ {:#x}, code {}",
+                            process.pid,
+                            thread.tid,
+                            signal,
+                            unsafe { info.field.addr },
+                            info.code
+                        );
                     }
-                    _ => (),
+                    info!("default action: {:?}", default);
+                    process.exit_by_signal(signal, core);
+                    drop(process);
+                    process_exited(&thread.proc);
+                    return true;
                 }
-            }
+                DefaultAction::Stop => {
+                    info!("default action: Stop");
+                    // the thread parks in `wait_while_stopped` before returning to user
+                    if let Some(change) = process.stop(signal) {
+                        job = Some(change);
+                    }
+                }
+                // SIGCONT has continued the process when sent
+                DefaultAction::Ign | DefaultAction::Cont => {
+                    info!("default action: ignore");
+                }
+            },
             x if x == SIG_IGN => {
-                // TODO: handle SIGCHLD
                 info!("ignore");
             }
             x if x == SIG_ERR => {
@@ -190,9 +410,9 @@ pub fn handle_signal(thread: &Arc<Thread>, tf: &mut UserContext) -> bool {
             _ => {
                 info!("goto handler at {:#x}", action.handler);
 
-                // save original sig mask
+                // save original sig mask, which is the one before sigsuspend if interrupted
                 let mut inner = thread.inner.lock();
-                let sig_mask = inner.sig_mask;
+                let sig_mask = inner.saved_sig_mask.take().unwrap_or(inner.sig_mask);
 
                 // update sig mask (see man sigaction(2))
                 // 1. block current
@@ -231,10 +451,7 @@ pub fn handle_signal(thread: &Arc<Thread>, tf: &mut UserContext) -> bool {
                 } - core::mem::size_of::<SignalFrame>();
 
                 let frame = if let Ok(frame) = unsafe {
-                    process
-                        .vm
-                        .lock()
-                        .check_write_ptr(sig_sp as *mut SignalFrame)
+                    lock_memory_set(&process.vm).check_write_ptr(sig_sp as *mut SignalFrame)
                 } {
                     frame
                 } else {
@@ -266,6 +483,16 @@ pub fn handle_signal(thread: &Arc<Thread>, tf: &mut UserContext) -> bool {
             }
         }
     }
+    // no handler has saved the mask before sigsuspend in its frame
+    let mut inner = thread.inner.lock();
+    if let Some(sig_mask) = inner.saved_sig_mask.take() {
+        inner.sig_mask = sig_mask;
+    }
+    drop(inner);
+    drop(process);
+    if let Some((code, status)) = job {
+        notify_job(&thread.proc, code, status);
+    }
     return false;
 }
 
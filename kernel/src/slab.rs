@@ -0,0 +1,341 @@
+//! Slab allocator for small kernel objects
+//!
+//! Objects of the same size are carved out of slabs of whole frames
+//! and recycled through a free list in each slab, so hot allocations like `Thread`
+//! and `Process` neither fragment nor contend on the buddy heap.
+//! A slab whose objects are all freed is given back to the frame allocator,
+//! except for one kept by each cache to absorb bursts.
+//!
+//! Other sizes go to `HEAP_ALLOCATOR`, and so does any allocation while no frame
+//! can be had, e.g. before `memory::init` on x86_64. Such objects are told apart
+//! on dealloc by the address, see `memory::in_heap`.
+
+use super::HEAP_ALLOCATOR;
+use crate::memory::{alloc_frame_contiguous, dealloc_frame, in_heap, phys_to_virt, virt_to_phys};
+use crate::process::{Process, Thread};
+use crate::sync::SpinNoIrqLock as Mutex;
+use alloc::alloc::{GlobalAlloc, Layout};
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use core::mem::size_of;
+use core::ptr::null_mut;
+use rcore_memory::PAGE_SIZE;
+
+/// Number of frames in a slab, a power of 2
+const SLAB_PAGES: usize = 4;
+const SLAB_SIZE: usize = SLAB_PAGES * PAGE_SIZE;
+/// Alignment of every object in a slab
+const SLAB_ALIGN: usize = 16;
+/// Space taken by `SlabHeader` at the start of each slab
+const HEADER_SIZE: usize = (size_of::<SlabHeader>() + SLAB_ALIGN - 1) & !(SLAB_ALIGN - 1);
+
+/// Size of the allocation behind `Arc<T>`: two counters followed by `T`
+const fn arc_size<T>() -> usize {
+    2 * size_of::<usize>() + size_of::<T>()
+}
+
+/// Caches of kernel objects, by the size of the `Arc` holding them.
+/// An allocation of exactly that size is served by them first,
+/// as a global allocator only knows the layout.
+static TYPED_CACHES: [SlabCache; 2] = [
+    SlabCache::new("process", arc_size::<Mutex<Process>>()),
+    SlabCache::new("thread", arc_size::<Thread>()),
+];
+
+/// Caches of other objects by size.
+/// An allocation is served by the smallest cache it fits in.
+static SIZE_CACHES: [SlabCache; 7] = [
+    SlabCache::new("size-32", 32),
+    SlabCache::new("size-64", 64),
+    SlabCache::new("size-128", 128),
+    SlabCache::new("size-256", 256),
+    SlabCache::new("size-512", 512),
+    SlabCache::new("size-1024", 1024),
+    SlabCache::new("size-2048", 2048),
+];
+
+pub struct SlabCache {
+    name: &'static str,
+    obj_size: usize,
+    inner: Mutex<SlabInner>,
+}
+
+struct SlabInner {
+    /// Address of the first slab with free objects, linked by `SlabHeader::next`
+    partial: usize,
+    /// Number of objects in all slabs
+    total: usize,
+    /// Number of objects in use
+    allocated: usize,
+    /// Number of slabs
+    slabs: usize,
+    /// Number of slabs with no object in use
+    empty: usize,
+}
+
+/// Start of each slab
+struct SlabHeader {
+    /// Address of the first free object, which holds the address of the next one
+    free: usize,
+    /// Number of objects in use
+    inuse: usize,
+    /// Neighbours in the list of slabs with free objects
+    prev: usize,
+    next: usize,
+}
+
+/// Usage of a cache
+#[derive(Debug, Clone, Copy)]
+pub struct SlabStats {
+    pub name: &'static str,
+    pub obj_size: usize,
+    pub allocated: usize,
+    pub free: usize,
+    pub slabs: usize,
+}
+
+impl SlabCache {
+    const fn new(name: &'static str, size: usize) -> Self {
+        SlabCache {
+            name,
+            obj_size: (size + SLAB_ALIGN - 1) & !(SLAB_ALIGN - 1),
+            inner: Mutex::new(SlabInner {
+                partial: 0,
+                total: 0,
+                allocated: 0,
+                slabs: 0,
+                empty: 0,
+            }),
+        }
+    }
+
+    /// Objects in a slab
+    fn capacity(&self) -> usize {
+        (SLAB_SIZE - HEADER_SIZE) / self.obj_size
+    }
+
+    /// Allocate an object, or return null if there is no frame for a new slab
+    unsafe fn alloc(&self) -> *mut u8 {
+        if let Some(ptr) = self.inner.lock().pop() {
+            return ptr;
+        }
+        // allocate a new slab without holding the lock:
+        // the frame allocator may free heap objects to get memory
+        let slab = match alloc_frame_contiguous(SLAB_PAGES, SLAB_PAGES.trailing_zeros() as usize) {
+            Some(paddr) => phys_to_virt(paddr),
+            None => return null_mut(),
+        };
+        let mut inner = self.inner.lock();
+        inner.grow(slab, self.obj_size, self.capacity());
+        inner.pop().unwrap()
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8) {
+        let mut inner = self.inner.lock();
+        let slab = inner.push(ptr);
+        if slab == 0 {
+            return;
+        }
+        inner.total -= self.capacity();
+        drop(inner);
+        let paddr = virt_to_phys(slab);
+        for i in 0..SLAB_PAGES {
+            dealloc_frame(paddr + i * PAGE_SIZE);
+        }
+    }
+
+    pub fn stats(&self) -> SlabStats {
+        let inner = self.inner.lock();
+        SlabStats {
+            name: self.name,
+            obj_size: self.obj_size,
+            allocated: inner.allocated,
+            free: inner.total - inner.allocated,
+            slabs: inner.slabs,
+        }
+    }
+}
+
+unsafe fn header(slab: usize) -> &'static mut SlabHeader {
+    &mut *(slab as *mut SlabHeader)
+}
+
+impl SlabInner {
+    unsafe fn pop(&mut self) -> Option<*mut u8> {
+        if self.partial == 0 {
+            return None;
+        }
+        let slab = header(self.partial);
+        let ptr = slab.free as *mut usize;
+        slab.free = *ptr;
+        if slab.inuse == 0 {
+            self.empty -= 1;
+        }
+        slab.inuse += 1;
+        if slab.free == 0 {
+            self.unlink(self.partial);
+        }
+        self.allocated += 1;
+        Some(ptr as *mut u8)
+    }
+
+    /// Free an object, returning its slab if it is now empty and should be released
+    unsafe fn push(&mut self, ptr: *mut u8) -> usize {
+        let base = ptr as usize & !(SLAB_SIZE - 1);
+        let slab = header(base);
+        if slab.free == 0 {
+            self.link(base);
+        }
+        *(ptr as *mut usize) = slab.free;
+        slab.free = ptr as usize;
+        slab.inuse -= 1;
+        self.allocated -= 1;
+        if slab.inuse != 0 {
+            return 0;
+        }
+        if self.empty == 0 {
+            self.empty += 1;
+            return 0;
+        }
+        self.unlink(base);
+        self.slabs -= 1;
+        base
+    }
+
+    /// Put all objects in the slab at `start` to its free list
+    unsafe fn grow(&mut self, start: usize, obj_size: usize, count: usize) {
+        let slab = header(start);
+        slab.free = 0;
+        slab.inuse = 0;
+        for i in (0..count).rev() {
+            let ptr = (start + HEADER_SIZE + i * obj_size) as *mut usize;
+            *ptr = slab.free;
+            slab.free = ptr as usize;
+        }
+        self.link(start);
+        self.total += count;
+        self.slabs += 1;
+        self.empty += 1;
+    }
+
+    unsafe fn link(&mut self, slab: usize) {
+        let header = header(slab);
+        header.prev = 0;
+        header.next = self.partial;
+        if self.partial != 0 {
+            self::header(self.partial).prev = slab;
+        }
+        self.partial = slab;
+    }
+
+    unsafe fn unlink(&mut self, slab: usize) {
+        let (prev, next) = (header(slab).prev, header(slab).next);
+        match prev {
+            0 => self.partial = next,
+            _ => header(prev).next = next,
+        }
+        if next != 0 {
+            header(next).prev = prev;
+        }
+    }
+}
+
+/// Find the cache serving allocations of `layout`
+fn cache_for(layout: &Layout) -> Option<&'static SlabCache> {
+    if layout.align() > SLAB_ALIGN {
+        return None;
+    }
+    let size = (layout.size() + SLAB_ALIGN - 1) & !(SLAB_ALIGN - 1);
+    TYPED_CACHES
+        .iter()
+        .find(|cache| cache.obj_size == size)
+        .or_else(|| SIZE_CACHES.iter().find(|cache| cache.obj_size >= size))
+}
+
+/// The global allocator: slab caches first, then the heap
+pub struct SlabAllocator;
+
+unsafe impl GlobalAlloc for SlabAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = match cache_for(&layout) {
+            Some(cache) => cache.alloc(),
+            None => null_mut(),
+        };
+        match ptr.is_null() {
+            true => HEAP_ALLOCATOR.alloc(layout),
+            false => ptr,
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        match cache_for(&layout) {
+            Some(cache) if !in_heap(ptr as usize) => cache.dealloc(ptr),
+            _ => HEAP_ALLOCATOR.dealloc(ptr, layout),
+        }
+    }
+}
+
+/// Usage of all caches
+pub fn slab_stats() -> Vec<SlabStats> {
+    TYPED_CACHES
+        .iter()
+        .chain(SIZE_CACHES.iter())
+        .map(|cache| cache.stats())
+        .collect()
+}
+
+/// Content of `/proc/slabinfo`
+pub fn slabinfo() -> String {
+    let mut res = String::from("# name            <active_objs> <num_objs> <objsize> <slabs>\n");
+    for stats in slab_stats() {
+        writeln!(
+            res,
+            "{:<18} {:>13} {:>10} {:>9} {:>7}",
+            stats.name,
+            stats.allocated,
+            stats.allocated + stats.free,
+            stats.obj_size,
+            stats.slabs
+        )
+        .unwrap();
+    }
+    res
+}
+
+/// Compare the cost of allocating the objects of fork and thread creation
+/// from their caches and from the heap, as the kernel did before slabs.
+///
+/// Each round allocates a batch of objects and frees them, as a burst of forks would.
+#[cfg(feature = "profile")]
+pub fn benchmark() {
+    use crate::arch::timer::timer_now;
+    const ROUNDS: usize = 64;
+    const BATCH: usize = 64;
+    let mut ptrs = [null_mut(); BATCH];
+    for cache in TYPED_CACHES.iter() {
+        let layout = Layout::from_size_align(cache.obj_size, SLAB_ALIGN).unwrap();
+        let mut run = |alloc: &dyn Fn() -> *mut u8, dealloc: &dyn Fn(*mut u8)| {
+            let begin = timer_now();
+            for _ in 0..ROUNDS {
+                for ptr in ptrs.iter_mut() {
+                    *ptr = alloc();
+                }
+                for &ptr in ptrs.iter() {
+                    dealloc(ptr);
+                }
+            }
+            (timer_now() - begin).as_nanos() / (ROUNDS * BATCH) as u128
+        };
+        let slab = run(&|| unsafe { cache.alloc() }, &|ptr| unsafe {
+            cache.dealloc(ptr)
+        });
+        let heap = run(&|| unsafe { HEAP_ALLOCATOR.alloc(layout) }, &|ptr| unsafe {
+            HEAP_ALLOCATOR.dealloc(ptr, layout)
+        });
+        warn!(
+            "slab benchmark: {} ({} bytes): alloc+free {} ns from slab, {} ns from heap",
+            cache.name, cache.obj_size, slab, heap
+        );
+    }
+}
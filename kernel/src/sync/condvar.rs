@@ -1,10 +1,16 @@
 use super::*;
+use crate::arch::timer::timer_now;
 use crate::consts::{INFORM_PER_MSEC, USEC_PER_TICK};
-use crate::process::{Process, Thread};
-use crate::syscall::TimeSpec;
+use crate::process::{lock_process, Process};
+use crate::trap::NAIVE_TIMER;
+use alloc::boxed::Box;
 use alloc::collections::VecDeque;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll, Waker};
+use core::time::Duration;
 
 pub struct RegisteredProcess {
     proc: Arc<SpinNoIrqLock<Process>>,
@@ -13,9 +19,24 @@ pub struct RegisteredProcess {
     fd: usize,
 }
 
+/// How `Condvar::wait_timeout` returned
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WaitResult {
+    Notified,
+    TimedOut,
+}
+
+/// A task waiting in `Condvar::wait_timeout`
+#[derive(Default)]
+struct Waiter {
+    /// Set by the first of notify and timeout, the other one is then ignored
+    result: Option<WaitResult>,
+    waker: Option<Waker>,
+}
+
 #[derive(Default)]
 pub struct Condvar {
-    wait_queue: SpinNoIrqLock<VecDeque<Arc<Thread>>>,
+    wait_queue: SpinNoIrqLock<VecDeque<Arc<SpinNoIrqLock<Waiter>>>>,
     pub epoll_queue: SpinNoIrqLock<VecDeque<RegisteredProcess>>,
 }
 
@@ -40,7 +61,7 @@ impl Condvar {
         //});
     }
 
-    fn add_to_wait_queue(&self) -> MutexGuard<VecDeque<Arc<Thread>>, SpinNoIrq> {
+    fn add_to_wait_queue(&self) -> MutexGuard<VecDeque<Arc<SpinNoIrqLock<Waiter>>>, SpinNoIrq> {
         let mut lock = self.wait_queue.lock();
         //lock.push_back(Arc::new(thread::current()));
         return lock;
@@ -105,54 +126,43 @@ impl Condvar {
         mutex.lock()
     }
 
-    /// Park current thread and wait for this condvar to be notified or timeout.
-    pub fn wait_timeout<'a, T, S>(
+    /// Release `guard` and wait for this condvar to be notified, or `deadline` of `timer_now`.
+    /// The lock is taken again before returning.
+    ///
+    /// The task is queued before `guard` is released, so a notify after that is not lost.
+    /// If a notify and the timeout happen together, whichever comes first under the lock
+    /// of the waiter wins, and a notify seen when polled wins over an expired deadline.
+    /// If the wait is dropped before it returns, a notify it got is passed on to another waiter.
+    pub async fn wait_timeout<'a, T, S>(
         &self,
         guard: MutexGuard<'a, T, S>,
-        timeout: TimeSpec,
-    ) -> Option<MutexGuard<'a, T, S>>
+        deadline: Duration,
+    ) -> (MutexGuard<'a, T, S>, WaitResult)
     where
         S: MutexSupport,
     {
         let mutex = guard.mutex;
-        //let token = Arc::new(thread::current());
-        let mut lock = self.wait_queue.lock();
-        //lock.push_back(token.clone());
-        drop(lock);
+        let waiter = Arc::new(SpinNoIrqLock::new(Waiter::default()));
+        self.wait_queue.lock().push_back(waiter.clone());
         drop(guard);
 
-        let timeout = core::time::Duration::new(timeout.sec as u64, timeout.nsec as u32);
-        let begin = crate::trap::uptime_msec();
-        if timeout.as_millis() != 0 {
-            //sleep(timeout);
-        }
-        // let mut lock = self.wait_queue.lock();
-        // lock.retain(|t| !Arc::ptr_eq(&t, &token));
-        let end = crate::trap::uptime_msec();
-        if end - begin >= timeout.as_millis() as usize {
-            None
-        } else {
-            Some(mutex.lock())
+        let result = WaitFuture {
+            condvar: self,
+            waiter,
+            deadline,
+            timer_added: false,
+            returned: false,
         }
+        .await;
+        (mutex.lock(), result)
     }
 
     pub fn notify_one(&self) {
-        let mut queue = self.wait_queue.lock();
-        if let Some(t) = queue.front() {
-            self.epoll_callback(t);
-            // info!("nofity thread: {}", t.id());
-            //t.unpark();
-            queue.pop_front();
-        }
+        self.notify_n(1);
     }
 
     pub fn notify_all(&self) {
-        let mut queue = self.wait_queue.lock();
-        for t in queue.iter() {
-            self.epoll_callback(t);
-            //t.unpark();
-        }
-        queue.clear();
+        self.notify_n(usize::max_value());
     }
 
     /// Notify up to `n` waiters.
@@ -160,16 +170,22 @@ impl Condvar {
     pub fn notify_n(&self, n: usize) -> usize {
         let mut count = 0;
         let mut queue = self.wait_queue.lock();
-        for t in queue.iter() {
-            if count >= n {
-                break;
+        while count < n {
+            let waiter = match queue.pop_front() {
+                Some(waiter) => waiter,
+                None => break,
+            };
+            let mut waiter = waiter.lock();
+            // skip those timed out but not removed yet
+            if waiter.result.is_none() {
+                waiter.result = Some(WaitResult::Notified);
+                if let Some(waker) = waiter.waker.take() {
+                    waker.wake();
+                }
+                drop(waiter);
+                self.epoll_callback();
+                count += 1;
             }
-            self.epoll_callback(t);
-            //t.unpark();
-            count += 1;
-        }
-        for _ in 0..count {
-            queue.pop_front();
         }
         count
     }
@@ -203,12 +219,12 @@ impl Condvar {
         return false;
     }
 
-    fn epoll_callback(&self, thread: &Arc<Thread>) {
+    fn epoll_callback(&self) {
         let epoll_list = self.epoll_queue.lock();
         for ist in epoll_list.iter() {
             //if thread.id() == ist.tid {
             if true {
-                let proc = ist.proc.lock();
+                let proc = lock_process(&ist.proc);
                 match proc.get_epoll_instance(ist.epfd) {
                     Ok(instacne) => {
                         let mut ready_list = instacne.ready_list.lock();
@@ -222,3 +238,62 @@ impl Condvar {
         }
     }
 }
+
+#[must_use = "future does nothing unless polled/`await`-ed"]
+struct WaitFuture<'a> {
+    condvar: &'a Condvar,
+    waiter: Arc<SpinNoIrqLock<Waiter>>,
+    deadline: Duration,
+    timer_added: bool,
+    /// Whether the result is returned
+    returned: bool,
+}
+
+impl Future for WaitFuture<'_> {
+    type Output = WaitResult;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let mut waiter = self.waiter.lock();
+        if let Some(result) = waiter.result {
+            drop(waiter);
+            self.returned = true;
+            return Poll::Ready(result);
+        }
+        if timer_now() >= self.deadline {
+            waiter.result = Some(WaitResult::TimedOut);
+            drop(waiter);
+            self.returned = true;
+            return Poll::Ready(WaitResult::TimedOut);
+        }
+        waiter.waker = Some(cx.waker().clone());
+        drop(waiter);
+
+        if !self.timer_added {
+            self.timer_added = true;
+            let waiter = self.waiter.clone();
+            NAIVE_TIMER.lock().add(
+                self.deadline,
+                Box::new(move |_| {
+                    if let Some(waker) = waiter.lock().waker.take() {
+                        waker.wake();
+                    }
+                }),
+            );
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for WaitFuture<'_> {
+    fn drop(&mut self) {
+        // a timed out waiter is still queued, and so is one dropped while waiting
+        self.condvar
+            .wait_queue
+            .lock()
+            .retain(|other| !Arc::ptr_eq(other, &self.waiter));
+        // no notify reaches it any more, and one it got but did not return is not lost
+        if !self.returned && self.waiter.lock().result == Some(WaitResult::Notified) {
+            self.condvar.notify_one();
+        }
+    }
+}
@@ -1,11 +1,13 @@
-use crate::sync::SpinNoIrqLock as Mutex;
+use crate::sync::{assert_unlocked, SpinNoIrqLock as Mutex};
 use alloc::boxed::Box;
-use alloc::{sync::Arc, vec::Vec};
+use alloc::{collections::VecDeque, sync::Arc, vec::Vec};
 use bitflags::bitflags;
 use core::{
     future::Future,
+    mem,
     pin::Pin,
-    task::{Context, Poll},
+    sync::atomic::{AtomicUsize, Ordering},
+    task::{Context, Poll, Waker},
 };
 
 bitflags! {
@@ -16,11 +18,15 @@ bitflags! {
         const WRITABLE                      = 1 << 1;
         const ERROR                         = 1 << 2;
         const CLOSED                        = 1 << 3;
+        const OPENED                        = 1 << 4;
 
         /// Process
         const PROCESS_QUIT                  = 1 << 10;
         const CHILD_PROCESS_QUIT            = 1 << 11;
         const RECEIVE_SIGNAL                = 1 << 12;
+        const PTRACE_RESUME                 = 1 << 13;
+        const PROCESS_CONTINUE              = 1 << 14;
+        const PROCESS_EXEC                  = 1 << 15;
 
         /// Semaphore
         const SEMAPHORE_REMOVED             = 1 << 20;
@@ -30,12 +36,45 @@ bitflags! {
 
 pub type EventHandler = Box<dyn Fn(Event) -> bool + Send>;
 
+/// Tells whether a waiter of `wait_for_event_one` with a key is interested in a notification,
+/// and if so whether it consumes it, see `EventBus::notify_one`
+pub type Accept = Arc<dyn Fn(usize) -> Option<bool> + Send + Sync>;
+
+/// Events set on a bus and the waiters of them.
+///
+/// Subscribers and `wait_for_event` are woken whenever the events change,
+/// for state that every waiter must re-check.
+/// Waiters of `wait_for_event_one` are queued, and `notify_one` wakes only the first of them
+/// whose key it accepts, for something that only one waiter can consume, e.g. a child to be reaped.
 #[derive(Default)]
 pub struct EventBus {
     event: Event,
     callbacks: Vec<EventHandler>,
+    /// Waiters of `wait_for_event_one` in FIFO order.
+    /// They keep their place until they see being woken.
+    waiters: VecDeque<Waiter>,
+}
+
+struct Waiter {
+    id: usize,
+    mask: Event,
+    key: usize,
+    state: WaiterState,
+}
+
+enum WaiterState {
+    /// With the waker once polled
+    Waiting(Option<Waker>),
+    /// Woken by a change of events, or by a notification it does not consume
+    Woken,
+    /// Woken by a notification it consumes, which is passed on if the waiter
+    /// is dropped before seeing it
+    Notified(Event, Accept),
 }
 
+/// Used to identify waiters of `wait_for_event_one`
+static WAITER_ID: AtomicUsize = AtomicUsize::new(1);
+
 impl EventBus {
     pub fn new() -> Arc<Mutex<Self>> {
         Arc::new(Mutex::new(Self::default()))
@@ -57,6 +96,72 @@ impl EventBus {
         self.event = new;
         if new != orig {
             self.callbacks.retain(|f| !f(new));
+            // a broadcast wakes single waiters too
+            self.wake_all(new);
+        }
+    }
+
+    /// Set `set` and wake waiters of `wait_for_event_one` interested in it in FIFO order,
+    /// even if it is already set, until one consumes it. Subscribers are woken as by `set`.
+    ///
+    /// `accept` returns `None` for a key not interested in it, `Some(true)` for one consuming it,
+    /// and `Some(false)` for one only looking at it, which is woken without stopping.
+    pub fn notify_one(
+        &mut self,
+        set: Event,
+        accept: impl Fn(usize) -> Option<bool> + Send + Sync + 'static,
+    ) {
+        self.event.insert(set);
+        let event = self.event;
+        self.callbacks.retain(|f| !f(event));
+        self.notify_from(0, set, Arc::new(accept));
+    }
+
+    /// Wake waiters from the `from`th on for `notify_one`
+    fn notify_from(&mut self, from: usize, set: Event, accept: Accept) {
+        for waiter in self.waiters.iter_mut().skip(from) {
+            let waiting = matches!(waiter.state, WaiterState::Waiting(_));
+            if !waiting || (waiter.mask & set).is_empty() {
+                continue;
+            }
+            let consume = match accept(waiter.key) {
+                Some(consume) => consume,
+                None => continue,
+            };
+            let state = match consume {
+                true => WaiterState::Notified(set, accept.clone()),
+                false => WaiterState::Woken,
+            };
+            if let WaiterState::Waiting(Some(waker)) = mem::replace(&mut waiter.state, state) {
+                waker.wake();
+            }
+            if consume {
+                return;
+            }
+        }
+    }
+
+    /// Set `set` and wake all subscribers and waiters interested in it, even if it is already set,
+    /// for an event that tells something new each time, e.g. a signal.
+    pub fn notify_all(&mut self, set: Event) {
+        self.event.insert(set);
+        let event = self.event;
+        self.callbacks.retain(|f| !f(event));
+        self.wake_all(set);
+    }
+
+    /// Wake all waiters of `wait_for_event_one` interested in `event`
+    fn wake_all(&mut self, event: Event) {
+        for waiter in self.waiters.iter_mut() {
+            if !matches!(waiter.state, WaiterState::Waiting(_)) || (waiter.mask & event).is_empty()
+            {
+                continue;
+            }
+            if let WaiterState::Waiting(Some(waker)) =
+                mem::replace(&mut waiter.state, WaiterState::Woken)
+            {
+                waker.wake();
+            }
         }
     }
 
@@ -69,14 +174,72 @@ impl EventBus {
     }
 }
 
+/// Wait until any event in `mask` is set on `bus`.
+///
+/// No process or memory set lock may be held while awaiting it: drop the lock,
+/// await, then lock again and re-check the condition, since the event only
+/// tells that something may have changed. See `wait_for_event_unlocked`.
 pub fn wait_for_event(bus: Arc<Mutex<EventBus>>, mask: Event) -> impl Future<Output = Event> {
-    EventBusFuture { bus, mask }
+    EventBusFuture {
+        bus,
+        mask,
+        one: None,
+    }
+}
+
+/// Queue behind other such waiters until woken by `EventBus::notify_one`
+/// for an event in `mask` with an accepted `key`, or by any change of events in `mask`.
+///
+/// Unlike `wait_for_event`, events already set do not end the wait,
+/// as the waiter is queued when this is called rather than when first polled.
+/// So a notification sent after that is never lost, and one consumed by a waiter
+/// dropped without seeing it is passed on to the next one.
+pub fn wait_for_event_one(
+    bus: Arc<Mutex<EventBus>>,
+    mask: Event,
+    key: usize,
+) -> impl Future<Output = Event> {
+    let id = WAITER_ID.fetch_add(1, Ordering::Relaxed);
+    bus.lock().waiters.push_back(Waiter {
+        id,
+        mask,
+        key,
+        state: WaiterState::Waiting(None),
+    });
+    EventBusFuture {
+        bus,
+        mask,
+        one: Some(id),
+    }
+}
+
+/// Release `guard`, then wait until any event in `mask` is set on `bus`.
+///
+/// Taking the guard by value makes sure it is not held while parked.
+pub async fn wait_for_event_unlocked<G>(guard: G, bus: Arc<Mutex<EventBus>>, mask: Event) -> Event {
+    drop(guard);
+    wait_for_event(bus, mask).await
+}
+
+/// Queue for `wait_for_event_one` while still holding `guard`, then release it and wait,
+/// so that no notification sent after `guard` is released is missed
+pub async fn wait_for_event_one_unlocked<G>(
+    guard: G,
+    bus: Arc<Mutex<EventBus>>,
+    mask: Event,
+    key: usize,
+) -> Event {
+    let wait = wait_for_event_one(bus, mask, key);
+    drop(guard);
+    wait.await
 }
 
 #[must_use = "future does nothing unless polled/`await`-ed"]
 struct EventBusFuture {
     bus: Arc<Mutex<EventBus>>,
     mask: Event,
+    /// Id in `EventBus::waiters` if waiting for `notify_one`
+    one: Option<usize>,
 }
 
 impl Future for EventBusFuture {
@@ -84,9 +247,24 @@ impl Future for EventBusFuture {
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
         let mut lock = self.bus.lock();
+        if let Some(id) = self.one {
+            let i = match lock.waiters.iter().position(|waiter| waiter.id == id) {
+                Some(i) => i,
+                // woken and seen already
+                None => return Poll::Ready(lock.event),
+            };
+            if let WaiterState::Waiting(waker) = &mut lock.waiters[i].state {
+                assert_unlocked("wait_for_event_one");
+                *waker = Some(cx.waker().clone());
+                return Poll::Pending;
+            }
+            lock.waiters.remove(i);
+            return Poll::Ready(lock.event);
+        }
         if !(lock.event & self.mask).is_empty() {
             return Poll::Ready(lock.event);
         }
+        assert_unlocked("wait_for_event");
         let waker = cx.waker().clone();
         let mask = self.mask;
         lock.subscribe(Box::new(move |s| {
@@ -99,3 +277,20 @@ impl Future for EventBusFuture {
         Poll::Pending
     }
 }
+
+impl Drop for EventBusFuture {
+    fn drop(&mut self) {
+        // so that `notify_one` does not wake a waiter that is gone
+        if let Some(id) = self.one {
+            let mut bus = self.bus.lock();
+            if let Some(i) = bus.waiters.iter().position(|waiter| waiter.id == id) {
+                // pass on a notification this waiter would have consumed,
+                // to the waiters queued after it
+                let waiter = bus.waiters.remove(i).unwrap();
+                if let WaiterState::Notified(set, accept) = waiter.state {
+                    bus.notify_from(i, set, accept);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,113 @@
+//! Lock ordering between processes and memory sets
+//!
+//! A `Thread` holds both `proc` and `vm`, and `proc.vm` is the same memory set,
+//! so the two locks are often needed together. To avoid ABBA deadlocks on SMP,
+//! they must always be taken in this order:
+//!
+//! 1. `Process`: the lock of any `Mutex<Process>`
+//! 2. `MemorySet`: the lock of any `Mutex<MemorySet>`
+//!
+//! That is, never lock a process while holding a memory set.
+//! Two processes may only be held at once as a parent and then its child,
+//! as wait4 does. So the parent of a locked process is never locked:
+//! it is told of changes once the child is unlocked, see `notify_job`.
+//!
+//! Take them with `Thread::lock_proc` and `Thread::lock_vm`,
+//! or `lock_process` and `lock_memory_set` for others, never with a bare `lock`.
+//! In debug builds the classes held by each CPU are recorded,
+//! and acquiring a lock out of order panics.
+//!
+//! Neither kind of lock may be held while a thread is parked on an await
+//! point, see `assert_unlocked`.
+
+use crate::consts::MAX_CPU_NUM;
+use core::ops::{Deref, DerefMut};
+
+/// Classes of locks, in the order they must be taken
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LockClass {
+    Process = 0,
+    MemorySet = 1,
+}
+
+const CLASS_NUM: usize = 2;
+
+/// Number of locks of each class held by each CPU.
+/// Only accessed with interrupts disabled by the lock itself.
+#[cfg(debug_assertions)]
+static mut HELD: [[usize; CLASS_NUM]; MAX_CPU_NUM] = [[0; CLASS_NUM]; MAX_CPU_NUM];
+
+/// A guard released in the order of `LockClass`
+pub struct OrderedGuard<G> {
+    class: LockClass,
+    guard: G,
+}
+
+/// Take a lock of `class` with `lock`, checking the order in debug builds
+pub fn lock_ordered<G>(class: LockClass, lock: impl FnOnce() -> G) -> OrderedGuard<G> {
+    check(class);
+    let guard = lock();
+    hold(class, true);
+    OrderedGuard { class, guard }
+}
+
+#[cfg(debug_assertions)]
+fn check(class: LockClass) {
+    let held = unsafe { &HELD[crate::arch::cpu::id()] };
+    for later in (class as usize + 1)..CLASS_NUM {
+        if held[later] != 0 {
+            panic!(
+                "lock order violation: locking {:?} while holding {} lock(s) of a later class",
+                class, held[later]
+            );
+        }
+    }
+}
+
+#[cfg(not(debug_assertions))]
+fn check(_class: LockClass) {}
+
+/// Panic in debug builds if this CPU holds any lock of a `LockClass`.
+/// Called where the running thread may be parked.
+#[cfg(debug_assertions)]
+pub fn assert_unlocked(context: &str) {
+    let held = unsafe { &HELD[crate::arch::cpu::id()] };
+    if held.iter().any(|&n| n != 0) {
+        panic!("{}: parked while holding locks {:?}", context, held);
+    }
+}
+
+#[cfg(not(debug_assertions))]
+pub fn assert_unlocked(_context: &str) {}
+
+#[cfg(debug_assertions)]
+fn hold(class: LockClass, acquire: bool) {
+    let held = unsafe { &mut HELD[crate::arch::cpu::id()][class as usize] };
+    if acquire {
+        *held += 1;
+    } else {
+        *held -= 1;
+    }
+}
+
+#[cfg(not(debug_assertions))]
+fn hold(_class: LockClass, _acquire: bool) {}
+
+impl<G: Deref> Deref for OrderedGuard<G> {
+    type Target = G::Target;
+    fn deref(&self) -> &G::Target {
+        &self.guard
+    }
+}
+
+impl<G: DerefMut> DerefMut for OrderedGuard<G> {
+    fn deref_mut(&mut self) -> &mut G::Target {
+        &mut self.guard
+    }
+}
+
+impl<G> Drop for OrderedGuard<G> {
+    fn drop(&mut self) {
+        hold(self.class, false);
+    }
+}
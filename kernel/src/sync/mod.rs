@@ -13,6 +13,9 @@
 //! * `condvar`: 条件变量。
 //!     依赖`thread`，为其它工具提供线程调度支持。
 //!
+//! * `lock_order`: 锁的获取顺序。
+//!     规定先锁`Process`再锁`MemorySet`，debug模式下检查并在违反时panic。
+//!
 //! * `semaphore`: 信号量。
 //!     完全照搬`std::sync::Semaphore`，std中已经废弃。
 //!     貌似在Rust中并不常用，一般都用`Mutex`。
@@ -49,10 +52,12 @@
 
 pub use self::condvar::*;
 pub use self::event_bus::*;
+pub use self::lock_order::*;
 pub use self::mutex::*;
 pub use self::semaphore::*;
 
 mod condvar;
 mod event_bus;
+mod lock_order;
 mod mutex;
 mod semaphore;
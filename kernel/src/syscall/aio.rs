@@ -0,0 +1,192 @@
+//! Linux AIO, with the operations run by `fs::aio`
+
+use super::*;
+use crate::arch::timer::timer_now;
+use crate::fs::aio::*;
+use crate::memory::GlobalFrameAlloc;
+use rcore_memory::memory_set::handler::Delay;
+use rcore_memory::memory_set::MemoryAttr;
+use rcore_memory::PAGE_SIZE;
+
+/// Most events a context may hold, `aio-max-nr` of Linux
+const AIO_MAX_NR: usize = 0x10000;
+
+/// Signal the eventfd in `resfd` on completion
+const IOCB_FLAG_RESFD: u32 = 1;
+
+impl Syscall<'_> {
+    /// Create a context for `nr_events` events, whose id is stored to `ctx_id`.
+    ///
+    /// The id is the address of a page mapped for the context, where Linux puts the
+    /// completion ring. It stays zero here, so libaio finds no ring to reap from user space
+    /// and always calls io_getevents.
+    pub fn sys_io_setup(&mut self, nr_events: usize, mut ctx_id: UserInOutPtr<usize>) -> SysResult {
+        info!("io_setup: nr_events: {}, ctx_id: {:?}", nr_events, ctx_id);
+        let nr_events = nr_events as u32 as usize;
+        if ctx_id.read()? != 0 || nr_events == 0 {
+            return Err(SysError::EINVAL);
+        }
+        if nr_events > AIO_MAX_NR {
+            return Err(SysError::EAGAIN);
+        }
+        let ring = {
+            let mut vm = self.vm();
+            let ring = vm.find_free_area(PAGE_SIZE, PAGE_SIZE);
+            vm.push(
+                ring,
+                ring + PAGE_SIZE,
+                MemoryAttr::default().user().readonly(),
+                Delay::new(GlobalFrameAlloc),
+                "aio_ring",
+            );
+            ring
+        };
+        if let Err(err) = ctx_id.write(ring) {
+            self.vm().pop(ring, ring + PAGE_SIZE);
+            self.shootdown();
+            return Err(err);
+        }
+        self.process()
+            .aio_contexts
+            .insert(ring, AioContext::new(nr_events));
+        Ok(0)
+    }
+
+    /// Destroy the context `ctx_id`. Operations in flight still run to the end,
+    /// but their events are dropped.
+    pub fn sys_io_destroy(&mut self, ctx_id: usize) -> SysResult {
+        info!("io_destroy: ctx_id: {:#x}", ctx_id);
+        self.process()
+            .aio_contexts
+            .remove(&ctx_id)
+            .ok_or(SysError::EINVAL)?;
+        // the ring may have been unmapped by the user
+        self.vm().pop_with_split(ctx_id, ctx_id + PAGE_SIZE)?;
+        self.shootdown();
+        Ok(0)
+    }
+
+    /// Queue the `nr` iocbs whose addresses are at `iocbpp` on context `ctx_id`.
+    /// Return how many are queued, failing only if none is.
+    pub fn sys_io_submit(
+        &mut self,
+        ctx_id: usize,
+        nr: usize,
+        iocbpp: UserInPtr<usize>,
+    ) -> SysResult {
+        info!(
+            "io_submit: ctx_id: {:#x}, nr: {}, iocbpp: {:?}",
+            ctx_id, nr as isize, iocbpp
+        );
+        if (nr as isize) < 0 {
+            return Err(SysError::EINVAL);
+        }
+        let context = self.aio_context(ctx_id)?;
+        let iocbs = iocbpp.read_array(nr)?;
+        for (i, &iocb) in iocbs.iter().enumerate() {
+            if let Err(err) = self.submit_iocb(&context, iocb) {
+                return if i == 0 { Err(err) } else { Ok(i) };
+            }
+        }
+        Ok(nr)
+    }
+
+    /// Queue the iocb at `addr` on `context`
+    fn submit_iocb(&mut self, context: &Arc<AioContext>, addr: usize) -> Result<(), SysError> {
+        let iocb = UserInPtr::<IoCb>::from(addr).read()?;
+        debug!("io_submit: {:#x?}", iocb);
+        if iocb.reserved2 != 0 || iocb.flags & IOCB_FLAG_RESFD != 0 {
+            // there is no eventfd to signal
+            return Err(SysError::EINVAL);
+        }
+        if iocb.rw_flags != 0 {
+            return Err(SysError::EOPNOTSUPP);
+        }
+        let file = self
+            .process()
+            .get_seekable_file(iocb.fildes as usize)?
+            .clone();
+        let options = file.options();
+        let (buf, len, offset) = (iocb.buf as usize, iocb.nbytes as usize, iocb.offset);
+        let op = match iocb.lio_opcode {
+            IOCB_CMD_PREAD | IOCB_CMD_PWRITE if offset < 0 || (len as isize) < 0 => {
+                return Err(SysError::EINVAL);
+            }
+            IOCB_CMD_PREAD => {
+                if !options.read {
+                    return Err(SysError::EBADF);
+                }
+                unsafe { self.vm().check_write_array(buf as *mut u8, len)? };
+                AioOp::Read {
+                    buf,
+                    len,
+                    offset: Some(offset as usize),
+                }
+            }
+            IOCB_CMD_PWRITE => {
+                if !options.write {
+                    return Err(SysError::EBADF);
+                }
+                unsafe { self.vm().check_read_array(buf as *const u8, len)? };
+                AioOp::Write {
+                    buf,
+                    len,
+                    offset: Some(offset as usize),
+                }
+            }
+            IOCB_CMD_FSYNC => AioOp::Sync { data_only: false },
+            IOCB_CMD_FDSYNC => AioOp::Sync { data_only: true },
+            _ => return Err(SysError::EINVAL),
+        };
+        context.submit(addr, iocb.data, file, self.thread.vm(), op)
+    }
+
+    /// Operations run to the end once submitted, so they can't be cancelled
+    pub fn sys_io_cancel(&mut self, ctx_id: usize, iocb: usize, _result: usize) -> SysResult {
+        info!("io_cancel: ctx_id: {:#x}, iocb: {:#x}", ctx_id, iocb);
+        let context = self.aio_context(ctx_id)?;
+        if context.is_in_flight(iocb) {
+            Err(SysError::EAGAIN)
+        } else {
+            Err(SysError::EINVAL)
+        }
+    }
+
+    /// Wait for at least `min_nr` events of context `ctx_id` until `timeout`,
+    /// then take up to `nr` of them to `events`.
+    pub async fn sys_io_getevents(
+        &mut self,
+        ctx_id: usize,
+        min_nr: usize,
+        nr: usize,
+        mut events: UserOutPtr<IoEvent>,
+        timeout: UserInPtr<TimeSpec>,
+    ) -> SysResult {
+        info!(
+            "io_getevents: ctx_id: {:#x}, min_nr: {}, nr: {}, events: {:?}, timeout: {:?}",
+            ctx_id, min_nr as isize, nr as isize, events, timeout
+        );
+        if (min_nr as isize) < 0 || (nr as isize) < 0 || min_nr > nr {
+            return Err(SysError::EINVAL);
+        }
+        let context = self.aio_context(ctx_id)?;
+        let deadline = timeout
+            .read_if_not_null()?
+            .map(|timeout| timer_now() + timeout.to_duration());
+        // taken events can't be put back, so check where they go first
+        unsafe { self.vm().check_write_array(events.ptr(), nr)? };
+        let taken = context
+            .get_events(self.thread.clone(), min_nr, nr, deadline)
+            .await?;
+        events.write_array(&taken)?;
+        Ok(taken.len())
+    }
+
+    fn aio_context(&self, ctx_id: usize) -> Result<Arc<AioContext>, SysError> {
+        self.process()
+            .aio_contexts
+            .get(&ctx_id)
+            .cloned()
+            .ok_or(SysError::EINVAL)
+    }
+}
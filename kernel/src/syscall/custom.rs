@@ -56,4 +56,14 @@ impl Syscall<'_> {
         }
         Ok(0)
     }
+
+    /// Trace syscalls of thread `tid`, or of all threads if 0, until disabled.
+    /// It affects every process, so it takes CAP_SYS_ADMIN.
+    pub fn sys_syscall_trace(&mut self, enable: usize, tid: usize) -> SysResult {
+        if !capable(Capability::SysAdmin) {
+            return Err(SysError::EPERM);
+        }
+        set_trace(enable != 0, tid);
+        Ok(0)
+    }
 }
@@ -2,15 +2,17 @@
 
 #![allow(dead_code)]
 
-use core::cell::UnsafeCell;
 use core::cmp::min;
 use core::mem::size_of;
 #[cfg(not(target_arch = "mips"))]
 use rcore_fs::vfs::Timespec;
 
 use crate::drivers::SOCKET_ACTIVITY;
+use crate::fs::buffer_cache::buffercache_info;
 use crate::fs::*;
-use crate::memory::MemorySet;
+use crate::memory::{meminfo, MemorySet};
+use crate::signal::{send_signal, Siginfo, SI_USER};
+use crate::slab::slabinfo;
 use crate::sync::Condvar;
 use crate::trap::TICK_ACTIVITY;
 use alloc::boxed::Box;
@@ -23,10 +25,18 @@ use bitvec::prelude::{BitSlice, BitVec, Lsb0};
 use super::*;
 use crate::fs::epoll::EpollInstance;
 use crate::fs::fcntl::{FD_CLOEXEC, F_SETFD, O_CLOEXEC, O_NONBLOCK};
+use crate::fs::inotify::{
+    self, Inotify, IN_ALL_EVENTS, IN_CREATE, IN_DELETE, IN_DONT_FOLLOW, IN_MODIFY,
+};
+use crate::fs::memfd::{MemFd, Seals};
+use crate::fs::mount::normalize;
+use crate::fs::page_cache::Advice;
 use crate::fs::FileLike;
 use crate::process::Process;
 use crate::syscall::SysError::{EINTR, EINVAL, ESPIPE};
-use rcore_fs::vfs::PollStatus;
+use rcore_fs::vfs::{FileSystem, PollStatus};
+use rcore_fs_ramfs::RamFS;
+use rcore_memory::PAGE_SIZE;
 
 impl Syscall<'_> {
     pub async fn sys_read(&mut self, fd: usize, base: UserOutPtr<u8>, len: usize) -> SysResult {
@@ -37,21 +47,26 @@ impl Syscall<'_> {
         }
         let slice = unsafe { self.vm().check_write_array(base.ptr(), len)? };
 
-        let file_like = proc.get_file_like(fd)?;
+        // do not hold the process lock while blocking
+        let mut file_like = proc.get_file_like(fd)?.clone();
+        drop(proc);
         let len = file_like.read(slice).await?;
         Ok(len)
     }
 
-    pub fn sys_write(&mut self, fd: usize, base: *const u8, len: usize) -> SysResult {
+    pub async fn sys_write(&mut self, fd: usize, base: *const u8, len: usize) -> SysResult {
         let mut proc = self.process();
         if !proc.pid.is_init() {
             //we trust pid 0 process
             info!("write: fd: {}, base: {:?}, len: {:#x}", fd, base, len);
         }
         let slice = unsafe { self.vm().check_read_array(base, len)? };
-        let file_like = proc.get_file_like(fd)?;
-        let len = file_like.write(slice)?;
-        Ok(len)
+
+        // do not hold the process lock while blocking on a full pipe
+        let mut file_like = proc.get_file_like(fd)?.clone();
+        drop(proc);
+        let result = file_like.async_write(slice).await;
+        self.sigpipe_on_epipe(result)
     }
 
     pub async fn sys_pread(
@@ -65,13 +80,16 @@ impl Syscall<'_> {
             "pread: fd: {}, base: {:?}, len: {}, offset: {}",
             fd, base, len, offset
         );
-        let mut proc = self.process();
+        if (offset as isize) < 0 {
+            return Err(EINVAL);
+        }
         let slice = unsafe { self.vm().check_write_array(base.ptr(), len)? };
-        let len = proc.get_file(fd)?.read_at(offset, slice).await?;
+        let file = self.process().get_seekable_file(fd)?.clone();
+        let len = file.read_at(offset, slice).await?;
         Ok(len)
     }
 
-    pub fn sys_pwrite(
+    pub async fn sys_pwrite(
         &mut self,
         fd: usize,
         base: *const u8,
@@ -82,9 +100,12 @@ impl Syscall<'_> {
             "pwrite: fd: {}, base: {:?}, len: {}, offset: {}",
             fd, base, len, offset
         );
-        let mut proc = self.process();
+        if (offset as isize) < 0 {
+            return Err(EINVAL);
+        }
         let slice = unsafe { self.vm().check_read_array(base, len)? };
-        let len = proc.get_file(fd)?.write_at(offset, slice)?;
+        let file = self.process().get_seekable_file(fd)?.clone();
+        let len = file.write_at(offset, slice).await?;
         Ok(len)
     }
 
@@ -404,7 +425,7 @@ impl Syscall<'_> {
             match proc.files.get(&fd) {
                 Some(file_like) => {
                     match file_like {
-                        FileLike::File(_file) => {
+                        FileLike::File(_) | FileLike::IoUring(_) => {
                             callbacks.push((
                                 0, 0, // thread::current().id(),
                                 epfd, *fd,
@@ -542,7 +563,8 @@ impl Syscall<'_> {
             unsafe { IoVecs::check_and_new(iov_ptr.ptr(), iov_count, &self.vm(), true)? };
 
         // read all data to a buf
-        let file_like = proc.get_file_like(fd)?;
+        let mut file_like = proc.get_file_like(fd)?.clone();
+        drop(proc);
         let mut buf = iovs.new_buf(true);
         let len = file_like.read(buf.as_mut_slice()).await?;
         // copy data to user
@@ -550,7 +572,12 @@ impl Syscall<'_> {
         Ok(len)
     }
 
-    pub fn sys_writev(&mut self, fd: usize, iov_ptr: *const IoVec, iov_count: usize) -> SysResult {
+    pub async fn sys_writev(
+        &mut self,
+        fd: usize,
+        iov_ptr: *const IoVec,
+        iov_count: usize,
+    ) -> SysResult {
         let mut proc = self.process();
         if !proc.pid.is_init() {
             // we trust pid 0 process
@@ -567,16 +594,32 @@ impl Syscall<'_> {
         let iovs = unsafe { IoVecs::check_and_new(iov_ptr, iov_count, &self.vm(), false)? };
 
         let buf = iovs.read_all_to_vec();
-        let file_like = proc.get_file_like(fd)?;
-        let len = file_like.write(buf.as_slice())?;
-        Ok(len)
+        let mut file_like = proc.get_file_like(fd)?.clone();
+        drop(proc);
+        let result = file_like.async_write(buf.as_slice()).await;
+        self.sigpipe_on_epipe(result)
+    }
+
+    /// Raise SIGPIPE on the current thread if `result` is EPIPE,
+    /// as a write to a pipe or socket which can not be read any more does
+    fn sigpipe_on_epipe(&self, result: SysResult) -> SysResult {
+        if let Err(SysError::EPIPE) = result {
+            let info = Siginfo {
+                signo: Signal::SIGPIPE as i32,
+                errno: 0,
+                code: SI_USER,
+                field: Default::default(),
+            };
+            send_signal(self.thread.proc.clone(), self.thread.tid as isize, info);
+        }
+        result
     }
 
-    pub fn sys_open(&mut self, path: *const u8, flags: usize, mode: usize) -> SysResult {
-        self.sys_openat(AT_FDCWD, path, flags, mode)
+    pub async fn sys_open(&mut self, path: *const u8, flags: usize, mode: usize) -> SysResult {
+        self.sys_openat(AT_FDCWD, path, flags, mode).await
     }
 
-    pub fn sys_openat(
+    pub async fn sys_openat(
         &mut self,
         dir_fd: usize,
         path: *const u8,
@@ -611,6 +654,7 @@ impl Syscall<'_> {
                     let inode = dir_inode.create(file_name, FileType::File, mode as u32)?;
                     TimeSpec::update(&inode);
                     TimeSpec::update(&dir_inode);
+                    inotify::notify_entry(&dir_inode, IN_CREATE, file_name, FileType::File);
                     inode
                 }
                 Err(e) => return Err(SysError::from(e)),
@@ -619,13 +663,44 @@ impl Syscall<'_> {
             proc.lookup_inode_at(dir_fd, &path, true)?
         };
 
-        let file = FileHandle::new(
-            inode,
-            flags.to_options(),
-            String::from(path),
-            false,
-            flags.contains(OpenFlags::CLOEXEC),
-        );
+        let options = flags.to_options();
+        let metadata = inode.metadata().ok();
+        let file = match metadata {
+            Some(metadata) if metadata.type_ == FileType::NamedPipe => {
+                drop(proc);
+                let pipe = open_fifo(&metadata, options).await?;
+                proc = self.process();
+                FileHandle::new(
+                    Arc::new(pipe),
+                    options,
+                    String::from(path),
+                    true,
+                    flags.contains(OpenFlags::CLOEXEC),
+                )
+            }
+            _ => {
+                // a device node made by mknod opens the device file of its driver
+                let inode = match metadata.as_ref().and_then(special::node_rdev) {
+                    Some(rdev) => special::find_device(rdev).ok_or(SysError::ENXIO)?,
+                    None => inode,
+                };
+                let file = FileHandle::new(
+                    inode.clone(),
+                    options,
+                    String::from(path),
+                    false,
+                    flags.contains(OpenFlags::CLOEXEC),
+                );
+                // events of writes are also reported to the watches on the directory
+                let regular = metadata.map_or(false, |m| m.type_ == FileType::File);
+                if options.write && regular {
+                    if let Some(entry) = proc.entry_at(dir_fd, &path, &inode) {
+                        file.set_entry(entry);
+                    }
+                }
+                file
+            }
+        };
 
         // for debugging
         if cfg!(debug_assertions) {
@@ -636,6 +711,8 @@ impl Syscall<'_> {
         Ok(fd)
     }
 
+    /// Close `fd`, then write back its dirty pages in the page cache.
+    /// `fd` is closed even if that fails, and the error is reported.
     pub fn sys_close(&mut self, fd: usize) -> SysResult {
         info!("close: fd: {:?}", fd);
         let mut proc = self.process();
@@ -645,7 +722,45 @@ impl Syscall<'_> {
             debug!("files before close {:#?}", proc.files);
         }
 
-        proc.files.remove(&fd).ok_or(SysError::EBADF)?;
+        let file_like = proc.files.remove(&fd).ok_or(SysError::EBADF)?;
+        drop(proc);
+        if let FileLike::File(file) = file_like {
+            file.sync_cache()?;
+        }
+        Ok(0)
+    }
+
+    /// Close fds in `[first, last]`, or only mark them close-on-exec.
+    /// `last` may be `!0u32` for all fds from `first`.
+    pub fn sys_close_range(&mut self, first: usize, last: usize, flags: usize) -> SysResult {
+        info!(
+            "close_range: first: {}, last: {:#x}, flags: {:#x}",
+            first, last, flags
+        );
+        let flags = CloseRangeFlags::from_bits(flags).ok_or(SysError::EINVAL)?;
+        // it is an unsigned int
+        let last = last as u32 as usize;
+        if first > last {
+            return Err(SysError::EINVAL);
+        }
+        let mut proc = self.process();
+        if flags.contains(CloseRangeFlags::CLOEXEC) {
+            for (_, file_like) in proc.files.range_mut(first..=last) {
+                if let FileLike::File(file) = file_like {
+                    file.fd_cloexec = true;
+                }
+            }
+            return Ok(0);
+        }
+        let fds: Vec<usize> = proc.files.range(first..=last).map(|(&fd, _)| fd).collect();
+        for fd in fds {
+            if let Some(FileLike::File(file)) = proc.files.remove(&fd) {
+                // like close, but keep going on errors
+                if let Err(err) = file.sync_cache() {
+                    warn!("close_range: failed to sync fd {}: {:?}", fd, err);
+                }
+            }
+        }
         Ok(0)
     }
 
@@ -754,7 +869,7 @@ impl Syscall<'_> {
         let inode = proc.lookup_inode_at(dirfd, &path, false)?;
         if inode.metadata()?.type_ == FileType::SymLink {
             // TODO: recursive link resolution and loop detection
-            let len = inode.read_at(0, slice)?;
+            let len = page_cache::read_inode_at(&inode, 0, slice)?;
             Ok(len)
         } else {
             Err(SysError::EINVAL)
@@ -762,6 +877,9 @@ impl Syscall<'_> {
     }
 
     pub fn sys_lseek(&mut self, fd: usize, offset: i64, whence: u8) -> SysResult {
+        if whence == SEEK_DATA || whence == SEEK_HOLE {
+            return self.seek_data_hole(fd, offset, whence == SEEK_HOLE);
+        }
         let pos = match whence {
             SEEK_SET => SeekFrom::Start(offset as u64),
             SEEK_END => SeekFrom::End(offset),
@@ -772,7 +890,7 @@ impl Syscall<'_> {
 
         let mut proc = self.process();
         let file = proc.get_file(fd)?;
-        if file.pipe {
+        if file.is_pipe() {
             Err(ESPIPE)
         } else {
             let offset = file.seek(pos)?;
@@ -780,6 +898,25 @@ impl Syscall<'_> {
         }
     }
 
+    /// Move the offset of `fd` to the next data or hole at or after `offset`
+    fn seek_data_hole(&mut self, fd: usize, offset: i64, hole: bool) -> SysResult {
+        info!("lseek: fd: {}, offset: {}, hole: {}", fd, offset, hole);
+        let mut proc = self.process();
+        let file = proc.get_file(fd)?;
+        if file.is_pipe() {
+            return Err(ESPIPE);
+        }
+        if offset < 0 {
+            return Err(SysError::ENXIO);
+        }
+        let offset = file
+            .inode()
+            .seek_data_hole(offset as usize, hole)?
+            .ok_or(SysError::ENXIO)?;
+        file.seek(SeekFrom::Start(offset as u64))?;
+        Ok(offset)
+    }
+
     pub fn sys_fsync(&mut self, fd: usize) -> SysResult {
         info!("fsync: fd: {}", fd);
         self.process().get_file(fd)?.sync_all()?;
@@ -809,11 +946,77 @@ impl Syscall<'_> {
         Ok(0)
     }
 
+    pub fn sys_fadvise64(
+        &mut self,
+        fd: usize,
+        offset: usize,
+        len: usize,
+        advice: usize,
+    ) -> SysResult {
+        info!(
+            "fadvise64: fd: {}, offset: {}, len: {}, advice: {}",
+            fd, offset, len, advice
+        );
+        const POSIX_FADV_NORMAL: usize = 0;
+        const POSIX_FADV_RANDOM: usize = 1;
+        const POSIX_FADV_SEQUENTIAL: usize = 2;
+        const POSIX_FADV_WILLNEED: usize = 3;
+        const POSIX_FADV_DONTNEED: usize = 4;
+        const POSIX_FADV_NOREUSE: usize = 5;
+
+        let file = self.process().get_file(fd)?.clone();
+        if file.is_pipe() {
+            return Err(SysError::ESPIPE);
+        }
+        match advice {
+            POSIX_FADV_NORMAL => file.set_advice(Advice::Normal),
+            POSIX_FADV_RANDOM => file.set_advice(Advice::Random),
+            POSIX_FADV_SEQUENTIAL => file.set_advice(Advice::Sequential),
+            POSIX_FADV_WILLNEED | POSIX_FADV_DONTNEED => {
+                let inode = file.inode();
+                // len 0 means until the end of file
+                let len = match len {
+                    0 => usize::MAX,
+                    _ => len,
+                };
+                match advice {
+                    _ if !page_cache::cacheable(&inode) => {}
+                    POSIX_FADV_WILLNEED => page_cache::prefetch(inode, offset, len),
+                    _ => page_cache::invalidate(&inode, offset, offset.saturating_add(len))?,
+                }
+            }
+            POSIX_FADV_NOREUSE => {}
+            _ => return Err(SysError::EINVAL),
+        }
+        Ok(0)
+    }
+
+    /// Start loading a range of a file into the page cache, without waiting for it
+    pub fn sys_readahead(&mut self, fd: usize, offset: usize, count: usize) -> SysResult {
+        info!(
+            "readahead: fd: {}, offset: {}, count: {}",
+            fd, offset, count
+        );
+        let file = self.process().get_file(fd)?.clone();
+        if !file.options().read {
+            return Err(SysError::EBADF);
+        }
+        let inode = file.inode();
+        if !page_cache::cacheable(&inode) {
+            return Err(SysError::EINVAL);
+        }
+        page_cache::prefetch(inode, offset, count);
+        Ok(0)
+    }
+
     pub fn sys_truncate(&mut self, path: *const u8, len: usize) -> SysResult {
         let proc = self.process();
         let path = check_and_clone_cstr(path)?;
         info!("truncate: path: {:?}, len: {}", path, len);
-        proc.lookup_inode(&path)?.resize(len)?;
+        let inode = proc.lookup_inode(&path)?;
+        inode.resize(len)?;
+        let entry = proc.entry_at(AT_FDCWD, &path, &inode);
+        inotify::notify_file(&inode, IN_MODIFY, entry.as_ref());
         Ok(0)
     }
 
@@ -823,6 +1026,63 @@ impl Syscall<'_> {
         Ok(0)
     }
 
+    /// Allocate space of `[offset, offset + len)` of a file.
+    /// No filesystem here can keep unwritten or sparse blocks, so only growing the file
+    /// and zeroing a range are supported.
+    pub async fn sys_fallocate(
+        &mut self,
+        fd: usize,
+        mode: usize,
+        offset: usize,
+        len: usize,
+    ) -> SysResult {
+        info!(
+            "fallocate: fd: {}, mode: {:#x}, offset: {}, len: {}",
+            fd, mode, offset, len
+        );
+        let mode = FallocFlags::from_bits(mode).ok_or(SysError::EOPNOTSUPP)?;
+        if (offset as isize) < 0 || len as isize <= 0 {
+            return Err(SysError::EINVAL);
+        }
+        let end = offset.checked_add(len).ok_or(SysError::EFBIG)?;
+        let mut file = self.process().get_file(fd)?.clone();
+        if file.is_pipe() {
+            return Err(SysError::ESPIPE);
+        }
+        let metadata = file.metadata()?;
+        let size = metadata.size;
+        match metadata.type_ {
+            FileType::File => {}
+            FileType::Dir => return Err(SysError::EISDIR),
+            _ => return Err(SysError::ENODEV),
+        }
+        if mode.is_empty() {
+            // the new part reads as zero, and the blocks are allocated by resizing
+            if end > size {
+                file.set_len(end as u64)?;
+            }
+            Ok(0)
+        } else if mode == FallocFlags::ZERO_RANGE
+            || mode == FallocFlags::ZERO_RANGE | FallocFlags::KEEP_SIZE
+            || mode == FallocFlags::PUNCH_HOLE | FallocFlags::KEEP_SIZE
+        {
+            // the file systems can not free blocks in the middle of a file,
+            // so a hole is filled with zeros, which read the same
+            let end = match mode.contains(FallocFlags::KEEP_SIZE) {
+                true => end.min(size),
+                false => end,
+            };
+            if end > offset {
+                file.zero_range(offset, end - offset).await?;
+            }
+            Ok(0)
+        } else {
+            // KEEP_SIZE alone would reserve blocks beyond the end,
+            // and PUNCH_HOLE always keeps the size
+            Err(SysError::EOPNOTSUPP)
+        }
+    }
+
     pub fn sys_getdents64(
         &mut self,
         fd: usize,
@@ -896,13 +1156,23 @@ impl Syscall<'_> {
             FIOCLEX => self.sys_fcntl(fd, F_SETFD, FD_CLOEXEC),
             FIONCLEX => self.sys_fcntl(fd, F_SETFD, 0),
             FIONBIO => {
-                let data = arg1 as *const i32;
-                let val = unsafe { *data };
-                if val == 0 {
-                    self.sys_fcntl(fd, F_SETFD, 0)
-                } else {
-                    self.sys_fcntl(fd, F_SETFD, O_NONBLOCK)
+                // only the O_NONBLOCK status flag changes
+                let nonblock = UserInPtr::<i32>::from(arg1).read()? != 0;
+                let mut proc = self.process();
+                match proc.get_file_like(fd)? {
+                    FileLike::File(file) => file.set_nonblocking(nonblock),
+                    FileLike::Socket(socket) => socket.set_nonblocking(nonblock),
+                    FileLike::EpollInstance(_) | FileLike::IoUring(_) => {}
                 }
+                Ok(0)
+            }
+            TIOCSTI => {
+                // read here, as errors of file systems have no EFAULT,
+                // and the tty gets a pointer to our copy
+                let c = UserInPtr::<u8>::from(arg1).read()?;
+                let mut proc = self.process();
+                let file_like = proc.get_file_like(fd)?;
+                file_like.ioctl(request, &c as *const u8 as usize, arg2, arg3)
             }
             _ => {
                 let mut proc = self.process();
@@ -955,6 +1225,25 @@ impl Syscall<'_> {
         Ok(0)
     }
 
+    /// Make the directory at `path` the root directory of this process
+    pub fn sys_chroot(&mut self, path: *const u8) -> SysResult {
+        let mut proc = self.process();
+        let path = check_and_clone_cstr(path)?;
+        info!("chroot: path: {:?}", path);
+        if !capable(Capability::SysChroot) {
+            return Err(SysError::EPERM);
+        }
+        let inode = proc.lookup_inode(&path)?;
+        if inode.metadata()?.type_ != FileType::Dir {
+            return Err(SysError::ENOTDIR);
+        }
+        let root_path = proc.real_path(&path);
+        proc.root = Some((root_path, inode));
+        // the cwd is kept as a path from the root, so it can not stay outside of the new one
+        proc.cwd = String::from("/");
+        Ok(0)
+    }
+
     pub fn sys_rename(&mut self, oldpath: *const u8, newpath: *const u8) -> SysResult {
         self.sys_renameat(AT_FDCWD, oldpath, AT_FDCWD, newpath)
     }
@@ -978,7 +1267,22 @@ impl Syscall<'_> {
         let (new_dir_path, new_file_name) = split_path(&newpath);
         let old_dir_inode = proc.lookup_inode_at(olddirfd, old_dir_path, false)?;
         let new_dir_inode = proc.lookup_inode_at(newdirfd, new_dir_path, false)?;
+        let type_ = old_dir_inode.find(old_file_name)?.metadata()?.type_;
+        // the file replaced, if any
+        let replaced = new_dir_inode.find(new_file_name).ok();
         old_dir_inode.move_(old_file_name, &new_dir_inode, new_file_name)?;
+        if let Some(replaced) = replaced {
+            if replaced.metadata().map_or(false, |m| m.nlinks == 0) {
+                inotify::notify_delete_self(&replaced);
+            }
+        }
+        inotify::notify_move(
+            &old_dir_inode,
+            old_file_name,
+            &new_dir_inode,
+            new_file_name,
+            type_,
+        );
         Ok(0)
     }
 
@@ -1003,6 +1307,56 @@ impl Syscall<'_> {
         let inode = dir_inode.create(file_name, FileType::Dir, mode as u32)?;
         TimeSpec::update(&inode);
         TimeSpec::update(&dir_inode);
+        inotify::notify_entry(&dir_inode, IN_CREATE, file_name, FileType::Dir);
+        Ok(0)
+    }
+
+    pub fn sys_mknod(&mut self, path: *const u8, mode: usize, dev: usize) -> SysResult {
+        self.sys_mknodat(AT_FDCWD, path, mode, dev)
+    }
+
+    /// Make a FIFO, or a device node opening the driver with device number `dev`.
+    /// Regular files can be made too, as by `open` with `O_CREAT`.
+    pub fn sys_mknodat(
+        &mut self,
+        dirfd: usize,
+        path: *const u8,
+        mode: usize,
+        dev: usize,
+    ) -> SysResult {
+        let proc = self.process();
+        let path = check_and_clone_cstr(path)?;
+        info!(
+            "mknodat: dirfd: {}, path: {:?}, mode: {:#o}, dev: {:#x}",
+            dirfd as isize, path, mode, dev
+        );
+        let stat_mode = StatMode::from_bits_truncate(mode as u32);
+        let type_ = match stat_mode & StatMode::TYPE_MASK {
+            StatMode::NULL | StatMode::FILE => FileType::File,
+            StatMode::FIFO => FileType::NamedPipe,
+            StatMode::CHAR => FileType::CharDevice,
+            StatMode::BLOCK => FileType::BlockDevice,
+            StatMode::DIR => return Err(SysError::EPERM),
+            _ => return Err(SysError::EINVAL),
+        };
+        if special::is_device(type_) && !capable(Capability::Mknod) {
+            return Err(SysError::EPERM);
+        }
+
+        let (dir_path, file_name) = split_path(&path);
+        let dir_inode = proc.lookup_inode_at(dirfd, dir_path, true)?;
+        if dir_inode.find(file_name).is_ok() {
+            return Err(SysError::EEXIST);
+        }
+        let mode = mode as u32 & 0o7777;
+        let inode = if special::is_device(type_) {
+            special::make_device_node(&dir_inode, file_name, type_, mode, dev)?
+        } else {
+            dir_inode.create(file_name, type_, mode)?
+        };
+        TimeSpec::update(&inode);
+        TimeSpec::update(&dir_inode);
+        inotify::notify_entry(&dir_inode, IN_CREATE, file_name, type_);
         Ok(0)
     }
 
@@ -1018,6 +1372,8 @@ impl Syscall<'_> {
             return Err(SysError::ENOTDIR);
         }
         dir_inode.unlink(file_name)?;
+        inotify::notify_entry(&dir_inode, IN_DELETE, file_name, FileType::Dir);
+        inotify::notify_delete_self(&file_inode);
         Ok(0)
     }
 
@@ -1046,6 +1402,8 @@ impl Syscall<'_> {
         let inode = proc.lookup_inode_at(olddirfd, &oldpath, true)?;
         let new_dir_inode = proc.lookup_inode_at(newdirfd, new_dir_path, true)?;
         new_dir_inode.link(new_file_name, &inode)?;
+        let type_ = inode.metadata()?.type_;
+        inotify::notify_entry(&new_dir_inode, IN_CREATE, new_file_name, type_);
         Ok(0)
     }
 
@@ -1082,6 +1440,7 @@ impl Syscall<'_> {
                     symlink.write_at(0, target.as_bytes())?;
                     TimeSpec::update(&symlink);
                     TimeSpec::update(&dir_inode);
+                    inotify::notify_entry(&dir_inode, IN_CREATE, filename, FileType::SymLink);
                     Ok(0)
                 }
                 _ => Err(e.into()),
@@ -1101,10 +1460,90 @@ impl Syscall<'_> {
         let (dir_path, file_name) = split_path(&path);
         let dir_inode = proc.lookup_inode_at(dirfd, dir_path, true)?;
         let file_inode = dir_inode.find(file_name)?;
-        if file_inode.metadata()?.type_ == FileType::Dir {
+        let type_ = file_inode.metadata()?.type_;
+        if type_ == FileType::Dir {
             return Err(SysError::EISDIR);
         }
         dir_inode.unlink(file_name)?;
+        inotify::notify_entry(&dir_inode, IN_DELETE, file_name, type_);
+        if file_inode.metadata().map_or(false, |m| m.nlinks == 0) {
+            inotify::notify_delete_self(&file_inode);
+        }
+        Ok(0)
+    }
+
+    /// Create an anonymous file in memory, see `crate::fs::memfd`
+    pub fn sys_memfd_create(&mut self, name: *const u8, flags: usize) -> SysResult {
+        let name = check_and_clone_cstr(name)?;
+        info!("memfd_create: name: {:?}, flags: {:#x}", name, flags);
+        let flags = MemFdFlags::from_bits(flags).ok_or(SysError::EINVAL)?;
+        if name.len() > MEMFD_NAME_MAX || flags.contains(MemFdFlags::HUGETLB) {
+            return Err(SysError::EINVAL);
+        }
+        let memfd = MemFd::new(flags.contains(MemFdFlags::ALLOW_SEALING))?;
+        let file = FileHandle::new(
+            Arc::new(memfd),
+            OpenOptions {
+                read: true,
+                write: true,
+                append: false,
+                nonblock: false,
+            },
+            format!("/memfd:{} (deleted)", name),
+            false,
+            flags.contains(MemFdFlags::CLOEXEC),
+        );
+        Ok(self.process().add_file(FileLike::File(file)))
+    }
+
+    pub fn sys_inotify_init(&mut self) -> SysResult {
+        self.sys_inotify_init1(0)
+    }
+
+    /// Create an inotify instance, see `crate::fs::inotify`
+    pub fn sys_inotify_init1(&mut self, flags: usize) -> SysResult {
+        info!("inotify_init1: flags: {:#x}", flags);
+        if flags & !(O_NONBLOCK | O_CLOEXEC) != 0 {
+            return Err(SysError::EINVAL);
+        }
+        let file = FileHandle::new(
+            Arc::new(Inotify::new()),
+            OpenOptions {
+                read: true,
+                write: false,
+                append: false,
+                nonblock: flags & O_NONBLOCK != 0,
+            },
+            String::from("anon_inode:inotify"),
+            false,
+            flags & O_CLOEXEC != 0,
+        );
+        Ok(self.process().add_file(FileLike::File(file)))
+    }
+
+    pub fn sys_inotify_add_watch(&mut self, fd: usize, path: *const u8, mask: u32) -> SysResult {
+        let mut proc = self.process();
+        let path = check_and_clone_cstr(path)?;
+        info!(
+            "inotify_add_watch: fd: {}, path: {:?}, mask: {:#x}",
+            fd, path, mask
+        );
+        let file = proc.get_file(fd)?.clone();
+        let inotify = file.inotify().ok_or(SysError::EINVAL)?;
+        if mask & IN_ALL_EVENTS == 0 {
+            return Err(SysError::EINVAL);
+        }
+        let follow = mask & IN_DONT_FOLLOW == 0;
+        let inode = proc.lookup_inode_at(AT_FDCWD, &path, follow)?;
+        let wd = inotify.add_watch(&inode, mask)?;
+        Ok(wd as usize)
+    }
+
+    pub fn sys_inotify_rm_watch(&mut self, fd: usize, wd: i32) -> SysResult {
+        info!("inotify_rm_watch: fd: {}, wd: {}", fd, wd);
+        let file = self.process().get_file(fd)?.clone();
+        let inotify = file.inotify().ok_or(SysError::EINVAL)?;
+        inotify.rm_watch(wd)?;
         Ok(0)
     }
 
@@ -1164,58 +1603,123 @@ impl Syscall<'_> {
             "utimensat(raw): dirfd: {}, pathname: {}, times: {}, flags: {:#x}",
             dirfd as i64, pathname as usize, times as usize, flags
         );
-        const UTIME_NOW: usize = 0x3fffffff;
-        const UTIME_OMIT: usize = 0x3ffffffe;
-        let mut proc = self.process();
-        let mut times = if times.is_null() {
-            let epoch = TimeSpec::get_epoch();
-            [epoch, epoch]
+        let times = if times.is_null() {
+            [TimeSpec {
+                sec: 0,
+                nsec: UTIME_NOW,
+            }; 2]
         } else {
             let times = unsafe { self.vm().check_read_array(times, 2)? };
             [times[0], times[1]]
         };
-        let mut inode = if pathname.is_null() {
-            let fd = dirfd;
-            info!("futimens: fd: {}, times: {:?}", fd, times);
-            proc.get_file(fd)?.inode()
+        let follow = match flags {
+            0 => true,
+            fcntl::AT_SYMLINK_NOFOLLOW => false,
+            _ => return Err(EINVAL),
+        };
+        if pathname.is_null() {
+            info!("futimens: fd: {}, times: {:?}", dirfd, times);
+            let inode = self.process().get_file(dirfd)?.inode();
+            set_file_times(&inode, times)
         } else {
             let pathname = check_and_clone_cstr(pathname)?;
             info!(
                 "utimensat: dirfd: {}, pathname: {}, times: {:?}, flags: {:#x}",
                 dirfd as i64, pathname, times, flags
             );
-            let follow = match flags {
-                0 => true,
-                fcntl::AT_SYMLINK_NOFOLLOW => false,
-                _ => return Err(EINVAL),
-            };
-            proc.lookup_inode_at(dirfd, &pathname, follow)?
+            let inode = self.process().lookup_inode_at(dirfd, &pathname, follow)?;
+            set_file_times(&inode, times)
+        }
+    }
+
+    /// Legacy `utimes`, with times in microseconds
+    pub fn sys_utimes(&mut self, pathname: *const u8, times: *const TimeVal) -> SysResult {
+        let pathname = check_and_clone_cstr(pathname)?;
+        let times = if times.is_null() {
+            [TimeSpec {
+                sec: 0,
+                nsec: UTIME_NOW,
+            }; 2]
+        } else {
+            let times = unsafe { self.vm().check_read_array(times, 2)? };
+            [times[0].to_timespec(), times[1].to_timespec()]
         };
-        let mut metadata = inode.metadata()?;
-        if times[0].nsec != UTIME_OMIT {
-            if times[0].nsec == UTIME_NOW {
-                times[0] = TimeSpec::get_epoch();
-            }
-            metadata.atime = rcore_fs::vfs::Timespec {
-                sec: times[0].sec as i64,
-                nsec: times[0].nsec as i32,
-            };
+        info!("utimes: pathname: {}, times: {:?}", pathname, times);
+        let inode = self.process().lookup_inode_at(AT_FDCWD, &pathname, true)?;
+        set_file_times(&inode, times)
+    }
+
+    pub fn sys_mount(
+        &mut self,
+        source: *const u8,
+        target: *const u8,
+        fstype: *const u8,
+        flags: usize,
+        data: usize,
+    ) -> SysResult {
+        if !capable(Capability::SysAdmin) {
+            return Err(SysError::EPERM);
         }
-        if times[1].nsec != UTIME_OMIT {
-            if times[1].nsec == UTIME_NOW {
-                times[1] = TimeSpec::get_epoch();
-            }
-            metadata.mtime = rcore_fs::vfs::Timespec {
-                sec: times[1].sec as i64,
-                nsec: times[1].nsec as i32,
-            };
+        let source = check_and_clone_cstr(source)?;
+        let target = check_and_clone_cstr(target)?;
+        let fstype = check_and_clone_cstr(fstype)?;
+        info!(
+            "mount: source: {:?}, target: {:?}, fstype: {:?}, flags: {:#x}",
+            source, target, fstype, flags
+        );
+        let fs: Arc<dyn FileSystem> = match fstype.as_str() {
+            "tmpfs" | "ramfs" => RamFS::new(),
+            _ => return Err(SysError::ENODEV),
+        };
+        let proc = self.process();
+        let inode = proc.lookup_inode(&target)?;
+        if inode.metadata()?.type_ != FileType::Dir {
+            return Err(SysError::ENOTDIR);
         }
-        inode.set_metadata(&metadata)?;
+        proc.mount_ns.write().mount(inode, fs);
         Ok(0)
     }
 
+    pub fn sys_umount2(&mut self, target: *const u8, flags: usize) -> SysResult {
+        if !capable(Capability::SysAdmin) {
+            return Err(SysError::EPERM);
+        }
+        let target = check_and_clone_cstr(target)?;
+        info!("umount2: target: {:?}, flags: {:#x}", target, flags);
+        let proc = self.process();
+        let root = proc.lookup_inode(&target)?;
+        if proc.mount_ns.write().umount(&root)? {
+            Ok(0)
+        } else {
+            Err(SysError::EINVAL)
+        }
+    }
+
+    /// Write back the page cache, then all file systems, then the buffer caches under them.
+    /// File systems mounted in other mount namespaces are reached by the last flush.
     pub fn sys_sync(&mut self) -> SysResult {
-        ROOT_INODE.fs().sync()?;
+        // sync everything even if some fails, and report the first error
+        let mut res = page_cache::sync_all();
+        let mut filesystems = self.process().mount_ns.read().filesystems();
+        filesystems.insert(0, ROOT_INODE.fs());
+        for fs in filesystems {
+            res = res.and(fs.sync());
+        }
+        res = res.and(buffer_cache::sync_all().map_err(FsError::from));
+        res?;
+        Ok(0)
+    }
+
+    /// Write back the file system containing `fd`
+    pub fn sys_syncfs(&mut self, fd: usize) -> SysResult {
+        info!("syncfs: fd: {}", fd);
+        let inode = self.process().get_file(fd)?.inode();
+        let fs = inode.fs();
+        let mut res = page_cache::sync_fs(&fs);
+        res = res.and(fs.sync());
+        // metadata written by `sync` stays in the buffer cache of the device
+        res = res.and(buffer_cache::sync_fs(&fs).map_err(FsError::from));
+        res?;
         Ok(0)
     }
 
@@ -1230,6 +1734,9 @@ impl Syscall<'_> {
             .await
     }
 
+    /// Copy a range of a regular file to another, or elsewhere in the same file.
+    /// None of our filesystems can share blocks between files, so the data is always
+    /// copied through the page cache, which also works across filesystems.
     pub async fn sys_copy_file_range(
         &mut self,
         in_fd: usize,
@@ -1240,78 +1747,277 @@ impl Syscall<'_> {
         flags: usize,
     ) -> SysResult {
         info!(
-            "copy_file_range:BEG in: {}, out: {}, in_offset: {:?}, out_offset: {:?}, count: {} flags {}",
+            "copy_file_range: in: {}, out: {}, in_offset: {:?}, out_offset: {:?}, count: {} flags {}",
             in_fd, out_fd, in_offset, out_offset, count, flags
         );
-        let proc = self.process();
-        // We know it's save, pacify the borrow checker
-        let proc_cell = UnsafeCell::new(proc);
-        let in_file = unsafe { (*proc_cell.get()).get_file(in_fd)? };
-        let out_file = unsafe { (*proc_cell.get()).get_file(out_fd)? };
-        drop(proc_cell);
-        let mut buffer = [0u8; 1024];
-
-        // for in_offset and out_offset
-        // null means update file offset
-        // non-null means update {in,out}_offset instead
-
-        let mut read_offset = if !in_offset.is_null() {
-            in_offset.read()?
+        if flags != 0 {
+            return Err(EINVAL);
+        }
+        let (mut in_file, mut out_file) = {
+            let mut proc = self.process();
+            let in_file = proc.get_file(in_fd)?.clone();
+            let out_file = proc.get_file(out_fd)?.clone();
+            (in_file, out_file)
+        };
+        let (in_options, out_options) = (in_file.options(), out_file.options());
+        if !in_options.read || !out_options.write || out_options.append {
+            return Err(SysError::EBADF);
+        }
+        let in_metadata = in_file.metadata()?;
+        let out_metadata = out_file.metadata()?;
+        for metadata in [&in_metadata, &out_metadata].iter() {
+            match metadata.type_ {
+                FileType::File => {}
+                FileType::Dir => return Err(SysError::EISDIR),
+                _ => return Err(EINVAL),
+            }
+        }
+
+        // null means the file offset, which is advanced,
+        // otherwise the offset pointed to is used and updated instead
+        let read_start = match in_offset.is_null() {
+            true => in_file.seek(SeekFrom::Current(0))? as usize,
+            false => in_offset.read()?,
+        };
+        let write_start = match out_offset.is_null() {
+            true => out_file.seek(SeekFrom::Current(0))? as usize,
+            false => out_offset.read()?,
+        };
+        if (read_start as isize) < 0 || (write_start as isize) < 0 {
+            return Err(EINVAL);
+        }
+        // so that no end overflows
+        let count = min(count, isize::MAX as usize);
+        // as Linux, overlapping ranges of the same file are refused
+        let same_file =
+            in_metadata.dev == out_metadata.dev && in_metadata.inode == out_metadata.inode;
+        if same_file && read_start < write_start + count && write_start < read_start + count {
+            return Err(EINVAL);
+        }
+
+        // stop at the first error or short write, which is only reported if nothing was copied
+        let count = min(count, in_metadata.size.saturating_sub(read_start));
+        let mut buffer = vec![0u8; min(count, PIPE_BUF_MAX)];
+        let mut copied = 0;
+        let mut error = None;
+        while copied < count {
+            let len = min(buffer.len(), count - copied);
+            let read_len = match in_file
+                .read_at(read_start + copied, &mut buffer[..len])
+                .await
+            {
+                Ok(0) => break,
+                Ok(read_len) => read_len,
+                Err(err) => {
+                    error = Some(err);
+                    break;
+                }
+            };
+            match out_file
+                .write_at(write_start + copied, &buffer[..read_len])
+                .await
+            {
+                Ok(write_len) => {
+                    copied += write_len;
+                    if write_len < read_len {
+                        break;
+                    }
+                }
+                Err(err) => {
+                    error = Some(err);
+                    break;
+                }
+            }
+        }
+        if let (0, Some(err)) = (copied, error) {
+            return Err(err.into());
+        }
+
+        if in_offset.is_null() {
+            in_file.seek(SeekFrom::Start((read_start + copied) as u64))?;
+        } else {
+            in_offset.write(read_start + copied)?;
+        }
+        if out_offset.is_null() {
+            out_file.seek(SeekFrom::Start((write_start + copied) as u64))?;
         } else {
-            in_file.seek(SeekFrom::Current(0))? as usize
+            out_offset.write(write_start + copied)?;
+        }
+        Ok(copied)
+    }
+
+    /// Move data between a pipe and a file or socket, or between two pipes
+    pub async fn sys_splice(
+        &mut self,
+        fd_in: usize,
+        mut off_in: UserInOutPtr<usize>,
+        fd_out: usize,
+        mut off_out: UserInOutPtr<usize>,
+        len: usize,
+        flags: usize,
+    ) -> SysResult {
+        let flags = SpliceFlags::from_bits_truncate(flags);
+        info!(
+            "splice: fd_in: {}, off_in: {:?}, fd_out: {}, off_out: {:?}, len: {}, flags: {:?}",
+            fd_in, off_in, fd_out, off_out, len, flags
+        );
+        let (mut in_file, mut out_file) = {
+            let mut proc = self.process();
+            let in_file = proc.get_file_like(fd_in)?.clone();
+            let out_file = proc.get_file_like(fd_out)?.clone();
+            (in_file, out_file)
+        };
+        let (in_pipe, out_pipe) = (pipe_of(&in_file).is_some(), pipe_of(&out_file).is_some());
+        if !in_pipe && !out_pipe {
+            return Err(SysError::EINVAL);
+        }
+        // only regular files have offsets
+        let seekable = |file: &FileLike| match file {
+            FileLike::File(file) => !file.is_pipe(),
+            _ => false,
         };
+        if (!seekable(&in_file) && !off_in.is_null())
+            || (!seekable(&out_file) && !off_out.is_null())
+        {
+            return Err(SysError::ESPIPE);
+        }
+        let nonblock = flags.contains(SpliceFlags::NONBLOCK);
+        if len == 0 {
+            return Ok(0);
+        }
 
-        let orig_out_file_offset = out_file.seek(SeekFrom::Current(0))?;
-        let write_offset = if !out_offset.is_null() {
-            out_file.seek(SeekFrom::Start(out_offset.read()? as u64))? as usize
+        // a file without `off_in` is read at its own offset, which moves by what is written
+        let read_offset = if !off_in.is_null() {
+            Some(off_in.read()?)
+        } else {
+            match &mut in_file {
+                FileLike::File(file) if !file.is_pipe() => {
+                    Some(file.seek(SeekFrom::Current(0))? as usize)
+                }
+                _ => None,
+            }
+        };
+        let write_offset = if !off_out.is_null() {
+            Some(off_out.read()?)
         } else {
-            0
+            None
         };
 
-        // read from specified offset and write new offset back
-        let mut bytes_read = 0;
-        let mut total_written = 0;
-        while bytes_read < count {
-            let len = min(buffer.len(), count - bytes_read);
-            let read_len = in_file.read_at(read_offset, &mut buffer[..len]).await?;
-            if read_len == 0 {
-                break;
+        let written = if in_pipe {
+            // what is in the pipe is left there until it is written
+            let pipe = pipe_of(&in_file).unwrap();
+            let mut buf = vec![0u8; min(len, pipe.capacity())];
+            let len = loop {
+                match pipe.peek(&mut buf) {
+                    Err(FsError::Again) if !nonblock => {
+                        in_file.async_poll().await?;
+                    }
+                    result => break result?,
+                }
+            };
+            if len == 0 {
+                return Ok(0);
             }
-            bytes_read += read_len;
-            read_offset += read_len;
-
-            let mut bytes_written = 0;
-            let mut rlen = read_len;
-            while bytes_written < read_len {
-                let write_len = out_file.write(&buffer[bytes_written..(bytes_written + rlen)])?;
-                if write_len == 0 {
-                    info!(
-                        "copy_file_range:END_ERR in: {}, out: {}, in_offset: {:?}, out_offset: {:?}, count: {} = bytes_read {}, bytes_written {}, write_len {}",
-                        in_fd, out_fd, in_offset, out_offset, count, bytes_read, bytes_written, write_len
-                    );
-                    return Err(SysError::EBADF);
+            let written = splice_write(&mut out_file, write_offset, &buf[..len], nonblock).await?;
+            pipe.consume(written);
+            written
+        } else {
+            // read no more than the pipe can take, as bytes read from a socket can not be put back
+            let pipe = pipe_of(&out_file).unwrap();
+            let room = loop {
+                match pipe.room() {
+                    None => return self.sigpipe_on_epipe(Err(SysError::EPIPE)),
+                    Some(0) if nonblock => return Err(SysError::EAGAIN),
+                    Some(0) => {
+                        out_file.async_poll().await?;
+                    }
+                    Some(room) => break room,
                 }
-                bytes_written += write_len;
-                rlen -= write_len;
+            };
+            let mut buf = vec![0u8; min(len, room)];
+            let len = match (&mut in_file, read_offset) {
+                (FileLike::File(file), Some(offset)) => file.read_at(offset, &mut buf).await?,
+                (file, _) => file.read(&mut buf).await?,
+            };
+            if len == 0 {
+                return Ok(0);
             }
-            total_written += bytes_written;
-        }
+            // another writer may have filled the pipe meanwhile, so wait for room
+            // rather than lose what was read
+            out_file.async_write(&buf[..len]).await?
+        };
 
-        if !in_offset.is_null() {
-            in_offset.write(read_offset)?;
-        } else {
-            in_file.seek(SeekFrom::Current(bytes_read as i64))?;
+        if let Some(offset) = read_offset {
+            if !off_in.is_null() {
+                off_in.write(offset + written)?;
+            } else if let FileLike::File(file) = &mut in_file {
+                file.seek(SeekFrom::Start((offset + written) as u64))?;
+            }
+        }
+        if let Some(offset) = write_offset {
+            off_out.write(offset + written)?;
         }
+        Ok(written)
+    }
 
-        if !out_offset.is_null() {
-            out_offset.write(write_offset + total_written)?;
-            out_file.seek(SeekFrom::Start(orig_out_file_offset))?;
+    /// Copy data from one pipe to another, leaving it in the first
+    pub async fn sys_tee(
+        &mut self,
+        fd_in: usize,
+        fd_out: usize,
+        len: usize,
+        flags: usize,
+    ) -> SysResult {
+        let flags = SpliceFlags::from_bits_truncate(flags);
+        info!(
+            "tee: fd_in: {}, fd_out: {}, len: {}, flags: {:?}",
+            fd_in, fd_out, len, flags
+        );
+        let (in_file, mut out_file) = {
+            let mut proc = self.process();
+            let in_file = proc.get_file(fd_in)?.clone();
+            let out_file = proc.get_file(fd_out)?.clone();
+            (in_file, out_file)
+        };
+        match (in_file.pipe(), out_file.pipe()) {
+            (Some(in_pipe), Some(out_pipe)) if !in_pipe.same_pipe(out_pipe) => {}
+            _ => return Err(SysError::EINVAL),
         }
+        let pipe = in_file.pipe().unwrap();
+        let mut buf = vec![0u8; min(len, PIPE_BUF_MAX)];
+        let len = loop {
+            match pipe.peek(&mut buf) {
+                Err(FsError::Again) if !flags.contains(SpliceFlags::NONBLOCK) => {
+                    in_file.async_poll().await?;
+                }
+                result => break result?,
+            }
+        };
+        out_file.async_write(&buf[..len]).await?;
+        Ok(len)
+    }
+
+    /// Copy user memory into a pipe
+    pub async fn sys_vmsplice(
+        &mut self,
+        fd: usize,
+        iov_ptr: *const IoVec,
+        iov_count: usize,
+        flags: usize,
+    ) -> SysResult {
         info!(
-            "copy_file_range:END in: {}, out: {}, in_offset: {:?}, out_offset: {:?}, count: {} flags {}",
-            in_fd, out_fd, in_offset, out_offset, count, flags
+            "vmsplice: fd: {}, iov: {:?}, count: {}, flags: {:#x}",
+            fd, iov_ptr, iov_count, flags
         );
-        return Ok(total_written);
+        let iovs = unsafe { IoVecs::check_and_new(iov_ptr, iov_count, &self.vm(), false)? };
+        let mut file = self.process().get_file(fd)?.clone();
+        if !file.is_pipe() {
+            return Err(SysError::EBADF);
+        }
+        // SPLICE_F_GIFT is only a hint, the pages are copied anyway
+        let buf = iovs.read_all_to_vec();
+        let len = file.write(buf.as_slice()).await?;
+        Ok(len)
     }
 
     pub fn sys_fcntl(&mut self, fd: usize, cmd: usize, arg: usize) -> SysResult {
@@ -1332,6 +2038,31 @@ impl Syscall<'_> {
                         Ok(0)
                     }
                     F_GETFL => self.unimplemented("F_GETFL", Ok(0)),
+                    F_ADD_SEALS => {
+                        let memfd = file.memfd().ok_or(SysError::EINVAL)?;
+                        let seals = Seals::from_bits(arg as u32).ok_or(SysError::EINVAL)?;
+                        memfd.add_seals(seals)?;
+                        Ok(0)
+                    }
+                    F_GET_SEALS => {
+                        let memfd = file.memfd().ok_or(SysError::EINVAL)?;
+                        Ok(memfd.seals().bits() as usize)
+                    }
+                    F_SETPIPE_SZ => {
+                        use crate::fs::PIPE_MAX_SIZE;
+                        let pipe = file.pipe().ok_or(SysError::EBADF)?;
+                        let size = arg as u32 as usize;
+                        if size > 1 << 31 {
+                            return Err(SysError::EINVAL);
+                        }
+                        if size > PIPE_MAX_SIZE && !capable(Capability::SysResource) {
+                            return Err(SysError::EPERM);
+                        }
+                        let size = (size.max(1) + PAGE_SIZE - 1) / PAGE_SIZE * PAGE_SIZE;
+                        pipe.set_capacity(size)?;
+                        Ok(size)
+                    }
+                    F_GETPIPE_SZ => Ok(file.pipe().ok_or(SysError::EBADF)?.capacity()),
                     F_DUPFD_CLOEXEC => {
                         info!("fcntl: dupfd_cloexec: arg: {:#x}", arg);
                         // let file_like = proc.get_file_like(fd1)?.clone();
@@ -1342,11 +2073,25 @@ impl Syscall<'_> {
                     _ => Ok(0),
                 }
             }
-            FileLike::Socket(_) => {
-                Ok(0)
-                //TODO
+            FileLike::Socket(socket) => {
+                use crate::fs::fcntl::*;
+                match cmd {
+                    F_SETFL => {
+                        socket.set_nonblocking(arg & O_NONBLOCK != 0);
+                        Ok(0)
+                    }
+                    F_GETFL if socket.nonblocking() => Ok(O_NONBLOCK),
+                    F_SETPIPE_SZ | F_GETPIPE_SZ => Err(SysError::EBADF),
+                    _ => Ok(0),
+                }
+            }
+            FileLike::EpollInstance(_) | FileLike::IoUring(_) => {
+                use crate::fs::fcntl::*;
+                match cmd {
+                    F_SETPIPE_SZ | F_GETPIPE_SZ => Err(SysError::EBADF),
+                    _ => Ok(0),
+                }
             }
-            FileLike::EpollInstance(_) => Ok(0),
         }
     }
 }
@@ -1361,12 +2106,46 @@ impl Process {
             _ => Err(SysError::EBADF),
         }
     }
+    /// Get the file at `fd` for I/O at an offset, which pipes and terminals can't do.
+    /// Other character devices like `/dev/zero` ignore the offset.
+    pub fn get_seekable_file(&mut self, fd: usize) -> Result<&mut FileHandle, SysError> {
+        match self.get_file_like(fd)? {
+            FileLike::File(file) => {
+                if file.is_pipe() || file.is_terminal() {
+                    return Err(ESPIPE);
+                }
+                match file.metadata()?.type_ {
+                    FileType::NamedPipe | FileType::Socket => Err(ESPIPE),
+                    _ => Ok(file),
+                }
+            }
+            FileLike::Socket(_) => Err(ESPIPE),
+            _ => Err(SysError::EBADF),
+        }
+    }
     pub fn get_file_const(&self, fd: usize) -> Result<&FileHandle, SysError> {
         match self.files.get(&fd).ok_or(SysError::EBADF)? {
             FileLike::File(file) => Ok(file),
             _ => Err(SysError::EBADF),
         }
     }
+    /// Normalized absolute path of `path` relative to current working directory
+    pub fn absolute_path(&self, path: &str) -> String {
+        if path.starts_with('/') {
+            normalize(path)
+        } else {
+            normalize(&format!("{}/{}", self.cwd, path))
+        }
+    }
+
+    /// `absolute_path` outside of the root directory set by chroot, as chroot records the root
+    pub fn real_path(&self, path: &str) -> String {
+        let path = self.absolute_path(path);
+        match &self.root {
+            Some((root_path, _)) => normalize(&format!("{}/{}", root_path, path)),
+            None => path,
+        }
+    }
     /// Lookup INode from the process.
     ///
     /// - If `path` is relative, then it is interpreted relative to the directory
@@ -1393,35 +2172,104 @@ impl Process {
             "/proc/self/exe" => {
                 return Ok(Arc::new(Pseudo::new(&self.exec_path, FileType::SymLink)));
             }
+            "/proc/cpuinfo" => {
+                return Ok(Arc::new(Pseudo::new(&cpuinfo(), FileType::File)));
+            }
+            "/proc/meminfo" => {
+                return Ok(Arc::new(Pseudo::new(&meminfo(), FileType::File)));
+            }
+            "/proc/uptime" => {
+                return Ok(Arc::new(Pseudo::new(&uptime(), FileType::File)));
+            }
+            "/proc/slabinfo" => {
+                return Ok(Arc::new(Pseudo::new(&slabinfo(), FileType::File)));
+            }
+            "/proc/buffercache" => {
+                return Ok(Arc::new(Pseudo::new(&buffercache_info(), FileType::File)));
+            }
+            #[cfg(any(
+                target_arch = "x86_64",
+                all(target_arch = "mips", feature = "board_malta")
+            ))]
+            "/proc/pci" => {
+                let info = crate::drivers::bus::pci::pciinfo();
+                return Ok(Arc::new(Pseudo::new(&info, FileType::File)));
+            }
             _ => {}
         }
         let (fd_dir_path, fd_name) = split_path(&path);
         match fd_dir_path {
             "/proc/self/fd" => {
                 let fd: usize = fd_name.parse().map_err(|_| SysError::EINVAL)?;
-                let fd_path = &self.get_file_const(fd)?.path;
+                let fd_path = self.get_file_const(fd)?.path();
                 return Ok(Arc::new(Pseudo::new(fd_path, FileType::SymLink)));
             }
             _ => {}
         }
 
-        let follow_max_depth = if follow { FOLLOW_MAX_DEPTH } else { 0 };
-        if dirfd == AT_FDCWD {
-            Ok(ROOT_INODE
-                .lookup(&self.cwd)?
-                .lookup_follow(path, follow_max_depth)?)
+        // absolute paths and symbolic links start at the root directory set by chroot if any,
+        // which can not be escaped
+        let root = match &self.root {
+            Some((_, root)) => root.clone(),
+            None => ROOT_INODE.clone(),
+        };
+        // mounts are found on the directories they cover as the path is walked
+        let mounts = self.mount_ns.read();
+        let dir = if path.starts_with('/') {
+            root.clone()
+        } else if dirfd == AT_FDCWD {
+            lookup_beneath(
+                &root,
+                &mounts,
+                root.clone(),
+                &self.cwd,
+                true,
+                FOLLOW_MAX_DEPTH,
+            )?
         } else {
-            let file = match self.files.get(&dirfd).ok_or(SysError::EBADF)? {
-                FileLike::File(file) => file,
+            let dir = match self.files.get(&dirfd).ok_or(SysError::EBADF)? {
+                FileLike::File(file) => file.inode(),
                 _ => return Err(SysError::EBADF),
             };
-            Ok(file.lookup_follow(path, follow_max_depth)?)
-        }
+            // a directory opened before chroot must not lead out of the root, as RESOLVE_BENEATH
+            if self.root.is_some()
+                && dir.metadata()?.type_ == FileType::Dir
+                && !is_beneath(&dir, &root, &mounts)?
+            {
+                return Err(SysError::EXDEV);
+            }
+            dir
+        };
+        Ok(lookup_beneath(
+            &root,
+            &mounts,
+            dir,
+            path,
+            follow,
+            FOLLOW_MAX_DEPTH,
+        )?)
     }
 
     pub fn lookup_inode(&self, path: &str) -> Result<Arc<dyn INode>, SysError> {
         self.lookup_inode_at(AT_FDCWD, path, true)
     }
+
+    /// The directory entry `path` names, if it is `inode` itself rather than a symlink to it
+    pub fn entry_at(
+        &self,
+        dirfd: usize,
+        path: &str,
+        inode: &Arc<dyn INode>,
+    ) -> Option<inotify::Entry> {
+        let (dir_path, file_name) = split_path(path);
+        let dir = self.lookup_inode_at(dirfd, dir_path, true).ok()?;
+        let found = dir.find(file_name).ok()?.metadata().ok()?;
+        let metadata = inode.metadata().ok()?;
+        if (found.dev, found.inode) != (metadata.dev, metadata.inode) {
+            return None;
+        }
+        inotify::Entry::new(&dir, file_name)
+    }
 }
 
 /// Split a `path` str to `(base_path, file_name)`
@@ -1461,8 +2309,144 @@ impl From<FsError> for SysError {
     }
 }
 
+/// Open an end of a FIFO. Unless nonblocking or opened for both reading and writing,
+/// wait for an end of the other direction.
+async fn open_fifo(metadata: &Metadata, options: OpenOptions) -> Result<Pipe, SysError> {
+    let key = (metadata.dev, metadata.inode);
+    let pipe = Pipe::open_fifo(key, options.read, options.write);
+    if !pipe.has_peer() {
+        if !options.nonblock {
+            pipe.wait_peer().await;
+        } else if options.write {
+            // a nonblocking reader may wait for writers by poll, but writes would be lost
+            return Err(SysError::ENXIO);
+        }
+    }
+    Ok(pipe)
+}
+
+/// Write `buf` spliced from a pipe to `file`, at `offset` if given.
+/// Writing to another pipe does not wait for room if `nonblock`.
+async fn splice_write(
+    file: &mut FileLike,
+    offset: Option<usize>,
+    buf: &[u8],
+    nonblock: bool,
+) -> SysResult {
+    match (file, offset) {
+        (FileLike::File(file), Some(offset)) => Ok(file.write_at(offset, buf).await?),
+        (FileLike::File(file), _) if file.is_pipe() && nonblock => {
+            Ok(file.inode().write_at(0, buf)?)
+        }
+        (file, _) => file.async_write(buf).await,
+    }
+}
+
+/// The pipe of `file`, if it is an end of a pipe
+fn pipe_of(file: &FileLike) -> Option<&Pipe> {
+    match file {
+        FileLike::File(file) => file.pipe(),
+        _ => None,
+    }
+}
+
+/// Max bytes duplicated by a `tee`
+const PIPE_BUF_MAX: usize = 0x10000;
+
+bitflags! {
+    struct SpliceFlags: usize {
+        /// Move pages instead of copying (only a hint)
+        const MOVE = 1;
+        /// Do not block on I/O
+        const NONBLOCK = 2;
+        /// More data will be coming in a subsequent splice
+        const MORE = 4;
+        /// Pages passed in are a gift (vmsplice)
+        const GIFT = 8;
+    }
+}
+
+/// Set atime to the current time
+const UTIME_NOW: usize = 0x3fffffff;
+/// Leave the timestamp unchanged
+const UTIME_OMIT: usize = 0x3ffffffe;
+
+/// Set atime and mtime of `inode` to `times`, which may be `UTIME_NOW` or `UTIME_OMIT`
+fn set_file_times(inode: &Arc<dyn INode>, times: [TimeSpec; 2]) -> SysResult {
+    if times
+        .iter()
+        .any(|t| t.nsec >= 1_000_000_000 && t.nsec != UTIME_NOW && t.nsec != UTIME_OMIT)
+    {
+        return Err(SysError::EINVAL);
+    }
+    if times.iter().all(|t| t.nsec == UTIME_OMIT) {
+        return Ok(0);
+    }
+    let mut metadata = inode.metadata()?;
+    // Only the owner may set arbitrary times, and anyone with write access may set them to now.
+    // Every process runs as uid 0, see getuid.
+    if metadata.uid != 0 && !capable(Capability::Fowner) {
+        if times
+            .iter()
+            .any(|t| t.nsec != UTIME_NOW && t.nsec != UTIME_OMIT)
+        {
+            return Err(SysError::EPERM);
+        }
+        if metadata.mode & 0o002 == 0 {
+            return Err(SysError::EACCES);
+        }
+    }
+    let now = TimeSpec::get_epoch();
+    let resolve = |t: TimeSpec| match t.nsec {
+        UTIME_OMIT => None,
+        UTIME_NOW => Some(now.into()),
+        _ => Some(t.into()),
+    };
+    if let Some(atime) = resolve(times[0]) {
+        metadata.atime = atime;
+    }
+    if let Some(mtime) = resolve(times[1]) {
+        metadata.mtime = mtime;
+    }
+    metadata.ctime = now.into();
+    inode.set_metadata(&metadata)?;
+    Ok(0)
+}
+
+bitflags! {
+    struct FallocFlags: usize {
+        /// Do not change the file size
+        const KEEP_SIZE = 1;
+        /// Deallocate the range, with KEEP_SIZE
+        const PUNCH_HOLE = 2;
+        /// Zero the range
+        const ZERO_RANGE = 0x10;
+    }
+}
+
+/// Max length of the name of a memfd, without the "memfd:" prefix
+const MEMFD_NAME_MAX: usize = 249;
+
+bitflags! {
+    struct MemFdFlags: usize {
+        const CLOEXEC = 1;
+        const ALLOW_SEALING = 2;
+        /// Back it with huge pages, not supported
+        const HUGETLB = 4;
+    }
+}
+
+bitflags! {
+    struct CloseRangeFlags: usize {
+        /// Unshare the fd table first, a no-op since it is never shared between processes
+        const UNSHARE = 1 << 1;
+        /// Set close-on-exec instead of closing
+        const CLOEXEC = 1 << 2;
+    }
+}
+
 bitflags! {
-    struct AtFlags: usize {
+    pub struct AtFlags: usize {
         const EMPTY_PATH = 0x1000;
         const SYMLINK_NOFOLLOW = 0x100;
     }
@@ -1877,6 +2861,8 @@ impl From<Metadata> for Stat {
 const SEEK_SET: u8 = 0;
 const SEEK_CUR: u8 = 1;
 const SEEK_END: u8 = 2;
+const SEEK_DATA: u8 = 3;
+const SEEK_HOLE: u8 = 4;
 
 #[derive(Debug, Copy, Clone)]
 #[repr(C)]
@@ -2033,4 +3019,4 @@ impl FdSet {
 }
 
 /// Pathname is interpreted relative to the current working directory(CWD)
-const AT_FDCWD: usize = -100isize as usize;
+pub const AT_FDCWD: usize = -100isize as usize;
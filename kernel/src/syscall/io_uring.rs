@@ -0,0 +1,192 @@
+//! A minimal io_uring, with the rings kept by `fs::io_uring`
+//!
+//! Only `IORING_OP_NOP`, `READ`, `WRITE`, `FSYNC` and `POLL_ADD` are supported,
+//! without linked entries or registered files and buffers. There is no polling thread,
+//! so entries are submitted only by `io_uring_enter`.
+
+use super::*;
+use crate::fs::aio::AioOp;
+use crate::fs::io_uring::*;
+use crate::fs::FileLike;
+
+/// Most submission entries of a ring
+const IORING_MAX_ENTRIES: usize = 4096;
+const IORING_MAX_CQ_ENTRIES: usize = 2 * IORING_MAX_ENTRIES;
+
+const IORING_SETUP_CQSIZE: u32 = 1 << 3;
+const IORING_SETUP_CLAMP: u32 = 1 << 4;
+
+/// Both rings are mapped at once
+const IORING_FEAT_SINGLE_MMAP: u32 = 1 << 0;
+/// Entries are copied when submitted, so user space may reuse them
+const IORING_FEAT_SUBMIT_STABLE: u32 = 1 << 2;
+/// Offset -1 reads and writes at the file position
+const IORING_FEAT_RW_CUR_POS: u32 = 1 << 3;
+
+const IORING_ENTER_GETEVENTS: usize = 1 << 0;
+
+const IORING_OP_NOP: u8 = 0;
+const IORING_OP_FSYNC: u8 = 3;
+const IORING_OP_POLL_ADD: u8 = 6;
+const IORING_OP_READ: u8 = 22;
+const IORING_OP_WRITE: u8 = 23;
+
+/// Run the entry on the executor, which it always is here
+const IOSQE_ASYNC: u8 = 1 << 4;
+
+const IORING_FSYNC_DATASYNC: u32 = 1;
+
+impl Syscall<'_> {
+    /// Create an io_uring with at least `entries` submission entries,
+    /// described to user space in `params`. Return its fd.
+    pub fn sys_io_uring_setup(
+        &mut self,
+        entries: usize,
+        mut params: UserInOutPtr<IoUringParams>,
+    ) -> SysResult {
+        info!("io_uring_setup: entries: {}, params: {:?}", entries, params);
+        let mut p = params.read()?;
+        if p.resv.iter().any(|&x| x != 0) {
+            return Err(SysError::EINVAL);
+        }
+        // no polling thread, nor sharing of workers
+        if p.flags & !(IORING_SETUP_CQSIZE | IORING_SETUP_CLAMP) != 0 {
+            return Err(SysError::EINVAL);
+        }
+        let clamp = p.flags & IORING_SETUP_CLAMP != 0;
+        let entries = entries as u32 as usize;
+        if entries == 0 {
+            return Err(SysError::EINVAL);
+        }
+        let sq_entries = match entries {
+            n if n <= IORING_MAX_ENTRIES => n.next_power_of_two(),
+            _ if clamp => IORING_MAX_ENTRIES,
+            _ => return Err(SysError::EINVAL),
+        };
+        let cq_entries = if p.flags & IORING_SETUP_CQSIZE != 0 {
+            match p.cq_entries as usize {
+                0 => return Err(SysError::EINVAL),
+                n if n <= IORING_MAX_CQ_ENTRIES => n.next_power_of_two().max(sq_entries),
+                _ if clamp => IORING_MAX_CQ_ENTRIES,
+                _ => return Err(SysError::EINVAL),
+            }
+        } else {
+            2 * sq_entries
+        };
+        let ring = IoUring::new(sq_entries, cq_entries, &mut p).ok_or(SysError::ENOMEM)?;
+        p.features = IORING_FEAT_SINGLE_MMAP | IORING_FEAT_SUBMIT_STABLE | IORING_FEAT_RW_CUR_POS;
+        params.write(p)?;
+        let fd = self.process().add_file(FileLike::IoUring(Arc::new(ring)));
+        Ok(fd)
+    }
+
+    /// Submit `to_submit` entries of the io_uring `fd`, then wait until `min_complete`
+    /// completions are in the ring if asked by `flags`.
+    /// Return how many entries are submitted.
+    ///
+    /// The signal mask `sig` is not supported yet.
+    pub async fn sys_io_uring_enter(
+        &mut self,
+        fd: usize,
+        to_submit: usize,
+        min_complete: usize,
+        flags: usize,
+        sig: usize,
+        _sigsz: usize,
+    ) -> SysResult {
+        info!(
+            "io_uring_enter: fd: {}, to_submit: {}, min_complete: {}, flags: {:#x}, sig: {:#x}",
+            fd, to_submit, min_complete, flags, sig
+        );
+        if flags & !IORING_ENTER_GETEVENTS != 0 {
+            return Err(SysError::EINVAL);
+        }
+        let ring = match self.process().get_file_like(fd)? {
+            FileLike::IoUring(ring) => ring.clone(),
+            _ => return Err(SysError::EOPNOTSUPP),
+        };
+        let to_submit = to_submit as u32 as usize;
+        let mut submitted = 0;
+        while submitted < to_submit {
+            let sqe = match ring.next_sqe() {
+                Some(sqe) => sqe,
+                None => break,
+            };
+            debug!("io_uring_enter: {:x?}", sqe);
+            // failures are reported in the completion
+            if let Err(err) = self.submit_sqe(&ring, &sqe) {
+                ring.complete(sqe.user_data, Err(err));
+            }
+            submitted += 1;
+        }
+        if flags & IORING_ENTER_GETEVENTS != 0 && min_complete > 0 {
+            let min_complete = (min_complete as u32 as usize).min(IORING_MAX_CQ_ENTRIES);
+            let waited = ring.wait_cq(self.thread.clone(), min_complete).await;
+            if let Err(err) = waited {
+                if submitted == 0 {
+                    return Err(err);
+                }
+            }
+        }
+        Ok(submitted)
+    }
+
+    /// Start the operation of `sqe` on `ring`
+    fn submit_sqe(&mut self, ring: &Arc<IoUring>, sqe: &IoUringSqe) -> Result<(), SysError> {
+        if sqe.flags & !IOSQE_ASYNC != 0 || sqe.ioprio != 0 {
+            return Err(SysError::EINVAL);
+        }
+        let fd = sqe.fd as usize;
+        match sqe.opcode {
+            IORING_OP_NOP => ring.complete(sqe.user_data, Ok(0)),
+            IORING_OP_READ | IORING_OP_WRITE => {
+                if sqe.op_flags != 0 {
+                    return Err(SysError::EOPNOTSUPP);
+                }
+                let (buf, len) = (sqe.addr as usize, sqe.len as usize);
+                // -1 for the file position, as read and write use
+                let offset = match sqe.off as i64 {
+                    -1 => None,
+                    off if off < 0 => return Err(SysError::EINVAL),
+                    off => Some(off as usize),
+                };
+                let mut proc = self.process();
+                let file = match offset {
+                    Some(_) => proc.get_seekable_file(fd)?.clone(),
+                    None => proc.get_file(fd)?.clone(),
+                };
+                drop(proc);
+                let options = file.options();
+                let op = if sqe.opcode == IORING_OP_READ {
+                    if !options.read {
+                        return Err(SysError::EBADF);
+                    }
+                    unsafe { self.vm().check_write_array(buf as *mut u8, len)? };
+                    AioOp::Read { buf, len, offset }
+                } else {
+                    if !options.write {
+                        return Err(SysError::EBADF);
+                    }
+                    unsafe { self.vm().check_read_array(buf as *const u8, len)? };
+                    AioOp::Write { buf, len, offset }
+                };
+                let vm = self.thread.vm();
+                ring.spawn(sqe.user_data, crate::fs::aio::run(file, vm, op));
+            }
+            IORING_OP_FSYNC => {
+                let file = self.process().get_file(fd)?.clone();
+                let op = AioOp::Sync {
+                    data_only: sqe.op_flags & IORING_FSYNC_DATASYNC != 0,
+                };
+                let vm = self.thread.vm();
+                ring.spawn(sqe.user_data, crate::fs::aio::run(file, vm, op));
+            }
+            IORING_OP_POLL_ADD => {
+                let file = self.process().get_file_like(fd)?.clone();
+                ring.spawn(sqe.user_data, poll_add(file, sqe.op_flags as u16));
+            }
+            _ => return Err(SysError::EINVAL),
+        }
+        Ok(())
+    }
+}
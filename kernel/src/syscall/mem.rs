@@ -1,7 +1,7 @@
 use rcore_fs::vfs::MMapArea;
 use rcore_memory::memory_set::handler::{Delay, File, Linear, Shared};
 use rcore_memory::memory_set::MemoryAttr;
-use rcore_memory::PAGE_SIZE;
+use rcore_memory::{HUGE_PAGE_SIZE, PAGE_SIZE};
 
 use super::*;
 use crate::memory::GlobalFrameAlloc;
@@ -24,6 +24,10 @@ impl Syscall<'_> {
         );
 
         let mut proc = self.process();
+        let mut prot = prot;
+        if proc.personality & READ_IMPLIES_EXEC != 0 && prot.contains(MmapProt::READ) {
+            prot |= MmapProt::EXEC;
+        }
         let mut addr = addr;
         if addr == 0 {
             // although NULL can be a valid address
@@ -34,7 +38,14 @@ impl Syscall<'_> {
 
         if flags.contains(MmapFlags::FIXED) {
             // we have to map it to addr, so remove the old mapping first
-            self.vm().pop_with_split(addr, addr + len);
+            self.vm().pop_with_split(addr, addr + len)?;
+            self.shootdown();
+        } else if flags.contains(MmapFlags::HUGETLB) {
+            // leave room to align the start to a huge page
+            let start = self
+                .vm()
+                .find_free_area(addr, len + HUGE_PAGE_SIZE - PAGE_SIZE);
+            addr = (start + HUGE_PAGE_SIZE - 1) & !(HUGE_PAGE_SIZE - 1);
         } else {
             addr = self.vm().find_free_area(addr, len);
         }
@@ -50,10 +61,14 @@ impl Syscall<'_> {
                 );
                 return Ok(addr);
             } else {
+                let mut attr = prot.to_attr();
+                if flags.contains(MmapFlags::HUGETLB) {
+                    attr = attr.huge();
+                }
                 self.vm().push(
                     addr,
                     addr + len,
-                    prot.to_attr(),
+                    attr,
                     Delay::new(GlobalFrameAlloc),
                     "mmap_anon",
                 );
@@ -79,27 +94,110 @@ impl Syscall<'_> {
             "mprotect: addr={:#x}, size={:#x}, prot={:?}",
             addr, len, prot
         );
-        let _attr = prot.to_attr();
-
-        // TODO: properly set the attribute of the area
-        //        now some mut ptr check is fault
-        let vm = self.vm();
-        let memory_area = vm
-            .iter()
-            .find(|area| area.is_overlap_with(addr, addr + len));
-        if memory_area.is_none() {
-            return Err(SysError::ENOMEM);
+        if addr % PAGE_SIZE != 0 {
+            return Err(SysError::EINVAL);
+        }
+        let mut prot = prot;
+        if self.process().personality & READ_IMPLIES_EXEC != 0 && prot.contains(MmapProt::READ) {
+            prot |= MmapProt::EXEC;
         }
+        self.vm().protect(addr, addr + len, prot.to_attr())?;
+        self.shootdown();
         Ok(0)
     }
 
     pub fn sys_munmap(&mut self, addr: usize, len: usize) -> SysResult {
         info!("munmap addr={:#x}, size={:#x}", addr, len);
-        self.vm().pop_with_split(addr, addr + len);
+        self.vm().pop_with_split(addr, addr + len)?;
+        self.shootdown();
+        Ok(0)
+    }
+
+    /// Invalidate stale TLB entries on other CPUs after changing mappings,
+    /// then free the frames unmapped
+    pub(super) fn shootdown(&self) {
+        let flush = self.vm().take_flush();
+        if let Some(flush) = flush {
+            tlb_shootdown(&self.thread.vm(), flush.start, flush.end);
+        }
+    }
+
+    pub fn sys_msync(&mut self, addr: usize, len: usize, flags: usize) -> SysResult {
+        info!(
+            "msync: addr={:#x}, size={:#x}, flags={:#x}",
+            addr, len, flags
+        );
+        if addr % PAGE_SIZE != 0 {
+            return Err(SysError::EINVAL);
+        }
+        let vm = self.vm();
+        let mapped = vm.iter().any(|area| area.is_overlap_with(addr, addr + len));
+        if !mapped {
+            return Err(SysError::ENOMEM);
+        }
+        // shared file mappings write back their pages in the page cache
+        let jobs = vm.sync(addr, addr + len);
+        drop(vm);
+        let mut res = Ok(0);
+        for job in jobs {
+            if !job() {
+                res = Err(SysError::EIO);
+            }
+        }
+        res
+    }
+
+    pub fn sys_madvise(&mut self, addr: usize, len: usize, advice: usize) -> SysResult {
+        info!(
+            "madvise: addr={:#x}, size={:#x}, advice={}",
+            addr, len, advice
+        );
+        if addr % PAGE_SIZE != 0 {
+            return Err(SysError::EINVAL);
+        }
+        let end = addr.checked_add(len).ok_or(SysError::EINVAL)?;
+        match advice {
+            MADV_DONTNEED => {
+                self.vm().discard(addr, end)?;
+                self.shootdown();
+            }
+            MADV_FREE => {
+                let marked = self.vm().lazy_free(addr, end)?;
+                // no other CPU may write through a stale writable entry, or the write is lost
+                self.shootdown();
+                if marked {
+                    add_freeable_vm(&self.thread.vm());
+                }
+            }
+            MADV_HUGEPAGE => self.vm().set_huge(addr, end, true)?,
+            MADV_NOHUGEPAGE => self.vm().set_huge(addr, end, false)?,
+            // hints which change nothing here
+            MADV_NORMAL | MADV_RANDOM | MADV_SEQUENTIAL | MADV_WILLNEED | MADV_DONTFORK
+            | MADV_DOFORK | MADV_MERGEABLE | MADV_UNMERGEABLE | MADV_DONTDUMP | MADV_DODUMP => {}
+            _ => return Err(SysError::EINVAL),
+        }
         Ok(0)
     }
 }
 
+const MADV_NORMAL: usize = 0;
+const MADV_RANDOM: usize = 1;
+const MADV_SEQUENTIAL: usize = 2;
+const MADV_WILLNEED: usize = 3;
+/// Drop the pages, anonymous ones read as zero afterwards
+const MADV_DONTNEED: usize = 4;
+/// Drop anonymous pages only when memory is short, unless written before
+const MADV_FREE: usize = 8;
+const MADV_DONTFORK: usize = 10;
+const MADV_DOFORK: usize = 11;
+const MADV_MERGEABLE: usize = 12;
+const MADV_UNMERGEABLE: usize = 13;
+/// Back the range with huge pages where aligned
+const MADV_HUGEPAGE: usize = 14;
+const MADV_NOHUGEPAGE: usize = 15;
+const MADV_DONTDUMP: usize = 16;
+const MADV_DODUMP: usize = 17;
+
 bitflags! {
     pub struct MmapProt: usize {
         /// Data cannot be accessed
@@ -124,6 +222,8 @@ bitflags! {
         const FIXED = 1 << 4;
         /// The mapping is not backed by any file. (non-POSIX)
         const ANONYMOUS = 0x800;
+        /// Back the mapping with huge pages where possible
+        const HUGETLB = 0x80000;
     }
 }
 
@@ -138,6 +238,8 @@ bitflags! {
         const FIXED = 1 << 4;
         /// The mapping is not backed by any file. (non-POSIX)
         const ANONYMOUS = 1 << 5;
+        /// Back the mapping with huge pages where possible
+        const HUGETLB = 0x40000;
     }
 }
 
@@ -147,8 +249,9 @@ impl MmapProt {
         if self.contains(MmapProt::EXEC) {
             attr = attr.execute();
         }
-        // TODO: see sys_mprotect
-        //        if !self.contains(MmapProt::WRITE) { attr = attr.readonly(); }
+        if !self.contains(MmapProt::WRITE) {
+            attr = attr.readonly();
+        }
         attr
     }
 }
@@ -2,11 +2,14 @@
 
 use super::*;
 use crate::arch::cpu;
-use crate::consts::{ARCH, USER_STACK_SIZE};
+use crate::consts::{ARCH, MAX_CPU_NUM, USER_STACK_SIZE};
 use crate::syscall::SysError::ETIMEDOUT;
 use crate::trap::TICK_ACTIVITY;
 use core::mem::size_of;
 use core::sync::atomic::{AtomicI32, Ordering};
+use core::time::Duration;
+use rcore_memory::PAGE_SIZE;
+use spin::RwLock;
 
 impl Syscall<'_> {
     #[cfg(target_arch = "x86_64")]
@@ -22,11 +25,32 @@ impl Syscall<'_> {
         }
     }
 
+    pub fn sys_personality(&mut self, persona: usize) -> SysResult {
+        info!("personality: persona: {:#x}", persona);
+        let mut proc = self.process();
+        let old = proc.personality;
+        // 0xffffffff only queries the current value
+        if persona as u32 != 0xffffffff {
+            // ADDR_NO_RANDOMIZE takes effect on the next exec
+            proc.personality = persona & 0xffffffff;
+        }
+        Ok(old)
+    }
+
     pub fn sys_uname(&mut self, buf: *mut u8) -> SysResult {
         info!("uname: buf: {:?}", buf);
 
-        let offset = 65;
-        let strings = ["Linux", "orz", "0.1.0", "1", ARCH, "domain"];
+        let offset = UTSNAME_LEN + 1;
+        let hostname = HOSTNAME.read().clone();
+        let domainname = DOMAINNAME.read().clone();
+        let strings = [
+            SYSNAME,
+            hostname.as_str(),
+            RELEASE,
+            VERSION,
+            MACHINE,
+            domainname.as_str(),
+        ];
         let buf = unsafe { self.vm().check_write_array(buf, strings.len() * offset)? };
 
         for i in 0..strings.len() {
@@ -37,27 +61,123 @@ impl Syscall<'_> {
         Ok(0)
     }
 
+    /// Set the node name of uname, which needs CAP_SYS_ADMIN
+    pub fn sys_sethostname(&mut self, name: *const u8, len: usize) -> SysResult {
+        if !capable(Capability::SysAdmin) {
+            return Err(SysError::EPERM);
+        }
+        let name = self.read_utsname(name, len)?;
+        info!("sethostname: name: {:?}", name);
+        *HOSTNAME.write() = name;
+        Ok(0)
+    }
+
+    /// Set the domain name of uname, like `sethostname`
+    pub fn sys_setdomainname(&mut self, name: *const u8, len: usize) -> SysResult {
+        if !capable(Capability::SysAdmin) {
+            return Err(SysError::EPERM);
+        }
+        let name = self.read_utsname(name, len)?;
+        info!("setdomainname: name: {:?}", name);
+        *DOMAINNAME.write() = name;
+        Ok(0)
+    }
+
+    /// Read a name of `len` bytes, not nul terminated, for a field of uname
+    fn read_utsname(&self, name: *const u8, len: usize) -> Result<String, SysError> {
+        if len > UTSNAME_LEN {
+            return Err(SysError::EINVAL);
+        }
+        let name = unsafe { self.vm().check_read_array(name, len)? };
+        String::from_utf8(name.to_vec()).map_err(|_| SysError::EINVAL)
+    }
+
     pub fn sys_sched_getaffinity(&mut self, pid: usize, size: usize, mask: *mut u32) -> SysResult {
         info!(
             "sched_getaffinity: pid: {}, size: {}, mask: {:?}",
             pid, size, mask
         );
-        let mask = unsafe { self.vm().check_write_array(mask, size / size_of::<u32>())? };
-
-        // we only have 4 cpu at most.
-        // so just set it.
-        mask[0] = 0b1111;
-        mask[1] = 0;
-        mask[2] = 0;
-        mask[3] = 0;
+        // the mask is in longs as in Linux, covering all possible CPUs
+        let len = (MAX_CPU_NUM + 8 * size_of::<usize>() - 1) / (8 * size_of::<usize>())
+            * size_of::<usize>();
+        if size < len || size % size_of::<usize>() != 0 {
+            return Err(SysError::EINVAL);
+        }
+        let mask = unsafe { self.vm().check_write_array(mask as *mut u8, len)? };
+
+        // any thread may run on any online CPU, as sysconf(_SC_NPROCESSORS_ONLN) counts
+        mask.iter_mut().for_each(|byte| *byte = 0);
+        for cpu in online_cpus() {
+            mask[cpu / 8] |= 1 << (cpu % 8);
+        }
+        Ok(len)
+    }
+
+    /// Get the CPU and NUMA node the thread is running on.
+    /// It may have migrated when the caller reads them.
+    pub fn sys_getcpu(
+        &mut self,
+        mut cpu: UserOutPtr<u32>,
+        mut node: UserOutPtr<u32>,
+        _tcache: usize,
+    ) -> SysResult {
+        // the thread is on `PROCESSORS` of this CPU while running the syscall
+        let id = self.thread.cpu().unwrap_or_else(cpu::id);
+        info!("getcpu: cpu: {}", id);
+        if !cpu.is_null() {
+            cpu.write(id as u32)?;
+        }
+        if !node.is_null() {
+            // no NUMA
+            node.write(0)?;
+        }
         Ok(0)
     }
 
+    /// Issue a memory barrier on all running threads, or on those of this process,
+    /// so that they can use compiler barriers where they would need fences.
+    pub fn sys_membarrier(&mut self, cmd: usize, flags: usize) -> SysResult {
+        info!("membarrier: cmd: {:#x}, flags: {:#x}", cmd, flags);
+        if flags != 0 {
+            return Err(SysError::EINVAL);
+        }
+        match cmd {
+            MEMBARRIER_CMD_QUERY => Ok(MEMBARRIER_CMD_SUPPORTED),
+            MEMBARRIER_CMD_GLOBAL | MEMBARRIER_CMD_GLOBAL_EXPEDITED => {
+                membarrier(None);
+                Ok(0)
+            }
+            MEMBARRIER_CMD_PRIVATE_EXPEDITED => {
+                membarrier(Some(&self.thread.vm()));
+                Ok(0)
+            }
+            // nothing to prepare, so the expedited commands work without registering
+            MEMBARRIER_CMD_REGISTER_GLOBAL_EXPEDITED
+            | MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED => Ok(0),
+            _ => Err(SysError::EINVAL),
+        }
+    }
+
     pub fn sys_sysinfo(&mut self, sys_info: *mut SysInfo) -> SysResult {
         let sys_info = unsafe { self.vm().check_write_ptr(sys_info)? };
 
-        let sysinfo = SysInfo::default();
-        *sys_info = sysinfo;
+        let stats = crate::memory::frame_stats();
+        let buffers = crate::fs::buffer_cache::cached_buffers();
+        let loads = load_averages();
+        // the loads of sysinfo have 16 bits of fraction
+        let load = |i: usize| loads[i] << (16 - FSHIFT);
+        *sys_info = SysInfo {
+            uptime: crate::arch::timer::timer_now().as_secs() as isize,
+            loads: [load(0), load(1), load(2)],
+            totalram: stats.total,
+            freeram: stats.free,
+            sharedram: crate::ipc::shm_frames(),
+            bufferram: buffers,
+            procs: PROCESSES.read().len() as u16,
+            // there is no swap
+            mem_unit: PAGE_SIZE as u32,
+            ..SysInfo::default()
+        };
         Ok(0)
     }
 
@@ -67,10 +187,12 @@ impl Syscall<'_> {
         op: u32,
         val: i32,
         timeout: UserInPtr<TimeSpec>,
+        _uaddr2: usize,
+        val3: u32,
     ) -> SysResult {
         info!(
-            "futex: [{}] uaddr: {:#x}, op: {:#x}, val: {}, timeout_ptr: {:?}",
-            self.thread.tid, uaddr, op, val, timeout
+            "futex: [{}] uaddr: {:#x}, op: {:#x}, val: {}, timeout_ptr: {:?}, val3: {:#x}",
+            self.thread.tid, uaddr, op, val, timeout, val3
         );
         if op & OP_PRIVATE == 0 {
             warn!("process-shared futex is unimplemented");
@@ -82,6 +204,11 @@ impl Syscall<'_> {
 
         const OP_WAIT: u32 = 0;
         const OP_WAKE: u32 = 1;
+        const OP_LOCK_PI: u32 = 6;
+        const OP_UNLOCK_PI: u32 = 7;
+        const OP_TRYLOCK_PI: u32 = 8;
+        const OP_WAIT_BITSET: u32 = 9;
+        const OP_WAKE_BITSET: u32 = 10;
         const OP_PRIVATE: u32 = 0x80;
 
         let mut proc = self.process();
@@ -98,17 +225,117 @@ impl Syscall<'_> {
                     queue.wait(None).await?;
                     Ok(0)
                 } else {
-                    // TODO: timeout
                     let timeout = timeout.read()?;
                     info!("futex wait timeout: {:?}", timeout);
                     queue.wait(Some(timeout.to_duration())).await?;
                     Ok(0)
                 }
             }
+            OP_WAIT_BITSET => {
+                if val3 == 0 {
+                    return Err(SysError::EINVAL);
+                }
+                let deadline = futex_deadline(timeout)?;
+                if atomic.load(Ordering::Acquire) != val {
+                    return Err(SysError::EAGAIN);
+                }
+                drop(proc);
+                queue.wait_bitset(deadline, val3).await
+            }
+            OP_LOCK_PI | OP_TRYLOCK_PI => {
+                let tid = self.thread.tid;
+                let word_tid = proc.pid_ns.local(Pid(tid)).unwrap_or(tid) as u32;
+                let deadline = match op & 0xf {
+                    OP_LOCK_PI => futex_deadline(timeout)?,
+                    _ => None,
+                };
+                loop {
+                    let value = atomic.load(Ordering::Acquire) as u32;
+                    let owner = value & FUTEX_TID_MASK;
+                    if owner == word_tid {
+                        return Err(SysError::EDEADLK);
+                    }
+                    let owner_tid = proc.pid_ns.global(owner as usize).unwrap_or(owner as usize);
+                    let alive = owner != 0 && THREADS.read().contains_key(&owner_tid);
+                    if !alive {
+                        if owner != 0 && value & FUTEX_OWNER_DIED == 0 {
+                            return Err(SysError::ESRCH);
+                        }
+                        // free, or its owner died and the new one is told so
+                        let new = word_tid | (value & (FUTEX_OWNER_DIED | FUTEX_WAITERS));
+                        if atomic
+                            .compare_exchange(
+                                value as i32,
+                                new as i32,
+                                Ordering::AcqRel,
+                                Ordering::Acquire,
+                            )
+                            .is_ok()
+                        {
+                            return Ok(0);
+                        }
+                        continue;
+                    }
+                    if op & 0xf == OP_TRYLOCK_PI {
+                        return Err(SysError::EAGAIN);
+                    }
+                    // make the owner unlock by the syscall
+                    let with_waiters = (value | FUTEX_WAITERS) as i32;
+                    if atomic
+                        .compare_exchange(
+                            value as i32,
+                            with_waiters,
+                            Ordering::AcqRel,
+                            Ordering::Acquire,
+                        )
+                        .is_err()
+                    {
+                        continue;
+                    }
+                    let waiter = PiWaiter {
+                        tid,
+                        word_tid,
+                        // as `effective_nice`, with the process locked
+                        nice: proc
+                            .nice
+                            .min(self.thread.inner.lock().pi_boost.unwrap_or(i8::MAX)),
+                    };
+                    let locked = queue.lock_pi(waiter, owner_tid, deadline);
+                    self.thread.inner.lock().pi_blocked_on = Some(uaddr);
+                    proc.update_pi_boost(owner_tid);
+                    drop(proc);
+                    let res = locked.await;
+                    self.thread.inner.lock().pi_blocked_on = None;
+                    if res.is_err() {
+                        // the boost of the owner may be lower without this waiter
+                        self.process().update_pi_boost(owner_tid);
+                    }
+                    return res;
+                }
+            }
+            OP_UNLOCK_PI => {
+                let tid = self.thread.tid;
+                let word_tid = proc.pid_ns.local(Pid(tid)).unwrap_or(tid) as u32;
+                if atomic.load(Ordering::Acquire) as u32 & FUTEX_TID_MASK != word_tid {
+                    return Err(SysError::EPERM);
+                }
+                let next = queue.unlock_pi(atomic, 0);
+                proc.update_pi_boost(tid);
+                if let Some(next) = next {
+                    proc.update_pi_boost(next);
+                }
+                Ok(0)
+            }
             OP_WAKE => {
                 let woken_up_count = queue.wake(val as usize);
                 Ok(woken_up_count)
             }
+            OP_WAKE_BITSET => {
+                if val3 == 0 {
+                    return Err(SysError::EINVAL);
+                }
+                Ok(queue.wake_bitset(val as usize, val3))
+            }
             _ => {
                 warn!("unsupported futex operation: {}", op);
                 Err(SysError::ENOSYS)
@@ -183,22 +410,40 @@ impl Syscall<'_> {
         }
     }
 
-    pub fn sys_getrandom(&mut self, buf: *mut u8, len: usize, _flag: u32) -> SysResult {
-        //info!("getrandom: buf: {:?}, len: {:?}, falg {:?}", buf, len,flag);
-        let slice = unsafe { self.vm().check_write_array(buf, len)? };
-        let mut i = 0;
-        for elm in slice {
-            unsafe {
-                // to prevent overflow
-                *elm = (i + crate::trap::TICK as u8 as u16) as u8;
-            }
-            i += 1;
+    /// Fill `buf` from the kernel random generator, which is seeded before any user program
+    /// runs, so it never blocks and GRND_NONBLOCK changes nothing.
+    /// GRND_RANDOM draws from the same generator, as in Linux since 5.6.
+    pub fn sys_getrandom(&mut self, buf: *mut u8, len: usize, flags: u32) -> SysResult {
+        info!(
+            "getrandom: buf: {:?}, len: {}, flags: {:#x}",
+            buf, len, flags
+        );
+        let flags = GetRandomFlags::from_bits(flags).ok_or(SysError::EINVAL)?;
+        if flags.contains(GetRandomFlags::RANDOM | GetRandomFlags::INSECURE) {
+            return Err(SysError::EINVAL);
         }
-
+        // larger requests are filled partially
+        let len = len.min(GETRANDOM_MAX);
+        let slice = unsafe { self.vm().check_write_array(buf, len)? };
+        crate::rand::fill(slice);
         Ok(len)
     }
 }
 
+bitflags! {
+    struct GetRandomFlags: u32 {
+        /// Do not block if the generator is not seeded
+        const NONBLOCK = 1;
+        /// Draw from the blocking pool
+        const RANDOM = 2;
+        /// Do not wait for the generator to be seeded
+        const INSECURE = 4;
+    }
+}
+
+/// Max bytes returned by one getrandom, as Linux
+const GETRANDOM_MAX: usize = 0x1ff_ffff;
+
 const LINUX_REBOOT_CMD_RESTART: u32 = 0x01234567;
 const LINUX_REBOOT_CMD_HALT: u32 = 0xCDEF0123;
 const LINUX_REBOOT_CMD_CAD_ON: u32 = 0x89ABCDEF;
@@ -208,21 +453,44 @@ const LINUX_REBOOT_CMD_RESTART2: u32 = 0xA1B2C3D4;
 const LINUX_REBOOT_CMD_SW_SUSPEND: u32 = 0xD000FCE2;
 const LINUX_REBOOT_CMD_KEXEC: u32 = 0x45584543;
 
+/// Max length of a field of uname, without the nul
+const UTSNAME_LEN: usize = 64;
+
+const SYSNAME: &str = "rCore";
+const RELEASE: &str = env!("CARGO_PKG_VERSION");
+const VERSION: &str = "#1 SMP";
+
+/// Machine of uname, for the ELF machine that `new_user_vm` accepts
+#[cfg(target_arch = "mips")]
+const MACHINE: &str = "mips";
+#[cfg(not(target_arch = "mips"))]
+const MACHINE: &str = ARCH;
+
+lazy_static! {
+    /// Node name of uname
+    static ref HOSTNAME: RwLock<String> = RwLock::new(String::from("rcore"));
+    /// Domain name of uname
+    static ref DOMAINNAME: RwLock<String> = RwLock::new(String::from("(none)"));
+}
+
+/// `struct sysinfo`, whose sizes are in `mem_unit`
 #[repr(C)]
 #[derive(Debug, Default)]
 pub struct SysInfo {
-    uptime: u64,
-    loads: [u64; 3],
-    totalram: u64,
-    freeram: u64,
-    sharedram: u64,
-    bufferram: u64,
-    totalswap: u64,
-    freeswap: u64,
+    uptime: isize,
+    loads: [usize; 3],
+    totalram: usize,
+    freeram: usize,
+    sharedram: usize,
+    bufferram: usize,
+    totalswap: usize,
+    freeswap: usize,
     procs: u16,
-    totalhigh: u64,
-    freehigh: u64,
+    totalhigh: usize,
+    freehigh: usize,
     mem_unit: u32,
+    /// Padding to 64 bytes on 32-bit targets
+    _f: [u8; 20 - 2 * size_of::<usize>() - size_of::<u32>()],
 }
 
 const RLIMIT_STACK: usize = 3;
@@ -236,3 +504,26 @@ pub struct RLimit {
     cur: u64, // soft limit
     max: u64, // hard limit
 }
+
+const MEMBARRIER_CMD_QUERY: usize = 0;
+const MEMBARRIER_CMD_GLOBAL: usize = 1 << 0;
+const MEMBARRIER_CMD_GLOBAL_EXPEDITED: usize = 1 << 1;
+const MEMBARRIER_CMD_REGISTER_GLOBAL_EXPEDITED: usize = 1 << 2;
+const MEMBARRIER_CMD_PRIVATE_EXPEDITED: usize = 1 << 3;
+const MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED: usize = 1 << 4;
+
+/// Commands reported by `MEMBARRIER_CMD_QUERY`
+const MEMBARRIER_CMD_SUPPORTED: usize = MEMBARRIER_CMD_GLOBAL
+    | MEMBARRIER_CMD_GLOBAL_EXPEDITED
+    | MEMBARRIER_CMD_REGISTER_GLOBAL_EXPEDITED
+    | MEMBARRIER_CMD_PRIVATE_EXPEDITED
+    | MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED;
+
+/// The deadline of an absolute futex timeout, on the clock of clock_gettime
+fn futex_deadline(timeout: UserInPtr<TimeSpec>) -> Result<Option<Duration>, SysError> {
+    Ok(timeout.read_if_not_null()?.map(|timeout| {
+        let now = TimeSpec::get_epoch().to_duration();
+        let left = timeout.to_duration().checked_sub(now).unwrap_or_default();
+        crate::arch::timer::timer_now() + left
+    }))
+}
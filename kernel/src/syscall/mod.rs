@@ -6,7 +6,7 @@ use crate::fs::epoll::EpollEvent;
 use crate::memory::{copy_from_user, MemorySet};
 use crate::process::*;
 use crate::signal::{Signal, SignalAction, SignalFrame, SignalStack, SignalUserContext, Sigset};
-use crate::sync::{Condvar, MutexGuard, SpinNoIrq};
+use crate::sync::{lock_ordered, Condvar, LockClass, MutexGuard, OrderedGuard, SpinNoIrq};
 use crate::util;
 use alloc::{string::String, sync::Arc, vec::Vec};
 use bitflags::bitflags;
@@ -17,28 +17,38 @@ use rcore_memory::VMError;
 use trapframe::TrapFrame;
 use trapframe::{GeneralRegs, UserContext};
 
+pub use self::aio::*;
 pub use self::custom::*;
 pub use self::fs::*;
+pub use self::io_uring::*;
 pub use self::ipc::*;
 pub use self::lkm::*;
 pub use self::mem::*;
 pub use self::misc::*;
 pub use self::net::*;
 pub use self::proc::*;
+pub use self::ptrace::*;
+pub use self::seccomp::*;
 pub use self::signal::*;
 pub use self::time::*;
+pub use self::trace::*;
 pub use self::user::*;
 
+mod aio;
 mod custom;
 mod fs;
+mod io_uring;
 mod ipc;
 mod lkm;
 mod mem;
 mod misc;
 mod net;
 mod proc;
+mod ptrace;
+mod seccomp;
 mod signal;
 mod time;
+mod trace;
 mod user;
 
 #[cfg(feature = "profile")]
@@ -67,14 +77,48 @@ pub async fn handle_syscall(thread: &Arc<Thread>, context: &mut UserContext) ->
         context.epc = context.epc + 4;
     }
 
+    if tracing(thread.tid) {
+        trace_entry(thread.tid, num, &args);
+    }
+    let traced = Ptrace::tracing_syscall(thread);
+    if traced {
+        let stop = PtraceStop::SyscallEntry {
+            nr: num,
+            args,
+            pc: user_pc(context),
+            sp: context.get_sp(),
+        };
+        ptrace_stop(thread, stop).await;
+    }
+
     let mut syscall = Syscall {
         thread,
         context,
         exit: false,
     };
-    let ret = syscall.syscall(num, args).await;
+    // seccomp filters run after the syscall-entry stop of ptrace, as in Linux
+    let ret = match syscall.seccomp(num, &args) {
+        Some(ret) => ret,
+        None => syscall.syscall(num, args).await,
+    };
     let exit = syscall.exit;
+    if tracing(thread.tid) {
+        trace_exit(thread.tid, num, ret);
+    }
     context.set_syscall_ret(ret as usize);
+
+    if (num == SYS_EXECVE || num == SYS_EXECVEAT) && ret == 0 && Ptrace::traced(thread) {
+        // a traced process gets SIGTRAP after execve
+        ptrace_stop(thread, PtraceStop::Exec).await;
+    }
+    if traced && !exit {
+        let stop = PtraceStop::SyscallExit {
+            ret,
+            pc: user_pc(context),
+            sp: context.get_sp(),
+        };
+        ptrace_stop(thread, stop).await;
+    }
     exit
 }
 
@@ -89,13 +133,14 @@ struct Syscall<'a> {
 impl Syscall<'_> {
     /// Get current process
     /// spinlock is tend to deadlock, use busy waiting
-    pub fn process(&self) -> MutexGuard<'_, Process, SpinNoIrq> {
-        self.thread.proc.busy_lock()
+    pub fn process(&self) -> OrderedGuard<MutexGuard<'_, Process, SpinNoIrq>> {
+        lock_ordered(LockClass::Process, || self.thread.proc.busy_lock())
     }
 
     /// Get current virtual memory
-    pub fn vm(&self) -> MutexGuard<'_, MemorySet, SpinNoIrq> {
-        self.thread.vm.lock()
+    /// Do not call `process()` while holding it, see `sync::lock_order`
+    pub fn vm(&self) -> VmGuard<'_> {
+        self.thread.lock_vm()
     }
 
     /// System call dispatcher
@@ -122,9 +167,13 @@ impl Syscall<'_> {
                 self.sys_read(args[0], UserOutPtr::from(args[1]), args[2])
                     .await
             }
-            SYS_WRITE => self.sys_write(args[0], args[1] as *const u8, args[2]),
-            SYS_OPENAT => self.sys_openat(args[0], args[1] as *const u8, args[2], args[3]),
+            SYS_WRITE => self.sys_write(args[0], args[1] as *const u8, args[2]).await,
+            SYS_OPENAT => {
+                self.sys_openat(args[0], args[1] as *const u8, args[2], args[3])
+                    .await
+            }
             SYS_CLOSE => self.sys_close(args[0]),
+            SYS_CLOSE_RANGE => self.sys_close_range(args[0], args[1], args[2]),
             SYS_FSTAT => self.sys_fstat(args[0], args[1] as *mut Stat),
             SYS_NEWFSTATAT => {
                 self.sys_fstatat(args[0], args[1] as *const u8, args[2] as *mut Stat, args[3])
@@ -135,22 +184,66 @@ impl Syscall<'_> {
                 self.sys_pread(args[0], UserOutPtr::from(args[1]), args[2], args[3])
                     .await
             }
-            SYS_PWRITE64 => self.sys_pwrite(args[0], args[1] as *const u8, args[2], args[3]),
+            SYS_PWRITE64 => {
+                self.sys_pwrite(args[0], args[1] as *const u8, args[2], args[3])
+                    .await
+            }
             SYS_READV => {
                 self.sys_readv(args[0], UserInPtr::from(args[1]), args[2])
                     .await
             }
-            SYS_WRITEV => self.sys_writev(args[0], args[1] as *const IoVec, args[2]),
+            SYS_WRITEV => {
+                self.sys_writev(args[0], args[1] as *const IoVec, args[2])
+                    .await
+            }
             SYS_SENDFILE => {
                 self.sys_sendfile(args[0], args[1], UserInOutPtr::from(args[2]), args[3])
                     .await
             }
+            SYS_SPLICE => {
+                self.sys_splice(
+                    args[0],
+                    UserInOutPtr::from(args[1]),
+                    args[2],
+                    UserInOutPtr::from(args[3]),
+                    args[4],
+                    args[5],
+                )
+                .await
+            }
+            SYS_TEE => self.sys_tee(args[0], args[1], args[2], args[3]).await,
+            SYS_IO_SETUP => self.sys_io_setup(args[0], UserInOutPtr::from(args[1])),
+            SYS_IO_DESTROY => self.sys_io_destroy(args[0]),
+            SYS_IO_SUBMIT => self.sys_io_submit(args[0], args[1], UserInPtr::from(args[2])),
+            SYS_IO_CANCEL => self.sys_io_cancel(args[0], args[1], args[2]),
+            SYS_IO_GETEVENTS => {
+                self.sys_io_getevents(
+                    args[0],
+                    args[1],
+                    args[2],
+                    UserOutPtr::from(args[3]),
+                    UserInPtr::from(args[4]),
+                )
+                .await
+            }
+            SYS_IO_URING_SETUP => self.sys_io_uring_setup(args[0], UserInOutPtr::from(args[1])),
+            SYS_IO_URING_ENTER => {
+                self.sys_io_uring_enter(args[0], args[1], args[2], args[3], args[4], args[5])
+                    .await
+            }
+            SYS_VMSPLICE => {
+                self.sys_vmsplice(args[0], args[1] as *const IoVec, args[2], args[3])
+                    .await
+            }
             SYS_FCNTL => self.sys_fcntl(args[0], args[1], args[2]),
             SYS_FLOCK => self.sys_flock(args[0], args[1]),
             SYS_FSYNC => self.sys_fsync(args[0]),
             SYS_FDATASYNC => self.sys_fdatasync(args[0]),
+            SYS_FADVISE64 => self.sys_fadvise64(args[0], args[1], args[2], args[3]),
+            SYS_READAHEAD => self.sys_readahead(args[0], args[1], args[2]),
             SYS_TRUNCATE => self.sys_truncate(args[0] as *const u8, args[1]),
             SYS_FTRUNCATE => self.sys_ftruncate(args[0], args[1]),
+            SYS_FALLOCATE => self.sys_fallocate(args[0], args[1], args[2], args[3]).await,
             SYS_GETDENTS64 => self.sys_getdents64(args[0], args[1] as *mut LinuxDirent64, args[2]),
             SYS_GETCWD => self.sys_getcwd(args[0] as *mut u8, args[1]),
             SYS_CHDIR => self.sys_chdir(args[0] as *const u8),
@@ -158,6 +251,13 @@ impl Syscall<'_> {
                 self.sys_renameat(args[0], args[1] as *const u8, args[2], args[3] as *const u8)
             }
             SYS_MKDIRAT => self.sys_mkdirat(args[0], args[1] as *const u8, args[2]),
+            SYS_CHROOT => self.sys_chroot(args[0] as *const u8),
+            SYS_MKNODAT => self.sys_mknodat(args[0], args[1] as *const u8, args[2], args[3]),
+            SYS_INOTIFY_INIT1 => self.sys_inotify_init1(args[0]),
+            SYS_INOTIFY_ADD_WATCH => {
+                self.sys_inotify_add_watch(args[0], args[1] as *const u8, args[2] as u32)
+            }
+            SYS_INOTIFY_RM_WATCH => self.sys_inotify_rm_watch(args[0], args[1] as i32),
             SYS_LINKAT => self.sys_linkat(
                 args[0],
                 args[1] as *const u8,
@@ -179,8 +279,13 @@ impl Syscall<'_> {
             SYS_FACCESSAT => self.sys_faccessat(args[0], args[1] as *const u8, args[2], args[3]),
             SYS_DUP3 => self.sys_dup3(args[0], args[1], args[2]),
             SYS_PIPE2 => self.sys_pipe2(args[0] as *mut u32, args[1]), // TODO: handle `flags`
-            SYS_SET_ROBUST_LIST => self.unimplemented("set_robuts_list", Ok(0)),
-            SYS_GET_ROBUST_LIST => self.unimplemented("get_robust_list", Ok(0)),
+            SYS_MEMFD_CREATE => self.sys_memfd_create(args[0] as *const u8, args[1]),
+            SYS_SET_ROBUST_LIST => self.sys_set_robust_list(args[0], args[1]),
+            SYS_GET_ROBUST_LIST => self.sys_get_robust_list(
+                args[0],
+                UserOutPtr::from(args[1]),
+                UserOutPtr::from(args[2]),
+            ),
             SYS_UTIMENSAT => self.sys_utimensat(
                 args[0],
                 args[1] as *const u8,
@@ -229,20 +334,30 @@ impl Syscall<'_> {
             ),
             SYS_EVENTFD2 => self.unimplemented("eventfd2", Err(SysError::EACCES)),
 
-            SYS_SOCKETPAIR => self.unimplemented("socketpair", Err(SysError::EACCES)),
+            SYS_SOCKETPAIR => {
+                self.sys_socketpair(args[0], args[1], args[2], args[3] as *mut [i32; 2])
+            }
             // file system
             SYS_STATFS => self.unimplemented("statfs", Err(SysError::EACCES)),
             SYS_FSTATFS => self.unimplemented("fstatfs", Err(SysError::EACCES)),
             SYS_SYNC => self.sys_sync(),
-            SYS_MOUNT => self.unimplemented("mount", Err(SysError::EACCES)),
-            SYS_UMOUNT2 => self.unimplemented("umount2", Err(SysError::EACCES)),
+            SYS_SYNCFS => self.sys_syncfs(args[0]),
+            SYS_MOUNT => self.sys_mount(
+                args[0] as *const u8,
+                args[1] as *const u8,
+                args[2] as *const u8,
+                args[3],
+                args[4],
+            ),
+            SYS_UMOUNT2 => self.sys_umount2(args[0] as *const u8, args[1]),
 
             // memory
             SYS_BRK => self.unimplemented("brk", Err(SysError::ENOMEM)),
             SYS_MMAP => self.sys_mmap(args[0], args[1], args[2], args[3], args[4], args[5]),
             SYS_MPROTECT => self.sys_mprotect(args[0], args[1], args[2]),
             SYS_MUNMAP => self.sys_munmap(args[0], args[1]),
-            SYS_MADVISE => self.unimplemented("madvise", Ok(0)),
+            SYS_MSYNC => self.sys_msync(args[0], args[1], args[2]),
+            SYS_MADVISE => self.sys_madvise(args[0], args[1], args[2]),
 
             // signal
             SYS_RT_SIGACTION => self.sys_rt_sigaction(
@@ -258,6 +373,11 @@ impl Syscall<'_> {
                 UserOutPtr::from(args[2]),
                 args[3],
             ),
+            SYS_RT_SIGSUSPEND => {
+                self.sys_rt_sigsuspend(UserInPtr::from(args[0]), args[1])
+                    .await
+            }
+            SYS_RT_SIGPENDING => self.sys_rt_sigpending(UserOutPtr::from(args[0]), args[1]),
             SYS_SIGALTSTACK => {
                 self.sys_sigaltstack(UserInPtr::from(args[0]), UserOutPtr::from(args[1]))
             }
@@ -268,6 +388,11 @@ impl Syscall<'_> {
             SYS_SCHED_GETAFFINITY => {
                 self.sys_sched_getaffinity(args[0], args[1], args[2] as *mut u32)
             }
+            SYS_GETCPU => self.sys_getcpu(
+                UserOutPtr::from(args[0]),
+                UserOutPtr::from(args[1]),
+                args[2],
+            ),
 
             // socket
             SYS_SOCKET => self.sys_socket(args[0], args[1], args[2]),
@@ -290,7 +415,7 @@ impl Syscall<'_> {
                 args[4] as *mut SockAddr,
                 args[5] as *mut u32,
             ),
-            //        SYS_SENDMSG => self.sys_sendmsg(),
+            SYS_SENDMSG => self.sys_sendmsg(args[0], args[1] as *const MsgHdr, args[2]),
             SYS_RECVMSG => self.sys_recvmsg(args[0], args[1] as *mut MsgHdr, args[2]),
             SYS_SHUTDOWN => self.sys_shutdown(args[0], args[1]),
             SYS_BIND => self.sys_bind(args[0], args[1] as *const SockAddr, args[2]),
@@ -313,24 +438,53 @@ impl Syscall<'_> {
             ),
 
             // process
-            SYS_CLONE => self.sys_clone(
-                args[0],
-                args[1],
-                args[2] as *mut u32,
-                args[3] as *mut u32,
-                args[4],
-            ),
+            // x86_64 passes tls after child_tid, other archs before it
+            #[cfg(target_arch = "x86_64")]
+            SYS_CLONE => {
+                self.sys_clone(
+                    args[0],
+                    args[1],
+                    args[2] as *mut u32,
+                    args[3] as *mut u32,
+                    args[4],
+                )
+                .await
+            }
+            #[cfg(not(target_arch = "x86_64"))]
+            SYS_CLONE => {
+                self.sys_clone(
+                    args[0],
+                    args[1],
+                    args[2] as *mut u32,
+                    args[4] as *mut u32,
+                    args[3],
+                )
+                .await
+            }
             SYS_EXECVE => self.sys_exec(
                 args[0] as *const u8,
                 args[1] as *const *const u8,
                 args[2] as *const *const u8,
             ),
+            SYS_EXECVEAT => self.sys_execveat(
+                args[0],
+                args[1] as *const u8,
+                args[2] as *const *const u8,
+                args[3] as *const *const u8,
+                args[4],
+            ),
             SYS_EXIT => self.sys_exit(args[0] as usize),
             SYS_EXIT_GROUP => self.sys_exit_group(args[0]),
             SYS_WAIT4 => {
-                self.sys_wait4(args[0] as isize, UserInOutPtr::from(args[1]))
+                self.sys_wait4(args[0] as isize, UserInOutPtr::from(args[1]), args[2])
                     .await
             } // TODO: wait4
+            SYS_WAITID => {
+                self.sys_waitid(args[0], args[1], UserOutPtr::from(args[2]), args[3])
+                    .await
+            }
+            SYS_PTRACE => self.sys_ptrace(args[0], args[1], args[2], args[3]),
+            SYS_SECCOMP => self.sys_seccomp(args[0], args[1], args[2]),
             SYS_SET_TID_ADDRESS => self.sys_set_tid_address(args[0] as *mut u32),
             SYS_FUTEX => {
                 self.sys_futex(
@@ -338,6 +492,8 @@ impl Syscall<'_> {
                     args[1] as u32,
                     args[2] as i32,
                     UserInPtr::from(args[3]),
+                    args[4],
+                    args[5] as u32,
                 )
                 .await
             }
@@ -379,6 +535,10 @@ impl Syscall<'_> {
             SYS_GETPID => self.sys_getpid(),
             SYS_GETTID => self.sys_gettid(),
             SYS_UNAME => self.sys_uname(args[0] as *mut u8),
+            SYS_SETHOSTNAME => self.sys_sethostname(args[0] as *const u8, args[1]),
+            SYS_SETDOMAINNAME => self.sys_setdomainname(args[0] as *const u8, args[1]),
+            SYS_PERSONALITY => self.sys_personality(args[0]),
+            SYS_UNSHARE => self.sys_unshare(args[0]),
             SYS_UMASK => self.unimplemented("umask", Ok(0o777)),
             //        SYS_GETRLIMIT => self.sys_getrlimit(),
             SYS_SETRLIMIT => self.unimplemented("setrlimit", Ok(0)),
@@ -388,21 +548,32 @@ impl Syscall<'_> {
             SYS_GETUID => self.unimplemented("getuid", Ok(0)),
             SYS_GETGID => self.unimplemented("getgid", Ok(0)),
             SYS_SETUID => self.unimplemented("setuid", Ok(0)),
-            SYS_GETEUID => self.unimplemented("geteuid", Ok(0)),
+            SYS_GETEUID => self.unimplemented("geteuid", Ok(EUID as usize)),
             SYS_GETEGID => self.unimplemented("getegid", Ok(0)),
             SYS_GETPPID => self.sys_getppid(),
             SYS_SETSID => self.unimplemented("setsid", Ok(0)),
             SYS_GETPGID => self.sys_getpgid(args[0]),
             SYS_SETPGID => self.sys_setpgid(args[0], args[1]),
             SYS_GETGROUPS => self.unimplemented("getgroups", Ok(0)),
-            SYS_RT_SIGTIMEDWAIT => self.unimplemented("rt_sigtimedwait", Ok(0)),
+            SYS_RT_SIGTIMEDWAIT => {
+                self.sys_rt_sigtimedwait(
+                    UserInPtr::from(args[0]),
+                    UserOutPtr::from(args[1]),
+                    UserInPtr::from(args[2]),
+                    args[3],
+                )
+                .await
+            }
             SYS_SETGROUPS => self.unimplemented("setgroups", Ok(0)),
             SYS_SETRESUID => self.unimplemented("setresuid", Ok(0)),
             SYS_SETRESGID => self.unimplemented("setresgid", Ok(0)),
             SYS_SETGID => self.unimplemented("setgid", Ok(0)),
-            SYS_SETPRIORITY => self.sys_set_priority(args[0]),
-            SYS_PRCTL => self.unimplemented("prctl", Ok(0)),
-            SYS_MEMBARRIER => self.unimplemented("membarrier", Ok(0)),
+            SYS_GETPRIORITY => self.sys_getpriority(args[0], args[1]),
+            SYS_SETPRIORITY => self.sys_setpriority(args[0], args[1], args[2]),
+            SYS_PRCTL => self.sys_prctl(args[0], args[1], args[2]),
+            SYS_CAPGET => self.sys_capget(UserInOutPtr::from(args[0]), UserOutPtr::from(args[1])),
+            SYS_CAPSET => self.sys_capset(UserInOutPtr::from(args[0]), UserInPtr::from(args[1])),
+            SYS_MEMBARRIER => self.sys_membarrier(args[0], args[1]),
             SYS_PRLIMIT64 => self.sys_prlimit64(
                 args[0],
                 args[1],
@@ -418,7 +589,9 @@ impl Syscall<'_> {
             SYS_GETRANDOM => {
                 self.sys_getrandom(args[0] as *mut u8, args[1] as usize, args[2] as u32)
             }
-            SYS_RT_SIGQUEUEINFO => self.unimplemented("rt_sigqueueinfo", Ok(0)),
+            SYS_RT_SIGQUEUEINFO => {
+                self.sys_rt_sigqueueinfo(args[0], args[1], UserInPtr::from(args[2]))
+            }
 
             // kernel module
             SYS_INIT_MODULE => {
@@ -435,6 +608,7 @@ impl Syscall<'_> {
             SYS_GET_PADDR => {
                 self.sys_get_paddr(args[0] as *const u64, args[1] as *mut u64, args[2])
             }
+            SYS_SYSCALL_TRACE => self.sys_syscall_trace(args[0], args[1]),
 
             _ => {
                 let ret = match () {
@@ -484,17 +658,20 @@ impl Syscall<'_> {
     #[cfg(target_arch = "mips")]
     async fn mips_syscall(&mut self, id: usize, args: [usize; 6]) -> Option<SysResult> {
         let ret = match id {
-            SYS_OPEN => self.sys_open(args[0] as *const u8, args[1], args[2]),
+            SYS_OPEN => self.sys_open(args[0] as *const u8, args[1], args[2]).await,
             SYS_POLL => {
                 self.sys_poll(UserInOutPtr::from(args[0]), args[1], args[2])
                     .await
             }
             SYS_DUP2 => self.sys_dup2(args[0], args[1]),
-            SYS_FORK => self.sys_fork(),
+            SYS_FORK => self.sys_fork().await,
             SYS_MMAP2 => self.sys_mmap(args[0], args[1], args[2], args[3], args[4], args[5] * 4096),
             SYS_FSTAT64 => self.sys_fstat(args[0], args[1] as *mut Stat),
             SYS_LSTAT64 => self.sys_lstat(args[0] as *const u8, args[1] as *mut Stat),
             SYS_STAT64 => self.sys_stat(args[0] as *const u8, args[1] as *mut Stat),
+            SYS_UTIMES => self.sys_utimes(args[0] as *const u8, args[1] as *const TimeVal),
+            SYS_MKNOD => self.sys_mknod(args[0] as *const u8, args[1], args[2]),
+            SYS_INOTIFY_INIT => self.sys_inotify_init(),
             SYS_PIPE => {
                 let fd_ptr = args[0] as *mut u32;
                 match self.sys_pipe(fd_ptr) {
@@ -536,7 +713,7 @@ impl Syscall<'_> {
     #[cfg(target_arch = "x86_64")]
     async fn x86_64_syscall(&mut self, id: usize, args: [usize; 6]) -> Option<SysResult> {
         let ret = match id {
-            SYS_OPEN => self.sys_open(args[0] as *const u8, args[1], args[2]),
+            SYS_OPEN => self.sys_open(args[0] as *const u8, args[1], args[2]).await,
             SYS_STAT => self.sys_stat(args[0] as *const u8, args[1] as *mut Stat),
             SYS_LSTAT => self.sys_lstat(args[0] as *const u8, args[1] as *mut Stat),
             SYS_POLL => {
@@ -544,6 +721,7 @@ impl Syscall<'_> {
                     .await
             }
             SYS_ACCESS => self.sys_access(args[0] as *const u8, args[1]),
+            SYS_UTIMES => self.sys_utimes(args[0] as *const u8, args[1] as *const TimeVal),
             SYS_PIPE => self.sys_pipe(args[0] as *mut u32),
             SYS_SELECT => self.sys_select(
                 args[0],
@@ -554,10 +732,12 @@ impl Syscall<'_> {
             ),
             SYS_DUP2 => self.sys_dup2(args[0], args[1]),
             SYS_ALARM => self.unimplemented("alarm", Ok(0)),
-            SYS_FORK => self.sys_fork(),
-            SYS_VFORK => self.sys_vfork(),
+            SYS_FORK => self.sys_fork().await,
+            SYS_VFORK => self.sys_vfork().await,
             SYS_RENAME => self.sys_rename(args[0] as *const u8, args[1] as *const u8),
             SYS_MKDIR => self.sys_mkdir(args[0] as *const u8, args[1]),
+            SYS_MKNOD => self.sys_mknod(args[0] as *const u8, args[1], args[2]),
+            SYS_INOTIFY_INIT => self.sys_inotify_init(),
             SYS_RMDIR => self.sys_rmdir(args[0] as *const u8),
             SYS_LINK => self.sys_link(args[0] as *const u8, args[1] as *const u8),
             SYS_UNLINK => self.sys_unlink(args[0] as *const u8),
@@ -627,13 +807,18 @@ pub enum SysError {
     EIDRM = 43,
     ENOTSOCK = 80,
     ENOPROTOOPT = 92,
+    EOPNOTSUPP = 95,
     EPFNOSUPPORT = 96,
     EAFNOSUPPORT = 97,
+    EADDRINUSE = 98,
     ENOBUFS = 105,
     EISCONN = 106,
     ENOTCONN = 107,
     ETIMEDOUT = 110,
     ECONNREFUSED = 111,
+    EALREADY = 114,
+    EINPROGRESS = 115,
+    ECANCELED = 125,
 }
 
 #[allow(non_snake_case)]
@@ -686,12 +871,17 @@ impl fmt::Display for SysError {
                 ELOOP => "Too many symbolic links encountered",
                 ENOTSOCK => "Socket operation on non-socket",
                 ENOPROTOOPT => "Protocol not available",
+                EOPNOTSUPP => "Operation not supported",
                 EPFNOSUPPORT => "Protocol family not supported",
                 EAFNOSUPPORT => "Address family not supported by protocol",
+                EADDRINUSE => "Address already in use",
                 ENOBUFS => "No buffer space available",
                 EISCONN => "Transport endpoint is already connected",
                 ENOTCONN => "Transport endpoint is not connected",
                 ECONNREFUSED => "Connection refused",
+                EALREADY => "Operation already in progress",
+                EINPROGRESS => "Operation now in progress",
+                ECANCELED => "Operation canceled",
                 _ => "Unknown error",
             },
         )
@@ -699,8 +889,13 @@ impl fmt::Display for SysError {
 }
 
 impl From<VMError> for SysError {
-    fn from(_: VMError) -> Self {
-        SysError::EFAULT
+    fn from(err: VMError) -> Self {
+        match err {
+            VMError::InvalidPtr => SysError::EFAULT,
+            VMError::Unmapped => SysError::ENOMEM,
+            VMError::AccessDenied => SysError::EACCES,
+            VMError::NoMem => SysError::ENOMEM,
+        }
     }
 }
 
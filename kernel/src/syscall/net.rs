@@ -6,9 +6,10 @@ use crate::fs::FileLike;
 use crate::memory::MemorySet;
 use crate::net::{
     Endpoint, LinkLevelEndpoint, NetlinkEndpoint, NetlinkSocketState, PacketSocketState,
-    RawSocketState, Socket, TcpSocketState, UdpSocketState,
+    RawSocketState, Socket, TcpSocketState, UdpSocketState, UnixSocketState,
 };
 use alloc::boxed::Box;
+use alloc::vec::Vec;
 use core::cmp::min;
 use core::mem::size_of;
 use smoltcp::wire::*;
@@ -16,13 +17,14 @@ use smoltcp::wire::*;
 impl Syscall<'_> {
     pub fn sys_socket(&mut self, domain: usize, socket_type: usize, protocol: usize) -> SysResult {
         let domain = AddressFamily::from(domain as u16);
+        let socket_type_flags = socket_type;
         let socket_type = SocketType::from(socket_type as u8 & SOCK_TYPE_MASK);
         info!(
             "socket: domain: {:?}, socket_type: {:?}, protocol: {}",
             domain, socket_type, protocol
         );
         let mut proc = self.process();
-        let socket: Box<dyn Socket> = match domain {
+        let mut socket: Box<dyn Socket> = match domain {
             AddressFamily::Internet | AddressFamily::Unix => match socket_type {
                 SocketType::Stream => Box::new(TcpSocketState::new()),
                 SocketType::Datagram => Box::new(UdpSocketState::new()),
@@ -39,10 +41,45 @@ impl Syscall<'_> {
             },
             _ => return Err(SysError::EAFNOSUPPORT),
         };
+        if socket_type_flags & SOCK_NONBLOCK != 0 {
+            socket.set_nonblocking(true);
+        }
         let fd = proc.add_file(FileLike::Socket(socket));
         Ok(fd)
     }
 
+    pub fn sys_socketpair(
+        &mut self,
+        domain: usize,
+        socket_type: usize,
+        protocol: usize,
+        sv: *mut [i32; 2],
+    ) -> SysResult {
+        info!(
+            "socketpair: domain: {}, socket_type: {}, protocol: {}",
+            domain, socket_type, protocol
+        );
+        match AddressFamily::from(domain as u16) {
+            AddressFamily::Unix => {}
+            _ => return Err(SysError::EOPNOTSUPP),
+        }
+        match SocketType::from(socket_type as u8 & SOCK_TYPE_MASK) {
+            SocketType::Stream => {}
+            _ => return Err(SysError::EINVAL),
+        }
+        let mut proc = self.process();
+        let sv = unsafe { self.vm().check_write_ptr(sv)? };
+        let (a, b) = UnixSocketState::pair();
+        let sockets: Vec<Box<dyn Socket>> = vec![Box::new(a), Box::new(b)];
+        for (fd, mut socket) in sv.iter_mut().zip(sockets) {
+            if socket_type & SOCK_NONBLOCK != 0 {
+                socket.set_nonblocking(true);
+            }
+            *fd = proc.add_file(FileLike::Socket(socket)) as i32;
+        }
+        Ok(0)
+    }
+
     pub fn sys_setsockopt(
         &mut self,
         fd: usize,
@@ -73,29 +110,13 @@ impl Syscall<'_> {
             "getsockopt: fd: {}, level: {}, optname: {} optval: {:?} optlen: {:?}",
             fd, level, optname, optval, optlen
         );
+        let mut proc = self.process();
         let optlen = unsafe { self.vm().check_write_ptr(optlen)? };
-        match level {
-            SOL_SOCKET => match optname {
-                SO_SNDBUF => {
-                    let optval = unsafe { self.vm().check_write_ptr(optval as *mut u32)? };
-                    *optval = crate::net::TCP_SENDBUF as u32;
-                    *optlen = 4;
-                    Ok(0)
-                }
-                SO_RCVBUF => {
-                    let optval = unsafe { self.vm().check_write_ptr(optval as *mut u32)? };
-                    *optval = crate::net::TCP_RECVBUF as u32;
-                    *optlen = 4;
-                    Ok(0)
-                }
-                _ => Err(SysError::ENOPROTOOPT),
-            },
-            IPPROTO_TCP => match optname {
-                TCP_CONGESTION => Ok(0),
-                _ => Err(SysError::ENOPROTOOPT),
-            },
-            _ => Err(SysError::ENOPROTOOPT),
-        }
+        let data = unsafe { self.vm().check_write_array(optval, *optlen as usize)? };
+        let socket = proc.get_socket(fd)?;
+        let len = socket.getsockopt(level, optname, data)?;
+        *optlen = len as u32;
+        Ok(0)
     }
 
     pub fn sys_connect(&mut self, fd: usize, addr: *const SockAddr, addr_len: usize) -> SysResult {
@@ -169,6 +190,53 @@ impl Syscall<'_> {
         result
     }
 
+    pub fn sys_sendmsg(&mut self, fd: usize, msg: *const MsgHdr, flags: usize) -> SysResult {
+        info!("sendmsg: fd: {}, msg: {:?}, flags: {}", fd, msg, flags);
+        let mut proc = self.process();
+        let hdr = unsafe { self.vm().check_read_ptr(msg)? };
+        let iovs =
+            unsafe { IoVecs::check_and_new(hdr.msg_iov, hdr.msg_iovlen, &self.vm(), false)? };
+        let buf = iovs.read_all_to_vec();
+        let endpoint = if hdr.msg_name.is_null() {
+            None
+        } else {
+            let endpoint =
+                sockaddr_to_endpoint(&mut self.vm(), hdr.msg_name, hdr.msg_namelen as usize)?;
+            Some(endpoint)
+        };
+
+        // files to pass, as new descriptions of the same open files
+        let mut files = Vec::new();
+        if hdr.msg_controllen != 0 {
+            let control = unsafe {
+                self.vm()
+                    .check_read_array(hdr.msg_control as *const u8, hdr.msg_controllen)?
+            };
+            let mut offset = 0;
+            while offset + size_of::<CmsgHdr>() <= control.len() {
+                let cmsg = unsafe { &*(control[offset..].as_ptr() as *const CmsgHdr) };
+                if cmsg.cmsg_len < size_of::<CmsgHdr>() || offset + cmsg.cmsg_len > control.len() {
+                    return Err(SysError::EINVAL);
+                }
+                if cmsg.cmsg_level == SOL_SOCKET as i32 && cmsg.cmsg_type == SCM_RIGHTS {
+                    let data = &control[offset + size_of::<CmsgHdr>()..offset + cmsg.cmsg_len];
+                    for fd in data.chunks_exact(size_of::<i32>()) {
+                        let fd = i32::from_ne_bytes([fd[0], fd[1], fd[2], fd[3]]);
+                        files.push(proc.get_file_like(fd as usize)?.dup(false));
+                    }
+                }
+                offset += cmsg_align(cmsg.cmsg_len);
+            }
+        }
+
+        let socket = proc.get_socket(fd)?;
+        if files.is_empty() {
+            socket.write(&buf, endpoint)
+        } else {
+            socket.write_rights(&buf, files)
+        }
+    }
+
     pub fn sys_recvmsg(&mut self, fd: usize, msg: *mut MsgHdr, flags: usize) -> SysResult {
         info!("recvmsg: fd: {}, msg: {:?}, flags: {}", fd, msg, flags);
         let mut proc = self.process();
@@ -178,7 +246,7 @@ impl Syscall<'_> {
 
         let mut buf = iovs.new_buf(true);
         let socket = proc.get_socket(fd)?;
-        let (result, endpoint) = socket.read(&mut buf);
+        let (result, endpoint, files) = socket.read_rights(&mut buf);
 
         if let Ok(len) = result {
             // copy data to user
@@ -191,6 +259,34 @@ impl Syscall<'_> {
                     &mut hdr.msg_namelen as *mut u32,
                 )?;
             }
+
+            // install the files passed, as many as fit, the rest are closed
+            hdr.msg_flags = 0;
+            let space = hdr.msg_controllen;
+            let fit = space.saturating_sub(size_of::<CmsgHdr>()) / size_of::<i32>();
+            if fit < files.len() {
+                hdr.msg_flags |= MSG_CTRUNC;
+            }
+            let count = min(fit, files.len());
+            hdr.msg_controllen = 0;
+            if count > 0 {
+                let cmsg_len = size_of::<CmsgHdr>() + count * size_of::<i32>();
+                let mut cmsg = Vec::with_capacity(cmsg_len);
+                cmsg.extend_from_slice(&cmsg_len.to_ne_bytes());
+                cmsg.extend_from_slice(&(SOL_SOCKET as i32).to_ne_bytes());
+                cmsg.extend_from_slice(&SCM_RIGHTS.to_ne_bytes());
+                let cloexec = flags & MSG_CMSG_CLOEXEC != 0;
+                for file in files.into_iter().take(count) {
+                    let fd = proc.add_file(file.dup(cloexec)) as i32;
+                    cmsg.extend_from_slice(&fd.to_ne_bytes());
+                }
+                let control = unsafe {
+                    self.vm()
+                        .check_write_array(hdr.msg_control as *mut u8, cmsg_len)?
+                };
+                control.copy_from_slice(&cmsg);
+                hdr.msg_controllen = min(cmsg_align(cmsg_len), space);
+            }
         }
         result
     }
@@ -221,7 +317,7 @@ impl Syscall<'_> {
         let mut proc = self.process();
 
         let socket = proc.get_socket(fd)?;
-        socket.shutdown()
+        socket.shutdown(how)
     }
 
     pub fn sys_accept(&mut self, fd: usize, addr: *mut SockAddr, addr_len: *mut u32) -> SysResult {
@@ -392,6 +488,10 @@ impl From<Endpoint> for SockAddr {
                     sll_addr: [0; 8],
                 },
             }
+        } else if let Endpoint::Unix = endpoint {
+            SockAddr {
+                family: AddressFamily::Unix.into(),
+            }
         } else if let Endpoint::Netlink(netlink) = endpoint {
             SockAddr {
                 addr_nl: SockAddrNl {
@@ -449,7 +549,8 @@ impl SockAddr {
             AddressFamily::Internet => Ok(size_of::<SockAddrIn>()),
             AddressFamily::Packet => Ok(size_of::<SockAddrLl>()),
             AddressFamily::Netlink => Ok(size_of::<SockAddrNl>()),
-            AddressFamily::Unix => Err(SysError::EINVAL),
+            // unnamed, only the family
+            AddressFamily::Unix => Ok(size_of::<u16>()),
             _ => Err(SysError::EINVAL),
         }
     }
@@ -489,6 +590,20 @@ pub struct MsgHdr {
     msg_flags: usize,
 }
 
+/// Header of a control message in `MsgHdr::msg_control`, followed by its data
+#[repr(C)]
+#[derive(Debug)]
+struct CmsgHdr {
+    cmsg_len: usize,
+    cmsg_level: i32,
+    cmsg_type: i32,
+}
+
+/// Round up to the alignment of control messages
+fn cmsg_align(len: usize) -> usize {
+    (len + size_of::<usize>() - 1) & !(size_of::<usize>() - 1)
+}
+
 enum_with_unknown! {
     /// Address families
     pub doc enum AddressFamily(u16) {
@@ -506,6 +621,7 @@ enum_with_unknown! {
 }
 
 const SOCK_TYPE_MASK: u8 = 0xf;
+const SOCK_NONBLOCK: usize = 0o4000;
 
 enum_with_unknown! {
     /// Socket types
@@ -524,10 +640,25 @@ pub const IPPROTO_ICMP: usize = 1;
 pub const IPPROTO_TCP: usize = 6;
 
 pub const SOL_SOCKET: usize = 1;
+pub const SO_REUSEADDR: usize = 2;
+pub const SO_ERROR: usize = 4;
 pub const SO_SNDBUF: usize = 7;
 pub const SO_RCVBUF: usize = 8;
+pub const SO_KEEPALIVE: usize = 9;
 pub const SO_LINGER: usize = 13;
 
+/// Control message carrying file descriptors
+const SCM_RIGHTS: i32 = 1;
+
+/// The control messages were cut short for lack of room
+const MSG_CTRUNC: usize = 0x8;
+const MSG_CMSG_CLOEXEC: usize = 0x4000_0000;
+
+pub const TCP_NODELAY: usize = 1;
 pub const TCP_CONGESTION: usize = 13;
 
 pub const IP_HDRINCL: usize = 3;
+
+pub const SHUT_RD: usize = 0;
+pub const SHUT_WR: usize = 1;
+pub const SHUT_RDWR: usize = 2;
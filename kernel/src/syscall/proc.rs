@@ -2,10 +2,15 @@
 
 use super::*;
 use crate::arch::timer::timer_now;
-use crate::fs::FileLike;
-use crate::signal::{send_signal, Signal};
+use crate::consts::USER_SPACE_END;
+use crate::fs::{FileLike, ROOT_INODE};
+use crate::memory::{phys_to_virt, PageFaultCause};
+use crate::signal::{
+    send_signal, Siginfo, SiginfoChild, SiginfoFields, Signal, SignalStack, CLD_DUMPED, CLD_EXITED,
+    CLD_KILLED, CLD_STOPPED, CLD_TRAPPED, SIG_IGN,
+};
 use crate::{
-    sync::{wait_for_event, Event, EventBus, SpinNoIrqLock as Mutex},
+    sync::{wait_for_event, wait_for_event_one_unlocked, Event, EventBus, SpinNoIrqLock as Mutex},
     syscall::SysError::{EINTR, ESRCH},
     trap::NAIVE_TIMER,
 };
@@ -14,31 +19,160 @@ use alloc::sync::Weak;
 use core::{
     future::Future,
     pin::Pin,
+    ptr::null_mut,
     task::{Context, Poll},
     time::Duration,
 };
+use rcore_memory::PAGE_SIZE;
+use spin::RwLock;
+
+const P_ALL: usize = 0;
+const P_PID: usize = 1;
+const P_PGID: usize = 2;
+
+const PRIO_PROCESS: usize = 0;
+const PRIO_PGRP: usize = 1;
+const PRIO_USER: usize = 2;
+
+const NICE_MIN: isize = -20;
+const NICE_MAX: isize = 19;
+
+const PR_SET_PDEATHSIG: usize = 1;
+const PR_GET_PDEATHSIG: usize = 2;
+const PR_GET_DUMPABLE: usize = 3;
+const PR_SET_DUMPABLE: usize = 4;
+const PR_GET_SECCOMP: usize = 21;
+const PR_SET_SECCOMP: usize = 22;
+const PR_SET_NO_NEW_PRIVS: usize = 38;
+const PR_GET_NO_NEW_PRIVS: usize = 39;
+
+/// Versions of the capget and capset ABI, taking 1 and 2 `CapUserData`
+const LINUX_CAPABILITY_VERSION_1: u32 = 0x19980330;
+const LINUX_CAPABILITY_VERSION_2: u32 = 0x20071026;
+const LINUX_CAPABILITY_VERSION_3: u32 = 0x20080522;
+
+/// `__user_cap_header_struct`
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct CapUserHeader {
+    version: u32,
+    pid: i32,
+}
+
+/// `__user_cap_data_struct`, 32 capabilities of each set
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CapUserData {
+    effective: u32,
+    permitted: u32,
+    inheritable: u32,
+}
 
 impl Syscall<'_> {
     /// Fork the current process. Return the child's PID.
-    pub fn sys_fork(&mut self) -> SysResult {
-        let new_thread = self.thread.fork(self.context);
-        let pid = new_thread.proc.lock().pid.get();
+    pub async fn sys_fork(&mut self) -> SysResult {
+        self.fork_with_flags(CloneFlags::empty(), 0, None, null_mut(), null_mut())
+            .await
+    }
+
+    /// Fork the current process, as `clone` without `CLONE_THREAD`.
+    /// The child is put into new namespaces as requested by CLONE_NEWPID and CLONE_NEWNS.
+    /// With `CLONE_VM` the address space is shared instead of copied,
+    /// with `CLONE_PARENT` the child becomes a sibling of the caller.
+    /// With `CLONE_VFORK` the caller is suspended until the child execs or exits.
+    /// Return the child's PID seen from the current namespace.
+    async fn fork_with_flags(
+        &mut self,
+        flags: CloneFlags,
+        newsp: usize,
+        tls: Option<usize>,
+        parent_tid: *mut u32,
+        child_tid: *mut u32,
+    ) -> SysResult {
+        let (ns, pid_ns) = {
+            let proc = self.process();
+            if flags.contains(CloneFlags::PARENT) && proc.pid_ns.is_init(proc.pid) {
+                // init has no parent to share
+                return Err(SysError::EINVAL);
+            }
+            let pid_ns = if flags.contains(CloneFlags::NEWPID) {
+                Some(PidNamespace::new_child(&proc.pid_ns_for_children)?)
+            } else {
+                None
+            };
+            (proc.pid_ns.clone(), pid_ns)
+        };
+        // check pointers before the process is created,
+        // `child_tid` is valid in the child too as its memory is a copy of ours
+        let parent_tid_ref = match flags.contains(CloneFlags::PARENT_SETTID) {
+            true => Some(unsafe { self.vm().check_write_ptr(parent_tid)? }),
+            false => None,
+        };
+        if flags.contains(CloneFlags::CHILD_SETTID) {
+            unsafe { self.vm().check_write_ptr(child_tid)? };
+        }
+        let mut context = self.context.clone();
+        if newsp != 0 {
+            context.set_sp(newsp);
+        }
+        if let Some(tls) = tls {
+            context.set_tls(tls);
+        }
+        let new_thread = self.thread.clone_process(
+            &context,
+            flags.contains(CloneFlags::VM),
+            flags.contains(CloneFlags::PARENT),
+            pid_ns,
+        )?;
+        if flags.contains(CloneFlags::NEWNS) {
+            let mut child = new_thread.lock_proc();
+            let mount_ns = child.mount_ns.read().clone();
+            child.mount_ns = Arc::new(RwLock::new(mount_ns));
+        }
+        let (pid, child_ns) = {
+            let child = new_thread.lock_proc();
+            (child.pid, child.pid_ns.clone())
+        };
+        let local_pid = ns.local(pid).unwrap();
+        if let Some(parent_tid_ref) = parent_tid_ref {
+            *parent_tid_ref = local_pid as u32;
+        }
+        if flags.contains(CloneFlags::CHILD_SETTID) {
+            // the child sees its pid in its own namespace
+            let child_pid = child_ns.local(pid).unwrap();
+            if !write_to_vm(&mut new_thread.lock_vm(), child_tid, child_pid as u32) {
+                warn!("clone: failed to set child tid at {:?}", child_tid);
+            }
+        }
+        if flags.contains(CloneFlags::CHILD_CLEARTID) {
+            new_thread.inner.lock().clear_child_tid = child_tid as usize;
+        }
         info!("fork: {} -> {}", self.process().pid, pid);
+        let child_bus = new_thread.lock_proc().eventbus.clone();
         spawn(new_thread);
-        Ok(pid)
+        if flags.contains(CloneFlags::VFORK) {
+            // until then the child runs in our memory, likely on our stack
+            wait_for_event(child_bus, Event::PROCESS_EXEC | Event::PROCESS_QUIT).await;
+        }
+        Ok(local_pid)
     }
 
     #[cfg(target_arch = "x86_64")]
-    pub fn sys_vfork(&mut self) -> SysResult {
-        self.sys_fork()
+    pub async fn sys_vfork(&mut self) -> SysResult {
+        let flags = CloneFlags::VM | CloneFlags::VFORK;
+        self.fork_with_flags(flags, 0, None, null_mut(), null_mut())
+            .await
     }
 
-    /// Create a new thread in the current process.
-    /// The new thread's stack pointer will be set to `newsp`,
-    /// and thread pointer will be set to `newtls`.
-    /// The child tid will be stored at both `parent_tid` and `child_tid`.
-    /// This is partially implemented for musl only.
-    pub fn sys_clone(
+    /// Create a new thread or process.
+    /// With `CLONE_THREAD`, a new thread is created in the current process,
+    /// otherwise a new process is created, see `fork_with_flags`.
+    /// Its stack pointer will be set to `newsp`,
+    /// and thread pointer will be set to `newtls` with `CLONE_SETTLS`.
+    /// The child tid will be stored at `parent_tid` with `CLONE_PARENT_SETTID`,
+    /// and at `child_tid` in the child's memory with `CLONE_CHILD_SETTID`.
+    /// With `CLONE_CHILD_CLEARTID`, `child_tid` is cleared and woken when the child exits.
+    pub async fn sys_clone(
         &mut self,
         flags: usize,
         newsp: usize,
@@ -51,138 +185,265 @@ impl Syscall<'_> {
             "clone: flags: {:?} == {:#x}, newsp: {:#x}, parent_tid: {:?}, child_tid: {:?}, newtls: {:#x}",
             clone_flags, flags, newsp, parent_tid, child_tid, newtls
         );
-        if flags == 0x4111 || flags == 0x11 {
-            warn!("sys_clone is calling sys_fork instead, ignoring other args");
-            return self.sys_fork();
-        }
-        if (flags != 0x7d0f00) && (flags != 0x5d0f00) {
-            // 0x5d0f00 is the args from gcc of alpine linux
-            warn!(
-                "sys_clone only support sys_fork or musl pthread_create without flags{:x}",
-                flags
-            );
-            return Err(SysError::ENOSYS);
-        }
-        let parent_tid_ref = unsafe { self.vm().check_write_ptr(parent_tid)? };
-        // child_tid buffer should not be set because CLONE_CHILD_SETTID flag is not specified in the current implementation
-        let child_tid_ref = unsafe { self.vm().check_write_ptr(child_tid)? };
-        let mut new_thread = self
-            .thread
-            .new_clone(self.context, newsp, newtls, child_tid as usize);
-        if clone_flags.contains(CloneFlags::CHILD_CLEARTID) {
-            new_thread.inner.lock().clear_child_tid = child_tid as usize;
+        // threads share signal handlers, which are only meaningful in a shared address space
+        if clone_flags.contains(CloneFlags::THREAD) && !clone_flags.contains(CloneFlags::SIGHAND)
+            || clone_flags.contains(CloneFlags::SIGHAND) && !clone_flags.contains(CloneFlags::VM)
+        {
+            return Err(SysError::EINVAL);
+        }
+        let namespaces = clone_flags & (CloneFlags::NEWPID | CloneFlags::NEWNS);
+        if !namespaces.is_empty() && clone_flags.contains(CloneFlags::THREAD) {
+            return Err(SysError::EINVAL);
+        }
+        if !namespaces.is_empty() && !capable(Capability::SysAdmin) {
+            return Err(SysError::EPERM);
+        }
+        // otherwise the child keeps the thread pointer of the parent
+        let tls = match clone_flags.contains(CloneFlags::SETTLS) {
+            true if newtls >= USER_SPACE_END => return Err(SysError::EPERM),
+            true => Some(newtls),
+            false => None,
+        };
+        if !clone_flags.contains(CloneFlags::THREAD) {
+            // a process has its own file table, fs info and signal handlers
+            let shared = CloneFlags::FILES | CloneFlags::FS | CloneFlags::SIGHAND;
+            if clone_flags.intersects(shared) {
+                return self.unimplemented("clone sharing with a process", Err(SysError::EINVAL));
+            }
+            return self
+                .fork_with_flags(clone_flags, newsp, tls, parent_tid, child_tid)
+                .await;
         }
+        // check pointers before the thread is created
+        let parent_tid_ref = match clone_flags.contains(CloneFlags::PARENT_SETTID) {
+            true => Some(unsafe { self.vm().check_write_ptr(parent_tid)? }),
+            false => None,
+        };
+        // the address space is shared, so the child's memory is ours
+        let child_tid_ref = match clone_flags.contains(CloneFlags::CHILD_SETTID) {
+            true => Some(unsafe { self.vm().check_write_ptr(child_tid)? }),
+            false => None,
+        };
+        let clear_child_tid = match clone_flags.contains(CloneFlags::CHILD_CLEARTID) {
+            true => child_tid as usize,
+            false => 0,
+        };
+        let new_thread = self
+            .thread
+            .new_clone(self.context, newsp, tls, clear_child_tid);
         let tid: usize = new_thread.tid;
         info!("clone: {} -> {}", self.thread.tid, tid);
-        *parent_tid_ref = tid as u32;
-        *child_tid_ref = tid as u32;
+        if let Some(parent_tid_ref) = parent_tid_ref {
+            *parent_tid_ref = tid as u32;
+        }
+        if let Some(child_tid_ref) = child_tid_ref {
+            *child_tid_ref = tid as u32;
+        }
         spawn(new_thread);
         Ok(tid)
     }
 
+    /// Disassociate parts of the process execution context.
+    /// Only CLONE_NEWPID and CLONE_NEWNS are supported:
+    /// with CLONE_NEWPID, children created afterwards are put into
+    /// a new PID namespace, the first one being its init;
+    /// with CLONE_NEWNS, the process gets a private copy of its mount table.
+    /// Both need CAP_SYS_ADMIN.
+    pub fn sys_unshare(&mut self, flags: usize) -> SysResult {
+        let clone_flags = CloneFlags::from_bits_truncate(flags);
+        info!("unshare: flags: {:?}", clone_flags);
+        if !(CloneFlags::NEWPID | CloneFlags::NEWNS).contains(clone_flags) {
+            return self.unimplemented("unshare", Err(SysError::EINVAL));
+        }
+        if !clone_flags.is_empty() && !capable(Capability::SysAdmin) {
+            return Err(SysError::EPERM);
+        }
+        let mut proc = self.process();
+        if clone_flags.contains(CloneFlags::NEWPID) {
+            proc.pid_ns_for_children = PidNamespace::new_child(&proc.pid_ns)?;
+        }
+        if clone_flags.contains(CloneFlags::NEWNS) {
+            let mount_ns = proc.mount_ns.read().clone();
+            proc.mount_ns = Arc::new(RwLock::new(mount_ns));
+        }
+        Ok(0)
+    }
+
     /// Wait for the process exit.
     /// Return the PID. Store exit code to `wstatus` if it's not null.
-    pub async fn sys_wait4(&mut self, pid: isize, wstatus: UserInOutPtr<i32>) -> SysResult {
-        info!("wait4: pid: {}, code: {:?}", pid, wstatus);
-        let wstatus = if !wstatus.is_null() {
-            Some(wstatus)
-        } else {
-            None
-        };
-        #[derive(Debug)]
-        enum WaitFor {
-            AnyChild,
-            AnyChildInGroup,
-            Pid(usize),
-        }
+    ///
+    /// `pid` selects the children: -1 for any child, 0 for those in our process group,
+    /// less than -1 for those in process group `-pid`.
+    pub async fn sys_wait4(
+        &mut self,
+        pid: isize,
+        mut wstatus: UserInOutPtr<i32>,
+        options: usize,
+    ) -> SysResult {
+        let options = WaitOptions::from_bits_truncate(options);
+        info!(
+            "wait4: pid: {}, code: {:?}, options: {:?}",
+            pid, wstatus, options
+        );
         let target = match pid {
             -1 => WaitFor::AnyChild,
-            0 => WaitFor::AnyChildInGroup,
-            p if p > 0 => WaitFor::Pid(p as usize),
-            _ => unimplemented!(),
+            0 => WaitFor::Group(self.process().pgid),
+            p if p > 0 => {
+                // translate to global pid
+                let ns = self.process().pid_ns.clone();
+                WaitFor::Pid(ns.global(p as usize).ok_or(SysError::ECHILD)?)
+            }
+            p => WaitFor::Group(p.wrapping_neg() as Pgid),
+        };
+        let options = options
+            & (WaitOptions::NOHANG | WaitOptions::UNTRACED | WaitOptions::CONTINUED)
+            | WaitOptions::EXITED;
+        let pid = self
+            .wait_child(target, options, |state| {
+                if !wstatus.is_null() {
+                    wstatus.write(state.wstatus)?;
+                }
+                Ok(())
+            })
+            .await?;
+        Ok(pid)
+    }
+
+    pub async fn sys_waitid(
+        &mut self,
+        idtype: usize,
+        id: usize,
+        mut infop: UserOutPtr<Siginfo>,
+        options: usize,
+    ) -> SysResult {
+        let options = WaitOptions::from_bits_truncate(options);
+        info!(
+            "waitid: idtype: {}, id: {}, infop: {:?}, options: {:?}",
+            idtype, id, infop, options
+        );
+        if !options.intersects(WaitOptions::EXITED | WaitOptions::UNTRACED | WaitOptions::CONTINUED)
+        {
+            return Err(SysError::EINVAL);
+        }
+        let target = match idtype {
+            P_ALL => WaitFor::AnyChild,
+            P_PID => {
+                let ns = self.process().pid_ns.clone();
+                WaitFor::Pid(ns.global(id).ok_or(SysError::ECHILD)?)
+            }
+            P_PGID if id == 0 => WaitFor::Group(self.process().pgid),
+            P_PGID => WaitFor::Group(id as Pgid),
+            _ => return Err(SysError::EINVAL),
         };
+        let pid = self
+            .wait_child(target, options, |state| {
+                if infop.is_null() {
+                    return Ok(());
+                }
+                let mut field = SiginfoFields::default();
+                field.chld = SiginfoChild {
+                    pid: state.pid as i32,
+                    uid: 0,
+                    status: state.status,
+                    utime: 0,
+                    stime: 0,
+                };
+                infop.write(Siginfo {
+                    signo: Signal::SIGCHLD as i32,
+                    errno: 0,
+                    code: state.code,
+                    field,
+                })
+            })
+            .await?;
+        if pid == 0 && !infop.is_null() {
+            // no child has changed state with WNOHANG
+            infop.write(Siginfo {
+                signo: 0,
+                errno: 0,
+                code: 0,
+                field: SiginfoFields::default(),
+            })?;
+        }
+        Ok(0)
+    }
+
+    /// Wait for a state change of a child in `target` as wait4 and waitid do,
+    /// and `report` it while the child can not be reaped by another waiter.
+    /// Return its pid, or 0 if no child has changed state with `WNOHANG`.
+    async fn wait_child(
+        &mut self,
+        target: WaitFor,
+        options: WaitOptions,
+        mut report: impl FnMut(&ChildState) -> Result<(), SysError>,
+    ) -> SysResult {
         loop {
-            info!("wait4 loop: pid: {}, code: {:?}", pid, wstatus);
+            info!("wait loop: {:?}, options: {:?}", target, options);
             let mut proc = self.process();
 
             // check child state
-            let find = match target {
-                WaitFor::AnyChild | WaitFor::AnyChildInGroup => {
-                    let mut res = None;
-                    for (pid, child) in &proc.children {
-                        if let Some(c) = child.upgrade() {
-                            let p = c.lock();
-                            if p.exited() {
-                                res = Some((p.pid, p.exit_code));
-                                break;
-                            }
-                        } else {
-                            info!("wait: pid {} is missing", pid);
+            let mut selected = false;
+            let mut found = None;
+            for (pid, child) in &proc.children {
+                if let Some(c) = child.upgrade() {
+                    // a parent is locked before its children
+                    let mut p = lock_process(&c);
+                    if target.selects(pid.get(), p.pgid) {
+                        selected = true;
+                        found = child_state(&mut p, options).map(|state| (state, p.pgid));
+                        if found.is_some() {
+                            break;
                         }
                     }
-                    res
-                }
-                WaitFor::Pid(pid) => {
-                    let mut res = None;
-                    if let Some(c) = process(pid) {
-                        let p = c.lock();
-                        if p.exited() {
-                            res = Some((p.pid, p.exit_code));
-                        }
-                    }
-                    res
+                } else {
+                    info!("wait: pid {} is missing", pid);
                 }
-            };
+            }
             // if found, return
-            if let Some((pid, exit_code)) = find {
+            if let Some(((pid, code, status, wstatus), pgid)) = found {
                 info!("wait: found pid {}", pid);
 
-                // write before removing to handle EFAULT
-                if let Some(mut wstatus) = wstatus {
-                    wstatus.write(exit_code as i32)?;
-                }
-
-                // remove from process table
-                if true {
-                    let mut process_table = PROCESSES.write();
-                    process_table.remove(&pid.get());
+                // children are always visible from our namespace
+                let state = ChildState {
+                    pid: proc.pid_ns.local(pid).unwrap_or(0),
+                    code,
+                    status,
+                    wstatus,
+                };
+                // report before removing to handle EFAULT,
+                // and leave the child to another waiter then
+                if let Err(err) = report(&state) {
+                    proc.wake_child_waiter(pid, pgid, code);
+                    return Err(err);
                 }
 
-                // remove from children
-                proc.children.retain(|(p, _)| *p != pid);
+                // a stopped or continued child is not reaped, nor one waited with WNOWAIT
+                let exited = code == CLD_EXITED || code == CLD_KILLED || code == CLD_DUMPED;
+                if exited && !options.contains(WaitOptions::NOWAIT) {
+                    // remove from process table
+                    remove_from_process_table(pid);
 
-                return Ok(pid.get());
+                    // remove from children
+                    proc.children.retain(|(p, _)| *p != pid);
+                }
+                return Ok(state.pid);
             }
             // if not, check pid
-            let invalid = {
-                let children = proc
-                    .children
-                    .iter()
-                    .filter_map(|(pid, weak)| {
-                        if weak.upgrade().is_none() {
-                            None
-                        } else {
-                            Some(pid)
-                        }
-                    })
-                    .collect::<Vec<_>>();
-                match target {
-                    WaitFor::AnyChild | WaitFor::AnyChildInGroup => children.len() == 0,
-                    WaitFor::Pid(pid) => children.iter().find(|p| p.get() == pid).is_none(),
-                }
-            };
-            if invalid {
+            if !selected {
                 info!("wait: no valid child proc");
                 return Err(SysError::ECHILD);
             }
 
+            if options.contains(WaitOptions::NOHANG) {
+                return Ok(0);
+            }
+
             info!("wait: thread {} -> {:?}, sleep", self.thread.tid, target);
 
+            // a child can be reaped by one waiter only, so waiters selecting it take turns
             let eventbus = proc.eventbus.clone();
-            drop(proc);
-
-            wait_for_event(eventbus.clone(), Event::CHILD_PROCESS_QUIT).await;
-            eventbus.lock().clear(Event::CHILD_PROCESS_QUIT);
+            let key = target.key(options);
+            wait_for_event_one_unlocked(proc, eventbus, Event::CHILD_PROCESS_QUIT, key).await;
         }
     }
 
@@ -204,7 +465,26 @@ impl Syscall<'_> {
         argv: *const *const u8,
         envp: *const *const u8,
     ) -> SysResult {
-        info!("exec: path: {:?}, argv: {:?}, envp: {:?}", path, argv, envp);
+        self.sys_execveat(AT_FDCWD, path, argv, envp, 0)
+    }
+
+    /// Like `sys_exec`, but a relative `path` is looked up in `dirfd`.
+    ///
+    /// With `AT_EMPTY_PATH` and an empty `path`, the file of `dirfd` itself is executed,
+    /// as `fexecve` does.
+    pub fn sys_execveat(
+        &mut self,
+        dirfd: usize,
+        path: *const u8,
+        argv: *const *const u8,
+        envp: *const *const u8,
+        flags: usize,
+    ) -> SysResult {
+        info!(
+            "execveat: dirfd: {}, path: {:?}, argv: {:?}, envp: {:?}, flags: {:#x}",
+            dirfd as isize, path, argv, envp, flags
+        );
+        let flags = AtFlags::from_bits(flags).ok_or(SysError::EINVAL)?;
         let mut proc = self.process();
         let path = check_and_clone_cstr(path)?;
         let args = check_and_clone_cstr_array(argv)?;
@@ -218,17 +498,74 @@ impl Syscall<'_> {
         info!("exec: path: {:?}, args: {:?}, envs: {:?}", path, args, envs);
 
         // Read program file
-        let inode = proc.lookup_inode(&path)?;
+        let (inode, path) = if path.is_empty() {
+            if !flags.contains(AtFlags::EMPTY_PATH) {
+                return Err(SysError::ENOENT);
+            }
+            let file = match proc.files.get(&dirfd).ok_or(SysError::EBADF)? {
+                FileLike::File(file) => file,
+                _ => return Err(SysError::EACCES),
+            };
+            (file.inode(), String::from(file.path()))
+        } else {
+            let follow = !flags.contains(AtFlags::SYMLINK_NOFOLLOW);
+            let inode = proc.lookup_inode_at(dirfd, &path, follow)?;
+            let path = if path.starts_with('/') || dirfd == AT_FDCWD {
+                path
+            } else {
+                format!("{}/{}", proc.get_file_const(dirfd)?.path(), path)
+            };
+            (inode, path)
+        };
+        match inode.metadata()?.type_ {
+            FileType::File => {}
+            // the last component was not followed
+            FileType::SymLink => return Err(SysError::ELOOP),
+            _ => return Err(SysError::EACCES),
+        }
 
-        // Make new Thread
-        // Re-create vm
-        let mut vm = self.vm();
-        let (entry_addr, ustack_top) =
-            Thread::new_user_vm(&inode, args, envs, &mut vm).map_err(|_| SysError::EINVAL)?;
+        // Make a new memory set rather than clearing the current one,
+        // which other processes may share by CLONE_VM and killed threads may still run in
+        let mut vm = MemorySet::new();
+        let mounts = proc.mount_ns.read().clone();
+        let root = match &proc.root {
+            Some((_, root)) => root.clone(),
+            None => ROOT_INODE.clone(),
+        };
+        let (entry_addr, ustack_top) = Thread::new_user_vm(
+            &inode,
+            args,
+            envs,
+            &mut vm,
+            proc.personality,
+            &root,
+            &mounts,
+        )
+        .map_err(|err| {
+            info!("exec: {}", err);
+            match err {
+                "interpreter not found" => SysError::ENOENT,
+                "failed to read from INode" => SysError::EIO,
+                _ => SysError::ENOEXEC,
+            }
+        })?;
 
         // Kill other threads
         // TODO: stop and wait until they are finished
+        let killed = proc
+            .threads
+            .iter()
+            .cloned()
+            .filter(|&tid| tid != self.thread.tid)
+            .collect::<Vec<_>>();
         proc.threads.retain(|&tid| tid == self.thread.tid);
+        // futexes in the old memory set are released as if their owners exited
+        for tid in killed.iter().chain(Some(&self.thread.tid)) {
+            let thread = THREADS.read().get(tid).cloned();
+            if let Some(thread) = thread {
+                proc.release_futexes(&thread);
+            }
+        }
 
         // close file that FD_CLOEXEC is set
         let close_fds = proc
@@ -254,16 +591,52 @@ impl Syscall<'_> {
         unsafe {
             vm.activate();
         }
-        drop(vm);
+        let vm_token = vm.token();
+        let vm = Arc::new(Mutex::new(vm));
+        self.thread.set_vm(vm.clone(), vm_token);
+        // the old one is released after switching away from it
+        proc.vm = vm;
 
         // Modify exec path
         proc.exec_path = path.clone();
 
-        // reset disposition (man signal(7))
+        // a vfork parent may run again, as we are out of its memory
+        proc.eventbus.lock().set(Event::PROCESS_EXEC);
+
+        // their rings are gone with the old memory set, and so are the buffers of their iocbs
+        for context in proc.aio_contexts.values() {
+            context.cancel();
+        }
+        proc.aio_contexts.clear();
+
+        // there are no setuid binaries, so the new image is always dumpable,
+        // and pdeathsig is kept as it is only cleared when credentials change
+        proc.dumpable = true;
+
+        // handlers are gone with the old memory set, but ignored signals stay ignored
+        // (man signal(7)), losing their flags and mask. The signal mask is preserved.
         for d in proc.dispositions.iter_mut() {
+            let ignored = d.handler == SIG_IGN;
             *d = SignalAction::default();
+            if ignored {
+                d.handler = SIG_IGN;
+            }
         }
         drop(proc);
+        {
+            let mut inner = self.thread.inner.lock();
+            // the alternate signal stack was in the old memory set too, see sigaltstack(2)
+            inner.signal_alternate_stack = SignalStack::default();
+            // as root, the new image gets all capabilities back, see capabilities(7)
+            let inheritable = inner.caps.inheritable;
+            inner.caps = CapSets {
+                inheritable,
+                ..CapSets::for_euid(EUID)
+            };
+        }
+        for tid in killed {
+            thread_exited(&self.thread.proc, tid);
+        }
 
         // Modify the TrapFrame
         self.context.set_ip(entry_addr);
@@ -281,55 +654,52 @@ impl Syscall<'_> {
     /// Get the current process id
     pub fn sys_getpid(&mut self) -> SysResult {
         info!("getpid");
-        Ok(self.process().pid.get())
+        let proc = self.process();
+        Ok(proc.pid_ns.local(proc.pid).unwrap())
     }
 
     pub fn sys_getpgid(&self, mut pid: usize) -> SysResult {
         if pid == 0 {
             pid = self.process().pid.get();
+        } else {
+            pid = self.process().pid_ns.global(pid).ok_or(ESRCH)?;
         }
         info!("getpgid: get pgid of process {}", pid);
 
-        let process_table = PROCESSES.read();
-        let proc = process_table.get(&pid);
-        if let Some(proc) = proc {
-            let proc = proc.lock();
-            Ok(proc.pgid as usize)
-        } else {
-            Err(ESRCH)
-        }
+        let proc = process(pid).ok_or(ESRCH)?;
+        let pgid = lock_process(&proc).pgid;
+        Ok(pgid as usize)
     }
 
     pub fn sys_setpgid(&self, mut pid: usize, pgid: usize) -> SysResult {
         if pid == 0 {
             pid = self.process().pid.get();
+        } else {
+            pid = self.process().pid_ns.global(pid).ok_or(ESRCH)?;
         }
         info!("setpgid: set pgid of process {} to {}", pid, pgid);
 
-        let process_table = PROCESSES.read();
-        let proc = process_table.get(&pid);
-        if let Some(proc) = proc {
-            // TODO: check process pid is the child of calling process
-            let mut proc = proc.lock();
-            proc.pgid = pgid as Pgid;
-            Ok(0)
-        } else {
-            Err(ESRCH)
-        }
+        // TODO: check process pid is the child of calling process
+        let proc = process(pid).ok_or(ESRCH)?;
+        lock_process(&proc).pgid = pgid as Pgid;
+        Ok(0)
     }
 
     /// Get the current thread id
     pub fn sys_gettid(&mut self) -> SysResult {
         info!("gettid");
-        Ok(self.thread.tid)
+        let tid = self.thread.tid;
+        Ok(self.process().pid_ns.local(Pid(tid)).unwrap())
     }
 
     /// Get the parent process id
     pub fn sys_getppid(&mut self) -> SysResult {
         info!("getppid");
-        let (pid, parent) = self.process().parent.clone();
+        let proc = self.process();
+        let (pid, parent) = proc.parent.clone();
         if parent.upgrade().is_some() {
-            Ok(pid.get())
+            // 0 for the init of a namespace, whose parent is outside
+            Ok(proc.pid_ns.local(pid).unwrap_or(0))
         } else {
             Ok(0)
         }
@@ -341,10 +711,15 @@ impl Syscall<'_> {
         info!("exit: {}, code: {}", tid, exit_code);
 
         let mut proc = self.process();
+        proc.release_futexes(&self.thread);
         proc.threads.retain(|&id| id != tid);
+        if tid != proc.pid.get() {
+            proc.pid_ns.free(Pid(tid));
+        }
 
         // for last thread, exit the process
-        if proc.threads.len() == 0 {
+        let exited = proc.threads.len() == 0;
+        if exited {
             proc.exit(exit_code);
         }
 
@@ -363,6 +738,14 @@ impl Syscall<'_> {
         }
 
         drop(proc);
+        // the process only removes its remaining threads from the table, so this one goes here.
+        // It stays there until its PI futexes are handed over, and leaves out of the process lock.
+        THREADS.write().remove(&tid);
+        if exited {
+            process_exited(&self.thread.proc);
+        } else {
+            thread_exited(&self.thread.proc, tid);
+        }
         self.exit = true;
         Ok(0)
     }
@@ -374,6 +757,7 @@ impl Syscall<'_> {
 
         proc.exit(exit_code);
         drop(proc);
+        process_exited(&self.thread.proc);
         // TODO: quit other threads
         self.exit = true;
         Ok(0)
@@ -391,14 +775,249 @@ impl Syscall<'_> {
         Ok(0)
     }
 
-    pub fn sys_set_priority(&mut self, priority: usize) -> SysResult {
+    /// Get the highest priority among the processes selected by `which` and `who`,
+    /// as `20 - nice` so that it is positive
+    pub fn sys_getpriority(&mut self, which: usize, who: usize) -> SysResult {
+        info!("getpriority: which: {}, who: {}", which, who);
+        let nice = self
+            .priority_targets(which, who)?
+            .iter()
+            .map(|proc| lock_process(proc).nice)
+            .min()
+            .ok_or(ESRCH)?;
+        Ok((20 - nice as isize) as usize)
+    }
+
+    /// Set the nice value of the processes selected by `which` and `who`
+    pub fn sys_setpriority(&mut self, which: usize, who: usize, prio: usize) -> SysResult {
+        let nice = (prio as i32 as isize).max(NICE_MIN).min(NICE_MAX) as i8;
+        info!(
+            "setpriority: which: {}, who: {}, nice: {}",
+            which, who, nice
+        );
+        let targets = self.priority_targets(which, who)?;
+        if targets.is_empty() {
+            return Err(ESRCH);
+        }
+        // all processes run as the same user, so any may be lowered,
+        // but raising one needs CAP_SYS_NICE
+        let mut res = Ok(0);
+        for proc in targets {
+            let mut proc = lock_process(&proc);
+            if nice < proc.nice && !capable(Capability::SysNice) {
+                res = Err(SysError::EACCES);
+                continue;
+            }
+            proc.nice = nice;
+        }
+        res
+    }
+
+    /// Live processes visible from our pid namespace selected by getpriority and setpriority:
+    /// the process `who`, the processes of group `who` or of user `who`,
+    /// where 0 is that of the caller.
+    fn priority_targets(
+        &mut self,
+        which: usize,
+        who: usize,
+    ) -> Result<Vec<Arc<Mutex<Process>>>, SysError> {
+        let (ns, pid, pgid) = {
+            let proc = self.process();
+            (proc.pid_ns.clone(), proc.pid.get(), proc.pgid)
+        };
+        let visible = |proc: &Arc<Mutex<Process>>| lock_process(proc).visible_in(&ns);
+        let who = who as u32 as usize;
+        let targets = match which {
+            PRIO_PROCESS => {
+                let pid = match who {
+                    0 => Some(pid),
+                    _ => ns.global(who),
+                };
+                pid.and_then(Process::find).into_iter().collect()
+            }
+            PRIO_PGRP => {
+                let pgid = match who {
+                    0 => pgid,
+                    _ => who as Pgid,
+                };
+                process_group(pgid).into_iter().filter(visible).collect()
+            }
+            // everyone is root
+            PRIO_USER if who == 0 => Process::iter().filter(visible).collect(),
+            PRIO_USER => Vec::new(),
+            _ => return Err(SysError::EINVAL),
+        };
+        Ok(targets)
+    }
+
+    pub fn sys_prctl(&mut self, option: usize, arg2: usize, arg3: usize) -> SysResult {
+        info!(
+            "prctl: option: {}, arg2: {:#x}, arg3: {:#x}",
+            option, arg2, arg3
+        );
+        match option {
+            PR_SET_PDEATHSIG => {
+                let signal = match arg2 {
+                    0 => None,
+                    _ => Some(<Signal as FromPrimitive>::from_usize(arg2).ok_or(SysError::EINVAL)?),
+                };
+                self.process().pdeathsig = signal;
+                Ok(0)
+            }
+            PR_GET_PDEATHSIG => {
+                let signal = self.process().pdeathsig.map_or(0, |signal| signal as i32);
+                UserOutPtr::<i32>::from(arg2).write(signal)?;
+                Ok(0)
+            }
+            PR_GET_DUMPABLE => Ok(self.process().dumpable as usize),
+            PR_SET_DUMPABLE => {
+                // SUID_DUMP_ROOT can not be set by prctl
+                if arg2 > 1 {
+                    return Err(SysError::EINVAL);
+                }
+                self.process().dumpable = arg2 == 1;
+                Ok(0)
+            }
+            PR_SET_NO_NEW_PRIVS => {
+                if arg2 != 1 {
+                    return Err(SysError::EINVAL);
+                }
+                self.process().no_new_privs = true;
+                Ok(0)
+            }
+            PR_GET_NO_NEW_PRIVS => Ok(self.process().no_new_privs as usize),
+            PR_GET_SECCOMP => Ok(self.thread.inner.lock().seccomp.mode()),
+            // SECCOMP_MODE_STRICT and SECCOMP_MODE_FILTER are one more than the seccomp(2) ops
+            PR_SET_SECCOMP if arg2 > 0 => self.sys_seccomp(arg2 - 1, 0, arg3),
+            PR_SET_SECCOMP => Err(SysError::EINVAL),
+            _ => self.unimplemented("prctl", Err(SysError::EINVAL)),
+        }
+    }
+
+    /// Get the capability sets of thread `hdr.pid`, the current one if 0
+    pub fn sys_capget(
+        &mut self,
+        mut hdr: UserInOutPtr<CapUserHeader>,
+        mut data: UserOutPtr<CapUserData>,
+    ) -> SysResult {
+        let header = hdr.read()?;
+        info!("capget: hdr: {:?}, data: {:?}", header, data);
+        // a null `data` only probes the version
+        let count = match self.cap_data_count(&mut hdr, header) {
+            Err(SysError::EINVAL) if data.is_null() => return Ok(0),
+            count => count?,
+        };
+        if data.is_null() {
+            return Ok(0);
+        }
+        let thread = match header.pid {
+            0 => self.thread.clone(),
+            pid if pid < 0 => return Err(SysError::EINVAL),
+            pid => {
+                let tid = self.process().pid_ns.global(pid as usize);
+                let thread = tid.and_then(|tid| THREADS.read().get(&tid).cloned());
+                thread.ok_or(SysError::ESRCH)?
+            }
+        };
+        let caps = thread.inner.lock().caps;
+        let words: Vec<_> = (0..count)
+            .map(|i| CapUserData {
+                effective: (caps.effective >> (32 * i)) as u32,
+                permitted: (caps.permitted >> (32 * i)) as u32,
+                inheritable: (caps.inheritable >> (32 * i)) as u32,
+            })
+            .collect();
+        data.write_array(&words)?;
+        Ok(0)
+    }
+
+    /// Set the capability sets of the current thread.
+    /// No capability may be gained, and the effective ones must be permitted.
+    pub fn sys_capset(
+        &mut self,
+        mut hdr: UserInOutPtr<CapUserHeader>,
+        data: UserInPtr<CapUserData>,
+    ) -> SysResult {
+        let header = hdr.read()?;
+        info!("capset: hdr: {:?}, data: {:?}", header, data);
+        let count = self.cap_data_count(&mut hdr, header)?;
+        if header.pid != 0 && header.pid as usize != self.thread.tid {
+            return Err(SysError::EPERM);
+        }
+        let mut new = CapSets::default();
+        for (i, word) in data.read_array(count)?.iter().enumerate() {
+            new.effective |= (word.effective as u64) << (32 * i);
+            new.permitted |= (word.permitted as u64) << (32 * i);
+            new.inheritable |= (word.inheritable as u64) << (32 * i);
+        }
+        let mut inner = self.thread.inner.lock();
+        let old = inner.caps;
+        if new.permitted & !old.permitted != 0
+            || new.effective & !new.permitted != 0
+            || new.inheritable & !(old.inheritable | old.permitted) != 0
+        {
+            return Err(SysError::EPERM);
+        }
+        inner.caps = new;
         Ok(0)
     }
 
+    /// Number of `CapUserData` of the version in `header`.
+    /// An unknown version is an error, and is replaced by the preferred one.
+    fn cap_data_count(
+        &self,
+        hdr: &mut UserInOutPtr<CapUserHeader>,
+        header: CapUserHeader,
+    ) -> Result<usize, SysError> {
+        match header.version {
+            LINUX_CAPABILITY_VERSION_1 => Ok(1),
+            LINUX_CAPABILITY_VERSION_2 | LINUX_CAPABILITY_VERSION_3 => Ok(2),
+            _ => {
+                hdr.write(CapUserHeader {
+                    version: LINUX_CAPABILITY_VERSION_3,
+                    ..header
+                })?;
+                Err(SysError::EINVAL)
+            }
+        }
+    }
+
     pub fn sys_set_tid_address(&mut self, tidptr: *mut u32) -> SysResult {
         info!("set_tid_address: {:?}", tidptr);
         self.thread.inner.lock().clear_child_tid = tidptr as usize;
-        Ok(self.thread.tid)
+        self.sys_gettid()
+    }
+
+    /// Set the list of robust futexes of the current thread,
+    /// which are marked `FUTEX_OWNER_DIED` when it exits holding them
+    pub fn sys_set_robust_list(&mut self, head: usize, len: usize) -> SysResult {
+        info!("set_robust_list: head: {:#x}, len: {}", head, len);
+        if len != ROBUST_LIST_HEAD_LEN {
+            return Err(SysError::EINVAL);
+        }
+        self.thread.inner.lock().robust_list = head;
+        Ok(0)
+    }
+
+    /// Get the list of robust futexes of thread `pid`, the current one if 0
+    pub fn sys_get_robust_list(
+        &mut self,
+        pid: usize,
+        head: UserOutPtr<usize>,
+        len: UserOutPtr<usize>,
+    ) -> SysResult {
+        info!("get_robust_list: pid: {}", pid);
+        let thread = if pid == 0 {
+            self.thread.clone()
+        } else {
+            let tid = self.process().pid_ns.global(pid).ok_or(SysError::ESRCH)?;
+            let thread = THREADS.read().get(&tid).cloned();
+            thread.ok_or(SysError::ESRCH)?
+        };
+        let robust_list = thread.inner.lock().robust_list;
+        head.write(robust_list)?;
+        len.write(ROBUST_LIST_HEAD_LEN)?;
+        Ok(0)
     }
 
     // sleeping
@@ -407,7 +1026,7 @@ impl Syscall<'_> {
             deadline: timer_now() + duration,
             duration,
             thread: self.thread.clone(),
-            eventbus: self.thread.proc.lock().eventbus.clone(),
+            eventbus: self.thread.lock_proc().eventbus.clone(),
         }
     }
 }
@@ -451,6 +1070,67 @@ impl Future for SleepFuture {
     }
 }
 
+bitflags! {
+    /// Options of wait4 and waitid
+    pub struct WaitOptions: usize {
+        /// Return at once if no child has changed state
+        const NOHANG = 1;
+        /// Report stopped children, `WSTOPPED` of waitid
+        const UNTRACED = 2;
+        /// Report exited children, always set for wait4
+        const EXITED = 4;
+        /// Report continued children
+        const CONTINUED = 8;
+        /// Leave the child waitable, waitid only
+        const NOWAIT = 0x0100_0000;
+    }
+}
+
+/// A state change of a child found by `wait_child`
+struct ChildState {
+    /// pid seen from the waiter
+    pid: usize,
+    /// One of `CLD_*`
+    code: i32,
+    /// Exit status or signal, `si_status` of waitid
+    status: i32,
+    /// Status of wait4
+    wstatus: i32,
+}
+
+/// The unreported state change of `child` selected by `options`,
+/// as its pid, `CLD_*` code, `si_status` and wait4 status.
+/// It is marked as reported unless `WNOWAIT`.
+fn child_state(child: &mut Process, options: WaitOptions) -> Option<(Pid, i32, i32, i32)> {
+    let consume = !options.contains(WaitOptions::NOWAIT);
+    if child.exited() {
+        if !options.contains(WaitOptions::EXITED) {
+            return None;
+        }
+        let (code, status) = child.exit_reason;
+        return Some((child.pid, code, status, child.exit_code as i32));
+    }
+    // the tracer gets ptrace stops even without WUNTRACED
+    if let Some(ptrace) = child.ptrace.as_mut() {
+        if let Some(signo) = ptrace.pending() {
+            ptrace.reported |= consume;
+            return Some((child.pid, CLD_TRAPPED, signo, (signo << 8) | 0x7f));
+        }
+    }
+    let (code, signo) = child.pending_job(
+        options.contains(WaitOptions::UNTRACED),
+        options.contains(WaitOptions::CONTINUED),
+    )?;
+    if consume {
+        child.job_report = None;
+    }
+    let wstatus = match code {
+        CLD_STOPPED => (signo << 8) | 0x7f,
+        _ => 0xffff,
+    };
+    Some((child.pid, code, signo, wstatus))
+}
+
 bitflags! {
     pub struct CloneFlags: usize {
         const CSIGNAL =         0x000000ff;
@@ -479,3 +1159,22 @@ bitflags! {
         const IO =              0x80000000;
     }
 }
+
+/// Write `value` to `ptr` in `vm`, which may not be the active address space.
+/// Return false if the page can not be mapped.
+fn write_to_vm(vm: &mut MemorySet, ptr: *mut u32, value: u32) -> bool {
+    let addr = ptr as usize;
+    if vm.translate(addr).is_none() {
+        // not mapped yet
+        vm.handle_page_fault(addr, PageFaultCause::default());
+    }
+    match vm.translate(addr) {
+        Some(paddr) => {
+            unsafe {
+                *(phys_to_virt(paddr + addr % PAGE_SIZE) as *mut u32) = value;
+            }
+            true
+        }
+        None => false,
+    }
+}
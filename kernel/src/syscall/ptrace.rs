@@ -0,0 +1,245 @@
+//! Tracer side of ptrace
+
+use super::*;
+use crate::memory::{MemorySet, PageFaultCause};
+use crate::signal::{send_signal, Siginfo, SI_USER};
+use crate::sync::{Event, SpinNoIrqLock as Mutex};
+use core::mem::size_of;
+use rcore_memory::paging::PageTable;
+use rcore_memory::PAGE_SIZE;
+
+const PTRACE_TRACEME: usize = 0;
+const PTRACE_PEEKTEXT: usize = 1;
+const PTRACE_PEEKDATA: usize = 2;
+const PTRACE_POKETEXT: usize = 4;
+const PTRACE_POKEDATA: usize = 5;
+const PTRACE_CONT: usize = 7;
+const PTRACE_KILL: usize = 8;
+const PTRACE_ATTACH: usize = 16;
+const PTRACE_DETACH: usize = 17;
+const PTRACE_SYSCALL: usize = 24;
+const PTRACE_SETOPTIONS: usize = 0x4200;
+const PTRACE_GET_SYSCALL_INFO: usize = 0x420e;
+
+const PTRACE_O_TRACESYSGOOD: usize = 1;
+
+const PTRACE_SYSCALL_INFO_NONE: u8 = 0;
+const PTRACE_SYSCALL_INFO_ENTRY: u8 = 1;
+const PTRACE_SYSCALL_INFO_EXIT: u8 = 2;
+
+#[cfg(target_arch = "x86_64")]
+pub(super) const AUDIT_ARCH: u32 = 0xc000_003e;
+#[cfg(target_arch = "riscv64")]
+pub(super) const AUDIT_ARCH: u32 = 0xc000_00f3;
+#[cfg(target_arch = "riscv32")]
+pub(super) const AUDIT_ARCH: u32 = 0x4000_00f3;
+#[cfg(target_arch = "aarch64")]
+pub(super) const AUDIT_ARCH: u32 = 0xc000_00b7;
+#[cfg(target_arch = "mips")]
+pub(super) const AUDIT_ARCH: u32 = 0x4000_0008;
+
+/// `struct ptrace_syscall_info` in Linux
+#[repr(C)]
+#[derive(Debug, Default)]
+pub struct PtraceSyscallInfo {
+    op: u8,
+    pad: [u8; 3],
+    arch: u32,
+    instruction_pointer: u64,
+    stack_pointer: u64,
+    /// entry: nr and args[6], exit: rval and is_error
+    data: [u64; 7],
+}
+
+impl Syscall<'_> {
+    pub fn sys_ptrace(
+        &mut self,
+        request: usize,
+        pid: usize,
+        addr: usize,
+        data: usize,
+    ) -> SysResult {
+        info!(
+            "ptrace: request: {:#x}, pid: {}, addr: {:#x}, data: {:#x}",
+            request, pid, addr, data
+        );
+        if request == PTRACE_TRACEME {
+            let mut proc = self.process();
+            if proc.ptrace.is_some() {
+                return Err(SysError::EPERM);
+            }
+            proc.ptrace = Some(Ptrace::default());
+            return Ok(0);
+        }
+        if request == PTRACE_ATTACH {
+            return self.ptrace_attach(pid);
+        }
+
+        let tracee = self.tracee(pid)?;
+        match request {
+            PTRACE_PEEKTEXT | PTRACE_PEEKDATA => {
+                let vm = lock_process(&tracee).vm.clone();
+                let mut word = [0u8; size_of::<usize>()];
+                access_tracee_vm(&mut lock_memory_set(&vm), addr, &mut word, false)?;
+                let mut out = UserOutPtr::<usize>::from(data);
+                out.write(usize::from_ne_bytes(word))?;
+                Ok(0)
+            }
+            PTRACE_POKETEXT | PTRACE_POKEDATA => {
+                let vm = lock_process(&tracee).vm.clone();
+                let mut word = data.to_ne_bytes();
+                access_tracee_vm(&mut lock_memory_set(&vm), addr, &mut word, true)?;
+                Ok(0)
+            }
+            PTRACE_SYSCALL | PTRACE_CONT | PTRACE_DETACH => {
+                let mut proc = lock_process(&tracee);
+                if request == PTRACE_DETACH {
+                    proc.ptrace = None;
+                } else if let Some(ptrace) = proc.ptrace.as_mut() {
+                    ptrace.syscall_trace = request == PTRACE_SYSCALL;
+                    ptrace.stop = None;
+                }
+                proc.eventbus.lock().set(Event::PTRACE_RESUME);
+                Ok(0)
+            }
+            PTRACE_KILL => {
+                let info = Siginfo {
+                    signo: Signal::SIGKILL as i32,
+                    errno: 0,
+                    code: SI_USER,
+                    field: Default::default(),
+                };
+                send_signal(tracee.clone(), -1, info);
+                lock_process(&tracee)
+                    .eventbus
+                    .lock()
+                    .set(Event::PTRACE_RESUME);
+                Ok(0)
+            }
+            PTRACE_SETOPTIONS => {
+                if let Some(ptrace) = lock_process(&tracee).ptrace.as_mut() {
+                    ptrace.sysgood = data & PTRACE_O_TRACESYSGOOD != 0;
+                }
+                Ok(0)
+            }
+            PTRACE_GET_SYSCALL_INFO => {
+                let stop = lock_process(&tracee).ptrace.as_ref().and_then(|t| t.stop);
+                let mut info = PtraceSyscallInfo {
+                    op: PTRACE_SYSCALL_INFO_NONE,
+                    arch: AUDIT_ARCH,
+                    ..Default::default()
+                };
+                match stop {
+                    Some(PtraceStop::SyscallEntry { nr, args, pc, sp }) => {
+                        info.op = PTRACE_SYSCALL_INFO_ENTRY;
+                        info.instruction_pointer = pc as u64;
+                        info.stack_pointer = sp as u64;
+                        info.data[0] = nr as u64;
+                        for (i, arg) in args.iter().enumerate() {
+                            info.data[i + 1] = *arg as u64;
+                        }
+                    }
+                    Some(PtraceStop::SyscallExit { ret, pc, sp }) => {
+                        info.op = PTRACE_SYSCALL_INFO_EXIT;
+                        info.instruction_pointer = pc as u64;
+                        info.stack_pointer = sp as u64;
+                        info.data[0] = ret as u64;
+                        info.data[1] = (ret < 0) as u64;
+                    }
+                    _ => {}
+                }
+                // copy at most `addr` bytes, and return the full size
+                let size = size_of::<PtraceSyscallInfo>();
+                let bytes = unsafe { slice::from_raw_parts(&info as *const _ as *const u8, size) };
+                let mut out = UserOutPtr::<u8>::from(data);
+                out.write_array(&bytes[..addr.min(size)])?;
+                Ok(size)
+            }
+            _ => self.unimplemented("ptrace", Err(SysError::EINVAL)),
+        }
+    }
+
+    /// Trace a child process, which stops at its next syscall entry
+    fn ptrace_attach(&self, pid: usize) -> SysResult {
+        let (my_pid, pid) = self.tracee_pid(pid)?;
+        let tracee = process(pid).ok_or(SysError::ESRCH)?;
+        let mut proc = lock_process(&tracee);
+        if proc.parent.0 != my_pid {
+            return Err(SysError::ESRCH);
+        }
+        // a non-dumpable tracee needs CAP_SYS_PTRACE, even from its parent
+        if proc.ptrace.is_some() || !proc.dumpable && !capable(Capability::SysPtrace) {
+            return Err(SysError::EPERM);
+        }
+        proc.ptrace = Some(Ptrace {
+            syscall_trace: true,
+            ..Ptrace::default()
+        });
+        Ok(0)
+    }
+
+    /// The pid of the current process, and `pid` in its pid namespace translated to the global one
+    fn tracee_pid(&self, pid: usize) -> Result<(Pid, usize), SysError> {
+        let proc = self.process();
+        let global = proc.pid_ns.global(pid).ok_or(SysError::ESRCH)?;
+        Ok((proc.pid, global))
+    }
+
+    /// Find a stopped tracee of the current process
+    fn tracee(&self, pid: usize) -> Result<Arc<Mutex<Process>>, SysError> {
+        let (my_pid, pid) = self.tracee_pid(pid)?;
+        let tracee = process(pid).ok_or(SysError::ESRCH)?;
+        let proc = lock_process(&tracee);
+        let stopped = proc.ptrace.as_ref().map_or(false, |t| t.stop.is_some());
+        if proc.parent.0 != my_pid || !stopped {
+            return Err(SysError::ESRCH);
+        }
+        drop(proc);
+        Ok(tracee)
+    }
+}
+
+/// Read or write `buf.len()` bytes at `addr` in the address space of a tracee.
+/// Pages not mapped yet are faulted in, read-only pages can be written too.
+fn access_tracee_vm(vm: &mut MemorySet, addr: usize, buf: &mut [u8], write: bool) -> SysResult {
+    unsafe {
+        vm.check_read_array(addr as *const u8, buf.len())?;
+    }
+    let mut pos = 0;
+    while pos < buf.len() {
+        let vaddr = addr + pos;
+        let present = vm
+            .get_page_table_mut()
+            .get_entry(vaddr)
+            .map_or(false, |entry| entry.present());
+        // map it in, then access it regardless of its protection
+        if !present && !vm.handle_page_fault(vaddr, PageFaultCause::default()) {
+            return Err(SysError::EFAULT);
+        }
+        let page_offset = vaddr % PAGE_SIZE;
+        let len = (PAGE_SIZE - page_offset).min(buf.len() - pos);
+        let pt = vm.get_page_table_mut();
+        let page = pt.get_page_slice_mut(vaddr);
+        if write {
+            page[page_offset..page_offset + len].copy_from_slice(&buf[pos..pos + len]);
+            // the word may be an instruction, e.g. a breakpoint
+            pt.flush_cache_copy_user(vaddr, vaddr + len, true);
+        } else {
+            buf[pos..pos + len].copy_from_slice(&page[page_offset..page_offset + len]);
+        }
+        pos += len;
+    }
+    Ok(0)
+}
+
+/// Program counter of user context
+pub fn user_pc(context: &UserContext) -> usize {
+    #[cfg(target_arch = "x86_64")]
+    return context.general.rip;
+    #[cfg(riscv)]
+    return context.sepc;
+    #[cfg(mipsel)]
+    return context.epc;
+    #[cfg(target_arch = "aarch64")]
+    return context.elr;
+}
@@ -0,0 +1,456 @@
+//! Syscall filtering of seccomp(2)
+//!
+//! A filter is a classic BPF program over `SeccompData`, run before every syscall of a thread.
+//! Filters can only be added: all of them are run, and the most restrictive action wins.
+//! They are inherited by new threads and forked children, and kept across execve.
+
+use super::ptrace::AUDIT_ARCH;
+use super::*;
+use crate::process::{process_exited, THREADS};
+use crate::signal::{force_signal, Siginfo, SiginfoFields, SiginfoSys, Signal};
+use core::mem::size_of;
+
+const SECCOMP_SET_MODE_STRICT: usize = 0;
+const SECCOMP_SET_MODE_FILTER: usize = 1;
+const SECCOMP_GET_ACTION_AVAIL: usize = 2;
+
+const SECCOMP_FILTER_FLAG_TSYNC: usize = 1;
+
+const SECCOMP_RET_KILL_PROCESS: u32 = 0x8000_0000;
+const SECCOMP_RET_KILL_THREAD: u32 = 0;
+const SECCOMP_RET_TRAP: u32 = 0x0003_0000;
+const SECCOMP_RET_ERRNO: u32 = 0x0005_0000;
+const SECCOMP_RET_TRACE: u32 = 0x7ff0_0000;
+const SECCOMP_RET_LOG: u32 = 0x7ffc_0000;
+const SECCOMP_RET_ALLOW: u32 = 0x7fff_0000;
+const SECCOMP_RET_ACTION_FULL: u32 = 0xffff_0000;
+const SECCOMP_RET_DATA: u32 = 0xffff;
+
+/// si_code of SIGSYS sent by `SECCOMP_RET_TRAP`
+const SYS_SECCOMP: i32 = 1;
+
+/// Max number of instructions of a filter
+const BPF_MAXINSNS: usize = 4096;
+/// Max number of instructions of all filters of a thread
+const MAX_INSNS_PER_PATH: usize = 1 << 15;
+/// Number of words of the scratch memory
+const BPF_MEMWORDS: usize = 16;
+/// Largest errno returned by `SECCOMP_RET_ERRNO`
+const MAX_ERRNO: u32 = 4095;
+
+// classic BPF opcodes allowed in seccomp filters
+const BPF_LD: u16 = 0x00;
+const BPF_LDX: u16 = 0x01;
+const BPF_ST: u16 = 0x02;
+const BPF_STX: u16 = 0x03;
+const BPF_ALU: u16 = 0x04;
+const BPF_JMP: u16 = 0x05;
+const BPF_RET: u16 = 0x06;
+const BPF_MISC: u16 = 0x07;
+const BPF_W: u16 = 0x00;
+const BPF_IMM: u16 = 0x00;
+const BPF_ABS: u16 = 0x20;
+const BPF_MEM: u16 = 0x60;
+const BPF_LEN: u16 = 0x80;
+const BPF_K: u16 = 0x00;
+const BPF_X: u16 = 0x08;
+const BPF_A: u16 = 0x10;
+const BPF_ADD: u16 = 0x00;
+const BPF_SUB: u16 = 0x10;
+const BPF_MUL: u16 = 0x20;
+const BPF_DIV: u16 = 0x30;
+const BPF_OR: u16 = 0x40;
+const BPF_AND: u16 = 0x50;
+const BPF_LSH: u16 = 0x60;
+const BPF_RSH: u16 = 0x70;
+const BPF_NEG: u16 = 0x80;
+const BPF_MOD: u16 = 0x90;
+const BPF_XOR: u16 = 0xa0;
+const BPF_JA: u16 = 0x00;
+const BPF_JEQ: u16 = 0x10;
+const BPF_JGT: u16 = 0x20;
+const BPF_JGE: u16 = 0x30;
+const BPF_JSET: u16 = 0x40;
+const BPF_TAX: u16 = 0x00;
+const BPF_TXA: u16 = 0x80;
+
+const LD_ABS: u16 = BPF_LD | BPF_W | BPF_ABS;
+const LD_LEN: u16 = BPF_LD | BPF_W | BPF_LEN;
+const LDX_LEN: u16 = BPF_LDX | BPF_W | BPF_LEN;
+const LD_IMM: u16 = BPF_LD | BPF_IMM;
+const LDX_IMM: u16 = BPF_LDX | BPF_IMM;
+const LD_MEM: u16 = BPF_LD | BPF_MEM;
+const LDX_MEM: u16 = BPF_LDX | BPF_MEM;
+const RET_K: u16 = BPF_RET | BPF_K;
+const RET_A: u16 = BPF_RET | BPF_A;
+const TAX: u16 = BPF_MISC | BPF_TAX;
+const TXA: u16 = BPF_MISC | BPF_TXA;
+
+/// Linux struct sock_filter
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct SockFilter {
+    code: u16,
+    jt: u8,
+    jf: u8,
+    k: u32,
+}
+
+/// Linux struct sock_fprog
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct SockFprog {
+    len: u16,
+    filter: *const SockFilter,
+}
+
+/// Linux struct seccomp_data, the input of a filter
+#[repr(C)]
+struct SeccompData {
+    nr: i32,
+    arch: u32,
+    instruction_pointer: u64,
+    args: [u64; 6],
+}
+
+/// What to do with a syscall, in order of precedence
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeccompAction {
+    /// Kill the process with `signal`
+    Kill(Signal),
+    /// Send SIGSYS with the data as si_errno, and skip the syscall
+    Trap(u16),
+    /// Skip the syscall and fail with this errno
+    Errno(u16),
+    /// Notify a tracer. `PTRACE_O_TRACESECCOMP` is not supported, so fail with ENOSYS
+    /// as Linux does when no tracer asked for it.
+    Trace,
+    /// Log and run the syscall
+    Log,
+    /// Run the syscall
+    Allow,
+}
+
+impl SeccompAction {
+    fn from_ret(ret: u32) -> Self {
+        let data = (ret & SECCOMP_RET_DATA) as u16;
+        match ret & SECCOMP_RET_ACTION_FULL {
+            SECCOMP_RET_ALLOW => SeccompAction::Allow,
+            SECCOMP_RET_LOG => SeccompAction::Log,
+            SECCOMP_RET_TRACE => SeccompAction::Trace,
+            SECCOMP_RET_ERRNO => SeccompAction::Errno(data.min(MAX_ERRNO as u16)),
+            SECCOMP_RET_TRAP => SeccompAction::Trap(data),
+            // killing a single thread is not supported, so the whole process is killed
+            _ => SeccompAction::Kill(Signal::SIGSYS),
+        }
+    }
+}
+
+/// A validated seccomp filter program
+pub struct SeccompFilter {
+    insns: Vec<SockFilter>,
+}
+
+impl SeccompFilter {
+    /// Check `insns` as Linux `seccomp_check_filter` does.
+    /// Only forward jumps exist and the last instruction returns, so a filter always terminates.
+    pub fn new(insns: Vec<SockFilter>) -> Result<Self, SysError> {
+        if insns.is_empty() || insns.len() > BPF_MAXINSNS {
+            return Err(SysError::EINVAL);
+        }
+        for (pc, insn) in insns.iter().enumerate() {
+            if insn.code > 0xff {
+                return Err(SysError::EINVAL);
+            }
+            let k = insn.k as usize;
+            // instructions after this one
+            let rest = insns.len() - pc - 1;
+            let valid = match insn.code {
+                LD_ABS => k % 4 == 0 && k < size_of::<SeccompData>(),
+                LD_LEN | LDX_LEN | LD_IMM | LDX_IMM | RET_K | RET_A | TAX | TXA => true,
+                LD_MEM | LDX_MEM => k < BPF_MEMWORDS,
+                BPF_ST | BPF_STX => k < BPF_MEMWORDS,
+                code if code & 0x07 == BPF_ALU => match code & 0xf0 {
+                    BPF_DIV | BPF_MOD if code & BPF_X == BPF_K => k != 0,
+                    BPF_NEG => code & BPF_X == BPF_K,
+                    BPF_ADD | BPF_SUB | BPF_MUL | BPF_DIV | BPF_OR | BPF_AND | BPF_LSH
+                    | BPF_RSH | BPF_MOD | BPF_XOR => true,
+                    _ => false,
+                },
+                code if code == BPF_JMP | BPF_JA => k < rest,
+                code if code & 0x07 == BPF_JMP => match code & 0xf0 {
+                    BPF_JEQ | BPF_JGT | BPF_JGE | BPF_JSET => {
+                        (insn.jt as usize) < rest && (insn.jf as usize) < rest
+                    }
+                    _ => false,
+                },
+                _ => false,
+            };
+            if !valid {
+                return Err(SysError::EINVAL);
+            }
+        }
+        match insns.last().unwrap().code {
+            RET_K | RET_A => Ok(SeccompFilter { insns }),
+            _ => Err(SysError::EINVAL),
+        }
+    }
+
+    /// Run the filter on `data`, the words of `SeccompData`
+    fn run(&self, data: &[u32]) -> u32 {
+        let mut a: u32 = 0;
+        let mut x: u32 = 0;
+        let mut mem = [0u32; BPF_MEMWORDS];
+        let mut pc = 0;
+        loop {
+            let insn = self.insns[pc];
+            let k = insn.k;
+            pc += 1;
+            match insn.code {
+                LD_ABS => a = data[k as usize / 4],
+                LD_LEN => a = size_of::<SeccompData>() as u32,
+                LDX_LEN => x = size_of::<SeccompData>() as u32,
+                LD_IMM => a = k,
+                LDX_IMM => x = k,
+                LD_MEM => a = mem[k as usize],
+                LDX_MEM => x = mem[k as usize],
+                BPF_ST => mem[k as usize] = a,
+                BPF_STX => mem[k as usize] = x,
+                RET_K => return k,
+                RET_A => return a,
+                TAX => x = a,
+                TXA => a = x,
+                code if code & 0x07 == BPF_ALU => {
+                    let src = if code & BPF_X == BPF_X { x } else { k };
+                    a = match code & 0xf0 {
+                        BPF_ADD => a.wrapping_add(src),
+                        BPF_SUB => a.wrapping_sub(src),
+                        BPF_MUL => a.wrapping_mul(src),
+                        // dividing by zero in X ends the filter with 0, i.e. kill
+                        BPF_DIV => match a.checked_div(src) {
+                            Some(a) => a,
+                            None => return 0,
+                        },
+                        BPF_MOD => match a.checked_rem(src) {
+                            Some(a) => a,
+                            None => return 0,
+                        },
+                        BPF_OR => a | src,
+                        BPF_AND => a & src,
+                        BPF_LSH => a.checked_shl(src).unwrap_or(0),
+                        BPF_RSH => a.checked_shr(src).unwrap_or(0),
+                        BPF_NEG => a.wrapping_neg(),
+                        _ => a ^ src,
+                    };
+                }
+                code if code == BPF_JMP | BPF_JA => pc += k as usize,
+                code => {
+                    let src = if code & BPF_X == BPF_X { x } else { k };
+                    let taken = match code & 0xf0 {
+                        BPF_JEQ => a == src,
+                        BPF_JGT => a > src,
+                        BPF_JGE => a >= src,
+                        _ => a & src != 0,
+                    };
+                    pc += if taken { insn.jt } else { insn.jf } as usize;
+                }
+            }
+        }
+    }
+}
+
+/// Seccomp state of a thread
+#[derive(Clone, Default)]
+pub struct Seccomp {
+    /// `SECCOMP_MODE_STRICT`: only read, write, exit and rt_sigreturn are allowed
+    strict: bool,
+    /// Installed filters
+    filters: Vec<Arc<SeccompFilter>>,
+}
+
+impl Seccomp {
+    /// `SECCOMP_MODE_DISABLED`, `SECCOMP_MODE_STRICT` or `SECCOMP_MODE_FILTER`
+    pub fn mode(&self) -> usize {
+        if self.strict {
+            1
+        } else if !self.filters.is_empty() {
+            2
+        } else {
+            0
+        }
+    }
+
+    /// What to do with syscall `nr` called at `pc`
+    pub fn check(&self, nr: usize, args: &[usize; 6], pc: usize) -> SeccompAction {
+        if self.strict {
+            return match nr {
+                SYS_READ | SYS_WRITE | SYS_EXIT | SYS_RT_SIGRETURN => SeccompAction::Allow,
+                _ => SeccompAction::Kill(Signal::SIGKILL),
+            };
+        }
+        if self.filters.is_empty() {
+            return SeccompAction::Allow;
+        }
+        // words of `SeccompData`, all supported architectures being little endian
+        let mut data = [0u32; size_of::<SeccompData>() / 4];
+        data[0] = nr as u32;
+        data[1] = AUDIT_ARCH;
+        data[2] = pc as u32;
+        data[3] = (pc as u64 >> 32) as u32;
+        for (i, &arg) in args.iter().enumerate() {
+            data[4 + i * 2] = arg as u32;
+            data[5 + i * 2] = (arg as u64 >> 32) as u32;
+        }
+        // the lowest action as a signed number takes precedence, kill being negative,
+        // and the most recently installed filter wins ties
+        let ret = self
+            .filters
+            .iter()
+            .rev()
+            .map(|filter| filter.run(&data))
+            .min_by_key(|ret| (ret & SECCOMP_RET_ACTION_FULL) as i32)
+            .unwrap();
+        SeccompAction::from_ret(ret)
+    }
+
+    fn can_add(&self, filter: &SeccompFilter) -> Result<(), SysError> {
+        if self.strict {
+            return Err(SysError::EINVAL);
+        }
+        let total: usize = self.filters.iter().map(|f| f.insns.len()).sum();
+        if total + filter.insns.len() > MAX_INSNS_PER_PATH {
+            return Err(SysError::ENOMEM);
+        }
+        Ok(())
+    }
+
+    fn add_filter(&mut self, filter: Arc<SeccompFilter>) -> Result<(), SysError> {
+        self.can_add(&filter)?;
+        self.filters.push(filter);
+        Ok(())
+    }
+}
+
+impl Syscall<'_> {
+    pub fn sys_seccomp(&mut self, op: usize, flags: usize, args: usize) -> SysResult {
+        info!(
+            "seccomp: op: {}, flags: {:#x}, args: {:#x}",
+            op, flags, args
+        );
+        match op {
+            SECCOMP_SET_MODE_STRICT => {
+                if flags != 0 || args != 0 {
+                    return Err(SysError::EINVAL);
+                }
+                let mut inner = self.thread.inner.lock();
+                if !inner.seccomp.filters.is_empty() {
+                    return Err(SysError::EINVAL);
+                }
+                inner.seccomp.strict = true;
+                Ok(0)
+            }
+            SECCOMP_SET_MODE_FILTER => {
+                if flags & !SECCOMP_FILTER_FLAG_TSYNC != 0 {
+                    return Err(SysError::EINVAL);
+                }
+                // an unprivileged process must not gain privileges that the filter could subvert
+                if !self.process().no_new_privs && !capable(Capability::SysAdmin) {
+                    return Err(SysError::EACCES);
+                }
+                let fprog = UserInPtr::<SockFprog>::from(args).read()?;
+                let insns = UserInPtr::<SockFilter>::from(fprog.filter as usize)
+                    .read_array(fprog.len as usize)?;
+                let filter = Arc::new(SeccompFilter::new(insns)?);
+                if flags & SECCOMP_FILTER_FLAG_TSYNC != 0 {
+                    // all threads get the filter, or none of them.
+                    // The process stays locked so that no thread is created meanwhile.
+                    let proc = self.process();
+                    let threads: Vec<_> = {
+                        let table = THREADS.read();
+                        proc.threads
+                            .iter()
+                            .filter_map(|tid| table.get(tid).cloned())
+                            .collect()
+                    };
+                    for thread in threads.iter() {
+                        thread.inner.lock().seccomp.can_add(&filter)?;
+                    }
+                    for thread in threads.iter() {
+                        thread.inner.lock().seccomp.add_filter(filter.clone())?;
+                    }
+                    Ok(0)
+                } else {
+                    self.thread.inner.lock().seccomp.add_filter(filter)?;
+                    Ok(0)
+                }
+            }
+            SECCOMP_GET_ACTION_AVAIL => {
+                if flags != 0 {
+                    return Err(SysError::EINVAL);
+                }
+                let action = UserInPtr::<u32>::from(args).read()?;
+                match action {
+                    SECCOMP_RET_KILL_PROCESS
+                    | SECCOMP_RET_KILL_THREAD
+                    | SECCOMP_RET_TRAP
+                    | SECCOMP_RET_ERRNO
+                    | SECCOMP_RET_TRACE
+                    | SECCOMP_RET_LOG
+                    | SECCOMP_RET_ALLOW => Ok(0),
+                    _ => Err(SysError::EOPNOTSUPP),
+                }
+            }
+            _ => Err(SysError::EINVAL),
+        }
+    }
+
+    /// Run the seccomp filters of this thread for syscall `id`.
+    /// Return the result if the syscall is not to be run.
+    pub fn seccomp(&mut self, id: usize, args: &[usize; 6]) -> Option<isize> {
+        let pc = user_pc(self.context);
+        let action = self.thread.inner.lock().seccomp.check(id, args, pc);
+        match action {
+            SeccompAction::Allow => None,
+            SeccompAction::Log => {
+                warn!(
+                    "seccomp: thread {} syscall {} @ {:#x} logged",
+                    self.thread.tid, id, pc
+                );
+                None
+            }
+            SeccompAction::Errno(errno) => Some(-(errno as isize)),
+            SeccompAction::Trace => Some(-(SysError::ENOSYS as isize)),
+            SeccompAction::Trap(data) => {
+                info!(
+                    "seccomp: thread {} syscall {} @ {:#x} trapped",
+                    self.thread.tid, id, pc
+                );
+                let mut field = SiginfoFields::default();
+                field.sys = SiginfoSys {
+                    call_addr: pc,
+                    syscall: id as i32,
+                    arch: AUDIT_ARCH,
+                };
+                let info = Siginfo {
+                    signo: Signal::SIGSYS as i32,
+                    errno: data as i32,
+                    code: SYS_SECCOMP,
+                    field,
+                };
+                force_signal(self.thread, info);
+                Some(-(SysError::ENOSYS as isize))
+            }
+            SeccompAction::Kill(signal) => {
+                warn!(
+                    "seccomp: thread {} syscall {} @ {:#x} killed by {:?}",
+                    self.thread.tid, id, pc, signal
+                );
+                self.process()
+                    .exit_by_signal(signal, signal == Signal::SIGSYS);
+                process_exited(&self.thread.proc);
+                self.exit = true;
+                Some(0)
+            }
+        }
+    }
+}
@@ -1,8 +1,16 @@
 use super::{UserInPtr, UserOutPtr};
+use crate::arch::timer::timer_now;
 use crate::process::*;
 use crate::signal::*;
-use crate::syscall::SysError::{EINVAL, ENOMEM, EPERM, ESRCH};
-use crate::syscall::{SysResult, Syscall};
+use crate::sync::SpinNoIrqLock as Mutex;
+use crate::syscall::SysError::{EAGAIN, EINTR, EINVAL, ENOMEM, EPERM, ESRCH};
+use crate::syscall::{SysError, SysResult, Syscall, TimeSpec};
+use crate::trap::NAIVE_TIMER;
+use alloc::{boxed::Box, sync::Arc, vec::Vec};
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use core::time::Duration;
 use num::FromPrimitive;
 
 impl Syscall<'_> {
@@ -51,9 +59,12 @@ impl Syscall<'_> {
         let ptr: UserInPtr<SignalFrame> = UserInPtr::from(self.context.get_sp() - 8);
         let frame: SignalFrame = ptr.read()?;
 
-        // restore signal alternate stack
+        // restore signal alternate stack and mask
         let mut inner = self.thread.inner.lock();
         inner.signal_alternate_stack = frame.ucontext.stack;
+        inner.sig_mask = frame.ucontext.sig_mask;
+        inner.sig_mask.remove(Signal::SIGKILL);
+        inner.sig_mask.remove(Signal::SIGSTOP);
         drop(inner);
 
         // restore context
@@ -101,10 +112,103 @@ impl Syscall<'_> {
                 }
                 _ => return Err(EINVAL),
             }
+            // SIGKILL and SIGSTOP can not be blocked
+            inner.sig_mask.remove(Signal::SIGKILL);
+            inner.sig_mask.remove(Signal::SIGSTOP);
         }
         return Ok(0);
     }
 
+    /// Replace the mask with `mask` and wait until a signal runs its action,
+    /// then return `EINTR` with the mask restored after the handler
+    pub async fn sys_rt_sigsuspend(
+        &mut self,
+        mask: UserInPtr<Sigset>,
+        sigsetsize: usize,
+    ) -> SysResult {
+        info!(
+            "rt_sigsuspend: mask: {:?}, sigsetsize: {}",
+            mask, sigsetsize
+        );
+        if sigsetsize != core::mem::size_of::<Sigset>() {
+            return Err(EINVAL);
+        }
+        let mut mask = mask.read()?;
+        mask.remove(Signal::SIGKILL);
+        mask.remove(Signal::SIGSTOP);
+        {
+            // swap under the process lock, so no signal is sent between the swap and the check
+            let _proc = self.process();
+            let mut inner = self.thread.inner.lock();
+            inner.saved_sig_mask = Some(inner.sig_mask);
+            inner.sig_mask = mask;
+        }
+        SigsuspendFuture {
+            thread: self.thread.clone(),
+        }
+        .await;
+        Err(EINTR)
+    }
+
+    /// Report signals which are pending but blocked
+    pub fn sys_rt_sigpending(
+        &mut self,
+        mut set: UserOutPtr<Sigset>,
+        sigsetsize: usize,
+    ) -> SysResult {
+        info!("rt_sigpending: set: {:?}, sigsetsize: {}", set, sigsetsize);
+        if sigsetsize != core::mem::size_of::<Sigset>() {
+            return Err(EINVAL);
+        }
+        let pending = {
+            let proc = self.process();
+            let inner = self.thread.inner.lock();
+            proc.pending_sigset.intersect(&inner.sig_mask)
+        };
+        set.write(pending)?;
+        Ok(0)
+    }
+
+    /// Wait for a signal in `set`, take it from the queue and return its number.
+    /// Wait forever if `timeout` is null.
+    pub async fn sys_rt_sigtimedwait(
+        &mut self,
+        set: UserInPtr<Sigset>,
+        mut info: UserOutPtr<Siginfo>,
+        timeout: UserInPtr<TimeSpec>,
+        sigsetsize: usize,
+    ) -> SysResult {
+        info!(
+            "rt_sigtimedwait: set: {:?}, info: {:?}, timeout: {:?}, sigsetsize: {}",
+            set, info, timeout, sigsetsize
+        );
+        if sigsetsize != core::mem::size_of::<Sigset>() {
+            return Err(EINVAL);
+        }
+        let mut set = set.read()?;
+        set.remove(Signal::SIGKILL);
+        set.remove(Signal::SIGSTOP);
+        let deadline = if timeout.is_null() {
+            None
+        } else {
+            let timeout = timeout.read()?;
+            if timeout.nsec >= 1_000_000_000 {
+                return Err(EINVAL);
+            }
+            Some(timer_now() + timeout.to_duration())
+        };
+        let siginfo = SigtimedwaitFuture {
+            thread: self.thread.clone(),
+            set,
+            deadline,
+        }
+        .await?;
+        if !info.is_null() {
+            info.write(siginfo)?;
+        }
+        Ok(siginfo.signo as usize)
+    }
+
     /// sending signal sig to process pid
     pub fn sys_kill(&mut self, pid: isize, signum: usize) -> SysResult {
         if let Some(signal) = <Signal as FromPrimitive>::from_usize(signum) {
@@ -115,9 +219,12 @@ impl Syscall<'_> {
                 code: SI_USER,
                 field: Default::default(),
             };
+            // only processes visible from our namespace can be signaled
+            let ns = self.process().pid_ns.clone();
+            let visible = |process: &Arc<Mutex<Process>>| lock_process(process).visible_in(&ns);
             match pid {
                 pid if pid > 0 => {
-                    if let Some(process) = process(pid as usize) {
+                    if let Some(process) = ns.global(pid as usize).and_then(process) {
                         send_signal(process, -1, info);
                         Ok(0)
                     } else {
@@ -127,7 +234,7 @@ impl Syscall<'_> {
                 0 => {
                     // to current process group
                     let pgid = self.process().pgid;
-                    for process in process_group(pgid) {
+                    for process in process_group(pgid).into_iter().filter(visible) {
                         send_signal(process, -1, info);
                     }
                     Ok(0)
@@ -136,13 +243,16 @@ impl Syscall<'_> {
                     // TODO: check permissions
                     // sig is sent to every process for which the calling process
                     // has permission to send signals, except for process 1 (init)
-                    for process in PROCESSES.read().values() {
-                        send_signal(process.clone(), -1, info);
+                    for process in Process::iter().filter(visible) {
+                        send_signal(process, -1, info);
                     }
                     Ok(0)
                 }
                 _ => {
-                    let process_group = process_group((-pid) as Pgid);
+                    let process_group: Vec<_> = process_group((-pid) as Pgid)
+                        .into_iter()
+                        .filter(visible)
+                        .collect();
                     if process_group.is_empty() {
                         Err(ESRCH)
                     } else {
@@ -159,9 +269,38 @@ impl Syscall<'_> {
         }
     }
 
+    /// Send signal `signum` with the info from user, e.g. a value of `sigqueue`, to process `pid`
+    pub fn sys_rt_sigqueueinfo(
+        &mut self,
+        pid: usize,
+        signum: usize,
+        uinfo: UserInPtr<Siginfo>,
+    ) -> SysResult {
+        let signal = <Signal as FromPrimitive>::from_usize(signum).ok_or(EINVAL)?;
+        let mut info = uinfo.read()?;
+        info!(
+            "rt_sigqueueinfo: pid: {}, signal: {:?}, code: {}",
+            pid, signal, info.code
+        );
+        info.signo = signum as i32;
+        let (pid, process) = {
+            let ns = self.process().pid_ns.clone();
+            let pid = ns.global(pid).ok_or(ESRCH)?;
+            (pid, process(pid).ok_or(ESRCH)?)
+        };
+        // others may not pretend to be the kernel or kill(2)
+        if pid != self.process().pid.get() && (info.code >= 0 || info.code == SI_TKILL) {
+            return Err(EPERM);
+        }
+        send_signal(process, -1, info);
+        Ok(0)
+    }
+
     pub fn sys_tkill(&mut self, tid: usize, signum: usize) -> SysResult {
         if let Some(signal) = <Signal as FromPrimitive>::from_usize(signum) {
             info!("tkill: tid: {}, signal: {:?}", tid, signal);
+            // the main thread's tid is the pid, which is namespace-local
+            let tid = self.process().pid_ns.global(tid).unwrap_or(tid);
             if let Some(process) = process_of(tid) {
                 send_signal(
                     process,
@@ -223,3 +362,67 @@ impl Syscall<'_> {
         Ok(0)
     }
 }
+
+/// Wait until a signal which runs an action can be delivered to `thread`
+#[must_use = "future does nothing unless polled/`await`-ed"]
+struct SigsuspendFuture {
+    thread: Arc<Thread>,
+}
+
+impl Future for SigsuspendFuture {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        // `send_signal` queues and notifies under the process lock,
+        // so holding it from the check to the subscription loses no wakeup
+        let mut proc = self.thread.lock_proc();
+        if has_action_to_run(&mut proc, &self.thread) {
+            return Poll::Ready(());
+        }
+        let waker = cx.waker().clone();
+        proc.eventbus.lock().subscribe(Box::new(move |_| {
+            waker.wake_by_ref();
+            true
+        }));
+        Poll::Pending
+    }
+}
+
+/// Wait until a signal in `set` is queued for `thread` and take it,
+/// or until another signal interrupts or `deadline` passes
+#[must_use = "future does nothing unless polled/`await`-ed"]
+struct SigtimedwaitFuture {
+    thread: Arc<Thread>,
+    set: Sigset,
+    deadline: Option<Duration>,
+}
+
+impl Future for SigtimedwaitFuture {
+    type Output = Result<Siginfo, SysError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let mut proc = self.thread.lock_proc();
+        if let Some(info) = dequeue_signal(&mut proc, &self.thread, &self.set) {
+            return Poll::Ready(Ok(info));
+        }
+        if has_signal_to_deliver(&proc, &self.thread) {
+            return Poll::Ready(Err(EINTR));
+        }
+        if let Some(deadline) = self.deadline {
+            if timer_now() >= deadline {
+                return Poll::Ready(Err(EAGAIN));
+            }
+            let waker = cx.waker().clone();
+            NAIVE_TIMER
+                .lock()
+                .add(deadline, Box::new(move |_| waker.wake()));
+        }
+        // checked and subscribed under the process lock, as in `SigsuspendFuture`
+        let waker = cx.waker().clone();
+        proc.eventbus.lock().subscribe(Box::new(move |_| {
+            waker.wake_by_ref();
+            true
+        }));
+        Poll::Pending
+    }
+}
@@ -119,6 +119,13 @@ impl TimeVal {
         (self.sec as u64) * MSEC_PER_SEC + (self.usec as u64) / USEC_PER_MSEC
     }
 
+    pub fn to_timespec(&self) -> TimeSpec {
+        TimeSpec {
+            sec: self.sec,
+            nsec: self.usec.saturating_mul(NSEC_PER_USEC as usize),
+        }
+    }
+
     pub fn get_epoch() -> Self {
         let usec = get_epoch_usec();
         TimeVal {
@@ -0,0 +1,725 @@
+//! Syscall tracing, like `strace`
+//!
+//! Off by default, and then it costs one atomic load per syscall.
+//! When on, each syscall is logged on entry with its arguments, decoded for the common ones,
+//! and again with its result when it returns, optionally only for one thread.
+//! It is turned on by the `strace` or `strace=<tid>` kernel command line option,
+//! or by the private `syscall_trace` syscall.
+
+use super::{check_and_clone_cstr, SysError, AT_FDCWD};
+use crate::arch::syscall::*;
+use crate::drivers::CMDLINE;
+use alloc::string::String;
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use num::FromPrimitive;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Trace only this tid, or all threads if 0
+static TID: AtomicUsize = AtomicUsize::new(0);
+
+/// Characters of a string argument shown
+const STR_MAX: usize = 64;
+
+/// Turn tracing on or off, only for thread `tid` if not 0
+pub fn set_trace(enabled: bool, tid: usize) {
+    TID.store(tid, Ordering::Relaxed);
+    ENABLED.store(enabled, Ordering::Release);
+}
+
+/// Turn tracing on if asked for in the kernel command line
+pub fn init_trace() {
+    for arg in CMDLINE.read().split_whitespace() {
+        if arg == "strace" {
+            set_trace(true, 0);
+        } else if arg.starts_with("strace=") {
+            if let Ok(tid) = arg["strace=".len()..].parse() {
+                set_trace(true, tid);
+            }
+        }
+    }
+}
+
+/// Whether syscalls of thread `tid` are traced
+#[inline]
+pub fn tracing(tid: usize) -> bool {
+    if !ENABLED.load(Ordering::Acquire) {
+        return false;
+    }
+    let filter = TID.load(Ordering::Relaxed);
+    filter == 0 || filter == tid
+}
+
+/// Log a syscall on entry, before it may block
+pub fn trace_entry(tid: usize, id: usize, args: &[usize; 6]) {
+    let mut line = String::new();
+    let kinds = arg_kinds(id).unwrap_or(&[Arg::Hex; 6]);
+    for (i, (&kind, &arg)) in kinds.iter().zip(args.iter()).enumerate() {
+        if i > 0 {
+            line.push_str(", ");
+        }
+        write_arg(&mut line, kind, arg);
+    }
+    println!("[strace] {} {}({})", tid, Name(id), line);
+}
+
+/// Log the result of a syscall
+pub fn trace_exit(tid: usize, id: usize, ret: isize) {
+    let err = match ret {
+        ret if ret < 0 => SysError::from_isize(-ret),
+        _ => None,
+    };
+    match err {
+        Some(err) => println!(
+            "[strace] {} {} = {} {:?} ({})",
+            tid,
+            Name(id),
+            ret,
+            err,
+            err
+        ),
+        None => println!("[strace] {} {} = {}", tid, Name(id), ret),
+    }
+}
+
+/// How an argument is shown
+#[derive(Debug, Clone, Copy)]
+enum Arg {
+    /// Signed decimal
+    Int,
+    /// Hexadecimal, for pointers and flags
+    Hex,
+    /// Octal, for modes
+    Oct,
+    /// File descriptor, or AT_FDCWD
+    Fd,
+    /// String in user memory
+    Str,
+}
+
+/// Arguments of syscall `id`, or None to show all six in hex
+fn arg_kinds(id: usize) -> Option<&'static [Arg]> {
+    use Arg::*;
+    let kinds: &[Arg] = match id {
+        SYS_GETPID | SYS_GETPPID | SYS_GETTID | SYS_SCHED_YIELD | SYS_SYNC => &[],
+        SYS_EXIT | SYS_EXIT_GROUP => &[Int],
+        SYS_READ | SYS_WRITE | SYS_READV | SYS_WRITEV | SYS_GETDENTS64 => &[Fd, Hex, Int],
+        SYS_PREAD64 | SYS_PWRITE64 => &[Fd, Hex, Int, Int],
+        SYS_CLOSE | SYS_DUP | SYS_FSYNC | SYS_FDATASYNC | SYS_FCHDIR => &[Fd],
+        SYS_DUP3 => &[Fd, Fd, Hex],
+        SYS_LSEEK => &[Fd, Int, Int],
+        SYS_FSTAT => &[Fd, Hex],
+        SYS_FCHMOD => &[Fd, Oct],
+        SYS_FTRUNCATE => &[Fd, Int],
+        SYS_IOCTL => &[Fd, Hex, Hex],
+        SYS_FCNTL => &[Fd, Int, Hex],
+        SYS_OPENAT => &[Fd, Str, Hex, Oct],
+        #[cfg(not(mipsel))]
+        SYS_NEWFSTATAT => &[Fd, Str, Hex, Hex],
+        SYS_FACCESSAT => &[Fd, Str, Oct, Hex],
+        SYS_MKDIRAT | SYS_FCHMODAT => &[Fd, Str, Oct],
+        SYS_UNLINKAT => &[Fd, Str, Hex],
+        SYS_RENAMEAT => &[Fd, Str, Fd, Str],
+        SYS_LINKAT => &[Fd, Str, Fd, Str, Hex],
+        SYS_SYMLINKAT => &[Str, Fd, Str],
+        SYS_READLINKAT => &[Fd, Str, Hex, Int],
+        SYS_CHDIR | SYS_CHROOT => &[Str],
+        SYS_TRUNCATE => &[Str, Int],
+        SYS_EXECVE => &[Str, Hex, Hex],
+        SYS_EXECVEAT => &[Fd, Str, Hex, Hex, Hex],
+        SYS_MOUNT => &[Str, Str, Str, Hex, Hex],
+        SYS_UMOUNT2 => &[Str, Hex],
+        SYS_KILL | SYS_TKILL => &[Int, Int],
+        SYS_TGKILL => &[Int, Int, Int],
+        SYS_WAIT4 => &[Int, Hex, Hex, Hex],
+        SYS_BRK => &[Hex],
+        SYS_MMAP => &[Hex, Int, Hex, Hex, Fd, Hex],
+        SYS_MUNMAP => &[Hex, Int],
+        SYS_MPROTECT => &[Hex, Int, Hex],
+        #[cfg(any(target_arch = "x86_64", mipsel))]
+        SYS_OPEN => &[Str, Hex, Oct],
+        #[cfg(any(target_arch = "x86_64", mipsel))]
+        SYS_STAT | SYS_LSTAT => &[Str, Hex],
+        #[cfg(any(target_arch = "x86_64", mipsel))]
+        SYS_ACCESS | SYS_MKDIR | SYS_CHMOD => &[Str, Oct],
+        #[cfg(any(target_arch = "x86_64", mipsel))]
+        SYS_UNLINK | SYS_RMDIR => &[Str],
+        #[cfg(any(target_arch = "x86_64", mipsel))]
+        SYS_RENAME | SYS_SYMLINK | SYS_LINK => &[Str, Str],
+        #[cfg(any(target_arch = "x86_64", mipsel))]
+        SYS_READLINK => &[Str, Hex, Int],
+        #[cfg(any(target_arch = "x86_64", mipsel))]
+        SYS_DUP2 => &[Fd, Fd],
+        _ => return None,
+    };
+    Some(kinds)
+}
+
+fn write_arg(line: &mut String, kind: Arg, arg: usize) {
+    match kind {
+        Arg::Int => write!(line, "{}", arg as isize),
+        Arg::Hex => write!(line, "{:#x}", arg),
+        Arg::Oct => write!(line, "{:#o}", arg),
+        Arg::Fd if arg == AT_FDCWD => write!(line, "AT_FDCWD"),
+        Arg::Fd => write!(line, "{}", arg as i32),
+        Arg::Str if arg == 0 => write!(line, "NULL"),
+        Arg::Str => match check_and_clone_cstr(arg as *const u8) {
+            Ok(s) if s.chars().count() > STR_MAX => {
+                let s: String = s.chars().take(STR_MAX).collect();
+                write!(line, "{:?}...", s)
+            }
+            Ok(s) => write!(line, "{:?}", s),
+            Err(_) => write!(line, "{:#x}", arg),
+        },
+    }
+    .unwrap();
+}
+
+/// Name of syscall `id` as in Linux, e.g. `openat`
+struct Name(usize);
+
+impl fmt::Display for Name {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match syscall_const(self.0) {
+            Some(name) => {
+                for c in name["SYS_".len()..].chars() {
+                    f.write_char(c.to_ascii_lowercase())?;
+                }
+                Ok(())
+            }
+            None => write!(f, "syscall_{}", self.0),
+        }
+    }
+}
+
+/// Name of the constant of syscall `id`, e.g. `SYS_OPENAT`.
+/// The table serves all architectures, with the syscalls only some of them have cfg'd.
+fn syscall_const(id: usize) -> Option<&'static str> {
+    macro_rules! names {
+        ($($(#[$attr:meta])* $name:ident,)*) => {
+            match id {
+                $($(#[$attr])* $name => stringify!($name),)*
+                _ => return None,
+            }
+        };
+    }
+    Some(names! {
+        SYS_IO_SETUP,
+        SYS_IO_DESTROY,
+        SYS_IO_SUBMIT,
+        SYS_IO_CANCEL,
+        SYS_IO_GETEVENTS,
+        SYS_SETXATTR,
+        SYS_LSETXATTR,
+        SYS_FSETXATTR,
+        SYS_GETXATTR,
+        SYS_LGETXATTR,
+        SYS_FGETXATTR,
+        SYS_LISTXATTR,
+        SYS_LLISTXATTR,
+        SYS_FLISTXATTR,
+        SYS_REMOVEXATTR,
+        SYS_LREMOVEXATTR,
+        SYS_FREMOVEXATTR,
+        SYS_GETCWD,
+        SYS_LOOKUP_DCOOKIE,
+        SYS_EVENTFD2,
+        SYS_EPOLL_CREATE1,
+        SYS_EPOLL_CTL,
+        SYS_EPOLL_PWAIT,
+        SYS_DUP,
+        SYS_DUP3,
+        SYS_FCNTL,
+        SYS_INOTIFY_INIT1,
+        SYS_INOTIFY_ADD_WATCH,
+        SYS_INOTIFY_RM_WATCH,
+        SYS_IOCTL,
+        SYS_IOPRIO_SET,
+        SYS_IOPRIO_GET,
+        SYS_FLOCK,
+        SYS_MKNODAT,
+        SYS_MKDIRAT,
+        SYS_UNLINKAT,
+        SYS_SYMLINKAT,
+        SYS_LINKAT,
+        SYS_RENAMEAT,
+        SYS_UMOUNT2,
+        SYS_MOUNT,
+        SYS_PIVOT_ROOT,
+        SYS_NFSSERVCTL,
+        SYS_STATFS,
+        SYS_FSTATFS,
+        SYS_TRUNCATE,
+        SYS_FTRUNCATE,
+        SYS_FALLOCATE,
+        SYS_FACCESSAT,
+        SYS_CHDIR,
+        SYS_FCHDIR,
+        SYS_CHROOT,
+        SYS_FCHMOD,
+        SYS_FCHMODAT,
+        SYS_FCHOWNAT,
+        SYS_FCHOWN,
+        SYS_OPENAT,
+        SYS_CLOSE,
+        SYS_VHANGUP,
+        SYS_PIPE2,
+        SYS_QUOTACTL,
+        SYS_GETDENTS64,
+        SYS_LSEEK,
+        SYS_READ,
+        SYS_WRITE,
+        SYS_READV,
+        SYS_WRITEV,
+        SYS_PREAD64,
+        SYS_PWRITE64,
+        SYS_PREADV,
+        SYS_PWRITEV,
+        SYS_SENDFILE,
+        SYS_PSELECT6,
+        SYS_PPOLL,
+        SYS_SIGNALFD4,
+        SYS_VMSPLICE,
+        SYS_SPLICE,
+        SYS_TEE,
+        SYS_READLINKAT,
+        SYS_FSTAT,
+        SYS_SYNC,
+        SYS_FSYNC,
+        SYS_FDATASYNC,
+        SYS_SYNC_FILE_RANGE,
+        SYS_TIMERFD_CREATE,
+        SYS_TIMERFD_SETTIME,
+        SYS_TIMERFD_GETTIME,
+        SYS_UTIMENSAT,
+        SYS_ACCT,
+        SYS_CAPGET,
+        SYS_CAPSET,
+        SYS_PERSONALITY,
+        SYS_EXIT,
+        SYS_EXIT_GROUP,
+        SYS_WAITID,
+        SYS_SET_TID_ADDRESS,
+        SYS_UNSHARE,
+        SYS_FUTEX,
+        SYS_SET_ROBUST_LIST,
+        SYS_GET_ROBUST_LIST,
+        SYS_NANOSLEEP,
+        SYS_GETITIMER,
+        SYS_SETITIMER,
+        SYS_KEXEC_LOAD,
+        SYS_INIT_MODULE,
+        SYS_DELETE_MODULE,
+        SYS_TIMER_CREATE,
+        SYS_TIMER_GETTIME,
+        SYS_TIMER_GETOVERRUN,
+        SYS_TIMER_SETTIME,
+        SYS_TIMER_DELETE,
+        SYS_CLOCK_SETTIME,
+        SYS_CLOCK_GETTIME,
+        SYS_CLOCK_GETRES,
+        SYS_CLOCK_NANOSLEEP,
+        SYS_SYSLOG,
+        SYS_PTRACE,
+        SYS_SCHED_SETPARAM,
+        SYS_SCHED_SETSCHEDULER,
+        SYS_SCHED_GETSCHEDULER,
+        SYS_SCHED_GETPARAM,
+        SYS_SCHED_SETAFFINITY,
+        SYS_SCHED_GETAFFINITY,
+        SYS_SCHED_YIELD,
+        SYS_SCHED_GET_PRIORITY_MAX,
+        SYS_SCHED_GET_PRIORITY_MIN,
+        SYS_SCHED_RR_GET_INTERVAL,
+        SYS_RESTART_SYSCALL,
+        SYS_KILL,
+        SYS_TKILL,
+        SYS_TGKILL,
+        SYS_SIGALTSTACK,
+        SYS_RT_SIGSUSPEND,
+        SYS_RT_SIGACTION,
+        SYS_RT_SIGPROCMASK,
+        SYS_RT_SIGPENDING,
+        SYS_RT_SIGTIMEDWAIT,
+        SYS_RT_SIGQUEUEINFO,
+        SYS_RT_SIGRETURN,
+        SYS_SETPRIORITY,
+        SYS_GETPRIORITY,
+        SYS_REBOOT,
+        SYS_SETREGID,
+        SYS_SETGID,
+        SYS_SETREUID,
+        SYS_SETUID,
+        SYS_SETRESUID,
+        SYS_GETRESUID,
+        SYS_SETRESGID,
+        SYS_GETRESGID,
+        SYS_SETFSUID,
+        SYS_SETFSGID,
+        SYS_TIMES,
+        SYS_SETPGID,
+        SYS_GETPGID,
+        SYS_GETSID,
+        SYS_SETSID,
+        SYS_GETGROUPS,
+        SYS_SETGROUPS,
+        SYS_UNAME,
+        SYS_SETHOSTNAME,
+        SYS_SETDOMAINNAME,
+        SYS_GETRLIMIT,
+        SYS_SETRLIMIT,
+        SYS_GETRUSAGE,
+        SYS_UMASK,
+        SYS_PRCTL,
+        SYS_GETCPU,
+        SYS_GETTIMEOFDAY,
+        SYS_SETTIMEOFDAY,
+        SYS_ADJTIMEX,
+        SYS_GETPID,
+        SYS_GETPPID,
+        SYS_GETUID,
+        SYS_GETEUID,
+        SYS_GETGID,
+        SYS_GETEGID,
+        SYS_GETTID,
+        SYS_SYSINFO,
+        SYS_MQ_OPEN,
+        SYS_MQ_UNLINK,
+        SYS_MQ_TIMEDSEND,
+        SYS_MQ_TIMEDRECEIVE,
+        SYS_MQ_NOTIFY,
+        SYS_MQ_GETSETATTR,
+        SYS_SOCKET,
+        SYS_SOCKETPAIR,
+        SYS_BIND,
+        SYS_LISTEN,
+        SYS_ACCEPT,
+        SYS_CONNECT,
+        SYS_GETSOCKNAME,
+        SYS_GETPEERNAME,
+        SYS_SENDTO,
+        SYS_RECVFROM,
+        SYS_SETSOCKOPT,
+        SYS_GETSOCKOPT,
+        SYS_SHUTDOWN,
+        SYS_SENDMSG,
+        SYS_RECVMSG,
+        SYS_READAHEAD,
+        SYS_BRK,
+        SYS_MUNMAP,
+        SYS_MREMAP,
+        SYS_ADD_KEY,
+        SYS_REQUEST_KEY,
+        SYS_KEYCTL,
+        SYS_CLONE,
+        SYS_EXECVE,
+        SYS_MMAP,
+        SYS_FADVISE64,
+        SYS_SWAPON,
+        SYS_SWAPOFF,
+        SYS_MPROTECT,
+        SYS_MSYNC,
+        SYS_MLOCK,
+        SYS_MUNLOCK,
+        SYS_MLOCKALL,
+        SYS_MUNLOCKALL,
+        SYS_MINCORE,
+        SYS_MADVISE,
+        SYS_REMAP_FILE_PAGES,
+        SYS_MBIND,
+        SYS_GET_MEMPOLICY,
+        SYS_SET_MEMPOLICY,
+        SYS_MIGRATE_PAGES,
+        SYS_MOVE_PAGES,
+        SYS_RT_TGSIGQUEUEINFO,
+        SYS_PERF_EVENT_OPEN,
+        SYS_ACCEPT4,
+        SYS_RECVMMSG,
+        SYS_WAIT4,
+        SYS_PRLIMIT64,
+        SYS_FANOTIFY_INIT,
+        SYS_FANOTIFY_MARK,
+        SYS_NAME_TO_HANDLE_AT,
+        SYS_OPEN_BY_HANDLE_AT,
+        SYS_CLOCK_ADJTIME,
+        SYS_SYNCFS,
+        SYS_SETNS,
+        SYS_SENDMMSG,
+        SYS_PROCESS_VM_READV,
+        SYS_PROCESS_VM_WRITEV,
+        SYS_KCMP,
+        SYS_FINIT_MODULE,
+        SYS_SCHED_SETATTR,
+        SYS_SCHED_GETATTR,
+        SYS_RENAMEAT2,
+        SYS_SECCOMP,
+        SYS_GETRANDOM,
+        SYS_MEMFD_CREATE,
+        SYS_BPF,
+        SYS_EXECVEAT,
+        SYS_USERFAULTFD,
+        SYS_MEMBARRIER,
+        SYS_MLOCK2,
+        SYS_COPY_FILE_RANGE,
+        SYS_PREADV2,
+        SYS_PWRITEV2,
+        SYS_PKEY_MPROTECT,
+        SYS_PKEY_ALLOC,
+        SYS_PKEY_FREE,
+        SYS_CLOSE_RANGE,
+        SYS_MAP_PCI_DEVICE,
+        SYS_GET_PADDR,
+        SYS_SYSCALL_TRACE,
+        #[cfg(any(target_arch = "x86_64", mipsel))]
+        SYS_OPEN,
+        #[cfg(any(target_arch = "x86_64", mipsel))]
+        SYS_STAT,
+        #[cfg(any(target_arch = "x86_64", mipsel))]
+        SYS_LSTAT,
+        #[cfg(any(target_arch = "x86_64", mipsel))]
+        SYS_POLL,
+        #[cfg(any(target_arch = "x86_64", mipsel))]
+        SYS_ACCESS,
+        #[cfg(any(target_arch = "x86_64", mipsel))]
+        SYS_PIPE,
+        #[cfg(any(target_arch = "x86_64", mipsel))]
+        SYS_DUP2,
+        #[cfg(any(target_arch = "x86_64", mipsel))]
+        SYS_PAUSE,
+        #[cfg(any(target_arch = "x86_64", mipsel))]
+        SYS_ALARM,
+        #[cfg(any(target_arch = "x86_64", mipsel))]
+        SYS_FORK,
+        #[cfg(any(target_arch = "x86_64", mipsel))]
+        SYS_GETDENTS,
+        #[cfg(any(target_arch = "x86_64", mipsel))]
+        SYS_RENAME,
+        #[cfg(any(target_arch = "x86_64", mipsel))]
+        SYS_MKDIR,
+        #[cfg(any(target_arch = "x86_64", mipsel))]
+        SYS_RMDIR,
+        #[cfg(any(target_arch = "x86_64", mipsel))]
+        SYS_CREAT,
+        #[cfg(any(target_arch = "x86_64", mipsel))]
+        SYS_LINK,
+        #[cfg(any(target_arch = "x86_64", mipsel))]
+        SYS_UNLINK,
+        #[cfg(any(target_arch = "x86_64", mipsel))]
+        SYS_SYMLINK,
+        #[cfg(any(target_arch = "x86_64", mipsel))]
+        SYS_READLINK,
+        #[cfg(any(target_arch = "x86_64", mipsel))]
+        SYS_CHMOD,
+        #[cfg(any(target_arch = "x86_64", mipsel))]
+        SYS_CHOWN,
+        #[cfg(any(target_arch = "x86_64", mipsel))]
+        SYS_LCHOWN,
+        #[cfg(any(target_arch = "x86_64", mipsel))]
+        SYS_GETPGRP,
+        #[cfg(any(target_arch = "x86_64", mipsel))]
+        SYS_UTIME,
+        #[cfg(any(target_arch = "x86_64", mipsel))]
+        SYS_MKNOD,
+        #[cfg(any(target_arch = "x86_64", mipsel))]
+        SYS_USELIB,
+        #[cfg(any(target_arch = "x86_64", mipsel))]
+        SYS_USTAT,
+        #[cfg(any(target_arch = "x86_64", mipsel))]
+        SYS_SYSFS,
+        #[cfg(any(target_arch = "x86_64", mipsel))]
+        SYS_MODIFY_LDT,
+        #[cfg(any(target_arch = "x86_64", mipsel))]
+        SYS__SYSCTL,
+        #[cfg(any(target_arch = "x86_64", mipsel))]
+        SYS_IOPL,
+        #[cfg(any(target_arch = "x86_64", mipsel))]
+        SYS_IOPERM,
+        #[cfg(any(target_arch = "x86_64", mipsel))]
+        SYS_CREATE_MODULE,
+        #[cfg(any(target_arch = "x86_64", mipsel))]
+        SYS_GET_KERNEL_SYMS,
+        #[cfg(any(target_arch = "x86_64", mipsel))]
+        SYS_QUERY_MODULE,
+        #[cfg(any(target_arch = "x86_64", mipsel))]
+        SYS_GETPMSG,
+        #[cfg(any(target_arch = "x86_64", mipsel))]
+        SYS_PUTPMSG,
+        #[cfg(any(target_arch = "x86_64", mipsel))]
+        SYS_AFS_SYSCALL,
+        #[cfg(any(target_arch = "x86_64", mipsel))]
+        SYS_TIME,
+        #[cfg(any(target_arch = "x86_64", mipsel))]
+        SYS_SET_THREAD_AREA,
+        #[cfg(any(target_arch = "x86_64", mipsel))]
+        SYS_EPOLL_CREATE,
+        #[cfg(any(target_arch = "x86_64", mipsel))]
+        SYS_EPOLL_WAIT,
+        #[cfg(any(target_arch = "x86_64", mipsel))]
+        SYS_UTIMES,
+        #[cfg(any(target_arch = "x86_64", mipsel))]
+        SYS_VSERVER,
+        #[cfg(any(target_arch = "x86_64", mipsel))]
+        SYS_INOTIFY_INIT,
+        #[cfg(any(target_arch = "x86_64", mipsel))]
+        SYS_FUTIMESAT,
+        #[cfg(any(target_arch = "x86_64", mipsel))]
+        SYS_SIGNALFD,
+        #[cfg(any(target_arch = "x86_64", mipsel))]
+        SYS_EVENTFD,
+        #[cfg(any(target_arch = "x86_64", mipsel))]
+        SYS_RSEQ,
+        #[cfg(mipsel)]
+        SYS_SYSCALL,
+        #[cfg(mipsel)]
+        SYS_WAITPID,
+        #[cfg(mipsel)]
+        SYS_BREAK,
+        #[cfg(mipsel)]
+        SYS_UMOUNT,
+        #[cfg(mipsel)]
+        SYS_STIME,
+        #[cfg(mipsel)]
+        SYS_STTY,
+        #[cfg(mipsel)]
+        SYS_GTTY,
+        #[cfg(mipsel)]
+        SYS_NICE,
+        #[cfg(mipsel)]
+        SYS_FTIME,
+        #[cfg(mipsel)]
+        SYS_PROF,
+        #[cfg(mipsel)]
+        SYS_SIGNAL,
+        #[cfg(mipsel)]
+        SYS_LOCK,
+        #[cfg(mipsel)]
+        SYS_MPX,
+        #[cfg(mipsel)]
+        SYS_ULIMIT,
+        #[cfg(mipsel)]
+        SYS_SIGACTION,
+        #[cfg(mipsel)]
+        SYS_SGETMASK,
+        #[cfg(mipsel)]
+        SYS_SSETMASK,
+        #[cfg(mipsel)]
+        SYS_SIGSUSPEND,
+        #[cfg(mipsel)]
+        SYS_SIGPENDING,
+        #[cfg(mipsel)]
+        SYS_RESERVED82,
+        #[cfg(mipsel)]
+        SYS_READDIR,
+        #[cfg(mipsel)]
+        SYS_PROFIL,
+        #[cfg(mipsel)]
+        SYS_SOCKETCALL,
+        #[cfg(mipsel)]
+        SYS_IDLE,
+        #[cfg(mipsel)]
+        SYS_VM86,
+        #[cfg(mipsel)]
+        SYS_IPC,
+        #[cfg(mipsel)]
+        SYS_SIGRETURN,
+        #[cfg(mipsel)]
+        SYS_SIGPROCMASK,
+        #[cfg(mipsel)]
+        SYS_BDFLUSH,
+        #[cfg(mipsel)]
+        SYS__LLSEEK,
+        #[cfg(mipsel)]
+        SYS__NEWSELECT,
+        #[cfg(mipsel)]
+        SYS_CACHEFLUSH,
+        #[cfg(mipsel)]
+        SYS_CACHECTL,
+        #[cfg(mipsel)]
+        SYS_SYSMIPS,
+        #[cfg(mipsel)]
+        SYS_RECV,
+        #[cfg(mipsel)]
+        SYS_SEND,
+        #[cfg(mipsel)]
+        SYS_MMAP2,
+        #[cfg(mipsel)]
+        SYS_TRUNCATE64,
+        #[cfg(mipsel)]
+        SYS_FTRUNCATE64,
+        #[cfg(mipsel)]
+        SYS_STAT64,
+        #[cfg(mipsel)]
+        SYS_LSTAT64,
+        #[cfg(mipsel)]
+        SYS_FSTAT64,
+        #[cfg(mipsel)]
+        SYS_FCNTL64,
+        #[cfg(mipsel)]
+        SYS_RESERVED221,
+        #[cfg(mipsel)]
+        SYS_SENDFILE64,
+        #[cfg(mipsel)]
+        SYS_STATFS64,
+        #[cfg(mipsel)]
+        SYS_FSTATFS64,
+        #[cfg(mipsel)]
+        SYS_SYS_SETALTROOT,
+        #[cfg(mipsel)]
+        SYS_FSTATAT64,
+        #[cfg(mipsel)]
+        SYS_TIMERFD,
+        #[cfg(target_arch = "x86_64")]
+        SYS_SELECT,
+        #[cfg(target_arch = "x86_64")]
+        SYS_VFORK,
+        #[cfg(target_arch = "x86_64")]
+        SYS_ARCH_PRCTL,
+        #[cfg(target_arch = "x86_64")]
+        SYS_TUXCALL,
+        #[cfg(target_arch = "x86_64")]
+        SYS_SECURITY,
+        #[cfg(target_arch = "x86_64")]
+        SYS_GET_THREAD_AREA,
+        #[cfg(target_arch = "x86_64")]
+        SYS_EPOLL_CTL_OLD,
+        #[cfg(target_arch = "x86_64")]
+        SYS_EPOLL_WAIT_OLD,
+        #[cfg(target_arch = "x86_64")]
+        SYS_KEXEC_FILE_LOAD,
+        #[cfg(riscv)]
+        SYS_ARCH_SPECIFIC_SYSCALL,
+        #[cfg(riscv)]
+        SYS_RISCV_FLUSH_ICACHE,
+        #[cfg(not(mipsel))]
+        SYS_NEWFSTATAT,
+        #[cfg(not(mipsel))]
+        SYS_MSGGET,
+        #[cfg(not(mipsel))]
+        SYS_MSGCTL,
+        #[cfg(not(mipsel))]
+        SYS_MSGRCV,
+        #[cfg(not(mipsel))]
+        SYS_MSGSND,
+        #[cfg(not(mipsel))]
+        SYS_SEMGET,
+        #[cfg(not(mipsel))]
+        SYS_SEMCTL,
+        #[cfg(not(mipsel))]
+        SYS_SEMTIMEDOP,
+        #[cfg(not(mipsel))]
+        SYS_SEMOP,
+        #[cfg(not(mipsel))]
+        SYS_SHMGET,
+        #[cfg(not(mipsel))]
+        SYS_SHMCTL,
+        #[cfg(not(mipsel))]
+        SYS_SHMAT,
+        #[cfg(not(mipsel))]
+        SYS_SHMDT,
+        #[cfg(not(riscv))]
+        SYS_STATX,
+        #[cfg(not(riscv))]
+        SYS_IO_PGETEVENTS,
+    })
+}
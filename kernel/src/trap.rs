@@ -3,6 +3,9 @@ use crate::consts::INFORM_PER_MSEC;
 use crate::process::*;
 use crate::sync::SpinNoIrqLock as Mutex;
 use crate::{signal::SignalUserContext, sync::Condvar};
+use alloc::boxed::Box;
+use alloc::collections::BinaryHeap;
+use core::cmp::Reverse;
 use core::time::Duration;
 use naive_timer::Timer;
 use trapframe::TrapFrame;
@@ -19,12 +22,48 @@ pub fn uptime_msec() -> usize {
 }
 
 lazy_static! {
-    pub static ref NAIVE_TIMER: Mutex<Timer> = Mutex::new(Timer::default());
+    pub static ref NAIVE_TIMER: Mutex<KernelTimer> = Mutex::new(KernelTimer::default());
+}
+
+/// Timers of the kernel, whose deadlines are kept to program the next timer interrupt
+#[derive(Default)]
+pub struct KernelTimer {
+    timer: Timer,
+    deadlines: BinaryHeap<Reverse<Duration>>,
+}
+
+impl KernelTimer {
+    /// Call `callback` at `deadline`
+    pub fn add(&mut self, deadline: Duration, callback: Box<dyn FnOnce(Duration) + Send + Sync>) {
+        self.timer.add(deadline, callback);
+        self.deadlines.push(Reverse(deadline));
+        // the timer interrupt may be far later than a tick
+        #[cfg(target_arch = "x86_64")]
+        crate::arch::timer::set_deadline(deadline);
+    }
+
+    /// Call the callbacks whose deadlines are not after `now`
+    pub fn expire(&mut self, now: Duration) {
+        self.timer.expire(now);
+        while let Some(&Reverse(deadline)) = self.deadlines.peek() {
+            if deadline > now {
+                break;
+            }
+            self.deadlines.pop();
+        }
+    }
+
+    /// The earliest deadline
+    pub fn next(&self) -> Option<Duration> {
+        self.deadlines.peek().map(|&Reverse(deadline)| deadline)
+    }
 }
 
 pub fn timer() {
     let now = crate::arch::timer::timer_now();
     NAIVE_TIMER.lock().expire(now);
+    sample_load();
+    update_vdso();
 }
 
 pub fn serial(c: u8) {
@@ -0,0 +1 @@
+tests/append_dup
@@ -0,0 +1 @@
+tests/blk_sector0
@@ -0,0 +1 @@
+tests/buffer_cache
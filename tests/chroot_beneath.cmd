@@ -0,0 +1 @@
+tests/chroot_beneath
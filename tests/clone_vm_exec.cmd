@@ -0,0 +1 @@
+tests/clone_vm_exec
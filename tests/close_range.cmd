@@ -0,0 +1 @@
+tests/close_range
@@ -0,0 +1 @@
+tests/exec_noexec
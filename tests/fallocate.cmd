@@ -0,0 +1 @@
+tests/fallocate
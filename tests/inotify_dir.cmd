@@ -0,0 +1 @@
+tests/inotify_dir
@@ -0,0 +1 @@
+tests/job_control
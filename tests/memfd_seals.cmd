@@ -0,0 +1 @@
+tests/memfd_seals
@@ -0,0 +1,70 @@
+#!/usr/bin/env python3
+# Make the partitioned disk images attached for tests/partition:
+# mbr.img with a primary and two logical partitions, and gpt.img.
+# Each partition starts with its name, e.g. "mbr5".
+import os
+import struct
+import sys
+import zlib
+
+SECTOR = 512
+DISK_SECTORS = 8192
+
+
+def put(img, lba, data):
+    img[lba * SECTOR:lba * SECTOR + len(data)] = data
+
+
+def mbr_entry(kind, start, blocks):
+    return struct.pack('<B3sB3sII', 0, b'', kind, b'', start, blocks)
+
+
+def boot_sector(entries):
+    sector = bytearray(SECTOR)
+    for i, entry in enumerate(entries):
+        sector[446 + i * 16:446 + (i + 1) * 16] = entry
+    sector[510:512] = b'\x55\xaa'
+    return sector
+
+
+def make_mbr():
+    img = bytearray(DISK_SECTORS * SECTOR)
+    put(img, 0, boot_sector([mbr_entry(0x83, 2048, 1024), mbr_entry(0x05, 4096, 4096)]))
+    # logical partitions start one sector after their EBR, the links are relative to 4096
+    put(img, 4096, boot_sector([mbr_entry(0x83, 1, 1023), mbr_entry(0x05, 1024, 1024)]))
+    put(img, 5120, boot_sector([mbr_entry(0x83, 1, 1023)]))
+    for name, lba in [('mbr1', 2048), ('mbr5', 4097), ('mbr6', 5121)]:
+        put(img, lba, name.encode())
+    return img
+
+
+def gpt_header(my_lba, alternate_lba, entries_lba, entries_crc):
+    header = bytearray(struct.pack(
+        '<8sIIIIQQQQ16sQIII', b'EFI PART', 0x10000, 92, 0, 0, my_lba, alternate_lba,
+        34, DISK_SECTORS - 34, b'rcore-test-disk!', entries_lba, 128, 128, entries_crc))
+    header[16:20] = struct.pack('<I', zlib.crc32(header))
+    return header
+
+
+def make_gpt():
+    img = bytearray(DISK_SECTORS * SECTOR)
+    put(img, 0, boot_sector([mbr_entry(0xee, 1, DISK_SECTORS - 1)]))
+    # the third entry is unused, so the last partition is gpt4
+    entries = bytearray(128 * 128)
+    for i, (first, last) in {0: (2048, 3071), 1: (3072, 4095), 3: (4096, 5119)}.items():
+        entry = struct.pack('<16s16sQQ', b'linux-data-type!', bytes([i + 1]) * 16, first, last)
+        entries[i * 128:i * 128 + len(entry)] = entry
+        put(img, first, 'gpt{}'.format(i + 1).encode())
+    crc = zlib.crc32(entries)
+    last = DISK_SECTORS - 1
+    put(img, 1, gpt_header(1, last, 2, crc))
+    put(img, 2, entries)
+    put(img, last - 32, entries)
+    put(img, last, gpt_header(last, 1, last - 32, crc))
+    return img
+
+
+out = sys.argv[1] if len(sys.argv) > 1 else '.'
+for name, make in [('mbr.img', make_mbr), ('gpt.img', make_gpt)]:
+    with open(os.path.join(out, name), 'wb') as f:
+        f.write(make())
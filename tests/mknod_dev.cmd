@@ -0,0 +1 @@
+tests/mknod_dev
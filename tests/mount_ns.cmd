@@ -0,0 +1 @@
+tests/mount_ns
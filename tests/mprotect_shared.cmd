@@ -0,0 +1 @@
+tests/mprotect_shared
@@ -0,0 +1 @@
+tests/page_cache
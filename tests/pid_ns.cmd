@@ -0,0 +1 @@
+tests/pid_ns
@@ -0,0 +1 @@
+tests/pipe_size
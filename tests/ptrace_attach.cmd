@@ -0,0 +1 @@
+tests/ptrace_attach
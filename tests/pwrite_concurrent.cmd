@@ -0,0 +1 @@
+tests/pwrite_concurrent
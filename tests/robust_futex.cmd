@@ -0,0 +1 @@
+tests/robust_futex
@@ -0,0 +1 @@
+tests/rt_signal_order
@@ -0,0 +1 @@
+tests/sigaltstack_fork
@@ -0,0 +1 @@
+tests/sigqueue
@@ -0,0 +1 @@
+tests/socket_shared
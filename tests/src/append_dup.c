@@ -0,0 +1,51 @@
+// Appends through dup'd and separately opened fds of a file, from several
+// processes at once, never overwrite or interleave with each other.
+#include <fcntl.h>
+#include <stdio.h>
+#include <string.h>
+#include <sys/wait.h>
+#include <unistd.h>
+
+#define WRITERS 4
+#define RECORDS 200
+#define RECORD_SIZE 100
+
+int main() {
+    const char *path = "/tmp/append_dup";
+    int fd = open(path, O_RDWR | O_CREAT | O_TRUNC | O_APPEND, 0644);
+    int dup_fd = dup(fd);
+    for (int i = 0; i < WRITERS; i++) {
+        if (fork() == 0) {
+            // half share the open file description, half have their own
+            int out = i % 2 ? dup_fd : open(path, O_WRONLY | O_APPEND);
+            char record[RECORD_SIZE];
+            memset(record, 'a' + i, RECORD_SIZE - 1);
+            record[RECORD_SIZE - 1] = '\n';
+            for (int j = 0; j < RECORDS; j++)
+                write(out, record, RECORD_SIZE);
+            _exit(0);
+        }
+    }
+    while (wait(NULL) > 0)
+        ;
+
+    int counts[WRITERS] = {0}, torn = 0;
+    char record[RECORD_SIZE];
+    lseek(fd, 0, SEEK_SET);
+    while (read(fd, record, RECORD_SIZE) == RECORD_SIZE) {
+        int id = record[0] - 'a';
+        for (int k = 1; k < RECORD_SIZE - 1; k++)
+            torn |= record[k] != record[0];
+        if (id < 0 || id >= WRITERS || record[RECORD_SIZE - 1] != '\n')
+            torn = 1;
+        else
+            counts[id]++;
+    }
+    printf("size: %ld\n", (long)lseek(fd, 0, SEEK_END));
+    for (int i = 0; i < WRITERS; i++)
+        printf("writer %d: %d records\n", i, counts[i]);
+    printf("torn: %d\n", torn);
+    close(fd);
+    unlink(path);
+    return 0;
+}
@@ -0,0 +1,37 @@
+// Sector 0 of each whole disk reads back as written by mkdisks.py:
+// a boot sector with a partition table on the MBR disk, a protective MBR on the GPT one.
+#include <fcntl.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <unistd.h>
+
+static int compare(const void *a, const void *b) {
+    return strcmp((const char *)a, (const char *)b);
+}
+
+int main() {
+    char found[8][32];
+    int count = 0;
+    for (char disk = 'a'; disk <= 'h'; disk++) {
+        char path[16];
+        unsigned char sector[512];
+        sprintf(path, "/dev/vd%c", disk);
+        int fd = open(path, O_RDONLY);
+        if (fd < 0)
+            continue;
+        ssize_t len = read(fd, sector, sizeof(sector));
+        close(fd);
+        if (len != sizeof(sector) || sector[510] != 0x55 || sector[511] != 0xaa)
+            continue;
+        // the type of the first partition entry
+        if (sector[450] == 0xee)
+            strcpy(found[count++], "gpt: protective mbr");
+        else
+            strcpy(found[count++], "mbr: partition table");
+    }
+    qsort(found, count, sizeof(found[0]), compare);
+    for (int i = 0; i < count; i++)
+        printf("%s\n", found[i]);
+    return 0;
+}
@@ -0,0 +1,42 @@
+// File data written through the page cache reaches the disk on sync,
+// leaving no dirty block in the buffer cache.
+#include <fcntl.h>
+#include <stdio.h>
+#include <string.h>
+#include <unistd.h>
+
+static char buf[65536];
+
+// Sum the dirty blocks of all devices in /proc/buffercache
+static int dirty_blocks(void) {
+    FILE *f = fopen("/proc/buffercache", "r");
+    char line[128], dev[64];
+    int buffers, dirty, total = 0;
+    fgets(line, sizeof(line), f);
+    while (fgets(line, sizeof(line), f)) {
+        if (sscanf(line, "%63s %d %d", dev, &buffers, &dirty) == 3)
+            total += dirty;
+    }
+    fclose(f);
+    return total;
+}
+
+int main() {
+    const char *path = "/tmp/buffer_cache";
+    int fd = open(path, O_RDWR | O_CREAT | O_TRUNC, 0644);
+    for (int i = 0; i < sizeof(buf); i++)
+        buf[i] = i % 251;
+    printf("write: %zd\n", write(fd, buf, sizeof(buf)));
+    sync();
+    printf("dirty after sync: %d\n", dirty_blocks());
+
+    memset(buf, 0, sizeof(buf));
+    printf("read: %zd\n", pread(fd, buf, sizeof(buf), 0));
+    int same = 1;
+    for (int i = 0; i < sizeof(buf); i++)
+        same &= buf[i] == (char)(i % 251);
+    printf("content: %s\n", same ? "ok" : "corrupted");
+    close(fd);
+    unlink(path);
+    return 0;
+}
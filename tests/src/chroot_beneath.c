@@ -0,0 +1,53 @@
+// A chroot can not be escaped through a directory opened above it, while
+// directories and symbolic links into file systems mounted inside of it work.
+#include <errno.h>
+#include <fcntl.h>
+#include <stdio.h>
+#include <string.h>
+#include <sys/mount.h>
+#include <sys/stat.h>
+#include <unistd.h>
+
+static void result(const char *what, int ret) {
+    printf("%s: %s\n", what, ret < 0 ? strerror(errno) : "ok");
+}
+
+static void report(const char *what, int fd) {
+    result(what, fd);
+    if (fd >= 0)
+        close(fd);
+}
+
+int main() {
+    mkdir("/tmp/jail", 0755);
+    mkdir("/tmp/jail/mnt", 0755);
+    mkdir("/tmp/jail/sub", 0755);
+    int outside = open("/tmp", O_RDONLY | O_DIRECTORY);
+
+    result("chroot", chroot("/tmp/jail"));
+    chdir("/");
+
+    // the directory opened before is above the root
+    report("openat above root", openat(outside, "jail", O_RDONLY));
+    report("openat .. above root", openat(outside, "..", O_RDONLY));
+
+    printf("mount: %d\n", mount("none", "/mnt", "tmpfs", 0, NULL));
+    mkdir("/mnt/d", 0755);
+    close(open("/mnt/d/file", O_CREAT | O_WRONLY, 0644));
+
+    // a directory in the mounted file system is beneath the root,
+    // and `..` of its root leads to the directory it is mounted on
+    int dir = open("/mnt/d", O_RDONLY | O_DIRECTORY);
+    report("openat in mount", openat(dir, "file", O_RDONLY));
+    report("openat out of mount", openat(dir, "../../sub", O_RDONLY | O_DIRECTORY));
+    report("openat .. of root", openat(dir, "../../../../sub", O_RDONLY | O_DIRECTORY));
+
+    // an absolute target starts at the root, and sees the mount
+    symlink("/mnt/d", "/sub/link");
+    report("absolute symlink into mount", open("/sub/link/file", O_RDONLY));
+
+    close(dir);
+    printf("umount: %d\n", umount("/mnt"));
+    report("after umount", open("/sub/link/file", O_RDONLY));
+    return 0;
+}
@@ -0,0 +1,51 @@
+// Exec in a process sharing the address space with CLONE_VM, and vfork.
+#define _GNU_SOURCE
+#include <errno.h>
+#include <sched.h>
+#include <signal.h>
+#include <stdio.h>
+#include <string.h>
+#include <sys/wait.h>
+#include <unistd.h>
+
+static const char *self = "/tests/clone_vm_exec";
+static char data[] = "parent data";
+static volatile int shared;
+static char stack[16384];
+
+static int exec_child(void *arg) {
+    shared = 1;
+    execl(self, self, "exec", NULL);
+    _exit(1);
+}
+
+int main(int argc, char **argv) {
+    setvbuf(stdout, NULL, _IONBF, 0);
+    if (argc > 1) {
+        printf("child exec'd\n");
+        return 0;
+    }
+
+    // the parent runs again once the child execs, in memory the child wrote to
+    pid_t pid = vfork();
+    if (pid == 0) {
+        usleep(100000);
+        shared = 2;
+        execl(self, self, "exec", NULL);
+        _exit(1);
+    }
+    waitpid(pid, NULL, 0);
+    printf("vfork: shared = %d\n", shared);
+
+    // the exec of a CLONE_VM child leaves our memory alone
+    pid = clone(exec_child, stack + sizeof(stack), CLONE_VM | SIGCHLD, NULL);
+    waitpid(pid, NULL, 0);
+    printf("clone vm: shared = %d, %s\n", shared, data);
+
+    // a process cannot share the file table or handlers
+    pid = clone(exec_child, stack + sizeof(stack), CLONE_VM | CLONE_FILES | SIGCHLD, NULL);
+    printf("clone files: %d %s\n", pid, strerror(errno));
+    pid = clone(exec_child, stack + sizeof(stack), CLONE_VM | CLONE_SIGHAND | SIGCHLD, NULL);
+    printf("clone sighand: %d %s\n", pid, strerror(errno));
+    return 0;
+}
@@ -0,0 +1,38 @@
+// close_range closes every fd in a range, or marks them close-on-exec.
+#define _GNU_SOURCE
+#include <errno.h>
+#include <fcntl.h>
+#include <stdio.h>
+#include <string.h>
+#include <sys/syscall.h>
+#include <unistd.h>
+
+#ifndef SYS_close_range
+#define SYS_close_range 436
+#endif
+#define CLOSE_RANGE_CLOEXEC (1U << 2)
+
+static void show(const char *what, int first, int last) {
+    printf("%s:", what);
+    for (int fd = first; fd <= last; fd++)
+        printf(" %d", fcntl(fd, F_GETFD));
+    printf("\n");
+}
+
+int main() {
+    for (int i = 0; i < 5; i++)
+        dup(1);
+    show("opened", 3, 7);
+    printf("cloexec: %ld\n", syscall(SYS_close_range, 4, 5, CLOSE_RANGE_CLOEXEC));
+    show("marked", 3, 7);
+    printf("close: %ld\n", syscall(SYS_close_range, 6, 6, 0));
+    show("closed one", 3, 7);
+    printf("close to the end: %ld\n", syscall(SYS_close_range, 3, ~0U, 0));
+    show("closed all", 3, 7);
+    long ret = syscall(SYS_close_range, 5, 4, 0);
+    printf("first > last: %ld %s\n", ret, strerror(errno));
+    ret = syscall(SYS_close_range, 3, 4, 0x100);
+    printf("bad flags: %ld %s\n", ret, strerror(errno));
+    printf("stdout still open: %d\n", fcntl(1, F_GETFD) >= 0);
+    return 0;
+}
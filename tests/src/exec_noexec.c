@@ -0,0 +1,30 @@
+// exec of a file that is neither ELF nor a script fails with ENOEXEC, and the caller goes on.
+#include <errno.h>
+#include <fcntl.h>
+#include <stdio.h>
+#include <string.h>
+#include <unistd.h>
+
+static void try_exec(const char *what, const char *path, const char *content, size_t len) {
+    int fd = open(path, O_WRONLY | O_CREAT | O_TRUNC, 0755);
+    write(fd, content, len);
+    close(fd);
+    char *const argv[] = {(char *)path, NULL};
+    char *const envp[] = {NULL};
+    execve(path, argv, envp);
+    printf("%s: %s\n", what, strerror(errno));
+    unlink(path);
+}
+
+int main() {
+    const char text[] = "just some text\n";
+    try_exec("text", "/tmp/exec_text", text, sizeof(text) - 1);
+    // the magic of an ELF file, but nothing after it
+    try_exec("truncated elf", "/tmp/exec_elf", "\177ELF", 4);
+    try_exec("empty", "/tmp/exec_empty", "", 0);
+    char *const argv[] = {"/tmp/no_such_file", NULL};
+    char *const envp[] = {NULL};
+    execve(argv[0], argv, envp);
+    printf("missing: %s\n", strerror(errno));
+    return 0;
+}
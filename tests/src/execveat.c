@@ -0,0 +1,39 @@
+// execveat runs a file relative to a directory fd, or the file of an fd itself.
+#define _GNU_SOURCE
+#include <errno.h>
+#include <fcntl.h>
+#include <stdio.h>
+#include <string.h>
+#include <sys/syscall.h>
+#include <sys/wait.h>
+#include <unistd.h>
+
+static char *const argv[] = {"execveat", "child", NULL};
+static char *const envp[] = {NULL};
+
+static void try(const char *what, int dirfd, const char *path, int flags) {
+    pid_t pid = fork();
+    if (pid == 0) {
+        syscall(SYS_execveat, dirfd, path, argv, envp, flags);
+        printf("%s: %s\n", what, strerror(errno));
+        _exit(0);
+    }
+    waitpid(pid, NULL, 0);
+}
+
+int main(int argc, char **argv) {
+    setvbuf(stdout, NULL, _IONBF, 0);
+    if (argc > 1) {
+        printf("child exec'd\n");
+        return 0;
+    }
+    int dir = open("/tests", O_RDONLY | O_DIRECTORY);
+    int file = open("/tests/execveat", O_RDONLY);
+    try("relative", dir, "execveat", 0);
+    try("empty path", file, "", AT_EMPTY_PATH);
+    try("empty path without flag", file, "", 0);
+    try("directory fd", dir, "", AT_EMPTY_PATH);
+    try("directory", dir, ".", 0);
+    try("unknown flag", dir, "execveat", 0x8000000);
+    return 0;
+}
@@ -0,0 +1,37 @@
+// fallocate extends a file, and zeroes or punches a range inside it.
+#define _GNU_SOURCE
+#include <errno.h>
+#include <fcntl.h>
+#include <stdio.h>
+#include <string.h>
+#include <sys/stat.h>
+#include <unistd.h>
+
+static void show(int fd, const char *what) {
+    struct stat st;
+    fstat(fd, &st);
+    char buf[16] = {0};
+    pread(fd, buf, 12, 0);
+    for (int i = 0; i < 12; i++) {
+        if (buf[i] == 0) {
+            buf[i] = '.';
+        }
+    }
+    printf("%s: size %ld, %s\n", what, (long)st.st_size, buf);
+}
+
+int main() {
+    int fd = open("/tmp/fallocate", O_RDWR | O_CREAT | O_TRUNC, 0644);
+    write(fd, "0123456789", 10);
+    printf("extend: %d\n", fallocate(fd, 0, 8, 4));
+    show(fd, "extended");
+    printf("zero: %d\n", fallocate(fd, FALLOC_FL_ZERO_RANGE, 1, 2));
+    show(fd, "zeroed");
+    printf("punch: %d\n", fallocate(fd, FALLOC_FL_PUNCH_HOLE | FALLOC_FL_KEEP_SIZE, 4, 100));
+    show(fd, "punched");
+    int ret = fallocate(fd, FALLOC_FL_PUNCH_HOLE, 0, 1);
+    printf("punch without keep size: %d %s\n", ret, strerror(errno));
+    close(fd);
+    unlink("/tmp/fallocate");
+    return 0;
+}
@@ -0,0 +1,19 @@
+// CPUs halt while no thread is runnable: most of a sleep counts as idle in /proc/uptime.
+#include <stdio.h>
+#include <unistd.h>
+
+static void read_uptime(double *uptime, double *idle) {
+    FILE *f = fopen("/proc/uptime", "r");
+    fscanf(f, "%lf %lf", uptime, idle);
+    fclose(f);
+}
+
+int main() {
+    double uptime, idle, uptime2, idle2;
+    read_uptime(&uptime, &idle);
+    sleep(1);
+    read_uptime(&uptime2, &idle2);
+    printf("slept: %d\n", uptime2 - uptime >= 0.9);
+    printf("idle while sleeping: %d\n", idle2 - idle >= (uptime2 - uptime) / 2);
+    return 0;
+}
@@ -0,0 +1,28 @@
+// A watch on a directory sees writes to the files in it,
+// and a watch on a file is removed with its last link.
+#include <fcntl.h>
+#include <stdio.h>
+#include <sys/inotify.h>
+#include <unistd.h>
+
+int main() {
+    int in = inotify_init1(IN_NONBLOCK);
+    int dir = inotify_add_watch(in, "/tmp", IN_MODIFY | IN_CLOSE_WRITE | IN_DELETE);
+    int fd = open("/tmp/inotify_dir", O_RDWR | O_CREAT | O_TRUNC, 0644);
+    int file = inotify_add_watch(in, "/tmp/inotify_dir", IN_DELETE_SELF);
+    write(fd, "data", 4);
+    ftruncate(fd, 2);
+    close(fd);
+    unlink("/tmp/inotify_dir");
+
+    char buf[4096];
+    ssize_t len = read(in, buf, sizeof(buf));
+    for (char *p = buf; p < buf + len;) {
+        struct inotify_event *event = (struct inotify_event *)p;
+        printf("%s %#x %s\n", event->wd == dir ? "dir" : event->wd == file ? "file" : "?",
+               event->mask, event->len ? event->name : "-");
+        p += sizeof(struct inotify_event) + event->len;
+    }
+    printf("removed: %d\n", inotify_rm_watch(in, file));
+    return 0;
+}
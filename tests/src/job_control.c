@@ -0,0 +1,30 @@
+// A parent sees its child stop and continue through waitpid.
+#include <signal.h>
+#include <stdio.h>
+#include <sys/wait.h>
+#include <unistd.h>
+
+int main() {
+    setvbuf(stdout, NULL, _IONBF, 0);
+    pid_t pid = fork();
+    if (pid == 0) {
+        for (;;)
+            pause();
+    }
+
+    int status;
+    kill(pid, SIGTSTP);
+    waitpid(pid, &status, WUNTRACED);
+    printf("stopped: %d, by SIGTSTP: %d\n", WIFSTOPPED(status), WSTOPSIG(status) == SIGTSTP);
+    // a stop is reported once
+    printf("again: %d\n", waitpid(pid, &status, WUNTRACED | WNOHANG));
+
+    kill(pid, SIGCONT);
+    waitpid(pid, &status, WCONTINUED);
+    printf("continued: %d\n", WIFCONTINUED(status));
+
+    kill(pid, SIGKILL);
+    waitpid(pid, &status, 0);
+    printf("killed: %d\n", WIFSIGNALED(status) && WTERMSIG(status) == SIGKILL);
+    return 0;
+}
@@ -0,0 +1,88 @@
+// Seals of a memfd: F_SEAL_WRITE is refused while the memfd is mapped shared,
+// nothing can be added after F_SEAL_SEAL, and the seals stay with the memfd
+// when it is passed to another process over a unix socket.
+#define _GNU_SOURCE
+#include <errno.h>
+#include <fcntl.h>
+#include <stdio.h>
+#include <string.h>
+#include <sys/mman.h>
+#include <sys/socket.h>
+#include <sys/wait.h>
+#include <unistd.h>
+
+static ssize_t send_fd(int sock, int fd) {
+    char byte = 'x';
+    struct iovec iov = {&byte, 1};
+    char control[CMSG_SPACE(sizeof(int))] = {0};
+    struct msghdr msg = {0};
+    msg.msg_iov = &iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control;
+    msg.msg_controllen = sizeof(control);
+    struct cmsghdr *cmsg = CMSG_FIRSTHDR(&msg);
+    cmsg->cmsg_level = SOL_SOCKET;
+    cmsg->cmsg_type = SCM_RIGHTS;
+    cmsg->cmsg_len = CMSG_LEN(sizeof(int));
+    memcpy(CMSG_DATA(cmsg), &fd, sizeof(int));
+    return sendmsg(sock, &msg, 0);
+}
+
+static int recv_fd(int sock) {
+    char byte;
+    struct iovec iov = {&byte, 1};
+    char control[CMSG_SPACE(sizeof(int))] = {0};
+    struct msghdr msg = {0};
+    msg.msg_iov = &iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control;
+    msg.msg_controllen = sizeof(control);
+    printf("recvmsg: %zd %c\n", recvmsg(sock, &msg, 0), byte);
+    struct cmsghdr *cmsg = CMSG_FIRSTHDR(&msg);
+    if (cmsg == NULL || cmsg->cmsg_type != SCM_RIGHTS) {
+        return -1;
+    }
+    int fd;
+    memcpy(&fd, CMSG_DATA(cmsg), sizeof(int));
+    return fd;
+}
+
+int main() {
+    setvbuf(stdout, NULL, _IONBF, 0);
+    int fd = memfd_create("seals", MFD_ALLOW_SEALING);
+    write(fd, "sealed data", 11);
+
+    // even a read-only shared mapping could be made writable by mprotect
+    char *map = mmap(NULL, 4096, PROT_READ, MAP_SHARED, fd, 0);
+    int ret = fcntl(fd, F_ADD_SEALS, F_SEAL_WRITE);
+    printf("seal while mapped: %s\n", ret ? strerror(errno) : "sealed");
+    munmap(map, 4096);
+    ret = fcntl(fd, F_ADD_SEALS, F_SEAL_WRITE | F_SEAL_SHRINK | F_SEAL_GROW);
+    printf("seal after munmap: %s\n", ret ? strerror(errno) : "sealed");
+    fcntl(fd, F_ADD_SEALS, F_SEAL_SEAL);
+    ret = fcntl(fd, F_ADD_SEALS, F_SEAL_SHRINK);
+    printf("seal after F_SEAL_SEAL: %s\n", ret ? strerror(errno) : "sealed");
+
+    int sv[2];
+    printf("socketpair: %d\n", socketpair(AF_UNIX, SOCK_STREAM, 0, sv));
+    pid_t pid = fork();
+    if (pid == 0) {
+        close(sv[0]);
+        int got = recv_fd(sv[1]);
+        printf("received: %s\n", got < 0 ? "nothing" : "fd");
+        printf("seals: %#x\n", fcntl(got, F_GET_SEALS));
+        map = mmap(NULL, 4096, PROT_READ, MAP_SHARED, got, 0);
+        printf("mapped: %.11s\n", map == MAP_FAILED ? strerror(errno) : map);
+        map = mmap(NULL, 4096, PROT_READ | PROT_WRITE, MAP_SHARED, got, 0);
+        printf("writable mapping: %s\n", map == MAP_FAILED ? "refused" : "mapped");
+        printf("write: %zd\n", write(got, "x", 1));
+        _exit(0);
+    }
+    close(sv[1]);
+    ssize_t sent = send_fd(sv[0], fd);
+    // the child's copy keeps the memfd alive
+    close(fd);
+    waitpid(pid, NULL, 0);
+    printf("sendmsg: %zd\n", sent);
+    return 0;
+}
@@ -0,0 +1,47 @@
+// A device node made by mknod opens the driver of its device number:
+// reads and writes through it reach the same disk as /dev/vda.
+#include <errno.h>
+#include <fcntl.h>
+#include <stdio.h>
+#include <string.h>
+#include <sys/stat.h>
+#include <sys/sysmacros.h>
+#include <unistd.h>
+
+int main() {
+    struct stat st;
+    if (stat("/dev/vda", &st) < 0) {
+        printf("stat: %s\n", strerror(errno));
+        return 1;
+    }
+    printf("mknod: %d\n", mknod("/tmp/disk", S_IFBLK | 0600, st.st_rdev));
+    stat("/tmp/disk", &st);
+    printf("block device: %d\n", S_ISBLK(st.st_mode));
+
+    unsigned char orig[512], sector[512], check[512];
+    int dev = open("/dev/vda", O_RDWR);
+    int node = open("/tmp/disk", O_RDWR);
+    printf("open: %d\n", node >= 0);
+    pread(dev, orig, sizeof(orig), 0);
+    printf("read: %zd\n", pread(node, sector, sizeof(sector), 0));
+    printf("same sector: %d\n", memcmp(orig, sector, sizeof(sector)) == 0);
+
+    // change the first byte through the node and see it on the disk
+    sector[0] ^= 0xff;
+    printf("write: %zd\n", pwrite(node, sector, sizeof(sector), 0));
+    pread(dev, check, sizeof(check), 0);
+    printf("written: %d\n", check[0] == sector[0]);
+    pwrite(node, orig, sizeof(orig), 0);
+    pread(dev, check, sizeof(check), 0);
+    printf("restored: %d\n", memcmp(orig, check, sizeof(check)) == 0);
+    close(node);
+    close(dev);
+
+    // no driver has this device number
+    printf("mknod unknown: %d\n", mknod("/tmp/nodev", S_IFCHR | 0600, makedev(240, 0)));
+    int fd = open("/tmp/nodev", O_RDWR);
+    printf("open unknown: %d %s\n", fd, strerror(errno));
+    unlink("/tmp/disk");
+    unlink("/tmp/nodev");
+    return 0;
+}
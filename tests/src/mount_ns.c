@@ -0,0 +1,87 @@
+// A tmpfs mounted in a new mount namespace, seen through paths and directory fds
+// by its process but not by the parent. Mounting needs CAP_SYS_ADMIN.
+#define _GNU_SOURCE
+#include <errno.h>
+#include <fcntl.h>
+#include <linux/capability.h>
+#include <sched.h>
+#include <stdio.h>
+#include <string.h>
+#include <sys/mount.h>
+#include <sys/stat.h>
+#include <sys/syscall.h>
+#include <sys/wait.h>
+#include <unistd.h>
+
+static const char *found(int dirfd, const char *path) {
+    struct stat st;
+    return fstatat(dirfd, path, &st, 0) == 0 ? "found" : strerror(errno);
+}
+
+static void drop_sys_admin(void) {
+    struct __user_cap_header_struct hdr = {_LINUX_CAPABILITY_VERSION_3, 0};
+    struct __user_cap_data_struct data[2];
+    syscall(SYS_capget, &hdr, data);
+    data[0].effective &= ~(1 << CAP_SYS_ADMIN);
+    data[0].permitted &= ~(1 << CAP_SYS_ADMIN);
+    syscall(SYS_capset, &hdr, data);
+}
+
+static void result(const char *what, int ret) {
+    printf("%s: %d %s\n", what, ret, ret < 0 ? strerror(errno) : "ok");
+}
+
+int main() {
+    setvbuf(stdout, NULL, _IONBF, 0);
+    mkdir("/tmp/mount_ns", 0755);
+    mkdir("/tmp/mount_ns/mnt", 0755);
+    mkdir("/tmp/mount_ns/a", 0755);
+    mkdir("/tmp/mount_ns/b", 0755);
+    mkdir("/tmp/mount_ns/b/target", 0755);
+    close(open("/tmp/mount_ns/b/marker", O_CREAT | O_WRONLY, 0644));
+    symlink("/tmp/mount_ns/b/target", "/tmp/mount_ns/a/link");
+    int dirfd = open("/tmp/mount_ns", O_RDONLY | O_DIRECTORY);
+
+    // `..` of a symbolic link to a directory is the parent of the directory
+    printf("link/..: %s\n", found(dirfd, "a/link/../marker"));
+
+    pid_t pid = fork();
+    if (pid == 0) {
+        printf("unshare: %d\n", unshare(CLONE_NEWNS));
+        printf("mount: %d\n", mount("none", "/tmp/mount_ns/mnt", "tmpfs", 0, NULL));
+        close(open("/tmp/mount_ns/mnt/inside", O_CREAT | O_WRONLY, 0644));
+        printf("child path: %s\n", found(AT_FDCWD, "/tmp/mount_ns/mnt/inside"));
+        printf("child dirfd: %s\n", found(dirfd, "mnt/inside"));
+        printf("child mnt/..: %s\n", found(dirfd, "mnt/../b/marker"));
+        _exit(0);
+    }
+    waitpid(pid, NULL, 0);
+    printf("parent path: %s\n", found(AT_FDCWD, "/tmp/mount_ns/mnt/inside"));
+    printf("parent dirfd: %s\n", found(dirfd, "mnt/inside"));
+
+    // a mount in our own namespace goes away with umount
+    mount("none", "/tmp/mount_ns/mnt", "tmpfs", 0, NULL);
+    close(open("/tmp/mount_ns/mnt/inside", O_CREAT | O_WRONLY, 0644));
+    printf("mounted: %s\n", found(dirfd, "mnt/inside"));
+    printf("umount: %d\n", umount("/tmp/mount_ns/a/../mnt"));
+    printf("unmounted: %s\n", found(dirfd, "mnt/inside"));
+
+    pid = fork();
+    if (pid == 0) {
+        drop_sys_admin();
+        result("unprivileged unshare", unshare(CLONE_NEWNS));
+        result("unprivileged mount", mount("none", "/tmp/mount_ns/mnt", "tmpfs", 0, NULL));
+        _exit(0);
+    }
+    waitpid(pid, NULL, 0);
+    mount("none", "/tmp/mount_ns/mnt", "tmpfs", 0, NULL);
+    pid = fork();
+    if (pid == 0) {
+        drop_sys_admin();
+        result("unprivileged umount", umount("/tmp/mount_ns/mnt"));
+        _exit(0);
+    }
+    waitpid(pid, NULL, 0);
+    printf("umount: %d\n", umount("/tmp/mount_ns/mnt"));
+    return 0;
+}
@@ -0,0 +1,37 @@
+// A shared mapping can only be made writable if the file can be written.
+#define _GNU_SOURCE
+#include <errno.h>
+#include <fcntl.h>
+#include <stdio.h>
+#include <string.h>
+#include <sys/mman.h>
+#include <unistd.h>
+
+static void protect(const char *name, void *map) {
+    int ret = mprotect(map, 4096, PROT_READ | PROT_WRITE);
+    printf("%s: %s\n", name, ret ? strerror(errno) : "writable");
+}
+
+int main() {
+    const char *path = "/tmp/mprotect_shared";
+    int fd = open(path, O_RDWR | O_CREAT | O_TRUNC, 0644);
+    ftruncate(fd, 4096);
+    close(fd);
+
+    fd = open(path, O_RDONLY);
+    protect("read-only fd", mmap(NULL, 4096, PROT_READ, MAP_SHARED, fd, 0));
+    protect("private", mmap(NULL, 4096, PROT_READ, MAP_PRIVATE, fd, 0));
+    close(fd);
+    fd = open(path, O_RDWR);
+    protect("read-write fd", mmap(NULL, 4096, PROT_READ, MAP_SHARED, fd, 0));
+    close(fd);
+    unlink(path);
+
+    fd = memfd_create("sealed", MFD_ALLOW_SEALING);
+    ftruncate(fd, 4096);
+    // a seal can not be added while the memfd is mapped shared, so seal first
+    fcntl(fd, F_ADD_SEALS, F_SEAL_WRITE);
+    void *map = mmap(NULL, 4096, PROT_READ, MAP_SHARED, fd, 0);
+    protect("sealed memfd", map);
+    return 0;
+}
@@ -0,0 +1,40 @@
+// Shared file mappings and file I/O see the same pages of the page cache.
+#include <errno.h>
+#include <fcntl.h>
+#include <stdio.h>
+#include <string.h>
+#include <sys/mman.h>
+#include <unistd.h>
+
+int main() {
+    const char *path = "/tmp/page_cache";
+    int fd = open(path, O_RDWR | O_CREAT | O_TRUNC, 0644);
+    ftruncate(fd, 8192);
+
+    // a write through the mapping is seen by read, and the other way round
+    char *map = mmap(NULL, 8192, PROT_READ | PROT_WRITE, MAP_SHARED, fd, 0);
+    strcpy(map + 4096, "written by mmap");
+    char buf[32] = {0};
+    pread(fd, buf, 15, 4096);
+    printf("read: %s\n", buf);
+    pwrite(fd, "written by write", 16, 0);
+    printf("mapped: %.16s\n", map);
+
+    // only the second page is in range
+    printf("msync: %d\n", msync(map + 4096, 4096, MS_SYNC));
+    munmap(map, 8192);
+    printf("close: %d\n", close(fd));
+
+    // a read-only fd can not be mapped shared and writable
+    fd = open(path, O_RDONLY);
+    map = mmap(NULL, 4096, PROT_READ | PROT_WRITE, MAP_SHARED, fd, 0);
+    printf("shared writable: %s\n", map == MAP_FAILED ? strerror(errno) : "mapped");
+    map = mmap(NULL, 4096, PROT_READ | PROT_WRITE, MAP_PRIVATE, fd, 0);
+    printf("private writable: %s\n", map == MAP_FAILED ? strerror(errno) : "mapped");
+    memset(buf, 0, sizeof(buf));
+    read(fd, buf, 16);
+    printf("reopened: %s\n", buf);
+    close(fd);
+    unlink(path);
+    return 0;
+}
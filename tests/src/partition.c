@@ -0,0 +1,37 @@
+// Partitions of the MBR and GPT disks made by mkdisks.py are block devices in /dev.
+// Each starts with its own name, and the disks may come in any order.
+#include <fcntl.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <sys/stat.h>
+#include <unistd.h>
+
+static int compare(const void *a, const void *b) {
+    return strcmp((const char *)a, (const char *)b);
+}
+
+int main() {
+    char found[32][64];
+    int count = 0;
+    for (char disk = 'a'; disk <= 'h'; disk++) {
+        for (int part = 1; part < 16; part++) {
+            char path[32], name[8] = {0};
+            sprintf(path, "/dev/vd%c%d", disk, part);
+            int fd = open(path, O_RDONLY);
+            if (fd < 0)
+                continue;
+            struct stat st;
+            fstat(fd, &st);
+            read(fd, name, 4);
+            close(fd);
+            if ((strncmp(name, "mbr", 3) == 0 || strncmp(name, "gpt", 3) == 0) && count < 32)
+                sprintf(found[count++], "%s: partition %d, %ld blocks, block device %d", name,
+                        part, (long)st.st_size / 512, S_ISBLK(st.st_mode));
+        }
+    }
+    qsort(found, count, sizeof(found[0]), compare);
+    for (int i = 0; i < count; i++)
+        printf("%s\n", found[i]);
+    return 0;
+}
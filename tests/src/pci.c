@@ -0,0 +1,30 @@
+// List the virtio functions found by the PCI scan and their BARs, from /proc/pci.
+// Boards without PCI, like the riscv32 one of test.sh, have no /proc/pci,
+// so this has no .cmd and is run by hand as init on x86_64.
+#include <stdio.h>
+#include <string.h>
+
+// vendor id of virtio devices
+#define VIRTIO_VENDOR "1af4:"
+
+int main() {
+    FILE *f = fopen("/proc/pci", "r");
+    if (f == NULL) {
+        perror("/proc/pci");
+        return 1;
+    }
+    char line[128];
+    int virtio = 0, count = 0;
+    while (fgets(line, sizeof(line), f)) {
+        if (line[0] != ' ') {
+            virtio = strstr(line, " " VIRTIO_VENDOR) != NULL;
+            count += virtio;
+        }
+        if (virtio) {
+            fputs(line, stdout);
+        }
+    }
+    printf("%d virtio functions\n", count);
+    fclose(f);
+    return 0;
+}
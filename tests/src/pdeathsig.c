@@ -0,0 +1,46 @@
+// A child asking for SIGTERM on the death of its parent gets it when the parent exits.
+#include <signal.h>
+#include <stdio.h>
+#include <sys/prctl.h>
+#include <sys/wait.h>
+#include <unistd.h>
+
+static int done[2];
+
+static void handler(int signo) {
+    printf("child got signal %d\n", signo);
+    write(done[1], "x", 1);
+    _exit(0);
+}
+
+int main() {
+    setvbuf(stdout, NULL, _IONBF, 0);
+    int ready[2];
+    pipe(ready);
+    pipe(done);
+
+    pid_t parent = fork();
+    if (parent == 0) {
+        if (fork() == 0) {
+            signal(SIGTERM, handler);
+            prctl(PR_SET_PDEATHSIG, SIGTERM);
+            int signo = 0;
+            prctl(PR_GET_PDEATHSIG, &signo);
+            printf("pdeathsig: %d\n", signo);
+            write(ready[1], "x", 1);
+            while (1) {
+                pause();
+            }
+        }
+        char c;
+        read(ready[0], &c, 1);
+        printf("parent exits\n");
+        _exit(0);
+    }
+    waitpid(parent, NULL, 0);
+
+    char c;
+    read(done[0], &c, 1);
+    printf("done\n");
+    return 0;
+}
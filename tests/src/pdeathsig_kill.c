@@ -0,0 +1,35 @@
+// A child with PDEATHSIG set to SIGKILL is killed when its parent exits.
+#include <signal.h>
+#include <stdio.h>
+#include <sys/prctl.h>
+#include <sys/wait.h>
+#include <unistd.h>
+
+int main() {
+    setvbuf(stdout, NULL, _IONBF, 0);
+    int ready[2], alive[2];
+    pipe(ready);
+    pipe(alive);
+
+    // the child holds the write end of `alive` until it dies
+    pid_t parent = fork();
+    if (parent == 0) {
+        if (fork() == 0) {
+            prctl(PR_SET_PDEATHSIG, SIGKILL);
+            write(ready[1], "x", 1);
+            while (1) {
+                pause();
+            }
+        }
+        char c;
+        read(ready[0], &c, 1);
+        printf("parent exits\n");
+        _exit(0);
+    }
+    close(alive[1]);
+    waitpid(parent, NULL, 0);
+
+    char c;
+    printf("child killed: %d\n", read(alive[0], &c, 1) == 0);
+    return 0;
+}
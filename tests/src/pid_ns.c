@@ -0,0 +1,45 @@
+// Nested pid namespaces, and thread ids seen from them.
+#define _GNU_SOURCE
+#include <pthread.h>
+#include <sched.h>
+#include <signal.h>
+#include <stdio.h>
+#include <sys/syscall.h>
+#include <sys/wait.h>
+#include <unistd.h>
+
+static char stacks[2][16384];
+
+static void *thread(void *arg) {
+    printf("thread: tid %ld\n", syscall(SYS_gettid));
+    return NULL;
+}
+
+static int inner(void *arg) {
+    printf("inner: pid %d, ppid %d\n", getpid(), getppid());
+    pthread_t t;
+    pthread_create(&t, NULL, thread, NULL);
+    pthread_join(t, NULL);
+    return 0;
+}
+
+static int outer(void *arg) {
+    printf("outer: pid %d, ppid %d\n", getpid(), getppid());
+    pid_t pid = fork();
+    if (pid == 0) {
+        printf("outer child: pid %d\n", getpid());
+        return 0;
+    }
+    waitpid(pid, NULL, 0);
+    pid = clone(inner, stacks[1] + sizeof(stacks[1]), CLONE_NEWPID | SIGCHLD, NULL);
+    printf("outer: inner pid %d\n", pid);
+    waitpid(pid, NULL, 0);
+    return 0;
+}
+
+int main() {
+    setvbuf(stdout, NULL, _IONBF, 0);
+    pid_t pid = clone(outer, stacks[0] + sizeof(stacks[0]), CLONE_NEWPID | SIGCHLD, NULL);
+    waitpid(pid, NULL, 0);
+    return 0;
+}
@@ -0,0 +1,51 @@
+// A write to a pipe with no reader fails with EPIPE and raises SIGPIPE,
+// which kills a writer like `yes | head` once the reader is gone.
+#include <errno.h>
+#include <poll.h>
+#include <signal.h>
+#include <stdio.h>
+#include <string.h>
+#include <sys/wait.h>
+#include <unistd.h>
+
+static volatile int sigpipes;
+
+static void handler(int signo) {
+    sigpipes++;
+}
+
+int main() {
+    setvbuf(stdout, NULL, _IONBF, 0);
+    int fds[2];
+    pipe(fds);
+    close(fds[0]);
+    signal(SIGPIPE, handler);
+    ssize_t ret = write(fds[1], "x", 1);
+    printf("write: %zd %s, SIGPIPE %d\n", ret, strerror(errno), sigpipes);
+    struct pollfd pfd = {.fd = fds[1], .events = POLLOUT};
+    poll(&pfd, 1, 0);
+    printf("poll: hangup %d\n", (pfd.revents & (POLLERR | POLLHUP)) != 0);
+    close(fds[1]);
+    signal(SIGPIPE, SIG_DFL);
+
+    // the writer is blocked on a full pipe when the reader closes it
+    pipe(fds);
+    pid_t pid = fork();
+    if (pid == 0) {
+        close(fds[0]);
+        char buf[4096];
+        memset(buf, 'y', sizeof(buf));
+        while (write(fds[1], buf, sizeof(buf)) > 0)
+            ;
+        _exit(0);
+    }
+    close(fds[1]);
+    char buf[10];
+    read(fds[0], buf, sizeof(buf));
+    usleep(100000);
+    close(fds[0]);
+    int status;
+    waitpid(pid, &status, 0);
+    printf("writer: signaled %d, signal %d\n", WIFSIGNALED(status), WTERMSIG(status));
+    return 0;
+}
@@ -0,0 +1,18 @@
+// F_SETPIPE_SZ rounds up to pages. pipe-max-size only bounds unprivileged
+// processes, and every process is root.
+#define _GNU_SOURCE
+#include <fcntl.h>
+#include <stdio.h>
+#include <unistd.h>
+
+int main() {
+    int fds[2];
+    pipe(fds);
+    printf("default: %d\n", fcntl(fds[0], F_GETPIPE_SZ));
+    printf("set 5000: %d\n", fcntl(fds[1], F_SETPIPE_SZ, 5000));
+    printf("set 1M: %d\n", fcntl(fds[1], F_SETPIPE_SZ, 1 << 20));
+    printf("set 1M+1: %d\n", fcntl(fds[1], F_SETPIPE_SZ, (1 << 20) + 1));
+    printf("set 1G: %d\n", fcntl(fds[1], F_SETPIPE_SZ, 1 << 30));
+    printf("size: %d\n", fcntl(fds[0], F_GETPIPE_SZ));
+    return 0;
+}
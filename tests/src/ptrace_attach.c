@@ -0,0 +1,76 @@
+// PTRACE_ATTACH takes the pid as seen in the pid namespace of the tracer,
+// and needs CAP_SYS_PTRACE to attach to a child which made itself non-dumpable.
+#define _GNU_SOURCE
+#include <errno.h>
+#include <linux/capability.h>
+#include <sched.h>
+#include <signal.h>
+#include <stdio.h>
+#include <string.h>
+#include <sys/prctl.h>
+#include <sys/ptrace.h>
+#include <sys/syscall.h>
+#include <sys/wait.h>
+#include <unistd.h>
+
+static char stack[16384];
+
+static pid_t spawn(int dumpable) {
+    int fds[2];
+    pipe(fds);
+    pid_t pid = fork();
+    if (pid == 0) {
+        prctl(PR_SET_DUMPABLE, dumpable);
+        write(fds[1], "x", 1);
+        for (;;)
+            pause();
+    }
+    char c;
+    read(fds[0], &c, 1);
+    close(fds[0]);
+    close(fds[1]);
+    printf("child pid: %d\n", pid);
+    return pid;
+}
+
+static void attach(const char *what, pid_t pid) {
+    long ret = ptrace(PTRACE_ATTACH, pid, NULL, NULL);
+    printf("%s: %ld %s\n", what, ret, ret < 0 ? strerror(errno) : "ok");
+}
+
+static void kill_child(pid_t pid) {
+    kill(pid, SIGKILL);
+    int status;
+    while (waitpid(pid, &status, 0) == pid && !WIFSIGNALED(status))
+        ;
+    printf("killed: %d\n", WTERMSIG(status));
+}
+
+static void drop_sys_ptrace(void) {
+    struct __user_cap_header_struct hdr = {_LINUX_CAPABILITY_VERSION_3, 0};
+    struct __user_cap_data_struct data[2];
+    syscall(SYS_capget, &hdr, data);
+    data[0].effective &= ~(1 << CAP_SYS_PTRACE);
+    data[0].permitted &= ~(1 << CAP_SYS_PTRACE);
+    printf("capset: %ld\n", syscall(SYS_capset, &hdr, data));
+}
+
+static int init(void *arg) {
+    drop_sys_ptrace();
+    pid_t pid = spawn(0);
+    attach("attach non-dumpable", pid);
+    kill_child(pid);
+
+    pid = spawn(1);
+    attach("attach", pid);
+    attach("attach again", pid);
+    kill_child(pid);
+    return 0;
+}
+
+int main() {
+    setvbuf(stdout, NULL, _IONBF, 0);
+    pid_t pid = clone(init, stack + sizeof(stack), CLONE_NEWPID | SIGCHLD, NULL);
+    waitpid(pid, NULL, 0);
+    return 0;
+}
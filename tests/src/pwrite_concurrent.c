@@ -0,0 +1,68 @@
+// Threads pwrite to their own parts of one fd at once, without moving its offset.
+// pread works on seekable devices and fails on pipes.
+#include <errno.h>
+#include <fcntl.h>
+#include <pthread.h>
+#include <stdio.h>
+#include <string.h>
+#include <unistd.h>
+
+#define THREADS 4
+#define ROUNDS 64
+#define BLOCK 512
+
+static int fd;
+
+static void *writer(void *arg) {
+    long id = (long)arg;
+    char buf[BLOCK];
+    memset(buf, 'a' + id, sizeof(buf));
+    for (int i = 0; i < ROUNDS; i++) {
+        off_t offset = ((off_t)i * THREADS + id) * BLOCK;
+        if (pwrite(fd, buf, BLOCK, offset) != BLOCK) {
+            printf("thread %ld: short pwrite\n", id);
+        }
+    }
+    return NULL;
+}
+
+int main() {
+    fd = open("/tmp/pwrite_concurrent", O_RDWR | O_CREAT | O_TRUNC, 0644);
+    write(fd, "start", 5);
+    pthread_t threads[THREADS];
+    for (long i = 0; i < THREADS; i++) {
+        pthread_create(&threads[i], NULL, writer, (void *)i);
+    }
+    for (int i = 0; i < THREADS; i++) {
+        pthread_join(threads[i], NULL);
+    }
+    printf("offset: %ld\n", lseek(fd, 0, SEEK_CUR));
+
+    int bad = 0;
+    char buf[BLOCK];
+    for (int block = 0; block < THREADS * ROUNDS; block++) {
+        pread(fd, buf, BLOCK, (off_t)block * BLOCK);
+        for (int j = 0; j < BLOCK; j++) {
+            if (buf[j] != 'a' + block % THREADS) {
+                bad++;
+                break;
+            }
+        }
+    }
+    printf("bad blocks: %d\n", bad);
+    close(fd);
+    unlink("/tmp/pwrite_concurrent");
+
+    ssize_t len;
+    int zero = open("/dev/zero", O_RDONLY);
+    memset(buf, 1, 4);
+    len = pread(zero, buf, 4, 4096);
+    printf("/dev/zero: %zd %d\n", len, buf[0] | buf[3]);
+    int null = open("/dev/null", O_WRONLY);
+    printf("/dev/null: %zd\n", pwrite(null, "x", 1, 100));
+    int p[2];
+    pipe(p);
+    len = pwrite(p[1], "x", 1, 0);
+    printf("pipe: %zd %s\n", len, strerror(errno));
+    return 0;
+}
@@ -0,0 +1,54 @@
+// readahead returns at once, and warms the page cache for a later read.
+#define _GNU_SOURCE
+#include <fcntl.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <time.h>
+#include <unistd.h>
+
+#define SIZE (1024 * 1024)
+
+static long elapsed_us(struct timespec *start) {
+    struct timespec now;
+    clock_gettime(CLOCK_MONOTONIC, &now);
+    return (now.tv_sec - start->tv_sec) * 1000000 + (now.tv_nsec - start->tv_nsec) / 1000;
+}
+
+// time reading the whole file
+static long read_all(int fd, char *buf) {
+    struct timespec start;
+    clock_gettime(CLOCK_MONOTONIC, &start);
+    pread(fd, buf, SIZE, 0);
+    return elapsed_us(&start);
+}
+
+int main() {
+    // on the disk rather than on tmpfs, whose pages are in memory anyway
+    const char *path = "/readahead_test";
+    char *buf = malloc(SIZE);
+    memset(buf, 'r', SIZE);
+    int fd = open(path, O_RDWR | O_CREAT | O_TRUNC, 0644);
+    write(fd, buf, SIZE);
+    fsync(fd);
+
+    // a read after dropping the cached pages goes to the disk
+    posix_fadvise(fd, 0, 0, POSIX_FADV_DONTNEED);
+    long cold = read_all(fd, buf);
+
+    posix_fadvise(fd, 0, 0, POSIX_FADV_DONTNEED);
+    struct timespec start;
+    clock_gettime(CLOCK_MONOTONIC, &start);
+    printf("readahead: %zd\n", readahead(fd, 0, SIZE));
+    long queued = elapsed_us(&start);
+    // give it time to finish
+    usleep(500000);
+    long warm = read_all(fd, buf);
+
+    printf("returned before the reads: %s\n", queued < cold ? "yes" : "no");
+    printf("warm read faster: %s\n", warm < cold ? "yes" : "no");
+    printf("data: %s\n", buf[0] == 'r' && buf[SIZE - 1] == 'r' ? "ok" : "bad");
+    close(fd);
+    unlink(path);
+    return 0;
+}
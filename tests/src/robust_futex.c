@@ -0,0 +1,81 @@
+// Futexes in the robust list of a thread that dies holding them are marked FUTEX_OWNER_DIED,
+// so the next locker can take them over.
+#define _GNU_SOURCE
+#include <errno.h>
+#include <linux/futex.h>
+#include <pthread.h>
+#include <stddef.h>
+#include <stdio.h>
+#include <string.h>
+#include <sys/mman.h>
+#include <sys/syscall.h>
+#include <sys/wait.h>
+#include <unistd.h>
+
+struct lock {
+    struct robust_list entry;
+    int futex;
+};
+
+struct shared {
+    struct lock plain, pi;
+    pthread_mutex_t mutex;
+};
+
+static struct shared *shared;
+
+// die holding both locks, without unlocking them as pthread_exit would
+static void die_holding(void) {
+    static struct robust_list_head head;
+    int tid = syscall(SYS_gettid);
+    head.list.next = &shared->plain.entry;
+    shared->plain.entry.next = (struct robust_list *)((char *)&shared->pi.entry + 1);
+    shared->pi.entry.next = &head.list;
+    head.futex_offset = offsetof(struct lock, futex) - offsetof(struct lock, entry);
+    head.list_op_pending = NULL;
+    shared->plain.futex = tid;
+    shared->pi.futex = tid;
+    syscall(SYS_set_robust_list, &head, sizeof(head));
+
+    struct robust_list_head *got;
+    size_t len;
+    syscall(SYS_get_robust_list, 0, &got, &len);
+    printf("get_robust_list: %d %d\n", got == &head, len == sizeof(head));
+    _exit(0);
+}
+
+int main() {
+    setvbuf(stdout, NULL, _IONBF, 0);
+    shared = mmap(NULL, 4096, PROT_READ | PROT_WRITE, MAP_SHARED | MAP_ANONYMOUS, -1, 0);
+    long ret = syscall(SYS_set_robust_list, NULL, 1);
+    printf("bad length: %ld %s\n", ret, strerror(errno));
+
+    pid_t pid = fork();
+    if (pid == 0)
+        die_holding();
+    waitpid(pid, NULL, 0);
+    printf("plain: %#x\n", shared->plain.futex);
+    printf("pi: %#x\n", shared->pi.futex);
+    int tid = syscall(SYS_gettid);
+    ret = syscall(SYS_futex, &shared->pi.futex, FUTEX_LOCK_PI, 0, NULL, NULL, 0);
+    printf("lock pi: %ld, owner died %d, owner is me %d\n", ret,
+           (shared->pi.futex & FUTEX_OWNER_DIED) != 0, (shared->pi.futex & FUTEX_TID_MASK) == tid);
+
+    // a robust mutex of a process killed holding it
+    pthread_mutexattr_t attr;
+    pthread_mutexattr_init(&attr);
+    pthread_mutexattr_setpshared(&attr, PTHREAD_PROCESS_SHARED);
+    pthread_mutexattr_setrobust(&attr, PTHREAD_MUTEX_ROBUST);
+    pthread_mutex_init(&shared->mutex, &attr);
+    pid = fork();
+    if (pid == 0) {
+        pthread_mutex_lock(&shared->mutex);
+        _exit(0);
+    }
+    waitpid(pid, NULL, 0);
+    int err = pthread_mutex_lock(&shared->mutex);
+    printf("mutex: %s\n", strerror(err));
+    printf("consistent: %d\n", pthread_mutex_consistent(&shared->mutex));
+    printf("unlock: %d\n", pthread_mutex_unlock(&shared->mutex));
+    return 0;
+}
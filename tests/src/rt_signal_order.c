@@ -0,0 +1,35 @@
+// Pending signals are taken lowest-numbered first, and queued
+// instances of a real-time signal in the order they were sent.
+#include <signal.h>
+#include <stdio.h>
+#include <unistd.h>
+
+static void queue(int signo, int value) {
+    union sigval sigval = {.sival_int = value};
+    sigqueue(getpid(), signo, sigval);
+}
+
+int main() {
+    sigset_t set;
+    sigemptyset(&set);
+    sigaddset(&set, SIGUSR1);
+    sigaddset(&set, SIGRTMIN);
+    sigaddset(&set, SIGRTMIN + 1);
+    sigprocmask(SIG_BLOCK, &set, NULL);
+
+    queue(SIGRTMIN + 1, 1);
+    queue(SIGRTMIN, 2);
+    queue(SIGUSR1, 3);
+    queue(SIGRTMIN, 4);
+
+    for (int i = 0; i < 4; i++) {
+        siginfo_t info;
+        int signo = sigwaitinfo(&set, &info);
+        if (signo == SIGUSR1) {
+            printf("SIGUSR1: value %d\n", info.si_value.sival_int);
+        } else {
+            printf("SIGRTMIN+%d: value %d\n", signo - SIGRTMIN, info.si_value.sival_int);
+        }
+    }
+    return 0;
+}
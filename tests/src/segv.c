@@ -0,0 +1,62 @@
+// Unresolved page faults raise SIGSEGV with the faulting address,
+// and kill the process with a core dump if it is not handled.
+#include <signal.h>
+#include <stdio.h>
+#include <string.h>
+#include <sys/mman.h>
+#include <sys/wait.h>
+#include <unistd.h>
+
+static void *expected;
+
+static void handler(int signo, siginfo_t *info, void *context) {
+    printf("signal %d, code %s, addr %s\n", signo,
+           info->si_code == SEGV_MAPERR   ? "MAPERR"
+           : info->si_code == SEGV_ACCERR ? "ACCERR"
+                                          : "other",
+           info->si_addr == expected ? "ok" : "wrong");
+    _exit(0);
+}
+
+static void run(void (*fault)(void)) {
+    pid_t pid = fork();
+    if (pid == 0) {
+        struct sigaction sa;
+        memset(&sa, 0, sizeof(sa));
+        sa.sa_sigaction = handler;
+        sa.sa_flags = SA_SIGINFO;
+        sigaction(SIGSEGV, &sa, NULL);
+        fault();
+        printf("not faulted\n");
+        _exit(1);
+    }
+    waitpid(pid, NULL, 0);
+}
+
+static void null_deref(void) {
+    expected = NULL;
+    *(volatile int *)expected = 1;
+}
+
+static void write_read_only(void) {
+    expected = mmap(NULL, 4096, PROT_READ, MAP_PRIVATE | MAP_ANONYMOUS, -1, 0);
+    *(volatile int *)expected = 1;
+}
+
+int main() {
+    setvbuf(stdout, NULL, _IONBF, 0);
+    run(null_deref);
+    run(write_read_only);
+
+    // no handler
+    pid_t pid = fork();
+    if (pid == 0) {
+        null_deref();
+        _exit(1);
+    }
+    int status;
+    waitpid(pid, &status, 0);
+    printf("unhandled: signaled %d, signal %d, core %d\n", WIFSIGNALED(status), WTERMSIG(status),
+           WCOREDUMP(status) != 0);
+    return 0;
+}
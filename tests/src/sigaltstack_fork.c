@@ -0,0 +1,45 @@
+// A forked child keeps the alternate signal stack, exec drops it,
+// and ignored signals stay ignored across exec without their flags and mask.
+#include <signal.h>
+#include <stdio.h>
+#include <sys/wait.h>
+#include <unistd.h>
+
+static const char *self = "/tests/sigaltstack_fork";
+static char stack[SIGSTKSZ];
+
+static void show(const char *who) {
+    stack_t ss;
+    sigaltstack(NULL, &ss);
+    printf("%s: altstack %s, size %s\n", who, ss.ss_flags & SS_DISABLE ? "disabled" : "enabled",
+           ss.ss_sp == stack && ss.ss_size == sizeof(stack) ? "same" : "other");
+    struct sigaction sa;
+    sigaction(SIGUSR1, NULL, &sa);
+    printf("%s: SIGUSR1 %s, restart %d, masks SIGUSR2 %d\n", who,
+           sa.sa_handler == SIG_IGN ? "ignored" : "not ignored", !!(sa.sa_flags & SA_RESTART),
+           sigismember(&sa.sa_mask, SIGUSR2));
+}
+
+int main(int argc, char **argv) {
+    setvbuf(stdout, NULL, _IONBF, 0);
+    if (argc > 1) {
+        show("exec");
+        return 0;
+    }
+    stack_t ss = {.ss_sp = stack, .ss_size = sizeof(stack), .ss_flags = 0};
+    sigaltstack(&ss, NULL);
+    struct sigaction sa = {.sa_handler = SIG_IGN, .sa_flags = SA_RESTART};
+    sigemptyset(&sa.sa_mask);
+    sigaddset(&sa.sa_mask, SIGUSR2);
+    sigaction(SIGUSR1, &sa, NULL);
+    show("parent");
+
+    pid_t pid = fork();
+    if (pid == 0) {
+        show("child");
+        execl(self, self, "exec", NULL);
+        _exit(1);
+    }
+    waitpid(pid, NULL, 0);
+    return 0;
+}
@@ -0,0 +1,27 @@
+// Real-time signals queued with sigqueue are all delivered, each with its value.
+#include <signal.h>
+#include <stdio.h>
+#include <unistd.h>
+
+#define COUNT 5
+
+int main() {
+    sigset_t set;
+    sigemptyset(&set);
+    sigaddset(&set, SIGRTMIN);
+    sigprocmask(SIG_BLOCK, &set, NULL);
+
+    for (int i = 1; i <= COUNT; i++) {
+        union sigval value = {.sival_int = i * 10};
+        sigqueue(getpid(), SIGRTMIN, value);
+    }
+
+    // none of them is merged into another
+    for (int i = 0; i < COUNT; i++) {
+        siginfo_t info;
+        int signo = sigwaitinfo(&set, &info);
+        printf("signal %d: value %d, queued %d\n", signo - SIGRTMIN, info.si_value.sival_int,
+               info.si_code == SI_QUEUE);
+    }
+    return 0;
+}
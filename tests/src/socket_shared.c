@@ -0,0 +1,39 @@
+// Options and O_NONBLOCK of a socket are shared by all its fds, after dup and after fork.
+#include <fcntl.h>
+#include <stdio.h>
+#include <sys/ioctl.h>
+#include <sys/socket.h>
+#include <sys/wait.h>
+#include <unistd.h>
+
+static int get(int fd, int opt) {
+    int value = 0;
+    socklen_t len = sizeof(value);
+    getsockopt(fd, SOL_SOCKET, opt, &value, &len);
+    return value;
+}
+
+int main() {
+    setvbuf(stdout, NULL, _IONBF, 0);
+    int one = 1, size = 8192;
+    int fd = socket(AF_INET, SOCK_STREAM, 0);
+    int copy = dup(fd);
+    setsockopt(fd, SOL_SOCKET, SO_REUSEADDR, &one, sizeof(one));
+    printf("dup: reuseaddr %d\n", get(copy, SO_REUSEADDR));
+    setsockopt(copy, SOL_SOCKET, SO_RCVBUF, &size, sizeof(size));
+    printf("dup: rcvbuf %d\n", get(fd, SO_RCVBUF));
+    fcntl(fd, F_SETFL, O_NONBLOCK);
+    printf("dup: nonblock %d\n", !!(fcntl(copy, F_GETFL) & O_NONBLOCK));
+    int zero = 0;
+    ioctl(copy, FIONBIO, &zero);
+    printf("dup: FIONBIO %d\n", !!(fcntl(fd, F_GETFL) & O_NONBLOCK));
+
+    pid_t pid = fork();
+    if (pid == 0) {
+        setsockopt(fd, SOL_SOCKET, SO_KEEPALIVE, &one, sizeof(one));
+        _exit(0);
+    }
+    waitpid(pid, NULL, 0);
+    printf("fork: keepalive %d\n", get(fd, SO_KEEPALIVE));
+    return 0;
+}
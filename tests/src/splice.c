@@ -0,0 +1,55 @@
+// splice between files and pipes, with offsets and SPLICE_F_NONBLOCK.
+#define _GNU_SOURCE
+#include <errno.h>
+#include <fcntl.h>
+#include <stdio.h>
+#include <string.h>
+#include <unistd.h>
+
+int main() {
+    int fd = open("/tmp/splice", O_RDWR | O_CREAT | O_TRUNC, 0644);
+    write(fd, "0123456789", 10);
+    int p[2];
+    pipe(p);
+
+    // file to pipe at an offset, which is moved and the file offset is not
+    off_t off = 2;
+    printf("file to pipe: %zd\n", splice(fd, &off, p[1], NULL, 5, 0));
+    printf("off_in: %lld, file offset: %ld\n", (long long)off, lseek(fd, 0, SEEK_CUR));
+
+    // pipe to file at the file offset
+    lseek(fd, 0, SEEK_SET);
+    printf("pipe to file: %zd\n", splice(p[0], NULL, fd, NULL, 100, 0));
+    printf("file offset: %ld\n", lseek(fd, 0, SEEK_CUR));
+    char buf[16] = {0};
+    pread(fd, buf, 10, 0);
+    printf("file: %s\n", buf);
+
+    // nothing to move from an empty pipe
+    ssize_t len = splice(p[0], NULL, fd, NULL, 5, SPLICE_F_NONBLOCK);
+    printf("empty: %zd %s\n", len, strerror(errno));
+
+    // a full pipe takes nothing, and what was to be moved stays
+    int q[2];
+    pipe(q);
+    fcntl(q[1], F_SETPIPE_SZ, 4096);
+    char page[4096];
+    memset(page, 'x', sizeof(page));
+    write(q[1], page, sizeof(page));
+    write(p[1], "abc", 3);
+    len = splice(p[0], NULL, q[1], NULL, 3, SPLICE_F_NONBLOCK);
+    printf("full: %zd %s\n", len, strerror(errno));
+    off = 0;
+    len = splice(fd, &off, q[1], NULL, 3, SPLICE_F_NONBLOCK);
+    printf("full: %zd %s, off_in %lld\n", len, strerror(errno), (long long)off);
+    memset(buf, 0, sizeof(buf));
+    printf("left: %zd ", read(p[0], buf, sizeof(buf)));
+    printf("%s\n", buf);
+
+    // two files can not be spliced
+    len = splice(fd, NULL, fd, NULL, 3, 0);
+    printf("files: %zd %s\n", len, strerror(errno));
+    close(fd);
+    unlink("/tmp/splice");
+    return 0;
+}
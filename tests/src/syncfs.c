@@ -0,0 +1,41 @@
+// syncfs writes back a file system down to its metadata blocks,
+// and sync reports success once everything is written.
+#define _GNU_SOURCE
+#include <fcntl.h>
+#include <stdio.h>
+#include <unistd.h>
+
+static char buf[16384];
+
+// Sum the dirty blocks of all devices in /proc/buffercache
+static int dirty_blocks(void) {
+    FILE *f = fopen("/proc/buffercache", "r");
+    char line[128], dev[64];
+    int buffers, dirty, total = 0;
+    fgets(line, sizeof(line), f);
+    while (fgets(line, sizeof(line), f)) {
+        if (sscanf(line, "%63s %d %d", dev, &buffers, &dirty) == 3)
+            total += dirty;
+    }
+    fclose(f);
+    return total;
+}
+
+int main() {
+    const char *path = "/tmp/syncfs";
+    int fd = open(path, O_RDWR | O_CREAT | O_TRUNC, 0644);
+    printf("write: %zd\n", write(fd, buf, sizeof(buf)));
+    printf("syncfs: %d\n", syncfs(fd));
+    printf("dirty after syncfs: %d\n", dirty_blocks());
+
+    // a new file dirties the directory and the inode table again
+    close(fd);
+    unlink(path);
+    fd = open(path, O_RDWR | O_CREAT | O_TRUNC, 0644);
+    write(fd, buf, sizeof(buf));
+    sync();
+    printf("dirty after sync: %d\n", dirty_blocks());
+    close(fd);
+    unlink(path);
+    return 0;
+}
@@ -0,0 +1,64 @@
+// TCP echo over the loopback interface, the server in a child process.
+#include <arpa/inet.h>
+#include <netinet/in.h>
+#include <poll.h>
+#include <stdio.h>
+#include <string.h>
+#include <sys/socket.h>
+#include <sys/wait.h>
+#include <unistd.h>
+
+#define PORT 7007
+
+int main() {
+    setvbuf(stdout, NULL, _IONBF, 0);
+    struct sockaddr_in addr;
+    memset(&addr, 0, sizeof(addr));
+    addr.sin_family = AF_INET;
+    addr.sin_port = htons(PORT);
+    addr.sin_addr.s_addr = htonl(INADDR_LOOPBACK);
+
+    int server = socket(AF_INET, SOCK_STREAM, 0);
+    int one = 1;
+    setsockopt(server, SOL_SOCKET, SO_REUSEADDR, &one, sizeof(one));
+    printf("bind: %d\n", bind(server, (struct sockaddr *)&addr, sizeof(addr)));
+    printf("listen: %d\n", listen(server, 1));
+
+    pid_t pid = fork();
+    if (pid == 0) {
+        // echo until the client closes
+        int conn = accept(server, NULL, NULL);
+        char buf[256];
+        ssize_t len;
+        while ((len = read(conn, buf, sizeof(buf))) > 0) {
+            write(conn, buf, len);
+        }
+        close(conn);
+        _exit(0);
+    }
+    close(server);
+
+    int client = socket(AF_INET, SOCK_STREAM, 0);
+    printf("connect: %d\n", connect(client, (struct sockaddr *)&addr, sizeof(addr)));
+    const char *messages[] = {"hello", "echo over loopback"};
+    for (int i = 0; i < 2; i++) {
+        write(client, messages[i], strlen(messages[i]));
+        // the reply wakes poll
+        struct pollfd pfd = {client, POLLIN, 0};
+        printf("poll: %d\n", poll(&pfd, 1, 5000));
+        char buf[256] = {0};
+        size_t got = 0;
+        while (got < strlen(messages[i])) {
+            ssize_t len = read(client, buf + got, sizeof(buf) - 1 - got);
+            if (len <= 0) {
+                break;
+            }
+            got += len;
+        }
+        printf("echo: %s\n", buf);
+    }
+    close(client);
+    waitpid(pid, NULL, 0);
+    printf("done\n");
+    return 0;
+}
@@ -0,0 +1,64 @@
+// A client half-closes with SHUT_WR to end its request, then reads the response.
+#include <arpa/inet.h>
+#include <errno.h>
+#include <netinet/in.h>
+#include <signal.h>
+#include <stdio.h>
+#include <string.h>
+#include <sys/socket.h>
+#include <sys/wait.h>
+#include <unistd.h>
+
+#define PORT 7008
+
+int main() {
+    setvbuf(stdout, NULL, _IONBF, 0);
+    signal(SIGPIPE, SIG_IGN);
+    struct sockaddr_in addr;
+    memset(&addr, 0, sizeof(addr));
+    addr.sin_family = AF_INET;
+    addr.sin_port = htons(PORT);
+    addr.sin_addr.s_addr = htonl(INADDR_LOOPBACK);
+
+    int server = socket(AF_INET, SOCK_STREAM, 0);
+    int one = 1;
+    setsockopt(server, SOL_SOCKET, SO_REUSEADDR, &one, sizeof(one));
+    bind(server, (struct sockaddr *)&addr, sizeof(addr));
+    listen(server, 1);
+
+    pid_t pid = fork();
+    if (pid == 0) {
+        // the request ends where the client stops writing
+        int conn = accept(server, NULL, NULL);
+        char request[256] = {0};
+        size_t got = 0;
+        ssize_t len;
+        while ((len = read(conn, request + got, sizeof(request) - 1 - got)) > 0)
+            got += len;
+        char response[300];
+        int n = snprintf(response, sizeof(response), "%zu bytes: %s", got, request);
+        write(conn, response, n);
+        close(conn);
+        _exit(0);
+    }
+    close(server);
+
+    int client = socket(AF_INET, SOCK_STREAM, 0);
+    connect(client, (struct sockaddr *)&addr, sizeof(addr));
+    const char *request = "GET /";
+    write(client, request, strlen(request));
+    printf("shutdown: %d\n", shutdown(client, SHUT_WR));
+    ssize_t ret = write(client, "more", 4);
+    printf("write after shutdown: %zd %s\n", ret, strerror(errno));
+
+    char response[300] = {0};
+    size_t got = 0;
+    ssize_t len;
+    while ((len = read(client, response + got, sizeof(response) - 1 - got)) > 0)
+        got += len;
+    printf("response: %s\n", response);
+    printf("eof: %zd\n", len);
+    close(client);
+    waitpid(pid, NULL, 0);
+    return 0;
+}
@@ -0,0 +1,32 @@
+// The text segment is mapped read-only: writing to it raises SIGSEGV.
+#include <signal.h>
+#include <stdio.h>
+#include <string.h>
+#include <sys/wait.h>
+#include <unistd.h>
+
+static void handler(int signo, siginfo_t *info, void *context) {
+    printf("signal %d, code %s, addr %s\n", signo,
+           info->si_code == SEGV_ACCERR ? "ACCERR" : "other",
+           info->si_addr == (void *)handler ? "ok" : "wrong");
+    _exit(0);
+}
+
+int main() {
+    setvbuf(stdout, NULL, _IONBF, 0);
+    pid_t pid = fork();
+    if (pid == 0) {
+        struct sigaction sa;
+        memset(&sa, 0, sizeof(sa));
+        sa.sa_sigaction = handler;
+        sa.sa_flags = SA_SIGINFO;
+        sigaction(SIGSEGV, &sa, NULL);
+        *(volatile char *)handler = 0;
+        printf("text written\n");
+        _exit(1);
+    }
+    int status;
+    waitpid(pid, &status, 0);
+    printf("exit code %d\n", WEXITSTATUS(status));
+    return 0;
+}
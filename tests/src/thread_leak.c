@@ -0,0 +1,49 @@
+// Exited threads are freed: after 1000 threads are created and joined,
+// the slab cache of threads in /proc/slabinfo is back to where it started.
+#include <pthread.h>
+#include <sched.h>
+#include <stdio.h>
+#include <string.h>
+#include <unistd.h>
+
+static void *worker(void *arg) {
+    return arg;
+}
+
+// objects in use in the "thread" cache, -1 if not found
+static long threads_in_use(void) {
+    FILE *file = fopen("/proc/slabinfo", "r");
+    if (!file)
+        return -1;
+    char line[128], name[32];
+    long active = -1, objs;
+    while (fgets(line, sizeof(line), file)) {
+        if (sscanf(line, "%31s %ld", name, &objs) == 2 && strcmp(name, "thread") == 0)
+            active = objs;
+    }
+    fclose(file);
+    return active;
+}
+
+int main() {
+    long baseline = threads_in_use();
+    int joined = 0;
+    for (int i = 0; i < 1000; i++) {
+        pthread_t thread;
+        if (pthread_create(&thread, NULL, worker, NULL) != 0)
+            break;
+        if (pthread_join(thread, NULL) == 0)
+            joined++;
+    }
+    printf("joined: %d\n", joined);
+
+    // the last thread may be joined before its task lets it go
+    long now = threads_in_use();
+    for (int i = 0; i < 100 && now > baseline; i++) {
+        sched_yield();
+        now = threads_in_use();
+    }
+    printf("baseline found: %d\n", baseline >= 0);
+    printf("leaked: %ld\n", now - baseline);
+    return 0;
+}
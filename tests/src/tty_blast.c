@@ -0,0 +1,36 @@
+// Input arriving faster than it is read is buffered in the tty up to its size,
+// 4096 bytes, and comes out in order. Bytes beyond that are dropped.
+// A bad address of the byte to push fails with EFAULT.
+#include <errno.h>
+#include <fcntl.h>
+#include <stdio.h>
+#include <string.h>
+#include <sys/ioctl.h>
+#include <unistd.h>
+
+#define TTY_BUF_SIZE 4096
+
+int main() {
+    int fd = open("/dev/tty", O_RDWR | O_NONBLOCK);
+    int ret = ioctl(fd, TIOCSTI, (char *)8);
+    printf("bad address: %d %s\n", ret, strerror(errno));
+    // nothing reads while it is pushed
+    for (int i = 0; i < TTY_BUF_SIZE + 16; i++) {
+        char c = 'a' + i % 26;
+        ioctl(fd, TIOCSTI, &c);
+    }
+    static char buf[TTY_BUF_SIZE + 16];
+    int total = 0, in_order = 1;
+    for (;;) {
+        // read in small pieces
+        int len = read(fd, buf + total, 100);
+        if (len <= 0)
+            break;
+        total += len;
+    }
+    printf("last read: %s\n", strerror(errno));
+    for (int i = 0; i < total; i++)
+        in_order &= buf[i] == 'a' + i % 26;
+    printf("received: %d, in order: %d\n", total, in_order);
+    return 0;
+}
@@ -0,0 +1,49 @@
+// uname reports rCore on the machine this test was compiled for,
+// and the node and domain names set by sethostname and setdomainname.
+#define _GNU_SOURCE
+#include <errno.h>
+#include <stdio.h>
+#include <string.h>
+#include <sys/utsname.h>
+#include <unistd.h>
+
+#if defined(__x86_64__)
+#define MACHINE "x86_64"
+#elif defined(__aarch64__)
+#define MACHINE "aarch64"
+#elif defined(__riscv) && __riscv_xlen == 64
+#define MACHINE "riscv64"
+#elif defined(__riscv)
+#define MACHINE "riscv32"
+#elif defined(__mips__)
+#define MACHINE "mips"
+#endif
+
+int main() {
+    struct utsname name;
+    printf("uname: %d\n", uname(&name));
+    printf("sysname: %s\n", name.sysname);
+    printf("machine matches: %d\n", strcmp(name.machine, MACHINE) == 0);
+    char host[65];
+    gethostname(host, sizeof(host));
+    printf("nodename matches gethostname: %d\n", strcmp(host, name.nodename) == 0);
+
+    // the new name is read back by both
+    printf("sethostname: %d\n", sethostname("rcore-test", 10));
+    uname(&name);
+    gethostname(host, sizeof(host));
+    printf("nodename: %s, gethostname: %s\n", name.nodename, host);
+    printf("setdomainname: %d\n", setdomainname("example", 7));
+    uname(&name);
+    printf("domainname: %s\n", name.domainname);
+
+    // a name is at most 64 bytes
+    char long_name[65];
+    memset(long_name, 'x', sizeof(long_name));
+    int ret = sethostname(long_name, sizeof(long_name));
+    printf("sethostname 65 bytes: %d %s\n", ret, strerror(errno));
+    ret = sethostname(long_name, 64);
+    gethostname(host, sizeof(host));
+    printf("sethostname 64 bytes: %d, length %zu\n", ret, strlen(host));
+    return 0;
+}
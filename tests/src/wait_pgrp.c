@@ -0,0 +1,38 @@
+// wait4 selects children by process group: pid < -1 for group -pid, 0 for the caller's group.
+#include <errno.h>
+#include <stdio.h>
+#include <string.h>
+#include <sys/wait.h>
+#include <unistd.h>
+
+static pid_t child(int own_group, int code, int delay) {
+    pid_t pid = fork();
+    if (pid == 0) {
+        if (own_group)
+            setpgid(0, 0);
+        usleep(delay);
+        _exit(code);
+    }
+    // also in the parent, so the group is set before it waits
+    if (own_group)
+        setpgid(pid, pid);
+    return pid;
+}
+
+int main() {
+    pid_t a = child(1, 1, 200000);
+    // exits first, but in our group
+    child(0, 2, 0);
+    usleep(50000);
+
+    int status;
+    pid_t pid = waitpid(-a, &status, 0);
+    printf("group of a: %s, code %d\n", pid == a ? "a" : "other", WEXITSTATUS(status));
+    pid = waitpid(-a, &status, 0);
+    printf("group of a again: %d %s\n", pid, strerror(errno));
+    pid = waitpid(0, &status, 0);
+    printf("own group: code %d\n", WEXITSTATUS(status));
+    pid = waitpid(-1, &status, 0);
+    printf("any: %d %s\n", pid, strerror(errno));
+    return 0;
+}
@@ -0,0 +1 @@
+tests/tcp_echo
@@ -1,12 +1,22 @@
 #!/bin/bash
+# test programs in src/ are put in /tests of the user image
+CC=${CC:-riscv32-linux-musl-gcc}
+mkdir -p ../user/build/riscv32/tests
+for src in src/*.c
+do
+    $CC -static -O2 -o ../user/build/riscv32/tests/$(basename ${src%.c}) $src || exit 1
+done
 cd ../kernel && make sfsimg arch=riscv32 && cd ../tests
+# partitioned disks for the partition test, attached after the root disk
+python3 mkdisks.py ../user/build/riscv32 || exit 1
+disks="$PWD/../user/build/riscv32/mbr.img $PWD/../user/build/riscv32/gpt.img"
 for f in *.cmd
 do
     echo testing $f begin
     (
         cd ../kernel
         make build arch=riscv32 init=$(cat ../tests/$f)
-        exec timeout 10s make justruntest arch=riscv32 init=$(cat ../tests/$f)
+        exec timeout 10s make justruntest arch=riscv32 init=$(cat ../tests/$f) EXTRA_DISKS="$disks"
     ) &
 
     pid=$!
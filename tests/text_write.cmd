@@ -0,0 +1 @@
+tests/text_write
@@ -0,0 +1 @@
+tests/thread_leak
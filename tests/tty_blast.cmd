@@ -0,0 +1 @@
+tests/tty_blast
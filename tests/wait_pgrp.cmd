@@ -0,0 +1 @@
+tests/wait_pgrp